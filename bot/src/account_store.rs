@@ -0,0 +1,330 @@
+//! Snapshot of the accounts a stake pool update reads, loaded by a prefetch phase before the
+//! phases that act on them. Reading every account of a phase at once, rather than one at a time as
+//! each validator is handled, keeps the number of RPC requests of a run independent of the number
+//! of validators, and gives every phase the same view of the pool
+use {
+    crate::{
+        rpc_client_utils::get_all_stake_accounts,
+        vote_account_cache::{self, VoteAccountCache},
+    },
+    crossbeam_utils::thread,
+    log::*,
+    solana_client::{
+        rpc_client::RpcClient,
+        rpc_response::{RpcVoteAccountStatus, StakeActivationState},
+    },
+    solana_sdk::{
+        account::{from_account, Account},
+        account_utils::StateMut,
+        clock::Epoch,
+        pubkey::Pubkey,
+        stake_history::StakeHistory,
+        sysvar,
+    },
+    solana_stake_program::stake_state::StakeState,
+    std::{collections::HashMap, error, mem},
+};
+
+/// Accounts requested by each getMultipleAccounts call, the limit of the RPC nodes
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Number of getMultipleAccounts calls in flight at a time
+const PREFETCH_PARALLELISM: usize = 8;
+
+pub struct AccountStore {
+    epoch: Epoch,
+    accounts: HashMap<Pubkey, Option<Account>>,
+    staker_stake_accounts: HashMap<Pubkey, Account>,
+    vote_accounts: RpcVoteAccountStatus,
+    stake_history: StakeHistory,
+    stake_rent_exemption: u64,
+}
+
+fn get_multiple_accounts(
+    rpc_client: &RpcClient,
+    addresses: &[Pubkey],
+) -> Result<HashMap<Pubkey, Option<Account>>, String> {
+    let chunks = addresses.chunks(MAX_MULTIPLE_ACCOUNTS).collect::<Vec<_>>();
+    let mut accounts = HashMap::new();
+    for batch in chunks.chunks(PREFETCH_PARALLELISM) {
+        let results = thread::scope(|scope| {
+            let handles = batch
+                .iter()
+                .map(|chunk| {
+                    scope.spawn(move |_| {
+                        rpc_client
+                            .get_multiple_accounts(chunk)
+                            .map(|chunk_accounts| {
+                                chunk
+                                    .iter()
+                                    .cloned()
+                                    .zip(chunk_accounts)
+                                    .collect::<Vec<_>>()
+                            })
+                            .map_err(|err| err.to_string())
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join())
+                .collect::<Vec<_>>()
+        })
+        .map_err(|_| "prefetch worker panicked")?;
+        for result in results {
+            accounts.extend(result.map_err(|_| "prefetch worker panicked")??);
+        }
+    }
+    Ok(accounts)
+}
+
+impl AccountStore {
+    /// Fetch `addresses`, the stake accounts authorized by `staker`, the vote accounts, through
+    /// `vote_account_cache` if there is one, and the stake history of `epoch`, concurrently
+    pub fn prefetch(
+        rpc_client: &RpcClient,
+        epoch: Epoch,
+        addresses: &[Pubkey],
+        staker: &Pubkey,
+        vote_account_cache: Option<&VoteAccountCache>,
+    ) -> Result<Self, Box<dyn error::Error>> {
+        info!("Prefetching {} accounts", addresses.len());
+        let (accounts, staker_stake_accounts, vote_accounts, stake_history, stake_rent_exemption) =
+            thread::scope(|scope| {
+                let accounts = scope.spawn(|_| get_multiple_accounts(rpc_client, addresses));
+                let staker_stake_accounts = scope.spawn(|_| {
+                    get_all_stake_accounts(rpc_client, *staker).map_err(|err| err.to_string())
+                });
+                let vote_accounts = scope.spawn(|_| {
+                    vote_account_cache::get_vote_accounts(rpc_client, vote_account_cache)
+                        .map_err(|err| err.to_string())
+                });
+                let stake_history = scope.spawn(|_| {
+                    let account = rpc_client
+                        .get_account(&sysvar::stake_history::id())
+                        .map_err(|err| err.to_string())?;
+                    from_account::<StakeHistory, _>(&account)
+                        .ok_or_else(|| "Failed to deserialize stake history".to_string())
+                });
+                let stake_rent_exemption = scope.spawn(|_| {
+                    rpc_client
+                        .get_minimum_balance_for_rent_exemption(mem::size_of::<StakeState>())
+                        .map_err(|err| format!("Error fetching rent exemption: {}", err))
+                });
+                (
+                    accounts.join(),
+                    staker_stake_accounts.join(),
+                    vote_accounts.join(),
+                    stake_history.join(),
+                    stake_rent_exemption.join(),
+                )
+            })
+            .map_err(|_| "prefetch worker panicked")?;
+        let panicked = |_| "prefetch worker panicked".to_string();
+
+        Ok(Self {
+            epoch,
+            accounts: accounts.map_err(panicked)??,
+            staker_stake_accounts: staker_stake_accounts
+                .map_err(panicked)??
+                .into_iter()
+                .collect(),
+            vote_accounts: vote_accounts.map_err(panicked)??,
+            stake_history: stake_history.map_err(panicked)??,
+            stake_rent_exemption: stake_rent_exemption.map_err(panicked)??,
+        })
+    }
+
+    /// The prefetched account at `address`, None if it does not exist
+    pub fn account(&self, address: &Pubkey) -> Result<Option<&Account>, Box<dyn error::Error>> {
+        self.accounts
+            .get(address)
+            .map(|account| account.as_ref())
+            .ok_or_else(|| format!("Account {} was not prefetched", address).into())
+    }
+
+    /// Balance of the prefetched account at `address`, zero if it does not exist
+    pub fn balance(&self, address: &Pubkey) -> Result<u64, Box<dyn error::Error>> {
+        Ok(self
+            .account(address)?
+            .map(|account| account.lamports)
+            .unwrap_or_default())
+    }
+
+    /// Activation state of the prefetched stake account at `address`, as `getStakeActivation`
+    /// reports it
+    pub fn stake_activation(
+        &self,
+        address: &Pubkey,
+    ) -> Result<StakeActivationState, Box<dyn error::Error>> {
+        let account = self
+            .account(address)?
+            .ok_or_else(|| format!("Stake account {} does not exist", address))?;
+        self.activation_of(address, account)
+    }
+
+    fn activation_of(
+        &self,
+        address: &Pubkey,
+        account: &Account,
+    ) -> Result<StakeActivationState, Box<dyn error::Error>> {
+        let stake_state: StakeState = account.state().map_err(|err| {
+            format!(
+                "Unable to get activation information for stake account: {}: {}",
+                address, err
+            )
+        })?;
+        Ok(match stake_state {
+            StakeState::Stake(_meta, stake) => {
+                let (effective, activating, deactivating) = stake
+                    .delegation
+                    .stake_activating_and_deactivating(self.epoch, Some(&self.stake_history), true);
+                if deactivating > 0 {
+                    StakeActivationState::Deactivating
+                } else if activating > 0 {
+                    StakeActivationState::Activating
+                } else if effective > 0 {
+                    StakeActivationState::Active
+                } else {
+                    StakeActivationState::Inactive
+                }
+            }
+            StakeState::Initialized(_meta) => StakeActivationState::Inactive,
+            _ => {
+                return Err(format!("Account {} is not a stake account", address).into());
+            }
+        })
+    }
+
+    /// Stake accounts authorized by the staker, with their activation state
+    pub fn staker_stake_accounts(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            &Pubkey,
+            &Account,
+            Result<StakeActivationState, Box<dyn error::Error>>,
+        ),
+    > {
+        self.staker_stake_accounts
+            .iter()
+            .map(move |(address, account)| (address, account, self.activation_of(address, account)))
+    }
+
+    pub fn vote_accounts(&self) -> &RpcVoteAccountStatus {
+        &self.vote_accounts
+    }
+
+    /// Rent-exempt balance of a stake account
+    pub fn stake_rent_exemption(&self) -> u64 {
+        self.stake_rent_exemption
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        solana_sdk::stake_history::StakeHistoryEntry,
+        solana_stake_program::stake_state::{Delegation, Meta, Stake},
+    };
+
+    fn stake_account(delegation: Delegation) -> Account {
+        let mut account = Account::new(
+            delegation.stake,
+            mem::size_of::<StakeState>(),
+            &solana_stake_program::id(),
+        );
+        account
+            .set_state(&StakeState::Stake(
+                Meta::default(),
+                Stake {
+                    delegation,
+                    credits_observed: 0,
+                },
+            ))
+            .unwrap();
+        account
+    }
+
+    #[test]
+    fn test_stake_activation() {
+        let active = Pubkey::new_unique();
+        let activating = Pubkey::new_unique();
+        let deactivating = Pubkey::new_unique();
+        let inactive = Pubkey::new_unique();
+        let missing = Pubkey::new_unique();
+        let delegation = Delegation {
+            stake: 1_000,
+            activation_epoch: 5,
+            ..Delegation::default()
+        };
+        let mut stake_history = StakeHistory::default();
+        stake_history.add(
+            5,
+            StakeHistoryEntry {
+                effective: 1_000_000,
+                activating: 1_000,
+                deactivating: 1_000,
+            },
+        );
+        let store = AccountStore {
+            epoch: 10,
+            accounts: vec![
+                (active, Some(stake_account(delegation))),
+                (
+                    activating,
+                    Some(stake_account(Delegation {
+                        activation_epoch: 10,
+                        ..delegation
+                    })),
+                ),
+                (
+                    deactivating,
+                    Some(stake_account(Delegation {
+                        deactivation_epoch: 10,
+                        ..delegation
+                    })),
+                ),
+                (
+                    inactive,
+                    Some(stake_account(Delegation {
+                        deactivation_epoch: 6,
+                        ..delegation
+                    })),
+                ),
+                (missing, None),
+            ]
+            .into_iter()
+            .collect(),
+            staker_stake_accounts: HashMap::new(),
+            vote_accounts: RpcVoteAccountStatus {
+                current: vec![],
+                delinquent: vec![],
+            },
+            stake_history,
+            stake_rent_exemption: 0,
+        };
+
+        assert_eq!(
+            store.stake_activation(&active).unwrap(),
+            StakeActivationState::Active
+        );
+        assert_eq!(
+            store.stake_activation(&activating).unwrap(),
+            StakeActivationState::Activating
+        );
+        assert_eq!(
+            store.stake_activation(&deactivating).unwrap(),
+            StakeActivationState::Deactivating
+        );
+        assert_eq!(
+            store.stake_activation(&inactive).unwrap(),
+            StakeActivationState::Inactive
+        );
+        assert!(store.stake_activation(&missing).is_err());
+        assert_eq!(store.balance(&missing).unwrap(), 0);
+        assert_eq!(store.balance(&active).unwrap(), 1_000);
+        assert!(store.account(&Pubkey::new_unique()).is_err());
+    }
+}
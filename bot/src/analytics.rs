@@ -0,0 +1,184 @@
+use {
+    crate::{
+        db::EpochClassificationV1,
+        generic_stake_pool::ValidatorStakeState,
+        schema::{self, Schema},
+    },
+    hmac::{Hmac, Mac, NewMac},
+    log::*,
+    serde::{Deserialize, Serialize},
+    sha2::Sha256,
+    solana_sdk::clock::Epoch,
+    std::{env, error, time::Duration},
+};
+
+/// Environment variable holding the secret that signs the webhook requests
+pub const SECRET_ENV_VAR: &str = "ANALYTICS_WEBHOOK_SECRET";
+
+/// HTTP header carrying the hex encoded HMAC-SHA256 of the request body
+pub const SIGNATURE_HTTP_HEADER_NAME: &str = "X-Stake-O-Matic-Signature";
+
+/// Scoring inputs and decision of a validator for an epoch
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ValidatorDecision {
+    pub identity: String,
+    pub vote_address: String,
+    pub stake_state: ValidatorStakeState,
+    pub stake_state_reason: String,
+    pub notes: Vec<String>,
+    pub data_center: Option<String>,
+    pub reputation: Option<f64>,
+    pub epoch_credits: Option<u64>,
+    pub cluster_credits_percentile: Option<f64>,
+    pub pool_credits_percentile: Option<f64>,
+    pub off_target_epochs: Option<usize>,
+    pub hosting_provider: Option<String>,
+    pub bonus_weight_penalty: Option<u64>,
+    pub stake_movement_lamports: Option<i64>,
+    pub time_weighted_stake_lamports: Option<u64>,
+}
+
+/// Body of a webhook request, posted once per epoch
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EpochDecisions {
+    pub cluster: String,
+    pub epoch: Epoch,
+    pub timestamp: Option<u64>,
+    pub validators: Vec<ValidatorDecision>,
+}
+
+impl Schema for EpochDecisions {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl EpochDecisions {
+    pub fn new(cluster: &str, epoch: Epoch, epoch_classification: &EpochClassificationV1) -> Self {
+        let stake_pool_summary = epoch_classification.stake_pool_summary.as_ref();
+        let mut validators = epoch_classification
+            .validator_classifications
+            .iter()
+            .flat_map(|validator_classifications| validator_classifications.values())
+            .map(|vc| ValidatorDecision {
+                identity: vc.identity.to_string(),
+                vote_address: vc.vote_address.to_string(),
+                stake_state: vc.stake_state,
+                stake_state_reason: vc.stake_state_reason.clone(),
+                notes: vc.notes.clone(),
+                data_center: vc.current_data_center.as_ref().map(|dc| dc.to_string()),
+                reputation: vc.reputation,
+                epoch_credits: vc.epoch_credits,
+                cluster_credits_percentile: vc.cluster_credits_percentile,
+                pool_credits_percentile: vc.pool_credits_percentile,
+                off_target_epochs: vc.off_target_epochs,
+                hosting_provider: vc.hosting_provider.clone(),
+                bonus_weight_penalty: vc.bonus_weight_penalty,
+                stake_movement_lamports: stake_pool_summary
+                    .and_then(|summary| summary.stake_movement_lamports.get(&vc.vote_address))
+                    .copied(),
+                time_weighted_stake_lamports: vc.time_weighted_stake,
+            })
+            .collect::<Vec<_>>();
+        validators.sort_by(|a, b| a.identity.cmp(&b.identity));
+
+        Self {
+            cluster: cluster.to_string(),
+            epoch,
+            timestamp: epoch_classification.timestamp,
+            validators,
+        }
+    }
+}
+
+/// HMAC-SHA256 of `message`
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// External analytics endpoint that receives the decisions of each run
+pub struct Webhook {
+    url: reqwest::Url,
+    secret: String,
+    client: reqwest::blocking::Client,
+}
+
+impl Webhook {
+    /// Webhook posting to `url`, signing the requests with the secret in `SECRET_ENV_VAR`, which
+    /// must be set
+    pub fn new(url: &str) -> Result<Self, Box<dyn error::Error>> {
+        Ok(Self {
+            url: reqwest::Url::parse(url)
+                .map_err(|err| format!("Invalid analytics webhook URL {}: {}", url, err))?,
+            secret: env::var(SECRET_ENV_VAR)
+                .ok()
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| {
+                    format!(
+                        "{} must be set to sign the decisions posted to {}",
+                        SECRET_ENV_VAR, url
+                    )
+                })?,
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+        })
+    }
+
+    pub fn post(&self, decisions: &EpochDecisions) -> Result<(), Box<dyn error::Error>> {
+        let body = schema::to_json(decisions)?.into_bytes();
+        let request = self
+            .client
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(
+                SIGNATURE_HTTP_HEADER_NAME,
+                format!(
+                    "sha256={}",
+                    to_hex(&hmac_sha256(self.secret.as_bytes(), &body))
+                ),
+            );
+
+        info!(
+            "Posting {} validator decisions for epoch {} to {}",
+            decisions.validators.len(),
+            decisions.epoch,
+            self.url
+        );
+        let response = request.body(body).send()?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Analytics webhook {} responded with {}",
+                self.url,
+                response.status()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231, test cases 2 and 6
+        assert_eq!(
+            to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            to_hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+}
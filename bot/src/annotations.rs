@@ -0,0 +1,135 @@
+//! Annotations the operators attach to an epoch, such as "cluster outage, criteria relaxed", so
+//! that the context of an unusual epoch travels with its data. They are kept apart from the epoch
+//! classification, which may be annotated long after it was decided, and rendered in the cluster
+//! report, the epoch feed and the Grafana datasource
+use {
+    serde::{Deserialize, Serialize},
+    solana_sdk::clock::Epoch,
+    std::{
+        error, fmt,
+        fs::{self, File},
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Annotation {
+    pub text: String,
+
+    /// Who attached the annotation, if given
+    #[serde(default)]
+    pub author: Option<String>,
+
+    /// Unix timestamp of the moment the annotation was attached
+    pub timestamp: u64,
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.author {
+            Some(author) => write!(f, "{} ({})", self.text, author),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+/// Annotations of an epoch, oldest first
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct EpochAnnotations {
+    pub annotations: Vec<Annotation>,
+}
+
+impl EpochAnnotations {
+    pub fn file_name<P>(epoch: Epoch, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref()
+            .join(format!("epoch-{}-annotations.yml", epoch))
+    }
+
+    pub fn load<P>(epoch: Epoch, path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file_name = Self::file_name(epoch, path);
+        if !file_name.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_yaml::from_reader(File::open(file_name)?)?)
+    }
+
+    pub fn save<P>(&self, epoch: Epoch, path: P) -> Result<(), Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(&path)?;
+        fs::write(Self::file_name(epoch, path), serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The annotations as rendered in the reports
+    pub fn lines(&self) -> Vec<String> {
+        self.annotations
+            .iter()
+            .map(|annotation| annotation.to_string())
+            .collect()
+    }
+}
+
+/// Attach `text` to `epoch` in the data directory `path`, returning the new annotation
+pub fn annotate<P>(
+    epoch: Epoch,
+    text: &str,
+    author: Option<String>,
+    path: P,
+) -> Result<Annotation, Box<dyn error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("An annotation cannot be empty".into());
+    }
+    let annotation = Annotation {
+        text: text.to_string(),
+        author: author.filter(|author| !author.trim().is_empty()),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+
+    let mut epoch_annotations = EpochAnnotations::load(epoch, &path)?;
+    epoch_annotations.annotations.push(annotation.clone());
+    epoch_annotations.save(epoch, &path)?;
+    Ok(annotation)
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_sdk::pubkey::Pubkey};
+
+    #[test]
+    fn test_annotate() {
+        let path = std::env::temp_dir().join(format!("annotations-{}", Pubkey::new_unique()));
+        assert_eq!(
+            EpochAnnotations::load(7, &path).unwrap(),
+            EpochAnnotations::default()
+        );
+
+        annotate(7, " cluster outage, criteria relaxed ", None, &path).unwrap();
+        annotate(7, "restart at slot 1000", Some("ops".to_string()), &path).unwrap();
+        assert!(annotate(7, "  ", None, &path).is_err());
+        assert_eq!(
+            EpochAnnotations::load(7, &path).unwrap().lines(),
+            vec![
+                "cluster outage, criteria relaxed".to_string(),
+                "restart at slot 1000 (ops)".to_string(),
+            ]
+        );
+        assert!(EpochAnnotations::load(8, &path)
+            .unwrap()
+            .annotations
+            .is_empty());
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
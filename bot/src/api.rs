@@ -0,0 +1,359 @@
+//! HTTP API served by the daemon alongside its runs, answering queries on the data directory and
+//! recording the annotations of the operators:
+//!
+//! * `GET /validators/<IDENTITY_OR_VOTE_ADDRESS>/stake-history`, the stake moved to and from the
+//!   validator in each epoch, see `stake_history`
+//! * `GET /epochs/<EPOCH>/annotations`, the annotations of the operators on the epoch, see
+//!   `annotations`
+//! * `POST /epochs/<EPOCH>/annotations`, attach the annotation given as `{"text": ..., "author":
+//!   ...}`. Only served to requests bearing the API token, if the daemon was given one
+//! * `GET /validator/<VOTE_ADDRESS_OR_IDENTITY>`, the classification, stake, pending stake changes
+//!   and staking criteria outcomes of the validator in the latest epoch, see `validator_status`
+//!
+//! Anyone may query the API, so each client address is throttled to a number of requests per
+//! minute. The throttle is checked as a connection is accepted, before its request is read, and a
+//! request not received within a deadline is dropped, so that no client holds up the others
+use {
+    crate::{
+        annotations::{self, EpochAnnotations},
+        http_server, stake_history, validator_status,
+    },
+    log::*,
+    serde::Deserialize,
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::{
+        collections::HashMap,
+        error,
+        net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+        path::{Path, PathBuf},
+        str::FromStr,
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// Clients tracked by the throttle before the ones whose window ended are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Requests served to each client address within a window
+struct Throttle {
+    max_requests: u32,
+    window: Duration,
+    clients: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl Throttle {
+    fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Whether a request of `client` received at `now` is served, counting it if so
+    fn allow(&mut self, client: IpAddr, now: Instant) -> bool {
+        let window = self.window;
+        if self.clients.len() >= MAX_TRACKED_CLIENTS {
+            self.clients
+                .retain(|_, (window_start, _)| now.duration_since(*window_start) < window);
+        }
+        let (window_start, requests) = self.clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*window_start) >= window {
+            *window_start = now;
+            *requests = 0;
+        }
+        if *requests >= self.max_requests {
+            return false;
+        }
+        *requests += 1;
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct AnnotationRequest {
+    text: String,
+    #[serde(default)]
+    author: Option<String>,
+}
+
+fn internal_server_error() -> (&'static str, String) {
+    (
+        "500 Internal Server Error",
+        "\"Internal Server Error\"".to_string(),
+    )
+}
+
+/// Response status and JSON body of the request for `path`. `authorized` tells whether the request
+/// bears the API token, which the requests that write to the data directory require
+fn route(
+    method: &str,
+    path: &str,
+    body: &[u8],
+    authorized: bool,
+    db_path: &Path,
+) -> (&'static str, String) {
+    let not_found = ("404 Not Found", "\"Not Found\"".to_string());
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match (method, segments.as_slice()) {
+        ("GET", ["validators", validator, "stake-history"]) => {
+            let validator = match Pubkey::from_str(validator) {
+                Ok(validator) => validator,
+                Err(_) => return ("400 Bad Request", "\"Invalid validator\"".to_string()),
+            };
+            match stake_history::load(&validator, db_path)
+                .map_err(|err| err.to_string())
+                .and_then(|history| serde_json::to_string(&history).map_err(|err| err.to_string()))
+            {
+                Ok(history) => ("200 OK", history),
+                Err(err) => {
+                    warn!("Unable to load the stake history of {}: {}", validator, err);
+                    internal_server_error()
+                }
+            }
+        }
+        ("GET", ["validator", validator]) => {
+            let validator = match Pubkey::from_str(validator) {
+                Ok(validator) => validator,
+                Err(_) => return ("400 Bad Request", "\"Invalid validator\"".to_string()),
+            };
+            match validator_status::load(&validator, db_path)
+                .map_err(|err| err.to_string())
+                .and_then(|status| {
+                    status
+                        .map(|status| serde_json::to_string(&status).map_err(|err| err.to_string()))
+                        .transpose()
+                }) {
+                Ok(Some(status)) => ("200 OK", status),
+                Ok(None) => not_found,
+                Err(err) => {
+                    warn!("Unable to load the status of {}: {}", validator, err);
+                    internal_server_error()
+                }
+            }
+        }
+        ("GET", ["epochs", epoch, "annotations"]) => {
+            let epoch = match epoch.parse::<Epoch>() {
+                Ok(epoch) => epoch,
+                Err(_) => return ("400 Bad Request", "\"Invalid epoch\"".to_string()),
+            };
+            match EpochAnnotations::load(epoch, db_path).and_then(|epoch_annotations| {
+                Ok(serde_json::to_string(&epoch_annotations.annotations)?)
+            }) {
+                Ok(annotations) => ("200 OK", annotations),
+                Err(err) => {
+                    warn!("Unable to load the annotations of epoch {}: {}", epoch, err);
+                    internal_server_error()
+                }
+            }
+        }
+        ("POST", ["epochs", epoch, "annotations"]) => {
+            if !authorized {
+                return ("403 Forbidden", "\"Forbidden\"".to_string());
+            }
+            let epoch = match epoch.parse::<Epoch>() {
+                Ok(epoch) => epoch,
+                Err(_) => return ("400 Bad Request", "\"Invalid epoch\"".to_string()),
+            };
+            let request = match serde_json::from_slice::<AnnotationRequest>(body) {
+                Ok(request) if !request.text.trim().is_empty() => request,
+                _ => return ("400 Bad Request", "\"Invalid annotation\"".to_string()),
+            };
+            match annotations::annotate(epoch, &request.text, request.author, db_path)
+                .and_then(|annotation| Ok(serde_json::to_string(&annotation)?))
+            {
+                Ok(annotation) => {
+                    info!("Epoch {} annotated through the API", epoch);
+                    ("200 OK", annotation)
+                }
+                Err(err) => {
+                    warn!("Unable to annotate epoch {}: {}", epoch, err);
+                    internal_server_error()
+                }
+            }
+        }
+        _ => not_found,
+    }
+}
+
+fn handle_request(
+    stream: TcpStream,
+    db_path: &Path,
+    api_token: Option<&str>,
+) -> Result<(), Box<dyn error::Error>> {
+    let request = http_server::read_request(&stream, &[])?;
+
+    let authorized = api_token.map_or(false, |api_token| {
+        request.header("authorization") == Some(format!("Bearer {}", api_token).as_str())
+    });
+
+    let (status, response) = route(
+        &request.method,
+        &request.path,
+        &request.body,
+        authorized,
+        db_path,
+    );
+    http_server::write_response(stream, status, &response, &[])?;
+    Ok(())
+}
+
+fn serve(
+    listener: TcpListener,
+    db_path: &Path,
+    api_token: Option<&str>,
+    rate_limit: u32,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut throttle = Throttle::new(rate_limit, Duration::from_secs(60));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let result: Result<(), Box<dyn error::Error>> = match stream.peer_addr() {
+            Ok(client) if !throttle.allow(client.ip(), Instant::now()) => {
+                debug!("Throttled {}", client);
+                http_server::write_response(
+                    stream,
+                    "429 Too Many Requests",
+                    "\"Too Many Requests\"",
+                    &[],
+                )
+                .map_err(|err| err.into())
+            }
+            Ok(_) => handle_request(stream, db_path, api_token),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            warn!("Failed to handle API request: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Serve the API for the data directory `db_path` on `listen_address`, from a thread of its own
+/// that runs until the listener fails. Without `api_token` the API only answers queries. Each
+/// client address is served up to `rate_limit` requests per minute
+pub fn spawn(
+    listen_address: SocketAddr,
+    db_path: PathBuf,
+    api_token: Option<String>,
+    rate_limit: u32,
+) -> Result<(), Box<dyn error::Error>> {
+    let listener = TcpListener::bind(listen_address)
+        .map_err(|err| format!("Unable to listen on {}: {}", listen_address, err))?;
+    info!("Serving the API on http://{}", listen_address);
+    thread::spawn(move || {
+        if let Err(err) = serve(listener, &db_path, api_token.as_deref(), rate_limit) {
+            error!("The API stopped: {}", err);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let db_path = std::env::temp_dir().join(format!("api-{}", Pubkey::new_unique()));
+        let validator = Pubkey::new_unique();
+
+        assert_eq!(
+            route(
+                "GET",
+                &format!("/validators/{}/stake-history", validator),
+                &[],
+                false,
+                &db_path
+            ),
+            ("200 OK", "[]".to_string())
+        );
+        assert_eq!(
+            route(
+                "GET",
+                "/validators/unknown/stake-history",
+                &[],
+                false,
+                &db_path
+            )
+            .0,
+            "400 Bad Request"
+        );
+        assert_eq!(route("GET", "/", &[], false, &db_path).0, "404 Not Found");
+        assert_eq!(
+            route(
+                "GET",
+                &format!("/validator/{}", validator),
+                &[],
+                false,
+                &db_path
+            )
+            .0,
+            "404 Not Found"
+        );
+        assert_eq!(
+            route("GET", "/validator/unknown", &[], false, &db_path).0,
+            "400 Bad Request"
+        );
+        assert_eq!(
+            route(
+                "POST",
+                &format!("/validators/{}/stake-history", validator),
+                &[],
+                true,
+                &db_path
+            )
+            .0,
+            "404 Not Found"
+        );
+    }
+
+    #[test]
+    fn test_route_annotations() {
+        let db_path = std::env::temp_dir().join(format!("api-{}", Pubkey::new_unique()));
+        let body = br#"{"text": "cluster outage, criteria relaxed", "author": "ops"}"#;
+
+        assert_eq!(
+            route("GET", "/epochs/7/annotations", &[], false, &db_path),
+            ("200 OK", "[]".to_string())
+        );
+        assert_eq!(
+            route("POST", "/epochs/7/annotations", body, false, &db_path).0,
+            "403 Forbidden"
+        );
+        assert_eq!(
+            route("POST", "/epochs/7/annotations", b"{}", true, &db_path).0,
+            "400 Bad Request"
+        );
+        assert_eq!(
+            route("POST", "/epochs/seven/annotations", body, true, &db_path).0,
+            "400 Bad Request"
+        );
+        assert_eq!(
+            route("POST", "/epochs/7/annotations", body, true, &db_path).0,
+            "200 OK"
+        );
+
+        let (status, response) = route("GET", "/epochs/7/annotations", &[], false, &db_path);
+        assert_eq!(status, "200 OK");
+        let annotations: Vec<annotations::Annotation> = serde_json::from_str(&response).unwrap();
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].text, "cluster outage, criteria relaxed");
+        assert_eq!(annotations[0].author.as_deref(), Some("ops"));
+        std::fs::remove_dir_all(&db_path).unwrap();
+    }
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(2, Duration::from_secs(60));
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let other_client = IpAddr::from([127, 0, 0, 2]);
+        let now = Instant::now();
+
+        assert!(throttle.allow(client, now));
+        assert!(throttle.allow(client, now));
+        assert!(!throttle.allow(client, now + Duration::from_secs(59)));
+        assert!(throttle.allow(other_client, now));
+        assert!(throttle.allow(client, now + Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,241 @@
+use {
+    log::*,
+    solana_sdk::clock::Epoch,
+    std::{error, path::Path, process::Command},
+};
+
+/// Destination for the files produced by a run, such as the epoch classification and the
+/// markdown report. Artifacts are grouped by epoch
+pub trait ArtifactSink {
+    /// Upload the file at `path` as `file_name` within the artifacts of `epoch`
+    fn upload(
+        &self,
+        epoch: Epoch,
+        file_name: &str,
+        path: &Path,
+    ) -> Result<(), Box<dyn error::Error>>;
+
+    /// Epochs that currently have artifacts
+    fn epochs(&self) -> Result<Vec<Epoch>, Box<dyn error::Error>>;
+
+    /// Remove all artifacts of `epoch`
+    fn remove(&self, epoch: Epoch) -> Result<(), Box<dyn error::Error>>;
+}
+
+fn epoch_key(epoch: Epoch) -> String {
+    format!("epoch-{}", epoch)
+}
+
+// Extract the epoch from a listed object name such as `gs://bucket/prefix/epoch-123/` or
+// `PRE epoch-123/`
+fn epoch_of(name: &str) -> Option<Epoch> {
+    name.trim_end_matches('/')
+        .rsplit(|c| c == '/' || c == ' ')
+        .next()?
+        .strip_prefix("epoch-")?
+        .parse()
+        .ok()
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String, Box<dyn error::Error>> {
+    debug!("Running {} {}", program, args.join(" "));
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Unable to run {}: {}", program, err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Amazon S3 bucket, accessed with the `aws` command-line tool and its usual credentials
+pub struct S3Sink {
+    url: String, // s3://bucket/prefix, without a trailing slash
+}
+
+impl ArtifactSink for S3Sink {
+    fn upload(
+        &self,
+        epoch: Epoch,
+        file_name: &str,
+        path: &Path,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let key = format!("{}/{}/{}", self.url, epoch_key(epoch), file_name);
+        run(
+            "aws",
+            &[
+                "s3",
+                "cp",
+                "--only-show-errors",
+                &path.to_string_lossy(),
+                &key,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn epochs(&self) -> Result<Vec<Epoch>, Box<dyn error::Error>> {
+        let listing = run("aws", &["s3", "ls", &format!("{}/", self.url)])?;
+        Ok(listing.lines().filter_map(epoch_of).collect())
+    }
+
+    fn remove(&self, epoch: Epoch) -> Result<(), Box<dyn error::Error>> {
+        let prefix = format!("{}/{}/", self.url, epoch_key(epoch));
+        run(
+            "aws",
+            &["s3", "rm", "--only-show-errors", "--recursive", &prefix],
+        )?;
+        Ok(())
+    }
+}
+
+/// Google Cloud Storage bucket, accessed with the `gsutil` command-line tool and its usual
+/// credentials
+pub struct GcsSink {
+    url: String, // gs://bucket/prefix, without a trailing slash
+}
+
+impl ArtifactSink for GcsSink {
+    fn upload(
+        &self,
+        epoch: Epoch,
+        file_name: &str,
+        path: &Path,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let key = format!("{}/{}/{}", self.url, epoch_key(epoch), file_name);
+        run("gsutil", &["-q", "cp", &path.to_string_lossy(), &key])?;
+        Ok(())
+    }
+
+    fn epochs(&self) -> Result<Vec<Epoch>, Box<dyn error::Error>> {
+        let listing = run("gsutil", &["ls", &format!("{}/", self.url)])?;
+        Ok(listing.lines().filter_map(epoch_of).collect())
+    }
+
+    fn remove(&self, epoch: Epoch) -> Result<(), Box<dyn error::Error>> {
+        let prefix = format!("{}/{}", self.url, epoch_key(epoch));
+        run("gsutil", &["-q", "-m", "rm", "-r", &prefix])?;
+        Ok(())
+    }
+}
+
+/// Create the sink for an `s3://bucket/prefix` or `gs://bucket/prefix` URL
+pub fn new(url: &str) -> Result<Box<dyn ArtifactSink>, String> {
+    let url = url.trim_end_matches('/').to_string();
+    if url.starts_with("s3://") {
+        Ok(Box::new(S3Sink { url }))
+    } else if url.starts_with("gs://") {
+        Ok(Box::new(GcsSink { url }))
+    } else {
+        Err(format!(
+            "Unsupported artifact URL {}, expected s3://... or gs://...",
+            url
+        ))
+    }
+}
+
+/// Upload `files` as the artifacts of `epoch`, then remove the artifacts of epochs older than
+/// `retention_epochs`
+pub fn publish(
+    sink: &dyn ArtifactSink,
+    epoch: Epoch,
+    files: &[&Path],
+    retention_epochs: Option<u64>,
+) -> Result<(), Box<dyn error::Error>> {
+    for path in files {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("Invalid artifact path: {}", path.display()))?
+            .to_string_lossy();
+        info!("Uploading artifact {} for epoch {}", file_name, epoch);
+        sink.upload(epoch, &file_name, path)?;
+    }
+
+    if let Some(retention_epochs) = retention_epochs {
+        for expired_epoch in sink
+            .epochs()?
+            .into_iter()
+            .filter(|e| *e + retention_epochs < epoch)
+        {
+            info!("Removing expired artifacts for epoch {}", expired_epoch);
+            sink.remove(expired_epoch)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, std::cell::RefCell};
+
+    #[test]
+    fn test_epoch_of() {
+        assert_eq!(
+            epoch_of("                           PRE epoch-123/"),
+            Some(123)
+        );
+        assert_eq!(epoch_of("gs://bucket/prefix/epoch-7/"), Some(7));
+        assert_eq!(epoch_of("gs://bucket/prefix/notes.txt"), None);
+        assert_eq!(epoch_of(""), None);
+    }
+
+    #[derive(Default)]
+    struct MemorySink {
+        artifacts: RefCell<Vec<(Epoch, String)>>,
+    }
+
+    impl ArtifactSink for MemorySink {
+        fn upload(
+            &self,
+            epoch: Epoch,
+            file_name: &str,
+            _path: &Path,
+        ) -> Result<(), Box<dyn error::Error>> {
+            self.artifacts
+                .borrow_mut()
+                .push((epoch, file_name.to_string()));
+            Ok(())
+        }
+
+        fn epochs(&self) -> Result<Vec<Epoch>, Box<dyn error::Error>> {
+            Ok(self.artifacts.borrow().iter().map(|a| a.0).collect())
+        }
+
+        fn remove(&self, epoch: Epoch) -> Result<(), Box<dyn error::Error>> {
+            self.artifacts.borrow_mut().retain(|a| a.0 != epoch);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_publish() {
+        let sink = MemorySink::default();
+        for epoch in 1..=5 {
+            publish(
+                &sink,
+                epoch,
+                &[Path::new("db/epoch.yml"), Path::new("md/Mainnet.md")],
+                Some(2),
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            *sink.artifacts.borrow(),
+            vec![
+                (3, "epoch.yml".to_string()),
+                (3, "Mainnet.md".to_string()),
+                (4, "epoch.yml".to_string()),
+                (4, "Mainnet.md".to_string()),
+                (5, "epoch.yml".to_string()),
+                (5, "Mainnet.md".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use {
+    solana_sdk::{
+        hash::{hash, Hash},
+        pubkey::Pubkey,
+        signature::{Keypair, Signature, Signer},
+    },
+    std::str::FromStr,
+};
+
+// The attestation is appended to the report as an HTML comment so it does not render
+const ATTESTATION_BEGIN: &str = "\n\n<!-- stake-o-matic attestation\n";
+const ATTESTATION_END: &str = "-->\n";
+
+/// Append an attestation to `report`: the SHA-256 hash of the report and the ed25519 signature
+/// of that hash by `keypair`
+pub fn attest(report: &str, keypair: &Keypair) -> String {
+    let content_hash = hash(report.as_bytes());
+    let signature = keypair.sign_message(content_hash.as_ref());
+    format!(
+        "{}{}signer: {}\nhash: {}\nsignature: {}\n{}",
+        report,
+        ATTESTATION_BEGIN,
+        keypair.pubkey(),
+        content_hash,
+        signature,
+        ATTESTATION_END
+    )
+}
+
+/// Verify the attestation of a report produced by `attest`, returning the signer
+pub fn verify(attested_report: &str) -> Result<Pubkey, String> {
+    let attestation_start = attested_report
+        .rfind(ATTESTATION_BEGIN)
+        .ok_or("Report has no attestation")?;
+    let (report, attestation) = attested_report.split_at(attestation_start);
+    let attestation = attestation[ATTESTATION_BEGIN.len()..]
+        .strip_suffix(ATTESTATION_END)
+        .ok_or("Malformed attestation")?;
+
+    let field = |name: &str| {
+        attestation
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            .ok_or(format!("Attestation is missing the {}", name))
+    };
+    let signer = Pubkey::from_str(field("signer")?)
+        .map_err(|err| format!("Invalid attestation signer: {}", err))?;
+    let content_hash = Hash::from_str(field("hash")?)
+        .map_err(|err| format!("Invalid attestation hash: {}", err))?;
+    let signature = Signature::from_str(field("signature")?)
+        .map_err(|err| format!("Invalid attestation signature: {}", err))?;
+
+    if hash(report.as_bytes()) != content_hash {
+        return Err("Report content does not match the attested hash".into());
+    }
+    if !signature.verify(signer.as_ref(), content_hash.as_ref()) {
+        return Err(format!("Invalid signature by {}", signer));
+    }
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_attest_and_verify() {
+        let keypair = Keypair::new();
+        let report = "### Epoch 123\n* Bonus stake amount: ◎42.000000000";
+
+        let attested_report = attest(report, &keypair);
+        assert!(attested_report.starts_with(report));
+        assert_eq!(verify(&attested_report), Ok(keypair.pubkey()));
+
+        let tampered_report = attested_report.replace("42", "43");
+        assert_eq!(
+            verify(&tampered_report),
+            Err("Report content does not match the attested hash".to_string())
+        );
+
+        let other_keypair = Keypair::new();
+        let forged_report = attested_report.replace(
+            &keypair.pubkey().to_string(),
+            &other_keypair.pubkey().to_string(),
+        );
+        assert_eq!(
+            verify(&forged_report),
+            Err(format!("Invalid signature by {}", other_keypair.pubkey()))
+        );
+
+        assert_eq!(verify(report), Err("Report has no attestation".to_string()));
+    }
+}
@@ -0,0 +1,119 @@
+//! Forecast of the SOL spent by the staker and the funder on a stake pool update
+use {
+    crate::generic_stake_pool::ValidatorStake, solana_sdk::pubkey::Pubkey,
+    spl_stake_pool::MAX_VALIDATORS_TO_UPDATE, std::collections::HashSet,
+};
+
+// Signatures assumed for every transaction, enough for the staker and the funder to both sign
+const SIGNATURES_PER_TRANSACTION: u64 = 2;
+
+/// SOL a stake pool update is expected to spend
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BalanceForecast {
+    /// Stake accounts created for the validators joining the pool, paid by the funder
+    pub new_stake_accounts: usize,
+    pub stake_account_lamports: u64,
+
+    /// Transactions paid for by the funder and the staker, and their fees
+    pub funder_transactions: usize,
+    pub funder_fee_lamports: u64,
+    pub staker_transactions: usize,
+    pub staker_fee_lamports: u64,
+}
+
+impl BalanceForecast {
+    /// Forecast from the difference between `desired_validator_stake` and the vote addresses of
+    /// the validators in the pool. Every validator joining the pool requires a stake account of
+    /// `min_stake_account_balance`, and every validator may move stake once. The fees are an
+    /// upper bound
+    pub fn new(
+        desired_validator_stake: &[ValidatorStake],
+        pool_vote_addresses: &HashSet<Pubkey>,
+        min_stake_account_balance: u64,
+        lamports_per_signature: u64,
+    ) -> Self {
+        let desired_vote_addresses = desired_validator_stake
+            .iter()
+            .map(|vs| vs.vote_address)
+            .collect::<HashSet<_>>();
+        let joining = desired_vote_addresses
+            .difference(pool_vote_addresses)
+            .count();
+        let staying = desired_vote_addresses
+            .intersection(pool_vote_addresses)
+            .count();
+        let leaving = pool_vote_addresses
+            .difference(&desired_vote_addresses)
+            .count();
+
+        // One transaction per chunk of the validator list and one for the pool balance
+        let update_transactions = (pool_vote_addresses.len() + MAX_VALIDATORS_TO_UPDATE - 1)
+            / MAX_VALIDATORS_TO_UPDATE
+            + 1;
+        // Joining validators are added to the pool, then receive stake. Leaving validators have
+        // their stake decreased, then are removed
+        let staker_transactions = update_transactions + 2 * joining + staying + 2 * leaving;
+        let fee = |transactions: usize| {
+            transactions as u64 * SIGNATURES_PER_TRANSACTION * lamports_per_signature
+        };
+
+        Self {
+            new_stake_accounts: joining,
+            stake_account_lamports: joining as u64 * min_stake_account_balance,
+            funder_transactions: joining,
+            funder_fee_lamports: fee(joining),
+            staker_transactions,
+            staker_fee_lamports: fee(staker_transactions),
+        }
+    }
+
+    pub fn funder_lamports(&self) -> u64 {
+        self.stake_account_lamports + self.funder_fee_lamports
+    }
+
+    pub fn staker_lamports(&self) -> u64 {
+        self.staker_fee_lamports
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::generic_stake_pool::ValidatorStakeState};
+
+    #[test]
+    fn test_balance_forecast() {
+        let validator_stake = |vote_address| {
+            ValidatorStake::new(
+                Pubkey::new_unique(),
+                vote_address,
+                ValidatorStakeState::Baseline,
+            )
+        };
+        let (staying, leaving, joining) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let forecast = BalanceForecast::new(
+            &[validator_stake(staying), validator_stake(joining)],
+            &vec![staying, leaving].into_iter().collect(),
+            1_000,
+            5,
+        );
+        assert_eq!(
+            forecast,
+            BalanceForecast {
+                new_stake_accounts: 1,
+                stake_account_lamports: 1_000,
+                funder_transactions: 1,
+                funder_fee_lamports: 10,
+                // Two update transactions, two for the joining validator, one for the staying
+                // validator and two for the leaving validator
+                staker_transactions: 7,
+                staker_fee_lamports: 70,
+            }
+        );
+        assert_eq!(forecast.funder_lamports(), 1_010);
+        assert_eq!(forecast.staker_lamports(), 70);
+    }
+}
@@ -0,0 +1,177 @@
+//! Recent blockhashes for the transaction executors, refreshed in the background. Transactions are
+//! signed with the freshest blockhash rather than one fetched per batch, and a blockhash that is
+//! about to expire is replaced before the transactions still to be sent are signed with it
+use {
+    log::*,
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        clock::{Slot, MAX_PROCESSING_AGE},
+        commitment_config::CommitmentConfig,
+        fee_calculator::FeeCalculator,
+        hash::Hash,
+    },
+    std::{
+        collections::HashMap,
+        error,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, RwLock,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// How often the blockhash and current slot are refreshed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Slots before its last valid slot from which a blockhash is no longer used to sign
+const EXPIRY_MARGIN_SLOTS: Slot = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentBlockhash {
+    pub blockhash: Hash,
+    pub fee_calculator: FeeCalculator,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    recent_blockhash: Option<RecentBlockhash>,
+    slot: Slot,
+
+    // Last valid slot of every blockhash handed out that has not expired yet
+    last_valid_slots: HashMap<Hash, Slot>,
+}
+
+impl State {
+    fn update(&mut self, recent_blockhash: RecentBlockhash, last_valid_slot: Slot, slot: Slot) {
+        self.slot = slot;
+        self.last_valid_slots
+            .insert(recent_blockhash.blockhash, last_valid_slot);
+        self.last_valid_slots
+            .retain(|_, last_valid_slot| *last_valid_slot >= slot);
+        self.recent_blockhash = Some(recent_blockhash);
+    }
+
+    /// Blockhashes that were never handed out, or expired already, count as near expiry
+    fn is_near_expiry(&self, blockhash: &Hash) -> bool {
+        self.last_valid_slots
+            .get(blockhash)
+            .map(|last_valid_slot| self.slot + EXPIRY_MARGIN_SLOTS >= *last_valid_slot)
+            .unwrap_or(true)
+    }
+}
+
+fn refresh(rpc_client: &RpcClient, state: &RwLock<State>) -> Result<(), Box<dyn error::Error>> {
+    let response = rpc_client.get_recent_blockhash_with_commitment(rpc_client.commitment())?;
+    let (blockhash, fee_calculator, last_valid_slot) = response.value;
+    // RPC nodes without the `getFees` method do not return the last valid slot
+    let last_valid_slot = if last_valid_slot == 0 {
+        response.context.slot + MAX_PROCESSING_AGE as Slot
+    } else {
+        last_valid_slot
+    };
+    let slot = rpc_client.get_slot_with_commitment(CommitmentConfig::processed())?;
+
+    state.write().unwrap().update(
+        RecentBlockhash {
+            blockhash,
+            fee_calculator,
+        },
+        last_valid_slot,
+        slot,
+    );
+    Ok(())
+}
+
+pub struct BlockhashService {
+    state: Arc<RwLock<State>>,
+    exit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BlockhashService {
+    /// Fetch a first blockhash from `json_rpc_url`, then keep refreshing it in the background
+    pub fn start(json_rpc_url: &str) -> Result<Self, Box<dyn error::Error>> {
+        let rpc_client = RpcClient::new(json_rpc_url.to_string());
+        let state = Arc::new(RwLock::new(State::default()));
+        refresh(&rpc_client, &state)
+            .map_err(|err| format!("Unable to get a recent blockhash: {}", err))?;
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let state = state.clone();
+            let exit = exit.clone();
+            thread::spawn(move || {
+                while !exit.load(Ordering::Relaxed) {
+                    thread::sleep(REFRESH_INTERVAL);
+                    if let Err(err) = refresh(&rpc_client, &state) {
+                        warn!("Unable to refresh the recent blockhash: {}", err);
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            state,
+            exit,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn recent_blockhash(&self) -> RecentBlockhash {
+        self.state
+            .read()
+            .unwrap()
+            .recent_blockhash
+            .clone()
+            .expect("refreshed on start")
+    }
+
+    /// Whether transactions still to be sent should no longer be signed with `blockhash`
+    pub fn is_near_expiry(&self, blockhash: &Hash) -> bool {
+        self.state.read().unwrap().is_near_expiry(blockhash)
+    }
+}
+
+impl Drop for BlockhashService {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("The blockhash service panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_near_expiry() {
+        let recent_blockhash = |blockhash| RecentBlockhash {
+            blockhash,
+            fee_calculator: FeeCalculator::default(),
+        };
+        let first = Hash::new_unique();
+        let second = Hash::new_unique();
+
+        let mut state = State::default();
+        state.update(recent_blockhash(first), 150, 0);
+        assert!(!state.is_near_expiry(&first));
+        assert!(state.is_near_expiry(&second));
+
+        state.update(recent_blockhash(second), 250, 100);
+        assert!(!state.is_near_expiry(&first));
+        state.update(recent_blockhash(second), 250, 120);
+        assert!(state.is_near_expiry(&first));
+        assert!(!state.is_near_expiry(&second));
+        assert_eq!(state.recent_blockhash, Some(recent_blockhash(second)));
+
+        // Expired blockhashes are forgotten
+        state.update(recent_blockhash(second), 250, 151);
+        assert_eq!(state.last_valid_slots.len(), 1);
+        assert!(state.is_near_expiry(&first));
+    }
+}
@@ -0,0 +1,137 @@
+//! Staged increases of the bonus stake. A large deposit grows the stake available for bonus
+//! validators all at once, and delegating it in one epoch leaves a large share of the pool warming
+//! up and earning nothing. The ramp instead raises the bonus stake over several epochs, keeping
+//! the remainder of the inflow in the reserve until its stage comes
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct BonusRamp {
+    /// Epochs over which a sudden increase of the bonus stake is delegated
+    pub epochs: u64,
+
+    /// Growth of the bonus stake, as a percentage of the bonus stake of the previous epoch, up to
+    /// which it is delegated at once
+    pub threshold_percentage: f64,
+}
+
+/// Bonus stake delegated in an epoch, recorded so that the next epoch continues the ramp
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct BonusRampStage {
+    /// Total bonus stake distributed to the bonus validators
+    pub staged_lamports: u64,
+
+    /// Stake the pool could have distributed as bonus stake
+    pub available_lamports: u64,
+
+    /// Increase of the staged bonus stake per epoch, zero once the ramp is complete
+    pub step_lamports: u64,
+}
+
+impl BonusRampStage {
+    /// Bonus stake held back in the reserve for the following epochs
+    pub fn pending_lamports(&self) -> u64 {
+        self.available_lamports.saturating_sub(self.staged_lamports)
+    }
+}
+
+impl BonusRamp {
+    /// The bonus stake to distribute this epoch, out of `available_lamports`, given the stage of
+    /// the previous epoch. Decreases and growth within the threshold are never staged, and a
+    /// deposit during a ramp joins it without slowing the pace of the stages
+    pub fn stage(
+        &self,
+        available_lamports: u64,
+        previous: Option<&BonusRampStage>,
+    ) -> BonusRampStage {
+        let all_at_once = BonusRampStage {
+            staged_lamports: available_lamports,
+            available_lamports,
+            step_lamports: 0,
+        };
+        let previous = match previous {
+            // Without a previous stage, as on the first run, there is nothing to ramp from
+            Some(previous) if previous.staged_lamports > 0 => previous,
+            _ => return all_at_once,
+        };
+        if available_lamports <= previous.staged_lamports {
+            return all_at_once;
+        }
+
+        let growth_lamports = available_lamports - previous.staged_lamports;
+        let threshold_lamports =
+            (previous.staged_lamports as f64 * self.threshold_percentage / 100.) as u64;
+        if previous.step_lamports == 0 && growth_lamports <= threshold_lamports {
+            return all_at_once;
+        }
+
+        let epochs = self.epochs.max(1);
+        let step_lamports = previous
+            .step_lamports
+            .max((growth_lamports + epochs - 1) / epochs);
+        let staged_lamports = available_lamports.min(previous.staged_lamports + step_lamports);
+        BonusRampStage {
+            staged_lamports,
+            available_lamports,
+            step_lamports: if staged_lamports < available_lamports {
+                step_lamports
+            } else {
+                0
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stage() {
+        let ramp = BonusRamp {
+            epochs: 4,
+            threshold_percentage: 10.,
+        };
+        let stage = |staged_lamports, step_lamports| BonusRampStage {
+            staged_lamports,
+            available_lamports: 0,
+            step_lamports,
+        };
+
+        // First run and organic growth are distributed at once
+        assert_eq!(ramp.stage(1_000, None).staged_lamports, 1_000);
+        assert_eq!(
+            ramp.stage(1_050, Some(&stage(1_000, 0))).staged_lamports,
+            1_050
+        );
+        assert_eq!(ramp.stage(900, Some(&stage(1_000, 0))).staged_lamports, 900);
+
+        // A deposit is staged over the ramp epochs
+        let first = ramp.stage(5_000, Some(&stage(1_000, 0)));
+        assert_eq!(
+            first,
+            BonusRampStage {
+                staged_lamports: 2_000,
+                available_lamports: 5_000,
+                step_lamports: 1_000,
+            }
+        );
+        assert_eq!(first.pending_lamports(), 3_000);
+        let second = ramp.stage(5_000, Some(&first));
+        assert_eq!(second.staged_lamports, 3_000);
+        let third = ramp.stage(5_000, Some(&second));
+        let fourth = ramp.stage(5_000, Some(&third));
+        assert_eq!(
+            fourth,
+            BonusRampStage {
+                staged_lamports: 5_000,
+                available_lamports: 5_000,
+                step_lamports: 0,
+            }
+        );
+
+        // A deposit during the ramp keeps at least the current pace
+        let deposit = ramp.stage(5_400, Some(&second));
+        assert_eq!(deposit.staged_lamports, 4_000);
+        assert_eq!(deposit.step_lamports, 1_000);
+    }
+}
@@ -0,0 +1,260 @@
+//! Canary rollout of a classification policy change. The validators of a fixed share of hash
+//! buckets are classified under the canary policy, the others under the current policy, and the
+//! epoch notes compare the outcome of both policies for the canary group
+use {
+    crate::{db::EpochClassificationV1, generic_stake_pool::ValidatorStakeState},
+    serde::{Deserialize, Serialize},
+    solana_sdk::{hash::hashv, pubkey::Pubkey},
+    std::{convert::TryInto, error, fmt, fs::File, path::Path},
+};
+
+/// Classifier settings that differ under the canary policy, each replacing the setting of the
+/// same name. Settings that are not given keep their current value
+///
+/// ```yaml
+/// min_epoch_credit_percentage_of_average: 60
+/// max_commission: 8
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryPolicy {
+    pub quality_block_producer_percentage: Option<usize>,
+    pub max_poor_block_producer_percentage: Option<usize>,
+    pub max_commission: Option<u8>,
+    pub max_old_release_version_percentage: Option<usize>,
+    pub max_poor_voter_percentage: Option<usize>,
+    pub max_infrastructure_concentration: Option<f64>,
+    pub bad_cluster_average_skip_rate: Option<usize>,
+    pub normalize_skip_rate: Option<bool>,
+    pub min_epoch_credit_percentage_of_average: Option<usize>,
+    pub min_self_stake_lamports: Option<u64>,
+    pub enforce_min_self_stake: Option<bool>,
+    pub max_vote_lag: Option<u64>,
+    pub reputation_decay_percentage: Option<usize>,
+    pub min_bonus_reputation: Option<f64>,
+    pub min_bonus_activated_stake_lamports: Option<u64>,
+    pub identity_change_bonus_hold_epochs: Option<u64>,
+}
+
+impl CanaryPolicy {
+    pub fn load<P>(path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+        let policy: Self = serde_yaml::from_reader(file)
+            .map_err(|err| format!("Invalid canary policy {}: {}", path.display(), err))?;
+        if policy == Self::default() {
+            return Err(format!("Canary policy {} changes no setting", path.display()).into());
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for CanaryPolicy {
+    /// The settings given, as `name: value` pairs
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let settings = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(settings)) => settings,
+            _ => return Err(fmt::Error),
+        };
+        let settings = settings
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<_>>();
+        write!(f, "{}", settings.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canary {
+    pub policy: CanaryPolicy,
+
+    /// Share of the hash buckets, in percent, whose validators are classified under the canary
+    /// policy
+    pub percentage: f64,
+}
+
+impl Canary {
+    /// Whether the validator `identity` belongs to the canary group. The buckets do not depend on
+    /// the epoch, so that the same validators remain in the group while its percentage holds
+    pub fn contains(&self, identity: &Pubkey) -> bool {
+        let hash = hashv(&[b"canary", identity.as_ref()]);
+        let bucket = u64::from_le_bytes(hash.as_ref()[..8].try_into().unwrap()) % 10_000;
+        (bucket as f64) < self.percentage * 100.
+    }
+
+    /// Replace the classification of the canary group in `epoch_classification`, made under the
+    /// current policy, with its classification under the canary policy, returning the notes that
+    /// compare both policies
+    pub fn apply(
+        &self,
+        epoch_classification: &mut EpochClassificationV1,
+        canary_classification: EpochClassificationV1,
+    ) -> Vec<String> {
+        let (validator_classifications, canary_validator_classifications) = match (
+            epoch_classification.validator_classifications.as_mut(),
+            canary_classification.validator_classifications,
+        ) {
+            (Some(validator_classifications), Some(canary_validator_classifications)) => {
+                (validator_classifications, canary_validator_classifications)
+            }
+            (None, _) => return vec![],
+            (Some(_), None) => {
+                return vec![
+                    "Canary policy not applied: it pauses the classification of this epoch"
+                        .to_string(),
+                ]
+            }
+        };
+
+        let canary_identities = validator_classifications
+            .keys()
+            .filter(|identity| self.contains(identity))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut notes = vec![format!(
+            "Canary policy applied to {} of {} validators ({}% of the hash buckets): {}",
+            canary_identities.len(),
+            validator_classifications.len(),
+            self.percentage,
+            self.policy
+        )];
+
+        let mut changes = vec![];
+        let mut current_counts = StakeStateCounts::default();
+        let mut canary_counts = StakeStateCounts::default();
+        for identity in canary_identities {
+            let canary_validator_classification =
+                match canary_validator_classifications.get(&identity) {
+                    Some(canary_validator_classification) => canary_validator_classification,
+                    None => continue,
+                };
+            let validator_classification = validator_classifications.get_mut(&identity).unwrap();
+            current_counts.add(validator_classification.stake_state);
+            canary_counts.add(canary_validator_classification.stake_state);
+            if canary_validator_classification.stake_state != validator_classification.stake_state {
+                changes.push(format!(
+                    "Canary: {}: {:?} under the canary policy, {:?} under the current policy: {}",
+                    identity,
+                    canary_validator_classification.stake_state,
+                    validator_classification.stake_state,
+                    canary_validator_classification.stake_state_reason
+                ));
+            }
+            *validator_classification = canary_validator_classification.clone();
+        }
+        notes.push(format!(
+            "Canary group under the canary policy: {}; under the current policy: {}",
+            canary_counts, current_counts
+        ));
+        changes.sort();
+        notes.extend(changes);
+        notes
+    }
+}
+
+#[derive(Default)]
+struct StakeStateCounts {
+    bonus: usize,
+    baseline: usize,
+    none: usize,
+}
+
+impl StakeStateCounts {
+    fn add(&mut self, stake_state: ValidatorStakeState) {
+        match stake_state {
+            ValidatorStakeState::Bonus => self.bonus += 1,
+            ValidatorStakeState::Baseline => self.baseline += 1,
+            ValidatorStakeState::None => self.none += 1,
+        }
+    }
+}
+
+impl fmt::Display for StakeStateCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} Bonus, {} Baseline, {} None",
+            self.bonus, self.baseline, self.none
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::db::ValidatorClassification};
+
+    #[test]
+    fn test_apply() {
+        let canary = Canary {
+            policy: CanaryPolicy {
+                max_commission: Some(5),
+                ..CanaryPolicy::default()
+            },
+            percentage: 50.,
+        };
+        assert_eq!(canary.policy.to_string(), "max_commission: 5");
+        let identities = (0..200).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let canary_count = identities
+            .iter()
+            .filter(|identity| canary.contains(identity))
+            .count();
+        assert!(canary_count > 50 && canary_count < 150);
+        assert!(identities.iter().all(|identity| !Canary {
+            percentage: 0.,
+            ..canary.clone()
+        }
+        .contains(identity)));
+
+        let classification = |stake_state| EpochClassificationV1 {
+            validator_classifications: Some(
+                identities
+                    .iter()
+                    .map(|identity| {
+                        (
+                            *identity,
+                            ValidatorClassification {
+                                identity: *identity,
+                                stake_state,
+                                ..ValidatorClassification::default()
+                            },
+                        )
+                    })
+                    .collect(),
+            ),
+            ..EpochClassificationV1::default()
+        };
+        let mut epoch_classification = classification(ValidatorStakeState::Bonus);
+        let notes = canary.apply(
+            &mut epoch_classification,
+            classification(ValidatorStakeState::Baseline),
+        );
+        assert_eq!(notes.len(), 2 + canary_count);
+        assert_eq!(
+            notes[1],
+            format!(
+                "Canary group under the canary policy: 0 Bonus, {} Baseline, 0 None; under the \
+                 current policy: {} Bonus, 0 Baseline, 0 None",
+                canary_count, canary_count
+            )
+        );
+        let validator_classifications = epoch_classification.validator_classifications.unwrap();
+        assert!(identities.iter().all(|identity| {
+            validator_classifications[identity].stake_state
+                == if canary.contains(identity) {
+                    ValidatorStakeState::Baseline
+                } else {
+                    ValidatorStakeState::Bonus
+                }
+        }));
+
+        let mut paused = EpochClassificationV1::default();
+        assert!(canary
+            .apply(&mut paused, classification(ValidatorStakeState::Bonus))
+            .is_empty());
+    }
+}
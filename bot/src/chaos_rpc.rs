@@ -0,0 +1,277 @@
+//! JSON RPC proxy that injects faults into the traffic between the bot and an RPC node, to
+//! exercise the recovery paths that a healthy test validator never takes
+use {
+    log::*,
+    serde_json::{json, Value},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{commitment_config::CommitmentConfig, hash::hashv, transaction::Transaction},
+    std::{
+        convert::TryInto,
+        error,
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        thread,
+    },
+};
+
+/// Probability of each fault, between 0 and 1
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FaultProbabilities {
+    /// `sendTransaction` reports success without forwarding the transaction
+    pub dropped_transaction: f64,
+    /// `getFeeCalculatorForBlockhash` reports the blockhash as expired
+    pub stale_blockhash: f64,
+    /// The request is refused with 429 Too Many Requests
+    pub rate_limit: f64,
+    /// The data of the returned accounts is truncated
+    pub partial_account_data: f64,
+}
+
+/// Number of faults injected so far, by kind
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct InjectedFaults {
+    pub dropped_transactions: usize,
+    pub stale_blockhashes: usize,
+    pub rate_limits: usize,
+    pub partial_account_data: usize,
+}
+
+impl InjectedFaults {
+    pub fn total(&self) -> usize {
+        self.dropped_transactions
+            + self.stale_blockhashes
+            + self.rate_limits
+            + self.partial_account_data
+    }
+}
+
+struct State {
+    probabilities: FaultProbabilities,
+    injected: InjectedFaults,
+    seed: u64,
+    draws: u64,
+}
+
+impl State {
+    // Deterministic for a given seed, so that a failing test can be replayed
+    fn draw(&mut self, probability: f64) -> bool {
+        if probability <= 0. {
+            return false;
+        }
+        self.draws += 1;
+        let hash = hashv(&[&self.seed.to_le_bytes(), &self.draws.to_le_bytes()]);
+        let sample = u64::from_le_bytes(hash.as_ref()[..8].try_into().unwrap());
+        (sample as f64 / u64::MAX as f64) < probability
+    }
+}
+
+pub struct ChaosRpcProxy {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl ChaosRpcProxy {
+    /// Proxy the RPC node at `upstream_url` on a local port, injecting faults with
+    /// `probabilities`. The faults drawn are determined by `seed`
+    pub fn start(upstream_url: &str, probabilities: FaultProbabilities, seed: u64) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State {
+            probabilities,
+            injected: InjectedFaults::default(),
+            seed,
+            draws: 0,
+        }));
+
+        let upstream_url = upstream_url.to_string();
+        let client = reqwest::blocking::Client::new();
+        let proxy_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(err) = handle_request(stream, &client, &upstream_url, &proxy_state) {
+                    warn!("Chaos RPC proxy failed to handle request: {}", err);
+                }
+            }
+        });
+
+        info!("Chaos RPC proxy for {} on {}", upstream_url, url);
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub fn rpc_client(&self, commitment_config: CommitmentConfig) -> RpcClient {
+        RpcClient::new_with_commitment(self.url(), commitment_config)
+    }
+
+    pub fn set_probabilities(&self, probabilities: FaultProbabilities) {
+        self.state.lock().unwrap().probabilities = probabilities;
+    }
+
+    pub fn injected(&self) -> InjectedFaults {
+        self.state.lock().unwrap().injected
+    }
+}
+
+/// Truncate the data of every account found in `value`, keeping base64 data decodable so that
+/// the failure surfaces when the account is deserialized
+pub fn truncate_account_data(value: &mut Value) -> bool {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(data)) = object.get_mut("data") {
+                if let Some(Value::String(encoded)) = data.first_mut() {
+                    let len = encoded.len() / 2 / 4 * 4;
+                    encoded.truncate(len);
+                    return true;
+                }
+            }
+            object.values_mut().fold(false, |truncated, value| {
+                truncate_account_data(value) || truncated
+            })
+        }
+        Value::Array(values) => values.iter_mut().fold(false, |truncated, value| {
+            truncate_account_data(value) || truncated
+        }),
+        _ => false,
+    }
+}
+
+// Signature of the transaction in the params of a `sendTransaction` request
+fn transaction_signature(params: &Value) -> Result<String, Box<dyn error::Error>> {
+    let encoded = params[0].as_str().ok_or("Missing transaction")?;
+    let serialized = match params[1]["encoding"].as_str() {
+        Some("base58") => bs58::decode(encoded).into_vec()?,
+        _ => base64::decode(encoded)?,
+    };
+    let transaction: Transaction = bincode::deserialize(&serialized)?;
+    Ok(transaction.signatures[0].to_string())
+}
+
+fn handle_request(
+    stream: TcpStream,
+    client: &reqwest::blocking::Client,
+    upstream_url: &str,
+    state: &Mutex<State>,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let mut header = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let request: Value = serde_json::from_slice(&body)?;
+    let method = request["method"].as_str().unwrap_or_default().to_string();
+
+    let mut state = state.lock().unwrap();
+    let probabilities = state.probabilities;
+    let (status, response) = if state.draw(probabilities.rate_limit) {
+        state.injected.rate_limits += 1;
+        debug!("Chaos: rate limiting {}", method);
+        ("429 Too Many Requests", String::new())
+    } else if method == "sendTransaction" && state.draw(probabilities.dropped_transaction) {
+        state.injected.dropped_transactions += 1;
+        let signature = transaction_signature(&request["params"])?;
+        debug!("Chaos: dropping transaction {}", signature);
+        (
+            "200 OK",
+            json!({"jsonrpc": "2.0", "result": signature, "id": request["id"]}).to_string(),
+        )
+    } else {
+        let mut response: Value = client
+            .post(upstream_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()?
+            .json()?;
+
+        match method.as_str() {
+            "getFeeCalculatorForBlockhash"
+                if response["result"]["value"].is_object()
+                    && state.draw(probabilities.stale_blockhash) =>
+            {
+                state.injected.stale_blockhashes += 1;
+                debug!("Chaos: reporting a stale blockhash");
+                response["result"]["value"] = Value::Null;
+            }
+            "getAccountInfo" | "getMultipleAccounts" | "getProgramAccounts"
+                if state.draw(probabilities.partial_account_data) =>
+            {
+                if truncate_account_data(&mut response["result"]) {
+                    state.injected.partial_account_data += 1;
+                    debug!("Chaos: truncating the account data of {}", method);
+                }
+            }
+            _ => {}
+        }
+        ("200 OK", response.to_string())
+    };
+    drop(state);
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Retry-After: 0\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_truncate_account_data() {
+        let mut result = json!([
+            {"pubkey": "a", "account": {"data": ["AAAAAAAAAAAAAAAA", "base64"], "lamports": 1}},
+            {"pubkey": "b", "account": {"data": {"parsed": {}}, "lamports": 2}},
+        ]);
+        assert!(truncate_account_data(&mut result));
+        assert_eq!(result[0]["account"]["data"][0], "AAAAAAAA");
+        assert_eq!(result[1]["account"]["data"], json!({"parsed": {}}));
+
+        assert!(!truncate_account_data(&mut json!({"value": null})));
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut state = State {
+            probabilities: FaultProbabilities::default(),
+            injected: InjectedFaults::default(),
+            seed: 42,
+            draws: 0,
+        };
+        assert!(!state.draw(0.));
+        assert_eq!(state.draws, 0);
+        assert!(state.draw(1.));
+
+        let faults = (0..1_000).filter(|_| state.draw(0.25)).count();
+        assert!(faults > 200 && faults < 300, "{}", faults);
+    }
+}
@@ -0,0 +1,120 @@
+use {
+    crate::{
+        commission_samples::SampledCommission,
+        data_center_info::{DataCenterId, DataCenterInfo},
+        endpoint_probe::EndpointProbe,
+        rpc_client_utils::VoteAccountInfo,
+        schema::{self, Schema},
+        vote_account_health::VoteAccountHealth,
+    },
+    serde::{Deserialize, Serialize},
+    solana_sdk::{
+        clock::{Epoch, Slot},
+        pubkey::Pubkey,
+    },
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs::{self, File},
+        io::{self, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Observations of the cluster that the validators of an epoch were classified from. Together
+/// with the previous epoch classification and the classifier settings, they determine the epoch
+/// classification, allowing anyone to verify a published classification
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct ClassifierInputs {
+    // Identities of the validators eligible for the pool, and their program participant
+    pub validator_list: HashSet<Pubkey>,
+    pub identity_to_participant: HashMap<Pubkey, Pubkey>,
+
+    // Whether each participant maintained the required testnet stake level, if required
+    pub testnet_participation: Option<HashMap<Pubkey, bool>>,
+
+    // Data center observations, as reported by validators.app
+    pub data_center_info: Vec<DataCenterInfo>,
+    pub data_center_by_identity: HashMap<Pubkey, DataCenterId>,
+
+    // Vote accounts and their credits in the previous epoch
+    pub vote_account_info: Vec<VoteAccountInfo>,
+
+    // Effective self stake of each vote account
+    pub self_stake_by_vote_account: HashMap<Pubkey, u64>,
+
+    // Balance, node identity and authorized withdrawer of each vote account
+    #[serde(default)]
+    pub vote_account_health: HashMap<Pubkey, VoteAccountHealth>,
+
+    // Validators excluded by the screening hooks, with the reasons
+    pub screened_validators: HashMap<Pubkey, String>,
+
+    // Solana release reported by each validator over gossip, if a minimum release is required
+    pub release_versions: HashMap<Pubkey, String>,
+
+    // Blocks produced and leader slots of each validator in the previous epoch
+    pub blocks_and_slots: HashMap<Pubkey, (usize, usize)>,
+
+    // Average vote lag of each vote account, if the vote lag is limited
+    pub average_vote_lag: HashMap<Pubkey, f64>,
+
+    // Reachability of the endpoints each validator publishes over gossip, if probed
+    #[serde(default)]
+    pub endpoint_probes: HashMap<Pubkey, EndpointProbe>,
+
+    // Why the cluster appeared halted or recently restarted, if it did
+    #[serde(default)]
+    pub cluster_outage: Option<String>,
+
+    // Slot at which each part of the cluster state was fetched, by name
+    #[serde(default)]
+    pub input_slots: BTreeMap<String, Slot>,
+
+    // Commission of each vote account over the samples of the previous epoch, if the commission
+    // grace policy applies
+    #[serde(default)]
+    pub sampled_commission: HashMap<Pubkey, SampledCommission>,
+}
+
+impl Schema for ClassifierInputs {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl ClassifierInputs {
+    pub fn file_name<P>(epoch: Epoch, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref()
+            .join(format!("epoch-{}-classifier-inputs.yml", epoch))
+    }
+
+    pub fn exists<P>(epoch: Epoch, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        Self::file_name(epoch, path).exists()
+    }
+
+    pub fn load<P>(epoch: Epoch, path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized = fs::read_to_string(Self::file_name(epoch, path))?;
+        schema::from_str(&serialized).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+
+    pub fn save<P>(&self, epoch: Epoch, path: P) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized =
+            schema::to_yaml(self).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        fs::create_dir_all(&path)?;
+        let mut file = File::create(Self::file_name(epoch, path))?;
+        file.write_all(&serialized.into_bytes())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Detection of a halted or recently restarted cluster. Validators cannot vote while the cluster is
+//! down, so their vote credits say nothing about their own performance
+use {
+    log::*,
+    solana_client::{rpc_client::RpcClient, rpc_response::RpcPerfSample},
+    solana_sdk::clock::Slot,
+    std::error,
+};
+
+/// Slot production below which the cluster is considered halted, against 2.5 slots per second
+/// while the cluster is healthy
+const MIN_SLOTS_PER_SECOND: f64 = 1.;
+
+/// Age of the newest vote of the cluster, as seen by the RPC node, beyond which votes are
+/// considered to have stopped landing
+const MAX_NEWEST_VOTE_AGE_SLOTS: Slot = 150;
+
+/// Why the cluster appears halted or recently restarted, from the performance `samples` of the
+/// RPC node and the `newest_vote_slot` of the cluster at `slot`
+fn outage_reason(samples: &[RpcPerfSample], slot: Slot, newest_vote_slot: Slot) -> Option<String> {
+    let newest_vote_age = slot.saturating_sub(newest_vote_slot);
+    if newest_vote_age > MAX_NEWEST_VOTE_AGE_SLOTS {
+        return Some(format!(
+            "the newest vote is {} slots behind slot {}",
+            newest_vote_age, slot
+        ));
+    }
+
+    samples
+        .iter()
+        .filter(|sample| sample.sample_period_secs > 0)
+        .map(|sample| {
+            (
+                sample.slot,
+                sample.num_slots as f64 / f64::from(sample.sample_period_secs),
+            )
+        })
+        .filter(|(_, slots_per_second)| *slots_per_second < MIN_SLOTS_PER_SECOND)
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(sample_slot, slots_per_second)| {
+            format!(
+                "slot production dropped to {:.2} slots per second near slot {}",
+                slots_per_second, sample_slot
+            )
+        })
+}
+
+/// Why the cluster appears halted, or restarted within the last `lookback_minutes`, if it does
+pub fn detect(
+    rpc_client: &RpcClient,
+    lookback_minutes: usize,
+) -> Result<Option<String>, Box<dyn error::Error>> {
+    // The RPC node takes a performance sample every minute
+    let samples = rpc_client.get_recent_performance_samples(Some(lookback_minutes))?;
+    let slot = rpc_client.get_slot()?;
+    let newest_vote_slot = rpc_client
+        .get_vote_accounts()?
+        .current
+        .iter()
+        .map(|vote_account| vote_account.last_vote)
+        .max()
+        .unwrap_or_default();
+
+    let reason = outage_reason(&samples, slot, newest_vote_slot);
+    match &reason {
+        Some(reason) => warn!("Cluster outage detected: {}", reason),
+        None => info!(
+            "No cluster outage in the last {} minutes ({} performance samples)",
+            lookback_minutes,
+            samples.len()
+        ),
+    }
+    Ok(reason)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_outage_reason() {
+        let sample = |slot, num_slots| RpcPerfSample {
+            slot,
+            num_transactions: 0,
+            num_slots,
+            sample_period_secs: 60,
+        };
+        let healthy = [sample(1_300, 150), sample(1_150, 140)];
+        assert_eq!(outage_reason(&healthy, 1_300, 1_299), None);
+        assert_eq!(outage_reason(&[], 1_300, 1_299), None);
+
+        assert_eq!(
+            outage_reason(&healthy, 1_300, 1_000),
+            Some("the newest vote is 300 slots behind slot 1300".to_string())
+        );
+
+        let restarted = [sample(1_300, 150), sample(1_150, 30), sample(1_120, 0)];
+        assert_eq!(
+            outage_reason(&restarted, 1_300, 1_299),
+            Some("slot production dropped to 0.00 slots per second near slot 1120".to_string())
+        );
+    }
+}
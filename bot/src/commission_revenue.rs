@@ -0,0 +1,84 @@
+//! Estimate of the commission the validators earn from the stake pool's delegation
+use {solana_sdk::pubkey::Pubkey, std::collections::HashMap};
+
+/// Reward of the pool's stake account delegated to a validator, for one epoch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StakeReward {
+    pub vote_address: Pubkey,
+
+    /// Stake account balance the reward was earned on
+    pub stake_lamports: u64,
+
+    /// Reward received by the stake account, net of the validator's commission
+    pub reward_lamports: u64,
+
+    /// Commission of the validator, in percent
+    pub commission: u8,
+}
+
+/// Commission earned by each validator from the `stake_rewards` of the pool's delegation, by vote
+/// address.
+///
+/// The stake accounts receive the rewards net of commission, so the gross reward is derived from
+/// the commission. Validators with a commission of 100% pass on no reward at all, their gross
+/// reward is estimated from the average gross reward rate of the other validators
+pub fn commission_revenue(stake_rewards: &[StakeReward]) -> HashMap<Pubkey, u64> {
+    let mut revenue = HashMap::new();
+    let mut total_gross_reward_lamports = 0u128;
+    let mut total_stake_lamports = 0u128;
+    for stake_reward in stake_rewards {
+        let commission = u128::from(stake_reward.commission.min(100));
+        if commission < 100 {
+            let reward_lamports = u128::from(stake_reward.reward_lamports);
+            let gross_reward_lamports = reward_lamports * 100 / (100 - commission);
+            total_gross_reward_lamports += gross_reward_lamports;
+            total_stake_lamports += u128::from(stake_reward.stake_lamports);
+            revenue.insert(
+                stake_reward.vote_address,
+                (gross_reward_lamports - reward_lamports) as u64,
+            );
+        }
+    }
+
+    for stake_reward in stake_rewards {
+        if stake_reward.commission >= 100 {
+            let estimate = if total_stake_lamports == 0 {
+                0
+            } else {
+                u128::from(stake_reward.stake_lamports) * total_gross_reward_lamports
+                    / total_stake_lamports
+            };
+            revenue.insert(stake_reward.vote_address, estimate as u64);
+        }
+    }
+    revenue
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_commission_revenue() {
+        let stake_reward = |stake_lamports, reward_lamports, commission| StakeReward {
+            vote_address: Pubkey::new_unique(),
+            stake_lamports,
+            reward_lamports,
+            commission,
+        };
+        let no_commission = stake_reward(1_000_000, 1_000, 0);
+        let ten_percent = stake_reward(1_000_000, 900, 10);
+        let all_commission = stake_reward(2_000_000, 0, 100);
+
+        let revenue = commission_revenue(&[no_commission, ten_percent, all_commission]);
+        assert_eq!(revenue[&no_commission.vote_address], 0);
+        assert_eq!(revenue[&ten_percent.vote_address], 100);
+        // The others earned 2_000 lamports on 2_000_000 lamports of stake
+        assert_eq!(revenue[&all_commission.vote_address], 2_000);
+
+        assert_eq!(
+            commission_revenue(&[all_commission]),
+            vec![(all_commission.vote_address, 0)].into_iter().collect()
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Commission of the vote accounts, sampled by each run of an epoch. The classification reads the
+//! commission of the moment it runs, so that a validator that raised its commission by mistake for
+//! a few minutes loses its stake if the bot happens to run then. The last sample of an epoch stands
+//! for the commission at the epoch boundary, and the commission grace policy only destakes the
+//! validators whose higher commission was already in place at the boundary and persists past it
+use {
+    serde::{Deserialize, Serialize},
+    solana_sdk::{
+        clock::{Epoch, Slot},
+        pubkey::Pubkey,
+    },
+    std::{
+        collections::HashMap,
+        error,
+        fs::{self, File},
+        path::{Path, PathBuf},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct CommissionSample {
+    pub slot: Slot,
+    pub commission: u8,
+}
+
+/// Commission of a vote account over the samples of an epoch
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct SampledCommission {
+    /// Commission of the last sample, closest to the epoch boundary
+    pub boundary: u8,
+
+    /// Highest commission sampled
+    pub max: u8,
+}
+
+/// Commission samples of an epoch. Only the first sample of each vote account and the samples
+/// that changed its commission are kept
+#[derive(Default, Deserialize, Serialize)]
+pub struct CommissionSamples {
+    pub samples: HashMap<Pubkey, Vec<CommissionSample>>,
+
+    /// Slot of the latest sample
+    pub slot: Slot,
+}
+
+impl CommissionSamples {
+    pub fn file_name<P>(epoch: Epoch, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref()
+            .join(format!("epoch-{}-commission-samples.yml", epoch))
+    }
+
+    pub fn load<P>(epoch: Epoch, path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file_name = Self::file_name(epoch, path);
+        if !file_name.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_yaml::from_reader(File::open(file_name)?)?)
+    }
+
+    pub fn save<P>(&self, epoch: Epoch, path: P) -> Result<(), Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(&path)?;
+        fs::write(Self::file_name(epoch, path), serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Record the commission of each vote address of `commissions`, sampled at `slot`
+    pub fn record<I>(&mut self, slot: Slot, commissions: I)
+    where
+        I: IntoIterator<Item = (Pubkey, u8)>,
+    {
+        for (vote_address, commission) in commissions {
+            let samples = self.samples.entry(vote_address).or_default();
+            if samples.last().map(|sample| sample.commission) != Some(commission) {
+                samples.push(CommissionSample { slot, commission });
+            }
+        }
+        self.slot = self.slot.max(slot);
+    }
+
+    /// The commission of each vote address over the samples of the epoch. A vote account missing
+    /// from the later samples, such as a delinquent one the RPC node dropped, keeps its last
+    /// sampled commission
+    pub fn sampled_commission(&self) -> HashMap<Pubkey, SampledCommission> {
+        self.samples
+            .iter()
+            .filter_map(|(vote_address, samples)| {
+                Some((
+                    *vote_address,
+                    SampledCommission {
+                        boundary: samples.last()?.commission,
+                        max: samples.iter().map(|sample| sample.commission).max()?,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Whether the commission grace policy tolerates a `commission` above `max_commission`: the
+/// commission at the epoch boundary was within the maximum, so that it was raised since
+pub fn tolerated(
+    commission: u8,
+    max_commission: u8,
+    sampled_commission: Option<&SampledCommission>,
+) -> bool {
+    commission > max_commission
+        && sampled_commission.map_or(false, |sampled_commission| {
+            sampled_commission.boundary <= max_commission
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_commission_samples() {
+        let steady = Pubkey::new_unique();
+        let brief = Pubkey::new_unique();
+        let raised = Pubkey::new_unique();
+
+        let mut samples = CommissionSamples::default();
+        samples.record(100, vec![(steady, 5), (brief, 5), (raised, 5)]);
+        samples.record(200, vec![(steady, 5), (brief, 100), (raised, 5)]);
+        samples.record(300, vec![(steady, 5), (brief, 5), (raised, 100)]);
+        assert_eq!(samples.samples[&steady].len(), 1);
+        assert_eq!(samples.samples[&brief].len(), 3);
+        assert_eq!(samples.slot, 300);
+
+        let sampled_commission = samples.sampled_commission();
+        assert_eq!(
+            sampled_commission[&brief],
+            SampledCommission {
+                boundary: 5,
+                max: 100
+            }
+        );
+        assert_eq!(
+            sampled_commission[&raised],
+            SampledCommission {
+                boundary: 100,
+                max: 100
+            }
+        );
+
+        // Raised after the boundary
+        assert!(tolerated(100, 10, sampled_commission.get(&brief)));
+        // Raised before the boundary, and persisting past it
+        assert!(!tolerated(100, 10, sampled_commission.get(&raised)));
+        // Not sampled, or within the maximum
+        assert!(!tolerated(100, 10, None));
+        assert!(!tolerated(5, 10, sampled_commission.get(&steady)));
+    }
+}
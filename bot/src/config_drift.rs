@@ -0,0 +1,81 @@
+//! Record of the configuration each epoch runs with, to flag the policy changes between epochs
+use {
+    solana_sdk::hash::hashv,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fs,
+        path::Path,
+    },
+};
+
+/// Settings that shape the classification and the stake distribution, by name
+pub type EffectiveConfig = BTreeMap<String, String>;
+
+pub fn config_hash(effective_config: &EffectiveConfig) -> String {
+    let serialized = effective_config
+        .iter()
+        .map(|(name, value)| format!("{}={}\n", name, value))
+        .collect::<String>();
+    hashv(&[serialized.as_bytes()]).to_string()
+}
+
+/// Hash of the contents of the file at `path`, so that editing a list such as a blacklist
+/// counts as a configuration change
+pub fn file_hash(path: &Path) -> String {
+    match fs::read(path) {
+        Ok(contents) => hashv(&[&contents]).to_string(),
+        Err(err) => format!("unreadable: {}", err),
+    }
+}
+
+/// Settings that differ between the `previous` and `current` configurations, in name order
+pub fn config_changes(previous: &EffectiveConfig, current: &EffectiveConfig) -> Vec<String> {
+    previous
+        .keys()
+        .chain(current.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|name| {
+            let (previous_value, current_value) = (previous.get(name), current.get(name));
+            if previous_value == current_value {
+                None
+            } else {
+                Some(format!(
+                    "{}: {} -> {}",
+                    name,
+                    previous_value.map_or("unset", String::as_str),
+                    current_value.map_or("unset", String::as_str)
+                ))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_changes() {
+        let config = |settings: &[(&str, &str)]| {
+            settings
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<EffectiveConfig>()
+        };
+        let previous = config(&[("max_commission", "10"), ("max_vote_lag", "None")]);
+        assert!(config_changes(&previous, &previous).is_empty());
+        assert_eq!(config_hash(&previous), config_hash(&previous.clone()));
+
+        let current = config(&[("max_commission", "5"), ("seed", "Some(1)")]);
+        assert_ne!(config_hash(&previous), config_hash(&current));
+        assert_eq!(
+            config_changes(&previous, &current),
+            vec![
+                "max_commission: 10 -> 5",
+                "max_vote_lag: None -> unset",
+                "seed: unset -> Some(1)"
+            ]
+        );
+    }
+}
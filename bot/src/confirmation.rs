@@ -0,0 +1,193 @@
+//! Operator confirmation required before the stake pool update issues unusually large changes
+use {
+    crate::run_status::guardrail,
+    log::*,
+    solana_sdk::{
+        clock::Epoch,
+        native_token::Sol,
+        pubkey::Pubkey,
+        signature::{Keypair, Signature, Signer},
+    },
+    std::{
+        error,
+        io::{self, BufRead, Write},
+    },
+};
+
+// Text the operator types to confirm at the terminal
+const CONFIRMATION_TEXT: &str = "confirm";
+
+/// How the operator confirms a stake pool update that exceeds the thresholds
+#[derive(Debug)]
+pub enum Approval {
+    /// Typed at the terminal
+    Interactive,
+    /// Approval token signed ahead of the run by `approver`, for unattended runs
+    Token {
+        approver: Pubkey,
+        token: Option<Signature>,
+    },
+}
+
+/// The message an approval token signs, binding the approval to one epoch of one stake pool
+pub fn approval_message(stake_pool_address: &Pubkey, epoch: Epoch) -> String {
+    format!(
+        "Approve the stake-o-matic update of stake pool {} for epoch {}",
+        stake_pool_address, epoch
+    )
+}
+
+/// Approval token for the update of `stake_pool_address` in `epoch`
+pub fn sign_approval(approver: &Keypair, stake_pool_address: &Pubkey, epoch: Epoch) -> Signature {
+    approver.sign_message(approval_message(stake_pool_address, epoch).as_bytes())
+}
+
+#[derive(Debug)]
+pub struct ConfirmationGate {
+    /// If Some(), stake movements totalling more than this amount require a confirmation
+    pub max_movement_lamports: Option<u64>,
+
+    /// If Some(), removing more than this many validators from the pool requires a confirmation
+    pub max_removals: Option<usize>,
+
+    pub approval: Approval,
+
+    // A single confirmation covers the rest of the update
+    confirmed: bool,
+}
+
+impl ConfirmationGate {
+    pub fn new(
+        max_movement_lamports: Option<u64>,
+        max_removals: Option<usize>,
+        approval: Approval,
+    ) -> Self {
+        Self {
+            max_movement_lamports,
+            max_removals,
+            approval,
+            confirmed: false,
+        }
+    }
+
+    /// Reasons the planned `movement_lamports` and `removals` require a confirmation
+    fn reasons(&self, movement_lamports: u64, removals: usize) -> Vec<String> {
+        let mut reasons = vec![];
+        if let Some(max_movement_lamports) = self.max_movement_lamports {
+            if movement_lamports > max_movement_lamports {
+                reasons.push(format!(
+                    "{} of stake movements exceeds {}",
+                    Sol(movement_lamports),
+                    Sol(max_movement_lamports)
+                ));
+            }
+        }
+        if let Some(max_removals) = self.max_removals {
+            if removals > max_removals {
+                reasons.push(format!(
+                    "{} validator removals exceeds {}",
+                    removals, max_removals
+                ));
+            }
+        }
+        reasons
+    }
+
+    /// Proceed with `movement_lamports` of stake movements and `removals` validator removals
+    /// from `stake_pool_address` in `epoch`, once confirmed if they exceed the thresholds.
+    /// Fails with a guardrail error if the update is not confirmed
+    pub fn check(
+        &mut self,
+        stake_pool_address: &Pubkey,
+        epoch: Epoch,
+        movement_lamports: u64,
+        removals: usize,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let reasons = self.reasons(movement_lamports, removals);
+        if self.confirmed || reasons.is_empty() {
+            return Ok(());
+        }
+        let reasons = reasons.join(", ");
+        warn!("Confirmation required: {}", reasons);
+
+        match &self.approval {
+            Approval::Interactive => {
+                eprint!(
+                    "Stake pool update requires confirmation: {}\nType `{}` to proceed: ",
+                    reasons, CONFIRMATION_TEXT
+                );
+                io::stderr().flush()?;
+                let mut response = String::new();
+                io::stdin().lock().read_line(&mut response)?;
+                if response.trim() != CONFIRMATION_TEXT {
+                    return Err(guardrail(format!("Update not confirmed: {}", reasons)));
+                }
+            }
+            Approval::Token { approver, token } => {
+                let message = approval_message(stake_pool_address, epoch);
+                match token {
+                    Some(token) if token.verify(approver.as_ref(), message.as_bytes()) => {}
+                    Some(_) => {
+                        return Err(guardrail(format!(
+                            "Approval token is not signed by {} for epoch {}: {}",
+                            approver, epoch, reasons
+                        )))
+                    }
+                    None => {
+                        return Err(guardrail(format!(
+                            "Update requires an approval token signed by {}: {}",
+                            approver, reasons
+                        )))
+                    }
+                }
+            }
+        }
+        info!("Stake pool update confirmed");
+        self.confirmed = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_approval_token() {
+        let approver = Keypair::new();
+        let stake_pool_address = Pubkey::new_unique();
+        let gate = |token| {
+            ConfirmationGate::new(
+                Some(100),
+                Some(2),
+                Approval::Token {
+                    approver: approver.pubkey(),
+                    token,
+                },
+            )
+        };
+
+        // Within the thresholds
+        assert!(gate(None).check(&stake_pool_address, 42, 100, 2).is_ok());
+
+        assert!(gate(None).check(&stake_pool_address, 42, 101, 0).is_err());
+        assert!(gate(None).check(&stake_pool_address, 42, 0, 3).is_err());
+
+        let token = sign_approval(&approver, &stake_pool_address, 42);
+        let mut approved = gate(Some(token));
+        assert!(approved.check(&stake_pool_address, 42, 101, 3).is_ok());
+        assert!(approved.check(&stake_pool_address, 42, 1_000, 0).is_ok());
+
+        // Tokens of another epoch, stake pool or approver
+        assert!(gate(Some(token))
+            .check(&stake_pool_address, 43, 101, 0)
+            .is_err());
+        assert!(gate(Some(token))
+            .check(&Pubkey::new_unique(), 42, 101, 0)
+            .is_err());
+        let token = sign_approval(&Keypair::new(), &stake_pool_address, 42);
+        assert!(gate(Some(token))
+            .check(&stake_pool_address, 42, 101, 0)
+            .is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Staking criteria of the classification. The stake state of a validator is decided by the first
+//! criterion it fails, so that its reason names a single failure. The failures of every criterion
+//! are recorded as well, so that a validator sees all it has to fix at once
+use {
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, fmt},
+};
+
+/// A staking criterion, in the order the classification checks them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Criterion {
+    Screening,
+    VoteAccount,
+    AuthorizedWithdrawer,
+    InfrastructureConcentration,
+    SelfStake,
+    Commission,
+    TestnetParticipation,
+    VoteCredits,
+    ReleaseVersion,
+    VoteLag,
+    BlockProduction,
+    BonusReputation,
+    BonusActivatedStake,
+    IdentityChange,
+    HostingProvider,
+}
+
+impl Criterion {
+    pub const ALL: &'static [Criterion] = &[
+        Criterion::Screening,
+        Criterion::VoteAccount,
+        Criterion::AuthorizedWithdrawer,
+        Criterion::InfrastructureConcentration,
+        Criterion::SelfStake,
+        Criterion::Commission,
+        Criterion::TestnetParticipation,
+        Criterion::VoteCredits,
+        Criterion::ReleaseVersion,
+        Criterion::VoteLag,
+        Criterion::BlockProduction,
+        Criterion::BonusReputation,
+        Criterion::BonusActivatedStake,
+        Criterion::IdentityChange,
+        Criterion::HostingProvider,
+    ];
+}
+
+impl fmt::Display for Criterion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(name)) => write!(f, "{}", name),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+/// Criteria failed by a validator, with the reason of each failure
+pub type FailedCriteria = BTreeMap<Criterion, String>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CriterionOutcome {
+    pub criterion: Criterion,
+    pub passed: bool,
+
+    /// Reason of the failure, if failed
+    pub reason: Option<String>,
+}
+
+/// The outcome of every criterion, given the criteria a validator failed. Criteria the policy
+/// does not enforce pass unless failed
+pub fn breakdown(failed_criteria: &FailedCriteria) -> Vec<CriterionOutcome> {
+    Criterion::ALL
+        .iter()
+        .map(|criterion| CriterionOutcome {
+            criterion: *criterion,
+            passed: !failed_criteria.contains_key(criterion),
+            reason: failed_criteria.get(criterion).cloned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_breakdown() {
+        let mut failed_criteria = FailedCriteria::new();
+        failed_criteria.insert(
+            Criterion::Commission,
+            "commission is too high: 100% commission".to_string(),
+        );
+        let outcomes = breakdown(&failed_criteria);
+        assert_eq!(outcomes.len(), Criterion::ALL.len());
+        assert_eq!(
+            outcomes
+                .iter()
+                .filter(|outcome| !outcome.passed)
+                .collect::<Vec<_>>(),
+            vec![&CriterionOutcome {
+                criterion: Criterion::Commission,
+                passed: false,
+                reason: Some("commission is too high: 100% commission".to_string()),
+            }]
+        );
+        assert_eq!(Criterion::VoteCredits.to_string(), "vote_credits");
+
+        let yaml = serde_yaml::to_string(&failed_criteria).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<FailedCriteria>(&yaml).unwrap(),
+            failed_criteria
+        );
+    }
+}
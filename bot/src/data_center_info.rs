@@ -1,14 +1,15 @@
 use {
-    crate::validators_app,
+    crate::{ip_to_asn::IpToAsn, validators_app},
     log::*,
     serde::{Deserialize, Serialize},
+    solana_client::rpc_client::RpcClient,
     solana_sdk::pubkey::Pubkey,
     std::{collections::HashMap, error, str::FromStr},
 };
 
 const DATA_CENTER_ID_UNKNOWN: &str = "0-Unknown";
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct DataCenterId {
     pub asn: u64,
     pub location: String,
@@ -36,6 +37,13 @@ impl std::str::FromStr for DataCenterId {
     }
 }
 
+impl DataCenterId {
+    /// Country code the location begins with, such as `DE` of `DE-Falkenstein`
+    pub fn country(&self) -> &str {
+        self.location.split('-').next().unwrap_or_default()
+    }
+}
+
 impl std::fmt::Display for DataCenterId {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}-{}", self.asn, self.location)
@@ -78,49 +86,21 @@ pub struct DataCenters {
     pub by_identity: HashMap<Pubkey, DataCenterId>,
 }
 
-pub fn get(cluster: &str) -> Result<DataCenters, Box<dyn error::Error>> {
-    let cluster_json = match cluster {
-        "mainnet-beta" => validators_app::ClusterJson::MainnetBeta,
-        "testnet" => validators_app::ClusterJson::Testnet,
-        _ => return Err(format!("Unsupported cluster: {}", cluster).into()),
-    };
-
-    let token = std::env::var("VALIDATORS_APP_TOKEN")?;
-    let client = validators_app::Client::new(token, cluster_json);
-    let validators = client.validators(None, None)?;
+/// Data centers of the `validators`, given by identity, stake and data center if known
+fn data_centers<I>(validators: I) -> DataCenters
+where
+    I: IntoIterator<Item = (Pubkey, u64, Option<DataCenterId>)>,
+{
     let mut data_center_map = HashMap::new();
     let mut total_stake = 0;
     let mut unknown_data_center_stake: u64 = 0;
 
     let mut by_identity = HashMap::new();
-    for v in validators.as_ref() {
-        let identity = v
-            .account
-            .as_ref()
-            .and_then(|pubkey| Pubkey::from_str(pubkey).ok());
-        let identity = if let Some(identity) = identity {
-            identity
-        } else {
-            warn!("No identity for: {:?}", v);
-            continue;
-        };
-
-        let stake = v.active_stake.unwrap_or(0);
-
-        let data_center = v
-            .data_center_key
-            .as_deref()
-            .or_else(|| {
-                unknown_data_center_stake = unknown_data_center_stake.saturating_add(stake);
-                None
-            })
-            .unwrap_or(DATA_CENTER_ID_UNKNOWN);
-        let data_center_id = DataCenterId::from_str(data_center)
-            .map_err(|e| {
-                unknown_data_center_stake = unknown_data_center_stake.saturating_add(stake);
-                e
-            })
-            .unwrap_or_default();
+    for (identity, stake, data_center_id) in validators {
+        let data_center_id = data_center_id.unwrap_or_else(|| {
+            unknown_data_center_stake = unknown_data_center_stake.saturating_add(stake);
+            DataCenterId::default()
+        });
 
         by_identity.insert(identity, data_center_id.clone());
 
@@ -144,5 +124,80 @@ pub fn get(cluster: &str) -> Result<DataCenters, Box<dyn error::Error>> {
             i
         })
         .collect();
-    Ok(DataCenters { info, by_identity })
+    DataCenters { info, by_identity }
+}
+
+pub fn get(cluster: &str) -> Result<DataCenters, Box<dyn error::Error>> {
+    let cluster_json = match cluster {
+        "mainnet-beta" => validators_app::ClusterJson::MainnetBeta,
+        "testnet" => validators_app::ClusterJson::Testnet,
+        _ => return Err(format!("Unsupported cluster: {}", cluster).into()),
+    };
+
+    let token = std::env::var("VALIDATORS_APP_TOKEN")?;
+    let client = validators_app::Client::new(token, cluster_json);
+    let validators = client.validators(None, None)?;
+
+    Ok(data_centers(validators.as_ref().iter().filter_map(|v| {
+        let identity = v
+            .account
+            .as_ref()
+            .and_then(|pubkey| Pubkey::from_str(pubkey).ok());
+        let identity = if let Some(identity) = identity {
+            identity
+        } else {
+            warn!("No identity for: {:?}", v);
+            return None;
+        };
+
+        let stake = v.active_stake.unwrap_or(0);
+        let data_center_id = v
+            .data_center_key
+            .as_deref()
+            .and_then(|data_center| DataCenterId::from_str(data_center).ok());
+        Some((identity, stake, data_center_id))
+    })))
+}
+
+/// Resolve the data centers offline, from the gossip address of each validator and the
+/// `ip_to_asn` database. The location of a data center is the country its ASN is registered in
+pub fn get_offline(
+    rpc_client: &RpcClient,
+    ip_to_asn: &IpToAsn,
+) -> Result<DataCenters, Box<dyn error::Error>> {
+    let gossip_ips = rpc_client
+        .get_cluster_nodes()?
+        .into_iter()
+        .filter_map(|rpc_contact_info| {
+            Some((
+                Pubkey::from_str(&rpc_contact_info.pubkey).ok()?,
+                rpc_contact_info.gossip?.ip(),
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let vote_accounts = rpc_client.get_vote_accounts()?;
+    let mut stake_by_identity = HashMap::<Pubkey, u64>::new();
+    for vote_account in vote_accounts
+        .current
+        .iter()
+        .chain(vote_accounts.delinquent.iter())
+    {
+        if let Ok(identity) = Pubkey::from_str(&vote_account.node_pubkey) {
+            *stake_by_identity.entry(identity).or_default() += vote_account.activated_stake;
+        }
+    }
+
+    Ok(data_centers(stake_by_identity.into_iter().map(
+        |(identity, stake)| {
+            let data_center_id = gossip_ips
+                .get(&identity)
+                .and_then(|ip| ip_to_asn.lookup(*ip))
+                .map(|(asn, country)| DataCenterId {
+                    asn,
+                    location: country.to_string(),
+                });
+            (identity, stake, data_center_id)
+        },
+    )))
 }
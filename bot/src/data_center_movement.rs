@@ -0,0 +1,177 @@
+//! Stake movements of a run summarized by data center and by country, showing whether the
+//! decisions of an epoch move stake toward or away from the locations already holding the most
+use {
+    crate::{data_center_info::DataCenterId, db::EpochClassificationV1},
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, hash::Hash},
+};
+
+/// Data centers listed in the epoch report, by largest net movement
+pub const TOP_DATA_CENTERS: usize = 10;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Movement {
+    pub gained_lamports: u64,
+    pub lost_lamports: u64,
+}
+
+impl Movement {
+    pub fn net_lamports(&self) -> i64 {
+        self.gained_lamports as i64 - self.lost_lamports as i64
+    }
+
+    fn add(&mut self, lamports: i64) {
+        if lamports > 0 {
+            self.gained_lamports += lamports as u64;
+        } else {
+            self.lost_lamports += -lamports as u64;
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DataCenterMovement {
+    /// Largest net movement first
+    pub by_data_center: Vec<(DataCenterId, Movement)>,
+    pub by_country: Vec<(String, Movement)>,
+}
+
+fn sorted<K: Ord>(movements: HashMap<K, Movement>) -> Vec<(K, Movement)> {
+    let mut movements = movements.into_iter().collect::<Vec<_>>();
+    movements.sort_by(|a, b| {
+        b.1.net_lamports()
+            .abs()
+            .cmp(&a.1.net_lamports().abs())
+            .then(a.0.cmp(&b.0))
+    });
+    movements
+}
+
+fn add<K: Eq + Hash>(movements: &mut HashMap<K, Movement>, key: K, lamports: i64) {
+    movements.entry(key).or_default().add(lamports);
+}
+
+/// The stake movements of `epoch_classification` by the data center its validators were
+/// observed at, unknown data centers included
+pub fn summarize(epoch_classification: &EpochClassificationV1) -> DataCenterMovement {
+    let stake_movement_lamports = match epoch_classification.stake_pool_summary.as_ref() {
+        Some(summary) => &summary.stake_movement_lamports,
+        None => return DataCenterMovement::default(),
+    };
+    let data_center_by_vote_address = epoch_classification
+        .validator_classifications
+        .iter()
+        .flat_map(|validator_classifications| validator_classifications.values())
+        .map(|classification| {
+            (
+                classification.vote_address,
+                classification.current_data_center.clone(),
+            )
+        })
+        .collect::<HashMap<Pubkey, _>>();
+
+    let mut by_data_center = HashMap::new();
+    let mut by_country = HashMap::new();
+    for (vote_address, lamports) in stake_movement_lamports {
+        if *lamports == 0 {
+            continue;
+        }
+        let data_center = data_center_by_vote_address
+            .get(vote_address)
+            .cloned()
+            .flatten()
+            .unwrap_or_default();
+        add(
+            &mut by_country,
+            data_center.country().to_string(),
+            *lamports,
+        );
+        add(&mut by_data_center, data_center, *lamports);
+    }
+
+    DataCenterMovement {
+        by_data_center: sorted(by_data_center),
+        by_country: sorted(by_country),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{db::ValidatorClassification, generic_stake_pool::StakePoolSummary},
+    };
+
+    #[test]
+    fn test_summarize() {
+        let data_center = |data_center: &str| data_center.parse::<DataCenterId>().unwrap();
+        let classification = |data_center: Option<DataCenterId>| ValidatorClassification {
+            vote_address: Pubkey::new_unique(),
+            current_data_center: data_center,
+            ..ValidatorClassification::default()
+        };
+        let falkenstein = classification(Some(data_center("24940-DE-Falkenstein")));
+        let frankfurt = classification(Some(data_center("16509-DE-Frankfurt")));
+        let ashburn = classification(Some(data_center("16509-US-Ashburn")));
+        let unknown = classification(None);
+        let stake_movement_lamports = vec![
+            (falkenstein.vote_address, 5),
+            (frankfurt.vote_address, -2),
+            (ashburn.vote_address, -7),
+            (unknown.vote_address, 1),
+        ]
+        .into_iter()
+        .collect();
+        let epoch_classification = EpochClassificationV1 {
+            validator_classifications: Some(
+                vec![falkenstein, frankfurt, ashburn, unknown]
+                    .into_iter()
+                    .map(|classification| (Pubkey::new_unique(), classification))
+                    .collect(),
+            ),
+            stake_pool_summary: Some(StakePoolSummary {
+                stake_movement_lamports,
+                ..StakePoolSummary::default()
+            }),
+            ..EpochClassificationV1::default()
+        };
+
+        let movement = summarize(&epoch_classification);
+        assert_eq!(
+            movement.by_country,
+            vec![
+                (
+                    "US".to_string(),
+                    Movement {
+                        gained_lamports: 0,
+                        lost_lamports: 7,
+                    }
+                ),
+                (
+                    "DE".to_string(),
+                    Movement {
+                        gained_lamports: 5,
+                        lost_lamports: 2,
+                    }
+                ),
+                (
+                    "Unknown".to_string(),
+                    Movement {
+                        gained_lamports: 1,
+                        lost_lamports: 0,
+                    }
+                ),
+            ]
+        );
+        assert_eq!(movement.by_data_center.len(), 4);
+        assert_eq!(
+            movement.by_data_center[0].0,
+            data_center("16509-US-Ashburn")
+        );
+
+        assert_eq!(
+            summarize(&EpochClassificationV1::default()),
+            DataCenterMovement::default()
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use {
     crate::{
+        config_drift::EffectiveConfig,
+        criteria::FailedCriteria,
         data_center_info::{DataCenterId, DataCenterInfo},
-        generic_stake_pool::ValidatorStakeState,
+        generic_stake_pool::{StakePoolSummary, ValidatorStakeState},
     },
     log::*,
     serde::{Deserialize, Serialize},
@@ -37,6 +39,46 @@ pub struct ValidatorClassification {
     // The identity of the staking program participant, used to establish a link between
     // testnet and mainnet validator classifications
     pub participant: Option<Pubkey>,
+
+    // Reputation score carried over from previous epochs. Each epoch the previous score decays
+    // and the current stake state is added: +1 for Bonus, 0 for Baseline and -1 for None
+    pub reputation: Option<f64>,
+
+    // Number of consecutive epochs in which the validator was far enough from its desired stake
+    // to require a stake movement
+    pub off_target_epochs: Option<usize>,
+
+    // Effective stake of the validator projected for the next epochs, accounting for the warmup
+    // and cooldown of its stake movements
+    pub projected_effective_stake: Option<Vec<u64>>,
+
+    // Stake delegated to the validator by the pool, averaged over the epoch from the time its
+    // stake movement was issued
+    pub time_weighted_stake: Option<u64>,
+
+    // Vote credits earned in the previous epoch, and their percentile among all validators of the
+    // cluster and among the validators eligible for the pool
+    pub epoch_credits: Option<u64>,
+    pub cluster_credits_percentile: Option<f64>,
+    pub pool_credits_percentile: Option<f64>,
+
+    // Most recent epoch in which the vote account was observed with a different validator
+    // identity than in the previous epoch
+    pub identity_change_epoch: Option<Epoch>,
+
+    // Authorized withdrawer of the vote account, and the epoch in which it changed to an address
+    // that is not in the withdrawer allowlist, while it is not allowlisted
+    pub authorized_withdrawer: Option<Pubkey>,
+    pub withdrawer_change_epoch: Option<Epoch>,
+
+    // Hosting provider of the validator's data center, if subject to the hosting policy, and the
+    // percentage by which the policy reduces the validator's bonus weight
+    pub hosting_provider: Option<String>,
+    pub bonus_weight_penalty: Option<u64>,
+
+    // Staking criteria the validator failed, whether or not they decided its stake state, with the
+    // reason of each failure
+    pub failed_criteria: Option<FailedCriteria>,
 }
 
 impl ValidatorClassification {
@@ -80,6 +122,19 @@ pub struct EpochClassificationV1 {
 
     // Informational notes regarding this epoch
     pub notes: Vec<String>,
+
+    // Unix timestamp, in seconds, of when stake was distributed for this epoch
+    pub timestamp: Option<u64>,
+
+    // Stake pool figures observed while distributing stake for this epoch
+    pub stake_pool_summary: Option<StakePoolSummary>,
+
+    // Settings the epoch was classified and staked with, and their hash. `None` for epochs
+    // recorded before the configuration was tracked
+    #[serde(default)]
+    pub effective_config: Option<EffectiveConfig>,
+    #[serde(default)]
+    pub config_hash: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -104,7 +159,7 @@ impl EpochClassification {
         }
     }
 
-    fn file_name<P>(epoch: Epoch, path: P) -> PathBuf
+    pub fn file_name<P>(epoch: Epoch, path: P) -> PathBuf
     where
         P: AsRef<Path>,
     {
@@ -171,9 +226,8 @@ impl EpochClassification {
         }
     }
 
-    // Loads the latest epoch that contains `Some(validator_classifications)`
-    // Returns `Ok(None)` if no epoch is available
-    pub fn load_latest<P>(path: P) -> Result<Option<(Epoch, Self)>, io::Error>
+    // Epochs with a saved EpochClassification, in ascending order
+    pub fn epochs<P>(path: P) -> Vec<Epoch>
     where
         P: AsRef<Path>,
     {
@@ -195,23 +249,52 @@ impl EpochClassification {
             }
         }
         epochs.sort_unstable();
+        epochs
+    }
 
-        if let Some(latest_epoch) = epochs.last() {
+    // Loads the latest epoch that contains `Some(validator_classifications)`
+    // Returns `Ok(None)` if no epoch is available
+    pub fn load_latest<P>(path: P) -> Result<Option<(Epoch, Self)>, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        if let Some(latest_epoch) = Self::epochs(&path).last() {
             Self::load_previous(*latest_epoch + 1, path)
         } else {
             Ok(None)
         }
     }
 
+    // File of the provisional classification of `epoch`, previewed before the epoch starts. It is
+    // never loaded as the classification of `epoch`
+    pub fn preview_file_name<P>(epoch: Epoch, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref().join(format!("preview-epoch-{}.yml", epoch))
+    }
+
     pub fn save<P>(&self, epoch: Epoch, path: P) -> Result<(), io::Error>
     where
         P: AsRef<Path>,
     {
+        fs::create_dir_all(&path)?;
+        self.write(&Self::file_name(epoch, path))
+    }
+
+    pub fn save_preview<P>(&self, epoch: Epoch, path: P) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(&path)?;
+        self.write(&Self::preview_file_name(epoch, path))
+    }
+
+    fn write(&self, file_name: &Path) -> Result<(), io::Error> {
         let serialized = serde_yaml::to_string(self)
             .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{:?}", err)))?;
 
-        fs::create_dir_all(&path)?;
-        let mut file = File::create(Self::file_name(epoch, path))?;
+        let mut file = File::create(file_name)?;
         file.write_all(&serialized.into_bytes())?;
 
         Ok(())
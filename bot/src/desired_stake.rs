@@ -0,0 +1,306 @@
+use {
+    crate::generic_stake_pool::{ValidatorStake, ValidatorStakeState, DEFAULT_BONUS_WEIGHT},
+    serde::Deserialize,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::HashSet,
+        error, fmt,
+        fs::File,
+        io::{self, BufRead},
+        path::{Path, PathBuf},
+        str::FromStr,
+    },
+};
+
+/// Input that contributes to the desired validator stake
+#[derive(Debug, Clone, PartialEq)]
+pub enum DesiredStakeSource {
+    /// The validator classification performed by the bot
+    Classifier,
+    /// Baseline stake for every validator approved in the on-chain registry
+    Registry,
+    /// JSON file listing the desired stake of validators
+    File(PathBuf),
+    /// Validator list in the format of the Solana Foundation deployments
+    Foundation(PathBuf),
+    /// JSON lines of desired stake entries read from the standard input, such as the output of an
+    /// external scoring tool piped into the bot
+    Stdin,
+}
+
+impl FromStr for DesiredStakeSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classifier" => Ok(Self::Classifier),
+            "registry" => Ok(Self::Registry),
+            "-" => Ok(Self::Stdin),
+            _ => match (s.strip_prefix("file:"), s.strip_prefix("foundation:")) {
+                (Some(path), _) if !path.is_empty() => Ok(Self::File(PathBuf::from(path))),
+                (_, Some(path)) if !path.is_empty() => Ok(Self::Foundation(PathBuf::from(path))),
+                _ => Err(format!(
+                    "Invalid desired stake source {}, expected classifier, registry, file:PATH, \
+                     foundation:PATH or -",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for DesiredStakeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Classifier => write!(f, "classifier"),
+            Self::Registry => write!(f, "registry"),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Foundation(path) => write!(f, "foundation:{}", path.display()),
+            Self::Stdin => write!(f, "-"),
+        }
+    }
+}
+
+// Entry of a desired stake file, with the addresses in base58
+#[derive(Deserialize)]
+struct DesiredStakeEntry {
+    identity: String,
+    vote_address: String,
+    stake_state: ValidatorStakeState,
+    bonus_weight: Option<u64>,
+}
+
+impl DesiredStakeEntry {
+    /// The desired stake of the entry, read from `name`
+    fn validator_stake(self, name: &str) -> Result<ValidatorStake, String> {
+        let parse = |address: &str| {
+            Pubkey::from_str(address)
+                .map_err(|err| format!("Invalid address {} in {}: {}", address, name, err))
+        };
+        Ok(ValidatorStake {
+            bonus_weight: self.bonus_weight.unwrap_or(DEFAULT_BONUS_WEIGHT),
+            ..ValidatorStake::new(
+                parse(&self.identity)?,
+                parse(&self.vote_address)?,
+                self.stake_state,
+            )
+        })
+    }
+}
+
+/// Load a JSON desired stake file, an array of
+/// `{"identity": ..., "vote_address": ..., "stake_state": "Bonus", "bonus_weight": 100}` objects
+/// where `bonus_weight` is optional
+pub fn load<P>(path: P) -> Result<Vec<ValidatorStake>, Box<dyn error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let file =
+        File::open(path).map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+    let entries: Vec<DesiredStakeEntry> = serde_json::from_reader(file)
+        .map_err(|err| format!("Invalid desired stake file {}: {}", path.display(), err))?;
+
+    entries
+        .into_iter()
+        .map(|entry| entry.validator_stake(&path.display().to_string()))
+        .collect()
+}
+
+/// Load desired stake entries from JSON lines, one object per line in the format of `load`.
+/// Blank lines are skipped. Reading no entry at all is an error, as a failed producer upstream
+/// of a pipe would otherwise remove the stake of every validator
+pub fn load_lines<R>(reader: R, name: &str) -> Result<Vec<ValidatorStake>, Box<dyn error::Error>>
+where
+    R: BufRead,
+{
+    let mut validator_stake = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| format!("Unable to read {}: {}", name, err))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: DesiredStakeEntry = serde_json::from_str(&line)
+            .map_err(|err| format!("Invalid desired stake in {} line {}: {}", name, i + 1, err))?;
+        validator_stake.push(entry.validator_stake(name)?);
+    }
+    if validator_stake.is_empty() {
+        return Err(format!("No desired stake read from {}", name).into());
+    }
+    Ok(validator_stake)
+}
+
+/// Load the desired stake entries piped into the standard input
+pub fn load_stdin() -> Result<Vec<ValidatorStake>, Box<dyn error::Error>> {
+    let stdin = io::stdin();
+    let validator_stake = load_lines(stdin.lock(), "stdin")?;
+    Ok(validator_stake)
+}
+
+/// Merge the desired validator stake of several sources, given in order of precedence. A
+/// validator takes its desired stake from the first source that lists it; any different desired
+/// stake from a later source is reported as a conflict
+pub fn merge(sources: Vec<(String, Vec<ValidatorStake>)>) -> (Vec<ValidatorStake>, Vec<String>) {
+    let mut merged: Vec<(&str, ValidatorStake)> = vec![];
+    let mut conflicts = vec![];
+
+    for (name, validator_stake) in &sources {
+        let mut seen = HashSet::new();
+        for vs in validator_stake {
+            if !seen.insert(vs.identity) {
+                conflicts.push(format!("{} lists {} more than once", name, vs.identity));
+                continue;
+            }
+            match merged.iter().find(|(_, m)| m.identity == vs.identity) {
+                Some((winner, m)) => {
+                    if m != vs {
+                        conflicts.push(format!(
+                            "{}: {} desires {:?} stake via {}, {} takes precedence with {:?} \
+                             stake via {}",
+                            vs.identity,
+                            name,
+                            vs.stake_state,
+                            vs.vote_address,
+                            winner,
+                            m.stake_state,
+                            m.vote_address
+                        ));
+                    }
+                }
+                None => merged.push((name.as_str(), vs.clone())),
+            }
+        }
+    }
+
+    (merged.into_iter().map(|(_, vs)| vs).collect(), conflicts)
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, std::io::Write};
+
+    #[test]
+    fn test_source_from_str() {
+        assert_eq!(
+            "classifier".parse::<DesiredStakeSource>(),
+            Ok(DesiredStakeSource::Classifier)
+        );
+        assert_eq!(
+            "file:overrides.json".parse::<DesiredStakeSource>(),
+            Ok(DesiredStakeSource::File(PathBuf::from("overrides.json")))
+        );
+        assert!("file:".parse::<DesiredStakeSource>().is_err());
+        assert_eq!(
+            "foundation:validators.yml".parse::<DesiredStakeSource>(),
+            Ok(DesiredStakeSource::Foundation(PathBuf::from(
+                "validators.yml"
+            )))
+        );
+        assert!("url".parse::<DesiredStakeSource>().is_err());
+        assert_eq!(
+            "-".parse::<DesiredStakeSource>(),
+            Ok(DesiredStakeSource::Stdin)
+        );
+        assert_eq!(
+            DesiredStakeSource::File(PathBuf::from("a.json")).to_string(),
+            "file:a.json"
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let identity = Pubkey::new_unique();
+        let vote_address = Pubkey::new_unique();
+        let path = std::env::temp_dir().join(format!("desired-stake-{}.json", identity));
+        let mut file = File::create(&path).unwrap();
+        write!(
+            file,
+            r#"[{{"identity": "{}", "vote_address": "{}", "stake_state": "Bonus"}}]"#,
+            identity, vote_address
+        )
+        .unwrap();
+
+        assert_eq!(
+            load(&path).unwrap(),
+            vec![ValidatorStake::new(
+                identity,
+                vote_address,
+                ValidatorStakeState::Bonus
+            )]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_lines() {
+        let identity = Pubkey::new_unique();
+        let vote_address = Pubkey::new_unique();
+        let lines = format!(
+            "{{\"identity\": \"{}\", \"vote_address\": \"{}\", \"stake_state\": \"Bonus\", \
+             \"bonus_weight\": 150}}\n\n",
+            identity, vote_address
+        );
+
+        assert_eq!(
+            load_lines(lines.as_bytes(), "stdin").unwrap(),
+            vec![ValidatorStake {
+                bonus_weight: 150,
+                ..ValidatorStake::new(identity, vote_address, ValidatorStakeState::Bonus)
+            }]
+        );
+        assert!(load_lines("".as_bytes(), "stdin").is_err());
+        assert!(load_lines("[]\n".as_bytes(), "stdin")
+            .unwrap_err()
+            .to_string()
+            .contains("line 1"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+        let vote =
+            |identity: &Pubkey| Pubkey::create_with_seed(identity, "vote", identity).unwrap();
+
+        let (merged, conflicts) = merge(vec![
+            (
+                "file:overrides.json".to_string(),
+                vec![ValidatorStake::new(a, vote(&a), ValidatorStakeState::None)],
+            ),
+            (
+                "classifier".to_string(),
+                vec![
+                    ValidatorStake::new(a, vote(&a), ValidatorStakeState::Bonus),
+                    ValidatorStake::new(b, vote(&b), ValidatorStakeState::Baseline),
+                ],
+            ),
+            (
+                "registry".to_string(),
+                vec![
+                    ValidatorStake::new(b, vote(&b), ValidatorStakeState::Baseline),
+                    ValidatorStake::new(c, vote(&c), ValidatorStakeState::Baseline),
+                ],
+            ),
+        ]);
+
+        assert_eq!(
+            merged,
+            vec![
+                ValidatorStake::new(a, vote(&a), ValidatorStakeState::None),
+                ValidatorStake::new(b, vote(&b), ValidatorStakeState::Baseline),
+                ValidatorStake::new(c, vote(&c), ValidatorStakeState::Baseline),
+            ]
+        );
+        assert_eq!(
+            conflicts,
+            vec![format!(
+                "{}: classifier desires Bonus stake via {}, file:overrides.json takes \
+                 precedence with None stake via {}",
+                a,
+                vote(&a),
+                vote(&a)
+            )]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use {
+    crate::{analytics::EpochDecisions, schema},
+    lettre::{
+        message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
+        transport::smtp::authentication::Credentials,
+        Message, SmtpTransport, Transport,
+    },
+    log::*,
+    solana_sdk::native_token::LAMPORTS_PER_SOL,
+    std::{env, error},
+};
+
+/// Environment variables holding the credentials of the SMTP relay, if it requires any
+pub const USERNAME_ENV_VAR: &str = "SMTP_USERNAME";
+pub const PASSWORD_ENV_VAR: &str = "SMTP_PASSWORD";
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Body of the report email: the notes of the run, then a table of the decision for every
+/// validator
+pub fn html_report(notes: &[String], decisions: &EpochDecisions) -> String {
+    let mut html = vec![format!(
+        "<h2>{} epoch {}</h2>",
+        escape_html(&decisions.cluster),
+        decisions.epoch
+    )];
+
+    html.push("<ul>".to_string());
+    for note in notes {
+        html.push(format!("<li>{}</li>", escape_html(note)));
+    }
+    html.push("</ul>".to_string());
+
+    html.push(
+        "<table><tr><th>Identity</th><th>Vote address</th><th>Stake state</th><th>Reason</th>\
+         <th>Stake movement (SOL)</th></tr>"
+            .to_string(),
+    );
+    for validator in &decisions.validators {
+        html.push(format!(
+            "<tr><td>{}</td><td>{}</td><td>{:?}</td><td>{}</td><td>{}</td></tr>",
+            validator.identity,
+            validator.vote_address,
+            validator.stake_state,
+            escape_html(&validator.stake_state_reason),
+            validator
+                .stake_movement_lamports
+                .map(|lamports| (lamports as f64 / LAMPORTS_PER_SOL as f64).to_string())
+                .unwrap_or_default()
+        ));
+    }
+    html.push("</table>".to_string());
+    html.join("\n")
+}
+
+/// Sends the epoch report by email, for compliance workflows that are email based
+pub struct Mailer {
+    transport: SmtpTransport,
+    sender: Mailbox,
+    recipients: Vec<Mailbox>,
+}
+
+impl Mailer {
+    /// Mailer sending from `sender` to `recipients` through the SMTP `relay`, over TLS,
+    /// authenticating with the credentials in `USERNAME_ENV_VAR` and `PASSWORD_ENV_VAR` if set
+    pub fn new(
+        relay: &str,
+        sender: &str,
+        recipients: &[String],
+    ) -> Result<Self, Box<dyn error::Error>> {
+        let parse = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|err| format!("Invalid email address {}: {}", address, err))
+        };
+
+        let mut transport = SmtpTransport::relay(relay)
+            .map_err(|err| format!("Invalid SMTP relay {}: {}", relay, err))?;
+        if let (Ok(username), Ok(password)) =
+            (env::var(USERNAME_ENV_VAR), env::var(PASSWORD_ENV_VAR))
+        {
+            transport = transport.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            sender: parse(sender)?,
+            recipients: recipients
+                .iter()
+                .map(|recipient| parse(recipient))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Send the report of `decisions`, as an HTML table with the decisions attached as JSON
+    pub fn send(
+        &self,
+        notes: &[String],
+        decisions: &EpochDecisions,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut message = Message::builder()
+            .from(self.sender.clone())
+            .subject(format!(
+                "Stake-o-matic {} epoch {} report",
+                decisions.cluster, decisions.epoch
+            ));
+        for recipient in &self.recipients {
+            message = message.to(recipient.clone());
+        }
+        let message = message.multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::html(html_report(notes, decisions)))
+                .singlepart(
+                    Attachment::new(format!("epoch-{}-decisions.json", decisions.epoch)).body(
+                        schema::to_json_pretty(decisions)?,
+                        ContentType::parse("application/json")?,
+                    ),
+                ),
+        )?;
+
+        info!(
+            "Emailing the epoch {} report to {} recipients",
+            decisions.epoch,
+            self.recipients.len()
+        );
+        self.transport.send(&message)?;
+        Ok(())
+    }
+
+    /// Send a plain text `body` to `recipient` only, rather than to the report recipients
+    pub fn send_notice(
+        &self,
+        recipient: &str,
+        subject: &str,
+        body: &str,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let message = Message::builder()
+            .from(self.sender.clone())
+            .to(recipient
+                .parse::<Mailbox>()
+                .map_err(|err| format!("Invalid email address {}: {}", recipient, err))?)
+            .subject(subject)
+            .singlepart(SinglePart::plain(body.to_string()))?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{analytics::ValidatorDecision, generic_stake_pool::ValidatorStakeState},
+    };
+
+    #[test]
+    fn test_html_report() {
+        let decisions = EpochDecisions {
+            cluster: "testnet".to_string(),
+            epoch: 42,
+            timestamp: None,
+            validators: vec![ValidatorDecision {
+                identity: "identity".to_string(),
+                vote_address: "vote".to_string(),
+                stake_state: ValidatorStakeState::Bonus,
+                stake_state_reason: "<script>".to_string(),
+                notes: vec![],
+                data_center: None,
+                reputation: None,
+                epoch_credits: None,
+                cluster_credits_percentile: None,
+                pool_credits_percentile: None,
+                off_target_epochs: None,
+                hosting_provider: None,
+                bonus_weight_penalty: None,
+                failed_criteria: None,
+                stake_movement_lamports: Some(-1_500_000_000),
+                time_weighted_stake_lamports: None,
+            }],
+        };
+
+        let html = html_report(&["Bonus stake amount: 5 SOL".to_string()], &decisions);
+        assert!(html.starts_with("<h2>testnet epoch 42</h2>"));
+        assert!(html.contains("<li>Bonus stake amount: 5 SOL</li>"));
+        assert!(html.contains(
+            "<tr><td>identity</td><td>vote</td><td>Bonus</td><td>&lt;script&gt;</td>\
+             <td>-1.5</td></tr>"
+        ));
+    }
+}
@@ -0,0 +1,162 @@
+//! Reachability probes of the gossip and RPC endpoints that validators publish over gossip.
+//! Voting alone does not reveal a validator whose node is about to fall over
+use {
+    log::*,
+    serde::{Deserialize, Serialize},
+    serde_json::{json, Value},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::HashMap,
+        net::{SocketAddr, TcpStream},
+        thread,
+        time::Duration,
+    },
+};
+
+/// Endpoints of a validator, as published over gossip
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatorEndpoints {
+    pub identity: Pubkey,
+    pub gossip: Option<SocketAddr>,
+    pub rpc: Option<SocketAddr>,
+}
+
+/// Result of probing the endpoints of a validator. Endpoints that are not published are None
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EndpointProbe {
+    pub gossip_reachable: Option<bool>,
+    pub rpc_reachable: Option<bool>,
+
+    /// Solana release reported by the RPC endpoint
+    pub rpc_version: Option<String>,
+}
+
+impl EndpointProbe {
+    /// Notes on the health of the validator, empty if all published endpoints are reachable
+    pub fn notes(&self) -> Vec<String> {
+        let mut notes = vec![];
+        if self.gossip_reachable == Some(false) {
+            notes.push("gossip port unreachable".to_string());
+        }
+        if self.rpc_reachable == Some(false) {
+            notes.push("published RPC endpoint unreachable".to_string());
+        }
+        notes
+    }
+}
+
+// Every validator accepts TCP connections on its gossip port, for the IP echo service
+fn probe_gossip(gossip: &SocketAddr, timeout: Duration) -> bool {
+    TcpStream::connect_timeout(gossip, timeout).is_ok()
+}
+
+fn probe_rpc(rpc: &SocketAddr, timeout: Duration) -> Option<String> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()
+        .ok()?;
+    let response = client
+        .post(&format!("http://{}", rpc))
+        .json(&json!({"jsonrpc": "2.0", "id": 1, "method": "getVersion"}))
+        .send()
+        .ok()?
+        .json::<Value>()
+        .ok()?;
+    response["result"]["solana-core"]
+        .as_str()
+        .map(|version| version.to_string())
+}
+
+fn probe(endpoints: &ValidatorEndpoints, timeout: Duration) -> EndpointProbe {
+    let rpc_version = endpoints.rpc.and_then(|rpc| probe_rpc(&rpc, timeout));
+    EndpointProbe {
+        gossip_reachable: endpoints
+            .gossip
+            .map(|gossip| probe_gossip(&gossip, timeout)),
+        rpc_reachable: endpoints.rpc.map(|_| rpc_version.is_some()),
+        rpc_version,
+    }
+}
+
+/// Probe the endpoints of the `validators`, up to `parallelism` validators at a time, each
+/// endpoint giving up after `timeout`
+pub fn probe_endpoints(
+    validators: Vec<ValidatorEndpoints>,
+    timeout: Duration,
+    parallelism: usize,
+) -> HashMap<Pubkey, EndpointProbe> {
+    let parallelism = parallelism.max(1);
+    let chunk_size = (validators.len() + parallelism - 1) / parallelism;
+    if chunk_size == 0 {
+        return HashMap::default();
+    }
+
+    let threads = validators
+        .chunks(chunk_size)
+        .map(|chunk| {
+            let chunk = chunk.to_vec();
+            thread::spawn(move || {
+                chunk
+                    .into_iter()
+                    .map(|endpoints| (endpoints.identity, probe(&endpoints, timeout)))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let probes = threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap_or_default())
+        .collect::<HashMap<_, _>>();
+    info!(
+        "Probed the endpoints of {} validators: {} with an unreachable endpoint",
+        probes.len(),
+        probes
+            .values()
+            .filter(|probe| !probe.notes().is_empty())
+            .count()
+    );
+    probes
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, std::net::TcpListener};
+
+    #[test]
+    fn test_probe_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let reachable = listener.local_addr().unwrap();
+        let unreachable = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let validators = vec![
+            ValidatorEndpoints {
+                identity: Pubkey::new_unique(),
+                gossip: Some(reachable),
+                rpc: None,
+            },
+            ValidatorEndpoints {
+                identity: Pubkey::new_unique(),
+                gossip: Some(unreachable),
+                rpc: Some(unreachable),
+            },
+        ];
+        let probes = probe_endpoints(validators.clone(), Duration::from_secs(1), 4);
+
+        let healthy = &probes[&validators[0].identity];
+        assert_eq!(healthy.gossip_reachable, Some(true));
+        assert_eq!(healthy.rpc_reachable, None);
+        assert!(healthy.notes().is_empty());
+
+        assert_eq!(
+            probes[&validators[1].identity].notes(),
+            vec![
+                "gossip port unreachable".to_string(),
+                "published RPC endpoint unreachable".to_string()
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Per-validator enrichment of the classifier inputs, such as the authorized withdrawer of each
+//! vote account. With 2000+ validators one query after the other is slow, so the queries are
+//! spread over a pool of worker threads, and their results are cached by validator and epoch so
+//! that a rerun of the same epoch does not query them again
+use {
+    log::*,
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io::{self, Write},
+        path::{Path, PathBuf},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+    },
+};
+
+/// Number of epochs, before the one being enriched, whose cached results are kept
+const CACHED_EPOCHS: Epoch = 1;
+
+#[derive(Deserialize, Serialize)]
+struct CachedEnrichment<T> {
+    pubkey: String,
+    epoch: Epoch,
+    value: T,
+}
+
+/// Enrichment results of `T`, by validator and epoch
+pub struct EnrichmentCache<T> {
+    file_name: PathBuf,
+    entries: HashMap<(Pubkey, Epoch), T>,
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> EnrichmentCache<T> {
+    /// The cache of the `name` enrichment under `path`, empty if it was never saved
+    pub fn load<P>(name: &str, path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let file_name = path.as_ref().join(format!("{}-cache.yml", name));
+        let mut entries = HashMap::new();
+        if file_name.exists() {
+            let cached: Vec<CachedEnrichment<T>> = serde_yaml::from_reader(File::open(&file_name)?)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            for CachedEnrichment {
+                pubkey,
+                epoch,
+                value,
+            } in cached
+            {
+                if let Ok(pubkey) = pubkey.parse() {
+                    entries.insert((pubkey, epoch), value);
+                }
+            }
+        }
+        Ok(Self { file_name, entries })
+    }
+
+    pub fn get(&self, pubkey: &Pubkey, epoch: Epoch) -> Option<&T> {
+        self.entries.get(&(*pubkey, epoch))
+    }
+
+    pub fn insert(&mut self, pubkey: Pubkey, epoch: Epoch, value: T) {
+        self.entries.insert((pubkey, epoch), value);
+    }
+
+    /// Save the results of `epoch` and of the `CACHED_EPOCHS` before it, dropping older ones
+    pub fn save(&mut self, epoch: Epoch) -> Result<(), io::Error> {
+        self.entries
+            .retain(|(_, cached_epoch), _| *cached_epoch + CACHED_EPOCHS >= epoch);
+        let cached = self
+            .entries
+            .iter()
+            .map(|((pubkey, epoch), value)| CachedEnrichment {
+                pubkey: pubkey.to_string(),
+                epoch: *epoch,
+                value: value.clone(),
+            })
+            .collect::<Vec<_>>();
+        let serialized = serde_yaml::to_string(&cached)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        if let Some(parent) = self.file_name.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&self.file_name)?;
+        file.write_all(&serialized.into_bytes())?;
+        Ok(())
+    }
+}
+
+/// Enrich each of `pubkeys` for `epoch` with `enrich`, on up to `parallelism` worker threads.
+/// Results found in `cache` are reused, and new ones are added to it. Validators that fail to be
+/// enriched are left out of the result
+pub fn enrich<T, F>(
+    pubkeys: &[Pubkey],
+    epoch: Epoch,
+    parallelism: usize,
+    cache: &mut EnrichmentCache<T>,
+    enrich: F,
+) -> HashMap<Pubkey, T>
+where
+    T: Clone + Serialize + DeserializeOwned + Send + 'static,
+    F: Fn(&Pubkey) -> Result<T, String> + Send + Sync + 'static,
+{
+    let mut enriched = HashMap::new();
+    let mut queue = vec![];
+    for pubkey in pubkeys {
+        match cache.get(pubkey, epoch) {
+            Some(value) => {
+                enriched.insert(*pubkey, value.clone());
+            }
+            None => queue.push(*pubkey),
+        }
+    }
+    info!(
+        "Enriching {} validators, {} cached",
+        queue.len(),
+        enriched.len()
+    );
+
+    let workers = parallelism.max(1).min(queue.len());
+    let queue = Arc::new(Mutex::new(queue));
+    let enrich = Arc::new(enrich);
+    let (sender, receiver) = mpsc::channel();
+    let threads = (0..workers)
+        .map(|_| {
+            let queue = queue.clone();
+            let enrich = enrich.clone();
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let pubkey = match queue.lock().unwrap().pop() {
+                    Some(pubkey) => pubkey,
+                    None => break,
+                };
+                if sender.send((pubkey, enrich(&pubkey))).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+
+    for (pubkey, result) in receiver {
+        match result {
+            Ok(value) => {
+                cache.insert(pubkey, epoch, value.clone());
+                enriched.insert(pubkey, value);
+            }
+            Err(err) => warn!("Failed to enrich {}: {}", pubkey, err),
+        }
+    }
+    for thread in threads {
+        if thread.join().is_err() {
+            warn!("An enrichment worker panicked");
+        }
+    }
+    enriched
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        std::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn test_enrich() {
+        let path = std::env::temp_dir().join(format!("enrichment-{}", Pubkey::new_unique()));
+        let pubkeys = (0..100).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let failing = pubkeys[0];
+        let queries = Arc::new(AtomicUsize::new(0));
+        let enrich_with_count = |queries: Arc<AtomicUsize>| {
+            move |pubkey: &Pubkey| {
+                queries.fetch_add(1, Ordering::SeqCst);
+                if *pubkey == failing {
+                    Err("unavailable".to_string())
+                } else {
+                    Ok(pubkey.to_bytes()[0])
+                }
+            }
+        };
+
+        let mut cache = EnrichmentCache::load("test", &path).unwrap();
+        let enriched = enrich(
+            &pubkeys,
+            10,
+            8,
+            &mut cache,
+            enrich_with_count(queries.clone()),
+        );
+        assert_eq!(queries.load(Ordering::SeqCst), 100);
+        assert_eq!(enriched.len(), 99);
+        assert!(!enriched.contains_key(&failing));
+        assert_eq!(enriched[&pubkeys[1]], pubkeys[1].to_bytes()[0]);
+        cache.save(10).unwrap();
+
+        // Only the validator that failed is queried again for the same epoch
+        let mut cache = EnrichmentCache::load("test", &path).unwrap();
+        let enriched = enrich(
+            &pubkeys,
+            10,
+            8,
+            &mut cache,
+            enrich_with_count(queries.clone()),
+        );
+        assert_eq!(queries.load(Ordering::SeqCst), 101);
+        assert_eq!(enriched.len(), 99);
+
+        // Results of another epoch are not reused, and are dropped once too old
+        let enriched = enrich(
+            &pubkeys[1..3],
+            11,
+            8,
+            &mut cache,
+            enrich_with_count(queries.clone()),
+        );
+        assert_eq!(queries.load(Ordering::SeqCst), 103);
+        assert_eq!(enriched.len(), 2);
+        cache.save(12).unwrap();
+        let cache = EnrichmentCache::<u8>::load("test", &path).unwrap();
+        assert!(cache.get(&pubkeys[1], 10).is_none());
+        assert!(cache.get(&pubkeys[1], 11).is_some());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+//! Stake deposited directly to a validator of the pool by someone else, such as through the
+//! stake pool's stake deposit instruction. Between two epochs the balance of a validator only
+//! changes by the stake movements of the bot and the rewards it accrued, so that anything beyond
+//! them was deposited
+use {solana_sdk::pubkey::Pubkey, std::collections::HashMap};
+
+/// External deposit to each validator, by vote address, of at least `min_lamports`.
+///
+/// `previous_balances` and `previous_movements` are the balances and stake movements of the
+/// previous epoch, `rewards` the rewards each validator earned during it, and `balances` the
+/// balances of the current epoch, once the stake movements of the previous epoch completed
+pub fn detect(
+    previous_balances: &HashMap<Pubkey, u64>,
+    previous_movements: &HashMap<Pubkey, i64>,
+    rewards: &HashMap<Pubkey, u64>,
+    balances: &HashMap<Pubkey, u64>,
+    min_lamports: u64,
+) -> HashMap<Pubkey, u64> {
+    balances
+        .iter()
+        .filter_map(|(vote_address, balance)| {
+            // Validators added since the previous epoch have nothing to compare with
+            let previous_balance = previous_balances.get(vote_address)?;
+            let expected_balance = *previous_balance as i128
+                + previous_movements
+                    .get(vote_address)
+                    .copied()
+                    .unwrap_or_default() as i128
+                + rewards.get(vote_address).copied().unwrap_or_default() as i128;
+            let deposit = *balance as i128 - expected_balance;
+            if deposit >= min_lamports.max(1) as i128 {
+                Some((*vote_address, deposit as u64))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let rewarded = Pubkey::new_unique();
+        let moved = Pubkey::new_unique();
+        let deposited = Pubkey::new_unique();
+        let added = Pubkey::new_unique();
+
+        let previous_balances = vec![(rewarded, 1_000), (moved, 1_000), (deposited, 1_000)]
+            .into_iter()
+            .collect();
+        let previous_movements = vec![(moved, -500), (deposited, 200)].into_iter().collect();
+        let rewards = vec![(rewarded, 10), (moved, 5), (deposited, 10)]
+            .into_iter()
+            .collect();
+        let balances = vec![
+            (rewarded, 1_010),
+            (moved, 505),
+            (deposited, 1_710),
+            (added, 5_000),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            detect(
+                &previous_balances,
+                &previous_movements,
+                &rewards,
+                &balances,
+                100
+            ),
+            vec![(deposited, 500)].into_iter().collect()
+        );
+        assert!(detect(
+            &previous_balances,
+            &previous_movements,
+            &rewards,
+            &balances,
+            1_000
+        )
+        .is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+//! Atom feed of the epoch reports, one entry per epoch with its notes and a link to the report
+//! artifact, so that validators follow the decisions of each epoch with a feed reader
+use solana_sdk::clock::Epoch;
+
+/// Epochs listed in the feed, newest first, unless the artifact retention keeps fewer
+pub const MAX_ENTRIES: usize = 50;
+
+pub struct FeedEntry<'a> {
+    pub epoch: Epoch,
+
+    /// Unix timestamp, in seconds, of the epoch's decisions
+    pub updated: u64,
+
+    pub notes: &'a [String],
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// RFC 3339 form of a Unix timestamp, in UTC
+fn rfc3339(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+
+    // Civil date of a count of days since 1970-01-01, in the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// URL of the report of `epoch`, as `artifact::publish` lays out the artifacts under
+/// `report_base_url`
+pub fn report_url(report_base_url: &str, epoch: Epoch, report_file_name: &str) -> String {
+    format!(
+        "{}/epoch-{}/{}",
+        report_base_url.trim_end_matches('/'),
+        epoch,
+        report_file_name
+    )
+}
+
+/// Atom feed of `entries` for `cluster`, expected to be served as `feed_file_name` next to the
+/// epoch directories of `report_base_url`
+pub fn atom_feed(
+    cluster: &str,
+    report_base_url: &str,
+    report_file_name: &str,
+    feed_file_name: &str,
+    entries: &[FeedEntry],
+) -> String {
+    let feed_url = format!(
+        "{}/{}",
+        report_base_url.trim_end_matches('/'),
+        feed_file_name
+    );
+    let mut xml = vec![
+        r#"<?xml version="1.0" encoding="utf-8"?>"#.to_string(),
+        r#"<feed xmlns="http://www.w3.org/2005/Atom">"#.to_string(),
+        format!("<id>{}</id>", escape_xml(&feed_url)),
+        format!(
+            "<title>stake-o-matic {} epoch reports</title>",
+            escape_xml(cluster)
+        ),
+        format!(r#"<link rel="self" href="{}"/>"#, escape_xml(&feed_url)),
+        format!(
+            "<updated>{}</updated>",
+            rfc3339(
+                entries
+                    .iter()
+                    .map(|entry| entry.updated)
+                    .max()
+                    .unwrap_or_default()
+            )
+        ),
+        "<author><name>stake-o-matic</name></author>".to_string(),
+    ];
+
+    for entry in entries {
+        let url = report_url(report_base_url, entry.epoch, report_file_name);
+        xml.push("<entry>".to_string());
+        xml.push(format!("<id>{}</id>", escape_xml(&url)));
+        xml.push(format!(
+            "<title>{} epoch {}</title>",
+            escape_xml(cluster),
+            entry.epoch
+        ));
+        xml.push(format!(r#"<link href="{}"/>"#, escape_xml(&url)));
+        xml.push(format!("<updated>{}</updated>", rfc3339(entry.updated)));
+        xml.push(format!(
+            "<summary>{}</summary>",
+            escape_xml(&entry.notes.join("\n"))
+        ));
+        xml.push("</entry>".to_string());
+    }
+    xml.push("</feed>".to_string());
+    xml.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_625_097_599), "2021-06-30T23:59:59Z");
+    }
+
+    #[test]
+    fn test_atom_feed() {
+        let notes = vec![
+            "Bonus stake amount: 5 SOL".to_string(),
+            "Validator <a> & <b>".to_string(),
+        ];
+        let feed = atom_feed(
+            "Mainnet",
+            "https://example.com/reports/",
+            "Mainnet.md",
+            "Mainnet.atom",
+            &[
+                FeedEntry {
+                    epoch: 201,
+                    updated: 1_625_097_599,
+                    notes: &notes,
+                },
+                FeedEntry {
+                    epoch: 200,
+                    updated: 0,
+                    notes: &[],
+                },
+            ],
+        );
+        assert!(feed.contains("<id>https://example.com/reports/Mainnet.atom</id>"));
+        assert!(feed.contains("<updated>2021-06-30T23:59:59Z</updated>\n<author>"));
+        assert!(feed.contains(
+            "<entry>\n<id>https://example.com/reports/epoch-201/Mainnet.md</id>\n\
+             <title>Mainnet epoch 201</title>\n\
+             <link href=\"https://example.com/reports/epoch-201/Mainnet.md\"/>\n\
+             <updated>2021-06-30T23:59:59Z</updated>\n\
+             <summary>Bonus stake amount: 5 SOL\nValidator &lt;a&gt; &amp; &lt;b&gt;</summary>\n\
+             </entry>"
+        ));
+        assert_eq!(feed.matches("<entry>").count(), 2);
+        assert!(feed.ends_with("</feed>"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Validator lists in the format published by the Solana Foundation stake-o-matic deployments,
+//! so that operators migrating from the upstream bot keep their data
+use {
+    crate::{
+        db::ValidatorClassificationByIdentity,
+        generic_stake_pool::{ValidatorStake, ValidatorStakeState},
+    },
+    serde::{Deserialize, Serialize},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        error,
+        fs::{self, File},
+        path::Path,
+        str::FromStr,
+    },
+};
+
+/// Entry of a foundation validator list, with the addresses in base58
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FoundationValidator {
+    pub identity: String,
+    pub vote: String,
+    pub state: ValidatorStakeState,
+    #[serde(default)]
+    pub reason: String,
+}
+
+// Lists ending in .yml or .yaml are YAML, any other is JSON
+fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yml") | Some("yaml")
+    )
+}
+
+/// Load a foundation validator list as the desired stake of its validators
+pub fn load<P>(path: P) -> Result<Vec<ValidatorStake>, Box<dyn error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let file =
+        File::open(path).map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+    let validators: Vec<FoundationValidator> = if is_yaml(path) {
+        serde_yaml::from_reader(file).map_err(|err| err.to_string())
+    } else {
+        serde_json::from_reader(file).map_err(|err| err.to_string())
+    }
+    .map_err(|err| format!("Invalid validator list {}: {}", path.display(), err))?;
+
+    validators
+        .into_iter()
+        .map(|validator| {
+            let parse = |address: &str| {
+                Pubkey::from_str(address).map_err(|err| {
+                    format!("Invalid address {} in {}: {}", address, path.display(), err)
+                })
+            };
+            Ok(ValidatorStake::new(
+                parse(&validator.identity)?,
+                parse(&validator.vote)?,
+                validator.state,
+            ))
+        })
+        .collect()
+}
+
+/// The classified validators as a foundation validator list, sorted by identity
+pub fn from_classifications(
+    validator_classifications: &ValidatorClassificationByIdentity,
+) -> Vec<FoundationValidator> {
+    let mut validators = validator_classifications
+        .values()
+        .map(|vc| FoundationValidator {
+            identity: vc.identity.to_string(),
+            vote: vc.vote_address.to_string(),
+            state: vc.stake_state,
+            reason: vc.stake_state_reason.clone(),
+        })
+        .collect::<Vec<_>>();
+    validators.sort_by(|a, b| a.identity.cmp(&b.identity));
+    validators
+}
+
+pub fn save<P>(path: P, validators: &[FoundationValidator]) -> Result<(), Box<dyn error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let serialized = if is_yaml(path) {
+        serde_yaml::to_string(validators)?
+    } else {
+        serde_json::to_string_pretty(validators)?
+    };
+    fs::write(path, serialized)
+        .map_err(|err| format!("Unable to write {}: {}", path.display(), err).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let identity = Pubkey::new_unique();
+        let vote_address = Pubkey::new_unique();
+        let validators = vec![FoundationValidator {
+            identity: identity.to_string(),
+            vote: vote_address.to_string(),
+            state: ValidatorStakeState::Baseline,
+            reason: "Baseline stake".to_string(),
+        }];
+
+        for extension in &["yml", "json"] {
+            let path = std::env::temp_dir().join(format!("validators-{}.{}", identity, extension));
+            save(&path, &validators).unwrap();
+            assert_eq!(
+                load(&path).unwrap(),
+                vec![ValidatorStake::new(
+                    identity,
+                    vote_address,
+                    ValidatorStakeState::Baseline
+                )]
+            );
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -1,8 +1,21 @@
 use {
+    crate::{
+        bonus_ramp::BonusRampStage, liquidity_buffer::LiquidityBufferStage,
+        rebalance_plan::RebalancePlan,
+    },
     serde::{Deserialize, Serialize},
+    solana_sdk::{clock::Epoch, hash::hashv, pubkey::Pubkey},
+    std::{
+        collections::{HashMap, HashSet},
+        fmt,
+    },
+};
+
+#[cfg(feature = "rpc")]
+use {
+    crate::{pool_state::PoolState, vote_account_cache::VoteAccountCache},
     solana_client::rpc_client::RpcClient,
-    solana_sdk::pubkey::Pubkey,
-    std::error,
+    std::{error, sync::Arc},
 };
 
 #[derive(Debug, PartialEq, Clone, Copy, Deserialize, Serialize)]
@@ -18,13 +31,476 @@ impl Default for ValidatorStakeState {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Smallest stake adjustment worth a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MinStakeChange {
+    pub lamports: u64,
+
+    // If Some(), adjustments smaller than this percentage of the validator's desired stake are
+    // skipped as well
+    pub target_percentage: Option<f64>,
+}
+
+impl MinStakeChange {
+    /// The smallest adjustment made towards `desired_balance`
+    pub fn amount(&self, desired_balance: u64) -> u64 {
+        let target_amount = self
+            .target_percentage
+            .map(|percentage| (desired_balance as f64 * percentage / 100.) as u64)
+            .unwrap_or_default();
+        self.lamports.max(target_amount)
+    }
+}
+
+/// Band around each validator's desired stake within which its stake is left alone, so that
+/// reward accrual does not trigger a small rebalance every epoch
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StakeTolerance {
+    // Percentage of the desired stake that a validator may fall short of
+    pub below_percentage: f64,
+
+    // Percentage of the desired stake that a validator may exceed
+    pub above_percentage: f64,
+}
+
+impl StakeTolerance {
+    pub fn contains(&self, balance: u64, desired_balance: u64) -> bool {
+        let desired = desired_balance as f64;
+        let balance = balance as f64;
+        balance >= desired * (1. - self.below_percentage / 100.)
+            && balance <= desired * (1. + self.above_percentage / 100.)
+    }
+}
+
+/// Bonus weight of a validator receiving a regular share of the bonus stake
+pub const DEFAULT_BONUS_WEIGHT: u64 = 100;
+
+fn default_bonus_weight() -> u64 {
+    DEFAULT_BONUS_WEIGHT
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ValidatorStake {
     pub identity: Pubkey,
     pub vote_address: Pubkey,
     pub stake_state: ValidatorStakeState,
+
+    // Share of the bonus stake relative to the other Bonus validators, ignored unless
+    // `stake_state` is `ValidatorStakeState::Bonus`
+    #[serde(default = "default_bonus_weight")]
+    pub bonus_weight: u64,
+
+    // Autonomous system number of the validator's data center, if known
+    #[serde(default)]
+    pub asn: Option<u64>,
+
+    // Reputation score of the validator, ranking the Baseline validators funded first when the
+    // stake does not cover the baseline
+    #[serde(default)]
+    pub score: Option<f64>,
+}
+
+impl ValidatorStake {
+    pub fn new(identity: Pubkey, vote_address: Pubkey, stake_state: ValidatorStakeState) -> Self {
+        Self {
+            identity,
+            vote_address,
+            stake_state,
+            bonus_weight: DEFAULT_BONUS_WEIGHT,
+            asn: None,
+            score: None,
+        }
+    }
+}
+
+/// Order `desired_validator_stake` by a hash of `seed` and each vote address. Stake pools
+/// process validators with equal balances in the order they are given, so the same seed always
+/// breaks ties the same way
+pub fn sort_by_seed(desired_validator_stake: &mut [ValidatorStake], seed: u64) {
+    desired_validator_stake
+        .sort_by_cached_key(|vs| hashv(&[&seed.to_le_bytes(), vs.vote_address.as_ref()]));
+}
+
+/// Sum of the bonus weights of all Bonus validators in `desired_validator_stake`
+pub fn total_bonus_weight(desired_validator_stake: &[ValidatorStake]) -> u64 {
+    desired_validator_stake
+        .iter()
+        .filter(|vs| vs.stake_state == ValidatorStakeState::Bonus)
+        .map(|vs| vs.bonus_weight)
+        .sum()
+}
+
+/// The portion of `total_bonus_stake_amount` owed to a Bonus validator with `bonus_weight`
+pub fn bonus_stake_amount(
+    total_bonus_stake_amount: u64,
+    total_bonus_weight: u64,
+    bonus_weight: u64,
+) -> u64 {
+    if total_bonus_weight == 0 {
+        0
+    } else {
+        (total_bonus_stake_amount as u128 * bonus_weight as u128 / total_bonus_weight as u128)
+            as u64
+    }
+}
+
+/// Bonus stake owed to each Bonus validator in `desired_validator_stake`, by vote address.
+///
+/// Each share is rounded down, and the lamports left over are then spread over the validators with
+/// bonus weight, one more to each of the first ones in the order of `desired_validator_stake`, so
+/// that all of `total_bonus_stake_amount` is allocated. Validators without bonus weight only share
+/// the lamports left over when no validator has any
+pub fn bonus_stake_amounts(
+    total_bonus_stake_amount: u64,
+    desired_validator_stake: &[ValidatorStake],
+) -> HashMap<Pubkey, u64> {
+    let total_bonus_weight = total_bonus_weight(desired_validator_stake);
+
+    let mut bonus_stake_amounts = desired_validator_stake
+        .iter()
+        .filter(|vs| vs.stake_state == ValidatorStakeState::Bonus)
+        .map(|vs| {
+            (
+                vs.vote_address,
+                vs.bonus_weight,
+                bonus_stake_amount(
+                    total_bonus_stake_amount,
+                    total_bonus_weight,
+                    vs.bonus_weight,
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let allocated = bonus_stake_amounts
+        .iter()
+        .map(|(_, _, amount)| *amount)
+        .sum::<u64>();
+    let remainder = total_bonus_stake_amount.saturating_sub(allocated);
+    let mut recipients = bonus_stake_amounts
+        .iter_mut()
+        .filter(|(_, bonus_weight, _)| total_bonus_weight == 0 || *bonus_weight > 0)
+        .map(|(_, _, amount)| amount)
+        .collect::<Vec<_>>();
+    let num_recipients = recipients.len() as u64;
+    for (i, amount) in recipients.iter_mut().enumerate() {
+        **amount += remainder / num_recipients + u64::from((i as u64) < remainder % num_recipients);
+    }
+
+    bonus_stake_amounts
+        .into_iter()
+        .map(|(vote_address, _, amount)| (vote_address, amount))
+        .collect()
+}
+
+/// Cap the desired stake of each ASN at `max_asn_stake_percentage` of `total_stake_amount` by
+/// lowering the bonus stake of its Bonus validators, handing the bonus stake freed this way to the
+/// Bonus validators of the ASNs below the cap as `bonus_stake_amounts` does, so that none of it is
+/// lost to rounding. Validators of unknown ASN are never capped.
+///
+/// Returns the capped bonus stake amounts and the ASNs that were capped
+pub fn cap_bonus_stake_by_asn(
+    total_stake_amount: u64,
+    baseline_stake_amount: u64,
+    max_asn_stake_percentage: f64,
+    desired_validator_stake: &[ValidatorStake],
+    bonus_stake_amounts: &HashMap<Pubkey, u64>,
+) -> (HashMap<Pubkey, u64>, Vec<u64>) {
+    let max_asn_stake_amount = (total_stake_amount as f64 * max_asn_stake_percentage / 100.) as u64;
+    let mut bonus_stake_amounts = bonus_stake_amounts.clone();
+    let mut capped_asns = HashSet::new();
+
+    // Each pass caps at least one more ASN, or stops
+    loop {
+        let mut asn_stake_amounts: HashMap<u64, (u64, u64)> = HashMap::new();
+        for vs in desired_validator_stake {
+            if let Some(asn) = vs.asn {
+                let (stake, bonus_stake) = asn_stake_amounts.entry(asn).or_default();
+                match vs.stake_state {
+                    ValidatorStakeState::None => {}
+                    ValidatorStakeState::Baseline => *stake += baseline_stake_amount,
+                    ValidatorStakeState::Bonus => {
+                        *stake += bonus_stake_amounts[&vs.vote_address];
+                        *bonus_stake += bonus_stake_amounts[&vs.vote_address];
+                    }
+                }
+            }
+        }
+
+        let over_cap = asn_stake_amounts
+            .into_iter()
+            .filter(|(asn, (stake, bonus_stake))| {
+                *stake > max_asn_stake_amount && *bonus_stake > 0 && !capped_asns.contains(asn)
+            })
+            .collect::<HashMap<_, _>>();
+        if over_cap.is_empty() {
+            break;
+        }
+
+        let mut freed_stake_amount = 0;
+        for vs in desired_validator_stake
+            .iter()
+            .filter(|vs| vs.stake_state == ValidatorStakeState::Bonus)
+        {
+            if let Some((stake, bonus_stake)) = vs.asn.and_then(|asn| over_cap.get(&asn)) {
+                let excess = stake - max_asn_stake_amount;
+                let amount = bonus_stake_amounts.get_mut(&vs.vote_address).unwrap();
+                let reduction = if excess >= *bonus_stake {
+                    *amount
+                } else {
+                    (*amount as u128 * excess as u128 / *bonus_stake as u128) as u64
+                };
+                *amount -= reduction;
+                freed_stake_amount += reduction;
+            }
+        }
+        capped_asns.extend(over_cap.keys());
+
+        let recipients = desired_validator_stake
+            .iter()
+            .filter(|vs| {
+                vs.stake_state == ValidatorStakeState::Bonus
+                    && !vs
+                        .asn
+                        .map(|asn| capped_asns.contains(&asn))
+                        .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for (vote_address, amount) in self::bonus_stake_amounts(freed_stake_amount, &recipients) {
+            *bonus_stake_amounts.get_mut(&vote_address).unwrap() += amount;
+        }
+    }
+
+    let mut capped_asns = capped_asns.into_iter().collect::<Vec<_>>();
+    capped_asns.sort_unstable();
+    (bonus_stake_amounts, capped_asns)
+}
+
+/// Stake pool figures observed while distributing stake
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StakePoolSummary {
+    // Stake held by the validators before any stake movement, by desired stake state
+    pub none_stake_lamports: u64,
+    pub baseline_stake_lamports: u64,
+    pub bonus_stake_lamports: u64,
+
+    // Reserve stake remaining available after the stake movements
+    pub reserve_stake_lamports: u64,
+
+    // Stake movements issued and the number of their transactions that failed
+    pub stake_added_lamports: u64,
+    pub stake_removed_lamports: u64,
+    pub failed_transactions: usize,
+
+    // Distance from the desired stake of each validator, by vote address, that was far enough
+    // from its desired stake to require a stake movement
+    #[serde(default)]
+    pub off_target_lamports: HashMap<Pubkey, u64>,
+
+    // Stake the reserve was projected to be unable to provide to each validator, by vote address
+    #[serde(default)]
+    pub reserve_shortfall_lamports: HashMap<Pubkey, u64>,
+
+    // Desired validators that are not in the stake pool, by vote address
+    #[serde(default)]
+    pub missing_from_pool: HashMap<Pubkey, PoolAdditionStatus>,
+
+    // Validator stake accounts created by the funding account, kept apart from the staker
+    #[serde(default)]
+    pub funding: FundingSummary,
+
+    // Rewards in excess of the validator stake account minimum, split into the staker's
+    // transient stake accounts when adding validators to the pool. The cumulative amount covers
+    // all epochs up to this one
+    #[serde(default)]
+    pub excess_rewards_reclaimed_lamports: u64,
+    #[serde(default)]
+    pub cumulative_excess_rewards_reclaimed_lamports: u64,
+
+    // Previously reclaimed excess rewards deposited back into the reserve
+    #[serde(default)]
+    pub excess_rewards_deposited_lamports: u64,
+
+    // Staker balance above its float transferred to the treasury
+    #[serde(default)]
+    pub treasury_sweep_lamports: u64,
+
+    // Multi-epoch schedule of stake movements, if stake movements are planned over several epochs
+    #[serde(default)]
+    pub rebalance_plan: Option<RebalancePlan>,
+
+    // Stake added (positive) or removed (negative) from each validator, by vote address
+    #[serde(default)]
+    pub stake_movement_lamports: HashMap<Pubkey, i64>,
+
+    // Effective stake of each validator, by vote address, projected for the next epochs from the
+    // warmup and cooldown of the stake movements
+    #[serde(default)]
+    pub projected_effective_stake_lamports: HashMap<Pubkey, Vec<u64>>,
+
+    // Stake that each validator skipped as busy, by vote address, is short (positive) or over
+    // (negative) of its desired stake
+    #[serde(default)]
+    pub busy_carryover_lamports: HashMap<Pubkey, i64>,
+
+    // Epoch the soft removal of each validator held in the pool at the minimum stake began, by
+    // vote address
+    #[serde(default)]
+    pub soft_removed_since: HashMap<Pubkey, Epoch>,
+
+    // Commission each validator is estimated to have earned from the pool's delegation in the
+    // previous epoch, by vote address
+    #[serde(default)]
+    pub validator_commission_lamports: HashMap<Pubkey, u64>,
+
+    // Balance of each validator before the stake movements, by vote address, and the slot index
+    // in the epoch of `slots_in_epoch` slots at which the stake movements were issued
+    #[serde(default)]
+    pub validator_balance_lamports: HashMap<Pubkey, u64>,
+    #[serde(default)]
+    pub movement_slot_index: u64,
+    #[serde(default)]
+    pub slots_in_epoch: u64,
+
+    // Bonus stake distributed this epoch when the bonus ramp stages its increases, out of the
+    // stake available for bonus validators
+    #[serde(default)]
+    pub bonus_ramp: Option<BonusRampStage>,
+
+    // Stake held back in the reserve for withdrawals this epoch, and the withdrawal trend it
+    // follows
+    #[serde(default)]
+    pub liquidity_buffer: Option<LiquidityBufferStage>,
+
+    // Consecutive epochs, up to this one, that the reserve held an idle share of the pool's stake
+    #[serde(default)]
+    pub idle_reserve_epochs: usize,
+
+    // Epoch of the update, zero in the summaries recorded before it was tracked
+    #[serde(default)]
+    pub epoch: Epoch,
+
+    // Stake deposited directly to each validator since the previous epoch, beyond the stake
+    // movements of the bot and the rewards the validator earned, by vote address
+    #[serde(default)]
+    pub external_deposit_lamports: HashMap<Pubkey, u64>,
+
+    // External deposits, accumulated over the epochs, that each validator keeps rather than have
+    // them rebalanced to the other validators, by vote address
+    #[serde(default)]
+    pub kept_external_deposit_lamports: HashMap<Pubkey, u64>,
+
+    // Desired stake of each validator, by vote address, including the external deposits it keeps
+    #[serde(default)]
+    pub target_lamports: HashMap<Pubkey, u64>,
+}
+
+/// Why a desired validator is not in the stake pool
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum PoolAdditionStatus {
+    /// The validator stake account is not yet active, the validator will be added once it is
+    Pending,
+    /// The validator stake account was active but adding it to the pool failed
+    Failed,
+    /// The validator has no stake account, which is created once the funding account can pay
+    /// for it
+    NoStakeAccount,
+}
+
+impl fmt::Display for PoolAdditionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolAdditionStatus::Pending => write!(f, "addition pending"),
+            PoolAdditionStatus::Failed => write!(f, "addition failed"),
+            PoolAdditionStatus::NoStakeAccount => write!(f, "no stake account"),
+        }
+    }
 }
 
+/// Activity of the account that funds new validator stake accounts
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FundingSummary {
+    // Balance of the funding account after the stake accounts were created
+    pub funder_balance_lamports: u64,
+
+    // Stake accounts created and the lamports they required
+    pub funded_stake_accounts: usize,
+    pub funded_lamports: u64,
+
+    // Stake accounts that could not be created due to insufficient funds
+    pub underfunded_stake_accounts: usize,
+}
+
+impl StakePoolSummary {
+    pub fn add_stake(&mut self, stake_state: ValidatorStakeState, lamports: u64) {
+        match stake_state {
+            ValidatorStakeState::None => self.none_stake_lamports += lamports,
+            ValidatorStakeState::Baseline => self.baseline_stake_lamports += lamports,
+            ValidatorStakeState::Bonus => self.bonus_stake_lamports += lamports,
+        }
+    }
+
+    /// Figures of the epoch once `rerun`, a later incremental run of the same epoch, followed
+    /// this one. The balances before the stake movements, the rewards and the external deposits
+    /// remain those of the first run, the stake movements add up and the rest is replaced
+    pub fn merge_rerun(self, rerun: StakePoolSummary) -> StakePoolSummary {
+        let mut stake_movement_lamports = self.stake_movement_lamports;
+        for (vote_address, movement_lamports) in rerun.stake_movement_lamports {
+            *stake_movement_lamports.entry(vote_address).or_default() += movement_lamports;
+        }
+        stake_movement_lamports.retain(|_, movement_lamports| *movement_lamports != 0);
+        let mut off_target_lamports = self.off_target_lamports;
+        off_target_lamports.extend(rerun.off_target_lamports);
+        let mut projected_effective_stake_lamports = self.projected_effective_stake_lamports;
+        projected_effective_stake_lamports.extend(rerun.projected_effective_stake_lamports);
+
+        StakePoolSummary {
+            reserve_stake_lamports: rerun.reserve_stake_lamports,
+            stake_added_lamports: self.stake_added_lamports + rerun.stake_added_lamports,
+            stake_removed_lamports: self.stake_removed_lamports + rerun.stake_removed_lamports,
+            failed_transactions: self.failed_transactions + rerun.failed_transactions,
+            off_target_lamports,
+            reserve_shortfall_lamports: rerun.reserve_shortfall_lamports,
+            missing_from_pool: rerun.missing_from_pool,
+            funding: FundingSummary {
+                funded_stake_accounts: self.funding.funded_stake_accounts
+                    + rerun.funding.funded_stake_accounts,
+                funded_lamports: self.funding.funded_lamports + rerun.funding.funded_lamports,
+                ..rerun.funding
+            },
+            excess_rewards_reclaimed_lamports: self.excess_rewards_reclaimed_lamports
+                + rerun.excess_rewards_reclaimed_lamports,
+            excess_rewards_deposited_lamports: self.excess_rewards_deposited_lamports
+                + rerun.excess_rewards_deposited_lamports,
+            treasury_sweep_lamports: self.treasury_sweep_lamports + rerun.treasury_sweep_lamports,
+            rebalance_plan: rerun.rebalance_plan.or(self.rebalance_plan),
+            stake_movement_lamports,
+            projected_effective_stake_lamports,
+            busy_carryover_lamports: rerun.busy_carryover_lamports,
+            soft_removed_since: rerun.soft_removed_since,
+            bonus_ramp: rerun.bonus_ramp,
+            kept_external_deposit_lamports: rerun.kept_external_deposit_lamports,
+            target_lamports: rerun.target_lamports,
+            ..self
+        }
+    }
+}
+
+/// Stake of a validator in the pool now, and under the desired validator stake
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StakeAllocation {
+    pub current_lamports: u64,
+    pub desired_lamports: u64,
+}
+
+impl StakeAllocation {
+    pub fn change_lamports(&self) -> i64 {
+        self.desired_lamports as i64 - self.current_lamports as i64
+    }
+}
+
+#[cfg(feature = "rpc")]
 pub trait GenericStakePool {
     fn apply(
         &mut self,
@@ -32,4 +508,272 @@ pub trait GenericStakePool {
         dry_run: bool,
         desired_validator_stake: &[ValidatorStake],
     ) -> Result<(Vec<String>, bool), Box<dyn error::Error>>;
+
+    /// Figures from the last `apply` that distributed stake, if any
+    fn summary(&self) -> Option<StakePoolSummary>;
+
+    /// Provide the figures recorded by the previous run, before calling `apply`
+    fn set_previous_summary(&mut self, _previous_summary: Option<StakePoolSummary>) {}
+
+    /// Provide the stake left pending by the previous epoch for the validators it skipped as
+    /// busy, before calling `apply`. These validators are funded ahead of all others
+    fn set_busy_carryover(&mut self, _busy_carryover_lamports: HashMap<Pubkey, i64>) {}
+
+    /// Provide the figures of the earlier runs of the same epoch, after `set_previous_summary`
+    /// and before calling `apply`, which then only moves the stake of the validators whose
+    /// desired stake changed since
+    fn set_incremental(&mut self, _latest_run: StakePoolSummary) {}
+
+    /// Provide the identifier of the run, recorded in the memo of every transaction, before
+    /// calling `apply`
+    fn set_run_id(&mut self, _run_id: &str) {}
+
+    /// Provide the vote accounts shared by the runs of the daemon, before calling `apply`
+    fn set_vote_account_cache(&mut self, _vote_account_cache: Arc<VoteAccountCache>) {}
+
+    /// Settings that shape the stake distribution, by name, recorded with each epoch
+    fn effective_config(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    /// The current and desired stake of every validator in the pool or in
+    /// `desired_validator_stake`, by vote address, without sending any transaction
+    fn allocation(
+        &mut self,
+        _rpc_client: &RpcClient,
+        _desired_validator_stake: &[ValidatorStake],
+    ) -> Result<HashMap<Pubkey, StakeAllocation>, Box<dyn error::Error>> {
+        Err("diff not supported by this stake pool".into())
+    }
+
+    /// The settings of the pool that only its authorities change, as found on chain, if this
+    /// stake pool tracks them
+    fn pool_state(
+        &self,
+        _rpc_client: &RpcClient,
+    ) -> Result<Option<PoolState>, Box<dyn error::Error>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_min_stake_change_amount() {
+        let min_stake_change = MinStakeChange {
+            lamports: 1_000,
+            target_percentage: None,
+        };
+        assert_eq!(min_stake_change.amount(0), 1_000);
+        assert_eq!(min_stake_change.amount(1_000_000), 1_000);
+
+        let min_stake_change = MinStakeChange {
+            target_percentage: Some(0.5),
+            ..min_stake_change
+        };
+        assert_eq!(min_stake_change.amount(0), 1_000);
+        assert_eq!(min_stake_change.amount(100_000), 1_000);
+        assert_eq!(min_stake_change.amount(1_000_000), 5_000);
+    }
+
+    #[test]
+    fn test_stake_tolerance() {
+        let no_tolerance = StakeTolerance::default();
+        assert!(no_tolerance.contains(100, 100));
+        assert!(!no_tolerance.contains(99, 100));
+        assert!(!no_tolerance.contains(101, 100));
+
+        let tolerance = StakeTolerance {
+            below_percentage: 1.,
+            above_percentage: 2.,
+        };
+        assert!(tolerance.contains(99, 100));
+        assert!(!tolerance.contains(98, 100));
+        assert!(tolerance.contains(102, 100));
+        assert!(!tolerance.contains(103, 100));
+
+        // Validators that should have no stake are only within tolerance once they have none
+        assert!(tolerance.contains(0, 0));
+        assert!(!tolerance.contains(1, 0));
+    }
+
+    #[test]
+    fn test_sort_by_seed() {
+        let desired_validator_stake = (0..10)
+            .map(|_| {
+                ValidatorStake::new(
+                    Pubkey::new_unique(),
+                    Pubkey::new_unique(),
+                    ValidatorStakeState::Bonus,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut sorted = desired_validator_stake.clone();
+        sort_by_seed(&mut sorted, 42);
+
+        // The order only depends on the seed, not on the input order
+        let mut reversed = desired_validator_stake
+            .iter()
+            .rev()
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_by_seed(&mut reversed, 42);
+        assert_eq!(sorted, reversed);
+
+        let mut other_seed = desired_validator_stake;
+        sort_by_seed(&mut other_seed, 43);
+        assert_ne!(sorted, other_seed);
+    }
+
+    #[test]
+    fn test_bonus_stake_amounts() {
+        let bonus = |bonus_weight| ValidatorStake {
+            bonus_weight,
+            ..ValidatorStake::new(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                ValidatorStakeState::Bonus,
+            )
+        };
+        let baseline = ValidatorStake::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            ValidatorStakeState::Baseline,
+        );
+
+        // 100 lamports across three equal weights leaves a remainder of 1 lamport, given to the
+        // first Bonus validator
+        let desired_validator_stake = vec![bonus(100), baseline.clone(), bonus(100), bonus(100)];
+        let amounts = bonus_stake_amounts(100, &desired_validator_stake);
+        assert_eq!(amounts.len(), 3);
+        assert_eq!(amounts.values().sum::<u64>(), 100);
+        assert_eq!(amounts[&desired_validator_stake[0].vote_address], 34);
+        assert_eq!(amounts[&desired_validator_stake[2].vote_address], 33);
+        assert_eq!(amounts[&desired_validator_stake[3].vote_address], 33);
+        assert!(!amounts.contains_key(&baseline.vote_address));
+
+        // Weighted shares are fully allocated as well
+        for total_bonus_stake_amount in &[0, 1, 7, 1_000_003, u64::MAX / 1_000] {
+            let desired_validator_stake = vec![bonus(100), bonus(150), bonus(33), bonus(7)];
+            let amounts = bonus_stake_amounts(*total_bonus_stake_amount, &desired_validator_stake);
+            assert_eq!(amounts.values().sum::<u64>(), *total_bonus_stake_amount);
+        }
+
+        // Validators without bonus weight get none of the remainder, unless no validator has weight
+        let desired_validator_stake = vec![bonus(0), bonus(100), bonus(100)];
+        let amounts = bonus_stake_amounts(101, &desired_validator_stake);
+        assert_eq!(amounts[&desired_validator_stake[0].vote_address], 0);
+        assert_eq!(amounts[&desired_validator_stake[1].vote_address], 51);
+        assert_eq!(amounts[&desired_validator_stake[2].vote_address], 50);
+        let desired_validator_stake = vec![bonus(0), bonus(0)];
+        let amounts = bonus_stake_amounts(u64::MAX / 1_000, &desired_validator_stake);
+        assert_eq!(amounts.values().sum::<u64>(), u64::MAX / 1_000);
+
+        // No Bonus validators, nothing to allocate
+        assert!(bonus_stake_amounts(100, &[baseline]).is_empty());
+    }
+
+    #[test]
+    fn test_cap_bonus_stake_by_asn() {
+        let validator = |stake_state, asn| ValidatorStake {
+            asn,
+            ..ValidatorStake::new(Pubkey::new_unique(), Pubkey::new_unique(), stake_state)
+        };
+        let desired_validator_stake = vec![
+            validator(ValidatorStakeState::Bonus, Some(1)),
+            validator(ValidatorStakeState::Bonus, Some(1)),
+            validator(ValidatorStakeState::Bonus, Some(2)),
+            validator(ValidatorStakeState::Baseline, Some(3)),
+            validator(ValidatorStakeState::Bonus, None),
+        ];
+        let amounts = bonus_stake_amounts(800, &desired_validator_stake);
+        assert!(amounts.values().all(|amount| *amount == 200));
+
+        // ASN 1 desires 400 of the 1000 lamports, the 100 above the cap goes to the other Bonus
+        // validators
+        let (capped_amounts, capped_asns) =
+            cap_bonus_stake_by_asn(1_000, 100, 30., &desired_validator_stake, &amounts);
+        assert_eq!(capped_asns, vec![1]);
+        let amount_of = |i: usize| capped_amounts[&desired_validator_stake[i].vote_address];
+        assert_eq!(amount_of(0), 150);
+        assert_eq!(amount_of(1), 150);
+        assert_eq!(amount_of(2), 250);
+        assert_eq!(amount_of(4), 250);
+
+        // Under a lower cap the recipients in ASN 2 are capped in turn, unknown ASNs never are
+        let (capped_amounts, capped_asns) =
+            cap_bonus_stake_by_asn(1_000, 100, 20., &desired_validator_stake, &amounts);
+        assert_eq!(capped_asns, vec![1, 2]);
+        let amount_of = |i: usize| capped_amounts[&desired_validator_stake[i].vote_address];
+        assert_eq!(amount_of(0), 100);
+        assert_eq!(amount_of(1), 100);
+        assert_eq!(amount_of(2), 200);
+        assert_eq!(amount_of(4), 400);
+        assert_eq!(capped_amounts.values().sum::<u64>(), 800);
+
+        // Nothing is over the cap
+        let (capped_amounts, capped_asns) =
+            cap_bonus_stake_by_asn(1_000, 100, 50., &desired_validator_stake, &amounts);
+        assert!(capped_asns.is_empty());
+        assert_eq!(capped_amounts, amounts);
+
+        // Baseline validators of a capped ASN count against its cap but keep their stake, and the
+        // freed stake is handed out in full
+        let desired_validator_stake = vec![
+            validator(ValidatorStakeState::Bonus, Some(1)),
+            validator(ValidatorStakeState::Baseline, Some(1)),
+            validator(ValidatorStakeState::Bonus, Some(2)),
+            validator(ValidatorStakeState::Bonus, None),
+        ];
+        let amounts = bonus_stake_amounts(800, &desired_validator_stake);
+        let (capped_amounts, capped_asns) =
+            cap_bonus_stake_by_asn(1_000, 100, 30., &desired_validator_stake, &amounts);
+        assert_eq!(capped_asns, vec![1, 2]);
+        let amount_of = |i: usize| capped_amounts[&desired_validator_stake[i].vote_address];
+        assert_eq!(amount_of(0), 200);
+        assert_eq!(amount_of(2), 300);
+        assert_eq!(amount_of(3), 300);
+        assert!(!capped_amounts.contains_key(&desired_validator_stake[1].vote_address));
+        assert_eq!(capped_amounts.values().sum::<u64>(), 800);
+    }
+
+    #[test]
+    fn test_merge_rerun() {
+        let validator = Pubkey::new_unique();
+        let other_validator = Pubkey::new_unique();
+        let first_run = StakePoolSummary {
+            stake_added_lamports: 500,
+            stake_movement_lamports: vec![(validator, 500), (other_validator, -200)]
+                .into_iter()
+                .collect(),
+            validator_balance_lamports: vec![(validator, 1_000)].into_iter().collect(),
+            target_lamports: vec![(validator, 1_500)].into_iter().collect(),
+            epoch: 10,
+            ..StakePoolSummary::default()
+        };
+        let rerun = StakePoolSummary {
+            stake_added_lamports: 200,
+            stake_movement_lamports: vec![(other_validator, 200)].into_iter().collect(),
+            target_lamports: vec![(validator, 1_500), (other_validator, 1_000)]
+                .into_iter()
+                .collect(),
+            epoch: 10,
+            ..StakePoolSummary::default()
+        };
+
+        let summary = first_run.clone().merge_rerun(rerun.clone());
+        assert_eq!(summary.stake_added_lamports, 700);
+        assert_eq!(
+            summary.stake_movement_lamports,
+            vec![(validator, 500)].into_iter().collect()
+        );
+        assert_eq!(
+            summary.validator_balance_lamports,
+            first_run.validator_balance_lamports
+        );
+        assert_eq!(summary.target_lamports, rerun.target_lamports);
+    }
 }
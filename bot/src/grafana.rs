@@ -0,0 +1,396 @@
+use {
+    crate::{
+        annotations::EpochAnnotations,
+        data_center_movement,
+        db::{EpochClassification, EpochClassificationV1},
+        generic_stake_pool::ValidatorStakeState,
+        http_server, idle_reserve,
+    },
+    log::*,
+    serde::{Deserialize, Serialize},
+    solana_sdk::{
+        clock::Epoch,
+        native_token::{lamports_to_sol, LAMPORTS_PER_SOL},
+    },
+    std::{
+        error, io,
+        net::{SocketAddr, TcpListener, TcpStream},
+        path::Path,
+    },
+};
+
+/// Metrics offered to Grafana by the `/search` endpoint
+pub const METRICS: &[&str] = &[
+    "bonus_validators",
+    "baseline_validators",
+    "none_validators",
+    "bonus_stake",
+    "baseline_stake",
+    "none_stake",
+    "reserve_stake",
+    "idle_reserve_percentage",
+    "stake_added",
+    "stake_removed",
+    "failed_transactions",
+    "funder_balance",
+    "excess_rewards_reclaimed",
+    "countries_gaining_stake",
+    "countries_losing_stake",
+];
+
+/// Prefix of the metrics of the net stake movement into a country, such as
+/// `net_stake_movement:DE`, which are queried by name rather than offered by `/search`
+pub const NET_STAKE_MOVEMENT_PREFIX: &str = "net_stake_movement:";
+
+#[derive(Deserialize)]
+struct QueryTarget {
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    targets: Vec<QueryTarget>,
+}
+
+#[derive(Deserialize)]
+struct AnnotationRequest {
+    annotation: serde_json::Value,
+}
+
+/// Annotation of an operator on an epoch, as Grafana's `/annotations` endpoint returns it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AnnotationEvent {
+    /// The annotation query of the request, echoed back
+    pub annotation: serde_json::Value,
+    // Unix timestamp in milliseconds
+    pub time: u64,
+    pub title: String,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TimeSeries {
+    pub target: String,
+    // (value, unix timestamp in milliseconds) pairs, oldest first
+    pub datapoints: Vec<(f64, u64)>,
+}
+
+// Value of `metric` for an epoch, `None` if the epoch did not record it
+fn metric_value(metric: &str, epoch_classification: &EpochClassificationV1) -> Option<f64> {
+    let validator_count = |stake_state| {
+        epoch_classification
+            .validator_classifications
+            .as_ref()
+            .map(|validator_classifications| {
+                validator_classifications
+                    .values()
+                    .filter(|vc| vc.stake_state == stake_state)
+                    .count() as f64
+            })
+    };
+    let summary = epoch_classification.stake_pool_summary.as_ref();
+    let net_movement_by_country = || {
+        summary.map(|_| {
+            data_center_movement::summarize(epoch_classification)
+                .by_country
+                .into_iter()
+                .map(|(country, movement)| (country, movement.net_lamports()))
+                .collect::<Vec<_>>()
+        })
+    };
+    let countries = |gaining: bool| {
+        net_movement_by_country().map(|by_country| {
+            by_country
+                .iter()
+                .filter(|(_, net_lamports)| *net_lamports != 0 && (*net_lamports > 0) == gaining)
+                .count() as f64
+        })
+    };
+
+    match metric {
+        "bonus_validators" => validator_count(ValidatorStakeState::Bonus),
+        "baseline_validators" => validator_count(ValidatorStakeState::Baseline),
+        "none_validators" => validator_count(ValidatorStakeState::None),
+        "bonus_stake" => summary.map(|s| lamports_to_sol(s.bonus_stake_lamports)),
+        "baseline_stake" => summary.map(|s| lamports_to_sol(s.baseline_stake_lamports)),
+        "none_stake" => summary.map(|s| lamports_to_sol(s.none_stake_lamports)),
+        "reserve_stake" => summary.map(|s| lamports_to_sol(s.reserve_stake_lamports)),
+        "idle_reserve_percentage" => summary.and_then(idle_reserve::idle_percentage),
+        "stake_added" => summary.map(|s| lamports_to_sol(s.stake_added_lamports)),
+        "stake_removed" => summary.map(|s| lamports_to_sol(s.stake_removed_lamports)),
+        "failed_transactions" => summary.map(|s| s.failed_transactions as f64),
+        "funder_balance" => summary.map(|s| lamports_to_sol(s.funding.funder_balance_lamports)),
+        "excess_rewards_reclaimed" => {
+            summary.map(|s| lamports_to_sol(s.excess_rewards_reclaimed_lamports))
+        }
+        "countries_gaining_stake" => countries(true),
+        "countries_losing_stake" => countries(false),
+        _ => {
+            let country = metric.strip_prefix(NET_STAKE_MOVEMENT_PREFIX)?;
+            net_movement_by_country().map(|by_country| {
+                by_country
+                    .into_iter()
+                    .find(|(movement_country, _)| movement_country == country)
+                    .map(|(_, net_lamports)| net_lamports as f64 / LAMPORTS_PER_SOL as f64)
+                    .unwrap_or_default()
+            })
+        }
+    }
+}
+
+/// Load the latest `epochs` epochs that contain validator classifications, oldest first
+pub fn load_epochs<P>(
+    path: P,
+    epochs: usize,
+) -> Result<Vec<(Epoch, EpochClassificationV1)>, io::Error>
+where
+    P: AsRef<Path>,
+{
+    let mut list = vec![];
+    let mut next = EpochClassification::load_latest(&path)?;
+    while let Some((epoch, epoch_classification)) = next {
+        if list.len() >= epochs {
+            break;
+        }
+        list.push((epoch, epoch_classification.into_current()));
+        next = EpochClassification::load_previous(epoch, &path)?;
+    }
+    list.reverse();
+    Ok(list)
+}
+
+/// Build a time series for each metric. Epochs without a timestamp are omitted
+pub fn query(metrics: &[String], epochs: &[(Epoch, EpochClassificationV1)]) -> Vec<TimeSeries> {
+    metrics
+        .iter()
+        .map(|metric| TimeSeries {
+            target: metric.clone(),
+            datapoints: epochs
+                .iter()
+                .filter_map(|(_, epoch_classification)| {
+                    Some((
+                        metric_value(metric, epoch_classification)?,
+                        epoch_classification.timestamp? * 1000,
+                    ))
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// The annotations of the operators on `epochs`, placed at the timestamp of the epoch or, for the
+/// epochs without one, at the moment they were attached
+pub fn annotation_events(
+    annotation: &serde_json::Value,
+    epochs: &[(Epoch, EpochClassificationV1)],
+    db_path: &Path,
+) -> Result<Vec<AnnotationEvent>, Box<dyn error::Error>> {
+    let mut events = vec![];
+    for (epoch, epoch_classification) in epochs {
+        for epoch_annotation in EpochAnnotations::load(*epoch, db_path)?.annotations {
+            events.push(AnnotationEvent {
+                annotation: annotation.clone(),
+                time: epoch_classification
+                    .timestamp
+                    .unwrap_or(epoch_annotation.timestamp)
+                    * 1000,
+                title: format!("Epoch {}", epoch),
+                text: epoch_annotation.to_string(),
+                tags: vec!["stake-o-matic".to_string()],
+            });
+        }
+    }
+    Ok(events)
+}
+
+/// Headers that let Grafana query the datasource from the browser
+const CORS_HEADERS: &[(&str, &str)] = &[
+    ("Access-Control-Allow-Origin", "*"),
+    ("Access-Control-Allow-Headers", "accept, content-type"),
+    ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
+];
+
+fn handle_request(
+    stream: TcpStream,
+    db_path: &Path,
+    epochs: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    let request = http_server::read_request(&stream, CORS_HEADERS)?;
+    let body = request.body;
+
+    let (status, response) = match (request.method.as_str(), request.path.as_str()) {
+        // Grafana's "Test connection" only checks for a successful response
+        ("GET", "/") => ("200 OK", "\"OK\"".to_string()),
+        ("POST", "/search") => ("200 OK", serde_json::to_string(METRICS)?),
+        ("POST", "/query") => {
+            let request: QueryRequest = serde_json::from_slice(&body)?;
+            let metrics = request
+                .targets
+                .into_iter()
+                .map(|target| target.target)
+                .collect::<Vec<_>>();
+            let epochs = load_epochs(db_path, epochs)?;
+            ("200 OK", serde_json::to_string(&query(&metrics, &epochs))?)
+        }
+        ("POST", "/annotations") => {
+            let request: AnnotationRequest = serde_json::from_slice(&body)?;
+            let epochs = load_epochs(db_path, epochs)?;
+            let events = annotation_events(&request.annotation, &epochs, db_path)?;
+            ("200 OK", serde_json::to_string(&events)?)
+        }
+        ("OPTIONS", _) => ("200 OK", String::new()),
+        _ => ("404 Not Found", "\"Not Found\"".to_string()),
+    };
+
+    http_server::write_response(stream, status, &response, CORS_HEADERS)?;
+    Ok(())
+}
+
+/// Serve the epoch summaries found in `db_path` to Grafana's JSON datasource plugin. Runs until
+/// the listener fails
+pub fn serve<P>(
+    listen_address: SocketAddr,
+    db_path: P,
+    epochs: usize,
+) -> Result<(), Box<dyn error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let listener = TcpListener::bind(listen_address)?;
+    info!("Serving epoch summaries on http://{}", listen_address);
+
+    for stream in listener.incoming() {
+        if let Err(err) = handle_request(stream?, db_path.as_ref(), epochs) {
+            warn!("Failed to handle request: {}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{annotations, db::ValidatorClassification, generic_stake_pool::StakePoolSummary},
+        solana_sdk::{native_token::sol_to_lamports, pubkey::Pubkey},
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn test_query() {
+        let mut validator_classifications = HashMap::new();
+        for stake_state in &[
+            ValidatorStakeState::Bonus,
+            ValidatorStakeState::Bonus,
+            ValidatorStakeState::None,
+        ] {
+            validator_classifications.insert(
+                Pubkey::new_unique(),
+                ValidatorClassification {
+                    stake_state: *stake_state,
+                    ..ValidatorClassification::default()
+                },
+            );
+        }
+
+        let epochs = vec![
+            (
+                1,
+                EpochClassificationV1 {
+                    validator_classifications: Some(validator_classifications.clone()),
+                    ..EpochClassificationV1::default()
+                },
+            ),
+            (
+                2,
+                EpochClassificationV1 {
+                    validator_classifications: Some(validator_classifications),
+                    timestamp: Some(1_000),
+                    stake_pool_summary: Some(StakePoolSummary {
+                        reserve_stake_lamports: sol_to_lamports(42.),
+                        stake_movement_lamports: vec![(
+                            Pubkey::new_unique(),
+                            -sol_to_lamports(3.) as i64,
+                        )]
+                        .into_iter()
+                        .collect(),
+                        ..StakePoolSummary::default()
+                    }),
+                    ..EpochClassificationV1::default()
+                },
+            ),
+        ];
+
+        assert_eq!(
+            query(
+                &[
+                    "bonus_validators".to_string(),
+                    "reserve_stake".to_string(),
+                    "countries_losing_stake".to_string(),
+                    "net_stake_movement:Unknown".to_string(),
+                    "unknown".to_string()
+                ],
+                &epochs
+            ),
+            vec![
+                TimeSeries {
+                    target: "bonus_validators".to_string(),
+                    datapoints: vec![(2., 1_000_000)],
+                },
+                TimeSeries {
+                    target: "reserve_stake".to_string(),
+                    datapoints: vec![(42., 1_000_000)],
+                },
+                TimeSeries {
+                    target: "countries_losing_stake".to_string(),
+                    datapoints: vec![(1., 1_000_000)],
+                },
+                TimeSeries {
+                    target: "net_stake_movement:Unknown".to_string(),
+                    datapoints: vec![(-3., 1_000_000)],
+                },
+                TimeSeries {
+                    target: "unknown".to_string(),
+                    datapoints: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_annotation_events() {
+        let db_path = std::env::temp_dir().join(format!("grafana-{}", Pubkey::new_unique()));
+        annotations::annotate(1, "cluster outage, criteria relaxed", None, &db_path).unwrap();
+        annotations::annotate(2, "restart", Some("ops".to_string()), &db_path).unwrap();
+        let epochs = vec![
+            (1, EpochClassificationV1::default()),
+            (
+                2,
+                EpochClassificationV1 {
+                    timestamp: Some(1_000),
+                    ..EpochClassificationV1::default()
+                },
+            ),
+            (3, EpochClassificationV1::default()),
+        ];
+
+        let annotation = serde_json::json!({"name": "Operator annotations"});
+        let events = annotation_events(&annotation, &epochs, &db_path).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].title, "Epoch 1");
+        assert_eq!(events[0].text, "cluster outage, criteria relaxed");
+        assert!(events[0].time > 1_000_000);
+        assert_eq!(
+            events[1],
+            AnnotationEvent {
+                annotation,
+                time: 1_000_000,
+                title: "Epoch 2".to_string(),
+                text: "restart (ops)".to_string(),
+                tags: vec!["stake-o-matic".to_string()],
+            }
+        );
+        std::fs::remove_dir_all(&db_path).unwrap();
+    }
+}
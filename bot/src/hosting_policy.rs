@@ -0,0 +1,109 @@
+use {
+    serde::Deserialize,
+    std::{error, fs::File, path::Path},
+};
+
+/// Treatment of the validators hosted by a provider
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProviderPolicy {
+    /// Validators are held at the Baseline stake level
+    Ineligible,
+    /// Validators receive a bonus weight reduced by this percentage, below 100
+    Penalty(u64),
+}
+
+/// Hosting provider, resolved from the ASN of a validator's data center
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct HostingProvider {
+    pub name: String,
+    pub asns: Vec<u64>,
+    pub policy: ProviderPolicy,
+}
+
+/// Hosting providers whose validators are not eligible for bonus stake, or are penalized
+///
+/// ```yaml
+/// providers:
+///   - name: Example Cloud
+///     asns: [64496, 64497]
+///     policy: ineligible
+///   - name: Other Cloud
+///     asns: [64498]
+///     policy:
+///       penalty: 50
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct HostingPolicy {
+    pub providers: Vec<HostingProvider>,
+}
+
+impl HostingPolicy {
+    pub fn load<P>(path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+        let policy: Self = serde_yaml::from_reader(file)
+            .map_err(|err| format!("Invalid hosting policy {}: {}", path.display(), err))?;
+        for provider in &policy.providers {
+            if let ProviderPolicy::Penalty(percentage) = provider.policy {
+                // A penalty of 100% leaves no bonus weight, which is what `ineligible` is for
+                if percentage >= 100 {
+                    return Err(format!(
+                        "Invalid penalty for {}: {}%, use the ineligible policy instead",
+                        provider.name, percentage
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    /// The provider operating `asn`, if it is subject to the policy
+    pub fn provider_of(&self, asn: u64) -> Option<&HostingProvider> {
+        self.providers
+            .iter()
+            .find(|provider| provider.asns.contains(&asn))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_provider_of() {
+        let policy: HostingPolicy = serde_yaml::from_str(
+            "providers:\n\
+             - name: Example Cloud\n  asns: [64496, 64497]\n  policy: ineligible\n\
+             - name: Other Cloud\n  asns: [64498]\n  policy:\n    penalty: 50\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy.provider_of(64497).map(|p| p.policy),
+            Some(ProviderPolicy::Ineligible)
+        );
+        assert_eq!(
+            policy.provider_of(64498).map(|p| p.policy),
+            Some(ProviderPolicy::Penalty(50))
+        );
+        assert_eq!(policy.provider_of(64499), None);
+    }
+
+    #[test]
+    fn test_load_rejects_full_penalty() {
+        let path = std::env::temp_dir().join(format!("hosting-policy-{}.yml", std::process::id()));
+        std::fs::write(
+            &path,
+            "providers:\n- name: Other Cloud\n  asns: [64498]\n  policy:\n    penalty: 100\n",
+        )
+        .unwrap();
+        assert!(HostingPolicy::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
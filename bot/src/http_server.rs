@@ -0,0 +1,272 @@
+//! HTTP/1.1 request handling shared by the servers of the bot, the Grafana datasource and the API.
+//!
+//! The servers answer one request at a time, so reading a request is bounded both in time and in
+//! size: a client that sends its request slowly, or sends lines without end, is dropped instead of
+//! holding up the others
+use {
+    log::*,
+    std::{
+        error, fmt,
+        io::{self, BufRead, BufReader, Read, Write},
+        net::TcpStream,
+        time::{Duration, Instant},
+    },
+};
+
+/// Largest request body read, the requests being small JSON documents
+pub const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+/// Longest request line or header line read
+pub const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// Most headers read from a request
+pub const MAX_HEADERS: usize = 64;
+
+/// Time a client is given to send its whole request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reader of a request that fails once the deadline of the request passes, however slowly the
+/// client sends it
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::default() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Request not received in time",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Request refused for its size before it was read in full, answered with `status`
+#[derive(Debug)]
+struct TooLarge {
+    status: &'static str,
+    message: String,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for TooLarge {}
+
+#[derive(Debug, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    // Header names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Line of at most `MAX_LINE_LENGTH` bytes, without its line ending. Empty once the client has
+/// nothing more to send
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    status: &'static str,
+) -> Result<String, Box<dyn error::Error>> {
+    let mut line = String::new();
+    (&mut *reader)
+        .take(MAX_LINE_LENGTH as u64 + 1)
+        .read_line(&mut line)?;
+    if line.len() > MAX_LINE_LENGTH {
+        return Err(TooLarge {
+            status,
+            message: format!(
+                "Request line or header longer than {} bytes",
+                MAX_LINE_LENGTH
+            ),
+        }
+        .into());
+    }
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn parse_request<R: BufRead>(reader: &mut R) -> Result<Request, Box<dyn error::Error>> {
+    let request_line = read_line(reader, "414 URI Too Long")?;
+    let mut request_line = request_line.split_whitespace();
+    let mut request = Request {
+        method: request_line.next().unwrap_or_default().to_string(),
+        path: request_line.next().unwrap_or_default().to_string(),
+        ..Request::default()
+    };
+
+    loop {
+        let header = read_line(reader, "431 Request Header Fields Too Large")?;
+        if header.is_empty() {
+            break;
+        }
+        if request.headers.len() >= MAX_HEADERS {
+            return Err(TooLarge {
+                status: "431 Request Header Fields Too Large",
+                message: format!("More than {} request headers", MAX_HEADERS),
+            }
+            .into());
+        }
+        let mut header = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            request
+                .headers
+                .push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let content_length = match request.header("content-length") {
+        Some(content_length) => content_length.parse()?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_LENGTH {
+        return Err(TooLarge {
+            status: "413 Payload Too Large",
+            message: format!("Request body of {} bytes is too large", content_length),
+        }
+        .into());
+    }
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+/// Read the request sent on `stream` within `REQUEST_TIMEOUT`. A request too large to be read is
+/// answered with its error status and `headers`
+pub fn read_request(
+    stream: &TcpStream,
+    headers: &[(&str, &str)],
+) -> Result<Request, Box<dyn error::Error>> {
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: stream.try_clone()?,
+        deadline: Instant::now() + REQUEST_TIMEOUT,
+    });
+    let request = parse_request(&mut reader);
+    if let Err(err) = &request {
+        if let Some(too_large) = err.downcast_ref::<TooLarge>() {
+            let status = too_large.status;
+            write_response(
+                stream.try_clone()?,
+                status,
+                &format!("\"{}\"", &status[4..]),
+                headers,
+            )?;
+        }
+    }
+    let request = request?;
+    debug!("{} {}", request.method, request.path);
+    Ok(request)
+}
+
+/// Answer with the JSON `response` and the additional `headers`, closing the connection
+pub fn write_response(
+    mut stream: TcpStream,
+    status: &str,
+    response: &str,
+    headers: &[(&str, &str)],
+) -> io::Result<()> {
+    let headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect::<String>();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         {}\
+         Connection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        headers,
+        response
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn too_large_status(request: &[u8]) -> Option<&'static str> {
+        parse_request(&mut &request[..])
+            .unwrap_err()
+            .downcast_ref::<TooLarge>()
+            .map(|too_large| too_large.status)
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request = b"POST /query HTTP/1.1\r\n\
+            Content-Type: application/json\r\n\
+            Content-Length: 2\r\n\r\n{}";
+        let request = parse_request(&mut &request[..]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/query");
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        assert_eq!(request.header("Content-Length"), Some("2"));
+        assert_eq!(request.body, b"{}");
+
+        let request = parse_request(&mut &b"GET / HTTP/1.1\r\n"[..]).unwrap();
+        assert_eq!(request.path, "/");
+        assert!(request.body.is_empty());
+
+        let request = b"GET / HTTP/1.1\r\nContent-Length: two\r\n\r\n";
+        assert!(parse_request(&mut &request[..]).is_err());
+    }
+
+    #[test]
+    fn test_parse_request_too_large() {
+        let long_path = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+        assert_eq!(
+            too_large_status(long_path.as_bytes()),
+            Some("414 URI Too Long")
+        );
+
+        // A line without end is not read past the limit
+        let endless_header = format!("GET / HTTP/1.1\r\nX-Header: {}", "a".repeat(1024 * 1024));
+        assert_eq!(
+            too_large_status(endless_header.as_bytes()),
+            Some("431 Request Header Fields Too Large")
+        );
+
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Header: a\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(
+            too_large_status(many_headers.as_bytes()),
+            Some("431 Request Header Fields Too Large")
+        );
+
+        let large_body = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LENGTH + 1
+        );
+        assert_eq!(
+            too_large_status(large_body.as_bytes()),
+            Some("413 Payload Too Large")
+        );
+
+        let headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Header: a\r\n".repeat(MAX_HEADERS)
+        );
+        assert!(parse_request(&mut headers.as_bytes()).is_ok());
+    }
+}
@@ -0,0 +1,193 @@
+//! Detection of pool stake left idle in the reserve, where it earns no rewards. The reserve keeps
+//! what the stake movements of an epoch did not deploy, such as the bonus stake of a pool without
+//! Bonus validators, and a reserve that stays large for several epochs is reported. Optionally the
+//! Baseline validators are then admitted to the Bonus stake level to deploy it. Bonus stake held
+//! back by the bonus ramp is on its way to the validators, and the liquidity buffer is held for
+//! withdrawals, so that neither counts as idle
+use {
+    crate::generic_stake_pool::{StakePoolSummary, ValidatorStake, ValidatorStakeState},
+    solana_sdk::native_token::Sol,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdleReservePolicy {
+    /// Share of the pool's stake, in percent, from which the reserve counts as idle
+    pub min_percentage: f64,
+
+    /// Consecutive idle epochs after which the idle reserve is reported
+    pub epochs: usize,
+
+    /// Promote the Baseline validators to the Bonus stake level once the idle reserve is reported
+    pub deploy: bool,
+}
+
+/// Share of the pool's stake, in percent, that remained in the reserve after the stake movements
+/// of `summary`, besides the bonus stake staged for the next epochs and the liquidity buffer
+pub fn idle_percentage(summary: &StakePoolSummary) -> Option<f64> {
+    let validator_stake_lamports = (summary.none_stake_lamports
+        + summary.baseline_stake_lamports
+        + summary.bonus_stake_lamports
+        + summary.stake_added_lamports)
+        .saturating_sub(summary.stake_removed_lamports);
+    let total_lamports = validator_stake_lamports + summary.reserve_stake_lamports;
+    if total_lamports == 0 {
+        return None;
+    }
+    let pending_lamports = summary
+        .bonus_ramp
+        .map(|bonus_ramp| bonus_ramp.pending_lamports())
+        .unwrap_or_default()
+        + summary
+            .liquidity_buffer
+            .map(|liquidity_buffer| liquidity_buffer.buffer_lamports)
+            .unwrap_or_default();
+    Some(
+        summary
+            .reserve_stake_lamports
+            .saturating_sub(pending_lamports) as f64
+            * 100.
+            / total_lamports as f64,
+    )
+}
+
+impl IdleReservePolicy {
+    /// Consecutive epochs the reserve has been idle, up to the epoch of `summary`, given the
+    /// count of the previous epoch
+    pub fn idle_epochs(&self, summary: &StakePoolSummary, previous_idle_epochs: usize) -> usize {
+        match idle_percentage(summary) {
+            Some(percentage) if percentage >= self.min_percentage => previous_idle_epochs + 1,
+            _ => 0,
+        }
+    }
+
+    /// Note reporting the idle reserve of `summary`, once idle for long enough
+    pub fn note(&self, summary: &StakePoolSummary) -> Option<String> {
+        if summary.idle_reserve_epochs < self.epochs {
+            return None;
+        }
+        Some(format!(
+            "{:.1}% of the pool's stake, {}, has been idle in the reserve for {} epochs",
+            idle_percentage(summary)?,
+            Sol(summary.reserve_stake_lamports),
+            summary.idle_reserve_epochs
+        ))
+    }
+
+    /// Whether the idle reserve reported by the previous epoch's `previous_summary` is to be
+    /// deployed this epoch
+    pub fn deploy(&self, previous_summary: Option<&StakePoolSummary>) -> bool {
+        self.deploy
+            && previous_summary
+                .map(|summary| summary.idle_reserve_epochs >= self.epochs)
+                .unwrap_or(false)
+    }
+}
+
+/// Admit the Baseline validators of `desired_validator_stake` to the Bonus stake level, so that
+/// the bonus stake covers the idle reserve. Returns the number of validators promoted
+pub fn promote_baseline(desired_validator_stake: &mut [ValidatorStake]) -> usize {
+    let mut promoted = 0;
+    for validator_stake in desired_validator_stake
+        .iter_mut()
+        .filter(|validator_stake| validator_stake.stake_state == ValidatorStakeState::Baseline)
+    {
+        validator_stake.stake_state = ValidatorStakeState::Bonus;
+        promoted += 1;
+    }
+    promoted
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{bonus_ramp::BonusRampStage, liquidity_buffer::LiquidityBufferStage},
+        solana_sdk::pubkey::Pubkey,
+    };
+
+    #[test]
+    fn test_idle_reserve() {
+        let policy = IdleReservePolicy {
+            min_percentage: 10.,
+            epochs: 2,
+            deploy: true,
+        };
+        let mut summary = StakePoolSummary {
+            baseline_stake_lamports: 800,
+            stake_added_lamports: 100,
+            stake_removed_lamports: 50,
+            reserve_stake_lamports: 150,
+            ..StakePoolSummary::default()
+        };
+        assert_eq!(idle_percentage(&summary), Some(15.));
+        assert_eq!(idle_percentage(&StakePoolSummary::default()), None);
+        assert_eq!(
+            idle_percentage(&StakePoolSummary {
+                bonus_ramp: Some(BonusRampStage {
+                    staged_lamports: 100,
+                    available_lamports: 200,
+                    step_lamports: 100,
+                }),
+                ..summary.clone()
+            }),
+            Some(5.)
+        );
+        assert_eq!(
+            idle_percentage(&StakePoolSummary {
+                liquidity_buffer: Some(LiquidityBufferStage {
+                    buffer_lamports: 50,
+                    ..LiquidityBufferStage::default()
+                }),
+                ..summary.clone()
+            }),
+            Some(10.)
+        );
+
+        assert_eq!(policy.idle_epochs(&summary, 0), 1);
+        summary.idle_reserve_epochs = 1;
+        assert_eq!(policy.note(&summary), None);
+        assert!(!policy.deploy(Some(&summary)));
+
+        summary.idle_reserve_epochs = policy.idle_epochs(&summary, 1);
+        assert_eq!(
+            policy.note(&summary).unwrap(),
+            "15.0% of the pool's stake, ◎0.000000150, has been idle in the reserve for 2 epochs"
+        );
+        assert!(policy.deploy(Some(&summary)));
+        assert!(!IdleReservePolicy {
+            deploy: false,
+            ..policy
+        }
+        .deploy(Some(&summary)));
+        assert!(!policy.deploy(None));
+
+        summary.reserve_stake_lamports = 50;
+        assert_eq!(policy.idle_epochs(&summary, 2), 0);
+    }
+
+    #[test]
+    fn test_promote_baseline() {
+        let validator_stake = |stake_state| {
+            ValidatorStake::new(Pubkey::new_unique(), Pubkey::new_unique(), stake_state)
+        };
+        let mut desired_validator_stake = vec![
+            validator_stake(ValidatorStakeState::Baseline),
+            validator_stake(ValidatorStakeState::None),
+            validator_stake(ValidatorStakeState::Bonus),
+            validator_stake(ValidatorStakeState::Baseline),
+        ];
+        assert_eq!(promote_baseline(&mut desired_validator_stake), 2);
+        assert_eq!(
+            desired_validator_stake
+                .iter()
+                .map(|validator_stake| validator_stake.stake_state)
+                .collect::<Vec<_>>(),
+            vec![
+                ValidatorStakeState::Bonus,
+                ValidatorStakeState::None,
+                ValidatorStakeState::Bonus,
+                ValidatorStakeState::Bonus
+            ]
+        );
+    }
+}
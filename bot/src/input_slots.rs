@@ -0,0 +1,53 @@
+//! Slots at which the classifier inputs were fetched from the cluster. Fetching all of them takes
+//! minutes, during which the cluster moves on, so that the inputs fetched first and last may
+//! describe noticeably different cluster states. Inputs fetched too long before the newest one
+//! are fetched again until all of them fall within a window of slots
+use {solana_sdk::clock::Slot, std::collections::BTreeMap};
+
+/// Rounds of fetching the stragglers again before giving up
+pub const MAX_REFETCH_ROUNDS: usize = 3;
+
+/// Slots between the oldest and the newest input
+pub fn spread(input_slots: &BTreeMap<String, Slot>) -> Slot {
+    match (input_slots.values().min(), input_slots.values().max()) {
+        (Some(oldest), Some(newest)) => newest - oldest,
+        _ => 0,
+    }
+}
+
+/// Inputs fetched more than `window` slots before the newest input, by name
+pub fn stragglers(input_slots: &BTreeMap<String, Slot>, window: Slot) -> Vec<String> {
+    let newest = input_slots.values().max().copied().unwrap_or_default();
+    input_slots
+        .iter()
+        .filter(|(_, slot)| *slot + window < newest)
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stragglers() {
+        let mut input_slots = vec![
+            ("blocks".to_string(), 1_000),
+            ("stake_accounts".to_string(), 1_100),
+            ("vote_accounts".to_string(), 1_250),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+        assert_eq!(spread(&input_slots), 250);
+        assert_eq!(stragglers(&input_slots, 150), vec!["blocks".to_string()]);
+        assert!(stragglers(&input_slots, 250).is_empty());
+
+        input_slots.insert("blocks".to_string(), 1_300);
+        assert_eq!(spread(&input_slots), 200);
+        assert_eq!(
+            stragglers(&input_slots, 150),
+            vec!["stake_accounts".to_string()]
+        );
+        assert_eq!(spread(&BTreeMap::new()), 0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Offline IP address to ASN database, in the TSV format published by iptoasn.com, so that data
+//! centers can be resolved without calling external APIs
+use {
+    flate2::read::GzDecoder,
+    log::*,
+    std::{error, fs, io::Read, net::IpAddr, path::Path, time::Duration},
+};
+
+pub const DEFAULT_DATABASE_URL: &str = "https://iptoasn.com/data/ip2asn-combined.tsv.gz";
+
+// IPv4 addresses are held as IPv4-mapped IPv6 addresses, so that both share one ordering
+fn ip_key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// Address range announced by an ASN
+#[derive(Debug, Clone, PartialEq)]
+struct AsnRange {
+    start: u128,
+    end: u128,
+    asn: u64,
+    country: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IpToAsn {
+    // Sorted by start address, without overlaps
+    ranges: Vec<AsnRange>,
+}
+
+impl IpToAsn {
+    /// Parse lines of `range_start range_end AS_number country_code AS_description`, separated
+    /// by tabs. Ranges that are not routed have the AS number 0 and are left out
+    pub fn parse(tsv: &str) -> Result<Self, String> {
+        let mut ranges = vec![];
+        for (line_number, line) in tsv.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid IP to ASN line {}: {}", line_number + 1, line);
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() < 4 {
+                return Err(invalid());
+            }
+            let start = fields[0].parse::<IpAddr>().map_err(|_| invalid())?;
+            let end = fields[1].parse::<IpAddr>().map_err(|_| invalid())?;
+            let asn = fields[2].parse::<u64>().map_err(|_| invalid())?;
+            if asn != 0 {
+                ranges.push(AsnRange {
+                    start: ip_key(start),
+                    end: ip_key(end),
+                    asn,
+                    country: fields[3].to_string(),
+                });
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(Self { ranges })
+    }
+
+    pub fn load<P>(path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let tsv = fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        let ip_to_asn = Self::parse(&tsv).map_err(|err| format!("{}: {}", path.display(), err))?;
+        info!(
+            "Loaded {} ASN ranges from {}",
+            ip_to_asn.ranges.len(),
+            path.display()
+        );
+        Ok(ip_to_asn)
+    }
+
+    /// The ASN announcing `ip` and the country it is registered in
+    pub fn lookup(&self, ip: IpAddr) -> Option<(u64, &str)> {
+        let key = ip_key(ip);
+        let index = match self.ranges.binary_search_by_key(&key, |range| range.start) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let range = &self.ranges[index];
+        if key <= range.end {
+            Some((range.asn, &range.country))
+        } else {
+            None
+        }
+    }
+}
+
+/// Download the database at `url`, gzip compressed if the URL ends in .gz, and replace the
+/// database at `path` with it once it parses
+pub fn refresh<P>(url: &str, path: P) -> Result<usize, Box<dyn error::Error>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    info!("Downloading the IP to ASN database from {}", url);
+    let response = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(300))
+        .build()?
+        .get(url)
+        .send()?
+        .error_for_status()?;
+    let body = response.bytes()?;
+
+    let mut tsv = String::new();
+    if url.ends_with(".gz") {
+        GzDecoder::new(&body[..]).read_to_string(&mut tsv)?;
+    } else {
+        tsv = String::from_utf8(body.to_vec())?;
+    }
+    let ranges = IpToAsn::parse(&tsv)?.ranges.len();
+
+    // Written aside first, so that an interrupted refresh leaves the previous database intact
+    let download_path = path.with_extension("download");
+    fs::write(&download_path, tsv)
+        .map_err(|err| format!("Unable to write {}: {}", download_path.display(), err))?;
+    fs::rename(&download_path, path)
+        .map_err(|err| format!("Unable to replace {}: {}", path.display(), err))?;
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let ip_to_asn = IpToAsn::parse(
+            "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+             1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+             1.0.4.0\t1.0.7.255\t38803\tAU\tWPL-AS-AP\n\
+             2001:200::\t2001:200:ffff:ffff:ffff:ffff:ffff:ffff\t2500\tJP\tWIDE-BB\n",
+        )
+        .unwrap();
+
+        let lookup = |ip: &str| ip_to_asn.lookup(ip.parse().unwrap());
+        assert_eq!(lookup("1.0.0.1"), Some((13335, "US")));
+        assert_eq!(lookup("1.0.2.1"), None);
+        assert_eq!(lookup("1.0.7.255"), Some((38803, "AU")));
+        assert_eq!(lookup("1.0.8.0"), None);
+        assert_eq!(lookup("0.255.255.255"), None);
+        assert_eq!(lookup("2001:200::1"), Some((2500, "JP")));
+
+        assert!(IpToAsn::parse("1.0.0.0\t1.0.0.255\tAS13335\tUS\t").is_err());
+    }
+}
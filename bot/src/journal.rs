@@ -0,0 +1,153 @@
+use {
+    crate::{
+        generic_stake_pool::ValidatorStake,
+        pool_state::PoolState,
+        schema::{self, Schema},
+    },
+    serde::{Deserialize, Serialize},
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io::{self, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Record of the inputs given to the stake pool during the first run of an epoch, allowing the
+/// run to be reproduced from the classifier inputs of the epoch
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Journal {
+    // Seed that determined the order of `desired_validator_stake`
+    pub seed: u64,
+
+    // The `--bonus-streak` setting of the run
+    pub bonus_streak: Option<(usize, u64)>,
+
+    // The `--desired-stake-source` settings of the run, in order of precedence
+    #[serde(default)]
+    pub desired_stake_sources: Vec<String>,
+
+    // Whether the Baseline validators were promoted to Bonus to deploy an idle reserve
+    #[serde(default)]
+    pub deploy_idle_reserve: bool,
+
+    // Desired stake read by the run from each of its file, foundation list and standard input
+    // sources, by source. None in journals written before the sources were recorded, whose runs
+    // cannot be reproduced
+    #[serde(default)]
+    pub source_validator_stake: Option<HashMap<String, Vec<ValidatorStake>>>,
+
+    // Desired validator stake passed to the stake pool, in processing order
+    pub desired_validator_stake: Vec<ValidatorStake>,
+
+    // Stake left pending for the validators skipped as busy, by vote address, fulfilled first by
+    // the next epoch
+    #[serde(default)]
+    pub busy_carryover_lamports: HashMap<Pubkey, i64>,
+
+    // Stake movements issued by the run, by vote address: added (positive) or removed (negative)
+    #[serde(default)]
+    pub stake_movement_lamports: HashMap<Pubkey, i64>,
+
+    // Settings of the stake pool found after the last run of the epoch, which the next run
+    // expects to find unchanged
+    #[serde(default)]
+    pub pool_state: Option<PoolState>,
+
+    // Epoch in which the stake movements of the run were rolled back, if they were
+    #[serde(default)]
+    pub rolled_back_in: Option<Epoch>,
+}
+
+impl Schema for Journal {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl Journal {
+    pub fn file_name<P>(epoch: Epoch, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref().join(format!("epoch-{}-journal.yml", epoch))
+    }
+
+    pub fn exists<P>(epoch: Epoch, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        Self::file_name(epoch, path).exists()
+    }
+
+    pub fn load<P>(epoch: Epoch, path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized = fs::read_to_string(Self::file_name(epoch, path))?;
+        schema::from_str(&serialized).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+
+    pub fn save<P>(&self, epoch: Epoch, path: P) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized =
+            schema::to_yaml(self).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        fs::create_dir_all(&path)?;
+        let mut file = File::create(Self::file_name(epoch, path))?;
+        file.write_all(&serialized.into_bytes())?;
+
+        Ok(())
+    }
+
+    /// Fails if the stake movements of the `run_epoch` run were rolled back already, as rolling
+    /// them back again would reverse stake the run never moved
+    pub fn ensure_not_rolled_back(&self, run_epoch: Epoch) -> Result<(), String> {
+        match self.rolled_back_in {
+            Some(epoch) => Err(format!(
+                "The stake movements of the epoch {} run were already rolled back in epoch {}",
+                run_epoch, epoch
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The latest epoch, up to `epoch`, with a journal
+    pub fn latest<P>(epoch: Epoch, path: P) -> Option<Epoch>
+    where
+        P: AsRef<Path>,
+    {
+        (0..=epoch)
+            .rev()
+            .find(|epoch| Self::exists(*epoch, path.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rolled_back() {
+        let path = std::env::temp_dir().join(format!("journal-{}", Pubkey::new_unique()));
+        let mut journal = Journal::default();
+        journal.save(10, &path).unwrap();
+        assert_eq!(Journal::latest(12, &path), Some(10));
+        assert_eq!(
+            Journal::load(10, &path).unwrap().ensure_not_rolled_back(10),
+            Ok(())
+        );
+
+        journal.rolled_back_in = Some(11);
+        journal.save(10, &path).unwrap();
+        assert_eq!(
+            Journal::load(10, &path).unwrap().ensure_not_rolled_back(10),
+            Err(
+                "The stake movements of the epoch 10 run were already rolled back in epoch 11"
+                    .to_string()
+            )
+        );
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+//! Timing of the transaction bursts sent to the TPU. A transaction sent to a leader that skips its
+//! slots does not land, and during congestion it is rarely forwarded to the next leader either, so
+//! bursts are held back until the upcoming leaders are ones that produced their blocks this epoch
+use {
+    log::*,
+    solana_client::{rpc_client::RpcClient, tpu_client::DEFAULT_FANOUT_SLOTS},
+    solana_sdk::{
+        clock::{Slot, DEFAULT_MS_PER_SLOT},
+        commitment_config::CommitmentConfig,
+        pubkey::Pubkey,
+    },
+    std::{cell::Cell, collections::HashMap, thread, time::Duration},
+};
+
+/// Share of its leader slots in which a leader must have produced a block this epoch
+const MIN_LANDING_RATE: f64 = 0.9;
+
+/// Consecutive slots of good leaders that a burst targets, the fanout of the TPU client
+const BURST_SLOTS: u64 = DEFAULT_FANOUT_SLOTS;
+
+/// Slots that the bursts of one submission wait at most in total, well within the lifetime of
+/// the blockhash they were signed with
+const MAX_WAIT_SLOTS: u64 = 48;
+
+/// Share of their leader slots in which the leaders of the current epoch produced a block
+#[derive(Debug, Default)]
+pub struct LandingHistory {
+    rates: HashMap<Pubkey, f64>,
+}
+
+impl LandingHistory {
+    /// From the `(leader slots, blocks produced)` of each leader identity
+    pub fn from_block_production(by_identity: &HashMap<String, (usize, usize)>) -> Self {
+        Self {
+            rates: by_identity
+                .iter()
+                .filter(|(_, (leader_slots, _))| *leader_slots > 0)
+                .filter_map(|(identity, (leader_slots, blocks_produced))| {
+                    identity
+                        .parse()
+                        .ok()
+                        .map(|identity| (identity, *blocks_produced as f64 / *leader_slots as f64))
+                })
+                .collect(),
+        }
+    }
+
+    /// Leaders without a leader slot yet this epoch have no history to hold against them
+    fn is_good(&self, leader: &Pubkey) -> bool {
+        self.rates
+            .get(leader)
+            .map(|rate| *rate >= MIN_LANDING_RATE)
+            .unwrap_or(true)
+    }
+}
+
+/// Offset, from the first of the upcoming `leaders` by slot, of the first `BURST_SLOTS`
+/// consecutive slots that all belong to good leaders, if it is no later than `max_offset`
+fn next_burst_offset(leaders: &[Pubkey], history: &LandingHistory, max_offset: u64) -> Option<u64> {
+    let burst_slots = BURST_SLOTS as usize;
+    (0..=max_offset as usize)
+        .take_while(|offset| offset + burst_slots <= leaders.len())
+        .find(|offset| {
+            leaders[*offset..*offset + burst_slots]
+                .iter()
+                .all(|leader| history.is_good(leader))
+        })
+        .map(|offset| offset as u64)
+}
+
+/// Holds the transactions of one submission until their burst targets good leaders
+pub struct BurstTimer {
+    history: LandingHistory,
+    burst_end_slot: Cell<Slot>,
+    waited_slots: Cell<u64>,
+}
+
+impl BurstTimer {
+    /// Without the block production of the epoch every leader counts as good, and transactions
+    /// are sent without waiting
+    pub fn new(rpc_client: &RpcClient) -> Self {
+        let history = match rpc_client.get_block_production() {
+            Ok(response) => LandingHistory::from_block_production(&response.value.by_identity),
+            Err(err) => {
+                warn!("Unable to get the block production of the epoch: {}", err);
+                LandingHistory::default()
+            }
+        };
+        Self {
+            history,
+            burst_end_slot: Cell::new(0),
+            waited_slots: Cell::new(0),
+        }
+    }
+
+    /// Return once the current burst still targets its leaders, or once the next burst targets
+    /// good leaders
+    pub fn wait(&self, rpc_client: &RpcClient) {
+        let slot = match rpc_client.get_slot_with_commitment(CommitmentConfig::processed()) {
+            Ok(slot) => slot,
+            Err(err) => {
+                warn!("Unable to get the current slot: {}", err);
+                return;
+            }
+        };
+        if slot < self.burst_end_slot.get() {
+            return;
+        }
+
+        let max_offset = MAX_WAIT_SLOTS.saturating_sub(self.waited_slots.get());
+        let offset = match rpc_client.get_slot_leaders(slot, max_offset + BURST_SLOTS) {
+            Ok(leaders) => next_burst_offset(&leaders, &self.history, max_offset).unwrap_or(0),
+            Err(err) => {
+                warn!("Unable to get the leader schedule: {}", err);
+                0
+            }
+        };
+        if offset > 0 {
+            info!(
+                "Holding transactions for {} slots, until leaders with a good landing history",
+                offset
+            );
+            thread::sleep(Duration::from_millis(offset * DEFAULT_MS_PER_SLOT));
+            self.waited_slots.set(self.waited_slots.get() + offset);
+        }
+        self.burst_end_slot.set(slot + offset + BURST_SLOTS);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_next_burst_offset() {
+        let good = Pubkey::new_unique();
+        let skipping = Pubkey::new_unique();
+        let new = Pubkey::new_unique();
+        let by_identity = vec![
+            (good.to_string(), (100, 99)),
+            (skipping.to_string(), (100, 50)),
+            (new.to_string(), (0, 0)),
+        ]
+        .into_iter()
+        .collect();
+        let history = LandingHistory::from_block_production(&by_identity);
+        assert!(history.is_good(&good));
+        assert!(!history.is_good(&skipping));
+        assert!(history.is_good(&new));
+
+        // Leaders hold 4 consecutive slots
+        let leaders = [skipping, good, new, good, good, skipping]
+            .iter()
+            .flat_map(|leader| vec![*leader; 4])
+            .collect::<Vec<_>>();
+        assert_eq!(next_burst_offset(&leaders, &history, 20), Some(4));
+        assert_eq!(next_burst_offset(&leaders[4..], &history, 20), Some(0));
+        assert_eq!(next_burst_offset(&leaders, &history, 3), None);
+        assert_eq!(next_burst_offset(&leaders[..12], &history, 20), None);
+    }
+}
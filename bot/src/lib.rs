@@ -0,0 +1,88 @@
+//! Stake pool management of stake-o-matic, for services that plan and report on a stake pool
+//! without running the `solana-stake-o-matic` binary.
+//!
+//! The entry points are:
+//! * `generic_stake_pool`: the `GenericStakePool` trait, the desired stake of each validator as
+//!   `ValidatorStake`, and the `StakePoolSummary` of an update
+//! * `stake_pool` and `stake_pool_v0`: the SPL stake pool and the legacy stake pool
+//!   implementations of `GenericStakePool`, including the allocation of the desired stake without
+//!   sending any transaction
+//! * `planner`: the allocation of the pool's stake between the desired validators, as the SPL
+//!   stake pool computes it
+//! * `rebalance_plan`, `balance_forecast`, `bonus_ramp`, `liquidity_buffer` and
+//!   `stake_projection`: the planning of the stake movements over one or several epochs
+//! * `snapshot` and `idle_reserve`: reports on the state of a stake pool
+//! * `reputation`: the reputation score of the validators, which the classification carries over
+//!   from one epoch to the next
+//! * `rpc_client_utils`, `vote_account_cache` and `transaction_executor`: the RPC helpers and
+//!   transaction submission the above are built on
+//!
+//! The staking criteria that classify the validators, the data directory and the notifications
+//! remain part of the binary.
+//!
+//! Without the default `rpc` feature, the library holds only the modules free of any RPC access:
+//! `planner`, `bonus_ramp`, `liquidity_buffer`, `rebalance_plan`, `reputation`, `idle_reserve`,
+//! `generic_stake_pool`, `run_status` and `schema`. This build targets `wasm32-unknown-unknown`,
+//! so that a web dashboard allocates the stake and reports on it with the code of the bot.
+
+#[cfg(feature = "rpc")]
+pub mod account_store;
+#[cfg(feature = "rpc")]
+pub mod balance_forecast;
+#[cfg(feature = "rpc")]
+pub mod blockhash_service;
+pub mod bonus_ramp;
+#[cfg(all(test, feature = "rpc"))]
+mod chaos_rpc;
+#[cfg(feature = "rpc")]
+pub mod commission_revenue;
+#[cfg(feature = "rpc")]
+pub mod confirmation;
+#[cfg(feature = "rpc")]
+pub mod external_deposit;
+pub mod generic_stake_pool;
+pub mod idle_reserve;
+#[cfg(feature = "rpc")]
+pub mod journal;
+#[cfg(feature = "rpc")]
+pub mod leader_timing;
+pub mod liquidity_buffer;
+#[cfg(feature = "rpc")]
+pub mod memo;
+#[cfg(feature = "rpc")]
+pub mod operation_graph;
+pub mod planner;
+#[cfg(feature = "rpc")]
+pub mod pool_migration;
+#[cfg(feature = "rpc")]
+pub mod pool_state;
+pub mod rebalance_plan;
+pub mod reputation;
+#[cfg(feature = "rpc")]
+pub mod roles;
+#[cfg(feature = "rpc")]
+pub mod rollback;
+#[cfg(feature = "rpc")]
+pub mod rpc_client_utils;
+pub mod run_status;
+pub mod schema;
+#[cfg(feature = "rpc")]
+pub mod small_pool;
+#[cfg(feature = "rpc")]
+pub mod snapshot;
+#[cfg(feature = "rpc")]
+pub mod stake_account_check;
+#[cfg(feature = "rpc")]
+pub mod stake_pool;
+#[cfg(feature = "rpc")]
+pub mod stake_pool_program;
+#[cfg(feature = "rpc")]
+pub mod stake_pool_v0;
+#[cfg(feature = "rpc")]
+pub mod stake_projection;
+#[cfg(feature = "rpc")]
+pub mod transaction_executor;
+#[cfg(feature = "rpc")]
+pub mod transaction_packer;
+#[cfg(feature = "rpc")]
+pub mod vote_account_cache;
@@ -0,0 +1,107 @@
+//! Liquidity held back in the reserve for the withdrawals of the pool's users. Withdrawals take
+//! stake from the validators, and refilling them from the reserve is immediate where moving stake
+//! between validators takes an epoch. The buffer follows the withdrawals observed from one epoch
+//! to the next, and is held back from the bonus stake before its distribution
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct LiquidityBuffer {
+    /// Liquidity the reserve holds whatever the withdrawals, as the operator expects them
+    pub target_lamports: u64,
+
+    /// Share of the pool's stake, in percent, up to which the buffer is held back
+    pub max_percentage: f64,
+}
+
+/// Liquidity buffer of an epoch, recorded so that the next epoch follows the trend of the
+/// withdrawals
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct LiquidityBufferStage {
+    /// Stake of the pool when the buffer was set
+    pub pool_stake_lamports: u64,
+
+    /// Withdrawals from the pool since the previous epoch, net of the deposits and besides the
+    /// rewards
+    pub withdrawal_lamports: u64,
+
+    /// Withdrawals the buffer expects for the next epoch: the latest withdrawals, or half the
+    /// previous trend if larger, so that the buffer decays once the withdrawals stop
+    pub trend_lamports: u64,
+
+    /// Stake held back in the reserve
+    pub buffer_lamports: u64,
+}
+
+impl LiquidityBuffer {
+    /// The buffer of a pool of `pool_stake_lamports`, which earned `reward_lamports` since the
+    /// stage of the previous epoch. Without a previous stage, as on the first run, only the
+    /// target is held back
+    pub fn stage(
+        &self,
+        pool_stake_lamports: u64,
+        reward_lamports: u64,
+        previous: Option<&LiquidityBufferStage>,
+    ) -> LiquidityBufferStage {
+        let (withdrawal_lamports, trend_lamports) = match previous {
+            Some(previous) => {
+                let withdrawal_lamports = (previous.pool_stake_lamports + reward_lamports)
+                    .saturating_sub(pool_stake_lamports);
+                (
+                    withdrawal_lamports,
+                    withdrawal_lamports.max(previous.trend_lamports / 2),
+                )
+            }
+            None => (0, 0),
+        };
+        let max_lamports = (pool_stake_lamports as f64 * self.max_percentage / 100.) as u64;
+        LiquidityBufferStage {
+            pool_stake_lamports,
+            withdrawal_lamports,
+            trend_lamports,
+            buffer_lamports: self.target_lamports.max(trend_lamports).min(max_lamports),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stage() {
+        let buffer = LiquidityBuffer {
+            target_lamports: 100,
+            max_percentage: 10.,
+        };
+
+        let first = buffer.stage(10_000, 0, None);
+        assert_eq!(
+            first,
+            LiquidityBufferStage {
+                pool_stake_lamports: 10_000,
+                withdrawal_lamports: 0,
+                trend_lamports: 0,
+                buffer_lamports: 100,
+            }
+        );
+
+        // Withdrawals raise the buffer at once
+        let second = buffer.stage(9_550, 50, Some(&first));
+        assert_eq!(second.withdrawal_lamports, 500);
+        assert_eq!(second.trend_lamports, 500);
+        assert_eq!(second.buffer_lamports, 500);
+
+        // and it decays once they stop, down to the target
+        let third = buffer.stage(9_600, 50, Some(&second));
+        assert_eq!(third.withdrawal_lamports, 0);
+        assert_eq!(third.buffer_lamports, 250);
+        let fourth = buffer.stage(9_650, 50, Some(&third));
+        assert_eq!(fourth.buffer_lamports, 125);
+        let fifth = buffer.stage(9_700, 50, Some(&fourth));
+        assert_eq!(fifth.buffer_lamports, 100);
+
+        // Deposits offset withdrawals, and the buffer never exceeds its share of the pool
+        assert_eq!(buffer.stage(10_100, 0, Some(&first)).withdrawal_lamports, 0);
+        assert_eq!(buffer.stage(5_000, 0, Some(&first)).buffer_lamports, 500);
+    }
+}
@@ -1,5 +1,38 @@
 use {
-    crate::{db::*, generic_stake_pool::*, rpc_client_utils::*},
+    crate::{
+        bonus_ramp::BonusRamp,
+        canary::{Canary, CanaryPolicy},
+        classifier_inputs::ClassifierInputs,
+        commission_samples::CommissionSamples,
+        config_drift::EffectiveConfig,
+        confirmation::{Approval, ConfirmationGate},
+        criteria::{Criterion, FailedCriteria},
+        db::*,
+        desired_stake::DesiredStakeSource,
+        endpoint_probe::{probe_endpoints, ValidatorEndpoints},
+        enrichment::EnrichmentCache,
+        generic_stake_pool::*,
+        hosting_policy::{HostingPolicy, ProviderPolicy},
+        idle_reserve::IdleReservePolicy,
+        ip_to_asn::IpToAsn,
+        journal::Journal,
+        liquidity_buffer::LiquidityBuffer,
+        report_template::ReportTemplates,
+        roles::StakePoolRoles,
+        rpc_client_utils::*,
+        run_status::{guardrail, ExitCode, RunStatus},
+        screening::{ScreeningSource, ValidatorLocation},
+        small_pool::SmallPool,
+        transaction_executor::{ExecutorKind, TransactionExecutor},
+        validator_application::{CheckOutcome, OnChainFacts, ValidatorApplication},
+        validator_notices::{
+            ContactRegistry, NoticeSender, NoticeThresholds, SentNotices, ValidatorState,
+        },
+        vote_account_cache::VoteAccountCache,
+        vote_account_health::VoteAccountHealth,
+        watchdog::{Watchdog, WatchdogEvent},
+        withdrawer_change::{WithdrawerChangeAction, WithdrawerChangePolicy},
+    },
     clap::{
         crate_description, crate_name, value_t, value_t_or_exit, values_t, App, AppSettings, Arg,
         ArgMatches, SubCommand,
@@ -10,10 +43,11 @@ use {
     solana_clap_utils::{
         input_parsers::{keypair_of, lamports_of_sol, pubkey_of},
         input_validators::{
-            is_amount, is_keypair, is_parsable, is_pubkey_or_keypair, is_url, is_valid_percentage,
+            is_amount, is_keypair, is_parsable, is_pubkey, is_pubkey_or_keypair, is_url,
+            is_valid_percentage,
         },
     },
-    solana_client::rpc_client::RpcClient,
+    solana_client::{rpc_client::RpcClient, rpc_response::RpcVoteAccountInfo},
     solana_notifier::Notifier,
     solana_sdk::{
         account::from_account,
@@ -22,33 +56,78 @@ use {
         commitment_config::CommitmentConfig,
         native_token::*,
         pubkey::Pubkey,
+        rent::Rent,
+        signature::{Keypair, Signature},
         slot_history::{self, SlotHistory},
         stake_history::StakeHistory,
         sysvar,
     },
     solana_stake_program::stake_state::StakeState,
-    solana_vote_program::vote_state::VoteState,
     std::{
-        collections::{HashMap, HashSet},
+        cmp::Ordering,
+        collections::{BTreeMap, HashMap, HashSet},
         error,
         fs::{self, File},
         io::Write,
-        path::PathBuf,
+        net::SocketAddr,
+        path::{Path, PathBuf},
         process,
         str::FromStr,
-        time::Duration,
+        sync::Arc,
+        thread,
+        time::{Duration, SystemTime, UNIX_EPOCH},
     },
     thiserror::Error,
 };
 
+// Stake pool management lives in the library, imported at the crate root so that the modules of
+// the binary reach it through `crate::` paths
+use solana_stake_o_matic::{
+    bonus_ramp, confirmation, generic_stake_pool, idle_reserve, journal, liquidity_buffer, planner,
+    pool_migration, pool_state, reputation, roles, rpc_client_utils, run_status, schema,
+    small_pool, snapshot, stake_pool, stake_pool_v0, stake_projection, transaction_executor,
+    vote_account_cache,
+};
+
+mod analytics;
+mod annotations;
+mod api;
+mod artifact;
+mod attestation;
+mod canary;
+mod classifier_inputs;
+mod cluster_outage;
+mod commission_samples;
+mod config_drift;
+mod criteria;
 mod data_center_info;
+mod data_center_movement;
 mod db;
-mod generic_stake_pool;
-mod rpc_client_utils;
-mod stake_pool;
-mod stake_pool_v0;
+mod desired_stake;
+mod email_report;
+mod endpoint_probe;
+mod enrichment;
+mod feed;
+mod foundation_list;
+mod grafana;
+mod hosting_policy;
+mod http_server;
+mod input_slots;
+mod ip_to_asn;
+mod report_template;
+mod screening;
+mod stake_history;
+mod streaks;
+mod token_holders;
+mod validator_application;
 mod validator_list;
+mod validator_notices;
+mod validator_status;
 mod validators_app;
+mod vote_account_health;
+mod warehouse;
+mod watchdog;
+mod withdrawer_change;
 
 type BoxResult<T> = Result<T, Box<dyn error::Error>>;
 type ValidatorList = HashSet<Pubkey>;
@@ -59,7 +138,7 @@ enum InfrastructureConcentrationAffectKind {
     Warn(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum InfrastructureConcentrationAffects {
     WarnAll,
     DestakeListed(ValidatorList),
@@ -186,15 +265,80 @@ impl std::fmt::Display for Cluster {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Config {
     json_rpc_url: String,
     cluster: Cluster,
     db_path: PathBuf,
     markdown_path: Option<PathBuf>,
 
+    /// If Some(), the epoch classification and report are uploaded to this `s3://` or `gs://`
+    /// URL after each run
+    artifact_url: Option<String>,
+
+    /// If Some(), uploaded artifacts older than this many epochs are removed
+    artifact_retention_epochs: Option<u64>,
+
+    /// If Some(), an Atom feed of the epoch reports is written next to the markdown reports,
+    /// linking each entry to its report artifact as served from this public URL
+    atom_feed_url: Option<String>,
+
+    /// If Some(), the scoring inputs and decision of every validator are posted to this URL after
+    /// each run
+    analytics_webhook_url: Option<String>,
+
+    /// If Some(), the decision of every validator is inserted into `warehouse_table` of this
+    /// BigQuery or ClickHouse warehouse after each run
+    warehouse_url: Option<String>,
+    warehouse_table: String,
+
+    /// If Some(), the epoch report is emailed through this SMTP relay from `email_sender` to
+    /// `email_recipients` after the first run of each epoch
+    smtp_relay: Option<String>,
+    email_sender: Option<String>,
+    email_recipients: Vec<String>,
+
+    /// If Some(), this watchdog URL is pinged on startup and after each run
+    watchdog_url: Option<String>,
+
+    /// If Some(), the bot runs as a daemon, applying the stake pool again after each interval
+    daemon_interval: Option<Duration>,
+
+    /// If Some(), the vote accounts that the runs of the daemon share
+    vote_account_cache: Option<Arc<VoteAccountCache>>,
+
+    /// If Some(), the daemon serves its API on this address
+    api_listen_address: Option<SocketAddr>,
+
+    /// If Some(), the API accepts the requests bearing this token that write to the data
+    /// directory, such as the annotations of an epoch
+    api_token: Option<String>,
+
+    /// Requests per minute the API serves to each client address
+    api_rate_limit: u32,
+
+    /// If Some(), the cluster report is signed with this keypair
+    report_keypair: Option<Arc<Keypair>>,
+
+    /// If Some(), the report templates in this directory replace the built-in ones
+    report_template_dir: Option<PathBuf>,
+
     dry_run: bool,
 
+    /// Later runs of an epoch only move the stake of the validators whose desired stake changed
+    /// since the earlier runs, and record their stake movements with the epoch
+    incremental: bool,
+
+    /// Backend that submits the stake pool transactions
+    transaction_executor: ExecutorKind,
+
+    /// File that receives the transactions handed over to be submitted elsewhere, required by the
+    /// offline file and multisig proposal executors
+    transaction_output_path: Option<PathBuf>,
+
+    /// ID of the stake pool program that manages the stake pool
+    stake_pool_program_id: Pubkey,
+
     /// Quality validators produce within this percentage of the cluster average skip rate over
     /// the previous epoch
     quality_block_producer_percentage: usize,
@@ -206,6 +350,10 @@ struct Config {
     /// Vote accounts with a larger commission than this amount will not be staked.
     max_commission: u8,
 
+    /// Only destake a validator for its commission if the commission was already too high at the
+    /// epoch boundary, as the commission samples of the previous epoch recorded it
+    commission_grace: bool,
+
     /// If Some(), destake validators with a version less than this version subject to the
     /// `max_old_release_version_percentage` limit
     min_release_version: Option<semver::Version>,
@@ -234,6 +382,11 @@ struct Config {
 
     bad_cluster_average_skip_rate: usize,
 
+    /// If true, a validator's skip rate is measured relative to the cluster average skip rate
+    /// rather than as an absolute offset from it, and block production is ignored entirely for
+    /// epochs where the cluster average skip rate exceeds `bad_cluster_average_skip_rate`
+    normalize_skip_rate: bool,
+
     /// Destake if the validator's vote credits for the latest full epoch are less than this percentage
     /// of the cluster average
     min_epoch_credit_percentage_of_average: usize,
@@ -256,6 +409,85 @@ struct Config {
     ///
     /// This setting is ignored if `cluster` is not `"mainnet-beta"`
     min_testnet_participation: Option<(/*n:*/ usize, /*m:*/ usize)>,
+
+    /// If Some(), validators whose average vote lag exceeds the cluster median vote lag by more
+    /// than this many slots are not eligible for bonus stake
+    max_vote_lag: Option<u64>,
+
+    /// Number of vote account samples used to compute the average vote lag
+    vote_lag_samples: usize,
+
+    /// If Some(), the gossip and RPC endpoints published by the validators are probed for
+    /// reachability, each probe giving up after this long
+    endpoint_probe_timeout: Option<Duration>,
+
+    /// Number of validators probed at a time
+    endpoint_probe_parallelism: usize,
+
+    /// Number of validators whose vote accounts are queried at a time
+    enrichment_parallelism: usize,
+
+    /// If Some(), the cluster state read by the classification is fetched again until all of it
+    /// was observed within this many slots
+    input_slot_window: Option<Slot>,
+
+    /// Validators are not classified if the cluster halted or restarted within this many minutes.
+    /// Zero disables the check
+    outage_lookback_minutes: usize,
+
+    /// Percentage of a validator's reputation score that carries over into the next epoch
+    reputation_decay_percentage: usize,
+
+    /// If Some(), validators with a reputation score below this value are held at the Baseline
+    /// stake level while the penalties of previous epochs decay
+    min_bonus_reputation: Option<f64>,
+
+    /// If Some(), validators with less activated stake than this, from any source, are held at
+    /// the Baseline stake level until they have built a track record
+    min_bonus_activated_stake_lamports: Option<u64>,
+
+    /// If Some(), validators whose vote account changed identity are held at the Baseline stake
+    /// level for this many epochs after the change
+    identity_change_bonus_hold_epochs: Option<u64>,
+
+    /// If Some(), validators hosted by the providers of the policy are held at the Baseline stake
+    /// level or receive a reduced bonus weight
+    hosting_policy: Option<HostingPolicy>,
+
+    /// If Some(), validators whose vote account withdrawer changed to an address that is not in
+    /// the allowlist of the policy are flagged, held at the Baseline stake level or destaked
+    withdrawer_change: Option<WithdrawerChangePolicy>,
+
+    /// If Some(), validators that held Bonus stake for at least `n` consecutive epochs receive a
+    /// share of the bonus stake that is `percentage` larger than other Bonus validators
+    bonus_streak: Option<(/*n:*/ usize, /*percentage:*/ u64)>,
+
+    /// Seed that orders validators with equal stake. The epoch is used if None
+    seed: Option<u64>,
+
+    /// Sources of the desired validator stake, in order of precedence
+    desired_stake_sources: Vec<DesiredStakeSource>,
+
+    /// Hooks that exclude validators located in jurisdictions prohibited by the operator
+    screening_sources: Vec<ScreeningSource>,
+
+    /// Validators that still require a stake movement after this many consecutive epochs are
+    /// reported as failing to converge
+    max_off_target_epochs: usize,
+
+    /// If Some(), a reserve holding an idle share of the pool's stake for several epochs is
+    /// reported, and optionally deployed by promoting the Baseline validators to Bonus
+    idle_reserve: Option<IdleReservePolicy>,
+
+    /// If Some(), at most this many validators receive stake, the remainder falling below the cut
+    max_desired_validators: Option<usize>,
+
+    /// If Some(), data centers are resolved offline from this IP to ASN database instead of
+    /// validators.app
+    ip_to_asn_path: Option<PathBuf>,
+
+    /// If Some(), a share of the validators is classified under the canary policy instead
+    canary: Option<Canary>,
 }
 
 impl Config {
@@ -266,10 +498,32 @@ impl Config {
             cluster: Cluster::MainnetBeta,
             db_path: PathBuf::default(),
             markdown_path: None,
+            artifact_url: None,
+            artifact_retention_epochs: None,
+            atom_feed_url: None,
+            analytics_webhook_url: None,
+            warehouse_url: None,
+            warehouse_table: warehouse::DEFAULT_TABLE.to_string(),
+            smtp_relay: None,
+            email_sender: None,
+            email_recipients: vec![],
+            watchdog_url: None,
+            daemon_interval: None,
+            vote_account_cache: None,
+            api_listen_address: None,
+            api_token: None,
+            api_rate_limit: 60,
+            report_keypair: None,
+            report_template_dir: None,
             dry_run: true,
+            incremental: false,
+            transaction_executor: ExecutorKind::Rpc,
+            transaction_output_path: None,
+            stake_pool_program_id: spl_stake_pool::id(),
             quality_block_producer_percentage: 15,
             max_poor_block_producer_percentage: 20,
             max_commission: 100,
+            commission_grace: false,
             min_release_version: None,
             max_old_release_version_percentage: 10,
             max_poor_voter_percentage: 20,
@@ -277,12 +531,248 @@ impl Config {
             max_infrastructure_concentration: 100.0,
             infrastructure_concentration_affects: InfrastructureConcentrationAffects::WarnAll,
             bad_cluster_average_skip_rate: 50,
+            normalize_skip_rate: false,
             min_epoch_credit_percentage_of_average: 50,
             min_self_stake_lamports: 0,
             enforce_min_self_stake: false,
             enforce_testnet_participation: false,
             min_testnet_participation: None,
+            max_vote_lag: None,
+            vote_lag_samples: 10,
+            endpoint_probe_timeout: None,
+            endpoint_probe_parallelism: 32,
+            enrichment_parallelism: 16,
+            input_slot_window: None,
+            outage_lookback_minutes: 60,
+            reputation_decay_percentage: 80,
+            min_bonus_reputation: None,
+            min_bonus_activated_stake_lamports: None,
+            identity_change_bonus_hold_epochs: None,
+            hosting_policy: None,
+            withdrawer_change: None,
+            bonus_streak: None,
+            seed: None,
+            desired_stake_sources: vec![DesiredStakeSource::Classifier],
+            screening_sources: vec![],
+            max_off_target_epochs: 3,
+            idle_reserve: None,
+            max_desired_validators: None,
+            ip_to_asn_path: None,
+            canary: None,
+        }
+    }
+
+    /// The classification policy, with the lists it reads identified by the hash of their
+    /// contents. Recorded each epoch so that policy changes can be audited
+    fn effective_config(&self) -> EffectiveConfig {
+        let infrastructure_concentration_affects = match &self.infrastructure_concentration_affects
+        {
+            InfrastructureConcentrationAffects::DestakeListed(validator_list) => {
+                let mut validator_list = validator_list
+                    .iter()
+                    .map(|identity| identity.to_string())
+                    .collect::<Vec<_>>();
+                validator_list.sort();
+                format!("DestakeListed([{}])", validator_list.join(", "))
+            }
+            affects => format!("{:?}", affects),
+        };
+        let screening_sources = self
+            .screening_sources
+            .iter()
+            .map(|screening_source| match screening_source {
+                ScreeningSource::List(path) => format!(
+                    "List({}, {})",
+                    path.display(),
+                    config_drift::file_hash(path)
+                ),
+                screening_source => format!("{:?}", screening_source),
+            })
+            .collect::<Vec<_>>();
+
+        vec![
+            ("cluster", self.cluster.to_string()),
+            (
+                "stake_pool_program_id",
+                self.stake_pool_program_id.to_string(),
+            ),
+            (
+                "quality_block_producer_percentage",
+                self.quality_block_producer_percentage.to_string(),
+            ),
+            (
+                "max_poor_block_producer_percentage",
+                self.max_poor_block_producer_percentage.to_string(),
+            ),
+            ("max_commission", self.max_commission.to_string()),
+            ("commission_grace", self.commission_grace.to_string()),
+            (
+                "min_release_version",
+                format!("{:?}", self.min_release_version),
+            ),
+            (
+                "max_old_release_version_percentage",
+                self.max_old_release_version_percentage.to_string(),
+            ),
+            (
+                "max_poor_voter_percentage",
+                self.max_poor_voter_percentage.to_string(),
+            ),
+            (
+                "max_infrastructure_concentration",
+                self.max_infrastructure_concentration.to_string(),
+            ),
+            (
+                "infrastructure_concentration_affects",
+                infrastructure_concentration_affects,
+            ),
+            (
+                "bad_cluster_average_skip_rate",
+                self.bad_cluster_average_skip_rate.to_string(),
+            ),
+            ("normalize_skip_rate", self.normalize_skip_rate.to_string()),
+            (
+                "min_epoch_credit_percentage_of_average",
+                self.min_epoch_credit_percentage_of_average.to_string(),
+            ),
+            (
+                "min_self_stake_lamports",
+                self.min_self_stake_lamports.to_string(),
+            ),
+            (
+                "enforce_min_self_stake",
+                self.enforce_min_self_stake.to_string(),
+            ),
+            (
+                "enforce_testnet_participation",
+                self.enforce_testnet_participation.to_string(),
+            ),
+            (
+                "min_testnet_participation",
+                format!("{:?}", self.min_testnet_participation),
+            ),
+            ("max_vote_lag", format!("{:?}", self.max_vote_lag)),
+            ("vote_lag_samples", self.vote_lag_samples.to_string()),
+            (
+                "endpoint_probe_timeout",
+                format!("{:?}", self.endpoint_probe_timeout),
+            ),
+            (
+                "endpoint_probe_parallelism",
+                self.endpoint_probe_parallelism.to_string(),
+            ),
+            (
+                "enrichment_parallelism",
+                self.enrichment_parallelism.to_string(),
+            ),
+            ("input_slot_window", format!("{:?}", self.input_slot_window)),
+            (
+                "outage_lookback_minutes",
+                self.outage_lookback_minutes.to_string(),
+            ),
+            (
+                "reputation_decay_percentage",
+                self.reputation_decay_percentage.to_string(),
+            ),
+            (
+                "min_bonus_reputation",
+                format!("{:?}", self.min_bonus_reputation),
+            ),
+            (
+                "min_bonus_activated_stake_lamports",
+                format!("{:?}", self.min_bonus_activated_stake_lamports),
+            ),
+            (
+                "identity_change_bonus_hold_epochs",
+                format!("{:?}", self.identity_change_bonus_hold_epochs),
+            ),
+            ("hosting_policy", format!("{:?}", self.hosting_policy)),
+            ("withdrawer_change", format!("{:?}", self.withdrawer_change)),
+            ("bonus_streak", format!("{:?}", self.bonus_streak)),
+            ("seed", format!("{:?}", self.seed)),
+            (
+                "desired_stake_sources",
+                format!("{:?}", self.desired_stake_sources),
+            ),
+            (
+                "screening_sources",
+                format!("[{}]", screening_sources.join(", ")),
+            ),
+            (
+                "max_off_target_epochs",
+                self.max_off_target_epochs.to_string(),
+            ),
+            ("idle_reserve", format!("{:?}", self.idle_reserve)),
+            (
+                "max_desired_validators",
+                format!("{:?}", self.max_desired_validators),
+            ),
+            ("ip_to_asn_path", format!("{:?}", self.ip_to_asn_path)),
+            (
+                "canary",
+                match &self.canary {
+                    Some(canary) => format!("{}% {{{}}}", canary.percentage, canary.policy),
+                    None => "None".to_string(),
+                },
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+
+    /// The settings of the canary `policy`, the others unchanged
+    fn with_canary_policy(&self, policy: &CanaryPolicy) -> Config {
+        let mut config = self.clone();
+        if let Some(quality_block_producer_percentage) = policy.quality_block_producer_percentage {
+            config.quality_block_producer_percentage = quality_block_producer_percentage;
         }
+        if let Some(max_poor_block_producer_percentage) = policy.max_poor_block_producer_percentage
+        {
+            config.max_poor_block_producer_percentage = max_poor_block_producer_percentage;
+        }
+        if let Some(max_commission) = policy.max_commission {
+            config.max_commission = max_commission;
+        }
+        if let Some(max_old_release_version_percentage) = policy.max_old_release_version_percentage
+        {
+            config.max_old_release_version_percentage = max_old_release_version_percentage;
+        }
+        if let Some(max_poor_voter_percentage) = policy.max_poor_voter_percentage {
+            config.max_poor_voter_percentage = max_poor_voter_percentage;
+        }
+        if let Some(max_infrastructure_concentration) = policy.max_infrastructure_concentration {
+            config.max_infrastructure_concentration = max_infrastructure_concentration;
+        }
+        if let Some(bad_cluster_average_skip_rate) = policy.bad_cluster_average_skip_rate {
+            config.bad_cluster_average_skip_rate = bad_cluster_average_skip_rate;
+        }
+        if let Some(normalize_skip_rate) = policy.normalize_skip_rate {
+            config.normalize_skip_rate = normalize_skip_rate;
+        }
+        if let Some(min_epoch_credit_percentage_of_average) =
+            policy.min_epoch_credit_percentage_of_average
+        {
+            config.min_epoch_credit_percentage_of_average = min_epoch_credit_percentage_of_average;
+        }
+        if let Some(min_self_stake_lamports) = policy.min_self_stake_lamports {
+            config.min_self_stake_lamports = min_self_stake_lamports;
+        }
+        if let Some(enforce_min_self_stake) = policy.enforce_min_self_stake {
+            config.enforce_min_self_stake = enforce_min_self_stake;
+        }
+        if let Some(reputation_decay_percentage) = policy.reputation_decay_percentage {
+            config.reputation_decay_percentage = reputation_decay_percentage;
+        }
+        config.max_vote_lag = policy.max_vote_lag.or(config.max_vote_lag);
+        config.min_bonus_reputation = policy.min_bonus_reputation.or(config.min_bonus_reputation);
+        config.min_bonus_activated_stake_lamports = policy
+            .min_bonus_activated_stake_lamports
+            .or(config.min_bonus_activated_stake_lamports);
+        config.identity_change_bonus_hold_epochs = policy
+            .identity_change_bonus_hold_epochs
+            .or(config.identity_change_bonus_hold_epochs);
+        config
     }
 
     fn cluster_db_path_for(&self, cluster: Cluster) -> PathBuf {
@@ -292,6 +782,15 @@ impl Config {
     fn cluster_db_path(&self) -> PathBuf {
         self.cluster_db_path_for(self.cluster)
     }
+
+    fn transaction_executor(&self) -> BoxResult<Box<dyn TransactionExecutor>> {
+        transaction_executor::new(
+            self.transaction_executor,
+            self.dry_run,
+            &self.json_rpc_url,
+            self.transaction_output_path.as_deref(),
+        )
+    }
 }
 
 fn default_confirmed_block_cache_path() -> PathBuf {
@@ -310,7 +809,96 @@ fn app_version() -> String {
     })
 }
 
-fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
+enum Command {
+    /// Classify the validators and apply the resulting stake to the stake pool
+    Apply(Box<dyn GenericStakePool>),
+    /// Compare the stake of each validator in the pool with the stake the current policy would
+    /// give it, without changing the pool
+    Diff(Box<dyn GenericStakePool>),
+    RotateStaker {
+        stake_pool_address: Pubkey,
+        manager: Keypair,
+        new_staker: Keypair,
+    },
+    /// Issue the inverse of the stake movements of the last run
+    Rollback {
+        stake_pool_address: Pubkey,
+        staker: Keypair,
+    },
+    MigratePool {
+        source: Pubkey,
+        destination: Pubkey,
+        holder: Keypair,
+        max_epoch_percentage: f64,
+    },
+    ExportSnapshot {
+        stake_pool_address: Pubkey,
+        output_path: PathBuf,
+    },
+    ExportTokenHolders {
+        stake_pool_address: Pubkey,
+        output_path: PathBuf,
+        slot: Option<Slot>,
+    },
+    /// Insert the validator decisions of the saved epochs into the warehouse
+    ExportWarehouse {
+        first_epoch: Option<Epoch>,
+    },
+    DiffSnapshots {
+        before_path: PathBuf,
+        after_path: PathBuf,
+    },
+    ServeEpochSummary {
+        listen_address: SocketAddr,
+        epochs: usize,
+    },
+    StakeHistory {
+        validator: Pubkey,
+        json: bool,
+    },
+    /// Attach an annotation to an epoch if `text` is given, and display its annotations
+    Annotate {
+        epoch: Epoch,
+        text: Option<String>,
+        author: Option<String>,
+    },
+    /// Check the application of a validator to the desired list against the chain
+    ValidateApplication {
+        application_path: PathBuf,
+    },
+    VerifyReport {
+        report_path: PathBuf,
+        signer: Option<Pubkey>,
+    },
+    Reproduce {
+        epoch: Epoch,
+    },
+    /// Classify the validators for the next epoch from the data of the epoch in progress
+    Preview,
+    Verify {
+        epoch: Epoch,
+        path: PathBuf,
+    },
+    ExportFoundationList {
+        epoch: Epoch,
+        output_path: PathBuf,
+    },
+    SignApproval {
+        stake_pool_address: Pubkey,
+        epoch: Epoch,
+        approver: Keypair,
+    },
+    RefreshIpToAsnDb {
+        path: PathBuf,
+        url: String,
+    },
+    SendValidatorNotices {
+        contact_registry_path: PathBuf,
+        discord_webhook_url: Option<String>,
+    },
+}
+
+fn get_config() -> BoxResult<(Config, RpcClient, Command)> {
     let default_confirmed_block_cache_path = default_confirmed_block_cache_path()
         .to_str()
         .unwrap()
@@ -346,12 +934,218 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
                 .takes_value(false)
                 .help("Confirm that the stake adjustments should actually be made")
         )
+        .arg(
+            Arg::with_name("incremental")
+                .long("incremental")
+                .takes_value(false)
+                .help("When the epoch already ran, only move the stake of the validators whose \
+                       desired stake changed since, such as after a large deposit, rather than \
+                       plan the stake of the whole pool again")
+        )
+        .arg(
+            Arg::with_name("executor")
+                .long("executor")
+                .value_name("KIND")
+                .possible_values(&["rpc", "tpu", "offline-file", "multisig-proposal"])
+                .takes_value(true)
+                .default_value("rpc")
+                .help("How the stake pool transactions are submitted: over RPC, straight to the \
+                       TPU of the upcoming leaders, timed toward leaders that produced their \
+                       blocks this epoch, or written to the --executor-output file unsigned for \
+                       an offline signer, or partially signed as multisig proposals")
+        )
+        .arg(
+            Arg::with_name("executor_output")
+                .long("executor-output")
+                .value_name("FILE")
+                .takes_value(true)
+                .required_ifs(&[("executor", "offline-file"), ("executor", "multisig-proposal")])
+                .help("File that the transactions are appended to, one JSON object per line")
+        )
+        .arg(
+            Arg::with_name("stake_pool_program_id")
+                .long("stake-pool-program-id")
+                .value_name("PROGRAM_ID")
+                .takes_value(true)
+                .validator(is_pubkey)
+                .help("ID of the stake pool program managing the pool, for forks and test \
+                       deployments [default: the SPL stake pool program]")
+        )
         .arg(
             Arg::with_name("markdown")
                 .long("markdown")
                 .takes_value(false)
                 .help("Output markdown")
         )
+        .arg(
+            Arg::with_name("artifact_url")
+                .long("artifact-url")
+                .value_name("URL")
+                .takes_value(true)
+                .validator(|url| artifact::new(&url).map(|_| ()))
+                .help("Upload the epoch classification and report to this s3://BUCKET/PREFIX \
+                       or gs://BUCKET/PREFIX location, using the aws or gsutil command-line tool")
+        )
+        .arg(
+            Arg::with_name("artifact_retention_epochs")
+                .long("artifact-retention-epochs")
+                .value_name("EPOCHS")
+                .takes_value(true)
+                .requires("artifact_url")
+                .validator(is_parsable::<u64>)
+                .help("Remove uploaded artifacts that are older than this many epochs")
+        )
+        .arg(
+            Arg::with_name("atom_feed_url")
+                .long("atom-feed")
+                .value_name("URL")
+                .takes_value(true)
+                .requires_all(&["markdown", "artifact_url"])
+                .validator(is_url)
+                .help("Write an Atom feed of the epoch reports next to the markdown reports, \
+                       linking each entry to its report artifact as served from this public \
+                       URL, the https:// address of the --artifact-url location")
+        )
+        .arg(
+            Arg::with_name("analytics_webhook_url")
+                .long("analytics-webhook")
+                .value_name("URL")
+                .takes_value(true)
+                .validator(is_url)
+                .help("POST the scoring inputs and decision of every validator to this URL as \
+                       JSON after each run, signed with the secret in the \
+                       ANALYTICS_WEBHOOK_SECRET environment variable, which must be set")
+        )
+        .arg(
+            Arg::with_name("warehouse_url")
+                .long("warehouse")
+                .value_name("URL")
+                .takes_value(true)
+                .help("Insert the scoring inputs and decision of every validator into this \
+                       warehouse after each run: bigquery://PROJECT/DATASET, written with the \
+                       bq tool, or clickhouse+https://USER@HOST:PORT/DATABASE, with the \
+                       password in the CLICKHOUSE_PASSWORD environment variable")
+        )
+        .arg(
+            Arg::with_name("warehouse_table")
+                .long("warehouse-table")
+                .value_name("TABLE")
+                .takes_value(true)
+                .default_value(warehouse::DEFAULT_TABLE)
+                .help("Warehouse table the validator decisions are inserted into")
+        )
+        .arg(
+            Arg::with_name("smtp_relay")
+                .long("smtp-relay")
+                .value_name("HOST")
+                .takes_value(true)
+                .requires_all(&["email_from", "email_to"])
+                .help("Email the epoch report, as an HTML table with the validator decisions \
+                       attached as JSON, through this SMTP relay. The relay is authenticated \
+                       with the SMTP_USERNAME and SMTP_PASSWORD environment variables if set")
+        )
+        .arg(
+            Arg::with_name("email_from")
+                .long("email-from")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .requires("smtp_relay")
+                .help("Sender of the epoch report email")
+        )
+        .arg(
+            Arg::with_name("email_to")
+                .long("email-to")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("smtp_relay")
+                .help("Recipient of the epoch report email. May be specified multiple times")
+        )
+        .arg(
+            Arg::with_name("watchdog_url")
+                .long("watchdog-url")
+                .value_name("URL")
+                .takes_value(true)
+                .validator(is_url)
+                .help("Ping this healthchecks.io or Cronitor style URL on startup and after each \
+                       run, so that the watchdog alerts when the pings stop")
+        )
+        .arg(
+            Arg::with_name("daemon")
+                .long("daemon")
+                .takes_value(false)
+                .help("Keep running, applying the stake pool again every --daemon-interval")
+        )
+        .arg(
+            Arg::with_name("daemon_interval")
+                .long("daemon-interval")
+                .value_name("MINUTES")
+                .takes_value(true)
+                .default_value("60")
+                .validator(is_parsable::<u64>)
+                .help("Minutes between the runs of the daemon")
+        )
+        .arg(
+            Arg::with_name("vote_accounts_max_age")
+                .long("vote-accounts-max-age")
+                .value_name("MINUTES")
+                .takes_value(true)
+                .default_value("60")
+                .requires("daemon")
+                .validator(is_parsable::<u64>)
+                .help("Time to live, in minutes, of the daemon's cache of the vote accounts \
+                       of the cluster, which are fetched again in full once it expires or the \
+                       epoch changes")
+        )
+        .arg(
+            Arg::with_name("api_listen_address")
+                .long("api-listen")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .requires("daemon")
+                .validator(is_parsable::<SocketAddr>)
+                .help("Serve the API of the daemon, such as the stake history of each \
+                       validator, on this address")
+        )
+        .arg(
+            Arg::with_name("api_token_file")
+                .long("api-token-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .requires("api_listen_address")
+                .help("Accept the API requests that annotate an epoch when they bear the \
+                       token read from this file as \"Authorization: Bearer TOKEN\"")
+        )
+        .arg(
+            Arg::with_name("api_rate_limit")
+                .long("api-rate-limit")
+                .value_name("REQUESTS")
+                .takes_value(true)
+                .default_value("60")
+                .requires("api_listen_address")
+                .validator(is_parsable::<u32>)
+                .help("Requests per minute the API serves to each client address, including \
+                       the requests bearing the API token")
+        )
+        .arg(
+            Arg::with_name("report_keypair")
+                .long("report-keypair")
+                .value_name("KEYPAIR")
+                .takes_value(true)
+                .validator(is_keypair)
+                .help("Sign the cluster report with this keypair, such as the authorized staker \
+                       or a dedicated reporting keypair")
+        )
+        .arg(
+            Arg::with_name("report_template_dir")
+                .long("report-templates")
+                .value_name("DIR")
+                .takes_value(true)
+                .requires("markdown")
+                .help("Render the reports with the cluster.md.hbs and validator.md.hbs \
+                       Handlebars templates of this directory, instead of the built-in ones")
+        )
         .arg(
             Arg::with_name("db_path")
                 .long("db-path")
@@ -379,6 +1173,14 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
                 .validator(is_valid_percentage)
                 .help("Threshold to notify for a poor average cluster skip rate.")
         )
+        .arg(
+            Arg::with_name("normalize_skip_rate")
+                .long("normalize-skip-rate")
+                .takes_value(false)
+                .help("Measure validator skip rates relative to the cluster average skip rate, \
+                       and ignore block production for epochs where the cluster average skip \
+                       rate exceeds --bad-cluster-average-skip-rate")
+        )
         .arg(
             Arg::with_name("max_poor_block_producer_percentage")
                 .long("max-poor-block-producer-percentage")
@@ -408,6 +1210,15 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
                 .validator(is_valid_percentage)
                 .help("Vote accounts with a larger commission than this amount will not be staked")
         )
+        .arg(
+            Arg::with_name("commission_grace")
+                .long("commission-grace")
+                .takes_value(false)
+                .help("Sample the commission of the vote accounts on every run, and only \
+                       destake a validator for a commission above --max-commission if the \
+                       commission was already above it in the last sample of the previous \
+                       epoch, tolerating a commission raised by mistake since the epoch boundary")
+        )
         .arg(
             Arg::with_name("min_release_version")
                 .long("min-release-version")
@@ -513,46 +1324,968 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
                 .help("Enforce the minimum testnet participation requirement.\n
                        This setting is ignored if the --cluster is not `mainnet-beta`")
         )
-        .subcommand(
-            SubCommand::with_name("stake-pool-v0").about("Use the stake-pool v0 solution")
-            .arg(
-                Arg::with_name("reserve_stake_address")
-                    .index(1)
-                    .value_name("RESERVE_STAKE_ADDRESS")
-                    .takes_value(true)
-                    .required(true)
-                    .validator(is_pubkey_or_keypair)
-                    .help("The reserve stake account used to fund the stake pool")
-            )
-            .arg(
-                Arg::with_name("authorized_staker")
-                    .index(2)
-                    .value_name("KEYPAIR")
-                    .validator(is_keypair)
-                    .required(true)
-                    .takes_value(true)
-                    .help("Keypair of the authorized staker")
-            )
-            .arg(
-                Arg::with_name("min_reserve_stake_balance")
-                    .long("min-reserve-stake-balance")
-                    .value_name("SOL")
-                    .takes_value(true)
-                    .default_value("1")
+        .arg(
+            Arg::with_name("max_vote_lag")
+                .long("max-vote-lag")
+                .value_name("SLOTS")
+                .takes_value(true)
+                .validator(is_parsable::<u64>)
+                .help("Validators whose average vote lag exceeds the cluster median \
+                       by more than this many slots are not eligible for bonus stake")
+        )
+        .arg(
+            Arg::with_name("vote_lag_samples")
+                .long("vote-lag-samples")
+                .value_name("COUNT")
+                .takes_value(true)
+                .default_value("10")
+                .validator(is_parsable::<usize>)
+                .help("Number of samples, taken one second apart, used to compute \
+                       each validator's average vote lag")
+        )
+        .arg(
+            Arg::with_name("probe_endpoints")
+                .long("probe-endpoints")
+                .value_name("MILLISECONDS")
+                .takes_value(true)
+                .validator(is_parsable::<u64>)
+                .help("Probe the gossip and RPC endpoints published by each validator, giving \
+                       up after this many milliseconds, and note the validators with an \
+                       unreachable endpoint")
+        )
+        .arg(
+            Arg::with_name("probe_parallelism")
+                .long("probe-parallelism")
+                .value_name("COUNT")
+                .takes_value(true)
+                .default_value("32")
+                .validator(is_parsable::<usize>)
+                .help("Number of validators whose endpoints are probed at a time")
+        )
+        .arg(
+            Arg::with_name("enrichment_parallelism")
+                .long("enrichment-parallelism")
+                .value_name("COUNT")
+                .takes_value(true)
+                .default_value("16")
+                .validator(is_parsable::<usize>)
+                .help("Number of validators whose vote accounts are queried at a time. \
+                       Results are cached for reruns of the same epoch")
+        )
+        .arg(
+            Arg::with_name("input_slot_window")
+                .long("input-slot-window")
+                .value_name("SLOTS")
+                .takes_value(true)
+                .validator(is_parsable::<Slot>)
+                .help("Fetch the vote accounts, stake accounts, cluster nodes, blocks and vote \
+                       lag samples read by the classification again until all of them were \
+                       observed within this many slots, aborting after a few attempts")
+        )
+        .arg(
+            Arg::with_name("outage_lookback_minutes")
+                .long("outage-lookback")
+                .value_name("MINUTES")
+                .takes_value(true)
+                .default_value("60")
+                .validator(is_parsable::<usize>)
+                .help("Skip the classification and stake movements of the epoch if the \
+                       cluster halted or restarted within this many minutes, or its newest \
+                       vote is stale. 0 disables the check")
+        )
+        .arg(
+            Arg::with_name("reputation_decay_percentage")
+                .long("reputation-decay-percentage")
+                .value_name("PERCENTAGE")
+                .takes_value(true)
+                .default_value("80")
+                .validator(is_valid_percentage)
+                .help("Percentage of a validator's reputation score that carries over \
+                       into the next epoch. Each epoch adds 1 to the score for Bonus \
+                       stake and subtracts 1 for no stake")
+        )
+        .arg(
+            Arg::with_name("min_bonus_reputation")
+                .long("min-bonus-reputation")
+                .value_name("SCORE")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .validator(is_parsable::<f64>)
+                .help("Validators with a reputation score below this value are held at \
+                       the Baseline stake level until their earlier penalties decay")
+        )
+        .arg(
+            Arg::with_name("min_bonus_activated_stake")
+                .long("min-bonus-activated-stake")
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .validator(is_amount)
+                .help("Minimum amount of SOL activated on a validator's vote account, from \
+                       any source, to be eligible for bonus stake")
+        )
+        .arg(
+            Arg::with_name("hosting_policy")
+                .long("hosting-policy")
+                .value_name("YAML")
+                .takes_value(true)
+                .help("YAML file listing hosting providers by ASN, whose validators are not \
+                       eligible for bonus stake or receive a reduced bonus weight")
+        )
+        .arg(
+            Arg::with_name("withdrawer_change")
+                .long("withdrawer-change")
+                .value_name("warn|baseline|destake")
+                .takes_value(true)
+                .possible_values(&["warn", "baseline", "destake"])
+                .help("Flag the validators whose vote account withdrawer changed to an \
+                       address that is not in the allowlist, and optionally hold them at the \
+                       Baseline stake level or destake them until it is allowlisted")
+        )
+        .arg(
+            Arg::with_name("withdrawer_allowlist")
+                .long("withdrawer-allowlist")
+                .value_name("YAML")
+                .takes_value(true)
+                .requires("withdrawer_change")
+                .help("YAML list of the withdrawer addresses of planned rotations, which are \
+                       not flagged")
+        )
+        .arg(
+            Arg::with_name("canary_policy")
+                .long("canary-policy")
+                .value_name("YAML")
+                .takes_value(true)
+                .help("YAML file of classifier settings, by name, of a policy change to roll \
+                       out on the validators of a share of hash buckets first, while the \
+                       others remain under the current settings")
+        )
+        .arg(
+            Arg::with_name("canary_percentage")
+                .long("canary-percentage")
+                .value_name("PERCENTAGE")
+                .takes_value(true)
+                .default_value("10")
+                .requires("canary_policy")
+                .validator(is_valid_percentage)
+                .help("Share of the hash buckets whose validators are classified under the \
+                       canary policy")
+        )
+        .arg(
+            Arg::with_name("identity_change_bonus_hold_epochs")
+                .long("identity-change-bonus-hold")
+                .value_name("EPOCHS")
+                .takes_value(true)
+                .validator(is_parsable::<u64>)
+                .help("Hold validators at the Baseline stake level for this many epochs after \
+                       their vote account changes validator identity")
+        )
+        .arg(
+            Arg::with_name("bonus_streak")
+                .long("bonus-streak")
+                .value_name("EPOCHS PERCENTAGE")
+                .multiple(true)
+                .min_values(2)
+                .max_values(2)
+                .validator(is_parsable::<u64>)
+                .help("Validators that held Bonus stake for at least EPOCHS consecutive \
+                       epochs receive PERCENTAGE more bonus stake than other Bonus validators")
+        )
+        .arg(
+            Arg::with_name("desired_stake_source")
+                .long("desired-stake-source")
+                .visible_alias("desired-validators")
+                .value_name("SOURCE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(is_parsable::<DesiredStakeSource>)
+                .help("Source of the desired validator stake: classifier, registry (baseline stake \
+                       for every approved registry participant), file:PATH (JSON desired stake \
+                       file), foundation:PATH (YAML or JSON validator list published by the \
+                       Solana Foundation stake-o-matic) or - (JSON lines of desired stake read \
+                       from the standard input). May be specified multiple times, in order of \
+                       precedence [default: classifier]")
+        )
+        .arg(
+            Arg::with_name("screening_hook")
+                .long("screening-hook")
+                .value_name("HOOK")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(is_parsable::<ScreeningSource>)
+                .help("Exclude validators located in prohibited jurisdictions: list:PATH (YAML \
+                       list of prohibited countries and data centers) or command:COMMAND \
+                       (command given the validator locations as JSON on stdin, that writes \
+                       the validators to exclude as JSON to stdout). May be specified multiple \
+                       times")
+        )
+        .arg(
+            Arg::with_name("ip_to_asn_db")
+                .long("ip-to-asn-db")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Resolve the data centers of validators offline from this IP to ASN \
+                       database, in the TSV format of iptoasn.com, instead of validators.app. \
+                       See the refresh-ip-to-asn-db subcommand")
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .takes_value(true)
+                .validator(is_parsable::<u64>)
+                .help("Seed that orders validators with equal stake [default: the current epoch]")
+        )
+        .arg(
+            Arg::with_name("max_off_target_epochs")
+                .long("max-off-target-epochs")
+                .value_name("EPOCHS")
+                .takes_value(true)
+                .default_value("3")
+                .validator(is_parsable::<usize>)
+                .help("Report validators that still require a stake movement to reach their \
+                       desired stake after this many consecutive epochs")
+        )
+        .arg(
+            Arg::with_name("idle_reserve_alert")
+                .long("idle-reserve-alert")
+                .value_name("PERCENTAGE EPOCHS")
+                .multiple(true)
+                .min_values(2)
+                .max_values(2)
+                .help("Report a reserve that held at least PERCENTAGE of the pool's stake, idle, \
+                       after the stake movements of EPOCHS consecutive epochs")
+        )
+        .arg(
+            Arg::with_name("deploy_idle_reserve")
+                .long("deploy-idle-reserve")
+                .takes_value(false)
+                .requires("idle_reserve_alert")
+                .help("Once an idle reserve is reported, promote the Baseline validators to \
+                       Bonus the next epoch to deploy it")
+        )
+        .arg(
+            Arg::with_name("max_desired_validators")
+                .long("max-desired-validators")
+                .value_name("COUNT")
+                .takes_value(true)
+                .validator(is_parsable::<usize>)
+                .help("Delegate to at most this many validators, ranked by stake state, \
+                       reputation and vote credits, to bound the stake pool's transaction \
+                       and rent costs")
+        )
+        .arg(
+            Arg::with_name("min_stake_change_amount")
+                .long("min-stake-change-amount")
+                .value_name("SOL")
+                .takes_value(true)
+                .default_value("1")
+                .validator(is_amount)
+                .help("Don't adjust a validator's stake by less than this amount. \
+                       The SPL stake pool program requires at least 1 SOL, its \
+                       MINIMUM_ACTIVE_STAKE, for every stake movement")
+        )
+        .arg(
+            Arg::with_name("min_stake_change_percentage")
+                .long("min-stake-change-percentage")
+                .value_name("PERCENTAGE")
+                .takes_value(true)
+                .validator(is_parsable::<f64>)
+                .help("Also don't adjust a validator's stake by less than this percentage \
+                       of its desired stake")
+        )
+        .arg(
+            Arg::with_name("stake_tolerance")
+                .long("stake-tolerance")
+                .value_name("PERCENTAGE [ABOVE_PERCENTAGE]")
+                .multiple(true)
+                .min_values(1)
+                .max_values(2)
+                .validator(is_parsable::<f64>)
+                .help("Leave a validator's stake alone while it is within this percentage of \
+                       its desired stake. If ABOVE_PERCENTAGE is given, PERCENTAGE only applies \
+                       below the desired stake and ABOVE_PERCENTAGE applies above it")
+        )
+        .arg(
+            Arg::with_name("small_pool")
+                .long("small-pool")
+                .takes_value(false)
+                .help("For pools holding under 100 SOL. Relax the 1 SOL minimums of the stake \
+                       accounts and stake changes to the minimum delegation of the cluster, \
+                       where the stake pool program allows it, and skip stake changes below \
+                       a percentage of each validator's desired stake, 5% unless \
+                       --min-stake-change-percentage is given")
+        )
+        .subcommand(
+            SubCommand::with_name("stake-pool-v0").about("Use the stake-pool v0 solution")
+            .arg(
+                Arg::with_name("reserve_stake_address")
+                    .index(1)
+                    .value_name("RESERVE_STAKE_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The reserve stake account used to fund the stake pool")
+            )
+            .arg(
+                Arg::with_name("authorized_staker")
+                    .index(2)
+                    .value_name("KEYPAIR")
+                    .validator(is_keypair)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Keypair of the authorized staker")
+            )
+            .arg(
+                Arg::with_name("min_reserve_stake_balance")
+                    .long("min-reserve-stake-balance")
+                    .value_name("SOL")
+                    .takes_value(true)
+                    .default_value("1")
                     .validator(is_amount)
                     .help("The minimum balance to keep in the reserve stake account")
             )
             .arg(
-                Arg::with_name("baseline_stake_amount")
-                    .long("baseline-stake-amount")
-                    .value_name("SOL")
+                Arg::with_name("baseline_stake_amount")
+                    .long("baseline-stake-amount")
+                    .value_name("SOL")
+                    .takes_value(true)
+                    .default_value("5000")
+                    .validator(is_amount)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("stake-pool").about("Use a stake pool")
+            .arg(
+                Arg::with_name("pool_address")
+                    .index(1)
+                    .value_name("POOL_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The stake pool address")
+            )
+            .arg(
+                Arg::with_name("authorized_staker")
+                    .index(2)
+                    .value_name("KEYPAIR")
+                    .validator(is_keypair)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Keypair of the authorized staker")
+            )
+            .arg(
+                Arg::with_name("baseline_stake_amount")
+                    .long("baseline-stake-amount")
+                    .value_name("SOL")
+                    .takes_value(true)
+                    .default_value("5000")
+                    .validator(is_amount)
+            )
+            .arg(
+                Arg::with_name("manager")
+                    .long("manager-keypair")
+                    .value_name("KEYPAIR")
+                    .takes_value(true)
+                    .validator(is_keypair)
+                    .help("Keypair of the stake pool manager, required for manager operations")
+            )
+            .arg(
+                Arg::with_name("funder")
+                    .long("funder-keypair")
+                    .value_name("KEYPAIR")
+                    .takes_value(true)
+                    .validator(is_keypair)
+                    .help("Keypair that pays for accounts created for the stake pool \
+                           [default: the authorized staker]")
+            )
+            .arg(
+                Arg::with_name("funder_low_balance")
+                    .long("funder-low-balance")
+                    .value_name("SOL")
+                    .takes_value(true)
+                    .validator(is_amount)
+                    .help("Send a notification when the funding account balance falls below this amount")
+            )
+            .arg(
+                Arg::with_name("deposit_excess_rewards")
+                    .long("deposit-excess-rewards")
+                    .takes_value(false)
+                    .help("Deposit the excess rewards split off when adding validators into the \
+                           stake pool reserve, instead of the authorized staker")
+            )
+            .arg(
+                Arg::with_name("keep_external_deposits")
+                    .long("keep-external-deposits")
+                    .takes_value(false)
+                    .help("Leave the stake deposited directly to a validator by someone else with \
+                           that validator, instead of rebalancing it to the other validators")
+            )
+            .arg(
+                Arg::with_name("treasury")
+                    .long("treasury")
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .validator(is_pubkey_or_keypair)
+                    .requires("staker_float")
+                    .help("Transfer the authorized staker balance above its float to this \
+                           address each epoch")
+            )
+            .arg(
+                Arg::with_name("staker_float")
+                    .long("staker-float")
+                    .value_name("SOL")
+                    .takes_value(true)
+                    .validator(is_amount)
+                    .requires("treasury")
+                    .help("Balance the authorized staker keeps when its balance is transferred \
+                           to the treasury")
+            )
+            .arg(
+                Arg::with_name("max_asn_stake_percentage")
+                    .long("max-asn-stake-percentage")
+                    .value_name("PERCENTAGE")
+                    .takes_value(true)
+                    .validator(is_valid_percentage)
+                    .help("Cap the bonus stake of validators in an ASN once the pool's stake \
+                           there would exceed this percentage of the pool, giving the remainder \
+                           to validators in other ASNs")
+            )
+            .arg(
+                Arg::with_name("max_epoch_stake_change_percentage")
+                    .long("max-epoch-stake-change")
+                    .value_name("PERCENTAGE")
+                    .takes_value(true)
+                    .validator(is_valid_percentage)
+                    .help("Plan the stake movements over several epochs, activating and \
+                           deactivating at most this percentage of the pool's stake per epoch, \
+                           instead of moving every validator to its desired stake at once")
+            )
+            .arg(
+                Arg::with_name("bonus_ramp_epochs")
+                    .long("bonus-ramp-epochs")
+                    .value_name("EPOCHS")
+                    .takes_value(true)
+                    .validator(is_parsable::<u64>)
+                    .help("When the bonus stake grows suddenly, such as after a large deposit, \
+                           delegate the increase over this many epochs, keeping the rest in the \
+                           reserve, rather than warming it all up at once")
+            )
+            .arg(
+                Arg::with_name("bonus_ramp_threshold_percentage")
+                    .long("bonus-ramp-threshold")
+                    .value_name("PERCENTAGE")
+                    .takes_value(true)
+                    .default_value("10")
+                    .requires("bonus_ramp_epochs")
+                    .validator(is_valid_percentage)
+                    .help("Growth of the bonus stake, as a percentage of the bonus stake of the \
+                           previous epoch, delegated at once without a ramp")
+            )
+            .arg(
+                Arg::with_name("baseline_shortfall")
+                    .long("baseline-shortfall")
+                    .value_name("POLICY")
+                    .takes_value(true)
+                    .default_value("abort")
+                    .possible_values(&["abort", "reduce-baseline", "top-scored", "fund-partially"])
+                    .help("When the pool's stake does not cover the baseline stake of every \
+                           Baseline validator, abort the update, or reduce the baseline stake \
+                           amount to what the stake covers, or fund the full baseline stake of \
+                           the validators with the highest reputation scores only, or split the \
+                           stake evenly between every Baseline and Bonus validator")
+            )
+            .arg(
+                Arg::with_name("liquidity_buffer")
+                    .long("liquidity-buffer")
+                    .takes_value(false)
+                    .help("Hold back a buffer of stake in the reserve for withdrawals, as large \
+                           as the withdrawals from the pool in the previous epoch, net of the \
+                           deposits, and decaying by half each epoch without withdrawals, before \
+                           distributing the bonus stake")
+            )
+            .arg(
+                Arg::with_name("target_liquidity")
+                    .long("target-liquidity")
+                    .value_name("SOL")
+                    .takes_value(true)
+                    .validator(is_amount)
+                    .help("Hold back at least this amount in the reserve for withdrawals before \
+                           distributing the bonus stake, whatever the withdrawals")
+            )
+            .arg(
+                Arg::with_name("max_liquidity_buffer_percentage")
+                    .long("max-liquidity-buffer")
+                    .value_name("PERCENTAGE")
+                    .takes_value(true)
+                    .default_value("10")
+                    .validator(is_valid_percentage)
+                    .help("Hold back at most this percentage of the pool's stake as the \
+                           liquidity buffer")
+            )
+            .arg(
+                Arg::with_name("epoch_boundary_margin_slots")
+                    .long("epoch-boundary-margin")
+                    .value_name("SLOTS")
+                    .takes_value(true)
+                    .default_value("150")
+                    .validator(is_parsable::<u64>)
+                    .help("Abort the stake pool update when fewer than this many slots remain \
+                           in the epoch, or once the epoch ends, rather than send transactions \
+                           that could land in the next epoch")
+            )
+            .arg(
+                Arg::with_name("disable_phase")
+                    .long("disable-phase")
+                    .value_name("PHASE")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .possible_values(stake_pool::Phase::ALL)
+                    .help("Skip this phase of the stake pool update. May be specified multiple times")
+            )
+            .arg(
+                Arg::with_name("additions_first")
+                    .long("additions-first")
+                    .takes_value(false)
+                    .help("Add new validators to the pool before removing the validators \
+                           no longer desired")
+            )
+            .arg(
+                Arg::with_name("decreases_first")
+                    .long("decreases-first")
+                    .takes_value(false)
+                    .help("Send the stake decreases before the stake increases")
+            )
+            .arg(
+                Arg::with_name("funding_priority")
+                    .long("funding-priority")
+                    .value_name("PRIORITY")
+                    .takes_value(true)
+                    .default_value("smallest-account")
+                    .possible_values(&["smallest-account", "largest-deficit", "pro-rata"])
+                    .help("When the reserve cannot fund every stake increase, fund the \
+                           validators with the smallest stake accounts first, or those \
+                           furthest below their desired stake first, or scale every \
+                           increase in proportion to its amount")
+            )
+            .arg(
+                Arg::with_name("soft_removal_epochs")
+                    .long("soft-removal-epochs")
+                    .value_name("EPOCHS")
+                    .takes_value(true)
+                    .validator(is_parsable::<u64>)
+                    .help("Hold validators that are no longer desired or desired at the minimum \
+                           stake in the pool at the minimum stake for this many epochs, then \
+                           remove them, so that validators recovering quickly are not removed \
+                           and added back")
+            )
+            .arg(
+                Arg::with_name("max_unconfirmed_movement")
+                    .long("max-unconfirmed-movement")
+                    .value_name("SOL")
+                    .takes_value(true)
+                    .validator(is_amount)
+                    .help("Require a confirmation before issuing stake movements totalling more \
+                           than this amount")
+            )
+            .arg(
+                Arg::with_name("max_unconfirmed_removals")
+                    .long("max-unconfirmed-removals")
+                    .value_name("COUNT")
+                    .takes_value(true)
+                    .validator(is_parsable::<usize>)
+                    .help("Require a confirmation before removing more than this many validators \
+                           from the pool")
+            )
+            .arg(
+                Arg::with_name("approver")
+                    .long("approver")
+                    .value_name("PUBKEY")
+                    .takes_value(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("Confirm with an approval token signed by this key, for unattended \
+                           runs, instead of a confirmation typed at the terminal")
+            )
+            .arg(
+                Arg::with_name("approval_token")
+                    .long("approval-token")
+                    .value_name("SIGNATURE")
+                    .takes_value(true)
+                    .requires("approver")
+                    .validator(is_parsable::<Signature>)
+                    .help("Approval token for the current epoch, produced by the sign-approval \
+                           command")
+            )
+            .subcommand(
+                SubCommand::with_name("diff")
+                .about("Compare the current stake of each validator in the pool with the stake \
+                        the current policy would give it, from the classifier inputs saved for \
+                        the current epoch. Nothing is sent to the cluster")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("rotate-staker")
+            .about("Use the manager to set a new staker on a stake pool. \
+                    Without --confirm the affected accounts are displayed but the staker is not changed")
+            .arg(
+                Arg::with_name("pool_address")
+                    .index(1)
+                    .value_name("POOL_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The stake pool address")
+            )
+            .arg(
+                Arg::with_name("manager")
+                    .index(2)
+                    .value_name("MANAGER_KEYPAIR")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_keypair)
+                    .help("Keypair of the stake pool manager")
+            )
+            .arg(
+                Arg::with_name("new_staker")
+                    .index(3)
+                    .value_name("NEW_STAKER_KEYPAIR")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_keypair)
+                    .help("Keypair of the new staker, which must be funded to pay for the \
+                           post-check transaction")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("rollback")
+            .about("Issue the inverse of the stake movements recorded in the journal of the last \
+                    run, as far as their warmup and cooldown allow. \
+                    Without --confirm the inverse movements are displayed but not issued")
+            .arg(
+                Arg::with_name("pool_address")
+                    .index(1)
+                    .value_name("POOL_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The stake pool address")
+            )
+            .arg(
+                Arg::with_name("staker")
+                    .index(2)
+                    .value_name("STAKER_KEYPAIR")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_keypair)
+                    .help("Keypair of the stake pool staker")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("migrate-pool")
+            .about("Migrate the stake of a pool token holder to another stake pool, mirroring the \
+                    allocation of the source pool. Run once per epoch until the migration \
+                    completes. Without --confirm the tranche of the epoch is only displayed")
+            .arg(
+                Arg::with_name("source")
+                    .index(1)
+                    .value_name("SOURCE_POOL_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The stake pool to migrate from")
+            )
+            .arg(
+                Arg::with_name("destination")
+                    .index(2)
+                    .value_name("DESTINATION_POOL_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The stake pool to migrate to")
+            )
+            .arg(
+                Arg::with_name("holder")
+                    .index(3)
+                    .value_name("HOLDER_KEYPAIR")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_keypair)
+                    .help("Keypair owning the pool tokens of the source pool, and a token \
+                           account of the destination pool")
+            )
+            .arg(
+                Arg::with_name("max_epoch_percentage")
+                    .long("max-epoch-percentage")
+                    .value_name("PERCENTAGE")
+                    .takes_value(true)
+                    .default_value("10")
+                    .validator(is_valid_percentage)
+                    .help("Migrate at most this percentage of the source pool's stake per epoch")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("export-snapshot")
+            .about("Export the stake pool, its validator list and staker accounts to a snapshot file")
+            .arg(
+                Arg::with_name("pool_address")
+                    .index(1)
+                    .value_name("POOL_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The stake pool address")
+            )
+            .arg(
+                Arg::with_name("output_path")
+                    .index(2)
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Snapshot file to write")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("export-token-holders")
+            .about("Export the holders of the stake pool tokens and the SOL underlying their \
+                    pool tokens, for governance weights and incentive distributions")
+            .arg(
+                Arg::with_name("pool_address")
+                    .index(1)
+                    .value_name("POOL_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("The stake pool address")
+            )
+            .arg(
+                Arg::with_name("output_path")
+                    .index(2)
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Token holder file to write, as CSV if the file name ends in .csv and \
+                           JSON otherwise")
+            )
+            .arg(
+                Arg::with_name("slot")
+                    .long("slot")
+                    .value_name("SLOT")
+                    .takes_value(true)
+                    .validator(is_parsable::<Slot>)
+                    .help("Wait for the cluster to reach this slot, then take the snapshot")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("export-warehouse")
+            .about("Insert the validator decisions of the epochs saved in the cluster data \
+                    directory into the --warehouse, to backfill it")
+            .arg(
+                Arg::with_name("first_epoch")
+                    .long("first-epoch")
+                    .value_name("EPOCH")
+                    .takes_value(true)
+                    .validator(is_parsable::<Epoch>)
+                    .help("Skip the epochs before this one [default: all saved epochs]")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("diff-snapshots")
+            .about("Display the differences between two stake pool snapshot files")
+            .arg(
+                Arg::with_name("before_path")
+                    .index(1)
+                    .value_name("BEFORE_PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The earlier snapshot file")
+            )
+            .arg(
+                Arg::with_name("after_path")
+                    .index(2)
+                    .value_name("AFTER_PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The later snapshot file")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("serve-epoch-summary")
+            .about("Serve recent epoch summaries as a Grafana JSON datasource")
+            .arg(
+                Arg::with_name("listen_address")
+                    .long("listen")
+                    .value_name("HOST:PORT")
                     .takes_value(true)
-                    .default_value("5000")
-                    .validator(is_amount)
+                    .default_value("127.0.0.1:3001")
+                    .validator(is_parsable::<SocketAddr>)
+                    .help("Address to serve the JSON datasource endpoints on")
+            )
+            .arg(
+                Arg::with_name("epochs")
+                    .long("epochs")
+                    .value_name("COUNT")
+                    .takes_value(true)
+                    .default_value("30")
+                    .validator(is_parsable::<usize>)
+                    .help("Number of most recent epochs to serve")
             )
         )
         .subcommand(
-            SubCommand::with_name("stake-pool").about("Use a stake pool")
+            SubCommand::with_name("stake-history")
+            .about("Display the stake moved to and from a validator in each epoch")
+            .arg(
+                Arg::with_name("validator")
+                    .index(1)
+                    .value_name("IDENTITY_OR_VOTE_ADDRESS")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_pubkey)
+                    .help("The identity or vote address of the validator")
+            )
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .takes_value(false)
+                    .help("Display the history as JSON, as served by the daemon API")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("annotate")
+            .about("Attach an annotation to an epoch, rendered in the reports and dashboards, \
+                    or display the annotations of the epoch")
+            .arg(
+                Arg::with_name("epoch")
+                    .index(1)
+                    .value_name("EPOCH")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_parsable::<Epoch>)
+                    .help("The epoch to annotate")
+            )
+            .arg(
+                Arg::with_name("text")
+                    .index(2)
+                    .value_name("TEXT")
+                    .takes_value(true)
+                    .help("The annotation, such as \"cluster outage, criteria relaxed\" \
+                           [default: display the annotations of the epoch]")
+            )
+            .arg(
+                Arg::with_name("author")
+                    .long("author")
+                    .value_name("NAME")
+                    .takes_value(true)
+                    .requires("text")
+                    .help("Who attaches the annotation")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("validate-application")
+            .about("Check that the on-chain facts match the application of a validator to the \
+                    desired list")
+            .arg(
+                Arg::with_name("application_path")
+                    .index(1)
+                    .value_name("APPLICATION_FILE")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The application, a YAML file declaring the identity, vote address, \
+                           contact, data center and self stake of the validator")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("verify-report")
+            .about("Verify the attestation of a signed cluster report")
+            .arg(
+                Arg::with_name("report_path")
+                    .index(1)
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The signed report")
+            )
+            .arg(
+                Arg::with_name("signer")
+                    .long("signer")
+                    .value_name("PUBKEY")
+                    .takes_value(true)
+                    .validator(is_pubkey_or_keypair)
+                    .help("Fail unless the report was signed by this key")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("preview")
+            .about("Classify the validators against the live data of the epoch in progress and \
+                    publish the result as a provisional classification of the next epoch")
+        )
+        .subcommand(
+            SubCommand::with_name("reproduce")
+            .about("Classify a previous epoch anew from its archived classifier inputs and the \
+                    desired stake sources recorded in its journal, and verify that the run \
+                    produces the same stake pool inputs. The classification settings must \
+                    match those of the run")
+            .arg(
+                Arg::with_name("epoch")
+                    .index(1)
+                    .value_name("EPOCH")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_parsable::<Epoch>)
+                    .help("The epoch to reproduce")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+            .about("Re-derive the classification of a published epoch from its archived \
+                    classifier inputs and report any mismatch. The classification settings \
+                    must match those of the published run")
+            .arg(
+                Arg::with_name("epoch")
+                    .index(1)
+                    .value_name("EPOCH")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_parsable::<Epoch>)
+                    .help("The epoch to verify")
+            )
+            .arg(
+                Arg::with_name("from")
+                    .long("from")
+                    .value_name("DIR")
+                    .takes_value(true)
+                    .help("Directory holding the published epoch classifications and classifier \
+                           inputs [default: the cluster database]")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("export-foundation-list")
+            .about("Export the validator classification of an epoch in the validator list \
+                    format of the Solana Foundation stake-o-matic")
+            .arg(
+                Arg::with_name("epoch")
+                    .index(1)
+                    .value_name("EPOCH")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_parsable::<Epoch>)
+                    .help("The epoch to export")
+            )
+            .arg(
+                Arg::with_name("output_path")
+                    .index(2)
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Validator list to write, as YAML if the file name ends in .yml or \
+                           .yaml and JSON otherwise")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("sign-approval")
+            .about("Sign the approval token that confirms a stake pool update exceeding the \
+                    confirmation thresholds in an unattended run")
             .arg(
                 Arg::with_name("pool_address")
                     .index(1)
@@ -563,26 +2296,74 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
                     .help("The stake pool address")
             )
             .arg(
-                Arg::with_name("authorized_staker")
+                Arg::with_name("epoch")
                     .index(2)
-                    .value_name("KEYPAIR")
+                    .value_name("EPOCH")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(is_parsable::<Epoch>)
+                    .help("The epoch of the update to approve")
+            )
+            .arg(
+                Arg::with_name("approver")
+                    .index(3)
+                    .value_name("APPROVER_KEYPAIR")
+                    .takes_value(true)
+                    .required(true)
                     .validator(is_keypair)
+                    .help("Keypair of the approver")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("refresh-ip-to-asn-db")
+            .about("Download the IP to ASN database used to resolve data centers offline")
+            .arg(
+                Arg::with_name("path")
+                    .index(1)
+                    .value_name("PATH")
+                    .takes_value(true)
                     .required(true)
+                    .help("Database file to replace")
+            )
+            .arg(
+                Arg::with_name("url")
+                    .long("url")
+                    .value_name("URL")
                     .takes_value(true)
-                    .help("Keypair of the authorized staker")
+                    .default_value(ip_to_asn::DEFAULT_DATABASE_URL)
+                    .help("Database to download, gzip compressed if the URL ends in .gz")
             )
+        )
+        .subcommand(
+            SubCommand::with_name("send-validator-notices")
+            .about("Warn the validators whose current commission or release would cost them \
+                    their stake at the next classification, before the stake moves")
             .arg(
-                Arg::with_name("baseline_stake_amount")
-                    .long("baseline-stake-amount")
-                    .value_name("SOL")
+                Arg::with_name("contact_registry")
+                    .index(1)
+                    .value_name("PATH")
                     .takes_value(true)
-                    .default_value("5000")
-                    .validator(is_amount)
+                    .required(true)
+                    .help("YAML file of the email address and Discord ID of each validator")
+            )
+            .arg(
+                Arg::with_name("discord_webhook")
+                    .long("discord-webhook")
+                    .value_name("URL")
+                    .takes_value(true)
+                    .validator(is_url)
+                    .help("Post the notices to this Discord webhook, mentioning the Discord ID \
+                           of each validator. Notices are emailed through --smtp-relay if set")
             )
         )
         .get_matches();
 
     let dry_run = !matches.is_present("confirm");
+    let incremental = matches.is_present("incremental");
+    let transaction_executor = value_t_or_exit!(matches, "executor", ExecutorKind);
+    let transaction_output_path = value_t!(matches, "executor_output", PathBuf).ok();
+    let stake_pool_program_id =
+        pubkey_of(&matches, "stake_pool_program_id").unwrap_or_else(spl_stake_pool::id);
     let cluster = match value_t_or_exit!(matches, "cluster", String).as_str() {
         "mainnet-beta" => Cluster::MainnetBeta,
         "testnet" => Cluster::Testnet,
@@ -593,6 +2374,7 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
     let min_epoch_credit_percentage_of_average =
         value_t_or_exit!(matches, "min_epoch_credit_percentage_of_average", usize);
     let max_commission = value_t_or_exit!(matches, "max_commission", u8);
+    let commission_grace = matches.is_present("commission_grace");
     let max_poor_voter_percentage = value_t_or_exit!(matches, "max_poor_voter_percentage", usize);
     let max_poor_block_producer_percentage =
         value_t_or_exit!(matches, "max_poor_block_producer_percentage", usize);
@@ -612,6 +2394,73 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
         process::exit(1);
     }
 
+    let max_vote_lag = value_t!(matches, "max_vote_lag", u64).ok();
+    let vote_lag_samples = value_t_or_exit!(matches, "vote_lag_samples", usize);
+    let endpoint_probe_timeout = value_t!(matches, "probe_endpoints", u64)
+        .ok()
+        .map(Duration::from_millis);
+    let endpoint_probe_parallelism = value_t_or_exit!(matches, "probe_parallelism", usize);
+    let enrichment_parallelism = value_t_or_exit!(matches, "enrichment_parallelism", usize);
+    let input_slot_window = value_t!(matches, "input_slot_window", Slot).ok();
+    let outage_lookback_minutes = value_t_or_exit!(matches, "outage_lookback_minutes", usize);
+
+    let reputation_decay_percentage =
+        value_t_or_exit!(matches, "reputation_decay_percentage", usize);
+    let min_bonus_reputation = value_t!(matches, "min_bonus_reputation", f64).ok();
+    let min_bonus_activated_stake_lamports = lamports_of_sol(&matches, "min_bonus_activated_stake");
+    let identity_change_bonus_hold_epochs =
+        value_t!(matches, "identity_change_bonus_hold_epochs", u64).ok();
+    let hosting_policy = match matches.value_of("hosting_policy") {
+        Some(path) => Some(HostingPolicy::load(path)?),
+        None => None,
+    };
+    let withdrawer_change = match value_t!(matches, "withdrawer_change", WithdrawerChangeAction) {
+        Ok(action) => Some(WithdrawerChangePolicy::new(
+            action,
+            matches.value_of("withdrawer_allowlist").map(Path::new),
+        )?),
+        Err(_) => None,
+    };
+    let bonus_streak = values_t!(matches, "bonus_streak", u64)
+        .ok()
+        .map(|v| (v[0] as usize, v[1]));
+    let seed = value_t!(matches, "seed", u64).ok();
+    let desired_stake_sources = values_t!(matches, "desired_stake_source", DesiredStakeSource)
+        .unwrap_or_else(|_| vec![DesiredStakeSource::Classifier]);
+    if matches.is_present("daemon") && desired_stake_sources.contains(&DesiredStakeSource::Stdin) {
+        return Err(
+            "The standard input is read once, the desired stake source - cannot be used \
+                    with --daemon"
+                .into(),
+        );
+    }
+    let screening_sources =
+        values_t!(matches, "screening_hook", ScreeningSource).unwrap_or_default();
+    let max_off_target_epochs = value_t_or_exit!(matches, "max_off_target_epochs", usize);
+    let idle_reserve = values_t!(matches, "idle_reserve_alert", String)
+        .ok()
+        .map(|v| {
+            Ok::<_, String>(IdleReservePolicy {
+                min_percentage: v[0]
+                    .parse()
+                    .map_err(|err| format!("Invalid idle reserve percentage {}: {}", v[0], err))?,
+                epochs: v[1]
+                    .parse()
+                    .map_err(|err| format!("Invalid idle reserve epochs {}: {}", v[1], err))?,
+                deploy: matches.is_present("deploy_idle_reserve"),
+            })
+        })
+        .transpose()?;
+    let max_desired_validators = value_t!(matches, "max_desired_validators", usize).ok();
+    let ip_to_asn_path = value_t!(matches, "ip_to_asn_db", PathBuf).ok();
+    let canary = match matches.value_of("canary_policy") {
+        Some(path) => Some(Canary {
+            policy: CanaryPolicy::load(path)?,
+            percentage: value_t_or_exit!(matches, "canary_percentage", f64),
+        }),
+        None => None,
+    };
+
     let json_rpc_url = match cluster {
         Cluster::MainnetBeta => value_t!(matches, "json_rpc_url", String)
             .unwrap_or_else(|_| "http://api.mainnet-beta.solana.com".into()),
@@ -625,6 +2474,46 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
         None
     };
 
+    let artifact_url = value_t!(matches, "artifact_url", String).ok();
+    let artifact_retention_epochs = value_t!(matches, "artifact_retention_epochs", u64).ok();
+    let atom_feed_url = value_t!(matches, "atom_feed_url", String).ok();
+    let analytics_webhook_url = value_t!(matches, "analytics_webhook_url", String).ok();
+    let smtp_relay = value_t!(matches, "smtp_relay", String).ok();
+    let email_sender = value_t!(matches, "email_from", String).ok();
+    let email_recipients = values_t!(matches, "email_to", String).unwrap_or_default();
+    let report_keypair = keypair_of(&matches, "report_keypair").map(Arc::new);
+    let report_template_dir = value_t!(matches, "report_template_dir", PathBuf).ok();
+    let warehouse_url = value_t!(matches, "warehouse_url", String).ok();
+    let warehouse_table = value_t_or_exit!(matches, "warehouse_table", String);
+    let watchdog_url = value_t!(matches, "watchdog_url", String).ok();
+    let daemon_interval = if matches.is_present("daemon") {
+        Some(Duration::from_secs(
+            value_t_or_exit!(matches, "daemon_interval", u64) * 60,
+        ))
+    } else {
+        None
+    };
+    let vote_account_cache = daemon_interval.map(|_| {
+        Arc::new(VoteAccountCache::new(Duration::from_secs(
+            value_t_or_exit!(matches, "vote_accounts_max_age", u64) * 60,
+        )))
+    });
+    let api_listen_address = value_t!(matches, "api_listen_address", SocketAddr).ok();
+    let api_token = match matches.value_of("api_token_file") {
+        Some(api_token_file) => {
+            let api_token = fs::read_to_string(api_token_file)
+                .map_err(|err| format!("Unable to read {}: {}", api_token_file, err))?
+                .trim()
+                .to_string();
+            if api_token.is_empty() {
+                return Err(format!("API token file {} is empty", api_token_file).into());
+            }
+            Some(api_token)
+        }
+        None => None,
+    };
+    let api_rate_limit = value_t_or_exit!(matches, "api_rate_limit", u32);
+
     let confirmed_block_cache_path = matches
         .value_of("confirmed_block_cache_path")
         .map(PathBuf::from)
@@ -632,6 +2521,7 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
 
     let bad_cluster_average_skip_rate =
         value_t!(matches, "bad_cluster_average_skip_rate", usize).unwrap_or(50);
+    let normalize_skip_rate = matches.is_present("normalize_skip_rate");
     let max_infrastructure_concentration =
         value_t!(matches, "max_infrastructure_concentration", f64).unwrap();
     let infrastructure_concentration_affects = value_t!(
@@ -646,10 +2536,32 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
         cluster,
         db_path,
         markdown_path,
+        artifact_url,
+        artifact_retention_epochs,
+        atom_feed_url,
+        analytics_webhook_url,
+        warehouse_url,
+        warehouse_table,
+        smtp_relay,
+        email_sender,
+        email_recipients,
+        watchdog_url,
+        daemon_interval,
+        vote_account_cache,
+        api_listen_address,
+        api_token,
+        api_rate_limit,
+        report_keypair,
+        report_template_dir,
         dry_run,
+        incremental,
+        transaction_executor,
+        transaction_output_path,
+        stake_pool_program_id,
         quality_block_producer_percentage,
         max_poor_block_producer_percentage,
         max_commission,
+        commission_grace,
         min_release_version,
         max_old_release_version_percentage,
         max_poor_voter_percentage,
@@ -657,11 +2569,34 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
         max_infrastructure_concentration,
         infrastructure_concentration_affects,
         bad_cluster_average_skip_rate,
+        normalize_skip_rate,
         min_epoch_credit_percentage_of_average,
         min_self_stake_lamports,
         enforce_min_self_stake,
         enforce_testnet_participation,
         min_testnet_participation,
+        max_vote_lag,
+        vote_lag_samples,
+        endpoint_probe_timeout,
+        endpoint_probe_parallelism,
+        enrichment_parallelism,
+        input_slot_window,
+        outage_lookback_minutes,
+        reputation_decay_percentage,
+        min_bonus_reputation,
+        min_bonus_activated_stake_lamports,
+        identity_change_bonus_hold_epochs,
+        hosting_policy,
+        withdrawer_change,
+        bonus_streak,
+        seed,
+        desired_stake_sources,
+        screening_sources,
+        max_off_target_epochs,
+        idle_reserve,
+        max_desired_validators,
+        ip_to_asn_path,
+        canary,
     };
 
     info!("RPC URL: {}", config.json_rpc_url);
@@ -669,11 +2604,41 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
         RpcClient::new_with_timeout(config.json_rpc_url.clone(), Duration::from_secs(180));
 
     // Sanity check that the RPC endpoint is healthy before performing too much work
-    rpc_client
-        .get_health()
-        .map_err(|err| format!("RPC endpoint is unhealthy: {:?}", err))?;
+    if !matches!(
+        matches.subcommand_name(),
+        Some("diff-snapshots")
+            | Some("export-warehouse")
+            | Some("serve-epoch-summary")
+            | Some("stake-history")
+            | Some("annotate")
+            | Some("verify-report")
+            | Some("reproduce")
+            | Some("verify")
+            | Some("refresh-ip-to-asn-db")
+    ) {
+        rpc_client.get_health().map_err(|err| {
+            error!("RPC endpoint is unhealthy");
+            err
+        })?;
+    }
+
+    let min_stake_change = MinStakeChange {
+        lamports: sol_to_lamports(value_t_or_exit!(matches, "min_stake_change_amount", f64)),
+        target_percentage: value_t!(matches, "min_stake_change_percentage", f64).ok(),
+    };
+
+    let stake_tolerance = values_t!(matches, "stake_tolerance", f64)
+        .ok()
+        .map(|v| StakeTolerance {
+            below_percentage: v[0],
+            above_percentage: *v.last().unwrap(),
+        })
+        .unwrap_or_default();
+
+    let small_pool = matches.is_present("small_pool");
+    let min_stake_change_amount_given = matches.occurrences_of("min_stake_change_amount") > 0;
 
-    let stake_pool: Box<dyn GenericStakePool> = match matches.subcommand() {
+    let command = match matches.subcommand() {
         ("stake-pool-v0", Some(matches)) => {
             let authorized_staker = keypair_of(&matches, "authorized_staker").unwrap();
             let reserve_stake_address = pubkey_of(&matches, "reserve_stake_address").unwrap();
@@ -681,30 +2646,235 @@ fn get_config() -> BoxResult<(Config, RpcClient, Box<dyn GenericStakePool>)> {
                 sol_to_lamports(value_t_or_exit!(matches, "min_reserve_stake_balance", f64));
             let baseline_stake_amount =
                 sol_to_lamports(value_t_or_exit!(matches, "baseline_stake_amount", f64));
-            Box::new(stake_pool_v0::new(
+            let small_pool = if small_pool {
+                Some(SmallPool {
+                    min_stake_lamports: small_pool::minimum_delegation(&config.json_rpc_url)?,
+                })
+            } else {
+                None
+            };
+            let min_stake_change = small_pool.map_or(min_stake_change, |small_pool| {
+                small_pool.min_stake_change(min_stake_change, min_stake_change_amount_given)
+            });
+            // Unless given explicitly, the amount is raised to the smallest movement the pool
+            // can make
+            let min_stake_change = if min_stake_change_amount_given {
+                min_stake_change
+            } else {
+                MinStakeChange {
+                    lamports: min_stake_change.lamports.max(
+                        stake_pool_v0::min_stake_change_lamports(&rpc_client, small_pool)?,
+                    ),
+                    ..min_stake_change
+                }
+            };
+            Command::Apply(Box::new(stake_pool_v0::new(
                 &rpc_client,
                 authorized_staker,
                 baseline_stake_amount,
                 reserve_stake_address,
                 min_reserve_stake_balance,
-            )?)
+                min_stake_change,
+                stake_tolerance,
+                small_pool,
+                config.transaction_executor()?,
+            )?))
         }
         ("stake-pool", Some(matches)) => {
-            let authorized_staker = keypair_of(&matches, "authorized_staker").unwrap();
+            let roles = StakePoolRoles {
+                staker: keypair_of(&matches, "authorized_staker").unwrap(),
+                manager: keypair_of(&matches, "manager"),
+                funder: keypair_of(&matches, "funder"),
+            };
             let pool_address = pubkey_of(&matches, "pool_address").unwrap();
             let baseline_stake_amount =
                 sol_to_lamports(value_t_or_exit!(matches, "baseline_stake_amount", f64));
-            Box::new(stake_pool::new(
+            let max_unconfirmed_movement = value_t!(matches, "max_unconfirmed_movement", f64)
+                .ok()
+                .map(sol_to_lamports);
+            let max_unconfirmed_removals =
+                value_t!(matches, "max_unconfirmed_removals", usize).ok();
+            let confirmation_gate =
+                if max_unconfirmed_movement.is_some() || max_unconfirmed_removals.is_some() {
+                    let approval = match pubkey_of(&matches, "approver") {
+                        Some(approver) => Approval::Token {
+                            approver,
+                            token: value_t!(matches, "approval_token", Signature).ok(),
+                        },
+                        None => Approval::Interactive,
+                    };
+                    Some(ConfirmationGate::new(
+                        max_unconfirmed_movement,
+                        max_unconfirmed_removals,
+                        approval,
+                    ))
+                } else {
+                    None
+                };
+            // The stake pool program requires its stake accounts to hold at least 1 SOL
+            let small_pool = if small_pool {
+                Some(SmallPool {
+                    min_stake_lamports: stake_pool::MIN_STAKE_ACCOUNT_BALANCE,
+                })
+            } else {
+                None
+            };
+            let stake_pool = Box::new(stake_pool::new(
                 &rpc_client,
-                authorized_staker,
+                roles,
+                config.stake_pool_program_id,
                 pool_address,
                 baseline_stake_amount,
-            )?)
+                small_pool.map_or(min_stake_change, |small_pool| {
+                    small_pool.min_stake_change(min_stake_change, min_stake_change_amount_given)
+                }),
+                stake_tolerance,
+                value_t!(matches, "funder_low_balance", f64)
+                    .ok()
+                    .map(sol_to_lamports),
+                matches.is_present("deposit_excess_rewards"),
+                matches.is_present("keep_external_deposits"),
+                values_t!(matches, "disable_phase", stake_pool::Phase)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                value_t!(matches, "max_asn_stake_percentage", f64).ok(),
+                value_t!(matches, "max_epoch_stake_change_percentage", f64).ok(),
+                value_t!(matches, "bonus_ramp_epochs", u64)
+                    .ok()
+                    .map(|epochs| BonusRamp {
+                        epochs,
+                        threshold_percentage: value_t_or_exit!(
+                            matches,
+                            "bonus_ramp_threshold_percentage",
+                            f64
+                        ),
+                    }),
+                value_t_or_exit!(matches, "baseline_shortfall", planner::BaselineShortfall),
+                if matches.is_present("liquidity_buffer") || matches.is_present("target_liquidity")
+                {
+                    Some(LiquidityBuffer {
+                        target_lamports: value_t!(matches, "target_liquidity", f64)
+                            .map(sol_to_lamports)
+                            .unwrap_or_default(),
+                        max_percentage: value_t_or_exit!(
+                            matches,
+                            "max_liquidity_buffer_percentage",
+                            f64
+                        ),
+                    })
+                } else {
+                    None
+                },
+                Some(value_t_or_exit!(
+                    matches,
+                    "epoch_boundary_margin_slots",
+                    u64
+                )),
+                pubkey_of(&matches, "treasury").map(|treasury| stake_pool::TreasurySweep {
+                    treasury,
+                    float_lamports: sol_to_lamports(value_t_or_exit!(matches, "staker_float", f64)),
+                }),
+                confirmation_gate,
+                value_t!(matches, "soft_removal_epochs", u64).ok(),
+                stake_pool::OperationOrder {
+                    removals_first: !matches.is_present("additions_first"),
+                    decreases_first: matches.is_present("decreases_first"),
+                    funding_priority: value_t_or_exit!(
+                        matches,
+                        "funding_priority",
+                        stake_pool::FundingPriority
+                    ),
+                },
+                small_pool,
+                config.transaction_executor()?,
+            )?);
+            if matches.subcommand_matches("diff").is_some() {
+                Command::Diff(stake_pool)
+            } else {
+                Command::Apply(stake_pool)
+            }
         }
+        ("rotate-staker", Some(matches)) => Command::RotateStaker {
+            stake_pool_address: pubkey_of(&matches, "pool_address").unwrap(),
+            manager: keypair_of(&matches, "manager").unwrap(),
+            new_staker: keypair_of(&matches, "new_staker").unwrap(),
+        },
+        ("rollback", Some(matches)) => Command::Rollback {
+            stake_pool_address: pubkey_of(&matches, "pool_address").unwrap(),
+            staker: keypair_of(&matches, "staker").unwrap(),
+        },
+        ("migrate-pool", Some(matches)) => Command::MigratePool {
+            source: pubkey_of(&matches, "source").unwrap(),
+            destination: pubkey_of(&matches, "destination").unwrap(),
+            holder: keypair_of(&matches, "holder").unwrap(),
+            max_epoch_percentage: value_t_or_exit!(matches, "max_epoch_percentage", f64),
+        },
+        ("export-snapshot", Some(matches)) => Command::ExportSnapshot {
+            stake_pool_address: pubkey_of(&matches, "pool_address").unwrap(),
+            output_path: value_t_or_exit!(matches, "output_path", PathBuf),
+        },
+        ("export-token-holders", Some(matches)) => Command::ExportTokenHolders {
+            stake_pool_address: pubkey_of(&matches, "pool_address").unwrap(),
+            output_path: value_t_or_exit!(matches, "output_path", PathBuf),
+            slot: value_t!(matches, "slot", Slot).ok(),
+        },
+        ("export-warehouse", Some(matches)) => Command::ExportWarehouse {
+            first_epoch: value_t!(matches, "first_epoch", Epoch).ok(),
+        },
+        ("diff-snapshots", Some(matches)) => Command::DiffSnapshots {
+            before_path: value_t_or_exit!(matches, "before_path", PathBuf),
+            after_path: value_t_or_exit!(matches, "after_path", PathBuf),
+        },
+        ("serve-epoch-summary", Some(matches)) => Command::ServeEpochSummary {
+            listen_address: value_t_or_exit!(matches, "listen_address", SocketAddr),
+            epochs: value_t_or_exit!(matches, "epochs", usize),
+        },
+        ("stake-history", Some(matches)) => Command::StakeHistory {
+            validator: pubkey_of(&matches, "validator").unwrap(),
+            json: matches.is_present("json"),
+        },
+        ("annotate", Some(matches)) => Command::Annotate {
+            epoch: value_t_or_exit!(matches, "epoch", Epoch),
+            text: matches.value_of("text").map(|text| text.to_string()),
+            author: matches.value_of("author").map(|author| author.to_string()),
+        },
+        ("validate-application", Some(matches)) => Command::ValidateApplication {
+            application_path: value_t_or_exit!(matches, "application_path", PathBuf),
+        },
+        ("verify-report", Some(matches)) => Command::VerifyReport {
+            report_path: value_t_or_exit!(matches, "report_path", PathBuf),
+            signer: pubkey_of(&matches, "signer"),
+        },
+        ("preview", Some(_)) => Command::Preview,
+        ("reproduce", Some(matches)) => Command::Reproduce {
+            epoch: value_t_or_exit!(matches, "epoch", Epoch),
+        },
+        ("verify", Some(matches)) => Command::Verify {
+            epoch: value_t_or_exit!(matches, "epoch", Epoch),
+            path: value_t!(matches, "from", PathBuf).unwrap_or_else(|_| config.cluster_db_path()),
+        },
+        ("sign-approval", Some(matches)) => Command::SignApproval {
+            stake_pool_address: pubkey_of(&matches, "pool_address").unwrap(),
+            epoch: value_t_or_exit!(matches, "epoch", Epoch),
+            approver: keypair_of(&matches, "approver").unwrap(),
+        },
+        ("refresh-ip-to-asn-db", Some(matches)) => Command::RefreshIpToAsnDb {
+            path: value_t_or_exit!(matches, "path", PathBuf),
+            url: value_t_or_exit!(matches, "url", String),
+        },
+        ("send-validator-notices", Some(matches)) => Command::SendValidatorNotices {
+            contact_registry_path: value_t_or_exit!(matches, "contact_registry", PathBuf),
+            discord_webhook_url: value_t!(matches, "discord_webhook", String).ok(),
+        },
+        ("export-foundation-list", Some(matches)) => Command::ExportFoundationList {
+            epoch: value_t_or_exit!(matches, "epoch", Epoch),
+            output_path: value_t_or_exit!(matches, "output_path", PathBuf),
+        },
         _ => unreachable!(),
     };
 
-    Ok((config, rpc_client, stake_pool))
+    Ok((config, rpc_client, command))
 }
 
 type ClassifyResult = (
@@ -720,28 +2890,25 @@ type ClassifyResult = (
     bool,
 );
 
-fn classify_producers(
+/// Count the blocks produced and the leader slots of each validator, by identity
+// Leader slots after `last_slot` are not counted, they are yet to come in an epoch in progress
+fn count_blocks_and_slots(
     first_slot_in_epoch: Slot,
-    confirmed_blocks: HashSet<u64>,
+    last_slot: Slot,
+    confirmed_blocks: &HashSet<u64>,
     leader_schedule: HashMap<String, Vec<usize>>,
-    config: &Config,
-) -> BoxResult<ClassifyResult> {
-    let mut poor_block_producers = HashSet::new();
-    let mut quality_block_producers = HashSet::new();
+) -> BoxResult<HashMap<Pubkey, (usize, usize)>> {
     let mut blocks_and_slots = HashMap::new();
-    let mut reason_msg = HashMap::new();
-
-    let mut total_blocks = 0;
-    let mut total_slots = 0;
     for (validator_identity, relative_slots) in leader_schedule {
         let mut validator_blocks = 0;
         let mut validator_slots = 0;
         for relative_slot in relative_slots {
             let slot = first_slot_in_epoch + relative_slot as Slot;
-            total_slots += 1;
+            if slot > last_slot {
+                continue;
+            }
             validator_slots += 1;
             if confirmed_blocks.contains(&slot) {
-                total_blocks += 1;
                 validator_blocks += 1;
             }
         }
@@ -752,20 +2919,64 @@ fn classify_producers(
             e.1 += validator_slots;
         }
     }
+    Ok(blocks_and_slots)
+}
+
+fn classify_blocks_and_slots(
+    blocks_and_slots: &HashMap<Pubkey, (usize, usize)>,
+    config: &Config,
+) -> BoxResult<ClassifyResult> {
+    let mut poor_block_producers = HashSet::new();
+    let mut quality_block_producers = HashSet::new();
+    let mut reason_msg = HashMap::new();
+
+    let total_blocks: usize = blocks_and_slots.values().map(|(blocks, _)| blocks).sum();
+    let total_slots: usize = blocks_and_slots.values().map(|(_, slots)| slots).sum();
     let cluster_average_skip_rate = 100 - total_blocks * 100 / total_slots;
-    for (validator_identity, (blocks, slots)) in blocks_and_slots {
-        let skip_rate: usize = 100 - (blocks * 100 / slots);
 
-        let msg = format!(
-            "{} blocks in {} slots, {:.2}% skip rate",
-            blocks, slots, skip_rate
+    // When the whole cluster had a bad epoch, slot skips are not the fault of the individual
+    // validators so nobody is classified as a poor block producer
+    let cluster_degraded = config.normalize_skip_rate
+        && cluster_average_skip_rate > config.bad_cluster_average_skip_rate;
+    if cluster_degraded {
+        warn!(
+            "cluster average skip rate {}% exceeds {}%, ignoring block production",
+            cluster_average_skip_rate, config.bad_cluster_average_skip_rate
         );
+    }
+
+    for (&validator_identity, &(blocks, slots)) in blocks_and_slots {
+        let skip_rate: usize = 100 - (blocks * 100 / slots);
+
+        let (msg, poor_block_producer) = if config.normalize_skip_rate {
+            // The validator's skip rate relative to the blocks the cluster as a whole was able
+            // to produce
+            let relative_skip_rate = if total_blocks == 0 {
+                0
+            } else {
+                100usize.saturating_sub(blocks * 100 * total_slots / (slots * total_blocks))
+            };
+            (
+                format!(
+                    "{} blocks in {} slots, {:.2}% skip rate ({:.2}% relative to the cluster)",
+                    blocks, slots, skip_rate, relative_skip_rate
+                ),
+                !cluster_degraded && relative_skip_rate > config.quality_block_producer_percentage,
+            )
+        } else {
+            (
+                format!(
+                    "{} blocks in {} slots, {:.2}% skip rate",
+                    blocks, slots, skip_rate
+                ),
+                skip_rate.saturating_sub(config.quality_block_producer_percentage)
+                    > cluster_average_skip_rate,
+            )
+        };
         trace!("Validator {} produced {}", validator_identity, msg);
         reason_msg.insert(validator_identity, msg);
 
-        if skip_rate.saturating_sub(config.quality_block_producer_percentage)
-            > cluster_average_skip_rate
-        {
+        if poor_block_producer {
             poor_block_producers.insert(validator_identity);
         } else {
             quality_block_producers.insert(validator_identity);
@@ -824,23 +3035,127 @@ fn classify_poor_voters(
     let poor_voter_percentage = poor_voters.len() * 100 / vote_account_info.len();
     let too_many_poor_voters = poor_voters.len() > max_poor_voters;
 
-    info!("Cluster average epoch credits: {}", avg_epoch_credits);
-    info!("Minimum required epoch credits: {}", min_epoch_credits);
-    info!("Poor voter: {}%", poor_voter_percentage);
-    debug!(
-        "poor_voters: {}, max poor_voters: {}",
-        poor_voters.len(),
-        max_poor_voters
-    );
-    trace!("poor_voters: {:?}", poor_voters);
+    info!("Cluster average epoch credits: {}", avg_epoch_credits);
+    info!("Minimum required epoch credits: {}", min_epoch_credits);
+    info!("Poor voter: {}%", poor_voter_percentage);
+    debug!(
+        "poor_voters: {}, max poor_voters: {}",
+        poor_voters.len(),
+        max_poor_voters
+    );
+    trace!("poor_voters: {:?}", poor_voters);
+
+    (
+        poor_voters,
+        poor_voter_percentage,
+        min_epoch_credits,
+        avg_epoch_credits,
+        too_many_poor_voters,
+    )
+}
+
+/// Percentile of `credits` within `all_credits`: the percentage of validators that earned fewer
+/// credits, counting validators that earned the same credits as half below
+fn credits_percentile(credits: u64, all_credits: &[u64]) -> f64 {
+    if all_credits.is_empty() {
+        return 0.;
+    }
+    let below = all_credits.iter().filter(|c| **c < credits).count();
+    let equal = all_credits.iter().filter(|c| **c == credits).count();
+    (below as f64 + equal as f64 / 2.) * 100. / all_credits.len() as f64
+}
+
+/// Limit the validators that receive stake to the `max_desired_validators` ranked highest, by
+/// stake state, then reputation, then vote credits, breaking ties by identity. The validators
+/// below the cut are moved to the None stake state and returned in rank order
+fn cap_desired_validators(
+    validator_classifications: &mut ValidatorClassificationByIdentity,
+    max_desired_validators: usize,
+) -> Vec<Pubkey> {
+    let stake_state_rank = |stake_state: ValidatorStakeState| match stake_state {
+        ValidatorStakeState::Bonus => 2,
+        ValidatorStakeState::Baseline => 1,
+        ValidatorStakeState::None => 0,
+    };
+    let mut ranked = validator_classifications
+        .values()
+        .filter(|vc| vc.stake_state != ValidatorStakeState::None)
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| {
+        stake_state_rank(b.stake_state)
+            .cmp(&stake_state_rank(a.stake_state))
+            .then(
+                b.reputation
+                    .unwrap_or_default()
+                    .partial_cmp(&a.reputation.unwrap_or_default())
+                    .unwrap_or(Ordering::Equal),
+            )
+            .then(b.epoch_credits.cmp(&a.epoch_credits))
+            .then(a.identity.cmp(&b.identity))
+    });
+    let eligible = ranked.len();
+    let below_cut = ranked
+        .into_iter()
+        .skip(max_desired_validators)
+        .map(|vc| vc.identity)
+        .collect::<Vec<_>>();
+
+    for (i, identity) in below_cut.iter().enumerate() {
+        let vc = validator_classifications.get_mut(identity).unwrap();
+        let reason = format!(
+            "ranked {} of the {} validators eligible for stake, below the limit of {}; {}",
+            max_desired_validators + i + 1,
+            eligible,
+            max_desired_validators,
+            vc.stake_state_reason
+        );
+        vc.stake_state = ValidatorStakeState::None;
+        vc.stake_state_reason = reason.clone();
+        if let Some(stake_state) = vc
+            .stake_states
+            .as_mut()
+            .and_then(|stake_states| stake_states.first_mut())
+        {
+            *stake_state = (ValidatorStakeState::None, reason);
+        }
+    }
+    below_cut
+}
+
+/// Split validators by their average vote lag, returning the vote addresses of the validators that
+/// lag the cluster median by more than `max_vote_lag` slots, along with the cluster median
+fn classify_vote_lag(
+    max_vote_lag: u64,
+    average_vote_lag: &HashMap<Pubkey, f64>,
+) -> (ValidatorList, f64) {
+    let mut vote_lags = average_vote_lag.values().cloned().collect::<Vec<_>>();
+    vote_lags.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let cluster_median_vote_lag = if vote_lags.is_empty() {
+        0.
+    } else if vote_lags.len() % 2 == 0 {
+        (vote_lags[vote_lags.len() / 2 - 1] + vote_lags[vote_lags.len() / 2]) / 2.
+    } else {
+        vote_lags[vote_lags.len() / 2]
+    };
+
+    let max_vote_lag = cluster_median_vote_lag + max_vote_lag as f64;
+    let laggy_voters = average_vote_lag
+        .iter()
+        .filter_map(|(vote_address, vote_lag)| {
+            if *vote_lag > max_vote_lag {
+                Some(*vote_address)
+            } else {
+                None
+            }
+        })
+        .collect::<HashSet<_>>();
+
+    info!("Cluster median vote lag: {:.1}", cluster_median_vote_lag);
+    info!("Laggy voters: {}", laggy_voters.len());
+    trace!("laggy_voters: {:?}", laggy_voters);
 
-    (
-        poor_voters,
-        poor_voter_percentage,
-        min_epoch_credits,
-        avg_epoch_credits,
-        too_many_poor_voters,
-    )
+    (laggy_voters, cluster_median_vote_lag)
 }
 
 fn get_confirmed_blocks(
@@ -870,18 +3185,19 @@ fn get_confirmed_blocks(
     }
 }
 
-/// Split validators into quality/poor lists based on their block production over the given `epoch`
-fn classify_block_producers(
+/// Count the blocks produced and the leader slots of each validator over the given `epoch`, up to
+/// the latest finalized slot if the epoch is still in progress
+fn get_blocks_and_slots(
     rpc_client: &RpcClient,
-    config: &Config,
     epoch: Epoch,
-) -> BoxResult<ClassifyResult> {
+) -> BoxResult<HashMap<Pubkey, (usize, usize)>> {
     let epoch_schedule = rpc_client.get_epoch_schedule()?;
     let first_slot_in_epoch = epoch_schedule.get_first_slot_in_epoch(epoch);
-    let last_slot_in_epoch = epoch_schedule.get_last_slot_in_epoch(epoch);
+    let last_slot = epoch_schedule
+        .get_last_slot_in_epoch(epoch)
+        .min(rpc_client.get_slot_with_commitment(CommitmentConfig::finalized())?);
 
-    let confirmed_blocks =
-        get_confirmed_blocks(rpc_client, first_slot_in_epoch, last_slot_in_epoch)?;
+    let confirmed_blocks = get_confirmed_blocks(rpc_client, first_slot_in_epoch, last_slot)?;
 
     let leader_schedule = rpc_client
         .get_leader_schedule_with_commitment(
@@ -890,34 +3206,66 @@ fn classify_block_producers(
         )?
         .unwrap();
 
-    classify_producers(
+    count_blocks_and_slots(
         first_slot_in_epoch,
-        confirmed_blocks,
+        last_slot,
+        &confirmed_blocks,
         leader_schedule,
-        config,
     )
 }
 
+fn get_vote_account_health(
+    rpc_client: &RpcClient,
+    config: &Config,
+    epoch: Epoch,
+    vote_account_info: &[VoteAccountInfo],
+) -> BoxResult<HashMap<Pubkey, VoteAccountHealth>> {
+    info!("Fetching vote accounts...");
+
+    let rent: Rent = from_account(&rpc_client.get_account(&sysvar::rent::id())?)
+        .ok_or("Failed to deserialize rent")?;
+    let vote_addresses = vote_account_info
+        .iter()
+        .map(|vai| vai.vote_address)
+        .collect::<Vec<_>>();
+    let mut cache = EnrichmentCache::load("vote-account", &config.cluster_db_path())?;
+    let worker_rpc_client =
+        RpcClient::new_with_timeout(config.json_rpc_url.clone(), Duration::from_secs(180));
+    let vote_account_health = enrichment::enrich(
+        &vote_addresses,
+        epoch,
+        config.enrichment_parallelism,
+        &mut cache,
+        move |vote_address| {
+            let vote_account = worker_rpc_client
+                .get_account(vote_address)
+                .map_err(|err| err.to_string())?;
+            Ok(VoteAccountHealth::new(&vote_account, &rent))
+        },
+    );
+    if vote_account_health.len() < vote_addresses.len() {
+        return Err(format!(
+            "Failed to fetch {} of {} vote accounts",
+            vote_addresses.len() - vote_account_health.len(),
+            vote_addresses.len()
+        )
+        .into());
+    }
+    if let Err(err) = cache.save(epoch) {
+        warn!("Failed to save the vote account cache: {}", err);
+    }
+    Ok(vote_account_health)
+}
+
 // Look for self stake, where the stake withdraw authority matches the vote account withdraw
 // authority
 fn get_self_stake_by_vote_account(
     rpc_client: &RpcClient,
     epoch: Epoch,
-    vote_account_info: &[VoteAccountInfo],
+    authorized_withdrawer: &HashMap<Pubkey, Pubkey>,
 ) -> BoxResult<HashMap<Pubkey, u64>> {
     let mut self_stake_by_vote_account = HashMap::new();
 
-    info!("Building list of authorized voters...");
-
-    let mut authorized_withdrawer = HashMap::new();
-    for VoteAccountInfo { vote_address, .. } in vote_account_info {
-        let vote_account = rpc_client.get_account(vote_address)?;
-
-        if let Some(vote_state) = VoteState::from(&vote_account) {
-            authorized_withdrawer.insert(vote_address, vote_state.authorized_withdrawer);
-        }
-    }
-
     info!("Fetching stake accounts...");
     let all_stake_accounts = rpc_client.get_program_accounts(&solana_stake_program::id())?;
 
@@ -952,6 +3300,18 @@ fn get_self_stake_by_vote_account(
     Ok(self_stake_by_vote_account)
 }
 
+/// The data center of each validator, resolved offline if an ip-to-asn database is configured
+fn get_data_centers(
+    rpc_client: &RpcClient,
+    config: &Config,
+) -> BoxResult<data_center_info::DataCenters> {
+    match &config.ip_to_asn_path {
+        Some(ip_to_asn_path) => IpToAsn::load(ip_to_asn_path)
+            .and_then(|ip_to_asn| data_center_info::get_offline(rpc_client, &ip_to_asn)),
+        None => data_center_info::get(&config.cluster.to_string()),
+    }
+}
+
 fn get_testnet_participation(config: &Config) -> BoxResult<Option<HashMap<Pubkey, bool>>> {
     if let Some((n, m)) = &config.min_testnet_participation {
         assert_eq!(config.cluster, Cluster::MainnetBeta);
@@ -996,27 +3356,247 @@ fn get_testnet_participation(config: &Config) -> BoxResult<Option<HashMap<Pubkey
     }
 }
 
-fn classify(
+/// Observe the cluster for the classification of `epoch`
+fn get_classifier_inputs(
     rpc_client: &RpcClient,
     config: &Config,
     epoch: Epoch,
     validator_list: &ValidatorList,
     identity_to_participant: &IdentityToParticipant,
-    previous_epoch_validator_classifications: Option<&ValidatorClassificationByIdentity>,
-) -> BoxResult<EpochClassificationV1> {
+) -> BoxResult<ClassifierInputs> {
     let last_epoch = epoch - 1;
 
+    let cluster_outage = if config.outage_lookback_minutes > 0 {
+        cluster_outage::detect(rpc_client, config.outage_lookback_minutes)?
+    } else {
+        None
+    };
+
     let testnet_participation = get_testnet_participation(config)?;
 
-    let data_centers = data_center_info::get(&config.cluster.to_string())
+    let data_centers = get_data_centers(rpc_client, config)
         .map_err(|e| {
             warn!("infrastructure concentration skipped: {}", e);
             e
         })
         .unwrap_or_default();
 
-    let infrastructure_concentration_too_high = data_centers
-        .info
+    // The cluster state read by the classification, each part recorded with the slot it was
+    // fetched at. The vote account health is cached for the epoch and not fetched again
+    let cluster_nodes_required = !config.screening_sources.is_empty()
+        || config.min_release_version.is_some()
+        || config.endpoint_probe_timeout.is_some();
+    let mut stragglers = vec!["vote_accounts", "stake_accounts", "blocks"];
+    if cluster_nodes_required {
+        stragglers.push("cluster_nodes");
+    }
+    // The canary policy may limit the vote lag alone
+    if config.max_vote_lag.is_some()
+        || config
+            .canary
+            .as_ref()
+            .map_or(false, |canary| canary.policy.max_vote_lag.is_some())
+    {
+        stragglers.push("vote_lag");
+    }
+    let mut stragglers = stragglers
+        .into_iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let mut input_slots = BTreeMap::new();
+    let mut vote_account_info = vec![];
+    let mut vote_account_health = HashMap::default();
+    let mut self_stake_by_vote_account = HashMap::default();
+    let mut cluster_nodes = vec![];
+    let mut blocks_and_slots = HashMap::default();
+    let mut average_vote_lag = HashMap::default();
+    for round in 0..=input_slots::MAX_REFETCH_ROUNDS {
+        if round > 0 {
+            info!(
+                "Classifier inputs span {} slots, fetching again: {}",
+                input_slots::spread(&input_slots),
+                stragglers.join(", ")
+            );
+        }
+        for name in &stragglers {
+            let slot = rpc_client.get_slot()?;
+            match name.as_str() {
+                "vote_accounts" => {
+                    vote_account_info = get_vote_account_info(&rpc_client, last_epoch)?;
+                    vote_account_health =
+                        get_vote_account_health(rpc_client, config, epoch, &vote_account_info)?;
+                }
+                "stake_accounts" => {
+                    let authorized_withdrawer_by_vote_account = vote_account_health
+                        .iter()
+                        .filter_map(|(vote_address, health)| {
+                            Some((*vote_address, health.authorized_withdrawer()?))
+                        })
+                        .collect();
+                    self_stake_by_vote_account = get_self_stake_by_vote_account(
+                        rpc_client,
+                        epoch,
+                        &authorized_withdrawer_by_vote_account,
+                    )?;
+                }
+                "cluster_nodes" => cluster_nodes = rpc_client.get_cluster_nodes()?,
+                "blocks" => blocks_and_slots = get_blocks_and_slots(&rpc_client, last_epoch)?,
+                "vote_lag" => {
+                    average_vote_lag = get_average_vote_lag(
+                        rpc_client,
+                        config.vote_lag_samples,
+                        Duration::from_secs(1),
+                    )?
+                }
+                _ => unreachable!(),
+            }
+            input_slots.insert(name.clone(), slot);
+        }
+        stragglers = match config.input_slot_window {
+            Some(input_slot_window) => input_slots::stragglers(&input_slots, input_slot_window),
+            None => vec![],
+        };
+        if stragglers.is_empty() {
+            break;
+        }
+    }
+    if !stragglers.is_empty() {
+        return Err(guardrail(format!(
+            "Classifier inputs still span {} slots after {} attempts, more than the window of {}",
+            input_slots::spread(&input_slots),
+            input_slots::MAX_REFETCH_ROUNDS + 1,
+            config.input_slot_window.unwrap_or_default()
+        )));
+    }
+    info!(
+        "Classifier inputs fetched within {} slots",
+        input_slots::spread(&input_slots)
+    );
+
+    let screened_validators = if config.screening_sources.is_empty() {
+        HashMap::default()
+    } else {
+        let gossip_ips = cluster_nodes
+            .iter()
+            .filter_map(|rpc_contact_info| {
+                Some((
+                    Pubkey::from_str(&rpc_contact_info.pubkey).ok()?,
+                    rpc_contact_info.gossip?.ip(),
+                ))
+            })
+            .collect::<HashMap<_, _>>();
+        let validator_locations = vote_account_info
+            .iter()
+            .filter(|vai| validator_list.contains(&vai.identity))
+            .map(|vai| {
+                ValidatorLocation::new(
+                    &vai.identity,
+                    &vai.vote_address,
+                    &data_centers
+                        .by_identity
+                        .get(&vai.identity)
+                        .cloned()
+                        .unwrap_or_default(),
+                    gossip_ips.get(&vai.identity).cloned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        screening::screen(&config.screening_sources, &validator_locations)?
+    };
+
+    let release_versions = if config.min_release_version.is_some() {
+        cluster_nodes
+            .iter()
+            .filter_map(|rpc_contact_info| {
+                let identity = Pubkey::from_str(&rpc_contact_info.pubkey).ok()?;
+                if validator_list.contains(&identity) {
+                    Some((identity, rpc_contact_info.version.clone()?))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    } else {
+        HashMap::default()
+    };
+
+    let endpoint_probes = match config.endpoint_probe_timeout {
+        Some(timeout) => {
+            let validators = cluster_nodes
+                .iter()
+                .filter_map(|rpc_contact_info| {
+                    let identity = Pubkey::from_str(&rpc_contact_info.pubkey).ok()?;
+                    if validator_list.contains(&identity) {
+                        Some(ValidatorEndpoints {
+                            identity,
+                            gossip: rpc_contact_info.gossip,
+                            rpc: rpc_contact_info.rpc,
+                        })
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            probe_endpoints(validators, timeout, config.endpoint_probe_parallelism)
+        }
+        None => HashMap::default(),
+    };
+
+    let sampled_commission = if config.commission_grace {
+        CommissionSamples::load(last_epoch, &config.cluster_db_path())?.sampled_commission()
+    } else {
+        HashMap::default()
+    };
+
+    Ok(ClassifierInputs {
+        validator_list: validator_list.clone(),
+        identity_to_participant: identity_to_participant.clone(),
+        testnet_participation,
+        data_center_info: data_centers.info,
+        data_center_by_identity: data_centers.by_identity,
+        vote_account_info,
+        self_stake_by_vote_account,
+        vote_account_health,
+        screened_validators,
+        release_versions,
+        blocks_and_slots,
+        average_vote_lag,
+        endpoint_probes,
+        cluster_outage,
+        input_slots,
+        sampled_commission,
+    })
+}
+
+/// Classify the validators for `epoch` from the observations of the cluster in `inputs`
+fn classify(
+    config: &Config,
+    epoch: Epoch,
+    inputs: &ClassifierInputs,
+    previous_epoch_validator_classifications: Option<&ValidatorClassificationByIdentity>,
+) -> BoxResult<EpochClassificationV1> {
+    let last_epoch = epoch - 1;
+
+    let ClassifierInputs {
+        validator_list,
+        identity_to_participant,
+        testnet_participation,
+        data_center_info,
+        data_center_by_identity,
+        vote_account_info,
+        self_stake_by_vote_account,
+        vote_account_health,
+        screened_validators,
+        release_versions,
+        blocks_and_slots,
+        average_vote_lag,
+        endpoint_probes,
+        cluster_outage,
+        input_slots,
+        sampled_commission,
+    } = inputs;
+
+    let infrastructure_concentration_too_high = data_center_info
         .iter()
         .filter_map(|dci| {
             if dci.stake_percent > config.max_infrastructure_concentration {
@@ -1028,27 +3608,15 @@ fn classify(
         .flat_map(|(v, sp)| v.into_iter().map(move |v| (v, sp)))
         .collect::<HashMap<_, _>>();
 
-    let vote_account_info = get_vote_account_info(&rpc_client, last_epoch)?;
-
-    let self_stake_by_vote_account =
-        get_self_stake_by_vote_account(rpc_client, epoch, &vote_account_info)?;
-
     let (cluster_nodes_with_old_version, min_release_version): (HashMap<String, _>, _) =
         match config.min_release_version {
             Some(ref min_release_version) => (
-                rpc_client
-                    .get_cluster_nodes()?
-                    .into_iter()
-                    .filter_map(|rpc_contact_info| {
-                        if let Ok(identity) = Pubkey::from_str(&rpc_contact_info.pubkey) {
-                            if validator_list.contains(&identity) {
-                                if let Some(ref version) = rpc_contact_info.version {
-                                    if let Ok(semver) = semver::Version::parse(version) {
-                                        if semver < *min_release_version {
-                                            return Some((rpc_contact_info.pubkey, semver));
-                                        }
-                                    }
-                                }
+                release_versions
+                    .iter()
+                    .filter_map(|(identity, version)| {
+                        if let Ok(semver) = semver::Version::parse(version) {
+                            if semver < *min_release_version {
+                                return Some((identity.to_string(), semver));
                             }
                         }
                         None
@@ -1072,7 +3640,7 @@ fn classify(
         block_producer_classification_reason,
         cluster_average_skip_rate,
         too_many_poor_block_producers,
-    ) = classify_block_producers(&rpc_client, &config, last_epoch)?;
+    ) = classify_blocks_and_slots(blocks_and_slots, &config)?;
 
     let not_in_leader_schedule: ValidatorList = validator_list
         .difference(
@@ -1095,7 +3663,24 @@ fn classify(
         min_epoch_credits,
         avg_epoch_credits,
         too_many_poor_voters,
-    ) = classify_poor_voters(&config, &vote_account_info);
+    ) = classify_poor_voters(&config, vote_account_info);
+
+    let (laggy_voters, vote_lag_note) = match config.max_vote_lag {
+        Some(max_vote_lag) => {
+            let (laggy_voters, cluster_median_vote_lag) =
+                classify_vote_lag(max_vote_lag, average_vote_lag);
+            (
+                laggy_voters,
+                Some(format!(
+                    "Maximum average vote lag: {:.1} slots (cluster median: {:.1} slots, grace: {} slots)",
+                    cluster_median_vote_lag + max_vote_lag as f64,
+                    cluster_median_vote_lag,
+                    max_vote_lag,
+                )),
+            )
+        }
+        None => (HashSet::default(), None),
+    };
 
     let mut notes = vec![
         format!(
@@ -1105,13 +3690,22 @@ fn classify(
             avg_epoch_credits,
             config.min_epoch_credit_percentage_of_average,
         ),
-        format!(
-            "Maximum allowed skip rate for epoch {}: {:.2}% (cluster average: {:.2}%, grace: {}%)",
-            last_epoch,
-            cluster_average_skip_rate + config.quality_block_producer_percentage,
-            cluster_average_skip_rate,
-            config.quality_block_producer_percentage,
-        ),
+        if config.normalize_skip_rate {
+            format!(
+                "Maximum allowed skip rate relative to the cluster for epoch {}: {:.2}% (cluster average: {:.2}%)",
+                last_epoch,
+                config.quality_block_producer_percentage,
+                cluster_average_skip_rate,
+            )
+        } else {
+            format!(
+                "Maximum allowed skip rate for epoch {}: {:.2}% (cluster average: {:.2}%, grace: {}%)",
+                last_epoch,
+                cluster_average_skip_rate + config.quality_block_producer_percentage,
+                cluster_average_skip_rate,
+                config.quality_block_producer_percentage,
+            )
+        },
         format!("Solana release {} or greater required", min_release_version),
         format!("Maximum commission: {}%", config.max_commission),
         format!(
@@ -1124,6 +3718,41 @@ fn classify(
         ),
     ];
 
+    if let Some(vote_lag_note) = vote_lag_note {
+        notes.push(vote_lag_note);
+    }
+
+    if config.endpoint_probe_timeout.is_some() {
+        notes.push(format!(
+            "Endpoint probes: {} of {} validators with an unreachable gossip or RPC endpoint",
+            endpoint_probes
+                .values()
+                .filter(|probe| !probe.notes().is_empty())
+                .count(),
+            endpoint_probes.len()
+        ));
+    }
+
+    if !config.screening_sources.is_empty() {
+        notes.push(format!(
+            "Screening hooks: {} ({} validators excluded)",
+            config
+                .screening_sources
+                .iter()
+                .map(|source| source.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            screened_validators.len()
+        ));
+    }
+
+    if let Some(min_bonus_activated_stake_lamports) = config.min_bonus_activated_stake_lamports {
+        notes.push(format!(
+            "Minimum activated stake for bonus stake: {}",
+            Sol(min_bonus_activated_stake_lamports)
+        ));
+    }
+
     if let Some((n, m)) = &config.min_testnet_participation {
         notes.push(format!(
             "Participants must maintain Baseline or Bonus stake level for {} of the last {} Testnet epochs",
@@ -1133,6 +3762,12 @@ fn classify(
 
     if cluster_average_skip_rate > config.bad_cluster_average_skip_rate {
         notes.push("Cluster average skip rate is poor".to_string());
+        if config.normalize_skip_rate {
+            notes.push(format!(
+                "Block production during epoch {} ignored due to cluster-wide degradation",
+                last_epoch
+            ));
+        }
     }
     if too_many_poor_voters {
         notes.push(format!(
@@ -1153,7 +3788,25 @@ fn classify(
         ));
     }
 
-    let validator_classifications = if too_many_poor_voters
+    if !input_slots.is_empty() {
+        notes.push(format!(
+            "Classifier inputs fetched between slots {} and {}",
+            input_slots.values().min().unwrap(),
+            input_slots.values().max().unwrap()
+        ));
+    }
+
+    if let Some(cluster_outage) = cluster_outage {
+        let note = format!(
+            "Cluster outage detected, validators are not classified as delinquent: {}",
+            cluster_outage
+        );
+        warn!("{}", note);
+        notes.push(note);
+    }
+
+    let validator_classifications = if cluster_outage.is_some()
+        || too_many_poor_voters
         || too_many_old_validators
         || too_many_poor_block_producers
     {
@@ -1162,12 +3815,32 @@ fn classify(
     } else {
         let mut validator_classifications = HashMap::new();
 
+        let cluster_credits = vote_account_info
+            .iter()
+            .map(|vai| vai.epoch_credits)
+            .collect::<Vec<_>>();
+        let pool_credits = vote_account_info
+            .iter()
+            .filter(|vai| validator_list.contains(&vai.identity))
+            .map(|vai| vai.epoch_credits)
+            .collect::<Vec<_>>();
+
+        // Identity of each vote account in the previous epoch, to detect identity changes
+        let previous_identity_by_vote_address = previous_epoch_validator_classifications
+            .map(|p| {
+                p.values()
+                    .map(|vc| (vc.vote_address, vc.identity))
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+
         for VoteAccountInfo {
             identity,
             vote_address,
             commission,
             epoch_credits,
-        } in vote_account_info
+            activated_stake,
+        } in vote_account_info.iter().cloned()
         {
             if !validator_list.contains(&identity) {
                 continue;
@@ -1175,8 +3848,7 @@ fn classify(
 
             let participant = identity_to_participant.get(&identity).cloned();
 
-            let current_data_center = data_centers
-                .by_identity
+            let current_data_center = data_center_by_identity
                 .get(&identity)
                 .cloned()
                 .unwrap_or_default();
@@ -1207,6 +3879,72 @@ fn classify(
                 format!("{} credits earned in epoch {}", epoch_credits, last_epoch);
 
             let mut validator_notes = vec![];
+            // Every criterion the validator fails, whether or not it decides the stake state
+            let mut failed_criteria = FailedCriteria::new();
+            if let Some(endpoint_probe) = endpoint_probes.get(&identity) {
+                validator_notes.extend(endpoint_probe.notes());
+            }
+
+            let identity_change_epoch = match previous_identity_by_vote_address
+                .get(&vote_address)
+                .filter(|previous_identity| **previous_identity != identity)
+            {
+                Some(previous_identity) => {
+                    let note = format!(
+                        "vote account {} changed identity from {} to {}",
+                        vote_address, previous_identity, identity
+                    );
+                    warn!("{}", note);
+                    notes.push(note.clone());
+                    validator_notes.push(note);
+                    Some(epoch)
+                }
+                None => previous_classification
+                    .map(|vc| vc.identity_change_epoch)
+                    .flatten(),
+            };
+
+            let health = vote_account_health.get(&vote_address);
+            let authorized_withdrawer = health.and_then(|health| health.authorized_withdrawer());
+            let vote_account_problem = health.and_then(|health| health.problem(&identity));
+            let withdrawer_change = config.withdrawer_change.as_ref().and_then(|policy| {
+                let withdrawer_change_epoch = policy.change_epoch(
+                    epoch,
+                    previous_classification
+                        .and_then(|vc| vc.authorized_withdrawer)
+                        .as_ref(),
+                    authorized_withdrawer.as_ref(),
+                    previous_classification.and_then(|vc| vc.withdrawer_change_epoch),
+                )?;
+                let reason = format!(
+                    "authorized withdrawer of vote account {} changed to the unknown address \
+                     {} in epoch {}",
+                    vote_address,
+                    authorized_withdrawer
+                        .map(|withdrawer| withdrawer.to_string())
+                        .unwrap_or_default(),
+                    withdrawer_change_epoch
+                );
+                if withdrawer_change_epoch == epoch {
+                    warn!("{}", reason);
+                    notes.push(reason.clone());
+                }
+                Some((policy.action, withdrawer_change_epoch, reason))
+            });
+            if let Some((_, _, reason)) = &withdrawer_change {
+                failed_criteria.insert(Criterion::AuthorizedWithdrawer, reason.clone());
+            }
+            let withdrawer_change_epoch = withdrawer_change
+                .as_ref()
+                .map(|(_, withdrawer_change_epoch, _)| *withdrawer_change_epoch);
+            let withdrawer_change_reason = match withdrawer_change {
+                Some((WithdrawerChangeAction::Warn, _, reason)) => {
+                    validator_notes.push(reason);
+                    None
+                }
+                Some((action, _, reason)) => Some((action, reason)),
+                None => None,
+            };
 
             let infrastructure_concentration_destake_reason = infrastructure_concentration_too_high
                 .get(&identity)
@@ -1218,8 +3956,14 @@ fn classify(
                     )
                 })
                 .and_then(|affect| match affect {
-                    InfrastructureConcentrationAffectKind::Destake(reason) => Some(reason),
+                    InfrastructureConcentrationAffectKind::Destake(reason) => {
+                        failed_criteria
+                            .insert(Criterion::InfrastructureConcentration, reason.clone());
+                        Some(reason)
+                    }
                     InfrastructureConcentrationAffectKind::Warn(reason) => {
+                        failed_criteria
+                            .insert(Criterion::InfrastructureConcentration, reason.clone());
                         validator_notes.push(reason);
                         None
                     }
@@ -1227,8 +3971,11 @@ fn classify(
 
             let insufficent_self_stake_msg =
                 format!("insufficient self stake: {}", Sol(self_stake));
-            if !config.enforce_min_self_stake && self_stake < config.min_self_stake_lamports {
-                validator_notes.push(insufficent_self_stake_msg.clone());
+            if self_stake < config.min_self_stake_lamports {
+                failed_criteria.insert(Criterion::SelfStake, insufficent_self_stake_msg.clone());
+                if !config.enforce_min_self_stake {
+                    validator_notes.push(insufficent_self_stake_msg.clone());
+                }
             }
 
             let insufficent_testnet_participation = testnet_participation
@@ -1237,6 +3984,7 @@ fn classify(
                     if let Some(participant) = participant {
                         if !testnet_participation.get(&participant).unwrap_or(&true) {
                             let note = "insufficient testnet participation".to_string();
+                            failed_criteria.insert(Criterion::TestnetParticipation, note.clone());
                             if config.enforce_testnet_participation {
                                 return Some(note);
                             } else {
@@ -1248,71 +3996,253 @@ fn classify(
                 })
                 .flatten();
 
+            // A commission raised since the epoch boundary may be a mistake, reverted before the
+            // next one
+            let commission_tolerated = config.commission_grace
+                && commission_samples::tolerated(
+                    commission,
+                    config.max_commission,
+                    sampled_commission.get(&vote_address),
+                );
+            if commission_tolerated {
+                let note = format!(
+                    "commission raised to {}% since the epoch boundary, destaked if it persists \
+                     past the next one",
+                    commission
+                );
+                notes.push(format!("{}: {}", identity, note));
+                validator_notes.push(note);
+            } else if let Some(sampled_commission) =
+                sampled_commission
+                    .get(&vote_address)
+                    .filter(|sampled_commission| {
+                        commission <= config.max_commission
+                            && sampled_commission.max > config.max_commission
+                    })
+            {
+                validator_notes.push(format!(
+                    "commission briefly raised to {}% during epoch {}",
+                    sampled_commission.max, last_epoch
+                ));
+            }
+
+            if let Some(reason) = screened_validators.get(&identity) {
+                failed_criteria.insert(
+                    Criterion::Screening,
+                    format!("excluded by screening: {}", reason),
+                );
+            }
+            if let Some(vote_account_problem) = &vote_account_problem {
+                failed_criteria.insert(Criterion::VoteAccount, vote_account_problem.clone());
+            }
+            if commission > config.max_commission && !commission_tolerated {
+                failed_criteria.insert(
+                    Criterion::Commission,
+                    format!("commission is too high: {}% commission", commission),
+                );
+            }
+            if poor_voters.contains(&identity) {
+                failed_criteria.insert(
+                    Criterion::VoteCredits,
+                    format!("insufficient vote credits: {}", vote_credits_msg),
+                );
+            }
+            if let Some(release_version) = cluster_nodes_with_old_version.get(&identity.to_string())
+            {
+                failed_criteria.insert(
+                    Criterion::ReleaseVersion,
+                    format!("Outdated solana release: {}", release_version),
+                );
+            }
+            if laggy_voters.contains(&vote_address) {
+                failed_criteria.insert(
+                    Criterion::VoteLag,
+                    format!(
+                        "high vote lag: {:.1} slots behind the cluster tip on average",
+                        average_vote_lag
+                            .get(&vote_address)
+                            .cloned()
+                            .unwrap_or_default()
+                    ),
+                );
+            }
+            if poor_block_producers.contains(&identity) {
+                failed_criteria.insert(
+                    Criterion::BlockProduction,
+                    format!(
+                        "poor block production during epoch {}: {} ",
+                        last_epoch, block_producer_classification_reason_msg
+                    ),
+                );
+            }
+
             let (stake_state, reason) = if let Some(reason) =
-                infrastructure_concentration_destake_reason
+                failed_criteria.get(&Criterion::Screening)
+            {
+                (ValidatorStakeState::None, reason.clone())
+            } else if let Some(reason) = failed_criteria.get(&Criterion::VoteAccount) {
+                (ValidatorStakeState::None, reason.clone())
+            } else if let Some((WithdrawerChangeAction::Destake, reason)) =
+                &withdrawer_change_reason
             {
+                (ValidatorStakeState::None, reason.clone())
+            } else if let Some(reason) = infrastructure_concentration_destake_reason {
                 (ValidatorStakeState::None, reason)
             } else if config.enforce_min_self_stake && self_stake < config.min_self_stake_lamports {
                 (ValidatorStakeState::None, insufficent_self_stake_msg)
-            } else if commission > config.max_commission {
-                (
-                    ValidatorStakeState::None,
-                    format!("commission is too high: {}% commission", commission),
-                )
+            } else if let Some(reason) = failed_criteria.get(&Criterion::Commission) {
+                (ValidatorStakeState::None, reason.clone())
             } else if let Some(insufficent_testnet_participation) =
                 insufficent_testnet_participation
             {
                 (ValidatorStakeState::None, insufficent_testnet_participation)
-            } else if poor_voters.contains(&identity) {
-                (
-                    ValidatorStakeState::None,
-                    format!("insufficient vote credits: {}", vote_credits_msg),
-                )
-            } else if cluster_nodes_with_old_version.contains_key(&identity.to_string()) {
+            } else if let Some(reason) = failed_criteria.get(&Criterion::VoteCredits) {
+                (ValidatorStakeState::None, reason.clone())
+            } else if let Some(reason) = failed_criteria.get(&Criterion::ReleaseVersion) {
+                (ValidatorStakeState::None, reason.clone())
+            } else if let Some(reason) = failed_criteria.get(&Criterion::VoteLag) {
+                (ValidatorStakeState::Baseline, reason.clone())
+            } else if quality_block_producers.contains(&identity) {
                 (
-                    ValidatorStakeState::None,
+                    ValidatorStakeState::Bonus,
                     format!(
-                        "Outdated solana release: {}",
-                        cluster_nodes_with_old_version
-                            .get(&identity.to_string())
-                            .unwrap()
+                        "good block production during epoch {}: {}",
+                        last_epoch, block_producer_classification_reason_msg
                     ),
                 )
-            } else if quality_block_producers.contains(&identity) {
+            } else if let Some(reason) = failed_criteria.get(&Criterion::BlockProduction) {
+                (ValidatorStakeState::Baseline, reason.clone())
+            } else {
+                assert!(!poor_voters.contains(&identity));
+                assert!(not_in_leader_schedule.contains(&identity));
                 (
-                    ValidatorStakeState::Bonus,
+                    // If the validator is not in the leader schedule but was Bonus previously,
+                    // maintain Bonus.
+                    //
+                    // Destaking due to delinquency will not be reflected in the leader schedule
+                    // until 2 epochs later, which point the validator may have recovered and
+                    // there's no need to punish the validator further by reducing it to the
+                    // Baseline level.
+                    if previous_stake_state == ValidatorStakeState::Bonus {
+                        ValidatorStakeState::Bonus
+                    } else {
+                        ValidatorStakeState::Baseline
+                    },
+                    format!("no leader slots; {}", vote_credits_msg),
+                )
+            };
+
+            let reputation_score = reputation::score(
+                stake_state,
+                previous_classification
+                    .map(|vc| vc.reputation)
+                    .flatten()
+                    .unwrap_or_default(),
+                config.reputation_decay_percentage,
+                config.min_bonus_reputation,
+            );
+            let reputation = reputation_score.reputation;
+            let reason = match &reputation_score.failure {
+                Some(failure) if reputation_score.stake_state != stake_state => {
+                    format!("{}; {}", failure, reason)
+                }
+                _ => reason,
+            };
+            if let Some(failure) = reputation_score.failure {
+                failed_criteria.insert(Criterion::BonusReputation, failure);
+            }
+            let stake_state = reputation_score.stake_state;
+
+            if let Some(min_bonus_activated_stake_lamports) = config
+                .min_bonus_activated_stake_lamports
+                .filter(|min_bonus_activated_stake_lamports| {
+                    activated_stake < *min_bonus_activated_stake_lamports
+                })
+            {
+                failed_criteria.insert(
+                    Criterion::BonusActivatedStake,
+                    format!(
+                        "activated stake {} is below the {} required for bonus stake",
+                        Sol(activated_stake),
+                        Sol(min_bonus_activated_stake_lamports),
+                    ),
+                );
+            }
+            let (stake_state, reason) = match failed_criteria.get(&Criterion::BonusActivatedStake) {
+                Some(failure) if stake_state == ValidatorStakeState::Bonus => (
+                    ValidatorStakeState::Baseline,
+                    format!("{}; {}", failure, reason),
+                ),
+                _ => (stake_state, reason),
+            };
+
+            if let (Some(hold_epochs), Some(identity_change_epoch)) = (
+                config.identity_change_bonus_hold_epochs,
+                identity_change_epoch,
+            ) {
+                if epoch < identity_change_epoch + hold_epochs {
+                    failed_criteria.insert(
+                        Criterion::IdentityChange,
+                        format!(
+                            "bonus stake held for {} epochs after the identity change in epoch {}",
+                            hold_epochs, identity_change_epoch
+                        ),
+                    );
+                }
+            }
+            let (stake_state, reason) = match failed_criteria.get(&Criterion::IdentityChange) {
+                Some(failure) if stake_state == ValidatorStakeState::Bonus => (
+                    ValidatorStakeState::Baseline,
+                    format!("{}; {}", failure, reason),
+                ),
+                _ => (stake_state, reason),
+            };
+
+            let (stake_state, reason) = match withdrawer_change_reason {
+                Some((WithdrawerChangeAction::Baseline, withdrawer_change_reason))
+                    if stake_state == ValidatorStakeState::Bonus =>
+                {
+                    (
+                        ValidatorStakeState::Baseline,
+                        format!("{}; {}", withdrawer_change_reason, reason),
+                    )
+                }
+                _ => (stake_state, reason),
+            };
+
+            let hosting_provider = config
+                .hosting_policy
+                .as_ref()
+                .and_then(|hosting_policy| hosting_policy.provider_of(current_data_center.asn));
+            if let Some(provider) =
+                hosting_provider.filter(|provider| provider.policy == ProviderPolicy::Ineligible)
+            {
+                failed_criteria.insert(
+                    Criterion::HostingProvider,
                     format!(
-                        "good block production during epoch {}: {}",
-                        last_epoch, block_producer_classification_reason_msg
+                        "hosted by {} (ASN {}), which is not eligible for bonus stake",
+                        provider.name, current_data_center.asn
                     ),
-                )
-            } else if poor_block_producers.contains(&identity) {
-                (
+                );
+            }
+            let (stake_state, reason) = match failed_criteria.get(&Criterion::HostingProvider) {
+                Some(failure) if stake_state == ValidatorStakeState::Bonus => (
                     ValidatorStakeState::Baseline,
-                    format!(
-                        "poor block production during epoch {}: {} ",
-                        last_epoch, block_producer_classification_reason_msg
-                    ),
-                )
-            } else {
-                assert!(!poor_voters.contains(&identity));
-                assert!(not_in_leader_schedule.contains(&identity));
-                (
-                    // If the validator is not in the leader schedule but was Bonus previously,
-                    // maintain Bonus.
-                    //
-                    // Destaking due to delinquency will not be reflected in the leader schedule
-                    // until 2 epochs later, which point the validator may have recovered and
-                    // there's no need to punish the validator further by reducing it to the
-                    // Baseline level.
-                    if previous_stake_state == ValidatorStakeState::Bonus {
-                        ValidatorStakeState::Bonus
-                    } else {
-                        ValidatorStakeState::Baseline
-                    },
-                    format!("no leader slots; {}", vote_credits_msg),
-                )
+                    format!("{}; {}", failure, reason),
+                ),
+                _ => (stake_state, reason),
             };
+            let bonus_weight_penalty =
+                hosting_provider.and_then(|provider| match provider.policy {
+                    ProviderPolicy::Penalty(percentage) => {
+                        validator_notes.push(format!(
+                            "hosted by {} (ASN {}), bonus weight reduced by {}%",
+                            provider.name, current_data_center.asn, percentage
+                        ));
+                        Some(percentage)
+                    }
+                    ProviderPolicy::Ineligible => None,
+                });
 
             // Data center seniority increases with Bonus stake and decreases
             // otherwise
@@ -1369,29 +4299,1069 @@ fn classify(
                     data_center_residency: Some(data_center_residency),
                     current_data_center: Some(current_data_center.clone()),
                     participant,
+                    reputation: Some(reputation),
+                    off_target_epochs: None,
+                    projected_effective_stake: None,
+                    time_weighted_stake: None,
+                    epoch_credits: Some(epoch_credits),
+                    cluster_credits_percentile: Some(credits_percentile(
+                        epoch_credits,
+                        &cluster_credits,
+                    )),
+                    pool_credits_percentile: Some(credits_percentile(epoch_credits, &pool_credits)),
+                    identity_change_epoch,
+                    authorized_withdrawer,
+                    withdrawer_change_epoch,
+                    hosting_provider: hosting_provider.map(|provider| provider.name.clone()),
+                    bonus_weight_penalty,
+                    failed_criteria: Some(failed_criteria),
                 },
             );
         }
-        notes.push(format!(
-            "{} validators processed",
-            validator_classifications.len()
-        ));
+        if let Some(max_desired_validators) = config.max_desired_validators {
+            let below_cut =
+                cap_desired_validators(&mut validator_classifications, max_desired_validators);
+            if !below_cut.is_empty() {
+                notes.push(format!(
+                    "{} validators fell below the limit of {} validators receiving stake: {}",
+                    below_cut.len(),
+                    max_desired_validators,
+                    below_cut
+                        .iter()
+                        .map(|identity| identity.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+        notes.push(format!(
+            "{} validators processed",
+            validator_classifications.len()
+        ));
+
+        Some(validator_classifications)
+    };
+
+    Ok(EpochClassificationV1 {
+        data_center_info: data_center_info.clone(),
+        validator_classifications,
+        notes,
+        ..EpochClassificationV1::default()
+    })
+}
+
+/// Classify the validators for `epoch` as a run does, settling the classification of the canary
+/// policy if one is configured
+fn classify_run(
+    config: &Config,
+    epoch: Epoch,
+    inputs: &ClassifierInputs,
+    previous_epoch_validator_classifications: Option<&ValidatorClassificationByIdentity>,
+) -> BoxResult<EpochClassificationV1> {
+    let mut epoch_classification = classify(
+        config,
+        epoch,
+        inputs,
+        previous_epoch_validator_classifications,
+    )?;
+    if let Some(canary) = &config.canary {
+        let canary_classification = classify(
+            &config.with_canary_policy(&canary.policy),
+            epoch,
+            inputs,
+            previous_epoch_validator_classifications,
+        )?;
+        let canary_notes = canary.apply(&mut epoch_classification, canary_classification);
+        for note in &canary_notes {
+            info!("{}", note);
+        }
+        epoch_classification.notes.extend(canary_notes);
+    }
+    Ok(epoch_classification)
+}
+
+// Desired stake of the classified validators
+fn classifier_validator_stake(
+    validator_classifications: &ValidatorClassificationByIdentity,
+    bonus_streak: Option<(usize, u64)>,
+) -> Vec<ValidatorStake> {
+    validator_classifications
+        .values()
+        .map(|vc| {
+            let bonus_weight = match bonus_streak {
+                Some((n, percentage))
+                    if vc.stake_state == ValidatorStakeState::Bonus
+                        && vc.stake_state_streak() >= n =>
+                {
+                    DEFAULT_BONUS_WEIGHT * (100 + percentage) / 100
+                }
+                _ => DEFAULT_BONUS_WEIGHT,
+            };
+            let bonus_weight =
+                bonus_weight * (100 - vc.bonus_weight_penalty.unwrap_or_default()) / 100;
+
+            ValidatorStake {
+                bonus_weight,
+                ..ValidatorStake::new(vc.identity, vc.vote_address, vc.stake_state)
+            }
+        })
+        .collect()
+}
+
+/// Desired stake read from each of `sources` that the bot does not derive from the validator
+/// classifications, by source
+fn read_desired_stake_sources(
+    sources: &[DesiredStakeSource],
+) -> BoxResult<HashMap<String, Vec<ValidatorStake>>> {
+    let mut source_validator_stake = HashMap::new();
+    for source in sources {
+        let validator_stake = match source {
+            DesiredStakeSource::Classifier | DesiredStakeSource::Registry => continue,
+            DesiredStakeSource::File(path) => desired_stake::load(path)?,
+            DesiredStakeSource::Foundation(path) => foundation_list::load(path)?,
+            DesiredStakeSource::Stdin => desired_stake::load_stdin()?,
+        };
+        source_validator_stake.insert(source.to_string(), validator_stake);
+    }
+    Ok(source_validator_stake)
+}
+
+/// Desired validator stake merged from `sources`, in the order given by `seed`, along with the
+/// conflicts between the sources. The sources that do not derive from the validator
+/// classifications are taken from `source_validator_stake`, see `read_desired_stake_sources`
+fn desired_validator_stake(
+    sources: &[DesiredStakeSource],
+    source_validator_stake: &HashMap<String, Vec<ValidatorStake>>,
+    validator_classifications: &ValidatorClassificationByIdentity,
+    bonus_streak: Option<(usize, u64)>,
+    seed: u64,
+) -> BoxResult<(Vec<ValidatorStake>, Vec<String>)> {
+    let mut inputs = vec![];
+    for source in sources {
+        let validator_stake = match source {
+            DesiredStakeSource::Classifier => {
+                classifier_validator_stake(validator_classifications, bonus_streak)
+            }
+            DesiredStakeSource::Registry => validator_classifications
+                .values()
+                .filter(|vc| vc.participant.is_some())
+                .map(|vc| {
+                    ValidatorStake::new(vc.identity, vc.vote_address, ValidatorStakeState::Baseline)
+                })
+                .collect(),
+            DesiredStakeSource::File(_)
+            | DesiredStakeSource::Foundation(_)
+            | DesiredStakeSource::Stdin => source_validator_stake
+                .get(&source.to_string())
+                .cloned()
+                .ok_or_else(|| format!("No desired stake read from {}", source))?,
+        };
+        inputs.push((source.to_string(), validator_stake));
+    }
+
+    let (mut desired_validator_stake, conflicts) = desired_stake::merge(inputs);
+    for vs in desired_validator_stake.iter_mut() {
+        vs.asn = validator_classifications
+            .get(&vs.identity)
+            .and_then(|vc| vc.current_data_center.as_ref())
+            .map(|data_center| data_center.asn)
+            .filter(|asn| *asn != 0);
+        vs.score = validator_classifications
+            .get(&vs.identity)
+            .and_then(|vc| vc.reputation);
+    }
+    sort_by_seed(&mut desired_validator_stake, seed);
+    Ok((desired_validator_stake, conflicts))
+}
+
+/// Run a command that does not classify validators
+fn process_command(config: &Config, rpc_client: &RpcClient, command: Command) -> BoxResult<()> {
+    match command {
+        Command::Apply(_) => unreachable!(),
+        Command::RotateStaker {
+            stake_pool_address,
+            manager,
+            new_staker,
+        } => {
+            stake_pool::rotate_staker(
+                rpc_client,
+                config.transaction_executor()?.as_ref(),
+                config.dry_run,
+                &config.stake_pool_program_id,
+                &manager,
+                &stake_pool_address,
+                &new_staker,
+            )?;
+            // The next run expects the new staker rather than reporting it as unexpected
+            if !config.dry_run {
+                record_pool_state(
+                    config,
+                    rpc_client.get_epoch_info()?.epoch,
+                    pool_state::fetch(rpc_client, &stake_pool_address)?,
+                )?;
+            }
+        }
+        Command::Rollback {
+            stake_pool_address,
+            staker,
+        } => {
+            let epoch = rpc_client.get_epoch_info()?.epoch;
+            let run_epoch = Journal::latest(epoch, &config.cluster_db_path())
+                .ok_or("No journal of a previous run to roll back")?;
+            let mut journal = Journal::load(run_epoch, &config.cluster_db_path())?;
+            journal.ensure_not_rolled_back(run_epoch)?;
+
+            // Journals written before the stake movements were recorded only have them in the
+            // stake pool summary of the epoch
+            let movements = if journal.stake_movement_lamports.is_empty() {
+                EpochClassification::load(run_epoch, &config.cluster_db_path())?
+                    .into_current()
+                    .stake_pool_summary
+                    .map(|summary| summary.stake_movement_lamports)
+                    .unwrap_or_default()
+            } else {
+                journal.stake_movement_lamports.clone()
+            };
+            println!(
+                "Rolling back {} stake movements of the epoch {} run",
+                movements.len(),
+                run_epoch
+            );
+
+            let notes = stake_pool::rollback(
+                rpc_client,
+                config.transaction_executor()?.as_ref(),
+                config.dry_run,
+                &config.stake_pool_program_id,
+                &staker,
+                &stake_pool_address,
+                &movements,
+                run_epoch,
+                &RunStatus::default().run_id(),
+            )?;
+            for note in notes {
+                println!("{}", note);
+            }
+            if !config.dry_run {
+                journal.rolled_back_in = Some(epoch);
+                journal.save(run_epoch, &config.cluster_db_path())?;
+            }
+        }
+        Command::MigratePool {
+            source,
+            destination,
+            holder,
+            max_epoch_percentage,
+        } => pool_migration::migrate(
+            rpc_client,
+            config.transaction_executor()?.as_ref(),
+            &RunStatus::default().run_id(),
+            config.dry_run,
+            &source,
+            &destination,
+            &holder,
+            max_epoch_percentage,
+            &config.cluster_db_path(),
+        )?,
+        Command::ExportSnapshot {
+            stake_pool_address,
+            output_path,
+        } => {
+            let snapshot = snapshot::export(
+                rpc_client,
+                &config.stake_pool_program_id,
+                &stake_pool_address,
+            )?;
+            snapshot.save(&output_path)?;
+            info!(
+                "Wrote snapshot of {} at epoch {} to {}",
+                stake_pool_address,
+                snapshot.epoch,
+                output_path.display()
+            );
+        }
+        Command::ExportTokenHolders {
+            stake_pool_address,
+            output_path,
+            slot,
+        } => {
+            let snapshot = token_holders::export(rpc_client, &stake_pool_address, slot)?;
+            snapshot.save(&output_path)?;
+            info!(
+                "Wrote {} token holders of {} at slot {} to {}",
+                snapshot.holders.len(),
+                stake_pool_address,
+                snapshot.slot,
+                output_path.display()
+            );
+        }
+        Command::ExportWarehouse { first_epoch } => {
+            let warehouse_url = config
+                .warehouse_url
+                .as_ref()
+                .ok_or("export-warehouse requires --warehouse")?;
+            let sink = warehouse::new(warehouse_url)?;
+            let mut exported_epochs = 0;
+            let mut exported_rows = 0;
+            for epoch in EpochClassification::epochs(&config.cluster_db_path())
+                .into_iter()
+                .filter(|epoch| *epoch >= first_epoch.unwrap_or_default())
+            {
+                let epoch_classification =
+                    EpochClassification::load(epoch, &config.cluster_db_path())?.into_current();
+                exported_rows += warehouse::export(
+                    sink.as_ref(),
+                    &config.warehouse_table,
+                    &config.cluster.to_string(),
+                    epoch,
+                    &epoch_classification,
+                )?;
+                exported_epochs += 1;
+            }
+            println!(
+                "Exported {} rows of {} epochs to {}",
+                exported_rows, exported_epochs, config.warehouse_table
+            );
+        }
+        Command::DiffSnapshots {
+            before_path,
+            after_path,
+        } => {
+            let before = snapshot::StakePoolSnapshot::load(&before_path)?;
+            let after = snapshot::StakePoolSnapshot::load(&after_path)?;
+            let differences = snapshot::diff(&before, &after);
+            if differences.is_empty() {
+                println!("No differences");
+            }
+            for difference in differences {
+                println!("{}", difference);
+            }
+        }
+        Command::ServeEpochSummary {
+            listen_address,
+            epochs,
+        } => grafana::serve(listen_address, config.cluster_db_path(), epochs)?,
+        Command::StakeHistory { validator, json } => {
+            let history = stake_history::load(&validator, &config.cluster_db_path())?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&history)?);
+            } else if history.is_empty() {
+                println!("No stake history for {}", validator);
+            } else {
+                for entry in history {
+                    println!("{}", entry);
+                }
+            }
+        }
+        Command::Annotate {
+            epoch,
+            text,
+            author,
+        } => {
+            if let Some(text) = text {
+                annotations::annotate(epoch, &text, author, &config.cluster_db_path())?;
+            }
+            let epoch_annotations =
+                annotations::EpochAnnotations::load(epoch, &config.cluster_db_path())?;
+            if epoch_annotations.annotations.is_empty() {
+                println!("No annotations for epoch {}", epoch);
+            }
+            for annotation in epoch_annotations.lines() {
+                println!("{}", annotation);
+            }
+        }
+        Command::VerifyReport {
+            report_path,
+            signer,
+        } => {
+            let report = fs::read_to_string(&report_path)?;
+            let report_signer = attestation::verify(&report)?;
+            if let Some(signer) = signer {
+                if report_signer != signer {
+                    return Err(
+                        format!("Report was signed by {}, not {}", report_signer, signer).into(),
+                    );
+                }
+            }
+            println!("{} was signed by {}", report_path.display(), report_signer);
+        }
+        Command::Preview => preview_classification(rpc_client, config)?,
+        Command::ValidateApplication { application_path } => {
+            validate_application(rpc_client, config, &application_path)?
+        }
+        Command::Diff(mut stake_pool) => diff_allocation(rpc_client, config, stake_pool.as_mut())?,
+        Command::Reproduce { epoch } => {
+            let journal = Journal::load(epoch, &config.cluster_db_path())?;
+            let source_validator_stake =
+                journal.source_validator_stake.as_ref().ok_or_else(|| {
+                    format!(
+                        "The journal of epoch {} predates the recording of the desired stake \
+                         sources, the run cannot be reproduced",
+                        epoch
+                    )
+                })?;
+            let inputs =
+                ClassifierInputs::load(epoch, &config.cluster_db_path()).map_err(|err| {
+                    format!(
+                        "Unable to load the classifier inputs of epoch {}: {}",
+                        epoch, err
+                    )
+                })?;
+            let previous_epoch_classification =
+                EpochClassification::load_previous(epoch, &config.cluster_db_path())?
+                    .map(|p| p.1)
+                    .unwrap_or_default()
+                    .into_current();
+            let validator_classifications = classify_run(
+                config,
+                epoch,
+                &inputs,
+                previous_epoch_classification
+                    .validator_classifications
+                    .as_ref(),
+            )?
+            .validator_classifications
+            .ok_or_else(|| format!("Classification of epoch {} is paused", epoch))?;
+
+            let sources = journal
+                .desired_stake_sources
+                .iter()
+                .map(|source| source.parse())
+                .collect::<Result<Vec<_>, _>>()?;
+            let (mut desired_validator_stake, _conflicts) = desired_validator_stake(
+                &sources,
+                source_validator_stake,
+                &validator_classifications,
+                journal.bonus_streak,
+                journal.seed,
+            )?;
+            if journal.deploy_idle_reserve {
+                idle_reserve::promote_baseline(&mut desired_validator_stake);
+            }
+            if desired_validator_stake != journal.desired_validator_stake {
+                return Err(format!(
+                    "Epoch {} did not reproduce: the journal does not match the stake pool \
+                     inputs derived from the classifier inputs",
+                    epoch
+                )
+                .into());
+            }
+            println!(
+                "Epoch {} reproduced with seed {}: {} validators",
+                epoch,
+                journal.seed,
+                desired_validator_stake.len()
+            );
+        }
+        Command::Verify { epoch, path } => {
+            let inputs = ClassifierInputs::load(epoch, &path).map_err(|err| {
+                format!(
+                    "Unable to load the classifier inputs of epoch {}: {}",
+                    epoch, err
+                )
+            })?;
+            let published = EpochClassification::load(epoch, &path)?.into_current();
+            let previous_epoch_classification = EpochClassification::load_previous(epoch, &path)?
+                .map(|p| p.1)
+                .unwrap_or_default()
+                .into_current();
+
+            let derived = classify(
+                config,
+                epoch,
+                &inputs,
+                previous_epoch_classification
+                    .validator_classifications
+                    .as_ref(),
+            )?;
+
+            let mismatches = classification_mismatches(&published, &derived);
+            for mismatch in &mismatches {
+                println!("Mismatch: {}", mismatch);
+            }
+            if !mismatches.is_empty() {
+                return Err(format!(
+                    "Epoch {} did not verify: {} mismatches",
+                    epoch,
+                    mismatches.len()
+                )
+                .into());
+            }
+            println!(
+                "Epoch {} verified: {} validators",
+                epoch,
+                published
+                    .validator_classifications
+                    .map(|vcs| vcs.len())
+                    .unwrap_or_default()
+            );
+        }
+        Command::ExportFoundationList { epoch, output_path } => {
+            let validator_classifications =
+                EpochClassification::load(epoch, &config.cluster_db_path())?
+                    .into_current()
+                    .validator_classifications
+                    .ok_or_else(|| format!("Epoch {} has no validator classifications", epoch))?;
+            let validators = foundation_list::from_classifications(&validator_classifications);
+            foundation_list::save(&output_path, &validators)?;
+            println!(
+                "Wrote {} validators of epoch {} to {}",
+                validators.len(),
+                epoch,
+                output_path.display()
+            );
+        }
+        Command::SignApproval {
+            stake_pool_address,
+            epoch,
+            approver,
+        } => {
+            println!(
+                "{}",
+                confirmation::sign_approval(&approver, &stake_pool_address, epoch)
+            );
+        }
+        Command::RefreshIpToAsnDb { path, url } => {
+            let ranges = ip_to_asn::refresh(&url, &path)?;
+            println!("Wrote {} ASN ranges to {}", ranges, path.display());
+        }
+        Command::SendValidatorNotices {
+            contact_registry_path,
+            discord_webhook_url,
+        } => send_validator_notices(
+            rpc_client,
+            &config,
+            &contact_registry_path,
+            discord_webhook_url,
+        )?,
+    }
+    Ok(())
+}
+
+/// Classify the validators for the next epoch from the data of the epoch in progress, marked as
+/// provisional, and publish it next to the classification of the current epoch
+fn preview_classification(rpc_client: &RpcClient, config: &Config) -> BoxResult<()> {
+    let epoch_info = rpc_client.get_epoch_info()?;
+    let epoch = epoch_info.epoch;
+    let next_epoch = epoch + 1;
+
+    // The participants of the pool are those the current epoch was classified with
+    let current_inputs = ClassifierInputs::load(epoch, &config.cluster_db_path())
+        .map_err(|err| format!("Epoch {} was not classified: {}", epoch, err))?;
+    let current_classification =
+        EpochClassification::load(epoch, &config.cluster_db_path())?.into_current();
+
+    let inputs = get_classifier_inputs(
+        rpc_client,
+        config,
+        next_epoch,
+        &current_inputs.validator_list,
+        &current_inputs.identity_to_participant,
+    )?;
+    let mut preview = classify(
+        config,
+        next_epoch,
+        &inputs,
+        current_classification.validator_classifications.as_ref(),
+    )?;
+    preview.notes.insert(
+        0,
+        format!(
+            "PROVISIONAL: preview of the epoch {} classification from the data of epoch {} \
+             through slot {}. The classification made at the start of epoch {} is final",
+            next_epoch, epoch, epoch_info.absolute_slot, next_epoch
+        ),
+    );
+
+    let mut changes = vec![];
+    if let (Some(current), Some(previewed)) = (
+        &current_classification.validator_classifications,
+        &preview.validator_classifications,
+    ) {
+        let mut previewed = previewed.values().collect::<Vec<_>>();
+        previewed.sort_by_key(|vc| vc.identity);
+        for vc in previewed {
+            let current_stake_state = current
+                .get(&vc.identity)
+                .map(|current| current.stake_state)
+                .unwrap_or_default();
+            if vc.stake_state != current_stake_state {
+                changes.push(format!(
+                    "{}: {:?} -> {:?}, {}",
+                    vc.identity, current_stake_state, vc.stake_state, vc.stake_state_reason
+                ));
+            }
+        }
+    }
+    for note in &preview.notes {
+        println!("{}", note);
+    }
+    println!(
+        "{} validators would change stake state in epoch {}",
+        changes.len(),
+        next_epoch
+    );
+    for change in &changes {
+        println!("  {}", change);
+    }
+
+    EpochClassification::new(preview).save_preview(next_epoch, &config.cluster_db_path())?;
+    let preview_path =
+        EpochClassification::preview_file_name(next_epoch, &config.cluster_db_path());
+    println!("Wrote {}", preview_path.display());
+    if let Some(ref artifact_url) = config.artifact_url {
+        // Published with the artifacts of the current epoch, without expiring any
+        artifact::publish(
+            artifact::new(artifact_url)?.as_ref(),
+            epoch,
+            &[preview_path.as_path()],
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the validators whose stake in the pool differs from the stake the current policy would
+/// give them, classifying the validators anew from the classifier inputs saved for the current
+/// epoch. Nothing is saved and no transaction is sent
+fn diff_allocation(
+    rpc_client: &RpcClient,
+    config: &Config,
+    stake_pool: &mut dyn GenericStakePool,
+) -> BoxResult<()> {
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    let inputs = ClassifierInputs::load(epoch, &config.cluster_db_path())
+        .map_err(|err| format!("Epoch {} was not classified: {}", epoch, err))?;
+    let previous_epoch_classification =
+        EpochClassification::load_previous(epoch, &config.cluster_db_path())?
+            .map(|p| p.1)
+            .unwrap_or_default()
+            .into_current();
+    let validator_classifications = classify(
+        config,
+        epoch,
+        &inputs,
+        previous_epoch_classification
+            .validator_classifications
+            .as_ref(),
+    )?
+    .validator_classifications
+    .ok_or_else(|| format!("Classification of epoch {} is paused", epoch))?;
+
+    let (desired_validator_stake, _conflicts) = desired_validator_stake(
+        &config.desired_stake_sources,
+        &read_desired_stake_sources(&config.desired_stake_sources)?,
+        &validator_classifications,
+        config.bonus_streak,
+        config.seed.unwrap_or(epoch),
+    )?;
+    let allocation = stake_pool.allocation(rpc_client, &desired_validator_stake)?;
+
+    let mut changes = allocation
+        .iter()
+        .filter(|(_, stake_allocation)| stake_allocation.change_lamports() != 0)
+        .collect::<Vec<_>>();
+    changes.sort_by(|a, b| {
+        b.1.change_lamports()
+            .abs()
+            .cmp(&a.1.change_lamports().abs())
+            .then(a.0.cmp(b.0))
+    });
+    println!(
+        "{} of {} validators would change stake in epoch {}",
+        changes.len(),
+        allocation.len(),
+        epoch
+    );
+    for (vote_address, stake_allocation) in &changes {
+        let change_lamports = stake_allocation.change_lamports();
+        println!(
+            "  {}: {} -> {} ({}{})",
+            vote_address,
+            Sol(stake_allocation.current_lamports),
+            Sol(stake_allocation.desired_lamports),
+            if change_lamports < 0 { "-" } else { "+" },
+            Sol(change_lamports.abs() as u64)
+        );
+    }
+
+    let (increase_lamports, decrease_lamports) =
+        changes
+            .iter()
+            .fold((0, 0), |(increase, decrease), (_, stake_allocation)| {
+                let change_lamports = stake_allocation.change_lamports();
+                if change_lamports > 0 {
+                    (increase + change_lamports as u64, decrease)
+                } else {
+                    (increase, decrease + change_lamports.abs() as u64)
+                }
+            });
+    println!(
+        "Total: {} of increases, {} of decreases",
+        Sol(increase_lamports),
+        Sol(decrease_lamports)
+    );
+    Ok(())
+}
+
+/// Check the application in `application_path` against the chain, and display the desired stake
+/// entry of the validator if it passes
+fn validate_application(
+    rpc_client: &RpcClient,
+    config: &Config,
+    application_path: &Path,
+) -> BoxResult<()> {
+    let application = ValidatorApplication::load(application_path)?;
+    let (identity, vote_address) = application.addresses()?;
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+
+    let rent: Rent = from_account(&rpc_client.get_account(&sysvar::rent::id())?)
+        .ok_or("Failed to deserialize rent")?;
+    let vote_account_health = rpc_client
+        .get_account_with_commitment(&vote_address, rpc_client.commitment())?
+        .value
+        .map(|vote_account| VoteAccountHealth::new(&vote_account, &rent));
+
+    let vote_accounts = rpc_client.get_vote_accounts()?;
+    let is_listed = |vote_accounts: &[RpcVoteAccountInfo]| {
+        vote_accounts
+            .iter()
+            .any(|vote_account| vote_account.vote_pubkey == application.vote_address)
+    };
+    let delinquent = if is_listed(&vote_accounts.current) {
+        Some(false)
+    } else if is_listed(&vote_accounts.delinquent) {
+        Some(true)
+    } else {
+        None
+    };
+
+    let data_center = get_data_centers(rpc_client, config)
+        .map(|data_centers| data_centers.by_identity.get(&identity).cloned())
+        .unwrap_or_else(|err| {
+            warn!("Unable to resolve the data centers: {}", err);
+            None
+        });
+
+    let self_stake_lamports = match vote_account_health
+        .as_ref()
+        .and_then(|vote_account_health| vote_account_health.authorized_withdrawer())
+    {
+        Some(authorized_withdrawer) => get_self_stake_by_vote_account(
+            rpc_client,
+            epoch,
+            &vec![(vote_address, authorized_withdrawer)]
+                .into_iter()
+                .collect::<HashMap<_, _>>(),
+        )?
+        .get(&vote_address)
+        .copied()
+        .unwrap_or_default(),
+        None => 0,
+    };
+
+    let checks = validator_application::validate(
+        &application,
+        &OnChainFacts {
+            vote_account_health,
+            delinquent,
+            data_center,
+            self_stake_lamports,
+        },
+        config.min_self_stake_lamports,
+    );
+    for check in &checks {
+        println!("{}", check);
+    }
+    let failed = checks
+        .iter()
+        .filter(|check| check.outcome == CheckOutcome::Fail)
+        .count();
+    if failed > 0 {
+        return Err(format!(
+            "{} of {} checks of {} failed",
+            failed,
+            checks.len(),
+            application_path.display()
+        )
+        .into());
+    }
+
+    println!(
+        "Desired stake entry of {}:\n{}",
+        application.contact.name,
+        serde_json::json!({
+            "identity": identity.to_string(),
+            "vote_address": vote_address.to_string(),
+            "stake_state": ValidatorStakeState::Baseline,
+        })
+    );
+    Ok(())
+}
+
+/// Send the advance notices projected from the current state of the validators staked by the
+/// latest classification. Notices already sent during the epoch are not sent again
+fn send_validator_notices(
+    rpc_client: &RpcClient,
+    config: &Config,
+    contact_registry_path: &Path,
+    discord_webhook_url: Option<String>,
+) -> BoxResult<()> {
+    let registry = ContactRegistry::load(contact_registry_path)?;
+    let (epoch, epoch_classification) =
+        EpochClassification::load_latest(&config.cluster_db_path())?
+            .ok_or("No epoch classification to project from")?;
+    let classifications = epoch_classification
+        .into_current()
+        .validator_classifications
+        .unwrap_or_default();
+
+    let vote_accounts = rpc_client.get_vote_accounts()?;
+    let release_versions = rpc_client
+        .get_cluster_nodes()?
+        .into_iter()
+        .filter_map(|rpc_contact_info| {
+            Some((
+                Pubkey::from_str(&rpc_contact_info.pubkey).ok()?,
+                semver::Version::parse(&rpc_contact_info.version?).ok()?,
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+    let current = vote_accounts
+        .current
+        .iter()
+        .chain(vote_accounts.delinquent.iter())
+        .filter_map(|vote_account| {
+            let identity = Pubkey::from_str(&vote_account.node_pubkey).ok()?;
+            Some((
+                identity,
+                ValidatorState {
+                    commission: vote_account.commission,
+                    release_version: release_versions.get(&identity).cloned(),
+                },
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let notices = validator_notices::project(
+        epoch,
+        &classifications,
+        &current,
+        &NoticeThresholds {
+            max_commission: config.max_commission,
+            min_release_version: config.min_release_version.clone(),
+        },
+    );
+
+    let mailer = match (&config.smtp_relay, &config.email_sender) {
+        (Some(smtp_relay), Some(email_sender)) => {
+            Some(email_report::Mailer::new(smtp_relay, email_sender, &[])?)
+        }
+        _ => None,
+    };
+    let sender = NoticeSender::new(mailer, discord_webhook_url)?;
+    let mut sent_notices = SentNotices::load(epoch, &config.cluster_db_path())?;
+    for notice in notices {
+        if sent_notices.messages.contains(&notice.message) {
+            continue;
+        }
+        let contact = match registry.validators.get(&notice.identity) {
+            Some(contact) => contact,
+            None => {
+                info!("No contact for {}: {}", notice.identity, notice.message);
+                continue;
+            }
+        };
+        if config.dry_run {
+            println!("Would notify {:?}: {}", contact, notice.message);
+            continue;
+        }
+        match sender.send(contact, &notice) {
+            Ok(true) => {
+                println!("Notified {}: {}", notice.identity, notice.message);
+                sent_notices.messages.insert(notice.message);
+            }
+            Ok(false) => {}
+            Err(err) => warn!("Failed to notify {}: {}", notice.identity, err),
+        }
+    }
+    if !config.dry_run {
+        sent_notices.save(epoch, &config.cluster_db_path())?;
+    }
+    Ok(())
+}
+
+/// Differences between a `published` epoch classification and the classification `derived` from
+/// its classifier inputs. Notes and validator details added after the classification, by the
+/// stake pool, are not compared
+fn classification_mismatches(
+    published: &EpochClassificationV1,
+    derived: &EpochClassificationV1,
+) -> Vec<String> {
+    let mut mismatches = vec![];
+
+    if !published.notes.starts_with(&derived.notes) {
+        for (i, note) in derived.notes.iter().enumerate() {
+            if published.notes.get(i) != Some(note) {
+                mismatches.push(format!(
+                    "published note {:?} does not match the derived note {:?}",
+                    published.notes.get(i).cloned().unwrap_or_default(),
+                    note
+                ));
+            }
+        }
+    }
+
+    match (
+        &published.validator_classifications,
+        &derived.validator_classifications,
+    ) {
+        (None, None) => {}
+        (Some(_), None) => {
+            mismatches.push("validators were classified, but stake adjustments are skipped".into())
+        }
+        (None, Some(_)) => {
+            mismatches.push("stake adjustments were skipped, but validators are classified".into())
+        }
+        (Some(published), Some(derived)) => {
+            let mut identities = published
+                .keys()
+                .chain(derived.keys())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+            identities.sort();
+            for identity in identities {
+                match (published.get(identity), derived.get(identity)) {
+                    (Some(published), Some(derived)) => {
+                        if published.stake_state != derived.stake_state
+                            || published.stake_state_reason != derived.stake_state_reason
+                        {
+                            mismatches.push(format!(
+                                "{}: published {:?} stake ({}), derived {:?} stake ({})",
+                                identity,
+                                published.stake_state,
+                                published.stake_state_reason,
+                                derived.stake_state,
+                                derived.stake_state_reason
+                            ));
+                        }
+                    }
+                    (Some(_), None) => {
+                        mismatches.push(format!("{}: published but not derived", identity))
+                    }
+                    (None, Some(_)) => {
+                        mismatches.push(format!("{}: derived but not published", identity))
+                    }
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+    }
 
-        Some(validator_classifications)
-    };
+    mismatches
+}
 
-    Ok(EpochClassificationV1 {
-        data_center_info: data_centers.info,
-        validator_classifications,
-        notes,
-    })
+fn exit_with_error(err: Box<dyn error::Error>) -> ! {
+    error!("{}", err);
+    process::exit(ExitCode::of_error(err.as_ref()) as i32)
 }
 
-fn main() -> BoxResult<()> {
+fn main() {
     solana_logger::setup_with_default("solana=info");
 
-    let (config, rpc_client, mut stake_pool) = get_config()?;
+    let (config, rpc_client, command) = get_config().unwrap_or_else(|err| exit_with_error(err));
+    let mut stake_pool = match command {
+        Command::Apply(stake_pool) => stake_pool,
+        command => {
+            if let Err(err) = process_command(&config, &rpc_client, command) {
+                exit_with_error(err);
+            }
+            return;
+        }
+    };
+
+    let watchdog = config
+        .watchdog_url
+        .as_ref()
+        .map(|url| Watchdog::new(url).unwrap_or_else(|err| exit_with_error(err)));
+    if let Some(watchdog) = &watchdog {
+        watchdog.ping(WatchdogEvent::Start, "");
+    }
+    if let Some(api_listen_address) = config.api_listen_address {
+        api::spawn(
+            api_listen_address,
+            config.cluster_db_path(),
+            config.api_token.clone(),
+            config.api_rate_limit,
+        )
+        .unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    loop {
+        let mut run_status = RunStatus::default();
+        let result = run(&config, &rpc_client, stake_pool.as_mut(), &mut run_status);
+        if let Err(ref err) = result {
+            error!("{}", err);
+        }
+        let exit_code = run_status.finish(result);
+        info!("Run finished: {:?}", exit_code);
+        if let Err(err) = run_status.save(&config.cluster_db_path()) {
+            error!("Unable to save the run status: {}", err);
+        }
+        if let Some(watchdog) = &watchdog {
+            if exit_code == ExitCode::Success {
+                watchdog.ping(WatchdogEvent::Success, "");
+            } else {
+                let message = run_status
+                    .error
+                    .clone()
+                    .unwrap_or_else(|| format!("{:?}", exit_code));
+                watchdog.ping(WatchdogEvent::Failure, &message);
+            }
+        }
+
+        match config.daemon_interval {
+            Some(daemon_interval) => {
+                info!("Next run in {:?}", daemon_interval);
+                thread::sleep(daemon_interval);
+            }
+            None => process::exit(exit_code as i32),
+        }
+    }
+}
+
+/// Record the current commission of every vote account in the commission samples of `epoch`
+fn sample_commission(rpc_client: &RpcClient, config: &Config, epoch: Epoch) -> BoxResult<()> {
+    let slot = rpc_client.get_slot()?;
+    let vote_accounts =
+        vote_account_cache::get_vote_accounts(rpc_client, config.vote_account_cache.as_deref())?;
+    let mut commission_samples = CommissionSamples::load(epoch, &config.cluster_db_path())?;
+    commission_samples.record(
+        slot,
+        vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter_map(|vote_account| {
+                Some((
+                    Pubkey::from_str(&vote_account.vote_pubkey).ok()?,
+                    vote_account.commission,
+                ))
+            }),
+    );
+    commission_samples.save(epoch, &config.cluster_db_path())
+}
 
+/// Classify the validators of the current epoch and apply the resulting desired stake to the
+/// stake pool, recording the outcome of each phase in `run_status`
+fn run(
+    config: &Config,
+    rpc_client: &RpcClient,
+    stake_pool: &mut dyn GenericStakePool,
+    run_status: &mut RunStatus,
+) -> BoxResult<()> {
+    run_status.start_phase("participants");
     info!("Loading participants...");
     let participants = get_participants_with_state(
         &RpcClient::new("https://api.mainnet-beta.solana.com".to_string()),
@@ -1421,7 +5391,13 @@ fn main() -> BoxResult<()> {
         .unzip();
 
     info!("{} participants loaded", participants.len());
-    assert!(participants.len() > 450); // Hard coded sanity check...
+    if participants.len() <= 450 {
+        // Hard coded sanity check...
+        return Err(guardrail(format!(
+            "Only {} participants loaded",
+            participants.len()
+        )));
+    }
 
     let (validator_list, identity_to_participant) = match config.cluster {
         Cluster::MainnetBeta => (
@@ -1441,23 +5417,37 @@ fn main() -> BoxResult<()> {
     };
 
     if !config.dry_run && notifier.is_empty() {
-        return Err("A notifier must be active with --confirm".into());
+        return Err(guardrail("A notifier must be active with --confirm"));
     }
 
     let epoch = rpc_client.get_epoch_info()?.epoch;
     info!("Epoch: {:?}", epoch);
+    run_status.epoch = Some(epoch);
     if epoch == 0 {
         return Ok(());
     }
 
     info!("Data directory: {}", config.cluster_db_path().display());
 
-    let previous_epoch_classification =
-        EpochClassification::load_previous(epoch, &config.cluster_db_path())?
-            .map(|p| p.1)
-            .unwrap_or_default()
-            .into_current();
+    if config.commission_grace {
+        if let Err(err) = sample_commission(rpc_client, config, epoch) {
+            warn!(
+                "Unable to sample the commission of the vote accounts: {}",
+                err
+            );
+        }
+    }
 
+    run_status.start_phase("classification");
+    let previous_epoch_classification =
+        EpochClassification::load_previous(epoch, &config.cluster_db_path())?;
+    let previous_epoch = previous_epoch_classification.as_ref().map(|p| p.0);
+    let previous_epoch_classification = previous_epoch_classification
+        .map(|p| p.1)
+        .unwrap_or_default()
+        .into_current();
+
+    let mut classifier_inputs = None;
     let (mut epoch_classification, first_time) =
         if EpochClassification::exists(epoch, &config.cluster_db_path()) {
             info!("Classification for {} already exists", epoch);
@@ -1466,25 +5456,78 @@ fn main() -> BoxResult<()> {
                 false,
             )
         } else {
-            (
-                classify(
-                    &rpc_client,
-                    &config,
-                    epoch,
-                    &validator_list,
-                    &identity_to_participant,
-                    previous_epoch_classification
-                        .validator_classifications
-                        .as_ref(),
-                )?,
-                true,
-            )
+            let inputs = get_classifier_inputs(
+                &rpc_client,
+                &config,
+                epoch,
+                &validator_list,
+                &identity_to_participant,
+            )?;
+            let epoch_classification = classify_run(
+                &config,
+                epoch,
+                &inputs,
+                previous_epoch_classification
+                    .validator_classifications
+                    .as_ref(),
+            )?;
+            classifier_inputs = Some(inputs);
+            (epoch_classification, true)
         };
 
+    if first_time {
+        let mut effective_config = config.effective_config();
+        effective_config.extend(stake_pool.effective_config());
+        let config_hash = config_drift::config_hash(&effective_config);
+        epoch_classification
+            .notes
+            .push(format!("Configuration hash: {}", config_hash));
+
+        if let (Some(previous_epoch), Some(previous_effective_config)) = (
+            previous_epoch,
+            previous_epoch_classification.effective_config.as_ref(),
+        ) {
+            let config_changes =
+                config_drift::config_changes(previous_effective_config, &effective_config);
+            if !config_changes.is_empty() {
+                let note = format!(
+                    "Configuration changed since epoch {}: {}",
+                    previous_epoch,
+                    config_changes.join("; ")
+                );
+                warn!("{}", note);
+                epoch_classification.notes.insert(0, note);
+            }
+        }
+        epoch_classification.effective_config = Some(effective_config);
+        epoch_classification.config_hash = Some(config_hash);
+    }
+
     let mut notifications = epoch_classification.notes.clone();
 
-    let success = if let Some(ref validator_classifications) =
-        epoch_classification.validator_classifications
+    // Sent right away rather than with the notifications of the epoch, which only the first run
+    // sends
+    if let Some(pool_state) = stake_pool.pool_state(&rpc_client)? {
+        if let Some(journal_epoch) = Journal::latest(epoch, &config.cluster_db_path()) {
+            if let Some(expected_pool_state) =
+                Journal::load(journal_epoch, &config.cluster_db_path())?.pool_state
+            {
+                for change in pool_state::unexpected_changes(&expected_pool_state, &pool_state) {
+                    let note = format!(
+                        "Unexpected stake pool change since the run of epoch {}: {}",
+                        journal_epoch, change
+                    );
+                    warn!("{}", note);
+                    notifier.send(&note);
+                    epoch_classification.notes.push(note);
+                }
+            }
+        }
+    }
+
+    let mut journal = None;
+    let mut incremental_rerun = false;
+    if let Some(ref mut validator_classifications) = epoch_classification.validator_classifications
     {
         let previous_validator_classifications = previous_epoch_classification
             .validator_classifications
@@ -1492,76 +5535,347 @@ fn main() -> BoxResult<()> {
 
         let mut validator_stake_change_notes = vec![];
         let mut validator_notes = vec![];
-        let desired_validator_stake: Vec<_> = validator_classifications
-            .values()
-            .map(|vc| {
-                validator_notes.extend(
-                    vc.notes
-                        .iter()
-                        .map(|note| format!("Note: {}: {}", vc.identity, note)),
-                );
+        for vc in validator_classifications.values() {
+            validator_notes.extend(
+                vc.notes
+                    .iter()
+                    .map(|note| format!("Note: {}: {}", vc.identity, note)),
+            );
 
-                let stake_state_changed = match previous_validator_classifications
-                    .get(&vc.identity)
-                    .map(|prev_vc| prev_vc.stake_state)
-                {
-                    Some(previous_stake_state) => previous_stake_state != vc.stake_state,
-                    None => true,
-                };
+            let stake_state_changed = match previous_validator_classifications
+                .get(&vc.identity)
+                .map(|prev_vc| prev_vc.stake_state)
+            {
+                Some(previous_stake_state) => previous_stake_state != vc.stake_state,
+                None => true,
+            };
 
-                if stake_state_changed {
-                    validator_stake_change_notes.push(format!(
-                        "* {:?} stake: {}: {}",
-                        vc.stake_state, vc.identity, vc.stake_state_reason
-                    ));
-                }
+            if stake_state_changed {
+                validator_stake_change_notes.push(format!(
+                    "* {:?} stake: {}: {}",
+                    vc.stake_state, vc.identity, vc.stake_state_reason
+                ));
+            }
+        }
 
-                ValidatorStake {
-                    identity: vc.identity,
-                    vote_address: vc.vote_address,
-                    stake_state: vc.stake_state,
-                }
+        let seed = config.seed.unwrap_or(epoch);
+        let source_validator_stake = read_desired_stake_sources(&config.desired_stake_sources)?;
+        let (mut desired_validator_stake, desired_stake_conflicts) = desired_validator_stake(
+            &config.desired_stake_sources,
+            &source_validator_stake,
+            validator_classifications,
+            config.bonus_streak,
+            seed,
+        )?;
+        let deploy_idle_reserve = config
+            .idle_reserve
+            .map(|idle_reserve| {
+                idle_reserve.deploy(previous_epoch_classification.stake_pool_summary.as_ref())
             })
-            .collect();
-
+            .unwrap_or(false);
+        if deploy_idle_reserve {
+            let promoted = idle_reserve::promote_baseline(&mut desired_validator_stake);
+            epoch_classification.notes.push(format!(
+                "Promoted {} Baseline validators to Bonus to deploy the idle reserve",
+                promoted
+            ));
+        }
+        epoch_classification
+            .notes
+            .push(format!("Validator ordering seed: {}", seed));
+        if config.desired_stake_sources != [DesiredStakeSource::Classifier] {
+            epoch_classification.notes.push(format!(
+                "Desired stake sources: {}",
+                config
+                    .desired_stake_sources
+                    .iter()
+                    .map(|source| source.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        for conflict in desired_stake_conflicts {
+            warn!("Desired stake conflict: {}", conflict);
+            epoch_classification
+                .notes
+                .push(format!("Desired stake conflict: {}", conflict));
+        }
+        journal = Some(Journal {
+            seed,
+            bonus_streak: config.bonus_streak,
+            deploy_idle_reserve,
+            desired_stake_sources: config
+                .desired_stake_sources
+                .iter()
+                .map(|source| source.to_string())
+                .collect(),
+            source_validator_stake: Some(source_validator_stake),
+            desired_validator_stake: desired_validator_stake.clone(),
+            busy_carryover_lamports: HashMap::new(),
+            stake_movement_lamports: HashMap::new(),
+            pool_state: None,
+            rolled_back_in: None,
+        });
+
+        run_status.start_phase("stake pool");
+        stake_pool.set_run_id(&run_status.run_id());
+        if let Some(vote_account_cache) = &config.vote_account_cache {
+            stake_pool.set_vote_account_cache(vote_account_cache.clone());
+        }
+        stake_pool.set_previous_summary(previous_epoch_classification.stake_pool_summary.clone());
+        if let Some(previous_epoch) = previous_epoch
+            .filter(|previous_epoch| Journal::exists(*previous_epoch, &config.cluster_db_path()))
+        {
+            stake_pool.set_busy_carryover(
+                Journal::load(previous_epoch, &config.cluster_db_path())?.busy_carryover_lamports,
+            );
+        }
+        // A rerun of an epoch whose earlier runs distributed no stake plans the stake of the whole
+        // pool
+        let latest_run = if config.incremental && !first_time && !config.dry_run {
+            epoch_classification.stake_pool_summary.clone()
+        } else {
+            None
+        };
+        if let Some(latest_run) = &latest_run {
+            stake_pool.set_incremental(latest_run.clone());
+            incremental_rerun = true;
+        }
         let (stake_pool_notes, success) =
             stake_pool.apply(&rpc_client, config.dry_run, &desired_validator_stake)?;
+        if !success {
+            run_status.partial_failure("One or more stake pool transactions failed");
+        }
         notifications.extend(stake_pool_notes.clone());
         epoch_classification.notes.extend(stake_pool_notes);
 
+        let mut stake_pool_summary = match (latest_run, stake_pool.summary()) {
+            (Some(latest_run), Some(summary)) => Some(latest_run.merge_rerun(summary)),
+            (latest_run, summary) => summary.or(latest_run),
+        };
+        if let Some(ref mut stake_pool_summary) = stake_pool_summary {
+            if let Some(ref mut journal) = journal {
+                journal.busy_carryover_lamports =
+                    stake_pool_summary.busy_carryover_lamports.clone();
+                journal.stake_movement_lamports =
+                    stake_pool_summary.stake_movement_lamports.clone();
+            }
+            stake_pool_summary.cumulative_excess_rewards_reclaimed_lamports =
+                previous_epoch_classification
+                    .stake_pool_summary
+                    .as_ref()
+                    .map(|s| s.cumulative_excess_rewards_reclaimed_lamports)
+                    .unwrap_or_default()
+                    + stake_pool_summary.excess_rewards_reclaimed_lamports;
+            if stake_pool_summary.cumulative_excess_rewards_reclaimed_lamports > 0 {
+                epoch_classification.notes.push(format!(
+                    "Excess rewards reclaimed by staker: {} this epoch, {} in total",
+                    Sol(stake_pool_summary.excess_rewards_reclaimed_lamports),
+                    Sol(stake_pool_summary.cumulative_excess_rewards_reclaimed_lamports)
+                ));
+            }
+
+            let mut off_target_notes = vec![];
+            for vc in validator_classifications.values_mut() {
+                let off_target_epochs =
+                    match stake_pool_summary.off_target_lamports.get(&vc.vote_address) {
+                        Some(off_target_lamports) => {
+                            let off_target_epochs = previous_validator_classifications
+                                .get(&vc.identity)
+                                .and_then(|prev_vc| prev_vc.off_target_epochs)
+                                .unwrap_or_default()
+                                + 1;
+                            if off_target_epochs >= config.max_off_target_epochs {
+                                off_target_notes.push(format!(
+                                "{} has not converged to its desired stake for {} epochs, {} off",
+                                vc.identity,
+                                off_target_epochs,
+                                Sol(*off_target_lamports)
+                            ));
+                            }
+                            off_target_epochs
+                        }
+                        None => 0,
+                    };
+                vc.off_target_epochs = Some(off_target_epochs);
+                vc.projected_effective_stake = stake_pool_summary
+                    .projected_effective_stake_lamports
+                    .get(&vc.vote_address)
+                    .cloned();
+                if stake_pool_summary.slots_in_epoch > 0 {
+                    vc.time_weighted_stake = stake_pool_summary
+                        .validator_balance_lamports
+                        .get(&vc.vote_address)
+                        .map(|balance| {
+                            stake_projection::time_weighted_stake(
+                                *balance,
+                                stake_pool_summary
+                                    .stake_movement_lamports
+                                    .get(&vc.vote_address)
+                                    .copied()
+                                    .unwrap_or_default(),
+                                stake_pool_summary.movement_slot_index as f64
+                                    / stake_pool_summary.slots_in_epoch as f64,
+                            )
+                        });
+                }
+            }
+            off_target_notes.sort();
+            notifications.extend(off_target_notes.clone());
+            epoch_classification.notes.extend(off_target_notes);
+
+            if let Some(idle_reserve) = config.idle_reserve {
+                stake_pool_summary.idle_reserve_epochs = idle_reserve.idle_epochs(
+                    stake_pool_summary,
+                    previous_epoch_classification
+                        .stake_pool_summary
+                        .as_ref()
+                        .map(|s| s.idle_reserve_epochs)
+                        .unwrap_or_default(),
+                );
+                if let Some(note) = idle_reserve.note(stake_pool_summary) {
+                    warn!("{}", note);
+                    notifications.push(note.clone());
+                    epoch_classification.notes.push(note);
+                }
+            }
+        }
+        epoch_classification.stake_pool_summary = stake_pool_summary;
+        epoch_classification.timestamp =
+            Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+
         validator_notes.sort();
         notifications.extend(validator_notes);
 
         validator_stake_change_notes.sort();
         notifications.extend(validator_stake_change_notes);
+    }
 
-        success
-    } else {
-        true
-    };
+    // Recorded once the stake pool is updated, so that the next run only reports the changes made
+    // by others
+    if let Some(pool_state) = stake_pool.pool_state(&rpc_client)? {
+        match journal {
+            Some(ref mut journal) if first_time || incremental_rerun => {
+                journal.pool_state = Some(pool_state)
+            }
+            _ => record_pool_state(config, epoch, pool_state)?,
+        }
+    }
 
     if first_time {
+        run_status.start_phase("report");
+        let epoch_decisions =
+            if config.analytics_webhook_url.is_some() || config.smtp_relay.is_some() {
+                Some(analytics::EpochDecisions::new(
+                    &config.cluster.to_string(),
+                    epoch,
+                    &epoch_classification,
+                ))
+            } else {
+                None
+            };
+        let epoch_notes = epoch_classification.notes.clone();
+        let warehouse_rows = config
+            .warehouse_url
+            .as_ref()
+            .map(|_| warehouse::rows(&config.cluster.to_string(), epoch, &epoch_classification));
         EpochClassification::new(epoch_classification).save(epoch, &config.cluster_db_path())?;
-        generate_markdown(epoch, &config)?;
+        if let Some(ref journal) = journal {
+            journal.save(epoch, &config.cluster_db_path())?;
+        }
+        if let Some(ref classifier_inputs) = classifier_inputs {
+            classifier_inputs.save(epoch, &config.cluster_db_path())?;
+        }
+        let report_path = generate_markdown(epoch, &config)?;
 
         // Only notify the user if this is the first run for this epoch
+        run_status.start_phase("notifications");
         for notification in notifications {
             info!("notification: {}", notification);
             notifier.send(&notification);
         }
+
+        if let Some(ref artifact_url) = config.artifact_url {
+            run_status.start_phase("artifacts");
+            let epoch_classification_path =
+                EpochClassification::file_name(epoch, &config.cluster_db_path());
+            let journal_path = Journal::file_name(epoch, &config.cluster_db_path());
+            let classifier_inputs_path =
+                ClassifierInputs::file_name(epoch, &config.cluster_db_path());
+            let mut artifacts = vec![epoch_classification_path.as_path()];
+            if journal.is_some() {
+                artifacts.push(journal_path.as_path());
+            }
+            if classifier_inputs.is_some() {
+                artifacts.push(classifier_inputs_path.as_path());
+            }
+            artifacts.extend(report_path.as_deref());
+            artifact::publish(
+                artifact::new(artifact_url)?.as_ref(),
+                epoch,
+                &artifacts,
+                config.artifact_retention_epochs,
+            )?;
+        }
+
+        if let (Some(analytics_webhook_url), Some(epoch_decisions)) =
+            (&config.analytics_webhook_url, &epoch_decisions)
+        {
+            run_status.start_phase("analytics");
+            analytics::Webhook::new(analytics_webhook_url)?.post(epoch_decisions)?;
+        }
+
+        if let (Some(warehouse_url), Some(warehouse_rows)) =
+            (&config.warehouse_url, &warehouse_rows)
+        {
+            run_status.start_phase("warehouse");
+            warehouse::insert_rows(
+                warehouse::new(warehouse_url)?.as_ref(),
+                &config.warehouse_table,
+                warehouse_rows,
+            )?;
+        }
+
+        if let (Some(smtp_relay), Some(email_sender), Some(epoch_decisions)) =
+            (&config.smtp_relay, &config.email_sender, &epoch_decisions)
+        {
+            run_status.start_phase("email");
+            email_report::Mailer::new(smtp_relay, email_sender, &config.email_recipients)?
+                .send(&epoch_notes, epoch_decisions)?;
+        }
+    } else if incremental_rerun {
+        // The stake movements of the rerun are recorded with those of the earlier runs, so that
+        // the next epoch and a rollback account for them
+        run_status.start_phase("report");
+        EpochClassification::new(epoch_classification).save(epoch, &config.cluster_db_path())?;
+        if let Some(ref journal) = journal {
+            journal.save(epoch, &config.cluster_db_path())?;
+        }
+        generate_markdown(epoch, &config)?;
     }
 
-    if success {
-        Ok(())
-    } else {
-        Err("something failed".into())
+    Ok(())
+}
+
+// Record `pool_state` as the state of the stake pool after the latest run, up to `epoch`
+fn record_pool_state(
+    config: &Config,
+    epoch: Epoch,
+    pool_state: pool_state::PoolState,
+) -> BoxResult<()> {
+    if let Some(journal_epoch) = Journal::latest(epoch, &config.cluster_db_path()) {
+        let mut journal = Journal::load(journal_epoch, &config.cluster_db_path())?;
+        journal.pool_state = Some(pool_state);
+        journal.save(journal_epoch, &config.cluster_db_path())?;
     }
+    Ok(())
 }
 
-fn generate_markdown(epoch: Epoch, config: &Config) -> BoxResult<()> {
+// Returns the path of the cluster report, if markdown output is enabled
+fn generate_markdown(epoch: Epoch, config: &Config) -> BoxResult<Option<PathBuf>> {
     let markdown_path = match config.markdown_path.as_ref() {
         Some(d) => d,
-        None => return Ok(()),
+        None => return Ok(None),
     };
     fs::create_dir_all(&markdown_path)?;
 
@@ -1581,93 +5895,109 @@ fn generate_markdown(epoch: Epoch, config: &Config) -> BoxResult<()> {
         list.push((epoch, epoch_classification.into_current()));
     }
 
-    let mut validators_markdown: HashMap<_, Vec<_>> = HashMap::new();
-
-    let mut cluster_markdown = vec![];
-
-    for (epoch, epoch_classification) in list {
-        cluster_markdown.push(format!("### Epoch {}", epoch));
-        for note in epoch_classification.notes {
-            cluster_markdown.push(format!("* {}", note));
-        }
-
-        if let Some(validator_classifications) = epoch_classification.validator_classifications {
-            let mut validator_classifications =
-                validator_classifications.into_iter().collect::<Vec<_>>();
-            validator_classifications.sort_by(|a, b| a.0.cmp(&b.0));
-            for (identity, classification) in validator_classifications {
-                let validator_markdown = validators_markdown.entry(identity).or_default();
-
-                validator_markdown.push(format!(
-                    "### [[{1} Epoch {0}|{1}#Epoch-{0}]]",
-                    epoch, cluster_md
-                ));
-                validator_markdown.push(classification.stake_state_reason.clone());
-
-                let stake_state_streak = classification.stake_state_streak();
-                validator_markdown.push(format!(
-                    "* Stake level: **{:?}**{}",
-                    classification.stake_state,
-                    if stake_state_streak > 1 {
-                        format!(" (for {} epochs)", stake_state_streak)
-                    } else {
-                        "".to_string()
-                    }
-                ));
-                validator_markdown.push(format!(
-                    "* Vote account address: {}",
-                    classification.vote_address
-                ));
-                if let (Some(current_data_center), Some(data_center_residency)) = (
-                    classification.current_data_center,
-                    classification.data_center_residency,
-                ) {
-                    validator_markdown.push(format!("* Data Center: {}", current_data_center));
-
-                    if data_center_residency.len() > 1
-                        || (data_center_residency.len() == 1
-                            && !data_center_residency.contains_key(&current_data_center))
-                    {
-                        let data_center_residency = data_center_residency
-                            .keys()
-                            .cloned()
-                            .map(|data_center| data_center.to_string())
-                            .collect::<Vec<_>>();
-                        validator_markdown.push(format!(
-                            "* Resident Data Center: {}",
-                            data_center_residency.join(",")
-                        ));
-                    }
-                }
-
-                for note in classification.notes {
-                    validator_markdown.push(format!("* {}", note));
-                }
-            }
-        }
+    let templates = ReportTemplates::new(config.report_template_dir.as_deref())?;
+    let streak_leaderboard = streaks::leaderboard(&list, streaks::LEADERBOARD_SIZE);
+    let (mut cluster_report, validator_reports) =
+        report_template::reports(cluster_md, &list, &streak_leaderboard);
+    let mut epoch_annotations = HashMap::new();
+    for epoch_report in cluster_report.epochs.iter_mut() {
+        epoch_report.annotations =
+            annotations::EpochAnnotations::load(epoch_report.epoch, &config.cluster_db_path())?
+                .lines();
+        epoch_annotations.insert(epoch_report.epoch, epoch_report.annotations.clone());
     }
 
-    for (identity, validator_markdown) in validators_markdown {
-        let markdown = validator_markdown.join("\n");
+    for (identity, validator_report) in validator_reports {
+        let markdown = templates.render_validator(&validator_report)?;
         let filename = markdown_path.join(format!("Validator-{}.md", identity));
         info!("Writing {}", filename.display());
         let mut file = File::create(filename)?;
         file.write_all(&markdown.into_bytes())?;
     }
 
-    let markdown = cluster_markdown.join("\n");
+    let mut markdown = templates.render_cluster(&cluster_report)?;
+    if let Some(ref report_keypair) = config.report_keypair {
+        markdown = attestation::attest(&markdown, report_keypair);
+    }
     let filename = markdown_path.join(format!("{}.md", cluster_md));
     info!("Writing {}", filename.display());
-    let mut file = File::create(filename)?;
+    let mut file = File::create(&filename)?;
     file.write_all(&markdown.into_bytes())?;
 
-    Ok(())
+    if let Some(ref atom_feed_url) = config.atom_feed_url {
+        // Epochs whose artifacts were removed would link to a missing report
+        let max_entries = config
+            .artifact_retention_epochs
+            .map(|retention_epochs| retention_epochs as usize + 1)
+            .unwrap_or(feed::MAX_ENTRIES)
+            .min(feed::MAX_ENTRIES);
+        let mut entry_notes = vec![];
+        for (epoch, epoch_classification) in list.iter().take(max_entries) {
+            // Paused epochs record no timestamp, their classification was saved when decided
+            let updated = match epoch_classification.timestamp {
+                Some(timestamp) => timestamp,
+                None => fs::metadata(EpochClassification::file_name(
+                    *epoch,
+                    &config.cluster_db_path(),
+                ))?
+                .modified()?
+                .duration_since(UNIX_EPOCH)?
+                .as_secs(),
+            };
+            // The annotations of the operators come first, they explain the notes
+            let notes = epoch_annotations
+                .get(epoch)
+                .into_iter()
+                .flatten()
+                .map(|annotation| format!("Operator annotation: {}", annotation))
+                .chain(epoch_classification.notes.iter().cloned())
+                .collect::<Vec<_>>();
+            entry_notes.push((*epoch, updated, notes));
+        }
+        let entries = entry_notes
+            .iter()
+            .map(|(epoch, updated, notes)| feed::FeedEntry {
+                epoch: *epoch,
+                updated: *updated,
+                notes,
+            })
+            .collect::<Vec<_>>();
+
+        let feed_filename = format!("{}.atom", cluster_md);
+        let feed = feed::atom_feed(
+            cluster_md,
+            atom_feed_url,
+            &format!("{}.md", cluster_md),
+            &feed_filename,
+            &entries,
+        );
+        let feed_filename = markdown_path.join(feed_filename);
+        info!("Writing {}", feed_filename.display());
+        fs::write(&feed_filename, feed)?;
+    }
+
+    Ok(Some(filename))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn classify_producers(
+        first_slot_in_epoch: Slot,
+        confirmed_blocks: HashSet<u64>,
+        leader_schedule: HashMap<String, Vec<usize>>,
+        config: &Config,
+    ) -> BoxResult<ClassifyResult> {
+        let blocks_and_slots = count_blocks_and_slots(
+            first_slot_in_epoch,
+            Slot::MAX,
+            &confirmed_blocks,
+            leader_schedule,
+        )?;
+        classify_blocks_and_slots(&blocks_and_slots, config)
+    }
+
     #[test]
     fn test_quality_producer_with_average_skip_rate() {
         solana_logger::setup();
@@ -1707,6 +6037,22 @@ mod test {
         assert!(!too_many_poor_block_producers);
     }
 
+    #[test]
+    fn test_count_blocks_and_slots_of_epoch_in_progress() {
+        let confirmed_blocks: HashSet<Slot> = (100..115).collect();
+        let mut leader_schedule = HashMap::new();
+        let l1 = Pubkey::new_unique();
+        let l2 = Pubkey::new_unique();
+        leader_schedule.insert(l1.to_string(), (0..10).collect());
+        leader_schedule.insert(l2.to_string(), (10..20).collect());
+
+        // Slots after 114 have not happened yet and do not count as skipped
+        let blocks_and_slots =
+            count_blocks_and_slots(100, 114, &confirmed_blocks, leader_schedule).unwrap();
+        assert_eq!(blocks_and_slots[&l1], (10, 10));
+        assert_eq!(blocks_and_slots[&l2], (5, 5));
+    }
+
     #[test]
     fn test_quality_producer_when_all_good() {
         solana_logger::setup();
@@ -1734,4 +6080,179 @@ mod test {
         assert_eq!(quality.len(), 5);
         assert!(!too_many_poor_block_producers);
     }
+
+    #[test]
+    fn test_quality_producer_normalized_skip_rate() {
+        solana_logger::setup();
+        let config = Config {
+            quality_block_producer_percentage: 30,
+            max_poor_block_producer_percentage: 40,
+            normalize_skip_rate: true,
+            ..Config::default_for_test()
+        };
+
+        // l1 produces every block, l2 half of its blocks, l3 none at all
+        let confirmed_blocks: HashSet<Slot> = (0..15).collect();
+        let mut leader_schedule = HashMap::new();
+        let l1 = Pubkey::new_unique();
+        let l2 = Pubkey::new_unique();
+        let l3 = Pubkey::new_unique();
+        leader_schedule.insert(l1.to_string(), (0..10).collect());
+        leader_schedule.insert(l2.to_string(), (10..20).collect());
+        leader_schedule.insert(l3.to_string(), (20..30).collect());
+        let (quality, poor, _reason_msg, cluster_average_skip_rate, _) = classify_producers(
+            0,
+            confirmed_blocks.clone(),
+            leader_schedule.clone(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(cluster_average_skip_rate, 50);
+        assert_eq!(quality.len(), 2);
+        assert!(quality.contains(&l1));
+        assert!(quality.contains(&l2));
+        assert_eq!(poor.len(), 1);
+        assert!(poor.contains(&l3));
+
+        // A degraded cluster exempts everybody
+        let config = Config {
+            bad_cluster_average_skip_rate: 40,
+            ..config
+        };
+        let (quality, poor, _reason_msg, _, _) =
+            classify_producers(0, confirmed_blocks, leader_schedule, &config).unwrap();
+        assert_eq!(quality.len(), 3);
+        assert!(poor.is_empty());
+    }
+
+    #[test]
+    fn test_credits_percentile() {
+        let all_credits = [100, 200, 200, 300];
+        assert_eq!(credits_percentile(50, &all_credits), 0.);
+        assert_eq!(credits_percentile(100, &all_credits), 12.5);
+        assert_eq!(credits_percentile(200, &all_credits), 50.);
+        assert_eq!(credits_percentile(300, &all_credits), 87.5);
+        assert_eq!(credits_percentile(300, &[]), 0.);
+    }
+
+    #[test]
+    fn test_cap_desired_validators() {
+        let classification =
+            |stake_state: ValidatorStakeState, reputation: f64, epoch_credits: u64| {
+                let identity = Pubkey::new_unique();
+                (
+                    identity,
+                    ValidatorClassification {
+                        identity,
+                        stake_state,
+                        stake_state_reason: "reason".to_string(),
+                        stake_states: Some(vec![(stake_state, "reason".to_string())]),
+                        reputation: Some(reputation),
+                        epoch_credits: Some(epoch_credits),
+                        ..ValidatorClassification::default()
+                    },
+                )
+            };
+        let validators = vec![
+            classification(ValidatorStakeState::Baseline, 5., 100),
+            classification(ValidatorStakeState::Bonus, 1., 100),
+            classification(ValidatorStakeState::Baseline, 5., 200),
+            classification(ValidatorStakeState::None, 9., 300),
+        ];
+        let mut validator_classifications = validators.iter().cloned().collect::<HashMap<_, _>>();
+
+        let below_cut = cap_desired_validators(&mut validator_classifications, 2);
+        assert_eq!(below_cut, vec![validators[0].0]);
+        let vc = &validator_classifications[&validators[0].0];
+        assert_eq!(vc.stake_state, ValidatorStakeState::None);
+        assert_eq!(
+            vc.stake_state_reason,
+            "ranked 3 of the 3 validators eligible for stake, below the limit of 2; reason"
+        );
+        assert_eq!(
+            vc.stake_states.as_ref().unwrap()[0],
+            (ValidatorStakeState::None, vc.stake_state_reason.clone())
+        );
+        assert_eq!(
+            validator_classifications[&validators[2].0].stake_state,
+            ValidatorStakeState::Baseline
+        );
+
+        assert!(cap_desired_validators(&mut validator_classifications, 2).is_empty());
+    }
+
+    #[test]
+    fn test_classification_mismatches() {
+        let identity = Pubkey::new_unique();
+        let classification =
+            |stake_state: ValidatorStakeState, notes: &[&str]| EpochClassificationV1 {
+                validator_classifications: Some(
+                    vec![(
+                        identity,
+                        ValidatorClassification {
+                            identity,
+                            stake_state,
+                            stake_state_reason: "reason".to_string(),
+                            ..ValidatorClassification::default()
+                        },
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+                notes: notes.iter().map(|note| note.to_string()).collect(),
+                ..EpochClassificationV1::default()
+            };
+
+        // Notes added by the stake pool after the classification are not compared
+        let published = classification(ValidatorStakeState::Bonus, &["a", "b", "stake pool"]);
+        assert!(classification_mismatches(
+            &published,
+            &classification(ValidatorStakeState::Bonus, &["a", "b"])
+        )
+        .is_empty());
+
+        let mismatches = classification_mismatches(
+            &published,
+            &classification(ValidatorStakeState::Baseline, &["a", "c"]),
+        );
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(
+            mismatches[1],
+            format!(
+                "{}: published Bonus stake (reason), derived Baseline stake (reason)",
+                identity
+            )
+        );
+
+        let derived = EpochClassificationV1 {
+            validator_classifications: Some(HashMap::default()),
+            ..published.clone()
+        };
+        assert_eq!(
+            classification_mismatches(&published, &derived),
+            vec![format!("{}: published but not derived", identity)]
+        );
+    }
+
+    #[test]
+    fn test_classify_vote_lag() {
+        solana_logger::setup();
+        let v1 = Pubkey::new_unique();
+        let v2 = Pubkey::new_unique();
+        let v3 = Pubkey::new_unique();
+        let v4 = Pubkey::new_unique();
+        let average_vote_lag = [(v1, 1.), (v2, 2.5), (v3, 3.5), (v4, 150.)]
+            .iter()
+            .cloned()
+            .collect::<HashMap<_, _>>();
+
+        let (laggy_voters, cluster_median_vote_lag) = classify_vote_lag(10, &average_vote_lag);
+        assert!((cluster_median_vote_lag - 3.).abs() < f64::EPSILON);
+        assert_eq!(laggy_voters.len(), 1);
+        assert!(laggy_voters.contains(&v4));
+
+        let (laggy_voters, cluster_median_vote_lag) = classify_vote_lag(0, &HashMap::new());
+        assert!(laggy_voters.is_empty());
+        assert!(cluster_median_vote_lag.abs() < f64::EPSILON);
+    }
 }
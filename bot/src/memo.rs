@@ -0,0 +1,87 @@
+//! Memos attributing each transaction to the run that issued it. Every transaction carries a memo
+//! instruction naming the run, the epoch, the phase and the validator it targets, so the stake
+//! movements of the pool can be traced back to a run from the chain alone
+use solana_sdk::{
+    clock::Epoch, instruction::Instruction, pubkey::Pubkey, transaction::Transaction,
+};
+
+const MEMO_PREFIX: &str = "stake-o-matic";
+
+/// Memo fields shared by the transactions of a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionMemo {
+    pub run_id: String,
+    pub epoch: Epoch,
+}
+
+impl TransactionMemo {
+    pub fn new(run_id: &str, epoch: Epoch) -> Self {
+        Self {
+            run_id: run_id.to_string(),
+            epoch,
+        }
+    }
+
+    /// Memo of a transaction of `phase` targeting the vote account `validator`, if any. The same
+    /// inputs always produce the same memo
+    pub fn text(&self, phase: &str, validator: Option<&Pubkey>) -> String {
+        format!(
+            "{}/{}/{}/{}/{}",
+            MEMO_PREFIX,
+            self.run_id,
+            self.epoch,
+            phase,
+            validator.map_or_else(|| "-".to_string(), |validator| validator.to_string())
+        )
+    }
+
+    pub fn instruction(&self, phase: &str, validator: Option<&Pubkey>) -> Instruction {
+        spl_memo::build_memo(self.text(phase, validator).as_bytes(), &[])
+    }
+
+    /// Transaction of `instructions` paid by `payer`, tagged with the memo of `phase` and
+    /// `validator`
+    pub fn transaction(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        phase: &str,
+        validator: Option<&Pubkey>,
+    ) -> Transaction {
+        let mut instructions = instructions.to_vec();
+        instructions.push(self.instruction(phase, validator));
+        Transaction::new_with_payer(&instructions, Some(payer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_memo() {
+        let memo = TransactionMemo::new("1700000000", 250);
+        let validator = Pubkey::new_unique();
+
+        assert_eq!(
+            memo.text("removals", Some(&validator)),
+            format!("stake-o-matic/1700000000/250/removals/{}", validator)
+        );
+        assert_eq!(
+            memo.text("update", None),
+            "stake-o-matic/1700000000/250/update/-"
+        );
+
+        let payer = Pubkey::new_unique();
+        let transaction = memo.transaction(&[], &payer, "withdrawals", None);
+        let instruction = &transaction.message.instructions[0];
+        assert_eq!(
+            transaction.message.account_keys[instruction.program_id_index as usize],
+            spl_memo::id()
+        );
+        assert_eq!(
+            instruction.data,
+            memo.text("withdrawals", None).as_bytes().to_vec()
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! Dependencies between the operations of a stake pool update, such as decreasing the stake of a
+//! validator before removing it from the pool. The operations form a directed acyclic graph that
+//! is submitted level by level: every operation whose dependencies have completed is submitted
+//! together with the others of its level, rather than waiting on unrelated operations
+use {
+    crate::transaction_executor::TransactionExecutor,
+    log::*,
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        transaction::Transaction,
+    },
+    std::{collections::BTreeMap, error},
+};
+
+pub type OperationId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperationStatus {
+    Succeeded,
+    Failed,
+    /// Not submitted, since one of its dependencies failed or was skipped
+    Skipped,
+    /// Handed over to be submitted elsewhere, in order after its dependencies
+    HandedOver,
+}
+
+impl OperationStatus {
+    fn unblocks_dependents(self) -> bool {
+        matches!(self, Self::Succeeded | Self::HandedOver)
+    }
+}
+
+struct Operation<'a> {
+    description: String,
+    transactions: Vec<Transaction>,
+    signers: Vec<&'a Keypair>,
+    dependencies: Vec<OperationId>,
+}
+
+#[derive(Default)]
+pub struct OperationGraph<'a> {
+    operations: Vec<Operation<'a>>,
+}
+
+impl<'a> OperationGraph<'a> {
+    /// Add an operation of `transactions` signed by `signers`, of which the first pays the fees.
+    /// It is submitted once all of its `dependencies`, operations added before it, have completed
+    pub fn add(
+        &mut self,
+        description: String,
+        transactions: Vec<Transaction>,
+        signers: &[&'a Keypair],
+        dependencies: &[OperationId],
+    ) -> OperationId {
+        let id = self.operations.len();
+        // Depending only on earlier operations keeps the graph acyclic
+        assert!(dependencies.iter().all(|dependency| *dependency < id));
+        self.operations.push(Operation {
+            description,
+            transactions,
+            signers: signers.to_vec(),
+            dependencies: dependencies.to_vec(),
+        });
+        id
+    }
+
+    /// Add `transactions` that must complete one after the other, such as those packed from the
+    /// instructions of a single operation. Returns the last of the operations, if any
+    pub fn add_sequence(
+        &mut self,
+        description: String,
+        transactions: Vec<Transaction>,
+        signers: &[&'a Keypair],
+        dependencies: &[OperationId],
+    ) -> Option<OperationId> {
+        let mut last = None;
+        for transaction in transactions {
+            let id = self.add(
+                description.clone(),
+                vec![transaction],
+                signers,
+                &last.map_or_else(|| dependencies.to_vec(), |last| vec![last]),
+            );
+            last = Some(id);
+        }
+        last
+    }
+
+    /// The operations grouped by level, each depending only on operations of earlier levels
+    pub fn levels(&self) -> Vec<Vec<OperationId>> {
+        let mut operation_levels: Vec<usize> = vec![];
+        let mut levels: Vec<Vec<OperationId>> = vec![];
+        for (id, operation) in self.operations.iter().enumerate() {
+            let level = operation
+                .dependencies
+                .iter()
+                .map(|dependency| operation_levels[*dependency] + 1)
+                .max()
+                .unwrap_or(0);
+            operation_levels.push(level);
+            if levels.len() <= level {
+                levels.resize(level + 1, vec![]);
+            }
+            levels[level].push(id);
+        }
+        levels
+    }
+
+    /// Submit the operations with `executor`, level by level. Returns the status of each operation
+    pub fn execute(
+        self,
+        rpc_client: &RpcClient,
+        executor: &dyn TransactionExecutor,
+    ) -> Result<Vec<OperationStatus>, Box<dyn error::Error>> {
+        let levels = self.levels();
+        let mut statuses = vec![OperationStatus::Skipped; self.operations.len()];
+
+        for (level, ids) in levels.into_iter().enumerate() {
+            // The executor signs every transaction with all of its signers, so the operations of
+            // a level are submitted in groups of the same signers
+            let mut groups: BTreeMap<Vec<Pubkey>, Vec<OperationId>> = BTreeMap::new();
+            for id in ids {
+                let operation = &self.operations[id];
+                if let Some(dependency) = operation
+                    .dependencies
+                    .iter()
+                    .find(|dependency| !statuses[**dependency].unblocks_dependents())
+                {
+                    warn!(
+                        "Skipping {}: {} did not succeed",
+                        operation.description, self.operations[*dependency].description
+                    );
+                    continue;
+                }
+                if operation.transactions.is_empty() {
+                    statuses[id] = OperationStatus::Succeeded;
+                    continue;
+                }
+
+                let mut signers = vec![];
+                for signer in &operation.signers {
+                    if !signers.contains(&signer.pubkey()) {
+                        signers.push(signer.pubkey());
+                    }
+                }
+                groups.entry(signers).or_default().push(id);
+            }
+
+            for group in groups.values() {
+                let signers = &self.operations[group[0]].signers;
+                let mut transaction_ids = vec![];
+                let mut transactions = vec![];
+                for id in group {
+                    for transaction in &self.operations[*id].transactions {
+                        transaction_ids.push(*id);
+                        transactions.push(transaction.clone());
+                    }
+                }
+                debug!("Submitting {} operations of level {}", group.len(), level);
+
+                let result = executor.execute(rpc_client, transactions, signers)?;
+                for id in group {
+                    statuses[*id] = OperationStatus::Succeeded;
+                }
+                for (id, signature) in transaction_ids.into_iter().zip(result.signatures) {
+                    let status = if result.failed.contains(&signature) {
+                        OperationStatus::Failed
+                    } else if result.succeeded.contains(&signature) {
+                        OperationStatus::Succeeded
+                    } else {
+                        OperationStatus::HandedOver
+                    };
+                    if status == OperationStatus::Failed
+                        || statuses[id] == OperationStatus::Succeeded
+                    {
+                        statuses[id] = status;
+                    }
+                }
+            }
+        }
+
+        for (operation, status) in self.operations.iter().zip(&statuses) {
+            if *status == OperationStatus::Failed {
+                error!("Failed to {}", operation.description);
+            }
+        }
+        Ok(statuses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_sdk::system_instruction};
+
+    #[test]
+    fn test_levels() {
+        let payer = Keypair::new();
+        let transaction = || {
+            Transaction::new_with_payer(
+                &[system_instruction::transfer(
+                    &payer.pubkey(),
+                    &Pubkey::new_unique(),
+                    1,
+                )],
+                Some(&payer.pubkey()),
+            )
+        };
+        let mut graph = OperationGraph::default();
+
+        let create = graph.add("create".to_string(), vec![transaction()], &[&payer], &[]);
+        let decrease = graph.add("decrease".to_string(), vec![transaction()], &[&payer], &[]);
+        let add = graph.add("add".to_string(), vec![transaction()], &[&payer], &[create]);
+        let remove = graph
+            .add_sequence(
+                "remove".to_string(),
+                vec![transaction(), transaction()],
+                &[&payer],
+                &[decrease],
+            )
+            .unwrap();
+        let rebalance = graph.add("rebalance".to_string(), vec![], &[&payer], &[add, remove]);
+
+        assert_eq!(remove, 4);
+        assert_eq!(
+            graph.levels(),
+            vec![
+                vec![create, decrease],
+                vec![add, 3],
+                vec![remove],
+                vec![rebalance]
+            ]
+        );
+        assert!(graph
+            .add_sequence("nothing".to_string(), vec![], &[&payer], &[])
+            .is_none());
+    }
+}
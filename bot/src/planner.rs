@@ -0,0 +1,526 @@
+//! Allocation of a stake pool's stake between the desired validators, free of any RPC access.
+//! The stake pool update allocates its stake through `allocate`, so that anything embedding the
+//! planner, such as the C ABI of the `ffi` package, reproduces the production allocation exactly
+use {
+    crate::{
+        bonus_ramp::{BonusRamp, BonusRampStage},
+        generic_stake_pool::*,
+        run_status::guardrail,
+    },
+    log::*,
+    serde::{Deserialize, Serialize},
+    solana_sdk::{native_token::Sol, pubkey::Pubkey},
+    std::{
+        collections::{HashMap, HashSet},
+        error, fmt,
+        str::FromStr,
+    },
+};
+
+/// How the allocation proceeds when the pool's stake does not cover the baseline stake of every
+/// Baseline validator
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BaselineShortfall {
+    /// Abort the run, leaving the stake where it is
+    Abort,
+    /// Reduce the baseline stake amount to what the stake covers, leaving no bonus stake
+    ReduceBaseline,
+    /// Fund the full baseline stake amount of the Baseline validators with the highest scores,
+    /// as many as the stake covers, and none of the others
+    TopScored,
+    /// Split the stake evenly between every Baseline and Bonus validator
+    FundPartially,
+}
+
+impl Default for BaselineShortfall {
+    fn default() -> Self {
+        BaselineShortfall::Abort
+    }
+}
+
+impl fmt::Display for BaselineShortfall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                BaselineShortfall::Abort => "abort",
+                BaselineShortfall::ReduceBaseline => "reduce-baseline",
+                BaselineShortfall::TopScored => "top-scored",
+                BaselineShortfall::FundPartially => "fund-partially",
+            }
+        )
+    }
+}
+
+impl FromStr for BaselineShortfall {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(BaselineShortfall::Abort),
+            "reduce-baseline" => Ok(BaselineShortfall::ReduceBaseline),
+            "top-scored" => Ok(BaselineShortfall::TopScored),
+            "fund-partially" => Ok(BaselineShortfall::FundPartially),
+            _ => Err(format!("Invalid baseline shortfall policy: {}", s)),
+        }
+    }
+}
+
+/// Pool figures and policies the allocation depends on
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AllocationParams {
+    /// Stake of the pool, besides the reserves it requires
+    pub total_stake_lamports: u64,
+    pub baseline_stake_lamports: u64,
+    #[serde(default)]
+    pub max_asn_stake_percentage: Option<f64>,
+    #[serde(default)]
+    pub bonus_ramp: Option<BonusRamp>,
+    #[serde(default)]
+    pub baseline_shortfall: BaselineShortfall,
+
+    /// Stake held back in the reserve for withdrawals, out of the stake left for bonus validators
+    #[serde(default)]
+    pub liquidity_buffer_lamports: u64,
+
+    /// Stage of the bonus ramp recorded by the previous epoch
+    #[serde(default)]
+    pub previous_bonus_ramp: Option<BonusRampStage>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Allocation {
+    /// Baseline stake of each funded Baseline validator, below the baseline stake amount of the
+    /// params when the pool's stake falls short of it
+    pub baseline_stake_lamports: u64,
+
+    /// Baseline validators the baseline shortfall policy leaves without stake, by vote address
+    pub unfunded_baseline: HashSet<Pubkey>,
+
+    /// The baseline shortfall policy applied, if the pool's stake fell short of the baseline
+    pub baseline_shortfall: Option<BaselineShortfall>,
+
+    /// Bonus stake of a validator of the default bonus weight
+    pub bonus_stake_lamports: u64,
+
+    /// Bonus stake of each Bonus validator, by vote address
+    pub bonus_stake_amounts: HashMap<Pubkey, u64>,
+
+    /// ASNs whose bonus stake was capped
+    pub capped_asns: Vec<u64>,
+
+    pub bonus_ramp: Option<BonusRampStage>,
+
+    /// Stake held back in the reserve for withdrawals, at most the stake left after the baseline
+    pub liquidity_buffer_lamports: u64,
+}
+
+impl Allocation {
+    /// `validator_stake` in the stake state this allocation funds it at: the None stake state for
+    /// the Baseline validators left unfunded
+    pub fn funded_validator_stake(&self, validator_stake: &ValidatorStake) -> ValidatorStake {
+        let mut validator_stake = validator_stake.clone();
+        if self
+            .unfunded_baseline
+            .contains(&validator_stake.vote_address)
+        {
+            validator_stake.stake_state = ValidatorStakeState::None;
+        }
+        validator_stake
+    }
+
+    /// Desired stake of `validator_stake` under this allocation
+    pub fn desired_lamports(&self, validator_stake: &ValidatorStake) -> u64 {
+        match self.funded_validator_stake(validator_stake).stake_state {
+            ValidatorStakeState::None => 0,
+            ValidatorStakeState::Baseline => self.baseline_stake_lamports,
+            ValidatorStakeState::Bonus => self
+                .bonus_stake_amounts
+                .get(&validator_stake.vote_address)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Notes on the allocation, as the epoch report of the bot displays them
+    pub fn notes(&self, params: &AllocationParams) -> Vec<String> {
+        let mut notes = vec![
+            format!(
+                "Baseline stake amount: {}",
+                Sol(self.baseline_stake_lamports)
+            ),
+            format!("Bonus stake amount: {}", Sol(self.bonus_stake_lamports)),
+        ];
+        if let Some(baseline_shortfall) = self.baseline_shortfall {
+            notes.push(format!(
+                "Not enough stake to cover the baseline stake amount of {}, {} ({})",
+                Sol(params.baseline_stake_lamports),
+                match baseline_shortfall {
+                    BaselineShortfall::Abort => unreachable!(),
+                    BaselineShortfall::ReduceBaseline =>
+                        "the baseline stake amount is reduced and there is no bonus stake"
+                            .to_string(),
+                    BaselineShortfall::TopScored => format!(
+                        "{} Baseline validators with the lowest scores receive no stake",
+                        self.unfunded_baseline.len()
+                    ),
+                    BaselineShortfall::FundPartially =>
+                        "every Baseline and Bonus validator receives an even share of the stake"
+                            .to_string(),
+                },
+                baseline_shortfall
+            ));
+        }
+        if self.liquidity_buffer_lamports > 0 {
+            notes.push(format!(
+                "Liquidity buffer: {} held back in the reserve for withdrawals",
+                Sol(self.liquidity_buffer_lamports)
+            ));
+        }
+        if let Some(bonus_ramp) = self
+            .bonus_ramp
+            .filter(|bonus_ramp| bonus_ramp.step_lamports > 0)
+        {
+            notes.push(format!(
+                "Bonus stake staged: {} of {} distributed, the remaining {} ramps in at {} per \
+                 epoch",
+                Sol(bonus_ramp.staged_lamports),
+                Sol(bonus_ramp.available_lamports),
+                Sol(bonus_ramp.pending_lamports()),
+                Sol(bonus_ramp.step_lamports)
+            ));
+        }
+        if !self.capped_asns.is_empty() {
+            notes.push(format!(
+                "Bonus stake capped at {}% of the pool in ASNs: {}",
+                params.max_asn_stake_percentage.unwrap_or_default(),
+                self.capped_asns
+                    .iter()
+                    .map(|asn| asn.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        notes
+    }
+}
+
+/// Allocate the stake of the pool to `desired_validator_stake`, in the order the stake pool
+/// processes it: the baseline stake first, then the stake remaining, less the liquidity buffer,
+/// staged by the bonus ramp and capped by ASN, as bonus stake
+pub fn allocate(
+    params: &AllocationParams,
+    desired_validator_stake: &[ValidatorStake],
+) -> Result<Allocation, Box<dyn error::Error>> {
+    let count = |stake_state| {
+        desired_validator_stake
+            .iter()
+            .filter(|validator_stake| validator_stake.stake_state == stake_state)
+            .count() as u64
+    };
+    let baseline_stake_node_count = count(ValidatorStakeState::Baseline);
+    let bonus_stake_node_count = count(ValidatorStakeState::Bonus);
+
+    let total_stake_amount = params.total_stake_lamports;
+    info!(
+        "Total stake pool balance minus required reserves: {}",
+        Sol(total_stake_amount)
+    );
+
+    let total_baseline_stake_amount = baseline_stake_node_count * params.baseline_stake_lamports;
+    info!("Baseline node count: {}", baseline_stake_node_count);
+    info!(
+        "Baseline stake amount: {}",
+        Sol(params.baseline_stake_lamports)
+    );
+    info!(
+        "Total baseline stake amount: {}",
+        Sol(total_baseline_stake_amount)
+    );
+
+    if total_stake_amount < total_baseline_stake_amount {
+        if params.baseline_shortfall == BaselineShortfall::Abort {
+            return Err(guardrail("Not enough stake to cover the baseline"));
+        }
+        warn!(
+            "Not enough stake to cover the baseline, applying the {} policy",
+            params.baseline_shortfall
+        );
+        return Ok(shortfall_allocation(params, desired_validator_stake));
+    }
+
+    info!("Bonus node count: {}", bonus_stake_node_count);
+    let total_bonus_stake_amount = total_stake_amount.saturating_sub(total_baseline_stake_amount);
+    let liquidity_buffer_lamports = params
+        .liquidity_buffer_lamports
+        .min(total_bonus_stake_amount);
+    if liquidity_buffer_lamports > 0 {
+        info!("Liquidity buffer: {}", Sol(liquidity_buffer_lamports));
+    }
+    let total_bonus_stake_amount = total_bonus_stake_amount - liquidity_buffer_lamports;
+    info!(
+        "Total bonus stake amount: {}",
+        Sol(total_bonus_stake_amount)
+    );
+    let bonus_ramp = params.bonus_ramp.map(|bonus_ramp| {
+        bonus_ramp.stage(
+            total_bonus_stake_amount,
+            params.previous_bonus_ramp.as_ref(),
+        )
+    });
+    let total_bonus_stake_amount = match &bonus_ramp {
+        Some(bonus_ramp) => {
+            info!(
+                "Staged bonus stake amount: {}, {} pending",
+                Sol(bonus_ramp.staged_lamports),
+                Sol(bonus_ramp.pending_lamports())
+            );
+            bonus_ramp.staged_lamports
+        }
+        None => total_bonus_stake_amount,
+    };
+
+    let total_bonus_weight = total_bonus_weight(desired_validator_stake);
+    let bonus_stake_amounts =
+        bonus_stake_amounts(total_bonus_stake_amount, desired_validator_stake);
+    let (bonus_stake_amounts, capped_asns) = match params.max_asn_stake_percentage {
+        Some(max_asn_stake_percentage) => cap_bonus_stake_by_asn(
+            total_stake_amount,
+            params.baseline_stake_lamports,
+            max_asn_stake_percentage,
+            desired_validator_stake,
+            &bonus_stake_amounts,
+        ),
+        None => (bonus_stake_amounts, vec![]),
+    };
+    if !capped_asns.is_empty() {
+        info!("Bonus stake capped in ASNs: {:?}", capped_asns);
+    }
+    let bonus_stake_lamports = bonus_stake_amount(
+        total_bonus_stake_amount,
+        total_bonus_weight,
+        DEFAULT_BONUS_WEIGHT,
+    );
+
+    info!("Bonus stake amount: {}", Sol(bonus_stake_lamports));
+    Ok(Allocation {
+        baseline_stake_lamports: params.baseline_stake_lamports,
+        bonus_stake_lamports,
+        bonus_stake_amounts,
+        capped_asns,
+        bonus_ramp,
+        liquidity_buffer_lamports,
+        ..Allocation::default()
+    })
+}
+
+/// Allocation of a stake that does not cover the baseline, under `params.baseline_shortfall`.
+/// The bonus ramp and the ASN cap do not apply to it
+fn shortfall_allocation(
+    params: &AllocationParams,
+    desired_validator_stake: &[ValidatorStake],
+) -> Allocation {
+    let baseline_validators = desired_validator_stake
+        .iter()
+        .filter(|vs| vs.stake_state == ValidatorStakeState::Baseline)
+        .collect::<Vec<_>>();
+    let bonus_validators = desired_validator_stake
+        .iter()
+        .filter(|vs| vs.stake_state == ValidatorStakeState::Bonus)
+        .collect::<Vec<_>>();
+    let total_stake_amount = params.total_stake_lamports;
+
+    let mut allocation = Allocation {
+        baseline_shortfall: Some(params.baseline_shortfall),
+        bonus_stake_amounts: bonus_validators
+            .iter()
+            .map(|vs| (vs.vote_address, 0))
+            .collect(),
+        ..Allocation::default()
+    };
+    match params.baseline_shortfall {
+        BaselineShortfall::Abort => unreachable!(),
+        BaselineShortfall::ReduceBaseline => {
+            allocation.baseline_stake_lamports =
+                total_stake_amount / baseline_validators.len() as u64;
+        }
+        BaselineShortfall::TopScored => {
+            // Validators without a score rank last, and validators of equal scores keep their
+            // order
+            let mut ranked_validators = baseline_validators;
+            ranked_validators.sort_by(|a, b| {
+                let score = |vs: &ValidatorStake| vs.score.unwrap_or(f64::NEG_INFINITY);
+                score(b)
+                    .partial_cmp(&score(a))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let funded_count = (total_stake_amount / params.baseline_stake_lamports) as usize;
+            allocation.baseline_stake_lamports = params.baseline_stake_lamports;
+            allocation.unfunded_baseline = ranked_validators
+                .into_iter()
+                .skip(funded_count)
+                .map(|vs| vs.vote_address)
+                .collect();
+        }
+        BaselineShortfall::FundPartially => {
+            let share =
+                total_stake_amount / (baseline_validators.len() + bonus_validators.len()) as u64;
+            allocation.baseline_stake_lamports = share;
+            allocation.bonus_stake_lamports = share;
+            for bonus_stake_amount in allocation.bonus_stake_amounts.values_mut() {
+                *bonus_stake_amount = share;
+            }
+        }
+    }
+    info!(
+        "Baseline stake amount: {}, {} Baseline validators unfunded",
+        Sol(allocation.baseline_stake_lamports),
+        allocation.unfunded_baseline.len()
+    );
+    allocation
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocate() {
+        let validator = |stake_state, asn| ValidatorStake {
+            asn,
+            ..ValidatorStake::new(Pubkey::new_unique(), Pubkey::new_unique(), stake_state)
+        };
+        let desired_validator_stake = vec![
+            validator(ValidatorStakeState::Baseline, None),
+            validator(ValidatorStakeState::Bonus, Some(1)),
+            validator(ValidatorStakeState::Bonus, Some(2)),
+            validator(ValidatorStakeState::None, None),
+        ];
+        let params = AllocationParams {
+            total_stake_lamports: 1_000,
+            baseline_stake_lamports: 100,
+            ..AllocationParams::default()
+        };
+
+        let allocation = allocate(&params, &desired_validator_stake).unwrap();
+        assert_eq!(allocation.bonus_stake_lamports, 450);
+        assert_eq!(
+            desired_validator_stake
+                .iter()
+                .map(|vs| allocation.desired_lamports(vs))
+                .collect::<Vec<_>>(),
+            vec![100, 450, 450, 0]
+        );
+        assert!(allocation.capped_asns.is_empty());
+
+        let capped_params = AllocationParams {
+            max_asn_stake_percentage: Some(40.),
+            ..params.clone()
+        };
+        let capped = allocate(&capped_params, &desired_validator_stake).unwrap();
+        assert_eq!(capped.capped_asns, vec![1, 2]);
+        assert_eq!(
+            capped.notes(&capped_params)[2],
+            "Bonus stake capped at 40% of the pool in ASNs: 1, 2"
+        );
+
+        let staged = allocate(
+            &AllocationParams {
+                bonus_ramp: Some(BonusRamp {
+                    epochs: 3,
+                    threshold_percentage: 10.,
+                }),
+                previous_bonus_ramp: Some(BonusRampStage {
+                    staged_lamports: 300,
+                    available_lamports: 300,
+                    step_lamports: 0,
+                }),
+                ..params.clone()
+            },
+            &desired_validator_stake,
+        )
+        .unwrap();
+        assert_eq!(staged.bonus_ramp.unwrap().staged_lamports, 500);
+        assert_eq!(staged.bonus_stake_amounts.values().sum::<u64>(), 500);
+
+        let buffered_params = AllocationParams {
+            liquidity_buffer_lamports: 300,
+            ..params.clone()
+        };
+        let buffered = allocate(&buffered_params, &desired_validator_stake).unwrap();
+        assert_eq!(buffered.liquidity_buffer_lamports, 300);
+        assert_eq!(buffered.bonus_stake_amounts.values().sum::<u64>(), 600);
+        assert_eq!(
+            buffered.notes(&buffered_params)[2],
+            "Liquidity buffer: ◎0.000000300 held back in the reserve for withdrawals"
+        );
+        let drained = allocate(
+            &AllocationParams {
+                liquidity_buffer_lamports: 2_000,
+                ..params.clone()
+            },
+            &desired_validator_stake,
+        )
+        .unwrap();
+        assert_eq!(drained.liquidity_buffer_lamports, 900);
+        assert_eq!(drained.baseline_stake_lamports, 100);
+        assert_eq!(drained.bonus_stake_lamports, 0);
+
+        let short_params = AllocationParams {
+            total_stake_lamports: 50,
+            ..params
+        };
+        assert!(allocate(&short_params, &desired_validator_stake).is_err());
+    }
+
+    #[test]
+    fn test_allocate_baseline_shortfall() {
+        let validator = |stake_state, score| ValidatorStake {
+            score,
+            ..ValidatorStake::new(Pubkey::new_unique(), Pubkey::new_unique(), stake_state)
+        };
+        let desired_validator_stake = vec![
+            validator(ValidatorStakeState::Baseline, Some(1.)),
+            validator(ValidatorStakeState::Baseline, None),
+            validator(ValidatorStakeState::Baseline, Some(2.)),
+            validator(ValidatorStakeState::Bonus, Some(3.)),
+            validator(ValidatorStakeState::None, None),
+        ];
+        let desired_lamports = |baseline_shortfall| {
+            let allocation = allocate(
+                &AllocationParams {
+                    total_stake_lamports: 250,
+                    baseline_stake_lamports: 100,
+                    baseline_shortfall,
+                    ..AllocationParams::default()
+                },
+                &desired_validator_stake,
+            )
+            .unwrap();
+            assert_eq!(allocation.baseline_shortfall, Some(baseline_shortfall));
+            desired_validator_stake
+                .iter()
+                .map(|vs| allocation.desired_lamports(vs))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            desired_lamports(BaselineShortfall::ReduceBaseline),
+            vec![83, 83, 83, 0, 0]
+        );
+        assert_eq!(
+            desired_lamports(BaselineShortfall::TopScored),
+            vec![100, 0, 100, 0, 0]
+        );
+        assert_eq!(
+            desired_lamports(BaselineShortfall::FundPartially),
+            vec![62, 62, 62, 62, 0]
+        );
+        assert_eq!(
+            "top-scored".parse::<BaselineShortfall>().unwrap(),
+            BaselineShortfall::TopScored
+        );
+    }
+}
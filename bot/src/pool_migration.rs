@@ -0,0 +1,615 @@
+//! Migration of a holder's stake from one stake pool to another, such as a new deployment of the
+//! pool, one tranche per epoch.
+//!
+//! The stake moves as active stake accounts: each tranche is withdrawn from the validator stake
+//! accounts of the source pool and deposited into the stake accounts of the same validators in
+//! the destination pool, so the stake never cools down and keeps earning rewards through the
+//! migration. The tranche is spread over the validators in proportion to their stake in the
+//! source pool, mirroring its allocation. Validators that the destination pool has not added yet
+//! keep their stake in the source pool until it does, which takes an epoch for their new
+//! validator stake account to activate
+use {
+    crate::{
+        memo::TransactionMemo,
+        schema::{self, Schema},
+        stake_pool::{update_stake_pool, MIN_STAKE_ACCOUNT_BALANCE},
+        transaction_executor::TransactionExecutor,
+    },
+    borsh::BorshDeserialize,
+    log::*,
+    serde::{Deserialize, Serialize},
+    solana_account_decoder::UiAccountEncoding,
+    solana_client::{
+        rpc_client::RpcClient,
+        rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+        rpc_filter::{Memcmp, MemcmpEncodedBytes, MemcmpEncoding, RpcFilterType},
+    },
+    solana_sdk::{
+        borsh::try_from_slice_unchecked,
+        clock::Epoch,
+        native_token::Sol,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        system_instruction,
+    },
+    solana_stake_program::stake_state::StakeState,
+    spl_stake_pool::{
+        find_stake_program_address, find_withdraw_authority_program_address,
+        state::{StakePool, StakeStatus, ValidatorList},
+    },
+    std::{
+        collections::{HashMap, HashSet},
+        error,
+        fs::{self, File},
+        io::{self, Write},
+        mem,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Stake withdrawn from a validator of the source pool and deposited into the destination pool
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MigrationMove {
+    pub vote_address: Pubkey,
+    pub lamports: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MigrationEpoch {
+    pub epoch: Epoch,
+    pub moves: Vec<MigrationMove>,
+    pub source_pool_tokens_burned: u64,
+}
+
+/// Progress of the migration from `source` to `destination`, one entry per epoch
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MigrationLog {
+    pub source: Pubkey,
+    pub destination: Pubkey,
+    pub epochs: Vec<MigrationEpoch>,
+}
+
+impl Schema for MigrationLog {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl MigrationLog {
+    pub fn file_name<P>(source: &Pubkey, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref().join(format!("pool-migration-{}.yml", source))
+    }
+
+    pub fn load_or_new<P>(source: &Pubkey, destination: &Pubkey, path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let file_name = Self::file_name(source, path);
+        if !file_name.exists() {
+            return Ok(Self {
+                source: *source,
+                destination: *destination,
+                epochs: vec![],
+            });
+        }
+        let serialized = fs::read_to_string(file_name)?;
+        let log: Self = schema::from_str(&serialized)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        if log.destination != *destination {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "Stake pool {} is being migrated to {}, not {}",
+                    source, log.destination, destination
+                ),
+            ));
+        }
+        Ok(log)
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized =
+            schema::to_yaml(self).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        fs::create_dir_all(&path)?;
+        let mut file = File::create(Self::file_name(&self.source, path))?;
+        file.write_all(&serialized.into_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Validator stake of the source pool that can be migrated
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MigratableStake {
+    vote_address: Pubkey,
+    stake_lamports: u64,
+
+    // Stake that can be withdrawn while leaving the validator stake account its minimum balance
+    available_lamports: u64,
+}
+
+/// Split `tranche_lamports` over the `validators` in proportion to their stake, leaving out
+/// moves smaller than `min_move_lamports`
+fn plan_moves(
+    validators: &[MigratableStake],
+    tranche_lamports: u64,
+    min_move_lamports: u64,
+) -> Vec<MigrationMove> {
+    let total_stake_lamports = validators
+        .iter()
+        .map(|validator| u128::from(validator.stake_lamports))
+        .sum::<u128>();
+    if total_stake_lamports == 0 {
+        return vec![];
+    }
+    validators
+        .iter()
+        .filter_map(|validator| {
+            let share = (u128::from(tranche_lamports) * u128::from(validator.stake_lamports)
+                / total_stake_lamports) as u64;
+            let lamports = share.min(validator.available_lamports);
+            if lamports < min_move_lamports {
+                None
+            } else {
+                Some(MigrationMove {
+                    vote_address: validator.vote_address,
+                    lamports,
+                })
+            }
+        })
+        .collect()
+}
+
+// Seed of the holder's stake account that carries the stake of `vote_address` between the pools
+fn migration_stake_address_seed(vote_address: &Pubkey) -> String {
+    format!("migrate{}", vote_address)[..32].to_string()
+}
+
+fn migration_stake_address(holder: &Pubkey, vote_address: &Pubkey) -> Pubkey {
+    Pubkey::create_with_seed(
+        holder,
+        &migration_stake_address_seed(vote_address),
+        &solana_stake_program::id(),
+    )
+    .unwrap()
+}
+
+struct Pool {
+    program_id: Pubkey,
+    address: Pubkey,
+    stake_pool: StakePool,
+    validator_list: ValidatorList,
+}
+
+impl Pool {
+    fn load(rpc_client: &RpcClient, address: &Pubkey) -> Result<Self, Box<dyn error::Error>> {
+        let account = rpc_client.get_account(address)?;
+        let stake_pool = StakePool::try_from_slice(account.data.as_slice())
+            .map_err(|err| format!("Invalid stake pool {}: {}", address, err))?;
+        let account_data = rpc_client.get_account_data(&stake_pool.validator_list)?;
+        let validator_list = try_from_slice_unchecked::<ValidatorList>(&account_data.as_slice())
+            .map_err(|err| {
+                format!(
+                    "Invalid validator list {}: {}",
+                    stake_pool.validator_list, err
+                )
+            })?;
+        Ok(Self {
+            program_id: account.owner,
+            address: *address,
+            stake_pool,
+            validator_list,
+        })
+    }
+
+    fn active_validators(&self) -> impl Iterator<Item = &Pubkey> {
+        self.validator_list
+            .validators
+            .iter()
+            .filter(|validator| validator.status == StakeStatus::Active)
+            .map(|validator| &validator.vote_account_address)
+    }
+
+    fn withdraw_authority(&self) -> Pubkey {
+        find_withdraw_authority_program_address(&self.program_id, &self.address).0
+    }
+
+    fn validator_stake_address(&self, vote_address: &Pubkey) -> Pubkey {
+        find_stake_program_address(&self.program_id, vote_address, &self.address).0
+    }
+
+    /// Update the pool if it has not been updated this epoch, as withdrawals and deposits require
+    fn update(
+        &mut self,
+        rpc_client: &RpcClient,
+        executor: &dyn TransactionExecutor,
+        memo: &TransactionMemo,
+        payer: &Keypair,
+    ) -> Result<(), Box<dyn error::Error>> {
+        if self.stake_pool.last_update_epoch < memo.epoch {
+            info!("Updating stake pool {}", self.address);
+            update_stake_pool(
+                rpc_client,
+                executor,
+                memo,
+                &self.program_id,
+                payer,
+                &self.address,
+                &self.stake_pool,
+                &self.validator_list,
+            )?;
+            *self = Self::load(rpc_client, &self.address)?;
+        }
+        Ok(())
+    }
+}
+
+/// The token account of `owner` with the largest balance of `mint`
+fn pool_token_account(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<Option<(Pubkey, u64)>, Box<dyn error::Error>> {
+    let token_accounts = rpc_client.get_program_accounts_with_config(
+        &spl_token::id(),
+        RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(spl_token::state::Account::LEN as u64),
+                // `Account::mint` begins at byte offset 0 and `Account::owner` at offset 32
+                RpcFilterType::Memcmp(Memcmp {
+                    offset: 0,
+                    bytes: MemcmpEncodedBytes::Binary(mint.to_string()),
+                    encoding: Some(MemcmpEncoding::Binary),
+                }),
+                RpcFilterType::Memcmp(Memcmp {
+                    offset: 32,
+                    bytes: MemcmpEncodedBytes::Binary(owner.to_string()),
+                    encoding: Some(MemcmpEncoding::Binary),
+                }),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(rpc_client.commitment()),
+                ..RpcAccountInfoConfig::default()
+            },
+        },
+    )?;
+    let mut largest = None;
+    for (address, account) in token_accounts {
+        let token_account = spl_token::state::Account::unpack(&account.data)
+            .map_err(|err| format!("Invalid token account {}: {}", address, err))?;
+        if largest.map_or(true, |(_, amount)| token_account.amount > amount) {
+            largest = Some((address, token_account.amount));
+        }
+    }
+    Ok(largest)
+}
+
+/// Migrate up to `max_epoch_percentage` of the source pool's stake from the pool tokens of
+/// `holder` in `source` to `destination`, once per epoch, recording the progress in `db_path`.
+/// Migration stake accounts left over by an earlier interrupted run are deposited along with the
+/// tranche. In a dry run the tranche is only displayed. The transactions are tagged with the memo
+/// of `run_id`
+#[allow(clippy::too_many_arguments)]
+pub fn migrate(
+    rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    run_id: &str,
+    dry_run: bool,
+    source: &Pubkey,
+    destination: &Pubkey,
+    holder: &Keypair,
+    max_epoch_percentage: f64,
+    db_path: &Path,
+) -> Result<(), Box<dyn error::Error>> {
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    let memo = TransactionMemo::new(run_id, epoch);
+    let mut log = MigrationLog::load_or_new(source, destination, db_path)?;
+
+    let mut source_pool = Pool::load(rpc_client, source)?;
+    let mut destination_pool = Pool::load(rpc_client, destination)?;
+    if !dry_run {
+        source_pool.update(rpc_client, executor, &memo, holder)?;
+        destination_pool.update(rpc_client, executor, &memo, holder)?;
+    }
+
+    let (source_token_account, source_pool_tokens) = pool_token_account(
+        rpc_client,
+        &holder.pubkey(),
+        &source_pool.stake_pool.pool_mint,
+    )?
+    .ok_or_else(|| {
+        format!(
+            "{} holds no pool tokens of stake pool {}",
+            holder.pubkey(),
+            source
+        )
+    })?;
+    let (destination_token_account, _) = pool_token_account(
+        rpc_client,
+        &holder.pubkey(),
+        &destination_pool.stake_pool.pool_mint,
+    )?
+    .ok_or_else(|| {
+        format!(
+            "{} has no token account for the pool mint {} of stake pool {}, create one first",
+            holder.pubkey(),
+            destination_pool.stake_pool.pool_mint,
+            destination
+        )
+    })?;
+    let holder_lamports = source_pool
+        .stake_pool
+        .calc_lamports_withdraw_amount(source_pool_tokens)
+        .unwrap_or_default();
+    info!(
+        "{} holds {} pool tokens of {}, worth {}",
+        holder.pubkey(),
+        source_pool_tokens,
+        source,
+        Sol(holder_lamports)
+    );
+
+    let destination_validators = destination_pool
+        .active_validators()
+        .cloned()
+        .collect::<HashSet<_>>();
+    let not_in_destination = source_pool
+        .active_validators()
+        .filter(|vote_address| !destination_validators.contains(vote_address))
+        .collect::<Vec<_>>();
+    if !not_in_destination.is_empty() {
+        warn!(
+            "{} validators of {} are not active in {} yet, their stake is not migrated: {:?}",
+            not_in_destination.len(),
+            source,
+            destination,
+            not_in_destination
+        );
+    }
+
+    let stake_rent =
+        rpc_client.get_minimum_balance_for_rent_exemption(mem::size_of::<StakeState>())?;
+    let min_stake_lamports = stake_rent + MIN_STAKE_ACCOUNT_BALANCE;
+
+    let mut moves = vec![];
+    if log.epochs.last().map(|e| e.epoch) == Some(epoch) {
+        info!(
+            "Stake pool {} was already migrated in epoch {}",
+            source, epoch
+        );
+    } else {
+        let mut validators = vec![];
+        for validator in &source_pool.validator_list.validators {
+            if validator.status == StakeStatus::Active
+                && destination_validators.contains(&validator.vote_account_address)
+            {
+                // Left over by an interrupted run, deposited below
+                let pending_deposit = rpc_client
+                    .get_account_with_commitment(
+                        &migration_stake_address(&holder.pubkey(), &validator.vote_account_address),
+                        rpc_client.commitment(),
+                    )?
+                    .value
+                    .is_some();
+                if pending_deposit {
+                    continue;
+                }
+                let stake_address =
+                    source_pool.validator_stake_address(&validator.vote_account_address);
+                validators.push(MigratableStake {
+                    vote_address: validator.vote_account_address,
+                    stake_lamports: validator.stake_lamports,
+                    available_lamports: rpc_client
+                        .get_balance(&stake_address)?
+                        .saturating_sub(min_stake_lamports),
+                });
+            }
+        }
+        let tranche_lamports = holder_lamports.min(
+            (source_pool.stake_pool.total_stake_lamports as f64 * max_epoch_percentage / 100.)
+                as u64,
+        );
+        moves = plan_moves(&validators, tranche_lamports, min_stake_lamports);
+        info!(
+            "Migrating {} of {} from {} validators in epoch {}",
+            Sol(moves.iter().map(|m| m.lamports).sum()),
+            source,
+            moves.len(),
+            epoch
+        );
+
+        let mut pool_tokens_burned = 0;
+        let mut transactions = vec![];
+        for MigrationMove {
+            vote_address,
+            lamports,
+        } in &moves
+        {
+            let pool_tokens = source_pool
+                .stake_pool
+                .calc_pool_tokens_for_withdraw(*lamports)
+                .unwrap_or_default()
+                .min(source_pool_tokens - pool_tokens_burned);
+            pool_tokens_burned += pool_tokens;
+            info!(
+                "Withdrawing {} from validator {}",
+                Sol(*lamports),
+                vote_address
+            );
+            transactions.push(memo.transaction(
+                &[
+                    system_instruction::create_account_with_seed(
+                        &holder.pubkey(),
+                        &migration_stake_address(&holder.pubkey(), vote_address),
+                        &holder.pubkey(),
+                        &migration_stake_address_seed(vote_address),
+                        0,
+                        mem::size_of::<StakeState>() as u64,
+                        &solana_stake_program::id(),
+                    ),
+                    spl_stake_pool::instruction::withdraw(
+                        &source_pool.program_id,
+                        source,
+                        &source_pool.stake_pool.validator_list,
+                        &source_pool.withdraw_authority(),
+                        &source_pool.validator_stake_address(vote_address),
+                        &migration_stake_address(&holder.pubkey(), vote_address),
+                        &holder.pubkey(),
+                        &holder.pubkey(),
+                        &source_token_account,
+                        &source_pool.stake_pool.pool_mint,
+                        &spl_token::id(),
+                        pool_tokens,
+                    ),
+                ],
+                &holder.pubkey(),
+                "migration-withdrawals",
+                Some(vote_address),
+            ));
+        }
+        if dry_run {
+            info!("Dry run, no stake was migrated");
+            return Ok(());
+        }
+        if !executor
+            .execute(rpc_client, transactions, &[holder])?
+            .failed
+            .is_empty()
+        {
+            warn!("Failed to withdraw some stake from {}", source);
+        }
+    }
+
+    // Deposit every migration stake account, including those left over by an interrupted run
+    let mut withdrawn_lamports = HashMap::new();
+    let mut transactions = vec![];
+    for vote_address in &destination_validators {
+        let migration_stake_address = migration_stake_address(&holder.pubkey(), vote_address);
+        let lamports = rpc_client
+            .get_account_with_commitment(&migration_stake_address, rpc_client.commitment())?
+            .value
+            .map(|account| account.lamports);
+        if let Some(lamports) = lamports {
+            withdrawn_lamports.insert(*vote_address, lamports);
+            info!(
+                "Depositing {} into validator {}",
+                Sol(lamports),
+                vote_address
+            );
+            transactions.push(memo.transaction(
+                &spl_stake_pool::instruction::deposit(
+                    &destination_pool.program_id,
+                    destination,
+                    &destination_pool.stake_pool.validator_list,
+                    &destination_pool.withdraw_authority(),
+                    &migration_stake_address,
+                    &holder.pubkey(),
+                    &destination_pool.validator_stake_address(vote_address),
+                    &destination_token_account,
+                    &destination_pool.stake_pool.pool_mint,
+                    &spl_token::id(),
+                ),
+                &holder.pubkey(),
+                "migration-deposits",
+                Some(vote_address),
+            ));
+        }
+    }
+    let deposits_failed = !executor
+        .execute(rpc_client, transactions, &[holder])?
+        .failed
+        .is_empty();
+
+    // Record the moves that were withdrawn, at the balance they were deposited with
+    let moves = moves
+        .into_iter()
+        .filter_map(|m| {
+            Some(MigrationMove {
+                lamports: *withdrawn_lamports.get(&m.vote_address)?,
+                ..m
+            })
+        })
+        .collect::<Vec<_>>();
+    if !moves.is_empty() {
+        let remaining_pool_tokens = pool_token_account(
+            rpc_client,
+            &holder.pubkey(),
+            &source_pool.stake_pool.pool_mint,
+        )?
+        .map(|(_, amount)| amount)
+        .unwrap_or_default();
+        log.epochs.push(MigrationEpoch {
+            epoch,
+            moves,
+            source_pool_tokens_burned: source_pool_tokens.saturating_sub(remaining_pool_tokens),
+        });
+        log.save(db_path)?;
+    }
+    if deposits_failed {
+        return Err(format!(
+            "Failed to deposit some stake into {}, rerun to retry the deposits",
+            destination
+        )
+        .into());
+    }
+
+    let migrated_lamports = log
+        .epochs
+        .iter()
+        .flat_map(|e| e.moves.iter())
+        .map(|m| m.lamports)
+        .sum();
+    info!(
+        "Migrated {} from {} to {} over {} epochs",
+        Sol(migrated_lamports),
+        source,
+        destination,
+        log.epochs.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan_moves() {
+        let stake = |stake_lamports, available_lamports| MigratableStake {
+            vote_address: Pubkey::new_unique(),
+            stake_lamports,
+            available_lamports,
+        };
+        let validators = [stake(600, 600), stake(300, 100), stake(100, 100)];
+
+        assert_eq!(
+            plan_moves(&validators, 500, 20),
+            vec![
+                MigrationMove {
+                    vote_address: validators[0].vote_address,
+                    lamports: 300,
+                },
+                // Limited to the stake available above the minimum balance
+                MigrationMove {
+                    vote_address: validators[1].vote_address,
+                    lamports: 100,
+                },
+                MigrationMove {
+                    vote_address: validators[2].vote_address,
+                    lamports: 50,
+                },
+            ]
+        );
+
+        // Moves below the minimum are left for a later epoch
+        assert_eq!(plan_moves(&validators, 500, 60).len(), 2);
+        assert!(plan_moves(&[], 500, 0).is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+//! Settings of a stake pool that only its authorities change. The state found after each run is
+//! recorded in the journal, so that the next run detects the changes the bot did not make, such
+//! as a new manager, different fees or validators added by someone else
+use {
+    borsh::BorshDeserialize,
+    serde::{Deserialize, Serialize},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{borsh::try_from_slice_unchecked, pubkey::Pubkey},
+    spl_stake_pool::state::{StakePool, ValidatorList},
+    std::{collections::BTreeSet, error},
+};
+
+#[derive(Default, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PoolState {
+    pub manager: Pubkey,
+    pub staker: Pubkey,
+    pub deposit_authority: Pubkey,
+    pub manager_fee_account: Pubkey,
+    pub fee_numerator: u64,
+    pub fee_denominator: u64,
+
+    // Vote addresses of the validators in the validator list
+    pub validators: BTreeSet<Pubkey>,
+}
+
+impl PoolState {
+    pub fn new(stake_pool: &StakePool, validator_list: &ValidatorList) -> Self {
+        Self {
+            manager: stake_pool.manager,
+            staker: stake_pool.staker,
+            deposit_authority: stake_pool.deposit_authority,
+            manager_fee_account: stake_pool.manager_fee_account,
+            fee_numerator: stake_pool.fee.numerator,
+            fee_denominator: stake_pool.fee.denominator,
+            validators: validator_list
+                .validators
+                .iter()
+                .map(|validator_stake_info| validator_stake_info.vote_account_address)
+                .collect(),
+        }
+    }
+}
+
+pub fn fetch(
+    rpc_client: &RpcClient,
+    stake_pool_address: &Pubkey,
+) -> Result<PoolState, Box<dyn error::Error>> {
+    let account_data = rpc_client.get_account_data(stake_pool_address)?;
+    let stake_pool = StakePool::try_from_slice(account_data.as_slice())
+        .map_err(|err| format!("Invalid stake pool {}: {}", stake_pool_address, err))?;
+    let account_data = rpc_client.get_account_data(&stake_pool.validator_list)?;
+    let validator_list = try_from_slice_unchecked::<ValidatorList>(&account_data.as_slice())
+        .map_err(|err| {
+            format!(
+                "Invalid validator list {}: {}",
+                stake_pool.validator_list, err
+            )
+        })?;
+    Ok(PoolState::new(&stake_pool, &validator_list))
+}
+
+/// Describe how `actual` departs from the `expected` state left by the last run, one line per
+/// change
+pub fn unexpected_changes(expected: &PoolState, actual: &PoolState) -> Vec<String> {
+    let mut changes = vec![];
+    let mut authority_changed = |name: &str, expected: &Pubkey, actual: &Pubkey| {
+        if expected != actual {
+            changes.push(format!("{} changed from {} to {}", name, expected, actual));
+        }
+    };
+    authority_changed("Manager", &expected.manager, &actual.manager);
+    authority_changed("Staker", &expected.staker, &actual.staker);
+    authority_changed(
+        "Deposit authority",
+        &expected.deposit_authority,
+        &actual.deposit_authority,
+    );
+    authority_changed(
+        "Manager fee account",
+        &expected.manager_fee_account,
+        &actual.manager_fee_account,
+    );
+
+    if (expected.fee_numerator, expected.fee_denominator)
+        != (actual.fee_numerator, actual.fee_denominator)
+    {
+        changes.push(format!(
+            "Fee changed from {}/{} to {}/{}",
+            expected.fee_numerator,
+            expected.fee_denominator,
+            actual.fee_numerator,
+            actual.fee_denominator
+        ));
+    }
+
+    let list = |validators: Vec<&Pubkey>| {
+        validators
+            .iter()
+            .map(|vote_address| vote_address.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let added = actual
+        .validators
+        .difference(&expected.validators)
+        .collect::<Vec<_>>();
+    if !added.is_empty() {
+        changes.push(format!("Validators added: {}", list(added)));
+    }
+    let removed = expected
+        .validators
+        .difference(&actual.validators)
+        .collect::<Vec<_>>();
+    if !removed.is_empty() {
+        changes.push(format!("Validators removed: {}", list(removed)));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unexpected_changes() {
+        let validator = Pubkey::new_unique();
+        let expected = PoolState {
+            manager: Pubkey::new_unique(),
+            staker: Pubkey::new_unique(),
+            fee_numerator: 3,
+            fee_denominator: 100,
+            validators: vec![validator].into_iter().collect(),
+            ..PoolState::default()
+        };
+        assert!(unexpected_changes(&expected, &expected).is_empty());
+
+        let new_staker = Pubkey::new_unique();
+        let new_validator = Pubkey::new_unique();
+        let actual = PoolState {
+            staker: new_staker,
+            fee_numerator: 10,
+            validators: vec![new_validator].into_iter().collect(),
+            ..expected.clone()
+        };
+        assert_eq!(
+            unexpected_changes(&expected, &actual),
+            vec![
+                format!("Staker changed from {} to {}", expected.staker, new_staker),
+                "Fee changed from 3/100 to 10/100".to_string(),
+                format!("Validators added: {}", new_validator),
+                format!("Validators removed: {}", validator),
+            ]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use {
+    serde::{Deserialize, Serialize},
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::collections::HashMap,
+};
+
+/// Longest schedule planned, in epochs
+pub const MAX_PLAN_EPOCHS: u64 = 64;
+
+/// Stake movement of a validator, planned for an epoch
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PlannedMove {
+    pub vote_address: Pubkey,
+    pub from_lamports: u64,
+    pub to_lamports: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EpochPlan {
+    pub epoch: Epoch,
+    pub moves: Vec<PlannedMove>,
+}
+
+impl EpochPlan {
+    pub fn stake_added_lamports(&self) -> u64 {
+        self.moves
+            .iter()
+            .map(|m| m.to_lamports.saturating_sub(m.from_lamports))
+            .sum()
+    }
+
+    pub fn stake_removed_lamports(&self) -> u64 {
+        self.moves
+            .iter()
+            .map(|m| m.from_lamports.saturating_sub(m.to_lamports))
+            .sum()
+    }
+}
+
+/// Schedule of stake movements that reaches the target allocation over several epochs
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RebalancePlan {
+    pub epochs: Vec<EpochPlan>,
+
+    // Distance from the target of each validator, by vote address, that the schedule does not
+    // cover because the reserve cannot fund it or the schedule would exceed MAX_PLAN_EPOCHS
+    pub unreachable_lamports: HashMap<Pubkey, u64>,
+}
+
+impl RebalancePlan {
+    /// The balance of each validator, by vote address, moved in the first epoch of the schedule
+    pub fn first_epoch_balances(&self) -> HashMap<Pubkey, u64> {
+        self.epochs
+            .first()
+            .map(|epoch_plan| {
+                epoch_plan
+                    .moves
+                    .iter()
+                    .map(|m| (m.vote_address, m.to_lamports))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Plan the stake movements that take each validator of `balances`, given as
+/// `(vote address, current lamports, target lamports)`, to its target starting at `epoch`.
+///
+/// A validator's stake is moved at most once per epoch and always in the direction of its
+/// target. Within each epoch no more than `max_epoch_change_percentage` of the pool's stake is
+/// activated, and no more than that is deactivated, keeping within the warmup and cooldown
+/// limits. Stake removed from a validator only returns to the reserve once it has cooled down,
+/// so it funds increases from the following epoch. Smaller movements are scheduled first, so
+/// that as many validators as possible reach their target with a single transient stake
+/// account, and no movement is smaller than `min_move_lamports`
+pub fn plan(
+    epoch: Epoch,
+    mut reserve_lamports: u64,
+    balances: &[(Pubkey, u64, u64)],
+    max_epoch_change_percentage: f64,
+    min_move_lamports: u64,
+) -> RebalancePlan {
+    let mut balances = balances.to_vec();
+    balances.sort_by_key(|(vote_address, ..)| *vote_address);
+
+    let total_lamports =
+        reserve_lamports + balances.iter().map(|(_, current, _)| current).sum::<u64>();
+    let epoch_change_limit = (total_lamports as f64 * max_epoch_change_percentage / 100.) as u64;
+    let min_move_lamports = min_move_lamports.max(1);
+
+    let mut epochs = vec![];
+    let mut cooling_down_lamports = 0;
+    for offset in 0..MAX_PLAN_EPOCHS {
+        reserve_lamports += cooling_down_lamports;
+        cooling_down_lamports = 0;
+        let mut moves = vec![];
+
+        let mut decreases = balances
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, current, target))| {
+                current.saturating_sub(*target) >= min_move_lamports
+            })
+            .map(|(i, (_, current, target))| (current - target, i))
+            .collect::<Vec<_>>();
+        decreases.sort();
+        let mut deactivation_limit = epoch_change_limit;
+        for (excess, i) in decreases {
+            let amount = excess.min(deactivation_limit);
+            if amount < min_move_lamports {
+                break;
+            }
+            let (vote_address, current, _) = &mut balances[i];
+            moves.push(PlannedMove {
+                vote_address: *vote_address,
+                from_lamports: *current,
+                to_lamports: *current - amount,
+            });
+            *current -= amount;
+            deactivation_limit -= amount;
+            cooling_down_lamports += amount;
+        }
+
+        let mut increases = balances
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, current, target))| {
+                target.saturating_sub(*current) >= min_move_lamports
+            })
+            .map(|(i, (_, current, target))| (target - current, i))
+            .collect::<Vec<_>>();
+        increases.sort();
+        let mut activation_limit = epoch_change_limit;
+        for (shortfall, i) in increases {
+            let amount = shortfall.min(activation_limit).min(reserve_lamports);
+            if amount < min_move_lamports {
+                continue;
+            }
+            let (vote_address, current, _) = &mut balances[i];
+            moves.push(PlannedMove {
+                vote_address: *vote_address,
+                from_lamports: *current,
+                to_lamports: *current + amount,
+            });
+            *current += amount;
+            activation_limit -= amount;
+            reserve_lamports -= amount;
+        }
+
+        if moves.is_empty() {
+            break;
+        }
+        epochs.push(EpochPlan {
+            epoch: epoch + offset,
+            moves,
+        });
+    }
+
+    let unreachable_lamports = balances
+        .iter()
+        .filter_map(|(vote_address, current, target)| {
+            let distance = current.max(target) - current.min(target);
+            if distance >= min_move_lamports {
+                Some((*vote_address, distance))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    RebalancePlan {
+        epochs,
+        unreachable_lamports,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, std::collections::HashSet};
+
+    #[test]
+    fn test_plan() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+
+        // 400 lamports in the pool, at most 100 activated and 100 deactivated per epoch
+        let schedule = plan(10, 0, &[(a, 300, 50), (b, 100, 150), (c, 0, 200)], 25., 10);
+
+        // `b` and `c` wait for the stake removed from `a` to cool down
+        assert_eq!(
+            schedule.epochs[0],
+            EpochPlan {
+                epoch: 10,
+                moves: vec![PlannedMove {
+                    vote_address: a,
+                    from_lamports: 300,
+                    to_lamports: 200,
+                }],
+            }
+        );
+        assert_eq!(schedule.epochs[1].epoch, 11);
+        assert_eq!(schedule.epochs[1].stake_removed_lamports(), 100);
+        assert_eq!(schedule.epochs[1].stake_added_lamports(), 100);
+        assert_eq!(schedule.epochs.len(), 4);
+        assert!(schedule.unreachable_lamports.is_empty());
+        for epoch_plan in &schedule.epochs {
+            assert!(epoch_plan.stake_added_lamports() <= 100);
+            assert!(epoch_plan.stake_removed_lamports() <= 100);
+            // One movement per validator per epoch
+            let vote_addresses = epoch_plan
+                .moves
+                .iter()
+                .map(|m| m.vote_address)
+                .collect::<HashSet<_>>();
+            assert_eq!(vote_addresses.len(), epoch_plan.moves.len());
+        }
+        assert_eq!(
+            schedule.first_epoch_balances(),
+            vec![(a, 200)].into_iter().collect()
+        );
+
+        // Stake the reserve can never provide is reported rather than planned
+        let schedule = plan(10, 0, &[(a, 100, 100), (b, 0, 50)], 25., 10);
+        assert!(schedule.epochs.is_empty());
+        assert_eq!(
+            schedule.unreachable_lamports,
+            vec![(b, 50)].into_iter().collect()
+        );
+    }
+}
@@ -0,0 +1,478 @@
+//! Rendering of the epoch reports from Handlebars templates. The built-in templates produce the
+//! default markdown, and operators override them with their own to change the branding, wording
+//! or language of the published reports. The templates only lay out the report contexts below,
+//! every figure is formatted before it reaches them
+use {
+    crate::{
+        data_center_movement::{self, DataCenterMovement, Movement},
+        db::EpochClassificationV1,
+        streaks::StreakLeaderboard,
+    },
+    handlebars::Handlebars,
+    log::*,
+    serde::Serialize,
+    solana_sdk::{clock::Epoch, native_token::Sol, pubkey::Pubkey},
+    std::{collections::HashMap, error, fs, path::Path},
+};
+
+/// File name of the cluster report template, in the template directory
+pub const CLUSTER_TEMPLATE: &str = "cluster.md.hbs";
+
+/// File name of the validator report template, in the template directory
+pub const VALIDATOR_TEMPLATE: &str = "validator.md.hbs";
+
+const DEFAULT_CLUSTER_TEMPLATE: &str = include_str!("templates/cluster.md.hbs");
+const DEFAULT_VALIDATOR_TEMPLATE: &str = include_str!("templates/validator.md.hbs");
+
+#[derive(Debug, Default, Serialize)]
+pub struct MovementRow {
+    pub location: String,
+    pub gained: String,
+    pub lost: String,
+    pub net: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StreakRow {
+    pub validator: String,
+    pub stake_level: Option<String>,
+    pub epochs: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CreditsRow {
+    pub validator: String,
+    pub stake_level: String,
+    pub vote_credits: u64,
+    pub cluster_percentile: String,
+    pub pool_percentile: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EpochReport {
+    pub epoch: Epoch,
+    pub notes: Vec<String>,
+
+    /// Annotations of the operators on the epoch, see `annotations`
+    pub annotations: Vec<String>,
+    pub top_data_centers: usize,
+    pub movement_by_country: Vec<MovementRow>,
+    pub movement_by_data_center: Vec<MovementRow>,
+
+    /// Streak leaderboard, only for the latest epoch
+    pub delinquency_streaks: Vec<StreakRow>,
+    pub criteria_failure_streaks: Vec<StreakRow>,
+    pub recovered: Vec<StreakRow>,
+
+    pub credits: Vec<CreditsRow>,
+}
+
+/// Context of the cluster report, newest epoch first
+#[derive(Debug, Default, Serialize)]
+pub struct ClusterReport {
+    pub cluster: String,
+    pub epochs: Vec<EpochReport>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VoteCredits {
+    pub credits: u64,
+    pub cluster_percentile: String,
+    pub pool_percentile: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ValidatorEpochReport {
+    pub epoch: Epoch,
+    pub stake_state_reason: String,
+    pub stake_level: String,
+
+    /// Epochs at the current stake level, if more than one
+    pub stake_state_streak: Option<usize>,
+    pub vote_address: String,
+    pub reputation: Option<String>,
+    pub vote_credits: Option<VoteCredits>,
+    pub projection_epochs: usize,
+    pub projected_effective_stake: Vec<String>,
+    pub time_weighted_stake: Option<String>,
+
+    /// Consecutive epochs that required a stake movement, if more than one
+    pub off_target_epochs: Option<usize>,
+    pub data_center: Option<String>,
+
+    /// Data centers the validator resided in, if other than its current data center
+    pub resident_data_centers: Option<String>,
+    pub notes: Vec<String>,
+}
+
+/// Context of the report of a validator, newest epoch first
+#[derive(Debug, Default, Serialize)]
+pub struct ValidatorReport {
+    pub cluster: String,
+    pub identity: String,
+    pub epochs: Vec<ValidatorEpochReport>,
+}
+
+pub fn signed_sol(lamports: i64) -> String {
+    if lamports < 0 {
+        format!("-{}", Sol(-lamports as u64))
+    } else {
+        format!("+{}", Sol(lamports as u64))
+    }
+}
+
+fn movement_row(location: String, movement: &Movement) -> MovementRow {
+    MovementRow {
+        location,
+        gained: Sol(movement.gained_lamports).to_string(),
+        lost: Sol(movement.lost_lamports).to_string(),
+        net: signed_sol(movement.net_lamports()),
+    }
+}
+
+fn streak_rows(streaks: &[(Pubkey, usize)]) -> Vec<StreakRow> {
+    streaks
+        .iter()
+        .map(|(identity, epochs)| StreakRow {
+            validator: identity.to_string(),
+            stake_level: None,
+            epochs: *epochs,
+        })
+        .collect()
+}
+
+/// The cluster report of `history`, newest first, and the report of every validator classified
+/// in it, by identity
+pub fn reports(
+    cluster: &str,
+    history: &[(Epoch, EpochClassificationV1)],
+    streak_leaderboard: &StreakLeaderboard,
+) -> (ClusterReport, HashMap<Pubkey, ValidatorReport>) {
+    let latest_epoch = history.first().map(|(epoch, _)| *epoch);
+    let mut cluster_report = ClusterReport {
+        cluster: cluster.to_string(),
+        epochs: vec![],
+    };
+    let mut validator_reports = HashMap::<Pubkey, ValidatorReport>::new();
+
+    for (epoch, epoch_classification) in history {
+        let movement = data_center_movement::summarize(epoch_classification);
+        let mut epoch_report = EpochReport {
+            epoch: *epoch,
+            notes: epoch_classification.notes.clone(),
+            top_data_centers: data_center_movement::TOP_DATA_CENTERS,
+            ..EpochReport::default()
+        };
+        if movement != DataCenterMovement::default() {
+            epoch_report.movement_by_country = movement
+                .by_country
+                .iter()
+                .map(|(country, movement)| movement_row(country.clone(), movement))
+                .collect();
+            epoch_report.movement_by_data_center = movement
+                .by_data_center
+                .iter()
+                .take(data_center_movement::TOP_DATA_CENTERS)
+                .map(|(data_center, movement)| movement_row(data_center.to_string(), movement))
+                .collect();
+        }
+        if Some(*epoch) == latest_epoch {
+            epoch_report.delinquency_streaks = streak_rows(&streak_leaderboard.delinquency);
+            epoch_report.criteria_failure_streaks =
+                streak_rows(&streak_leaderboard.criteria_failure);
+            epoch_report.recovered = streak_leaderboard
+                .recovered
+                .iter()
+                .map(|(identity, stake_state, epochs)| StreakRow {
+                    validator: identity.to_string(),
+                    stake_level: Some(format!("{:?}", stake_state)),
+                    epochs: *epochs,
+                })
+                .collect();
+        }
+
+        if let Some(validator_classifications) = &epoch_classification.validator_classifications {
+            let mut validator_classifications =
+                validator_classifications.iter().collect::<Vec<_>>();
+            validator_classifications.sort_by(|a, b| a.0.cmp(b.0));
+
+            epoch_report.credits = validator_classifications
+                .iter()
+                .filter_map(|(identity, classification)| {
+                    Some(CreditsRow {
+                        validator: identity.to_string(),
+                        stake_level: format!("{:?}", classification.stake_state),
+                        vote_credits: classification.epoch_credits?,
+                        cluster_percentile: format!(
+                            "{:.1}",
+                            classification.cluster_credits_percentile?
+                        ),
+                        pool_percentile: format!("{:.1}", classification.pool_credits_percentile?),
+                    })
+                })
+                .collect();
+
+            for (identity, classification) in validator_classifications {
+                let stake_state_streak = classification.stake_state_streak();
+                let vote_credits = match (
+                    classification.epoch_credits,
+                    classification.cluster_credits_percentile,
+                    classification.pool_credits_percentile,
+                ) {
+                    (
+                        Some(epoch_credits),
+                        Some(cluster_credits_percentile),
+                        Some(pool_credits_percentile),
+                    ) => Some(VoteCredits {
+                        credits: epoch_credits,
+                        cluster_percentile: format!("{:.1}", cluster_credits_percentile),
+                        pool_percentile: format!("{:.1}", pool_credits_percentile),
+                    }),
+                    _ => None,
+                };
+                let (data_center, resident_data_centers) = match (
+                    &classification.current_data_center,
+                    &classification.data_center_residency,
+                ) {
+                    (Some(current_data_center), Some(data_center_residency)) => {
+                        let resident_data_centers = if data_center_residency.len() > 1
+                            || (data_center_residency.len() == 1
+                                && !data_center_residency.contains_key(current_data_center))
+                        {
+                            Some(
+                                data_center_residency
+                                    .keys()
+                                    .map(|data_center| data_center.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(","),
+                            )
+                        } else {
+                            None
+                        };
+                        (Some(current_data_center.to_string()), resident_data_centers)
+                    }
+                    _ => (None, None),
+                };
+                let projected_effective_stake = classification
+                    .projected_effective_stake
+                    .iter()
+                    .flatten()
+                    .map(|lamports| Sol(*lamports).to_string())
+                    .collect::<Vec<_>>();
+
+                validator_reports
+                    .entry(*identity)
+                    .or_insert_with(|| ValidatorReport {
+                        cluster: cluster.to_string(),
+                        identity: identity.to_string(),
+                        epochs: vec![],
+                    })
+                    .epochs
+                    .push(ValidatorEpochReport {
+                        epoch: *epoch,
+                        stake_state_reason: classification.stake_state_reason.clone(),
+                        stake_level: format!("{:?}", classification.stake_state),
+                        stake_state_streak: Some(stake_state_streak).filter(|streak| *streak > 1),
+                        vote_address: classification.vote_address.to_string(),
+                        reputation: classification
+                            .reputation
+                            .map(|reputation| format!("{:.2}", reputation)),
+                        vote_credits,
+                        projection_epochs: projected_effective_stake.len(),
+                        projected_effective_stake,
+                        time_weighted_stake: classification
+                            .time_weighted_stake
+                            .map(|lamports| Sol(lamports).to_string()),
+                        off_target_epochs: classification
+                            .off_target_epochs
+                            .filter(|off_target_epochs| *off_target_epochs > 1),
+                        data_center,
+                        resident_data_centers,
+                        notes: classification.notes.clone(),
+                    });
+            }
+        }
+        cluster_report.epochs.push(epoch_report);
+    }
+    (cluster_report, validator_reports)
+}
+
+pub struct ReportTemplates {
+    handlebars: Handlebars<'static>,
+}
+
+impl ReportTemplates {
+    /// The built-in templates, replaced by the `CLUSTER_TEMPLATE` and `VALIDATOR_TEMPLATE` files
+    /// of `template_dir` that exist
+    pub fn new(template_dir: Option<&Path>) -> Result<Self, Box<dyn error::Error>> {
+        let mut handlebars = Handlebars::new();
+        // Reports are markdown, not HTML
+        handlebars.register_escape_fn(handlebars::no_escape);
+
+        for (name, default_template) in &[
+            (CLUSTER_TEMPLATE, DEFAULT_CLUSTER_TEMPLATE),
+            (VALIDATOR_TEMPLATE, DEFAULT_VALIDATOR_TEMPLATE),
+        ] {
+            let path = template_dir.map(|template_dir| template_dir.join(name));
+            let template = match path {
+                Some(ref path) if path.exists() => {
+                    info!("Using report template {}", path.display());
+                    fs::read_to_string(path).map_err(|err| {
+                        format!("Unable to read report template {}: {}", path.display(), err)
+                    })?
+                }
+                _ => default_template.to_string(),
+            };
+            handlebars
+                .register_template_string(name, template)
+                .map_err(|err| format!("Invalid report template {}: {}", name, err))?;
+        }
+        Ok(Self { handlebars })
+    }
+
+    pub fn render_cluster(&self, report: &ClusterReport) -> Result<String, Box<dyn error::Error>> {
+        Ok(self.handlebars.render(CLUSTER_TEMPLATE, report)?)
+    }
+
+    pub fn render_validator(
+        &self,
+        report: &ValidatorReport,
+    ) -> Result<String, Box<dyn error::Error>> {
+        Ok(self.handlebars.render(VALIDATOR_TEMPLATE, report)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            data_center_info::DataCenterId,
+            db::ValidatorClassification,
+            generic_stake_pool::{StakePoolSummary, ValidatorStakeState},
+            streaks,
+        },
+    };
+
+    fn history() -> (Pubkey, Vec<(Epoch, EpochClassificationV1)>) {
+        let identity = Pubkey::new_unique();
+        let data_center = "16509-US-Ashburn".parse::<DataCenterId>().unwrap();
+        let classification = |stake_state, reason: &str| ValidatorClassification {
+            identity,
+            vote_address: Pubkey::new_unique(),
+            stake_state,
+            stake_state_reason: reason.to_string(),
+            current_data_center: Some(data_center.clone()),
+            data_center_residency: Some(vec![(data_center.clone(), 1)].into_iter().collect()),
+            epoch_credits: Some(1_000),
+            cluster_credits_percentile: Some(95.0),
+            pool_credits_percentile: Some(50.0),
+            notes: vec!["Validator note".to_string()],
+            ..ValidatorClassification::default()
+        };
+        let epoch_classification = |classification: ValidatorClassification| {
+            let stake_movement_lamports =
+                vec![(classification.vote_address, 5)].into_iter().collect();
+            EpochClassificationV1 {
+                validator_classifications: Some(
+                    vec![(identity, classification)].into_iter().collect(),
+                ),
+                notes: vec!["Epoch note".to_string()],
+                stake_pool_summary: Some(StakePoolSummary {
+                    stake_movement_lamports,
+                    ..StakePoolSummary::default()
+                }),
+                ..EpochClassificationV1::default()
+            }
+        };
+        let history = vec![
+            (
+                11,
+                epoch_classification(classification(ValidatorStakeState::Bonus, "good")),
+            ),
+            (
+                10,
+                epoch_classification(classification(
+                    ValidatorStakeState::None,
+                    "insufficient vote credits: 10",
+                )),
+            ),
+        ];
+        (identity, history)
+    }
+
+    #[test]
+    fn test_render_default_templates() {
+        let (identity, history) = history();
+        let (mut cluster_report, validator_reports) = reports(
+            "Mainnet",
+            &history,
+            &streaks::leaderboard(&history, streaks::LEADERBOARD_SIZE),
+        );
+        assert_eq!(cluster_report.epochs.len(), 2);
+        cluster_report.epochs[1].annotations = vec!["cluster outage, criteria relaxed".to_string()];
+        assert_eq!(cluster_report.epochs[0].recovered.len(), 1);
+        assert!(cluster_report.epochs[1].recovered.is_empty());
+        assert_eq!(
+            cluster_report.epochs[0].movement_by_country[0].net,
+            "+◎0.000000005"
+        );
+
+        let templates = ReportTemplates::new(None).unwrap();
+        let cluster_markdown = templates.render_cluster(&cluster_report).unwrap();
+        let lines = cluster_markdown.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "### Epoch 11");
+        assert_eq!(lines[1], "* Epoch note");
+        assert_eq!(lines[2], "");
+        assert_eq!(lines[3], "#### Stake movement by country");
+        assert!(lines.contains(&"### Epoch 10"));
+        assert!(lines.contains(&"#### Operator annotations"));
+        assert!(lines.contains(&"* cluster outage, criteria relaxed"));
+        assert!(lines.contains(&"#### Newly recovered"));
+        assert!(lines.contains(&format!("| {} | Bonus | 1 |", identity).as_str()));
+        assert!(lines.contains(&format!("| {} | Bonus | 1000 | 95.0 | 50.0 |", identity).as_str()));
+
+        let validator_markdown = templates
+            .render_validator(&validator_reports[&identity])
+            .unwrap();
+        let lines = validator_markdown.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "### [[Mainnet Epoch 11|Mainnet#Epoch-11]]");
+        assert_eq!(lines[1], "good");
+        assert_eq!(lines[2], "* Stake level: **Bonus**");
+        assert!(lines.contains(
+            &"* Vote credits: 1000 (95.0 percentile of the cluster, 50.0 percentile of the pool)"
+        ));
+        assert!(lines.contains(&"* Data Center: 16509-US-Ashburn"));
+        assert!(!validator_markdown.contains("Resident Data Center"));
+        assert!(lines.contains(&"### [[Mainnet Epoch 10|Mainnet#Epoch-10]]"));
+    }
+
+    #[test]
+    fn test_template_override() {
+        let template_dir = std::env::temp_dir().join(format!("templates-{}", Pubkey::new_unique()));
+        fs::create_dir_all(&template_dir).unwrap();
+        fs::write(
+            template_dir.join(CLUSTER_TEMPLATE),
+            "{{#each epochs}}Époque {{epoch}}\n{{/each}}",
+        )
+        .unwrap();
+
+        let (identity, history) = history();
+        let (cluster_report, validator_reports) =
+            reports("Mainnet", &history, &StreakLeaderboard::default());
+        let templates = ReportTemplates::new(Some(&template_dir)).unwrap();
+        assert!(templates
+            .render_cluster(&cluster_report)
+            .unwrap()
+            .starts_with("Époque 11"));
+        // The validator template was not overridden
+        assert!(templates
+            .render_validator(&validator_reports[&identity])
+            .unwrap()
+            .starts_with("### [[Mainnet Epoch 11"));
+
+        fs::write(template_dir.join(VALIDATOR_TEMPLATE), "{{#each epochs}").unwrap();
+        assert!(ReportTemplates::new(Some(&template_dir)).is_err());
+        fs::remove_dir_all(&template_dir).unwrap();
+    }
+}
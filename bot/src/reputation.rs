@@ -0,0 +1,109 @@
+//! Reputation score of the validators. The score carries the stake states of the previous epochs
+//! over into the classification, so that a validator penalized recently is held back from bonus
+//! stake, and ranks the Baseline validators funded first when the pool falls short of the baseline
+use {
+    crate::generic_stake_pool::ValidatorStakeState,
+    serde::{Deserialize, Serialize},
+};
+
+/// Outcome of the reputation for a validator in an epoch
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ReputationScore {
+    pub reputation: f64,
+
+    /// Stake state of the validator, held at the Baseline if its reputation is too low for the
+    /// Bonus stake level
+    pub stake_state: ValidatorStakeState,
+
+    /// Failure of the bonus reputation criterion, if the reputation is below the minimum
+    pub failure: Option<String>,
+}
+
+/// Reputation score after an epoch classified `stake_state`: `previous_reputation` decays to
+/// `decay_percentage` of itself, and the stake state adds +1 for Bonus, 0 for Baseline and -1 for
+/// None
+pub fn next_reputation(
+    stake_state: ValidatorStakeState,
+    previous_reputation: f64,
+    decay_percentage: usize,
+) -> f64 {
+    previous_reputation * decay_percentage as f64 / 100.
+        + match stake_state {
+            ValidatorStakeState::None => -1.,
+            ValidatorStakeState::Baseline => 0.,
+            ValidatorStakeState::Bonus => 1.,
+        }
+}
+
+/// Score a validator classified `stake_state` by the staking criteria of the epoch. A validator
+/// whose reputation falls below `min_bonus_reputation` fails the bonus reputation criterion, and
+/// is held at the Baseline if it was classified Bonus
+pub fn score(
+    stake_state: ValidatorStakeState,
+    previous_reputation: f64,
+    decay_percentage: usize,
+    min_bonus_reputation: Option<f64>,
+) -> ReputationScore {
+    let reputation = next_reputation(stake_state, previous_reputation, decay_percentage);
+    let failure = min_bonus_reputation
+        .filter(|min_bonus_reputation| reputation < *min_bonus_reputation)
+        .map(|min_bonus_reputation| {
+            format!(
+                "reputation {:.2} is below the {:.2} required for bonus stake",
+                reputation, min_bonus_reputation
+            )
+        });
+    ReputationScore {
+        reputation,
+        stake_state: match stake_state {
+            ValidatorStakeState::Bonus if failure.is_some() => ValidatorStakeState::Baseline,
+            _ => stake_state,
+        },
+        failure,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_next_reputation() {
+        let mut reputation = 0.;
+        for _ in 0..3 {
+            reputation = next_reputation(ValidatorStakeState::Bonus, reputation, 50);
+        }
+        assert!((reputation - 1.75).abs() < f64::EPSILON);
+
+        // A penalty takes a couple of good epochs to decay away
+        reputation = next_reputation(ValidatorStakeState::None, reputation, 50);
+        assert!((reputation + 0.125).abs() < f64::EPSILON);
+        reputation = next_reputation(ValidatorStakeState::Baseline, reputation, 50);
+        assert!(reputation < 0.);
+        reputation = next_reputation(ValidatorStakeState::Bonus, reputation, 50);
+        assert!(reputation > 0.);
+    }
+
+    #[test]
+    fn test_score() {
+        let reputation_score = score(ValidatorStakeState::Bonus, -4., 50, Some(0.));
+        assert!((reputation_score.reputation + 1.).abs() < f64::EPSILON);
+        assert_eq!(reputation_score.stake_state, ValidatorStakeState::Baseline);
+        assert_eq!(
+            reputation_score.failure.as_deref(),
+            Some("reputation -1.00 is below the 0.00 required for bonus stake")
+        );
+
+        let reputation_score = score(ValidatorStakeState::None, -4., 50, Some(0.));
+        assert_eq!(reputation_score.stake_state, ValidatorStakeState::None);
+        assert!(reputation_score.failure.is_some());
+
+        let reputation_score = score(ValidatorStakeState::Bonus, 2., 50, Some(0.));
+        assert_eq!(reputation_score.stake_state, ValidatorStakeState::Bonus);
+        assert_eq!(reputation_score.failure, None);
+        assert_eq!(
+            score(ValidatorStakeState::Bonus, -4., 50, None).stake_state,
+            ValidatorStakeState::Bonus
+        );
+    }
+}
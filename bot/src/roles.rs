@@ -0,0 +1,222 @@
+use {
+    solana_sdk::{
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+    },
+    spl_stake_pool::state::StakePool,
+    std::fmt,
+};
+
+/// Authorities of a stake pool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    /// Controls the pool's fees and authorities
+    Manager,
+    /// Controls which validators are in the pool and how the stake is distributed among them
+    Staker,
+    /// Pays for the accounts created on behalf of the pool
+    Funder,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Role::Manager => "manager",
+                Role::Staker => "staker",
+                Role::Funder => "funder",
+            }
+        )
+    }
+}
+
+/// Stake pool operations performed by the bot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    AddValidator,
+    RemoveValidator,
+    CreateValidatorStakeAccount,
+    AdjustValidatorStake,
+    SetFee,
+    SetManager,
+    SetStaker,
+}
+
+impl Operation {
+    /// Roles allowed to authorize the operation, in order of preference
+    pub fn roles(&self) -> &'static [Role] {
+        match self {
+            Operation::AddValidator
+            | Operation::RemoveValidator
+            | Operation::CreateValidatorStakeAccount
+            | Operation::AdjustValidatorStake => &[Role::Staker],
+            Operation::SetFee | Operation::SetManager => &[Role::Manager],
+            Operation::SetStaker => &[Role::Manager, Role::Staker],
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Operation::AddValidator => "add validator",
+                Operation::RemoveValidator => "remove validator",
+                Operation::CreateValidatorStakeAccount => "create validator stake account",
+                Operation::AdjustValidatorStake => "adjust validator stake",
+                Operation::SetFee => "set fee",
+                Operation::SetManager => "set manager",
+                Operation::SetStaker => "set staker",
+            }
+        )
+    }
+}
+
+/// The keypairs configured for each role of a stake pool. The staker is required, the manager
+/// is only needed for manager operations and the staker pays for accounts if no funder is given
+#[derive(Debug)]
+pub struct StakePoolRoles {
+    pub staker: Keypair,
+    pub manager: Option<Keypair>,
+    pub funder: Option<Keypair>,
+}
+
+impl StakePoolRoles {
+    pub fn new(staker: Keypair) -> Self {
+        Self {
+            staker,
+            manager: None,
+            funder: None,
+        }
+    }
+
+    /// The keypair configured for `role`, if any
+    pub fn keypair(&self, role: Role) -> Option<&Keypair> {
+        match role {
+            Role::Manager => self.manager.as_ref(),
+            Role::Staker => Some(&self.staker),
+            Role::Funder => Some(self.funder.as_ref().unwrap_or(&self.staker)),
+        }
+    }
+
+    /// Check that the configured manager and staker keypairs are the authorities of
+    /// `stake_pool`
+    pub fn verify(
+        &self,
+        stake_pool_address: &Pubkey,
+        stake_pool: &StakePool,
+    ) -> Result<(), String> {
+        let check = |role: Role, authority: &Pubkey| match self.keypair(role) {
+            Some(keypair) if keypair.pubkey() != *authority => Err(format!(
+                "{} is not the {} of stake pool {}, the {} is {}",
+                keypair.pubkey(),
+                role,
+                stake_pool_address,
+                role,
+                authority
+            )),
+            _ => Ok(()),
+        };
+        check(Role::Staker, &stake_pool.staker)?;
+        check(Role::Manager, &stake_pool.manager)
+    }
+
+    /// The keypair that authorizes `operation`
+    pub fn authority_for(&self, operation: Operation) -> Result<&Keypair, String> {
+        operation
+            .roles()
+            .iter()
+            .find_map(|role| self.keypair(*role))
+            .ok_or_else(|| {
+                format!(
+                    "{} requires the {} keypair, which is not configured",
+                    operation,
+                    operation
+                        .roles()
+                        .iter()
+                        .map(|role| role.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_authority_for() {
+        let roles = StakePoolRoles::new(Keypair::new());
+        let staker = roles.staker.pubkey();
+        assert_eq!(
+            roles
+                .authority_for(Operation::AddValidator)
+                .unwrap()
+                .pubkey(),
+            staker
+        );
+        assert_eq!(
+            roles.authority_for(Operation::SetStaker).unwrap().pubkey(),
+            staker
+        );
+        assert_eq!(
+            roles.authority_for(Operation::SetFee).unwrap_err(),
+            "set fee requires the manager keypair, which is not configured"
+        );
+        assert_eq!(roles.keypair(Role::Funder).unwrap().pubkey(), staker);
+
+        let roles = StakePoolRoles {
+            manager: Some(Keypair::new()),
+            funder: Some(Keypair::new()),
+            ..roles
+        };
+        let manager = roles.manager.as_ref().unwrap().pubkey();
+        assert_eq!(
+            roles.authority_for(Operation::SetFee).unwrap().pubkey(),
+            manager
+        );
+        assert_eq!(
+            roles.authority_for(Operation::SetStaker).unwrap().pubkey(),
+            manager
+        );
+        assert_ne!(roles.keypair(Role::Funder).unwrap().pubkey(), staker);
+    }
+
+    #[test]
+    fn test_verify() {
+        let roles = StakePoolRoles::new(Keypair::new());
+        let stake_pool_address = Pubkey::new_unique();
+        let mut stake_pool = StakePool {
+            staker: roles.staker.pubkey(),
+            manager: Pubkey::new_unique(),
+            ..StakePool::default()
+        };
+        assert_eq!(roles.verify(&stake_pool_address, &stake_pool), Ok(()));
+
+        let roles = StakePoolRoles {
+            manager: Some(Keypair::new()),
+            ..roles
+        };
+        assert_eq!(
+            roles.verify(&stake_pool_address, &stake_pool),
+            Err(format!(
+                "{} is not the manager of stake pool {}, the manager is {}",
+                roles.manager.as_ref().unwrap().pubkey(),
+                stake_pool_address,
+                stake_pool.manager
+            ))
+        );
+
+        stake_pool.manager = roles.manager.as_ref().unwrap().pubkey();
+        assert_eq!(roles.verify(&stake_pool_address, &stake_pool), Ok(()));
+
+        stake_pool.staker = Pubkey::new_unique();
+        assert!(roles.verify(&stake_pool_address, &stake_pool).is_err());
+    }
+}
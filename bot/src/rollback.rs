@@ -0,0 +1,210 @@
+//! Rollback of the stake movements issued by a run, such as one made with a bad configuration.
+//! Stake moves through transient stake accounts that only settle at the next epoch boundary, so
+//! nothing can be undone during the epoch of the run: its activating and deactivating stake can
+//! neither be cancelled nor moved again. From the next epoch on, the stake that was added is
+//! removed again, going through a cooldown, and the stake that was removed is added again from the
+//! reserve, going through a warmup
+use {
+    crate::stake_pool::StakeOp,
+    solana_sdk::{clock::Epoch, native_token::Sol, pubkey::Pubkey},
+    std::collections::{HashMap, HashSet},
+};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RollbackPlan {
+    /// Inverse stake movements, decreases first so that no increase waits on them
+    pub stake_ops: Vec<StakeOp>,
+
+    /// Movements that cannot be rolled back now, in full or in part, and why
+    pub notes: Vec<String>,
+}
+
+/// Plan the inverse of the stake `movements` issued in `run_epoch`, by vote address: added
+/// (positive) or removed (negative). `balances` holds the current stake of each validator in the
+/// pool, which keeps at least `min_balance`, and `busy` the validators whose transient stake has
+/// not settled yet. Increases are funded from the `reserve_lamports` available, and movements
+/// under `min_lamports` are not worth a transaction
+#[allow(clippy::too_many_arguments)]
+pub fn plan(
+    movements: &HashMap<Pubkey, i64>,
+    run_epoch: Epoch,
+    current_epoch: Epoch,
+    balances: &HashMap<Pubkey, u64>,
+    busy: &HashSet<Pubkey>,
+    min_balance: u64,
+    min_lamports: u64,
+    mut reserve_lamports: u64,
+) -> RollbackPlan {
+    let mut movements = movements
+        .iter()
+        .filter(|(_, lamports)| **lamports != 0)
+        .map(|(vote_address, lamports)| (*vote_address, *lamports))
+        .collect::<Vec<_>>();
+    // Decreases first, then in a stable order
+    movements.sort_by_key(|(vote_address, lamports)| (*lamports < 0, *vote_address));
+
+    let mut plan = RollbackPlan::default();
+    for (vote_address, lamports) in movements {
+        let movement = if lamports > 0 {
+            format!("{} added to {}", Sol(lamports as u64), vote_address)
+        } else {
+            format!("{} removed from {}", Sol(-lamports as u64), vote_address)
+        };
+        if current_epoch <= run_epoch {
+            plan.notes.push(format!(
+                "{} is still settling, it can be rolled back from epoch {}",
+                movement,
+                run_epoch + 1
+            ));
+            continue;
+        }
+        let balance = match balances.get(&vote_address) {
+            Some(balance) => *balance,
+            None => {
+                plan.notes.push(format!(
+                    "{} cannot be rolled back: no longer in the pool",
+                    movement
+                ));
+                continue;
+            }
+        };
+        if busy.contains(&vote_address) {
+            plan.notes.push(format!(
+                "{} cannot be rolled back yet: the validator has unsettled transient stake",
+                movement
+            ));
+            continue;
+        }
+
+        let (stake_op, lamports, limit) = if lamports > 0 {
+            let lamports = lamports as u64;
+            let removable = balance.saturating_sub(min_balance);
+            (
+                StakeOp::Decrease {
+                    vote_address,
+                    lamports: lamports.min(removable),
+                },
+                lamports,
+                "the validator must keep its minimum stake",
+            )
+        } else {
+            let lamports = -lamports as u64;
+            (
+                StakeOp::Increase {
+                    vote_address,
+                    lamports: lamports.min(reserve_lamports),
+                },
+                lamports,
+                "the reserve cannot fund it",
+            )
+        };
+        let rolled_back = match stake_op {
+            StakeOp::Increase { lamports, .. } | StakeOp::Decrease { lamports, .. } => lamports,
+        };
+        if rolled_back < min_lamports {
+            plan.notes
+                .push(format!("{} cannot be rolled back: {}", movement, limit));
+            continue;
+        }
+        if rolled_back < lamports {
+            plan.notes.push(format!(
+                "{} is only rolled back by {}: {}",
+                movement,
+                Sol(rolled_back),
+                limit
+            ));
+        }
+        if let StakeOp::Increase { .. } = stake_op {
+            reserve_lamports -= rolled_back;
+        }
+        plan.stake_ops.push(stake_op);
+    }
+    plan
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_sdk::native_token::LAMPORTS_PER_SOL};
+
+    #[test]
+    fn test_plan() {
+        let sol = |sol: u64| sol * LAMPORTS_PER_SOL;
+        let added = Pubkey::new_unique();
+        let removed = Pubkey::new_unique();
+        let departed = Pubkey::new_unique();
+        let movements = vec![
+            (added, sol(10) as i64),
+            (removed, -(sol(20) as i64)),
+            (departed, sol(5) as i64),
+        ]
+        .into_iter()
+        .collect();
+        let balances = vec![(added, sol(30)), (removed, sol(10))]
+            .into_iter()
+            .collect();
+        let no_busy = HashSet::new();
+
+        // Nothing settled during the epoch of the run
+        let plan_now = plan(
+            &movements,
+            7,
+            7,
+            &balances,
+            &no_busy,
+            sol(1),
+            sol(1),
+            sol(100),
+        );
+        assert!(plan_now.stake_ops.is_empty());
+        assert_eq!(plan_now.notes.len(), 3);
+
+        let next_epoch = plan(
+            &movements,
+            7,
+            8,
+            &balances,
+            &no_busy,
+            sol(1),
+            sol(1),
+            sol(100),
+        );
+        assert_eq!(
+            next_epoch.stake_ops,
+            vec![
+                StakeOp::Decrease {
+                    vote_address: added,
+                    lamports: sol(10),
+                },
+                StakeOp::Increase {
+                    vote_address: removed,
+                    lamports: sol(20),
+                },
+            ]
+        );
+        assert_eq!(next_epoch.notes.len(), 1);
+
+        // The reserve only covers part of the removed stake
+        let short = plan(
+            &movements,
+            7,
+            8,
+            &balances,
+            &no_busy,
+            sol(1),
+            sol(1),
+            sol(15),
+        );
+        assert_eq!(
+            short.stake_ops[1],
+            StakeOp::Increase {
+                vote_address: removed,
+                lamports: sol(15),
+            }
+        );
+        assert_eq!(short.notes.len(), 2);
+
+        let busy = vec![added].into_iter().collect();
+        let busy = plan(&movements, 7, 8, &balances, &busy, sol(1), sol(1), sol(100));
+        assert_eq!(busy.stake_ops.len(), 1);
+    }
+}
@@ -1,6 +1,8 @@
 use {
+    crate::blockhash_service::BlockhashService,
     log::*,
     reqwest::StatusCode,
+    serde::{Deserialize, Serialize},
     solana_client::{
         client_error,
         rpc_client::RpcClient,
@@ -11,6 +13,7 @@ use {
         rpc_response::{RpcVoteAccountInfo, RpcVoteAccountStatus},
     },
     solana_sdk::{
+        account::Account,
         clock::Epoch,
         native_token::*,
         pubkey::Pubkey,
@@ -41,10 +44,14 @@ where
             let can_retry = reqwest_error.is_timeout()
                 || reqwest_error
                     .status()
-                    .map(|s| s == StatusCode::BAD_GATEWAY || s == StatusCode::GATEWAY_TIMEOUT)
+                    .map(|s| {
+                        s == StatusCode::BAD_GATEWAY
+                            || s == StatusCode::GATEWAY_TIMEOUT
+                            || s == StatusCode::TOO_MANY_REQUESTS
+                    })
                     .unwrap_or(false);
             if can_retry && retries > 0 {
-                info!("RPC request timeout, {} retries remaining", retries);
+                info!("RPC request failed, {} retries remaining", retries);
                 retries -= 1;
                 continue;
             }
@@ -92,38 +99,101 @@ pub fn simulate_transactions(
 pub struct SendAndConfirmTransactionResult {
     pub succeeded: HashSet<Signature>,
     pub failed: HashSet<Signature>,
+
+    /// Signature of each transaction, in the order given. Transactions handed over to be signed
+    /// elsewhere have the default signature
+    pub signatures: Vec<Signature>,
 }
 
-pub fn send_and_confirm_transactions(
+/// Sign `transactions` with `signers`, of which the first pays the transaction fees, then send
+/// them and wait for them to complete. Transactions are signed with the blockhashes of
+/// `blockhash_service` if given, otherwise with a single blockhash fetched from `rpc_client`
+pub fn send_and_confirm_transactions_with_signers(
     rpc_client: &RpcClient,
+    blockhash_service: Option<&BlockhashService>,
     dry_run: bool,
     transactions: Vec<Transaction>,
-    authorized_staker: &Keypair,
+    signers: &[&Keypair],
 ) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>> {
-    let authorized_staker_balance = rpc_client.get_balance(&authorized_staker.pubkey())?;
+    send_and_confirm_transactions_via(
+        rpc_client,
+        blockhash_service,
+        dry_run,
+        transactions,
+        signers,
+        |transaction| {
+            rpc_client.send_transaction(transaction)?;
+            Ok(())
+        },
+    )
+}
+
+/// Like `send_and_confirm_transactions_with_signers`, submitting each signed transaction with
+/// `send` rather than the `sendTransaction` RPC method. Completion is still confirmed over RPC
+pub fn send_and_confirm_transactions_via<F>(
+    rpc_client: &RpcClient,
+    blockhash_service: Option<&BlockhashService>,
+    dry_run: bool,
+    transactions: Vec<Transaction>,
+    signers: &[&Keypair],
+    send: F,
+) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>>
+where
+    F: Fn(&Transaction) -> Result<(), Box<dyn error::Error>>,
+{
+    let fee_payer = signers.first().ok_or("No signers")?;
+    // The same keypair may fill several roles
+    let mut unique_signers: Vec<&Keypair> = vec![];
+    for signer in signers {
+        if !unique_signers.iter().any(|s| s.pubkey() == signer.pubkey()) {
+            unique_signers.push(*signer);
+        }
+    }
+
+    let fee_payer_balance = rpc_client.get_balance(&fee_payer.pubkey())?;
     info!(
-        "Authorized staker balance: {} SOL",
-        lamports_to_sol(authorized_staker_balance)
+        "Fee payer {} balance: {} SOL",
+        fee_payer.pubkey(),
+        lamports_to_sol(fee_payer_balance)
     );
 
-    let (blockhash, fee_calculator) = rpc_client.get_recent_blockhash()?;
+    let (mut blockhash, fee_calculator) = match blockhash_service {
+        Some(blockhash_service) => {
+            let recent_blockhash = blockhash_service.recent_blockhash();
+            (recent_blockhash.blockhash, recent_blockhash.fee_calculator)
+        }
+        None => rpc_client.get_recent_blockhash()?,
+    };
     info!("{} transactions to send", transactions.len());
 
     let required_fee = transactions.iter().fold(0, |fee, transaction| {
         fee + fee_calculator.calculate_fee(&transaction.message)
     });
     info!("Required fee: {} SOL", lamports_to_sol(required_fee));
-    if required_fee > authorized_staker_balance {
-        return Err("Authorized staker has insufficient funds".into());
+    if required_fee > fee_payer_balance {
+        return Err(format!("Fee payer {} has insufficient funds", fee_payer.pubkey()).into());
     }
 
-    let mut pending_signatures = HashSet::new();
+    let mut signatures = vec![];
+    // Blockhash that each pending transaction was signed with
+    let mut pending_signatures = HashMap::new();
     for mut transaction in transactions {
-        transaction.sign(&[authorized_staker], blockhash);
+        if let Some(blockhash_service) = blockhash_service {
+            if blockhash_service.is_near_expiry(&blockhash) {
+                let recent_blockhash = blockhash_service.recent_blockhash().blockhash;
+                info!(
+                    "Blockhash {} is near expiry, signing the remaining transactions with {}",
+                    blockhash, recent_blockhash
+                );
+                blockhash = recent_blockhash;
+            }
+        }
+        transaction.sign(&unique_signers, blockhash);
 
-        pending_signatures.insert(transaction.signatures[0]);
+        signatures.push(transaction.signatures[0]);
+        pending_signatures.insert(transaction.signatures[0], blockhash);
         if !dry_run {
-            rpc_client.send_transaction(&transaction)?;
+            send(&transaction)?;
         }
     }
 
@@ -134,25 +204,37 @@ pub fn send_and_confirm_transactions(
             break;
         }
 
-        let blockhash_expired = rpc_client
-            .get_fee_calculator_for_blockhash(&blockhash)?
-            .is_none();
-        if blockhash_expired {
+        let mut expired_blockhashes = HashSet::new();
+        for blockhash in pending_signatures.values().collect::<HashSet<_>>() {
+            if rpc_client
+                .get_fee_calculator_for_blockhash(blockhash)?
+                .is_none()
+            {
+                expired_blockhashes.insert(*blockhash);
+            }
+        }
+        if !expired_blockhashes.is_empty() {
+            let (expired_signatures, still_pending_signatures): (HashMap<_, _>, HashMap<_, _>) =
+                pending_signatures
+                    .into_iter()
+                    .partition(|(_, blockhash)| expired_blockhashes.contains(blockhash));
             error!(
-                "Blockhash {} expired with {} pending transactions",
-                blockhash,
-                pending_signatures.len()
+                "Blockhashes {:?} expired with {} pending transactions",
+                expired_blockhashes,
+                expired_signatures.len()
             );
-
-            for signature in pending_signatures.into_iter() {
-                failed_transactions.insert(signature);
-            }
-            break;
+            failed_transactions.extend(
+                expired_signatures
+                    .into_iter()
+                    .map(|(signature, _)| signature),
+            );
+            pending_signatures = still_pending_signatures;
+            continue;
         }
 
         let mut statuses = vec![];
         for pending_signatures_chunk in pending_signatures
-            .iter()
+            .keys()
             .cloned()
             .collect::<Vec<_>>()
             .chunks(MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS - 1)
@@ -170,8 +252,10 @@ pub fn send_and_confirm_transactions(
         }
         assert_eq!(statuses.len(), pending_signatures.len());
 
-        let mut still_pending_signatures = HashSet::new();
-        for (signature, status) in pending_signatures.into_iter().zip(statuses.into_iter()) {
+        let mut still_pending_signatures = HashMap::new();
+        for ((signature, blockhash), status) in
+            pending_signatures.into_iter().zip(statuses.into_iter())
+        {
             trace!("{}: status={:?}", signature, status);
             let completed = if dry_run {
                 Some(true)
@@ -193,7 +277,7 @@ pub fn send_and_confirm_transactions(
                     failed_transactions.insert(signature);
                 }
             } else {
-                still_pending_signatures.insert(signature);
+                still_pending_signatures.insert(signature, blockhash);
             }
         }
         pending_signatures = still_pending_signatures;
@@ -203,9 +287,11 @@ pub fn send_and_confirm_transactions(
     Ok(SendAndConfirmTransactionResult {
         succeeded: succeeded_transactions,
         failed: failed_transactions,
+        signatures,
     })
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct VoteAccountInfo {
     pub identity: Pubkey,
     pub vote_address: Pubkey,
@@ -213,6 +299,10 @@ pub struct VoteAccountInfo {
 
     /// Credits earned in the epoch
     pub epoch_credits: u64,
+
+    /// Stake activated on the vote account, from any source
+    #[serde(default)]
+    pub activated_stake: u64,
 }
 
 pub fn get_vote_account_info(
@@ -246,6 +336,7 @@ pub fn get_vote_account_info(
                  node_pubkey,
                  vote_pubkey,
                  epoch_credits,
+                 activated_stake,
                  ..
              }| {
                 let epoch_credits = if let Some((_last_epoch, credits, prev_credits)) =
@@ -263,20 +354,62 @@ pub fn get_vote_account_info(
                     vote_address,
                     commission: *commission,
                     epoch_credits,
+                    activated_stake: *activated_stake,
                 }
             },
         )
         .collect())
 }
 
-pub fn get_all_stake(
+/// Sample the vote accounts `samples` times, `sample_interval` apart, and return the average
+/// number of slots each vote account's last vote trailed the cluster tip by.
+///
+/// The cluster tip of a sample is the most recent vote observed across all current vote accounts.
+pub fn get_average_vote_lag(
     rpc_client: &RpcClient,
-    authorized_staker: Pubkey,
-) -> Result<(HashSet<Pubkey>, u64), Box<dyn error::Error>> {
-    let mut all_stake_addresses = HashSet::new();
-    let mut total_stake_balance = 0;
+    samples: usize,
+    sample_interval: Duration,
+) -> Result<HashMap<Pubkey, f64>, Box<dyn error::Error>> {
+    let mut total_vote_lag = HashMap::<Pubkey, (u64, u64)>::new();
+
+    for sample in 0..samples {
+        if sample > 0 {
+            sleep(sample_interval);
+        }
+        let RpcVoteAccountStatus {
+            current,
+            delinquent,
+        } = retry_rpc_operation(10, || rpc_client.get_vote_accounts())?;
+
+        let tip = current
+            .iter()
+            .map(|vote_account_info| vote_account_info.last_vote)
+            .max()
+            .unwrap_or_default();
+        trace!("vote lag sample {}: tip is slot {}", sample, tip);
+
+        for vote_account_info in current.iter().chain(delinquent.iter()) {
+            let vote_address = Pubkey::from_str(&vote_account_info.vote_pubkey)?;
+            let entry = total_vote_lag.entry(vote_address).or_default();
+            entry.0 += tip.saturating_sub(vote_account_info.last_vote);
+            entry.1 += 1;
+        }
+    }
+
+    Ok(total_vote_lag
+        .into_iter()
+        .map(|(vote_address, (total_lag, num_samples))| {
+            (vote_address, total_lag as f64 / num_samples as f64)
+        })
+        .collect())
+}
 
-    let all_stake_accounts = rpc_client.get_program_accounts_with_config(
+/// The stake accounts whose authorized staker is `authorized_staker`
+pub fn get_all_stake_accounts(
+    rpc_client: &RpcClient,
+    authorized_staker: Pubkey,
+) -> Result<Vec<(Pubkey, Account)>, Box<dyn error::Error>> {
+    Ok(rpc_client.get_program_accounts_with_config(
         &solana_stake_program::id(),
         RpcProgramAccountsConfig {
             filters: Some(vec![
@@ -293,9 +426,17 @@ pub fn get_all_stake(
                 ..RpcAccountInfoConfig::default()
             },
         },
-    )?;
+    )?)
+}
+
+pub fn get_all_stake(
+    rpc_client: &RpcClient,
+    authorized_staker: Pubkey,
+) -> Result<(HashSet<Pubkey>, u64), Box<dyn error::Error>> {
+    let mut all_stake_addresses = HashSet::new();
+    let mut total_stake_balance = 0;
 
-    for (address, account) in all_stake_accounts {
+    for (address, account) in get_all_stake_accounts(rpc_client, authorized_staker)? {
         all_stake_addresses.insert(address);
         total_stake_balance += account.lamports;
     }
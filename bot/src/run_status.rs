@@ -0,0 +1,237 @@
+use {
+    crate::schema::{self, Schema},
+    serde::{Deserialize, Serialize},
+    solana_sdk::clock::Epoch,
+    std::{
+        error,
+        fs::{self, File},
+        io::{self, Write},
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    thiserror::Error,
+};
+
+/// Process exit codes, allowing an orchestrator to branch on the outcome of a run
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum ExitCode {
+    /// Every phase completed
+    Success = 0,
+    /// The run failed for a reason not covered by the other exit codes
+    Error = 1,
+    /// The run completed but some stake pool transactions failed
+    PartialFailure = 2,
+    /// A safety check stopped the run before it could complete
+    AbortedByGuardrail = 3,
+    /// An RPC request failed
+    RpcFailure = 4,
+}
+
+impl ExitCode {
+    pub fn of_error(err: &(dyn error::Error + 'static)) -> Self {
+        if err.downcast_ref::<GuardrailError>().is_some() {
+            ExitCode::AbortedByGuardrail
+        } else if is_rpc_failure(err) {
+            ExitCode::RpcFailure
+        } else {
+            ExitCode::Error
+        }
+    }
+}
+
+#[cfg(feature = "rpc")]
+fn is_rpc_failure(err: &(dyn error::Error + 'static)) -> bool {
+    err.downcast_ref::<solana_client::client_error::ClientError>()
+        .is_some()
+}
+
+/// Without the RPC client, as in the wasm32 build, no error comes from an RPC request
+#[cfg(not(feature = "rpc"))]
+fn is_rpc_failure(_err: &(dyn error::Error + 'static)) -> bool {
+    false
+}
+
+/// A safety check that aborts the run rather than risk an unintended stake movement
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct GuardrailError(pub String);
+
+pub fn guardrail<S: Into<String>>(message: S) -> Box<dyn error::Error> {
+    Box::new(GuardrailError(message.into()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PhaseStatus {
+    Running,
+    Succeeded,
+    PartialFailure,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PhaseOutcome {
+    pub phase: String,
+    pub status: PhaseStatus,
+    pub message: Option<String>,
+}
+
+/// Outcome of a run, written to `run-status.json` when the run ends
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RunStatus {
+    pub epoch: Option<Epoch>,
+    pub start_timestamp: u64,
+    pub end_timestamp: Option<u64>,
+    pub exit_code: Option<ExitCode>,
+    pub error: Option<String>,
+
+    // Phases in the order they ran, the run stops at the first failed phase
+    pub phases: Vec<PhaseOutcome>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+impl Default for RunStatus {
+    fn default() -> Self {
+        Self {
+            epoch: None,
+            start_timestamp: now(),
+            end_timestamp: None,
+            exit_code: None,
+            error: None,
+            phases: vec![],
+        }
+    }
+}
+
+impl Schema for RunStatus {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl RunStatus {
+    /// Identifier of the run, its start timestamp, recorded in the memo of its transactions
+    pub fn run_id(&self) -> String {
+        self.start_timestamp.to_string()
+    }
+
+    pub fn file_name<P>(path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref().join("run-status.json")
+    }
+
+    fn end_phase(&mut self, status: PhaseStatus, message: Option<String>) {
+        if let Some(phase) = self.phases.last_mut() {
+            if phase.status == PhaseStatus::Running {
+                phase.status = status;
+                phase.message = message;
+            }
+        }
+    }
+
+    /// Begin `phase`, completing the phase currently running
+    pub fn start_phase(&mut self, phase: &str) {
+        self.end_phase(PhaseStatus::Succeeded, None);
+        self.phases.push(PhaseOutcome {
+            phase: phase.to_string(),
+            status: PhaseStatus::Running,
+            message: None,
+        });
+    }
+
+    /// Complete the phase currently running with some of its operations failed
+    pub fn partial_failure(&mut self, message: &str) {
+        self.end_phase(PhaseStatus::PartialFailure, Some(message.to_string()));
+    }
+
+    /// Record the result of the run, returning its exit code
+    pub fn finish(&mut self, result: Result<(), Box<dyn error::Error>>) -> ExitCode {
+        let exit_code = match result {
+            Ok(()) => {
+                self.end_phase(PhaseStatus::Succeeded, None);
+                if self
+                    .phases
+                    .iter()
+                    .any(|phase| phase.status == PhaseStatus::PartialFailure)
+                {
+                    ExitCode::PartialFailure
+                } else {
+                    ExitCode::Success
+                }
+            }
+            Err(err) => {
+                self.end_phase(PhaseStatus::Failed, Some(err.to_string()));
+                self.error = Some(err.to_string());
+                ExitCode::of_error(err.as_ref())
+            }
+        };
+        self.end_timestamp = Some(now());
+        self.exit_code = Some(exit_code);
+        exit_code
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized = schema::to_json_pretty(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        fs::create_dir_all(&path)?;
+        let mut file = File::create(Self::file_name(path))?;
+        file.write_all(&serialized.into_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_finish() {
+        let mut run_status = RunStatus::default();
+        run_status.start_phase("classification");
+        run_status.start_phase("stake pool");
+        run_status.partial_failure("One or more stake pool transactions failed");
+        run_status.start_phase("report");
+        assert_eq!(run_status.finish(Ok(())), ExitCode::PartialFailure);
+        assert_eq!(
+            run_status
+                .phases
+                .iter()
+                .map(|phase| phase.status)
+                .collect::<Vec<_>>(),
+            vec![
+                PhaseStatus::Succeeded,
+                PhaseStatus::PartialFailure,
+                PhaseStatus::Succeeded
+            ]
+        );
+
+        let mut run_status = RunStatus::default();
+        run_status.start_phase("stake pool");
+        assert_eq!(
+            run_status.finish(Err(guardrail("Not enough stake to cover the baseline"))),
+            ExitCode::AbortedByGuardrail
+        );
+        assert_eq!(run_status.phases[0].status, PhaseStatus::Failed);
+        assert_eq!(
+            run_status.error.as_deref(),
+            Some("Not enough stake to cover the baseline")
+        );
+
+        let mut run_status = RunStatus::default();
+        assert_eq!(
+            run_status.finish(Err("Invalid stake pool".into())),
+            ExitCode::Error
+        );
+    }
+}
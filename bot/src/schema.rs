@@ -0,0 +1,127 @@
+//! Versioned schemas of the artifacts the bot emits. Each artifact is written with a
+//! `schema_version` field, so that downstream consumers can tell which fields to expect, and the
+//! bot can still read the artifacts written by older releases
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub trait Schema: Serialize + DeserializeOwned {
+    /// Version of the schema written by this release. Increment it whenever a change would
+    /// prevent the previous release from reading the artifact, migrating the older versions in
+    /// `upgrade`
+    const SCHEMA_VERSION: u32;
+
+    /// Read an artifact of an older `schema_version` from its fields. Fields added since then
+    /// must have a serde default
+    fn upgrade(schema_version: u32, fields: serde_yaml::Value) -> Result<Self, String> {
+        serde_yaml::from_value(fields)
+            .map_err(|err| format!("Unable to read schema version {}: {}", schema_version, err))
+    }
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    artifact: &'a T,
+}
+
+#[derive(Deserialize)]
+struct VersionHeader {
+    // Artifacts written before artifacts carried a version are version 0
+    #[serde(default)]
+    schema_version: u32,
+}
+
+fn versioned<T: Schema>(artifact: &T) -> Versioned<T> {
+    Versioned {
+        schema_version: T::SCHEMA_VERSION,
+        artifact,
+    }
+}
+
+pub fn to_yaml<T: Schema>(artifact: &T) -> Result<String, String> {
+    serde_yaml::to_string(&versioned(artifact)).map_err(|err| err.to_string())
+}
+
+pub fn to_json<T: Schema>(artifact: &T) -> Result<String, String> {
+    serde_json::to_string(&versioned(artifact)).map_err(|err| err.to_string())
+}
+
+pub fn to_json_pretty<T: Schema>(artifact: &T) -> Result<String, String> {
+    serde_json::to_string_pretty(&versioned(artifact)).map_err(|err| err.to_string())
+}
+
+/// Read an artifact written as YAML or JSON by this or an older release
+pub fn from_str<T: Schema>(s: &str) -> Result<T, String> {
+    let VersionHeader { schema_version } =
+        serde_yaml::from_str(s).map_err(|err| format!("Invalid artifact: {}", err))?;
+    if schema_version > T::SCHEMA_VERSION {
+        return Err(format!(
+            "Schema version {} is newer than the supported version {}, upgrade stake-o-matic",
+            schema_version,
+            T::SCHEMA_VERSION
+        ));
+    }
+    if schema_version == T::SCHEMA_VERSION {
+        serde_yaml::from_str(s).map_err(|err| err.to_string())
+    } else {
+        T::upgrade(
+            schema_version,
+            serde_yaml::from_str(s).map_err(|err| err.to_string())?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, std::collections::HashMap};
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct ArtifactV2 {
+        name: String,
+        #[serde(default)]
+        lamports: HashMap<String, u64>,
+    }
+
+    impl Schema for ArtifactV2 {
+        const SCHEMA_VERSION: u32 = 2;
+
+        fn upgrade(schema_version: u32, mut fields: serde_yaml::Value) -> Result<Self, String> {
+            // Version 1 named the field `label`
+            if schema_version == 1 {
+                if let serde_yaml::Value::Mapping(mapping) = &mut fields {
+                    if let Some(label) = mapping.remove(&"label".into()) {
+                        mapping.insert("name".into(), label);
+                    }
+                }
+            }
+            serde_yaml::from_value(fields).map_err(|err| err.to_string())
+        }
+    }
+
+    #[test]
+    fn test_schema_versions() {
+        let artifact = ArtifactV2 {
+            name: "pool".to_string(),
+            lamports: vec![("a".to_string(), 42)].into_iter().collect(),
+        };
+
+        let yaml = to_yaml(&artifact).unwrap();
+        assert!(yaml.contains("schema_version: 2"));
+        assert_eq!(from_str::<ArtifactV2>(&yaml).unwrap(), artifact);
+
+        let json = to_json(&artifact).unwrap();
+        assert!(json.starts_with(r#"{"schema_version":2,"name":"pool""#));
+        assert_eq!(from_str::<ArtifactV2>(&json).unwrap(), artifact);
+
+        let name_only = ArtifactV2 {
+            name: "pool".to_string(),
+            lamports: HashMap::default(),
+        };
+        assert_eq!(from_str::<ArtifactV2>("name: pool").unwrap(), name_only);
+        assert_eq!(
+            from_str::<ArtifactV2>(r#"{"schema_version": 1, "label": "pool"}"#).unwrap(),
+            name_only
+        );
+        assert!(from_str::<ArtifactV2>("schema_version: 3\nname: pool").is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use {
+    crate::data_center_info::DataCenterId,
+    log::*,
+    serde::{Deserialize, Serialize},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::{HashMap, HashSet},
+        error, fmt,
+        fs::File,
+        io::Write,
+        net::IpAddr,
+        path::PathBuf,
+        process::{Command, Stdio},
+        str::FromStr,
+    },
+};
+
+/// Location of a validator, as given to the screening hooks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidatorLocation {
+    pub identity: String,
+    pub vote_address: String,
+    pub data_center: String,
+    pub gossip_ip: Option<IpAddr>,
+}
+
+impl ValidatorLocation {
+    pub fn new(
+        identity: &Pubkey,
+        vote_address: &Pubkey,
+        data_center: &DataCenterId,
+        gossip_ip: Option<IpAddr>,
+    ) -> Self {
+        Self {
+            identity: identity.to_string(),
+            vote_address: vote_address.to_string(),
+            data_center: data_center.to_string(),
+            gossip_ip,
+        }
+    }
+}
+
+/// Screens validators against the jurisdictions prohibited by the operator
+pub trait ScreeningHook {
+    /// Data source of the screening results, recorded with every exclusion
+    fn source(&self) -> String;
+
+    /// Validators of `validators` to exclude from stake, by identity, with the reason
+    fn screen(
+        &self,
+        validators: &[ValidatorLocation],
+    ) -> Result<HashMap<Pubkey, String>, Box<dyn error::Error>>;
+}
+
+/// Prohibited jurisdictions read from a YAML file, matched against the data center of each
+/// validator as reported by validators.app
+///
+/// ```yaml
+/// countries: [XX, YY]            # ISO 3166 country codes
+/// data_centers: [12345-XX-City]  # data center keys
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct JurisdictionList {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    countries: HashSet<String>,
+    #[serde(default)]
+    data_centers: HashSet<String>,
+}
+
+impl JurisdictionList {
+    pub fn load(path: PathBuf) -> Result<Self, Box<dyn error::Error>> {
+        let file = File::open(&path)
+            .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+        let list: Self = serde_yaml::from_reader(file)
+            .map_err(|err| format!("Invalid jurisdiction list {}: {}", path.display(), err))?;
+        Ok(Self { path, ..list })
+    }
+}
+
+impl ScreeningHook for JurisdictionList {
+    fn source(&self) -> String {
+        format!("jurisdiction list {}", self.path.display())
+    }
+
+    fn screen(
+        &self,
+        validators: &[ValidatorLocation],
+    ) -> Result<HashMap<Pubkey, String>, Box<dyn error::Error>> {
+        let mut excluded = HashMap::new();
+        for validator in validators {
+            // Data center keys are formatted as ASN-COUNTRY-CITY
+            let country = validator.data_center.splitn(3, '-').nth(1);
+            let reason = if self.data_centers.contains(&validator.data_center) {
+                format!("data center {} is prohibited", validator.data_center)
+            } else if let Some(country) = country.filter(|c| self.countries.contains(*c)) {
+                format!(
+                    "data center {} is in prohibited country {}",
+                    validator.data_center, country
+                )
+            } else {
+                continue;
+            };
+            excluded.insert(Pubkey::from_str(&validator.identity)?, reason);
+        }
+        Ok(excluded)
+    }
+}
+
+// Exclusion reported by a screening command
+#[derive(Deserialize)]
+struct ScreeningCommandEntry {
+    identity: String,
+    reason: String,
+}
+
+/// External command given the validator locations as a JSON array on stdin, that writes the
+/// validators to exclude to stdout as a JSON array of `{"identity": ..., "reason": ...}` objects.
+/// Allows screening by gossip IP address with the operator's own geolocation data
+#[derive(Debug)]
+pub struct ScreeningCommand {
+    command: String,
+}
+
+impl ScreeningHook for ScreeningCommand {
+    fn source(&self) -> String {
+        format!("screening command {}", self.command)
+    }
+
+    fn screen(
+        &self,
+        validators: &[ValidatorLocation],
+    ) -> Result<HashMap<Pubkey, String>, Box<dyn error::Error>> {
+        debug!("Running {}", self.command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("Unable to run {}: {}", self.command, err))?;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(&serde_json::to_vec(validators)?)?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                self.command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        let entries: Vec<ScreeningCommandEntry> = serde_json::from_slice(&output.stdout)
+            .map_err(|err| format!("Invalid output from {}: {}", self.command, err))?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                let identity = Pubkey::from_str(&entry.identity).map_err(|err| {
+                    format!(
+                        "Invalid identity {} from {}: {}",
+                        entry.identity, self.command, err
+                    )
+                })?;
+                Ok((identity, entry.reason))
+            })
+            .collect()
+    }
+}
+
+/// Screening hook configured by the operator
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScreeningSource {
+    /// YAML list of prohibited countries and data centers
+    List(PathBuf),
+    /// Command run through `sh -c`
+    Command(String),
+}
+
+impl FromStr for ScreeningSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("list:").filter(|path| !path.is_empty()) {
+            Ok(Self::List(PathBuf::from(path)))
+        } else if let Some(command) = s.strip_prefix("command:").filter(|c| !c.is_empty()) {
+            Ok(Self::Command(command.to_string()))
+        } else {
+            Err(format!(
+                "Invalid screening hook {}, expected list:PATH or command:COMMAND",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for ScreeningSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::List(path) => write!(f, "list:{}", path.display()),
+            Self::Command(command) => write!(f, "command:{}", command),
+        }
+    }
+}
+
+impl ScreeningSource {
+    pub fn hook(&self) -> Result<Box<dyn ScreeningHook>, Box<dyn error::Error>> {
+        Ok(match self {
+            Self::List(path) => Box::new(JurisdictionList::load(path.clone())?),
+            Self::Command(command) => Box::new(ScreeningCommand {
+                command: command.clone(),
+            }),
+        })
+    }
+}
+
+/// Run every screening hook of `sources` over `validators`, returning the validators to exclude by
+/// identity, with the reasons and data sources of their exclusion. A hook that fails aborts the
+/// screening rather than let a validator through unscreened
+pub fn screen(
+    sources: &[ScreeningSource],
+    validators: &[ValidatorLocation],
+) -> Result<HashMap<Pubkey, String>, Box<dyn error::Error>> {
+    let mut excluded: HashMap<Pubkey, String> = HashMap::new();
+    for source in sources {
+        let hook = source.hook()?;
+        let source = hook.source();
+        let results = hook
+            .screen(validators)
+            .map_err(|err| format!("Screening by {} failed: {}", source, err))?;
+        info!(
+            "Screening by {}: {} of {} validators excluded",
+            source,
+            results.len(),
+            validators.len()
+        );
+        for (identity, reason) in results {
+            let reason = format!("{} (source: {})", reason, source);
+            warn!("Screening excluded {}: {}", identity, reason);
+            excluded
+                .entry(identity)
+                .and_modify(|reasons| {
+                    reasons.push_str("; ");
+                    reasons.push_str(&reason);
+                })
+                .or_insert(reason);
+        }
+    }
+    Ok(excluded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_jurisdiction_list() {
+        let list = JurisdictionList {
+            countries: ["XX".to_string()].iter().cloned().collect(),
+            data_centers: ["2-YY-Town".to_string()].iter().cloned().collect(),
+            ..JurisdictionList::default()
+        };
+        let location = |data_center: &str| {
+            ValidatorLocation::new(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &DataCenterId::from_str(data_center).unwrap(),
+                None,
+            )
+        };
+        let validators = vec![
+            location("1-XX-City"),
+            location("2-YY-Town"),
+            location("2-YY-City"),
+            location("0-Unknown"),
+        ];
+
+        let excluded = list.screen(&validators).unwrap();
+        assert_eq!(excluded.len(), 2);
+        assert_eq!(
+            excluded[&Pubkey::from_str(&validators[0].identity).unwrap()],
+            "data center 1-XX-City is in prohibited country XX"
+        );
+        assert_eq!(
+            excluded[&Pubkey::from_str(&validators[1].identity).unwrap()],
+            "data center 2-YY-Town is prohibited"
+        );
+    }
+
+    #[test]
+    fn test_screening_source_from_str() {
+        assert_eq!(
+            "list:prohibited.yml".parse::<ScreeningSource>(),
+            Ok(ScreeningSource::List(PathBuf::from("prohibited.yml")))
+        );
+        assert_eq!(
+            "command:geo-screen --strict".parse::<ScreeningSource>(),
+            Ok(ScreeningSource::Command("geo-screen --strict".to_string()))
+        );
+        assert!("list:".parse::<ScreeningSource>().is_err());
+        assert!("prohibited.yml".parse::<ScreeningSource>().is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Small pool mode, for community pools holding too little stake for the fixed 1 SOL minimums of
+//! the stake accounts and stake changes. The minimums are derived from the minimum delegation of
+//! the cluster instead, and stake changes are skipped in proportion to each validator's stake
+use {
+    crate::generic_stake_pool::MinStakeChange,
+    serde_json::{json, Value},
+    solana_sdk::native_token::{Sol, LAMPORTS_PER_SOL},
+    std::error,
+};
+
+/// Pool stake above which small pool mode is not meant to be used
+pub const SMALL_POOL_LAMPORTS: u64 = 100 * LAMPORTS_PER_SOL;
+
+/// Percentage of a validator's desired stake below which its stake changes are skipped, unless
+/// `--min-stake-change-percentage` is given
+pub const DEFAULT_MIN_STAKE_CHANGE_PERCENTAGE: f64 = 5.;
+
+// JSON-RPC error code of a method the RPC node does not know
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The minimum delegation of the cluster at `json_rpc_url`. Clusters that predate the
+/// `getStakeMinimumDelegation` RPC method accept any nonzero delegation
+pub fn minimum_delegation(json_rpc_url: &str) -> Result<u64, Box<dyn error::Error>> {
+    let response = reqwest::blocking::Client::new()
+        .post(json_rpc_url)
+        .json(&json!({"jsonrpc": "2.0", "id": 1, "method": "getStakeMinimumDelegation"}))
+        .send()?
+        .json::<Value>()?;
+
+    if response["error"]["code"].as_i64() == Some(METHOD_NOT_FOUND) {
+        return Ok(1);
+    }
+    response["result"]["value"]
+        .as_u64()
+        .map(|lamports| lamports.max(1))
+        .ok_or_else(|| format!("Invalid getStakeMinimumDelegation response: {}", response).into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmallPool {
+    /// Smallest stake, above the rent-exempt reserve, that the stake accounts of the pool hold
+    pub min_stake_lamports: u64,
+}
+
+impl SmallPool {
+    /// `min_stake_change` relaxed to the minimum stake of the pool, unless its amount was
+    /// `amount_given` explicitly, and proportional to each validator's desired stake
+    pub fn min_stake_change(
+        &self,
+        min_stake_change: MinStakeChange,
+        amount_given: bool,
+    ) -> MinStakeChange {
+        MinStakeChange {
+            lamports: if amount_given {
+                min_stake_change.lamports
+            } else {
+                self.min_stake_lamports
+            },
+            target_percentage: Some(
+                min_stake_change
+                    .target_percentage
+                    .unwrap_or(DEFAULT_MIN_STAKE_CHANGE_PERCENTAGE),
+            ),
+        }
+    }
+
+    /// Warnings for a pool of `total_stake_lamports` over `stake_account_count` stake accounts,
+    /// each holding `stake_rent_exemption`
+    pub fn warnings(
+        &self,
+        total_stake_lamports: u64,
+        stake_account_count: usize,
+        stake_rent_exemption: u64,
+    ) -> Vec<String> {
+        let mut warnings = vec![];
+        if total_stake_lamports >= SMALL_POOL_LAMPORTS {
+            warnings.push(format!(
+                "Small pool mode is meant for pools under {}, this pool holds {}",
+                Sol(SMALL_POOL_LAMPORTS),
+                Sol(total_stake_lamports)
+            ));
+        }
+        if self.min_stake_lamports < LAMPORTS_PER_SOL {
+            warnings.push(format!(
+                "Stake accounts hold as little as {}, transaction fees are a larger share of \
+                 their stake changes",
+                Sol(self.min_stake_lamports)
+            ));
+        }
+        let rent_lamports = stake_rent_exemption * stake_account_count as u64;
+        if rent_lamports * 10 > total_stake_lamports {
+            warnings.push(format!(
+                "The rent-exempt reserves of {} stake accounts hold {}, over 10% of the pool \
+                 stake of {}",
+                stake_account_count,
+                Sol(rent_lamports),
+                Sol(total_stake_lamports)
+            ));
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_small_pool() {
+        let small_pool = SmallPool {
+            min_stake_lamports: 1,
+        };
+        let min_stake_change = MinStakeChange {
+            lamports: LAMPORTS_PER_SOL,
+            target_percentage: None,
+        };
+        assert_eq!(
+            small_pool.min_stake_change(min_stake_change, false),
+            MinStakeChange {
+                lamports: 1,
+                target_percentage: Some(DEFAULT_MIN_STAKE_CHANGE_PERCENTAGE),
+            }
+        );
+        assert_eq!(
+            small_pool.min_stake_change(
+                MinStakeChange {
+                    target_percentage: Some(1.),
+                    ..min_stake_change
+                },
+                true
+            ),
+            MinStakeChange {
+                lamports: LAMPORTS_PER_SOL,
+                target_percentage: Some(1.),
+            }
+        );
+
+        let rent = 2_282_880;
+        assert_eq!(small_pool.warnings(50 * LAMPORTS_PER_SOL, 4, rent).len(), 1);
+        assert_eq!(small_pool.warnings(SMALL_POOL_LAMPORTS, 4, rent).len(), 2);
+        assert_eq!(
+            small_pool.warnings(LAMPORTS_PER_SOL / 10, 50, rent).len(),
+            2
+        );
+        assert!(SmallPool {
+            min_stake_lamports: LAMPORTS_PER_SOL
+        }
+        .warnings(50 * LAMPORTS_PER_SOL, 4, rent)
+        .is_empty());
+    }
+}
@@ -0,0 +1,394 @@
+use {
+    crate::{
+        rpc_client_utils::get_all_stake,
+        schema::{self, Schema},
+    },
+    borsh::BorshDeserialize,
+    log::*,
+    serde::{Deserialize, Serialize},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        borsh::try_from_slice_unchecked,
+        clock::{Epoch, Slot},
+        native_token::Sol,
+        pubkey::Pubkey,
+    },
+    spl_stake_pool::{
+        find_stake_program_address, find_transient_stake_program_address,
+        state::{StakePool, ValidatorList},
+    },
+    std::{
+        collections::{BTreeMap, HashMap},
+        error,
+        fs::{self, File},
+        io::{self, Write},
+        path::Path,
+    },
+};
+
+#[derive(Default, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ValidatorSnapshot {
+    pub vote_address: Pubkey,
+    pub status: String,
+
+    // Stake lamports as recorded in the validator list
+    pub stake_lamports: u64,
+    pub last_update_epoch: Epoch,
+
+    pub stake_address: Pubkey,
+    pub stake_account_lamports: u64,
+
+    pub transient_stake_address: Pubkey,
+    pub transient_stake_account_lamports: u64,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct StakePoolSnapshot {
+    // The slot and epoch at which the snapshot was taken
+    pub slot: Slot,
+    pub epoch: Epoch,
+
+    pub stake_pool_address: Pubkey,
+    pub manager: Pubkey,
+    pub staker: Pubkey,
+    pub validator_list: Pubkey,
+    pub reserve_stake: Pubkey,
+    pub pool_mint: Pubkey,
+    pub manager_fee_account: Pubkey,
+    pub fee_numerator: u64,
+    pub fee_denominator: u64,
+    pub total_stake_lamports: u64,
+    pub pool_token_supply: u64,
+    pub last_update_epoch: Epoch,
+
+    pub reserve_stake_lamports: u64,
+    pub max_validators: u32,
+    pub validators: Vec<ValidatorSnapshot>,
+
+    // SOL balance of the staker and the balances of the stake accounts it is the authorized
+    // staker of
+    pub staker_lamports: u64,
+    pub staker_stake_accounts: BTreeMap<String, u64>,
+}
+
+impl Schema for StakePoolSnapshot {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl StakePoolSnapshot {
+    pub fn load<P>(path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized = fs::read_to_string(path)?;
+        schema::from_str(&serialized).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let serialized =
+            schema::to_yaml(self).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(path)?;
+        file.write_all(&serialized.into_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Fetch the stake pool, its validator list and all stake accounts of the pool and its staker
+pub fn export(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    stake_pool_address: &Pubkey,
+) -> Result<StakePoolSnapshot, Box<dyn error::Error>> {
+    let epoch_info = rpc_client.get_epoch_info()?;
+
+    let account_data = rpc_client.get_account_data(&stake_pool_address)?;
+    let stake_pool = StakePool::try_from_slice(account_data.as_slice())
+        .map_err(|err| format!("Invalid stake pool {}: {}", stake_pool_address, err))?;
+    let account_data = rpc_client.get_account_data(&stake_pool.validator_list)?;
+    let validator_list = try_from_slice_unchecked::<ValidatorList>(&account_data.as_slice())
+        .map_err(|err| {
+            format!(
+                "Invalid validator list {}: {}",
+                stake_pool.validator_list, err
+            )
+        })?;
+
+    info!(
+        "Exporting {} validators of stake pool {}",
+        validator_list.validators.len(),
+        stake_pool_address
+    );
+    let mut validators = vec![];
+    for validator_stake_info in &validator_list.validators {
+        let vote_address = validator_stake_info.vote_account_address;
+        let stake_address =
+            find_stake_program_address(program_id, &vote_address, stake_pool_address).0;
+        let transient_stake_address =
+            find_transient_stake_program_address(program_id, &vote_address, stake_pool_address).0;
+
+        validators.push(ValidatorSnapshot {
+            vote_address,
+            status: format!("{:?}", validator_stake_info.status),
+            stake_lamports: validator_stake_info.stake_lamports,
+            last_update_epoch: validator_stake_info.last_update_epoch,
+            stake_address,
+            stake_account_lamports: rpc_client.get_balance(&stake_address)?,
+            transient_stake_address,
+            transient_stake_account_lamports: rpc_client.get_balance(&transient_stake_address)?,
+        });
+    }
+
+    let (staker_stake_addresses, _) = get_all_stake(rpc_client, stake_pool.staker)?;
+    let mut staker_stake_accounts = BTreeMap::new();
+    for stake_address in staker_stake_addresses {
+        staker_stake_accounts.insert(
+            stake_address.to_string(),
+            rpc_client.get_balance(&stake_address)?,
+        );
+    }
+
+    Ok(StakePoolSnapshot {
+        slot: epoch_info.absolute_slot,
+        epoch: epoch_info.epoch,
+        stake_pool_address: *stake_pool_address,
+        manager: stake_pool.manager,
+        staker: stake_pool.staker,
+        validator_list: stake_pool.validator_list,
+        reserve_stake: stake_pool.reserve_stake,
+        pool_mint: stake_pool.pool_mint,
+        manager_fee_account: stake_pool.manager_fee_account,
+        fee_numerator: stake_pool.fee.numerator,
+        fee_denominator: stake_pool.fee.denominator,
+        total_stake_lamports: stake_pool.total_stake_lamports,
+        pool_token_supply: stake_pool.pool_token_supply,
+        last_update_epoch: stake_pool.last_update_epoch,
+        reserve_stake_lamports: rpc_client.get_balance(&stake_pool.reserve_stake)?,
+        max_validators: validator_list.max_validators,
+        validators,
+        staker_lamports: rpc_client.get_balance(&stake_pool.staker)?,
+        staker_stake_accounts,
+    })
+}
+
+/// Describe the differences between two snapshots, one line per difference
+pub fn diff(before: &StakePoolSnapshot, after: &StakePoolSnapshot) -> Vec<String> {
+    let mut differences = vec![];
+
+    macro_rules! diff_field {
+        ($field:ident) => {
+            if before.$field != after.$field {
+                differences.push(format!(
+                    "{}: {:?} -> {:?}",
+                    stringify!($field),
+                    before.$field,
+                    after.$field
+                ));
+            }
+        };
+    }
+    diff_field!(stake_pool_address);
+    diff_field!(epoch);
+    diff_field!(manager);
+    diff_field!(staker);
+    diff_field!(validator_list);
+    diff_field!(reserve_stake);
+    diff_field!(pool_mint);
+    diff_field!(manager_fee_account);
+    diff_field!(fee_numerator);
+    diff_field!(fee_denominator);
+    diff_field!(max_validators);
+
+    let lamports_changed = |name: &str, before: u64, after: u64| {
+        if before != after {
+            Some(format!(
+                "{}: {} -> {} ({}{})",
+                name,
+                Sol(before),
+                Sol(after),
+                if after > before { "+" } else { "-" },
+                Sol(if after > before {
+                    after - before
+                } else {
+                    before - after
+                })
+            ))
+        } else {
+            None
+        }
+    };
+    differences.extend(lamports_changed(
+        "total_stake_lamports",
+        before.total_stake_lamports,
+        after.total_stake_lamports,
+    ));
+    differences.extend(lamports_changed(
+        "reserve_stake_lamports",
+        before.reserve_stake_lamports,
+        after.reserve_stake_lamports,
+    ));
+    differences.extend(lamports_changed(
+        "staker_lamports",
+        before.staker_lamports,
+        after.staker_lamports,
+    ));
+    if before.pool_token_supply != after.pool_token_supply {
+        differences.push(format!(
+            "pool_token_supply: {} -> {}",
+            before.pool_token_supply, after.pool_token_supply
+        ));
+    }
+
+    let before_validators = before
+        .validators
+        .iter()
+        .map(|v| (v.vote_address, v))
+        .collect::<HashMap<_, _>>();
+    let after_validators = after
+        .validators
+        .iter()
+        .map(|v| (v.vote_address, v))
+        .collect::<HashMap<_, _>>();
+
+    for before_validator in &before.validators {
+        match after_validators.get(&before_validator.vote_address) {
+            None => differences.push(format!(
+                "validator {} removed ({})",
+                before_validator.vote_address,
+                Sol(before_validator.stake_lamports)
+            )),
+            Some(after_validator) => {
+                if before_validator.status != after_validator.status {
+                    differences.push(format!(
+                        "validator {} status: {} -> {}",
+                        before_validator.vote_address,
+                        before_validator.status,
+                        after_validator.status
+                    ));
+                }
+                differences.extend(lamports_changed(
+                    &format!("validator {} stake", before_validator.vote_address),
+                    before_validator.stake_lamports,
+                    after_validator.stake_lamports,
+                ));
+                differences.extend(lamports_changed(
+                    &format!(
+                        "validator {} transient stake",
+                        before_validator.vote_address
+                    ),
+                    before_validator.transient_stake_account_lamports,
+                    after_validator.transient_stake_account_lamports,
+                ));
+            }
+        }
+    }
+    for after_validator in &after.validators {
+        if !before_validators.contains_key(&after_validator.vote_address) {
+            differences.push(format!(
+                "validator {} added ({})",
+                after_validator.vote_address,
+                Sol(after_validator.stake_lamports)
+            ));
+        }
+    }
+
+    for (stake_address, lamports) in &before.staker_stake_accounts {
+        if !after.staker_stake_accounts.contains_key(stake_address) {
+            differences.push(format!(
+                "staker stake account {} closed ({})",
+                stake_address,
+                Sol(*lamports)
+            ));
+        }
+    }
+    for (stake_address, lamports) in &after.staker_stake_accounts {
+        match before.staker_stake_accounts.get(stake_address) {
+            None => differences.push(format!(
+                "staker stake account {} created ({})",
+                stake_address,
+                Sol(*lamports)
+            )),
+            Some(before_lamports) => differences.extend(lamports_changed(
+                &format!("staker stake account {}", stake_address),
+                *before_lamports,
+                *lamports,
+            )),
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_sdk::native_token::sol_to_lamports};
+
+    #[test]
+    fn test_diff() {
+        let v1 = ValidatorSnapshot {
+            vote_address: Pubkey::new_unique(),
+            status: "Active".to_string(),
+            stake_lamports: sol_to_lamports(10.),
+            ..ValidatorSnapshot::default()
+        };
+        let v2 = ValidatorSnapshot {
+            vote_address: Pubkey::new_unique(),
+            status: "Active".to_string(),
+            stake_lamports: sol_to_lamports(20.),
+            ..ValidatorSnapshot::default()
+        };
+        let v3 = ValidatorSnapshot {
+            vote_address: Pubkey::new_unique(),
+            status: "Active".to_string(),
+            ..ValidatorSnapshot::default()
+        };
+
+        let before = StakePoolSnapshot {
+            epoch: 10,
+            total_stake_lamports: sol_to_lamports(30.),
+            validators: vec![v1.clone(), v2.clone()],
+            ..StakePoolSnapshot::default()
+        };
+        assert!(diff(&before, &before).is_empty());
+
+        let after = StakePoolSnapshot {
+            epoch: 11,
+            staker: Pubkey::new_unique(),
+            validators: vec![
+                ValidatorSnapshot {
+                    stake_lamports: sol_to_lamports(15.),
+                    ..v1
+                },
+                ValidatorSnapshot {
+                    status: "ReadyForRemoval".to_string(),
+                    ..v3.clone()
+                },
+            ],
+            ..before.clone()
+        };
+        let differences = diff(&before, &after);
+        assert_eq!(differences.len(), 5);
+        assert!(differences.contains(&"epoch: 10 -> 11".to_string()));
+        assert!(differences.iter().any(|d| d.starts_with("staker: ")));
+        assert!(differences.contains(&format!(
+            "validator {} stake: {} -> {} (+{})",
+            v1.vote_address,
+            Sol(sol_to_lamports(10.)),
+            Sol(sol_to_lamports(15.)),
+            Sol(sol_to_lamports(5.)),
+        )));
+        assert!(differences.contains(&format!(
+            "validator {} removed ({})",
+            v2.vote_address,
+            Sol(sol_to_lamports(20.))
+        )));
+        assert!(differences.contains(&format!("validator {} added ({})", v3.vote_address, Sol(0))));
+    }
+}
@@ -0,0 +1,185 @@
+//! Sanity checks of the stake accounts the bot builds instructions against. A stake account whose
+//! authorities or delegation were changed outside the bot, such as by a manual intervention, is
+//! refused rather than producing transactions that are bound to fail
+use {
+    solana_sdk::{account::Account, pubkey::Pubkey},
+    solana_stake_program::stake_state::{Meta, StakeState},
+};
+
+/// Authorities and delegation a stake account is expected to have
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExpectedStakeAccount {
+    pub staker: Pubkey,
+    pub withdrawer: Pubkey,
+
+    /// If Some(), the vote account the stake must be delegated to, if it is delegated
+    pub voter: Option<Pubkey>,
+}
+
+impl ExpectedStakeAccount {
+    /// A stake account with `authority` as both its staker and withdrawer
+    pub fn authorized(authority: Pubkey) -> Self {
+        Self {
+            staker: authority,
+            withdrawer: authority,
+            voter: None,
+        }
+    }
+
+    pub fn delegated_to(self, voter: Pubkey) -> Self {
+        Self {
+            voter: Some(voter),
+            ..self
+        }
+    }
+}
+
+fn check_meta(meta: &Meta, expected: &ExpectedStakeAccount) -> Vec<String> {
+    let mut mismatches = vec![];
+    if meta.authorized.staker != expected.staker {
+        mismatches.push(format!(
+            "staker is {}, expected {}",
+            meta.authorized.staker, expected.staker
+        ));
+    }
+    if meta.authorized.withdrawer != expected.withdrawer {
+        mismatches.push(format!(
+            "withdrawer is {}, expected {}",
+            meta.authorized.withdrawer, expected.withdrawer
+        ));
+    }
+    mismatches
+}
+
+/// Verify that the stake account at `address` has the `expected` authorities and delegation
+pub fn check(
+    address: &Pubkey,
+    account: &Account,
+    expected: &ExpectedStakeAccount,
+) -> Result<(), String> {
+    if account.owner != solana_stake_program::id() {
+        return Err(format!(
+            "{} is not a stake account, it is owned by {}",
+            address, account.owner
+        ));
+    }
+    let stake_state = bincode::deserialize::<StakeState>(&account.data)
+        .map_err(|err| format!("Invalid stake account {}: {}", address, err))?;
+
+    let mismatches = match stake_state {
+        StakeState::Initialized(meta) => check_meta(&meta, expected),
+        StakeState::Stake(meta, stake) => {
+            let mut mismatches = check_meta(&meta, expected);
+            if let Some(voter) = expected.voter {
+                if stake.delegation.voter_pubkey != voter {
+                    mismatches.push(format!(
+                        "delegated to {}, expected {}",
+                        stake.delegation.voter_pubkey, voter
+                    ));
+                }
+            }
+            mismatches
+        }
+        StakeState::Uninitialized | StakeState::RewardsPool => {
+            vec!["not initialized".to_string()]
+        }
+    };
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Stake account {} differs from what is expected: {}",
+            address,
+            mismatches.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        solana_stake_program::stake_state::{Authorized, Stake},
+    };
+
+    fn stake_account(stake_state: StakeState) -> Account {
+        Account {
+            lamports: 1,
+            data: bincode::serialize(&stake_state).unwrap(),
+            owner: solana_stake_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let address = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let voter = Pubkey::new_unique();
+        let meta = Meta {
+            authorized: Authorized {
+                staker: authority,
+                withdrawer: authority,
+            },
+            ..Meta::default()
+        };
+        let mut stake = Stake::default();
+        stake.delegation.voter_pubkey = voter;
+        let expected = ExpectedStakeAccount::authorized(authority).delegated_to(voter);
+
+        assert!(check(
+            &address,
+            &stake_account(StakeState::Initialized(meta)),
+            &expected
+        )
+        .is_ok());
+        assert!(check(
+            &address,
+            &stake_account(StakeState::Stake(meta, stake)),
+            &expected
+        )
+        .is_ok());
+        assert!(check(
+            &address,
+            &stake_account(StakeState::Stake(meta, stake)),
+            &ExpectedStakeAccount::authorized(authority)
+        )
+        .is_ok());
+
+        // Withdrawer changed by a manual intervention
+        let other = Pubkey::new_unique();
+        let mut changed_meta = meta;
+        changed_meta.authorized.withdrawer = other;
+        assert_eq!(
+            check(
+                &address,
+                &stake_account(StakeState::Initialized(changed_meta)),
+                &expected
+            ),
+            Err(format!(
+                "Stake account {} differs from what is expected: withdrawer is {}, expected {}",
+                address, other, authority
+            ))
+        );
+
+        let mut redelegated = stake;
+        redelegated.delegation.voter_pubkey = other;
+        assert!(check(
+            &address,
+            &stake_account(StakeState::Stake(meta, redelegated)),
+            &expected
+        )
+        .is_err());
+
+        assert!(check(
+            &address,
+            &stake_account(StakeState::Uninitialized),
+            &expected
+        )
+        .is_err());
+        let mut not_stake = stake_account(StakeState::Initialized(meta));
+        not_stake.owner = Pubkey::new_unique();
+        assert!(check(&address, &not_stake, &expected).is_err());
+    }
+}
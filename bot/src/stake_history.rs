@@ -0,0 +1,181 @@
+//! History of the stake moved to and from a validator, assembled from the epoch classifications
+//! of the data directory, so that the stake a validator was given or lost, and the stake state
+//! behind it, can be looked up without reconstructing it from the logs
+use {
+    crate::{
+        db::{EpochClassification, EpochClassificationV1},
+        generic_stake_pool::ValidatorStakeState,
+        report_template::signed_sol,
+    },
+    serde::Serialize,
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::{fmt, io, path::Path},
+};
+
+/// Stake moved to or from the validator in one epoch. Pubkeys are strings, as in the other JSON
+/// documents of the bot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StakeHistoryEntry {
+    pub epoch: Epoch,
+    pub vote_address: String,
+
+    /// None in the epochs the validator was not classified, such as after its removal
+    pub stake_state: Option<ValidatorStakeState>,
+    pub stake_state_reason: Option<String>,
+
+    /// Stake added (positive) or removed (negative) by the run of the epoch
+    pub stake_movement_lamports: i64,
+
+    /// Sum of the stake movements up to and including the epoch
+    pub net_stake_movement_lamports: i64,
+}
+
+impl fmt::Display for StakeHistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Epoch {}: {}, net {}",
+            self.epoch,
+            signed_sol(self.stake_movement_lamports),
+            signed_sol(self.net_stake_movement_lamports)
+        )?;
+        if let (Some(stake_state), Some(reason)) = (&self.stake_state, &self.stake_state_reason) {
+            write!(f, " ({:?}: {})", stake_state, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// The stake history of `validator`, given by identity or vote address, over `epochs` in
+/// ascending order. Epochs in which the validator was neither classified nor moved are omitted
+pub fn stake_history(
+    validator: &Pubkey,
+    epochs: &[(Epoch, EpochClassificationV1)],
+) -> Vec<StakeHistoryEntry> {
+    // Stake movements are recorded by vote address, which remains known once the validator is no
+    // longer classified
+    let mut vote_address = *validator;
+    let mut net_stake_movement_lamports = 0;
+    let mut history = vec![];
+    for (epoch, epoch_classification) in epochs {
+        let classification = epoch_classification
+            .validator_classifications
+            .as_ref()
+            .and_then(|validator_classifications| {
+                validator_classifications.values().find(|classification| {
+                    classification.identity == *validator
+                        || classification.vote_address == *validator
+                })
+            });
+        if let Some(classification) = classification {
+            vote_address = classification.vote_address;
+        }
+        let stake_movement_lamports = epoch_classification
+            .stake_pool_summary
+            .as_ref()
+            .and_then(|summary| summary.stake_movement_lamports.get(&vote_address))
+            .copied();
+        if classification.is_none() && stake_movement_lamports.is_none() {
+            continue;
+        }
+
+        let stake_movement_lamports = stake_movement_lamports.unwrap_or_default();
+        net_stake_movement_lamports += stake_movement_lamports;
+        history.push(StakeHistoryEntry {
+            epoch: *epoch,
+            vote_address: vote_address.to_string(),
+            stake_state: classification.map(|classification| classification.stake_state),
+            stake_state_reason: classification
+                .map(|classification| classification.stake_state_reason.clone()),
+            stake_movement_lamports,
+            net_stake_movement_lamports,
+        });
+    }
+    history
+}
+
+/// The stake history of `validator` over every epoch classification in `path`
+pub fn load<P>(validator: &Pubkey, path: P) -> Result<Vec<StakeHistoryEntry>, io::Error>
+where
+    P: AsRef<Path>,
+{
+    let epochs = EpochClassification::epochs(&path)
+        .into_iter()
+        .map(|epoch| {
+            EpochClassification::load(epoch, &path)
+                .map(|epoch_classification| (epoch, epoch_classification.into_current()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(stake_history(validator, &epochs))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{db::ValidatorClassification, generic_stake_pool::StakePoolSummary},
+        std::collections::HashMap,
+    };
+
+    fn epoch_classification(
+        classification: Option<&ValidatorClassification>,
+        stake_movement_lamports: Option<(Pubkey, i64)>,
+    ) -> EpochClassificationV1 {
+        EpochClassificationV1 {
+            validator_classifications: Some(
+                classification
+                    .map(|classification| (classification.identity, classification.clone()))
+                    .into_iter()
+                    .collect::<HashMap<_, _>>(),
+            ),
+            stake_pool_summary: Some(StakePoolSummary {
+                stake_movement_lamports: stake_movement_lamports.into_iter().collect(),
+                ..StakePoolSummary::default()
+            }),
+            ..EpochClassificationV1::default()
+        }
+    }
+
+    #[test]
+    fn test_stake_history() {
+        let classification = ValidatorClassification {
+            identity: Pubkey::new_unique(),
+            vote_address: Pubkey::new_unique(),
+            stake_state: ValidatorStakeState::Bonus,
+            stake_state_reason: "good".to_string(),
+            ..ValidatorClassification::default()
+        };
+        let vote_address = classification.vote_address;
+        let epochs = vec![
+            (10, epoch_classification(None, None)),
+            (
+                11,
+                epoch_classification(Some(&classification), Some((vote_address, 300))),
+            ),
+            (12, epoch_classification(Some(&classification), None)),
+            (
+                13,
+                epoch_classification(None, Some((Pubkey::new_unique(), 7))),
+            ),
+            (14, epoch_classification(None, Some((vote_address, -250)))),
+        ];
+
+        let history = stake_history(&classification.identity, &epochs);
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (
+                    entry.epoch,
+                    entry.stake_movement_lamports,
+                    entry.net_stake_movement_lamports
+                ))
+                .collect::<Vec<_>>(),
+            vec![(11, 300, 300), (12, 0, 300), (14, -250, 50)]
+        );
+        assert_eq!(history[0].stake_state, Some(ValidatorStakeState::Bonus));
+        assert_eq!(history[2].stake_state, None);
+        assert_eq!(history[2].vote_address, vote_address.to_string());
+        assert_eq!(stake_history(&vote_address, &epochs), history);
+        assert!(stake_history(&Pubkey::new_unique(), &epochs).is_empty());
+    }
+}
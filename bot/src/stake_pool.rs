@@ -1,13 +1,40 @@
 use {
     crate::{
+        account_store::AccountStore,
+        balance_forecast::BalanceForecast,
+        bonus_ramp::{BonusRamp, BonusRampStage},
+        commission_revenue::{self, StakeReward},
+        confirmation::ConfirmationGate,
+        external_deposit,
         generic_stake_pool::*,
-        rpc_client_utils::{get_all_stake, send_and_confirm_transactions},
+        liquidity_buffer::{LiquidityBuffer, LiquidityBufferStage},
+        memo::TransactionMemo,
+        operation_graph::{OperationGraph, OperationId, OperationStatus},
+        planner::{self, AllocationParams, BaselineShortfall},
+        pool_state::{self, PoolState},
+        rebalance_plan,
+        roles::{Operation, Role, StakePoolRoles},
+        rollback,
+        rpc_client_utils::get_all_stake,
+        run_status::guardrail,
+        small_pool::SmallPool,
+        stake_account_check::{self, ExpectedStakeAccount},
+        stake_pool_program,
+        stake_projection::{self, ClusterStake, StakeActivity, PROJECTION_EPOCHS},
+        transaction_executor::TransactionExecutor,
+        transaction_packer,
+        vote_account_cache::VoteAccountCache,
     },
     borsh::BorshDeserialize,
     log::*,
-    solana_client::{rpc_client::RpcClient, rpc_response::StakeActivationState},
+    serde::{Deserialize, Serialize},
+    solana_client::{
+        rpc_client::RpcClient,
+        rpc_response::{RpcInflationReward, RpcVoteAccountStatus, StakeActivationState},
+    },
     solana_sdk::{
         borsh::try_from_slice_unchecked,
+        clock::Epoch,
         native_token::{Sol, LAMPORTS_PER_SOL},
         pubkey::Pubkey,
         signature::{Keypair, Signer},
@@ -17,10 +44,17 @@ use {
     solana_stake_program::{stake_instruction, stake_state::StakeState},
     spl_stake_pool::{
         self, find_stake_program_address, find_transient_stake_program_address,
+        find_withdraw_authority_program_address,
         stake_program::split_only,
         state::{StakePool, StakeStatus, ValidatorList},
     },
-    std::{collections::HashSet, error, mem},
+    std::{
+        cmp::Reverse,
+        collections::{HashMap, HashSet},
+        error, fmt, mem,
+        str::FromStr,
+        sync::Arc,
+    },
 };
 
 /// Minimum amount of lamports in a validator stake account, on top of the
@@ -31,9 +65,117 @@ pub const MIN_STAKE_ACCOUNT_BALANCE: u64 = LAMPORTS_PER_SOL;
 /// amount
 pub const MIN_STAKE_RESERVE_BALANCE: u64 = 1;
 
-/// Don't bother adjusting stake if less than this amount of lamports will be affected
-/// (must be >= MIN_STAKE_ACCOUNT_BALANCE)
-const MIN_STAKE_CHANGE_AMOUNT: u64 = MIN_STAKE_ACCOUNT_BALANCE;
+/// Stages of the stake pool update performed each epoch, which can be disabled individually
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Withdraw inactive stake accounts of the staker
+    Withdrawals,
+    /// Remove validators no longer desired from the pool
+    Removals,
+    /// Add validators with an active stake account to the pool
+    Additions,
+    /// Create and delegate stake accounts for validators joining the pool
+    AccountCreation,
+    /// Move stake between the reserve and the validators
+    Rebalancing,
+}
+
+impl Phase {
+    pub const ALL: &'static [&'static str] = &[
+        "withdrawals",
+        "removals",
+        "additions",
+        "account-creation",
+        "rebalancing",
+    ];
+}
+
+impl FromStr for Phase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "withdrawals" => Ok(Phase::Withdrawals),
+            "removals" => Ok(Phase::Removals),
+            "additions" => Ok(Phase::Additions),
+            "account-creation" => Ok(Phase::AccountCreation),
+            "rebalancing" => Ok(Phase::Rebalancing),
+            _ => Err(format!("Invalid phase: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Phase::Withdrawals => "withdrawals",
+                Phase::Removals => "removals",
+                Phase::Additions => "additions",
+                Phase::AccountCreation => "account-creation",
+                Phase::Rebalancing => "rebalancing",
+            }
+        )
+    }
+}
+
+/// Which stake increases the reserve funds first when it cannot fund them all
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FundingPriority {
+    /// Fund the smallest stake accounts first, to maximize the number of validators funded
+    SmallestAccount,
+    /// Fund the validators furthest below their desired stake first, so that the validators
+    /// receiving the most stake are fully funded
+    LargestDeficit,
+    /// Scale the increases of each stake state in proportion to their amount, so that every
+    /// validator receives a share of the reserve rather than some being fully funded and the
+    /// rest starved
+    ProRata,
+}
+
+impl FromStr for FundingPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "smallest-account" => Ok(FundingPriority::SmallestAccount),
+            "largest-deficit" => Ok(FundingPriority::LargestDeficit),
+            "pro-rata" => Ok(FundingPriority::ProRata),
+            _ => Err(format!("Invalid funding priority: {}", s)),
+        }
+    }
+}
+
+/// Order in which the operations of the stake pool update are issued
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperationOrder {
+    /// Remove validators from the pool before adding new ones, rather than after
+    pub removals_first: bool,
+    /// Issue all the stake decreases before the stake increases, rather than in funding order
+    pub decreases_first: bool,
+    pub funding_priority: FundingPriority,
+}
+
+impl Default for OperationOrder {
+    fn default() -> Self {
+        Self {
+            removals_first: true,
+            decreases_first: false,
+            funding_priority: FundingPriority::SmallestAccount,
+        }
+    }
+}
+
+/// Policy moving the SOL accumulated by the staker, from reclaimed rent and split off rewards,
+/// to a treasury
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TreasurySweep {
+    pub treasury: Pubkey,
+    /// Balance the staker keeps to pay for the stake pool updates
+    pub float_lamports: u64,
+}
 
 fn get_minimum_stake_balance_for_rent_exemption(
     rpc_client: &RpcClient,
@@ -71,20 +213,86 @@ fn staker_transient_stake_address(authorized_staker: Pubkey, vote_address: Pubke
 
 #[derive(Debug)]
 pub struct StakePoolOMatic {
-    authorized_staker: Keypair,
+    roles: StakePoolRoles,
+    program_id: Pubkey,
     baseline_stake_amount: u64,
+    min_stake_change: MinStakeChange,
+    stake_tolerance: StakeTolerance,
+    funder_low_balance: Option<u64>,
+    deposit_excess_rewards: bool,
+    keep_external_deposits: bool,
+    disabled_phases: HashSet<Phase>,
+    max_asn_stake_percentage: Option<f64>,
+    max_epoch_stake_change_percentage: Option<f64>,
+    bonus_ramp: Option<BonusRamp>,
+    baseline_shortfall: BaselineShortfall,
+    liquidity_buffer: Option<LiquidityBuffer>,
+    previous_bonus_ramp: Option<BonusRampStage>,
+    // Epoch and liquidity buffer recorded by the previous epoch
+    previous_liquidity_buffer: Option<(Epoch, LiquidityBufferStage)>,
+    previous_stake_movement_lamports: HashMap<Pubkey, i64>,
+    // Epoch and balance of each validator recorded by the previous epoch, if all of its stake
+    // movements went through
+    previous_balances: Option<(Epoch, HashMap<Pubkey, u64>)>,
+    kept_external_deposit_lamports: HashMap<Pubkey, u64>,
+    busy_carryover_lamports: HashMap<Pubkey, i64>,
+    // Figures of the earlier runs of the epoch, if this is an incremental rerun
+    latest_run: Option<StakePoolSummary>,
+    epoch_boundary_margin_slots: Option<u64>,
+    treasury_sweep: Option<TreasurySweep>,
+    confirmation_gate: Option<ConfirmationGate>,
+    soft_removal_epochs: Option<u64>,
+    soft_removed_since: HashMap<Pubkey, Epoch>,
+    operation_order: OperationOrder,
+    small_pool: Option<SmallPool>,
     stake_pool_address: Pubkey,
     stake_pool: StakePool,
     validator_list: ValidatorList,
+    summary: Option<StakePoolSummary>,
+    run_id: Option<String>,
+    vote_account_cache: Option<Arc<VoteAccountCache>>,
+    transaction_executor: Box<dyn TransactionExecutor>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn new(
     rpc_client: &RpcClient,
-    authorized_staker: Keypair,
+    roles: StakePoolRoles,
+    program_id: Pubkey,
     stake_pool_address: Pubkey,
     baseline_stake_amount: u64,
+    min_stake_change: MinStakeChange,
+    stake_tolerance: StakeTolerance,
+    funder_low_balance: Option<u64>,
+    deposit_excess_rewards: bool,
+    keep_external_deposits: bool,
+    disabled_phases: HashSet<Phase>,
+    max_asn_stake_percentage: Option<f64>,
+    max_epoch_stake_change_percentage: Option<f64>,
+    bonus_ramp: Option<BonusRamp>,
+    baseline_shortfall: BaselineShortfall,
+    liquidity_buffer: Option<LiquidityBuffer>,
+    epoch_boundary_margin_slots: Option<u64>,
+    treasury_sweep: Option<TreasurySweep>,
+    confirmation_gate: Option<ConfirmationGate>,
+    soft_removal_epochs: Option<u64>,
+    operation_order: OperationOrder,
+    small_pool: Option<SmallPool>,
+    transaction_executor: Box<dyn TransactionExecutor>,
 ) -> Result<StakePoolOMatic, Box<dyn error::Error>> {
-    if baseline_stake_amount < MIN_STAKE_CHANGE_AMOUNT {
+    // Stake movements create transient stake accounts that must hold at least
+    // MIN_STAKE_ACCOUNT_BALANCE, the MINIMUM_ACTIVE_STAKE the stake pool program enforces even in
+    // small pool mode
+    if min_stake_change.lamports < MIN_STAKE_ACCOUNT_BALANCE {
+        return Err(format!(
+            "minimum stake change amount too small: {}. Minimum is {}",
+            Sol(min_stake_change.lamports),
+            Sol(MIN_STAKE_ACCOUNT_BALANCE)
+        )
+        .into());
+    }
+
+    if baseline_stake_amount < min_stake_change.lamports {
         return Err(format!(
             "baseline stake amount too small: {}",
             Sol(baseline_stake_amount)
@@ -92,6 +300,12 @@ pub fn new(
         .into());
     }
 
+    let program_version = stake_pool_program::detect(rpc_client, &program_id, &stake_pool_address)?;
+    info!(
+        "Stake pool program {} version: {}",
+        program_id, program_version
+    );
+
     let account_data = rpc_client.get_account_data(&stake_pool_address)?;
     let stake_pool = StakePool::try_from_slice(account_data.as_slice())
         .map_err(|err| format!("Invalid stake pool {}: {}", stake_pool_address, err))?;
@@ -103,24 +317,442 @@ pub fn new(
                 stake_pool.validator_list, err
             )
         })?;
+    roles.verify(&stake_pool_address, &stake_pool)?;
 
     Ok(StakePoolOMatic {
-        authorized_staker,
+        roles,
+        program_id,
         baseline_stake_amount,
+        min_stake_change,
+        stake_tolerance,
+        funder_low_balance,
+        deposit_excess_rewards,
+        keep_external_deposits,
+        disabled_phases,
+        max_asn_stake_percentage,
+        max_epoch_stake_change_percentage,
+        bonus_ramp,
+        baseline_shortfall,
+        liquidity_buffer,
+        previous_bonus_ramp: None,
+        previous_liquidity_buffer: None,
+        previous_stake_movement_lamports: HashMap::new(),
+        previous_balances: None,
+        kept_external_deposit_lamports: HashMap::new(),
+        busy_carryover_lamports: HashMap::new(),
+        latest_run: None,
+        epoch_boundary_margin_slots,
+        treasury_sweep,
+        confirmation_gate,
+        soft_removal_epochs,
+        soft_removed_since: HashMap::new(),
+        operation_order,
+        small_pool,
         stake_pool_address,
         stake_pool,
         validator_list,
+        summary: None,
+        run_id: None,
+        vote_account_cache: None,
+        transaction_executor,
     })
 }
 
 impl StakePoolOMatic {
+    fn enabled(&self, phase: Phase) -> bool {
+        if self.disabled_phases.contains(&phase) {
+            info!("Phase {} is disabled, skipping", phase);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Fail before sending transactions that could land in a later epoch than `epoch`, the epoch
+    /// the update started in. The transient stake accounts and validator list read in one epoch
+    /// do not describe the stake pool in the next, so their transactions fail in confusing ways
+    fn check_epoch_boundary(
+        &self,
+        rpc_client: &RpcClient,
+        epoch: Epoch,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let epoch_boundary_margin_slots = match self.epoch_boundary_margin_slots {
+            Some(epoch_boundary_margin_slots) => epoch_boundary_margin_slots,
+            None => return Ok(()),
+        };
+        let epoch_info = rpc_client.get_epoch_info()?;
+        if epoch_info.epoch != epoch {
+            return Err(guardrail(format!(
+                "Epoch {} ended during the stake pool update, rerun to update the stake pool for \
+                 epoch {}",
+                epoch, epoch_info.epoch
+            )));
+        }
+        let slots_remaining = epoch_info
+            .slots_in_epoch
+            .saturating_sub(epoch_info.slot_index);
+        if slots_remaining < epoch_boundary_margin_slots {
+            return Err(guardrail(format!(
+                "Only {} slots remain in epoch {}, transactions could land after the epoch \
+                 boundary. Rerun once epoch {} begins",
+                slots_remaining,
+                epoch,
+                epoch + 1
+            )));
+        }
+        Ok(())
+    }
+
+    /// Add the removals of validators to `graph`, after the operations of `dependencies`.
+    /// Returns the last operation of each removal
+    fn remove_validators<'a>(
+        &'a self,
+        accounts: &AccountStore,
+        memo: &TransactionMemo,
+        remove_vote_addresses: HashSet<Pubkey>,
+        graph: &mut OperationGraph<'a>,
+        dependencies: &[OperationId],
+    ) -> Result<Vec<OperationId>, Box<dyn error::Error>> {
+        info!("Remove validators no longer present in the desired list");
+        if self.enabled(Phase::Removals) {
+            remove_validators_from_pool(
+                accounts,
+                memo,
+                &self.program_id,
+                self.roles.authority_for(Operation::RemoveValidator)?,
+                &self.stake_pool_address,
+                &self.stake_pool,
+                &self.validator_list,
+                remove_vote_addresses,
+                graph,
+                dependencies,
+            )
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// The desired validator stake once the validators failing the criteria, those no longer
+    /// desired or desired at the minimum stake, are soft removed: held in the pool at the minimum
+    /// stake for `soft_removal_epochs`, then left out of the desired validator stake to be
+    /// removed from the pool. Also returns the epoch the soft removal of each held validator
+    /// began, and notes on the soft removals
+    #[allow(clippy::type_complexity)]
+    fn soft_remove_validators(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        epoch: Epoch,
+        desired_validator_stake: &[ValidatorStake],
+    ) -> Result<(Vec<ValidatorStake>, HashMap<Pubkey, Epoch>, Vec<String>), Box<dyn error::Error>>
+    {
+        let soft_removal_epochs = match self.soft_removal_epochs {
+            Some(soft_removal_epochs) => soft_removal_epochs,
+            None => return Ok((desired_validator_stake.to_vec(), HashMap::new(), vec![])),
+        };
+
+        let desired_vote_addresses = desired_validator_stake
+            .iter()
+            .map(|vs| vs.vote_address)
+            .collect::<HashSet<_>>();
+        let undesired_vote_addresses = self
+            .validator_list
+            .validators
+            .iter()
+            .filter(|validator| {
+                validator.status == StakeStatus::Active
+                    && !desired_vote_addresses.contains(&validator.vote_account_address)
+            })
+            .map(|validator| validator.vote_account_address)
+            .collect::<Vec<_>>();
+        let failing_vote_addresses = desired_validator_stake
+            .iter()
+            .filter(|vs| vs.stake_state == ValidatorStakeState::None)
+            .map(|vs| vs.vote_address)
+            .chain(undesired_vote_addresses.iter().cloned())
+            .collect::<HashSet<_>>();
+        let (mut soft_removed_since, removed_vote_addresses) = soft_removals(
+            epoch,
+            soft_removal_epochs,
+            &self.soft_removed_since,
+            &failing_vote_addresses,
+        );
+
+        let mut validator_stake = desired_validator_stake
+            .iter()
+            .filter(|vs| !removed_vote_addresses.contains(&vs.vote_address))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // The validators no longer desired are held under the identity of their vote account
+        let held_vote_addresses = undesired_vote_addresses
+            .into_iter()
+            .filter(|vote_address| soft_removed_since.contains_key(vote_address))
+            .collect::<Vec<_>>();
+        if !held_vote_addresses.is_empty() {
+            let identities = vote_accounts
+                .current
+                .iter()
+                .chain(vote_accounts.delinquent.iter())
+                .filter_map(|vote_account| {
+                    Some((
+                        Pubkey::from_str(&vote_account.vote_pubkey).ok()?,
+                        Pubkey::from_str(&vote_account.node_pubkey).ok()?,
+                    ))
+                })
+                .collect::<HashMap<_, _>>();
+            for vote_address in held_vote_addresses {
+                match identities.get(&vote_address) {
+                    Some(identity) => validator_stake.push(ValidatorStake::new(
+                        *identity,
+                        vote_address,
+                        ValidatorStakeState::None,
+                    )),
+                    None => {
+                        warn!(
+                            "Vote account {} not found, removing it without soft removal",
+                            vote_address
+                        );
+                        soft_removed_since.remove(&vote_address);
+                    }
+                }
+            }
+        }
+
+        let mut notes = vec![];
+        if !soft_removed_since.is_empty() {
+            notes.push(format!(
+                "{} validators soft removed, held at the minimum stake for up to {} epochs",
+                soft_removed_since.len(),
+                soft_removal_epochs
+            ));
+        }
+        let removed_from_pool = removed_vote_addresses
+            .iter()
+            .filter(|vote_address| self.validator_list.contains(vote_address))
+            .count();
+        if removed_from_pool > 0 {
+            notes.push(format!(
+                "{} validators removed from the pool after {} epochs of soft removal",
+                removed_from_pool, soft_removal_epochs
+            ));
+        }
+        Ok((validator_stake, soft_removed_since, notes))
+    }
+
+    /// Rewards the validator stake accounts of the pool received for the epoch before `epoch`, by
+    /// vote address
+    fn validator_rewards(
+        &self,
+        rpc_client: &RpcClient,
+        epoch: Epoch,
+    ) -> Result<HashMap<Pubkey, RpcInflationReward>, Box<dyn error::Error>> {
+        if epoch == 0 || self.validator_list.validators.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let vote_addresses = self
+            .validator_list
+            .validators
+            .iter()
+            .map(|validator| validator.vote_account_address)
+            .collect::<Vec<_>>();
+        let stake_addresses = vote_addresses
+            .iter()
+            .map(|vote_address| {
+                find_stake_program_address(&self.program_id, vote_address, &self.stake_pool_address)
+                    .0
+            })
+            .collect::<Vec<_>>();
+        let rewards = rpc_client.get_inflation_reward(&stake_addresses, Some(epoch - 1))?;
+        Ok(vote_addresses
+            .into_iter()
+            .zip(rewards)
+            .filter_map(|(vote_address, reward)| Some((vote_address, reward?)))
+            .collect())
+    }
+
+    /// Commission the validators of the pool earned from its delegation, by vote address, given
+    /// the `rewards` of their stake accounts. The commission of each validator is its current
+    /// commission
+    fn validator_commission(
+        &self,
+        accounts: &AccountStore,
+        rewards: &HashMap<Pubkey, RpcInflationReward>,
+    ) -> HashMap<Pubkey, u64> {
+        let vote_accounts = accounts.vote_accounts();
+        let commissions = vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter_map(|vote_account| {
+                Some((
+                    Pubkey::from_str(&vote_account.vote_pubkey).ok()?,
+                    vote_account.commission,
+                ))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let stake_rewards = rewards
+            .iter()
+            .filter_map(|(vote_address, reward)| {
+                Some(StakeReward {
+                    vote_address: *vote_address,
+                    stake_lamports: reward.post_balance.saturating_sub(reward.amount),
+                    reward_lamports: reward.amount,
+                    commission: *commissions.get(vote_address)?,
+                })
+            })
+            .collect::<Vec<_>>();
+        commission_revenue::commission_revenue(&stake_rewards)
+    }
+
+    /// Add the additions of validators to `graph`, after the operations of `dependencies`.
+    /// Returns the attempted additions and their operations
+    fn add_validators<'a>(
+        &'a self,
+        accounts: &AccountStore,
+        memo: &TransactionMemo,
+        desired_validator_stake: &[ValidatorStake],
+        graph: &mut OperationGraph<'a>,
+        dependencies: &[OperationId],
+    ) -> Result<(HashMap<Pubkey, u64>, Vec<OperationId>), Box<dyn error::Error>> {
+        info!("Add new validators to pool if active");
+        if self.enabled(Phase::Additions) {
+            add_validators_to_pool(
+                accounts,
+                memo,
+                &self.program_id,
+                self.roles.authority_for(Operation::AddValidator)?,
+                desired_validator_stake,
+                &self.stake_pool_address,
+                &self.stake_pool,
+                &self.validator_list,
+                graph,
+                dependencies,
+            )
+        } else {
+            Ok((HashMap::new(), vec![]))
+        }
+    }
+
+    /// Notes for the staker and funder balances projected to be insufficient for the update to
+    /// `desired_validator_stake`
+    fn forecast_balances(
+        &self,
+        rpc_client: &RpcClient,
+        accounts: &AccountStore,
+        desired_validator_stake: &[ValidatorStake],
+    ) -> Result<Vec<String>, Box<dyn error::Error>> {
+        let stake_rent_exemption = accounts.stake_rent_exemption();
+        let (_blockhash, fee_calculator) = rpc_client.get_recent_blockhash()?;
+        let forecast = BalanceForecast::new(
+            desired_validator_stake,
+            &self
+                .validator_list
+                .validators
+                .iter()
+                .map(|x| x.vote_account_address)
+                .collect(),
+            stake_rent_exemption + MIN_STAKE_ACCOUNT_BALANCE,
+            fee_calculator.lamports_per_signature,
+        );
+        info!("Balance forecast: {:?}", forecast);
+
+        let staker = self.roles.staker.pubkey();
+        let funder = self.roles.keypair(Role::Funder).unwrap().pubkey();
+        let mut required_lamports = vec![(Role::Staker, staker, forecast.staker_lamports())];
+        if funder == staker {
+            required_lamports[0].2 += forecast.funder_lamports();
+        } else {
+            required_lamports.push((Role::Funder, funder, forecast.funder_lamports()));
+        }
+
+        let mut notes = vec![];
+        for (role, address, required_lamports) in required_lamports {
+            let balance = accounts.balance(&address)?;
+            if balance < required_lamports {
+                warn!(
+                    "The {} {} holds {} but is projected to need {}",
+                    role,
+                    address,
+                    Sol(balance),
+                    Sol(required_lamports)
+                );
+                notes.push(format!(
+                    "The {} {} balance is projected to be insufficient for this epoch: {} \
+                     available, {} required",
+                    role,
+                    address,
+                    Sol(balance),
+                    Sol(required_lamports)
+                ));
+            }
+        }
+        Ok(notes)
+    }
+
+    /// The liquidity buffer of `epoch`, given the rewards the pool earned in the previous epoch.
+    /// An incremental rerun keeps the buffer of the earlier runs of the epoch, and the withdrawals
+    /// are only observed over a single epoch
+    fn liquidity_buffer_stage(
+        &self,
+        epoch: Epoch,
+        reward_lamports: u64,
+    ) -> Option<LiquidityBufferStage> {
+        if let Some(latest_run) = &self.latest_run {
+            return latest_run.liquidity_buffer;
+        }
+        let previous = self
+            .previous_liquidity_buffer
+            .as_ref()
+            .filter(|(previous_epoch, _)| previous_epoch + 1 == epoch)
+            .map(|(_, previous)| previous);
+        self.liquidity_buffer.map(|liquidity_buffer| {
+            liquidity_buffer.stage(
+                self.stake_pool.total_stake_lamports,
+                reward_lamports,
+                previous,
+            )
+        })
+    }
+
+    /// The figures and policies of the pool that its allocation depends on
+    fn allocation_params(
+        &self,
+        liquidity_buffer: Option<&LiquidityBufferStage>,
+    ) -> AllocationParams {
+        AllocationParams {
+            // The external deposits kept by their validators are not for the pool to allocate
+            total_stake_lamports: self
+                .stake_pool
+                .total_stake_lamports
+                .saturating_sub(self.kept_external_deposit_lamports.values().sum::<u64>()),
+            baseline_stake_lamports: self.baseline_stake_amount,
+            max_asn_stake_percentage: self.max_asn_stake_percentage,
+            bonus_ramp: self.bonus_ramp,
+            baseline_shortfall: self.baseline_shortfall,
+            liquidity_buffer_lamports: liquidity_buffer
+                .map(|liquidity_buffer| liquidity_buffer.buffer_lamports)
+                .unwrap_or_default(),
+            previous_bonus_ramp: self.previous_bonus_ramp,
+        }
+    }
+
+    /// Memo of the transactions of this run in `epoch`
+    fn memo(&self, epoch: Epoch) -> TransactionMemo {
+        TransactionMemo::new(self.run_id.as_deref().unwrap_or("-"), epoch)
+    }
+
     /// Perform the double update, required at the start of an epoch:
     /// * call into the stake pool program to update the accounting of lamports
     /// * update the StakePool and ValidatorList objects based on the accounting
     pub fn epoch_update(&mut self, rpc_client: &RpcClient) -> Result<(), Box<dyn error::Error>> {
+        let epoch = rpc_client.get_epoch_info()?.epoch;
         update_stake_pool(
             rpc_client,
-            &self.authorized_staker,
+            self.transaction_executor.as_ref(),
+            &self.memo(epoch),
+            &self.program_id,
+            &self.roles.staker,
             &self.stake_pool_address,
             &self.stake_pool,
             &self.validator_list,
@@ -129,6 +761,57 @@ impl StakePoolOMatic {
         Ok(())
     }
 
+    /// Prefetch the accounts the phases of the update read: the staker, funder and reserve
+    /// balances, and the stake accounts of the validators of the pool and of
+    /// `desired_validator_stake`
+    fn prefetch(
+        &self,
+        rpc_client: &RpcClient,
+        epoch: Epoch,
+        desired_validator_stake: &[ValidatorStake],
+    ) -> Result<AccountStore, Box<dyn error::Error>> {
+        let staker = self.roles.staker.pubkey();
+        let vote_addresses = self
+            .validator_list
+            .validators
+            .iter()
+            .map(|validator| validator.vote_account_address)
+            .chain(desired_validator_stake.iter().map(|vs| vs.vote_address))
+            .collect::<HashSet<_>>();
+        let mut addresses = vec![
+            staker,
+            self.roles.keypair(Role::Funder).unwrap().pubkey(),
+            self.stake_pool.reserve_stake,
+        ];
+        for vote_address in vote_addresses {
+            addresses.push(
+                find_stake_program_address(
+                    &self.program_id,
+                    &vote_address,
+                    &self.stake_pool_address,
+                )
+                .0,
+            );
+            addresses.push(
+                find_transient_stake_program_address(
+                    &self.program_id,
+                    &vote_address,
+                    &self.stake_pool_address,
+                )
+                .0,
+            );
+        }
+        addresses.sort();
+        addresses.dedup();
+        AccountStore::prefetch(
+            rpc_client,
+            epoch,
+            &addresses,
+            &staker,
+            self.vote_account_cache.as_deref(),
+        )
+    }
+
     /// Update the StakePoolOMatic instance with the current StakePool and ValidatorList
     /// from the network.
     pub fn update(&mut self, rpc_client: &RpcClient) -> Result<(), Box<dyn error::Error>> {
@@ -147,6 +830,42 @@ impl StakePoolOMatic {
     }
 }
 
+/// The current stake of each validator of `validator_list` and the desired stake of each
+/// validator of `desired_validator_stake`
+fn stake_allocation(
+    validator_list: &ValidatorList,
+    desired_validator_stake: &[ValidatorStake],
+    baseline_stake_amount: u64,
+    bonus_stake_amounts: &HashMap<Pubkey, u64>,
+) -> HashMap<Pubkey, StakeAllocation> {
+    let mut allocation = HashMap::<Pubkey, StakeAllocation>::new();
+    for validator in &validator_list.validators {
+        allocation
+            .entry(validator.vote_account_address)
+            .or_default()
+            .current_lamports = validator.stake_lamports;
+    }
+    for ValidatorStake {
+        vote_address,
+        stake_state,
+        ..
+    } in desired_validator_stake
+    {
+        allocation
+            .entry(*vote_address)
+            .or_default()
+            .desired_lamports = match stake_state {
+            ValidatorStakeState::None => 0,
+            ValidatorStakeState::Baseline => baseline_stake_amount,
+            ValidatorStakeState::Bonus => bonus_stake_amounts
+                .get(vote_address)
+                .cloned()
+                .unwrap_or_default(),
+        };
+    }
+    allocation
+}
+
 impl GenericStakePool for StakePoolOMatic {
     fn apply(
         &mut self,
@@ -157,134 +876,947 @@ impl GenericStakePool for StakePoolOMatic {
         if dry_run {
             return Err("dryrun not supported".into());
         }
+        let epoch = rpc_client.get_epoch_info()?.epoch;
+        let memo = self.memo(epoch);
 
-        let mut bonus_stake_node_count = 0;
-        let mut baseline_stake_node_count = 0;
+        info!("Prefetch the accounts of the stake pool");
+        let accounts = self.prefetch(rpc_client, epoch, desired_validator_stake)?;
 
-        // used to find any validators that should be removed from the stake pool
-        let mut inuse_vote_addresses = HashSet::new();
+        let (desired_validator_stake, soft_removed_since, soft_removal_notes) =
+            self.soft_remove_validators(accounts.vote_accounts(), epoch, desired_validator_stake)?;
+        let desired_validator_stake = desired_validator_stake.as_slice();
 
-        for ValidatorStake {
-            stake_state,
-            vote_address,
-            ..
-        } in desired_validator_stake
-        {
-            inuse_vote_addresses.insert(*vote_address);
+        // Warn up front, rather than run into insufficient funds part way through the update
+        let balance_notes =
+            self.forecast_balances(rpc_client, &accounts, desired_validator_stake)?;
 
-            match stake_state {
-                ValidatorStakeState::Bonus => bonus_stake_node_count += 1,
-                ValidatorStakeState::Baseline => baseline_stake_node_count += 1,
-                ValidatorStakeState::None => (),
-            }
-        }
+        // used to find any validators that should be removed from the stake pool
+        let inuse_vote_addresses = desired_validator_stake
+            .iter()
+            .map(|validator_stake| validator_stake.vote_address)
+            .collect::<HashSet<_>>();
 
         info!("Withdraw inactive transient stake accounts to the staker");
-        withdraw_inactive_stakes_to_staker(rpc_client, &self.authorized_staker)?;
+        self.check_epoch_boundary(rpc_client, epoch)?;
+        let excess_rewards_deposited_lamports = if self.enabled(Phase::Withdrawals) {
+            withdraw_inactive_stakes_to_staker(
+                rpc_client,
+                self.transaction_executor.as_ref(),
+                &accounts,
+                &memo,
+                &self.roles.staker,
+                &self
+                    .validator_list
+                    .validators
+                    .iter()
+                    .map(|x| x.vote_account_address)
+                    .chain(inuse_vote_addresses.iter().cloned())
+                    .collect(),
+                if self.deposit_excess_rewards {
+                    Some(&self.stake_pool.reserve_stake)
+                } else {
+                    None
+                },
+            )?
+        } else {
+            0
+        };
 
         info!("Update the stake pool, merging transient stakes and orphaned accounts");
+        self.check_epoch_boundary(rpc_client, epoch)?;
         self.epoch_update(rpc_client)?;
 
+        // The update merged the transient stake accounts and moved stake between the reserve and
+        // the validators, and the withdrawals emptied stake accounts of the staker
+        info!("Prefetch the accounts of the updated stake pool");
+        let accounts = self.prefetch(rpc_client, epoch, desired_validator_stake)?;
+
+        // Without the rewards, neither the commission nor the external deposits are estimated
+        let validator_rewards = self
+            .validator_rewards(rpc_client, epoch)
+            .map_err(|err| {
+                warn!("Unable to fetch the rewards of the validators: {}", err);
+            })
+            .ok();
+        let validator_commission_lamports = validator_rewards
+            .as_ref()
+            .map(|rewards| self.validator_commission(&accounts, rewards))
+            .unwrap_or_default();
+        let reward_lamports = validator_rewards.as_ref().map(|rewards| {
+            rewards
+                .iter()
+                .map(|(vote_address, reward)| (*vote_address, reward.amount))
+                .collect::<HashMap<_, _>>()
+        });
+        let external_deposit_lamports = match (&reward_lamports, &self.previous_balances) {
+            // The stake movements of the earlier runs of the epoch would pass for external
+            // deposits, which the first run already detected
+            (Some(reward_lamports), Some((previous_epoch, previous_balances)))
+                if previous_epoch + 1 == epoch && self.latest_run.is_none() =>
+            {
+                external_deposit::detect(
+                    previous_balances,
+                    &self.previous_stake_movement_lamports,
+                    reward_lamports,
+                    &self
+                        .validator_list
+                        .validators
+                        .iter()
+                        .map(|validator| (validator.vote_account_address, validator.stake_lamports))
+                        .collect(),
+                    self.min_stake_change.lamports,
+                )
+            }
+            _ => HashMap::new(),
+        };
+        let mut external_deposit_notes = external_deposit_lamports
+            .iter()
+            .map(|(vote_address, deposit_lamports)| {
+                format!(
+                    "External deposit of {} to validator {}, besides {} of rewards",
+                    Sol(*deposit_lamports),
+                    vote_address,
+                    Sol(reward_lamports
+                        .as_ref()
+                        .and_then(|reward_lamports| reward_lamports.get(vote_address))
+                        .copied()
+                        .unwrap_or_default())
+                )
+            })
+            .collect::<Vec<_>>();
+        external_deposit_notes.sort();
+        for note in &external_deposit_notes {
+            warn!("{}", note);
+        }
+
+        // External deposits stay with their validator while it remains desired in the pool,
+        // rather than being rebalanced to the other validators
+        if self.keep_external_deposits {
+            let keeping_vote_addresses = desired_validator_stake
+                .iter()
+                .filter(|vs| {
+                    vs.stake_state != ValidatorStakeState::None
+                        && self.validator_list.contains(&vs.vote_address)
+                })
+                .map(|vs| vs.vote_address)
+                .collect::<HashSet<_>>();
+            self.kept_external_deposit_lamports
+                .retain(|vote_address, _| keeping_vote_addresses.contains(vote_address));
+            for (vote_address, deposit_lamports) in &external_deposit_lamports {
+                if keeping_vote_addresses.contains(vote_address) {
+                    *self
+                        .kept_external_deposit_lamports
+                        .entry(*vote_address)
+                        .or_default() += deposit_lamports;
+                }
+            }
+        } else {
+            self.kept_external_deposit_lamports.clear();
+        }
+
         let all_vote_addresses: HashSet<Pubkey> = self
             .validator_list
             .validators
             .iter()
             .map(|x| x.vote_account_address)
             .collect();
-        info!("Remove validators no longer present in the desired list");
-        remove_validators_from_pool(
-            rpc_client,
-            &self.authorized_staker,
-            &self.stake_pool_address,
-            &self.stake_pool,
-            &self.validator_list,
-            &all_vote_addresses - &inuse_vote_addresses,
-        )?;
+        let remove_vote_addresses = &all_vote_addresses - &inuse_vote_addresses;
+        if let Some(confirmation_gate) = &mut self.confirmation_gate {
+            confirmation_gate.check(
+                &self.stake_pool_address,
+                epoch,
+                0,
+                remove_vote_addresses.len(),
+            )?;
+        }
 
-        info!("Add new validators to pool if active");
-        add_validators_to_pool(
-            rpc_client,
-            &self.authorized_staker,
-            desired_validator_stake,
-            &self.stake_pool_address,
-            &self.stake_pool,
-            &self.validator_list,
-        )?;
-        self.update(rpc_client)?;
+        // Removals, additions and the creation of validator stake accounts are submitted as a
+        // single graph of operations, each waiting only on the operations it depends on
+        let mut graph = OperationGraph::default();
+        let (removals, attempted_additions) = if self.operation_order.removals_first {
+            let removals =
+                self.remove_validators(&accounts, &memo, remove_vote_addresses, &mut graph, &[])?;
+            let (attempted_additions, _additions) = self.add_validators(
+                &accounts,
+                &memo,
+                desired_validator_stake,
+                &mut graph,
+                &removals,
+            )?;
+            (removals, attempted_additions)
+        } else {
+            let (attempted_additions, additions) =
+                self.add_validators(&accounts, &memo, desired_validator_stake, &mut graph, &[])?;
+            let removals = self.remove_validators(
+                &accounts,
+                &memo,
+                remove_vote_addresses,
+                &mut graph,
+                &additions,
+            )?;
+            (removals, attempted_additions)
+        };
 
         let mut busy_validators = HashSet::new();
+        info!("Create validator stake accounts if needed");
+        let (mut funding_summary, creations, created_vote_addresses) =
+            if self.enabled(Phase::AccountCreation) {
+                let (funding_summary, creations, created_vote_addresses) =
+                    create_validator_stake_accounts(
+                        &accounts,
+                        &memo,
+                        &self.program_id,
+                        self.roles
+                            .authority_for(Operation::CreateValidatorStakeAccount)?,
+                        self.roles.keypair(Role::Funder).unwrap(),
+                        desired_validator_stake,
+                        &self.stake_pool_address,
+                        &mut busy_validators,
+                        &mut graph,
+                    )?;
+                (Some(funding_summary), creations, created_vote_addresses)
+            } else {
+                (None, vec![], HashSet::new())
+            };
+
+        self.check_epoch_boundary(rpc_client, epoch)?;
+        let statuses = graph.execute(rpc_client, self.transaction_executor.as_ref())?;
+        let completed = |operations: &[OperationId]| {
+            operations.iter().all(|id| {
+                !matches!(
+                    statuses[*id],
+                    OperationStatus::Failed | OperationStatus::Skipped
+                )
+            })
+        };
+        // Failed additions are identified below once the validator list is updated
+        if !completed(&removals) {
+            return Err("Failed to remove validators from the stake pool".into());
+        }
+        if !completed(&creations) {
+            return Err("Failed to create validator stake accounts".into());
+        }
+        let funder = self.roles.keypair(Role::Funder).unwrap().pubkey();
+        if let Some(funding_summary) = &mut funding_summary {
+            funding_summary.funder_balance_lamports = rpc_client.get_balance(&funder)?;
+        }
+        self.update(rpc_client)?;
+
+        // Excess rewards are only split off when the addition succeeds
+        let excess_rewards_reclaimed_lamports = attempted_additions
+            .iter()
+            .filter(|(vote_address, _)| self.validator_list.contains(vote_address))
+            .map(|(_, split_lamports)| split_lamports)
+            .sum::<u64>();
+
+        // Validators desiring stake that they cannot receive this epoch
+        let missing_from_pool = desired_validator_stake
+            .iter()
+            .filter(|vs| {
+                vs.stake_state != ValidatorStakeState::None
+                    && !self.validator_list.contains(&vs.vote_address)
+            })
+            .map(|vs| -> Result<_, Box<dyn error::Error>> {
+                let stake_address = find_stake_program_address(
+                    &self.program_id,
+                    &vs.vote_address,
+                    &self.stake_pool_address,
+                )
+                .0;
+                let status = if attempted_additions.contains_key(&vs.vote_address) {
+                    PoolAdditionStatus::Failed
+                } else if accounts.account(&stake_address)?.is_none()
+                    && !created_vote_addresses.contains(&vs.vote_address)
+                {
+                    PoolAdditionStatus::NoStakeAccount
+                } else {
+                    PoolAdditionStatus::Pending
+                };
+                Ok((vs.vote_address, status))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
         info!("Add unmerged transient stake accounts to the busy set");
         add_unmerged_transient_stake_accounts(
-            rpc_client,
-            desired_validator_stake,
-            &self.stake_pool_address,
-            &mut busy_validators,
-        )?;
-
-        info!("Create validator stake accounts if needed");
-        create_validator_stake_accounts(
-            rpc_client,
-            &self.authorized_staker,
+            &accounts,
+            &self.program_id,
             desired_validator_stake,
             &self.stake_pool_address,
             &mut busy_validators,
         )?;
 
         let total_stake_amount = self.stake_pool.total_stake_lamports;
-        info!(
-            "Total stake pool balance minus required reserves: {}",
-            Sol(total_stake_amount)
+        let liquidity_buffer = self.liquidity_buffer_stage(
+            epoch,
+            reward_lamports
+                .as_ref()
+                .map(|reward_lamports| reward_lamports.values().sum())
+                .unwrap_or_default(),
         );
+        let allocation_params = self.allocation_params(liquidity_buffer.as_ref());
+        let allocation = planner::allocate(&allocation_params, desired_validator_stake)?;
 
-        let total_baseline_stake_amount = baseline_stake_node_count * self.baseline_stake_amount;
-        info!("Baseline node count: {}", baseline_stake_node_count);
-        info!("Baseline stake amount: {}", Sol(self.baseline_stake_amount));
-        info!(
-            "Total baseline stake amount: {}",
-            Sol(total_baseline_stake_amount)
-        );
+        let mut notes = allocation.notes(&allocation_params);
+        if let Some(liquidity_buffer) = liquidity_buffer {
+            if liquidity_buffer.withdrawal_lamports > 0 {
+                notes.push(format!(
+                    "Withdrawals from the pool since the previous epoch: {}, net of the deposits",
+                    Sol(liquidity_buffer.withdrawal_lamports)
+                ));
+            }
+        }
+
+        let target_lamports = desired_validator_stake
+            .iter()
+            .map(|vs| {
+                (
+                    vs.vote_address,
+                    allocation.desired_lamports(vs)
+                        + self
+                            .kept_external_deposit_lamports
+                            .get(&vs.vote_address)
+                            .copied()
+                            .unwrap_or_default(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        // An incremental rerun leaves the validators whose desired stake did not change since the
+        // earlier runs of the epoch where they are
+        let settled_validators = match &self.latest_run {
+            Some(latest_run) => {
+                let settled_validators = desired_validator_stake
+                    .iter()
+                    .filter(|vs| {
+                        latest_run.target_lamports.get(&vs.vote_address)
+                            == target_lamports.get(&vs.vote_address)
+                    })
+                    .map(|vs| vs.identity)
+                    .collect::<HashSet<_>>();
+                notes.insert(
+                    0,
+                    format!(
+                        "Incremental run: {} of {} validators left as they are, their desired \
+                         stake unchanged",
+                        settled_validators.len(),
+                        desired_validator_stake.len()
+                    ),
+                );
+                settled_validators
+            }
+            None => HashSet::new(),
+        };
+        notes.extend(balance_notes);
+        notes.extend(soft_removal_notes);
+        notes.extend(external_deposit_notes);
+        if !self.kept_external_deposit_lamports.is_empty() {
+            notes.push(format!(
+                "External deposits kept by {} validators: {} in total",
+                self.kept_external_deposit_lamports.len(),
+                Sol(self.kept_external_deposit_lamports.values().sum::<u64>())
+            ));
+        }
+        if let Some(small_pool) = &self.small_pool {
+            let warnings = small_pool.warnings(
+                total_stake_amount,
+                // The validator stake accounts and the reserve
+                self.validator_list.validators.len() + 1,
+                accounts.stake_rent_exemption(),
+            );
+            for warning in &warnings {
+                warn!("{}", warning);
+            }
+            notes.extend(warnings);
+        }
+        if !validator_commission_lamports.is_empty() {
+            notes.push(format!(
+                "Commission paid to {} validators in epoch {}: {} (estimated)",
+                validator_commission_lamports.len(),
+                epoch - 1,
+                Sol(validator_commission_lamports.values().sum::<u64>())
+            ));
+        }
+        if !self.disabled_phases.is_empty() {
+            let mut disabled_phases = self
+                .disabled_phases
+                .iter()
+                .map(|phase| phase.to_string())
+                .collect::<Vec<_>>();
+            disabled_phases.sort();
+            notes.push(format!(
+                "Stake pool phases disabled: {}",
+                disabled_phases.join(", ")
+            ));
+        }
+        if excess_rewards_deposited_lamports > 0 {
+            notes.push(format!(
+                "Excess rewards deposited into the reserve: {}",
+                Sol(excess_rewards_deposited_lamports)
+            ));
+        }
+        if let Some(funding_summary) = &funding_summary {
+            if funding_summary.underfunded_stake_accounts > 0 {
+                notes.push(format!(
+                    "Funding account {} has insufficient funds to create {} validator stake \
+                     accounts",
+                    funder, funding_summary.underfunded_stake_accounts
+                ));
+            }
+            if let Some(funder_low_balance) = self.funder_low_balance {
+                if funding_summary.funder_balance_lamports < funder_low_balance {
+                    notes.push(format!(
+                        "Funding account {} balance is low: {}",
+                        funder,
+                        Sol(funding_summary.funder_balance_lamports)
+                    ));
+                }
+            }
+        }
+        self.check_epoch_boundary(rpc_client, epoch)?;
+        let rebalance = self.enabled(Phase::Rebalancing);
+        let mut summary = distribute_validator_stake(
+            rpc_client,
+            &accounts,
+            &memo,
+            self.transaction_executor.as_ref(),
+            &self.program_id,
+            self.roles.authority_for(Operation::AdjustValidatorStake)?,
+            &self.stake_pool_address,
+            &self.stake_pool,
+            &self.validator_list,
+            desired_validator_stake
+                .iter()
+                .filter(|vs| {
+                    !busy_validators.contains(&vs.identity)
+                        && !settled_validators.contains(&vs.identity)
+                })
+                .map(|vs| allocation.funded_validator_stake(vs)),
+            allocation.baseline_stake_lamports,
+            &allocation.bonus_stake_amounts,
+            &self.kept_external_deposit_lamports,
+            self.min_stake_change,
+            self.stake_tolerance,
+            self.max_epoch_stake_change_percentage,
+            &self.previous_stake_movement_lamports,
+            &self.busy_carryover_lamports,
+            self.operation_order,
+            self.confirmation_gate.as_mut(),
+            rebalance,
+        )?;
+        if let Some(rebalance_plan) = &summary.rebalance_plan {
+            notes.push(format!(
+                "Stake movements planned over {} epochs, at most {}% of the pool per epoch",
+                rebalance_plan.epochs.len(),
+                self.max_epoch_stake_change_percentage.unwrap_or_default()
+            ));
+            for epoch_plan in &rebalance_plan.epochs {
+                notes.push(format!(
+                    "Epoch {}: {} stake movements, adding {} and removing {}",
+                    epoch_plan.epoch,
+                    epoch_plan.moves.len(),
+                    Sol(epoch_plan.stake_added_lamports()),
+                    Sol(epoch_plan.stake_removed_lamports())
+                ));
+            }
+            if !rebalance_plan.unreachable_lamports.is_empty() {
+                notes.push(format!(
+                    "Stake movements of {} validators cannot be planned, {} in total",
+                    rebalance_plan.unreachable_lamports.len(),
+                    Sol(rebalance_plan.unreachable_lamports.values().sum::<u64>())
+                ));
+            }
+        }
+        if !summary.reserve_shortfall_lamports.is_empty() {
+            notes.push(format!(
+                "Reserve stake is insufficient for {} validators, {} short in total",
+                summary.reserve_shortfall_lamports.len(),
+                Sol(summary.reserve_shortfall_lamports.values().sum::<u64>())
+            ));
+        }
+        let mut missing_from_pool_notes = desired_validator_stake
+            .iter()
+            .filter_map(|vs| {
+                missing_from_pool.get(&vs.vote_address).map(|status| {
+                    format!(
+                        "{} is not in the stake pool and receives no stake: {}",
+                        vs.identity, status
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        missing_from_pool_notes.sort();
+        notes.extend(missing_from_pool_notes);
+        summary.missing_from_pool = missing_from_pool;
+        summary.bonus_ramp = allocation.bonus_ramp;
+        summary.liquidity_buffer = liquidity_buffer;
+        summary.soft_removed_since = soft_removed_since;
+        summary.validator_commission_lamports = validator_commission_lamports;
+        summary.epoch = epoch;
+        summary.external_deposit_lamports = external_deposit_lamports;
+        summary.kept_external_deposit_lamports = self.kept_external_deposit_lamports.clone();
+        summary.target_lamports = target_lamports;
+        summary.funding = funding_summary.unwrap_or_default();
+        summary.excess_rewards_reclaimed_lamports = excess_rewards_reclaimed_lamports;
+        summary.excess_rewards_deposited_lamports = excess_rewards_deposited_lamports;
+
+        if let Some(treasury_sweep) = &self.treasury_sweep {
+            info!("Sweep the staker balance above its float to the treasury");
+            self.check_epoch_boundary(rpc_client, epoch)?;
+            summary.treasury_sweep_lamports = sweep_to_treasury(
+                rpc_client,
+                self.transaction_executor.as_ref(),
+                &memo,
+                &self.roles.staker,
+                treasury_sweep,
+            )?;
+            if summary.treasury_sweep_lamports > 0 {
+                notes.push(format!(
+                    "Swept {} from the staker {} to the treasury {}, keeping a float of {}",
+                    Sol(summary.treasury_sweep_lamports),
+                    self.roles.staker.pubkey(),
+                    treasury_sweep.treasury,
+                    Sol(treasury_sweep.float_lamports)
+                ));
+            }
+        }
 
-        if total_stake_amount < total_baseline_stake_amount {
-            return Err("Not enough stake to cover the baseline".into());
+        // Busy validators receive no stake movement this epoch, leaving them off their desired
+        // stake until the next one
+        summary.busy_carryover_lamports = desired_validator_stake
+            .iter()
+            .filter(|vs| busy_validators.contains(&vs.identity))
+            .filter_map(|vs| {
+                let balance = self.validator_list.find(&vs.vote_address)?.stake_lamports;
+                let desired_balance = allocation.desired_lamports(vs);
+                let carryover_lamports = desired_balance as i64 - balance as i64;
+                if carryover_lamports != 0 {
+                    Some((vs.vote_address, carryover_lamports))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if !summary.busy_carryover_lamports.is_empty() {
+            notes.push(format!(
+                "{} busy validators carry over their stake movements to the next epoch",
+                summary.busy_carryover_lamports.len()
+            ));
         }
+        let ok = summary.failed_transactions == 0;
+        self.summary = Some(summary);
+        Ok((notes, ok))
+    }
+
+    fn summary(&self) -> Option<StakePoolSummary> {
+        self.summary.clone()
+    }
+
+    fn set_previous_summary(&mut self, previous_summary: Option<StakePoolSummary>) {
+        let previous_summary = previous_summary.unwrap_or_default();
+        // A stake movement that failed would pass for an external deposit or withdrawal
+        self.previous_balances =
+            if previous_summary.epoch > 0 && previous_summary.failed_transactions == 0 {
+                Some((
+                    previous_summary.epoch,
+                    previous_summary.validator_balance_lamports,
+                ))
+            } else {
+                None
+            };
+        self.kept_external_deposit_lamports = previous_summary.kept_external_deposit_lamports;
+        self.previous_stake_movement_lamports = previous_summary.stake_movement_lamports;
+        self.previous_bonus_ramp = previous_summary.bonus_ramp;
+        self.previous_liquidity_buffer = previous_summary
+            .liquidity_buffer
+            .map(|liquidity_buffer| (previous_summary.epoch, liquidity_buffer));
+        self.soft_removed_since = previous_summary.soft_removed_since;
+    }
+
+    fn set_busy_carryover(&mut self, busy_carryover_lamports: HashMap<Pubkey, i64>) {
+        self.busy_carryover_lamports = busy_carryover_lamports;
+    }
+
+    fn set_incremental(&mut self, latest_run: StakePoolSummary) {
+        // The external deposits of the epoch are already kept
+        self.kept_external_deposit_lamports = latest_run.kept_external_deposit_lamports.clone();
+        self.latest_run = Some(latest_run);
+    }
+
+    fn set_run_id(&mut self, run_id: &str) {
+        self.run_id = Some(run_id.to_string());
+    }
+
+    fn set_vote_account_cache(&mut self, vote_account_cache: Arc<VoteAccountCache>) {
+        self.vote_account_cache = Some(vote_account_cache);
+    }
+
+    fn effective_config(&self) -> Vec<(String, String)> {
+        let mut disabled_phases = self
+            .disabled_phases
+            .iter()
+            .map(|phase| phase.to_string())
+            .collect::<Vec<_>>();
+        disabled_phases.sort();
+
+        vec![
+            ("stake_pool_address", self.stake_pool_address.to_string()),
+            (
+                "baseline_stake_amount",
+                self.baseline_stake_amount.to_string(),
+            ),
+            ("min_stake_change", format!("{:?}", self.min_stake_change)),
+            ("stake_tolerance", format!("{:?}", self.stake_tolerance)),
+            (
+                "deposit_excess_rewards",
+                self.deposit_excess_rewards.to_string(),
+            ),
+            (
+                "keep_external_deposits",
+                self.keep_external_deposits.to_string(),
+            ),
+            (
+                "disabled_phases",
+                format!("[{}]", disabled_phases.join(", ")),
+            ),
+            (
+                "max_asn_stake_percentage",
+                format!("{:?}", self.max_asn_stake_percentage),
+            ),
+            (
+                "max_epoch_stake_change_percentage",
+                format!("{:?}", self.max_epoch_stake_change_percentage),
+            ),
+            ("bonus_ramp", format!("{:?}", self.bonus_ramp)),
+            ("baseline_shortfall", self.baseline_shortfall.to_string()),
+            ("liquidity_buffer", format!("{:?}", self.liquidity_buffer)),
+            ("operation_order", format!("{:?}", self.operation_order)),
+            ("small_pool", format!("{:?}", self.small_pool)),
+            ("treasury_sweep", format!("{:?}", self.treasury_sweep)),
+            (
+                "soft_removal_epochs",
+                format!("{:?}", self.soft_removal_epochs),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+
+    fn allocation(
+        &mut self,
+        rpc_client: &RpcClient,
+        desired_validator_stake: &[ValidatorStake],
+    ) -> Result<HashMap<Pubkey, StakeAllocation>, Box<dyn error::Error>> {
+        self.update(rpc_client)?;
+        let epoch = rpc_client.get_epoch_info()?.epoch;
+        let (desired_validator_stake, _soft_removed_since, _soft_removal_notes) = self
+            .soft_remove_validators(
+                &rpc_client.get_vote_accounts()?,
+                epoch,
+                desired_validator_stake,
+            )?;
+        // Without the rewards, the withdrawals are underestimated by as much
+        let reward_lamports = self
+            .validator_rewards(rpc_client, epoch)
+            .map(|rewards| rewards.values().map(|reward| reward.amount).sum())
+            .unwrap_or_default();
+        let liquidity_buffer = self.liquidity_buffer_stage(epoch, reward_lamports);
+        let allocation = planner::allocate(
+            &self.allocation_params(liquidity_buffer.as_ref()),
+            &desired_validator_stake,
+        )?;
+
+        Ok(stake_allocation(
+            &self.validator_list,
+            &desired_validator_stake
+                .iter()
+                .map(|vs| allocation.funded_validator_stake(vs))
+                .collect::<Vec<_>>(),
+            allocation.baseline_stake_lamports,
+            &allocation.bonus_stake_amounts,
+        ))
+    }
+
+    fn pool_state(
+        &self,
+        rpc_client: &RpcClient,
+    ) -> Result<Option<PoolState>, Box<dyn error::Error>> {
+        Ok(Some(pool_state::fetch(
+            rpc_client,
+            &self.stake_pool_address,
+        )?))
+    }
+}
+
+/// Use the manager to set `new_staker` as the staker of the stake pool, then confirm that
+/// `new_staker` is able to authorize stake pool updates.
+///
+/// The stake pool's program addresses do not depend on the staker and are unaffected, but stake
+/// accounts authorized by the previous staker, such as the staker transient stake accounts, stay
+/// with the previous staker. In a dry run the affected accounts are only displayed
+pub fn rotate_staker(
+    rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    dry_run: bool,
+    program_id: &Pubkey,
+    manager: &Keypair,
+    stake_pool_address: &Pubkey,
+    new_staker: &Keypair,
+) -> Result<(), Box<dyn error::Error>> {
+    let account_data = rpc_client.get_account_data(stake_pool_address)?;
+    let stake_pool = StakePool::try_from_slice(account_data.as_slice())
+        .map_err(|err| format!("Invalid stake pool {}: {}", stake_pool_address, err))?;
+    let account_data = rpc_client.get_account_data(&stake_pool.validator_list)?;
+    let validator_list = try_from_slice_unchecked::<ValidatorList>(&account_data.as_slice())
+        .map_err(|err| {
+            format!(
+                "Invalid validator list {}: {}",
+                stake_pool.validator_list, err
+            )
+        })?;
+
+    // Only the manager needs to match the stake pool, the staker is the one being replaced
+    if stake_pool.manager != manager.pubkey() {
+        return Err(format!(
+            "{} is not the manager of stake pool {}, the manager is {}",
+            manager.pubkey(),
+            stake_pool_address,
+            stake_pool.manager
+        )
+        .into());
+    }
+    if stake_pool.staker == new_staker.pubkey() {
+        return Err(format!("{} is already the staker", new_staker.pubkey()).into());
+    }
 
-        info!("Bonus node count: {}", bonus_stake_node_count);
-        let total_bonus_stake_amount =
-            total_stake_amount.saturating_sub(total_baseline_stake_amount);
+    info!("Stake pool: {}", stake_pool_address);
+    info!("Manager: {}", stake_pool.manager);
+    info!("Current staker: {}", stake_pool.staker);
+    info!("New staker: {}", new_staker.pubkey());
+    info!(
+        "Withdraw authority: {}",
+        find_withdraw_authority_program_address(program_id, stake_pool_address).0
+    );
+    for validator in &validator_list.validators {
         info!(
-            "Total bonus stake amount: {}",
-            Sol(total_bonus_stake_amount)
+            "Validator {}: stake account {}, transient stake account {}",
+            validator.vote_account_address,
+            find_stake_program_address(
+                program_id,
+                &validator.vote_account_address,
+                stake_pool_address
+            )
+            .0,
+            find_transient_stake_program_address(
+                program_id,
+                &validator.vote_account_address,
+                stake_pool_address
+            )
+            .0,
+        );
+    }
+    let (staker_stake_addresses, staker_stake_balance) =
+        get_all_stake(rpc_client, stake_pool.staker)?;
+    if !staker_stake_addresses.is_empty() {
+        warn!(
+            "{} stake accounts holding {} remain authorized by the current staker {}",
+            staker_stake_addresses.len(),
+            Sol(staker_stake_balance),
+            stake_pool.staker
         );
+        for stake_address in &staker_stake_addresses {
+            info!("Current staker stake account: {}", stake_address);
+        }
+    }
 
-        let bonus_stake_amount = if bonus_stake_node_count == 0 {
-            0
-        } else {
-            total_bonus_stake_amount / (bonus_stake_node_count as u64)
-        };
+    if dry_run {
+        info!("Dry run, the staker was not changed");
+        return Ok(());
+    }
 
-        info!("Bonus stake amount: {}", Sol(bonus_stake_amount));
+    let transaction = Transaction::new_with_payer(
+        &[spl_stake_pool::instruction::set_staker(
+            program_id,
+            stake_pool_address,
+            &manager.pubkey(),
+            &new_staker.pubkey(),
+        )],
+        Some(&manager.pubkey()),
+    );
+    if !executor
+        .execute(rpc_client, vec![transaction], &[manager])?
+        .failed
+        .is_empty()
+    {
+        return Err("Failed to set the new staker".into());
+    }
+    info!("Staker set to {}", new_staker.pubkey());
 
-        let notes = vec![
-            format!("Baseline stake amount: {}", Sol(self.baseline_stake_amount)),
-            format!("Bonus stake amount: {}", Sol(bonus_stake_amount)),
-        ];
-        Ok((
-            notes,
-            distribute_validator_stake(
-                rpc_client,
-                &self.authorized_staker,
-                &self.stake_pool_address,
-                &self.stake_pool,
-                &self.validator_list,
-                desired_validator_stake
-                    .iter()
-                    .filter(|vs| !busy_validators.contains(&vs.identity))
-                    .cloned(),
-                self.baseline_stake_amount,
-                bonus_stake_amount,
-            )?,
-        ))
+    // Post-check: a no-op update of the staker to itself is only accepted if signed by the
+    // staker (or the manager)
+    let transaction = Transaction::new_with_payer(
+        &[spl_stake_pool::instruction::set_staker(
+            program_id,
+            stake_pool_address,
+            &new_staker.pubkey(),
+            &new_staker.pubkey(),
+        )],
+        Some(&new_staker.pubkey()),
+    );
+    if !executor
+        .execute(rpc_client, vec![transaction], &[new_staker])?
+        .failed
+        .is_empty()
+    {
+        return Err(format!(
+            "The staker is now {}, but it failed to sign a stake pool update",
+            new_staker.pubkey()
+        )
+        .into());
+    }
+    info!("New staker {} verified", new_staker.pubkey());
+    Ok(())
+}
+
+/// Roll back the stake `movements` that the run of `run_epoch` issued to the stake pool, as far as
+/// the warmup and cooldown of the stake allow, see `rollback::plan`. Returns the notes of the
+/// rollback
+#[allow(clippy::too_many_arguments)]
+pub fn rollback(
+    rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    dry_run: bool,
+    program_id: &Pubkey,
+    staker: &Keypair,
+    stake_pool_address: &Pubkey,
+    movements: &HashMap<Pubkey, i64>,
+    run_epoch: Epoch,
+    run_id: &str,
+) -> Result<Vec<String>, Box<dyn error::Error>> {
+    let load = || -> Result<(StakePool, ValidatorList), Box<dyn error::Error>> {
+        let account_data = rpc_client.get_account_data(stake_pool_address)?;
+        let stake_pool = StakePool::try_from_slice(account_data.as_slice())
+            .map_err(|err| format!("Invalid stake pool {}: {}", stake_pool_address, err))?;
+        let account_data = rpc_client.get_account_data(&stake_pool.validator_list)?;
+        let validator_list = try_from_slice_unchecked::<ValidatorList>(&account_data.as_slice())
+            .map_err(|err| {
+                format!(
+                    "Invalid validator list {}: {}",
+                    stake_pool.validator_list, err
+                )
+            })?;
+        Ok((stake_pool, validator_list))
+    };
+    let (mut stake_pool, mut validator_list) = load()?;
+    if stake_pool.staker != staker.pubkey() {
+        return Err(format!(
+            "{} is not the staker of stake pool {}, the staker is {}",
+            staker.pubkey(),
+            stake_pool_address,
+            stake_pool.staker
+        )
+        .into());
+    }
+
+    let epoch = rpc_client.get_epoch_info()?.epoch;
+    let memo = TransactionMemo::new(run_id, epoch);
+    if stake_pool.last_update_epoch < epoch && epoch > run_epoch {
+        if dry_run {
+            return Err(format!(
+                "Stake pool {} is not updated for epoch {}, the balances to roll back from are \
+                 unknown",
+                stake_pool_address, epoch
+            )
+            .into());
+        }
+        info!("Updating the stake pool for epoch {}", epoch);
+        update_stake_pool(
+            rpc_client,
+            executor,
+            &memo,
+            program_id,
+            staker,
+            stake_pool_address,
+            &stake_pool,
+            &validator_list,
+        )?;
+        let (updated_stake_pool, updated_validator_list) = load()?;
+        stake_pool = updated_stake_pool;
+        validator_list = updated_validator_list;
+    }
+
+    let mut busy = HashSet::new();
+    for vote_address in movements.keys() {
+        let transient_stake_address =
+            find_transient_stake_program_address(program_id, vote_address, stake_pool_address).0;
+        if rpc_client
+            .get_account_with_commitment(&transient_stake_address, rpc_client.commitment())?
+            .value
+            .is_some()
+        {
+            busy.insert(*vote_address);
+        }
+    }
+    let balances = validator_list
+        .validators
+        .iter()
+        .filter(|validator| validator.status == StakeStatus::Active)
+        .map(|validator| (validator.vote_account_address, validator.stake_lamports))
+        .collect::<HashMap<_, _>>();
+
+    // Each stake movement funds a rent-exempt transient stake account, and the validator stake
+    // accounts keep their minimum
+    let stake_rent_exemption = get_minimum_stake_balance_for_rent_exemption(rpc_client)?;
+    let reserve_stake_balance = get_available_stake_balance(
+        rpc_client,
+        stake_pool.reserve_stake,
+        MIN_STAKE_RESERVE_BALANCE,
+    )?
+    .saturating_sub(stake_rent_exemption);
+    let plan = rollback::plan(
+        movements,
+        run_epoch,
+        epoch,
+        &balances,
+        &busy,
+        MIN_STAKE_ACCOUNT_BALANCE + stake_rent_exemption,
+        MIN_STAKE_ACCOUNT_BALANCE + stake_rent_exemption,
+        reserve_stake_balance,
+    );
+
+    let mut notes = plan.notes;
+    for stake_op in &plan.stake_ops {
+        notes.push(match stake_op {
+            StakeOp::Increase {
+                vote_address,
+                lamports,
+            } => format!("Adding {} back to {}", Sol(*lamports), vote_address),
+            StakeOp::Decrease {
+                vote_address,
+                lamports,
+            } => format!("Removing {} again from {}", Sol(*lamports), vote_address),
+        });
+    }
+    if dry_run || plan.stake_ops.is_empty() {
+        return Ok(notes);
+    }
+
+    let transactions = stake_op_transactions(
+        &memo,
+        "rollback",
+        program_id,
+        &stake_pool,
+        stake_pool_address,
+        &staker.pubkey(),
+        &plan.stake_ops,
+    );
+    let failed = executor
+        .execute(rpc_client, transactions, &[staker])?
+        .failed
+        .len();
+    if failed > 0 {
+        return Err(format!("{} rollback transactions failed", failed).into());
     }
+    Ok(notes)
 }
 
 // Get the balance of a stake account excluding the reserve
@@ -299,6 +1831,15 @@ fn get_available_stake_balance(
             stake_address, err
         )
     })?;
+    available_stake_balance(stake_address, balance, reserve_stake_balance)
+}
+
+// The `balance` of a stake account excluding the reserve
+fn available_stake_balance(
+    stake_address: Pubkey,
+    balance: u64,
+    reserve_stake_balance: u64,
+) -> Result<u64, Box<dyn error::Error>> {
     if balance < reserve_stake_balance {
         Err(format!(
             "Stake account {} balance too low, {}. Minimum is {}",
@@ -315,7 +1856,8 @@ fn get_available_stake_balance(
 /// Iterates through all possible transient stake accounts on the stake pool,
 /// and if any is present, mark the validator as busy.
 fn add_unmerged_transient_stake_accounts(
-    rpc_client: &RpcClient,
+    accounts: &AccountStore,
+    program_id: &Pubkey,
     desired_validator_stake: &[ValidatorStake],
     stake_pool_address: &Pubkey,
     busy_validators: &mut HashSet<Pubkey>,
@@ -326,18 +1868,10 @@ fn add_unmerged_transient_stake_accounts(
         ..
     } in desired_validator_stake
     {
-        let transient_stake_address = find_transient_stake_program_address(
-            &spl_stake_pool::id(),
-            vote_address,
-            stake_pool_address,
-        )
-        .0;
-
-        let transient_stake_account = rpc_client
-            .get_account_with_commitment(&transient_stake_address, rpc_client.commitment())?
-            .value;
+        let transient_stake_address =
+            find_transient_stake_program_address(program_id, vote_address, stake_pool_address).0;
 
-        if transient_stake_account.is_some() {
+        if accounts.account(&transient_stake_address)?.is_some() {
             busy_validators.insert(*identity);
         }
     }
@@ -354,68 +1888,100 @@ fn add_unmerged_transient_stake_accounts(
 ///
 /// Every epoch, this function checks for any of these inactive stake accounts,
 /// and withdraws the entirety back to the staker.
+///
+/// If `reserve_stake_address` is provided, the transient stake accounts of
+/// `vote_addresses` are instead withdrawn into the stake pool reserve, returning the
+/// excess rewards to the pool. Returns the amount deposited into the reserve
 fn withdraw_inactive_stakes_to_staker(
     rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    accounts: &AccountStore,
+    memo: &TransactionMemo,
     authorized_staker: &Keypair,
-) -> Result<(), Box<dyn error::Error>> {
-    let mut transactions = vec![];
-    let (all_stake_addresses, _all_stake_total_amount) =
-        get_all_stake(rpc_client, authorized_staker.pubkey())?;
-
-    for stake_address in all_stake_addresses {
-        let stake_account = rpc_client
-            .get_account_with_commitment(&stake_address, rpc_client.commitment())?
-            .value;
+    vote_addresses: &HashSet<Pubkey>,
+    reserve_stake_address: Option<&Pubkey>,
+) -> Result<u64, Box<dyn error::Error>> {
+    let staker_transient_stake_addresses: HashMap<Pubkey, Pubkey> = vote_addresses
+        .iter()
+        .map(|vote_address| {
+            (
+                staker_transient_stake_address(authorized_staker.pubkey(), *vote_address),
+                *vote_address,
+            )
+        })
+        .collect();
+    let mut deposited_lamports = 0;
 
-        if let Some(stake_account) = stake_account {
-            // Check if the stake account is busy
-            let stake_activation = rpc_client
-                .get_stake_activation(stake_address, None)
-                .map_err(|err| {
-                    format!(
-                        "Unable to get activation information for stake account: {}: {}",
-                        stake_address, err
-                    )
-                })?;
+    let mut transactions = vec![];
+    for (stake_address, stake_account, stake_activation) in accounts.staker_stake_accounts() {
+        if let Err(err) = stake_account_check::check(
+            stake_address,
+            stake_account,
+            &ExpectedStakeAccount::authorized(authorized_staker.pubkey()),
+        ) {
+            warn!("{}, not withdrawing", err);
+            continue;
+        }
 
-            if stake_activation.state == StakeActivationState::Inactive {
-                let stake_lamports = stake_account.lamports;
-                transactions.push(Transaction::new_with_payer(
-                    &[stake_instruction::withdraw(
-                        &stake_address,
-                        &authorized_staker.pubkey(),
-                        &authorized_staker.pubkey(),
-                        stake_lamports,
-                        None,
-                    )],
-                    Some(&authorized_staker.pubkey()),
-                ));
-            } else {
-                debug!("Staker's stake at {} not inactive, skipping", stake_address);
-            }
+        // Check if the stake account is busy
+        if stake_activation? == StakeActivationState::Inactive {
+            let stake_lamports = stake_account.lamports;
+            let destination = match reserve_stake_address {
+                Some(reserve_stake_address)
+                    if staker_transient_stake_addresses.contains_key(stake_address) =>
+                {
+                    info!(
+                        "Depositing {} from {} into the reserve",
+                        Sol(stake_lamports),
+                        stake_address
+                    );
+                    deposited_lamports += stake_lamports;
+                    *reserve_stake_address
+                }
+                _ => authorized_staker.pubkey(),
+            };
+            transactions.push(memo.transaction(
+                &[stake_instruction::withdraw(
+                    stake_address,
+                    &authorized_staker.pubkey(),
+                    &destination,
+                    stake_lamports,
+                    None,
+                )],
+                &authorized_staker.pubkey(),
+                &Phase::Withdrawals.to_string(),
+                staker_transient_stake_addresses.get(stake_address),
+            ));
+        } else {
+            debug!("Staker's stake at {} not inactive, skipping", stake_address);
         }
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
+    if !executor
+        .execute(rpc_client, transactions, &[authorized_staker])?
         .failed
         .is_empty()
     {
-        Err("Failed to add validators to the stake pool".into())
+        Err("Failed to withdraw inactive stake accounts".into())
     } else {
-        Ok(())
+        Ok(deposited_lamports)
     }
 }
 
 /// Create and send all transactions to update the stake pool balances, required
 /// once per epoch to perform any operations on the stake pool.
-fn update_stake_pool(
+pub fn update_stake_pool(
     rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    memo: &TransactionMemo,
+    program_id: &Pubkey,
     payer: &Keypair,
     stake_pool_address: &Pubkey,
     stake_pool: &StakePool,
     validator_list: &ValidatorList,
 ) -> Result<(), Box<dyn error::Error>> {
-    let instructions = spl_stake_pool::instruction::update_stake_pool(
+    let instructions = stake_pool_program::update_stake_pool(
+        program_id,
         stake_pool,
         validator_list,
         stake_pool_address,
@@ -424,18 +1990,20 @@ fn update_stake_pool(
 
     let mut transactions: Vec<Transaction> = instructions
         .into_iter()
-        .map(|i| Transaction::new_with_payer(&[i], Some(&payer.pubkey())))
+        .map(|i| memo.transaction(&[i], &payer.pubkey(), "update", None))
         .collect();
     let update_balance_transaction = transactions.split_off(transactions.len() - 1);
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, payer)?
+    if !executor
+        .execute(rpc_client, transactions, &[payer])?
         .failed
         .is_empty()
     {
         return Err("Failed to update stake pool".into());
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, update_balance_transaction, payer)?
+    if !executor
+        .execute(rpc_client, update_balance_transaction, &[payer])?
         .failed
         .is_empty()
     {
@@ -445,60 +2013,133 @@ fn update_stake_pool(
     }
 }
 
+/// Transfer the balance of `staker` above the float of `treasury_sweep` to the treasury, net of
+/// the transfer fee. Returns the amount transferred
+fn sweep_to_treasury(
+    rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    memo: &TransactionMemo,
+    staker: &Keypair,
+    treasury_sweep: &TreasurySweep,
+) -> Result<u64, Box<dyn error::Error>> {
+    let balance = rpc_client.get_balance(&staker.pubkey())?;
+    let (_blockhash, fee_calculator) = rpc_client.get_recent_blockhash()?;
+    let lamports = balance
+        .saturating_sub(treasury_sweep.float_lamports)
+        .saturating_sub(fee_calculator.lamports_per_signature);
+    if lamports == 0 {
+        info!(
+            "Staker balance of {} is within its float of {}",
+            Sol(balance),
+            Sol(treasury_sweep.float_lamports)
+        );
+        return Ok(0);
+    }
+
+    info!(
+        "Transferring {} from the staker to the treasury {}",
+        Sol(lamports),
+        treasury_sweep.treasury
+    );
+    let transaction = memo.transaction(
+        &[system_instruction::transfer(
+            &staker.pubkey(),
+            &treasury_sweep.treasury,
+            lamports,
+        )],
+        &staker.pubkey(),
+        "treasury-sweep",
+        None,
+    );
+    if !executor
+        .execute(rpc_client, vec![transaction], &[staker])?
+        .failed
+        .is_empty()
+    {
+        return Err("Failed to sweep the staker balance to the treasury".into());
+    }
+    Ok(lamports)
+}
+
 /// Remove validators no longer present in the desired validator list
 ///
 /// In order to properly remove a validator from the stake pool, their stake
 /// account must first be reduced down to the minimum of rent-exemption + 1 SOL.
 /// The staker will take control of the validator stake account on removal, so
 /// this also deactivates the stake, to be reclaimed in the next epoch.
-fn remove_validators_from_pool(
-    rpc_client: &RpcClient,
-    authorized_staker: &Keypair,
-    stake_pool_address: &Pubkey,
+///
+/// The instructions of a removal share a single transaction when they fit, and are otherwise
+/// split across dependent transactions that are submitted in order. The removals are added to
+/// `graph` after the operations of `dependencies`, returning the last operation of each.
+#[allow(clippy::too_many_arguments)]
+fn remove_validators_from_pool<'a>(
+    accounts: &AccountStore,
+    memo: &TransactionMemo,
+    program_id: &Pubkey,
+    authorized_staker: &'a Keypair,
+    stake_pool_address: &Pubkey,
     stake_pool: &StakePool,
     validator_list: &ValidatorList,
     remove_vote_addresses: HashSet<Pubkey>,
-) -> Result<(), Box<dyn error::Error>> {
-    let mut transactions = vec![];
-    let stake_rent_exemption = get_minimum_stake_balance_for_rent_exemption(rpc_client)?;
+    graph: &mut OperationGraph<'a>,
+    dependencies: &[OperationId],
+) -> Result<Vec<OperationId>, Box<dyn error::Error>> {
+    let mut removals = vec![];
+    let stake_rent_exemption = accounts.stake_rent_exemption();
+    let withdraw_authority =
+        find_withdraw_authority_program_address(program_id, stake_pool_address).0;
 
     for vote_address in remove_vote_addresses {
         let validator_list_entry = validator_list.find(&vote_address);
         if let Some(validator_list_entry) = validator_list_entry {
             if validator_list_entry.status == StakeStatus::Active {
-                let removed_stake_address = find_stake_program_address(
-                    &spl_stake_pool::id(),
-                    &vote_address,
-                    stake_pool_address,
-                )
-                .0;
-                let mut instructions = vec![];
-                if validator_list_entry.stake_lamports > stake_rent_exemption {
-                    instructions.push(
-                        spl_stake_pool::instruction::decrease_validator_stake_with_vote(
-                            stake_pool,
-                            stake_pool_address,
-                            &vote_address,
-                            validator_list_entry.stake_lamports,
-                        ),
-                    );
+                let removed_stake_address =
+                    find_stake_program_address(program_id, &vote_address, stake_pool_address).0;
+                let removed_stake_account =
+                    accounts.account(&removed_stake_address)?.ok_or_else(|| {
+                        format!("Stake account {} does not exist", removed_stake_address)
+                    })?;
+                if let Err(err) = stake_account_check::check(
+                    &removed_stake_address,
+                    removed_stake_account,
+                    &ExpectedStakeAccount::authorized(withdraw_authority)
+                        .delegated_to(vote_address),
+                ) {
+                    warn!("{}, not removing validator {}", err, vote_address);
+                    continue;
                 }
 
-                instructions.push(
-                    spl_stake_pool::instruction::remove_validator_from_pool_with_vote(
+                let mut instructions = vec![];
+                if validator_list_entry.stake_lamports > stake_rent_exemption {
+                    instructions.push(stake_pool_program::decrease_validator_stake_with_vote(
+                        program_id,
                         stake_pool,
                         stake_pool_address,
                         &vote_address,
-                        &authorized_staker.pubkey(),
-                    ),
-                );
+                        validator_list_entry.stake_lamports,
+                    ));
+                }
+
+                instructions.push(stake_pool_program::remove_validator_from_pool_with_vote(
+                    program_id,
+                    stake_pool,
+                    stake_pool_address,
+                    &vote_address,
+                    &authorized_staker.pubkey(),
+                ));
                 instructions.push(stake_instruction::deactivate_stake(
                     &removed_stake_address,
                     &authorized_staker.pubkey(),
                 ));
-                transactions.push(Transaction::new_with_payer(
-                    &instructions,
-                    Some(&authorized_staker.pubkey()),
+                removals.extend(graph.add_sequence(
+                    format!("remove validator {} from the pool", vote_address),
+                    transaction_packer::pack(
+                        &authorized_staker.pubkey(),
+                        &instructions,
+                        Some(&memo.instruction(&Phase::Removals.to_string(), Some(&vote_address))),
+                    )?,
+                    &[authorized_staker],
+                    dependencies,
                 ));
             } else {
                 debug!("Validator {} already removed, ignoring", vote_address);
@@ -511,28 +2152,28 @@ fn remove_validators_from_pool(
         }
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
-        .failed
-        .is_empty()
-    {
-        Err("Failed to add validators to the stake pool".into())
-    } else {
-        Ok(())
-    }
+    Ok(removals)
 }
 
 /// Add validator stake accounts that have been created and delegated, but not
-/// included yet in the stake pool
-fn add_validators_to_pool(
-    rpc_client: &RpcClient,
-    authorized_staker: &Keypair,
+/// included yet in the stake pool. The additions are added to `graph` after the operations of
+/// `dependencies`
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn add_validators_to_pool<'a>(
+    accounts: &AccountStore,
+    memo: &TransactionMemo,
+    program_id: &Pubkey,
+    authorized_staker: &'a Keypair,
     desired_validator_stake: &[ValidatorStake],
     stake_pool_address: &Pubkey,
     stake_pool: &StakePool,
     validator_list: &ValidatorList,
-) -> Result<(), Box<dyn error::Error>> {
-    let mut transactions = vec![];
-    let stake_rent_exemption = get_minimum_stake_balance_for_rent_exemption(rpc_client)?;
+    graph: &mut OperationGraph<'a>,
+    dependencies: &[OperationId],
+) -> Result<(HashMap<Pubkey, u64>, Vec<OperationId>), Box<dyn error::Error>> {
+    let mut additions = vec![];
+    let mut attempted_additions = HashMap::new();
+    let stake_rent_exemption = accounts.stake_rent_exemption();
     let min_stake_account_balance = stake_rent_exemption + MIN_STAKE_ACCOUNT_BALANCE;
 
     for ValidatorStake {
@@ -543,28 +2184,27 @@ fn add_validators_to_pool(
     {
         if !validator_list.contains(vote_address) {
             let stake_address =
-                find_stake_program_address(&spl_stake_pool::id(), vote_address, stake_pool_address)
-                    .0;
-            let stake_account = rpc_client
-                .get_account_with_commitment(&stake_address, rpc_client.commitment())?
-                .value;
+                find_stake_program_address(program_id, vote_address, stake_pool_address).0;
+            if let Some(stake_account) = accounts.account(&stake_address)? {
+                // The stake account is created with the staker as both of its authorities
+                if let Err(err) = stake_account_check::check(
+                    &stake_address,
+                    stake_account,
+                    &ExpectedStakeAccount::authorized(authorized_staker.pubkey())
+                        .delegated_to(*vote_address),
+                ) {
+                    warn!("{}, not adding validator {}", err, identity);
+                    continue;
+                }
 
-            if let Some(stake_account) = stake_account {
                 // Check if the stake account is busy
-                let stake_activation = rpc_client
-                    .get_stake_activation(stake_address, None)
-                    .map_err(|err| {
-                        format!(
-                            "Unable to get activation information for stake account: {}: {}",
-                            stake_address, err
-                        )
-                    })?;
-
-                if stake_activation.state == StakeActivationState::Active {
+                if accounts.stake_activation(&stake_address)? == StakeActivationState::Active {
                     info!("Adding validator {} to the pool", identity);
+                    attempted_additions.insert(*vote_address, 0);
                     let mut instructions = vec![];
                     if stake_account.lamports > min_stake_account_balance {
                         let split_lamports = stake_account.lamports - min_stake_account_balance;
+                        attempted_additions.insert(*vote_address, split_lamports);
                         let transient_stake_address = staker_transient_stake_address(
                             authorized_staker.pubkey(),
                             *vote_address,
@@ -596,49 +2236,60 @@ fn add_validators_to_pool(
                             &authorized_staker.pubkey(),
                         ));
                     }
-                    instructions.push(
-                        spl_stake_pool::instruction::add_validator_to_pool_with_vote(
-                            stake_pool,
-                            stake_pool_address,
-                            vote_address,
-                        ),
-                    );
-                    transactions.push(Transaction::new_with_payer(
-                        &instructions,
-                        Some(&authorized_staker.pubkey()),
+                    instructions.push(stake_pool_program::add_validator_to_pool_with_vote(
+                        program_id,
+                        stake_pool,
+                        stake_pool_address,
+                        vote_address,
+                    ));
+                    additions.push(graph.add(
+                        format!("add validator {} to the pool", identity),
+                        vec![memo.transaction(
+                            &instructions,
+                            &authorized_staker.pubkey(),
+                            &Phase::Additions.to_string(),
+                            Some(vote_address),
+                        )],
+                        &[authorized_staker],
+                        dependencies,
                     ));
                 }
             }
         }
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
-        .failed
-        .is_empty()
-    {
-        Err("Failed to add validators to the stake pool".into())
-    } else {
-        Ok(())
-    }
+    // Failed additions are identified by the caller once the validator list is updated
+    Ok((attempted_additions, additions))
 }
 
 /// Create validator stake accounts that are not currently included in the stake pool.
 /// For any newly created account, the validator identity is added to the set of
-/// busy validators.
-fn create_validator_stake_accounts(
-    rpc_client: &RpcClient,
-    authorized_staker: &Keypair,
+/// busy validators. The vote addresses of the validators whose stake account is created are
+/// returned along with the operations.
+fn create_validator_stake_accounts<'a>(
+    accounts: &AccountStore,
+    memo: &TransactionMemo,
+    program_id: &Pubkey,
+    authorized_staker: &'a Keypair,
+    funder: &'a Keypair,
     desired_validator_stake: &[ValidatorStake],
     stake_pool_address: &Pubkey,
     busy_validators: &mut HashSet<Pubkey>,
-) -> Result<(), Box<dyn error::Error>> {
-    let mut staker_balance = rpc_client.get_balance(&authorized_staker.pubkey()).unwrap();
-    info!("Staker available balance: {}", Sol(staker_balance));
+    graph: &mut OperationGraph<'a>,
+) -> Result<(FundingSummary, Vec<OperationId>, HashSet<Pubkey>), Box<dyn error::Error>> {
+    let mut funder_balance = accounts.balance(&funder.pubkey())?;
+    info!(
+        "Funding account {} available balance: {}",
+        funder.pubkey(),
+        Sol(funder_balance)
+    );
+    let mut funding_summary = FundingSummary::default();
 
-    let stake_rent_exemption = get_minimum_stake_balance_for_rent_exemption(rpc_client)?;
+    let stake_rent_exemption = accounts.stake_rent_exemption();
     let min_stake_account_balance = stake_rent_exemption + MIN_STAKE_ACCOUNT_BALANCE;
 
-    let mut transactions = vec![];
+    let mut creations = vec![];
+    let mut created_vote_addresses = HashSet::new();
     for ValidatorStake {
         identity,
         vote_address,
@@ -646,23 +2297,12 @@ fn create_validator_stake_accounts(
     } in desired_validator_stake
     {
         let stake_address =
-            find_stake_program_address(&spl_stake_pool::id(), vote_address, stake_pool_address).0;
-        let stake_account = rpc_client
-            .get_account_with_commitment(&stake_address, rpc_client.commitment())?
-            .value;
-
-        if stake_account.is_some() {
+            find_stake_program_address(program_id, vote_address, stake_pool_address).0;
+        if accounts.account(&stake_address)?.is_some() {
             // Check if the stake account is busy
-            let stake_activation = rpc_client
-                .get_stake_activation(stake_address, None)
-                .map_err(|err| {
-                    format!(
-                        "Unable to get activation information for stake account: {}: {}",
-                        stake_address, err
-                    )
-                })?;
+            let stake_activation = accounts.stake_activation(&stake_address)?;
 
-            match stake_activation.state {
+            match stake_activation {
                 StakeActivationState::Activating | StakeActivationState::Deactivating => {
                     warn!(
                         "Validator {} busy due to stake activation or deactivation of {}: {:?}",
@@ -676,13 +2316,20 @@ fn create_validator_stake_accounts(
                         "Validator {} busy due to inactive stake {}: {:?}",
                         identity, stake_address, stake_activation
                     );
-                    transactions.push(Transaction::new_with_payer(
-                        &[stake_instruction::delegate_stake(
-                            &stake_address,
-                            &authorized_staker.pubkey(),
-                            vote_address,
+                    creations.push(graph.add(
+                        format!("activate the stake account of validator {}", identity),
+                        vec![memo.transaction(
+                            &[stake_instruction::delegate_stake(
+                                &stake_address,
+                                &authorized_staker.pubkey(),
+                                vote_address,
+                            )],
+                            &funder.pubkey(),
+                            &Phase::AccountCreation.to_string(),
+                            Some(vote_address),
                         )],
-                        Some(&authorized_staker.pubkey()),
+                        &[funder, authorized_staker],
+                        &[],
                     ));
                     debug!(
                         "Activating stake account for validator {} ({})",
@@ -692,28 +2339,39 @@ fn create_validator_stake_accounts(
                 }
             }
         } else {
-            if staker_balance < min_stake_account_balance {
+            if funder_balance < min_stake_account_balance {
                 // Try again next epoch
                 warn!(
-                    "Insufficient funds in reserve stake account to create stake account: {} required, {} balance",
-                    Sol(min_stake_account_balance), Sol(staker_balance)
+                    "Insufficient funds in funding account to create stake account: {} required, {} balance",
+                    Sol(min_stake_account_balance), Sol(funder_balance)
                 );
+                funding_summary.underfunded_stake_accounts += 1;
             } else {
                 // Create a stake account for the validator
-                staker_balance -= min_stake_account_balance;
+                funder_balance -= min_stake_account_balance;
+                funding_summary.funded_stake_accounts += 1;
+                funding_summary.funded_lamports += min_stake_account_balance;
 
-                let instruction =
-                    spl_stake_pool::instruction::create_validator_stake_account_with_vote(
-                        stake_pool_address,
-                        &authorized_staker.pubkey(),
-                        &authorized_staker.pubkey(),
-                        vote_address,
-                    );
+                let instruction = stake_pool_program::create_validator_stake_account_with_vote(
+                    program_id,
+                    stake_pool_address,
+                    &authorized_staker.pubkey(),
+                    &funder.pubkey(),
+                    vote_address,
+                );
 
-                transactions.push(Transaction::new_with_payer(
-                    &[instruction],
-                    Some(&authorized_staker.pubkey()),
+                creations.push(graph.add(
+                    format!("create the stake account of validator {}", identity),
+                    vec![memo.transaction(
+                        &[instruction],
+                        &funder.pubkey(),
+                        &Phase::AccountCreation.to_string(),
+                        Some(vote_address),
+                    )],
+                    &[funder, authorized_staker],
+                    &[],
                 ));
+                created_vote_addresses.insert(*vote_address);
                 info!(
                     "Creating stake account for validator {} ({})",
                     identity, stake_address
@@ -724,94 +2382,343 @@ fn create_validator_stake_accounts(
         }
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
-        .failed
-        .is_empty()
-    {
-        Err("Failed to create validator stake accounts".into())
+    // The funder balance is updated by the caller once the operations are submitted
+    Ok((funding_summary, creations, created_vote_addresses))
+}
+
+/// Amount of an increase of `amount` that a reserve with `reserve_stake_balance` available can
+/// fund, zero if none.
+///
+/// Each increase moves the lamports into a new transient stake account, which must hold its own
+/// rent-exemption on top of the minimum stake, so less than `min_transient_stake_balance`
+/// cannot be moved
+fn fundable_increase(
+    amount: u64,
+    reserve_stake_balance: u64,
+    min_transient_stake_balance: u64,
+) -> u64 {
+    let funded = amount.min(reserve_stake_balance);
+    if funded < min_transient_stake_balance {
+        0
     } else {
-        Ok(())
+        funded
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn distribute_validator_stake<V>(
-    rpc_client: &RpcClient,
-    authorized_staker: &Keypair,
-    stake_pool_address: &Pubkey,
-    stake_pool: &StakePool,
-    validator_list: &ValidatorList,
-    desired_validator_stake: V,
-    baseline_stake_amount: u64,
-    bonus_stake_amount: u64,
-) -> Result<bool, Box<dyn error::Error>>
-where
-    V: IntoIterator<Item = ValidatorStake>,
-{
-    let mut reserve_stake_balance = get_available_stake_balance(
-        rpc_client,
-        stake_pool.reserve_stake,
-        MIN_STAKE_RESERVE_BALANCE,
-    )
-    .map_err(|err| {
-        format!(
-            "Unable to get reserve stake account balance: {}: {}",
-            stake_pool.reserve_stake, err
-        )
-    })?;
+/// Simulate the stake `increases`, in processing order, against the available reserve balance.
+/// Returns the shortfall of each validator, by vote address, that will receive less than
+/// requested
+fn project_reserve_shortfall(
+    mut reserve_stake_balance: u64,
+    min_transient_stake_balance: u64,
+    increases: &[(Pubkey, u64)],
+) -> HashMap<Pubkey, u64> {
+    let mut shortfall = HashMap::new();
+    for (vote_address, amount) in increases {
+        let funded = fundable_increase(*amount, reserve_stake_balance, min_transient_stake_balance);
+        reserve_stake_balance -= funded;
+        if funded < *amount {
+            shortfall.insert(*vote_address, amount - funded);
+        }
+    }
+    shortfall
+}
 
-    info!(
-        "Reserve stake available balance before updates: {}",
-        Sol(reserve_stake_balance)
-    );
+/// Split the `failing_vote_addresses` into the validators held in the pool, along with the epoch
+/// their soft removal began, and those soft removed for `soft_removal_epochs` that are due for
+/// removal. `soft_removed_since` holds the epoch the soft removal of each validator began as of
+/// the previous epoch
+fn soft_removals(
+    epoch: Epoch,
+    soft_removal_epochs: u64,
+    soft_removed_since: &HashMap<Pubkey, Epoch>,
+    failing_vote_addresses: &HashSet<Pubkey>,
+) -> (HashMap<Pubkey, Epoch>, HashSet<Pubkey>) {
+    let mut held = HashMap::new();
+    let mut removed = HashSet::new();
+    for vote_address in failing_vote_addresses {
+        let since = soft_removed_since
+            .get(vote_address)
+            .copied()
+            .unwrap_or(epoch);
+        if epoch.saturating_sub(since) < soft_removal_epochs {
+            held.insert(*vote_address, since);
+        } else {
+            removed.insert(*vote_address);
+        }
+    }
+    (held, removed)
+}
+
+/// Share `reserve_stake_balance` among the `increases` in proportion to their amount, funding
+/// them in full if it can. The smallest increases are dropped until every share is at least
+/// `min_transient_stake_balance`. Returns the amount allotted to each validator, by vote address
+fn pro_rata_increases(
+    reserve_stake_balance: u64,
+    min_transient_stake_balance: u64,
+    increases: &[(Pubkey, u64)],
+) -> HashMap<Pubkey, u64> {
+    let mut increases = increases
+        .iter()
+        .filter(|(_, amount)| *amount >= min_transient_stake_balance)
+        .cloned()
+        .collect::<Vec<_>>();
+    increases.sort_by_key(|(_, amount)| Reverse(*amount));
+
+    loop {
+        let total = increases
+            .iter()
+            .map(|(_, amount)| *amount as u128)
+            .sum::<u128>();
+        if total <= reserve_stake_balance as u128 {
+            return increases.into_iter().collect();
+        }
+        let share = |amount: u64| (amount as u128 * reserve_stake_balance as u128 / total) as u64;
+        match increases.last() {
+            Some((_, amount)) if share(*amount) < min_transient_stake_balance => {
+                increases.pop();
+            }
+            _ => {
+                return increases
+                    .iter()
+                    .map(|(vote_address, amount)| (*vote_address, share(*amount)))
+                    .collect()
+            }
+        }
+    }
+}
 
-    // Prioritize funding smaller stake accounts to maximize the number of accounts that will be
-    // funded with the available reserve stake.
+/// Stake movement of a validator within the stake pool
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum StakeOp {
+    /// Move `lamports` from the reserve to the validator
+    Increase { vote_address: Pubkey, lamports: u64 },
+    /// Move `lamports` from the validator back to the reserve
+    Decrease { vote_address: Pubkey, lamports: u64 },
+}
+
+/// Plan the stake changes that take each validator of `validator_stake`, given with its
+/// current balance, towards its desired stake, funding increases from the
+/// `reserve_stake_balance` available. The desired stake of the validators of
+/// `kept_deposit_lamports` includes the external deposits they keep. The validators of
+/// `busy_carryover_lamports` are funded first. Returns the summary of the changes, and the changes themselves in the order they are
+/// to be issued
+#[allow(clippy::too_many_arguments)]
+pub fn plan_stake_changes(
+    epoch: Epoch,
+    validator_stake: Vec<(u64, ValidatorStake)>,
+    mut reserve_stake_balance: u64,
+    min_transient_stake_balance: u64,
+    baseline_stake_amount: u64,
+    bonus_stake_amounts: &HashMap<Pubkey, u64>,
+    kept_deposit_lamports: &HashMap<Pubkey, u64>,
+    min_stake_change: MinStakeChange,
+    stake_tolerance: StakeTolerance,
+    max_epoch_stake_change_percentage: Option<f64>,
+    previous_stake_movement_lamports: &HashMap<Pubkey, i64>,
+    busy_carryover_lamports: &HashMap<Pubkey, i64>,
+    operation_order: OperationOrder,
+) -> (StakePoolSummary, Vec<StakeOp>) {
+    // Validators are funded by stake state, and within each state in the order of
+    // `operation_order.funding_priority`
     let mut min_stake = vec![];
     let mut baseline_stake = vec![];
     let mut bonus_stake = vec![];
 
-    for validator_stake in desired_validator_stake {
-        match validator_list.find(&validator_stake.vote_address) {
-            None => warn!(
-                "Vote address {} found in desired validator stake, but not in stake pool",
-                &validator_stake.vote_address
-            ),
-            Some(validator_entry) => {
-                let list = match validator_stake.stake_state {
-                    ValidatorStakeState::None => &mut min_stake,
-                    ValidatorStakeState::Baseline => &mut baseline_stake,
-                    ValidatorStakeState::Bonus => &mut bonus_stake,
-                };
+    for (balance, validator_stake) in validator_stake {
+        let list = match validator_stake.stake_state {
+            ValidatorStakeState::None => &mut min_stake,
+            ValidatorStakeState::Baseline => &mut baseline_stake,
+            ValidatorStakeState::Bonus => &mut bonus_stake,
+        };
+
+        list.push((balance, validator_stake));
+    }
 
-                list.push((validator_entry.stake_lamports, validator_stake));
+    let desired_balance_of = |validator_stake: &ValidatorStake| {
+        let kept_deposit = kept_deposit_lamports
+            .get(&validator_stake.vote_address)
+            .copied()
+            .unwrap_or_default();
+        match validator_stake.stake_state {
+            ValidatorStakeState::None => 0,
+            ValidatorStakeState::Baseline => baseline_stake_amount + kept_deposit,
+            ValidatorStakeState::Bonus => {
+                bonus_stake_amounts[&validator_stake.vote_address] + kept_deposit
+            }
+        }
+    };
+    for list in [&mut min_stake, &mut baseline_stake, &mut bonus_stake].iter_mut() {
+        match operation_order.funding_priority {
+            // Sort from lowest to highest balance
+            FundingPriority::SmallestAccount | FundingPriority::ProRata => {
+                list.sort_by_key(|k| k.0)
+            }
+            // Sort from largest to smallest distance below the desired stake
+            FundingPriority::LargestDeficit => list.sort_by_key(|(balance, validator_stake)| {
+                (
+                    Reverse(desired_balance_of(validator_stake).saturating_sub(*balance)),
+                    *balance,
+                )
+            }),
+        }
+    }
+    let mut validator_stake = min_stake
+        .into_iter()
+        .chain(baseline_stake)
+        .chain(bonus_stake)
+        .collect::<Vec<_>>();
+    // Validators skipped as busy by the previous epoch go first, so that they do not keep
+    // lagging their desired stake
+    validator_stake.sort_by_key(|(_, validator_stake)| {
+        !busy_carryover_lamports.contains_key(&validator_stake.vote_address)
+    });
+
+    // Project the reserve before issuing any stake movement, to report up front the validators
+    // it cannot fully fund
+    let increases = validator_stake
+        .iter()
+        .filter_map(|(balance, validator_stake)| {
+            let desired_balance = desired_balance_of(validator_stake);
+            let amount_to_add = desired_balance.saturating_sub(*balance);
+            if amount_to_add >= min_stake_change.amount(desired_balance)
+                && !stake_tolerance.contains(*balance, desired_balance)
+            {
+                Some((validator_stake.vote_address, amount_to_add))
+            } else {
+                None
             }
+        })
+        .collect::<Vec<_>>();
+    let pro_rata_allotments = if operation_order.funding_priority == FundingPriority::ProRata {
+        // The validators carried over from the previous epoch are funded first, then each stake
+        // state only from what the previous ones leave in the reserve
+        let tiers = validator_stake
+            .iter()
+            .map(|(_, validator_stake)| {
+                let tier = if busy_carryover_lamports.contains_key(&validator_stake.vote_address) {
+                    0
+                } else {
+                    match validator_stake.stake_state {
+                        ValidatorStakeState::Baseline => 1,
+                        ValidatorStakeState::Bonus => 2,
+                        ValidatorStakeState::None => 3,
+                    }
+                };
+                (validator_stake.vote_address, tier)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut available_reserve_balance = reserve_stake_balance;
+        let mut allotments = HashMap::new();
+        for tier in 0..=3 {
+            let tier_increases = increases
+                .iter()
+                .filter(|(vote_address, _)| tiers[vote_address] == tier)
+                .cloned()
+                .collect::<Vec<_>>();
+            let tier_allotments = pro_rata_increases(
+                available_reserve_balance,
+                min_transient_stake_balance,
+                &tier_increases,
+            );
+            available_reserve_balance -= tier_allotments.values().sum::<u64>();
+            allotments.extend(tier_allotments);
+        }
+        Some(allotments)
+    } else {
+        None
+    };
+    let reserve_shortfall_lamports = match &pro_rata_allotments {
+        Some(allotments) => increases
+            .iter()
+            .filter_map(|(vote_address, amount)| {
+                let funded = allotments.get(vote_address).copied().unwrap_or_default();
+                if funded < *amount {
+                    Some((*vote_address, amount - funded))
+                } else {
+                    None
+                }
+            })
+            .collect(),
+        None => project_reserve_shortfall(
+            reserve_stake_balance,
+            min_transient_stake_balance,
+            &increases,
+        ),
+    };
+    for (_, validator_stake) in &validator_stake {
+        if let Some(shortfall) = reserve_shortfall_lamports.get(&validator_stake.vote_address) {
+            warn!(
+                "Reserve projected to fall {} short of the desired stake of {}",
+                Sol(*shortfall),
+                validator_stake.identity
+            );
         }
     }
 
-    // Sort from lowest to highest balance
-    min_stake.sort_by_key(|k| k.0);
-    baseline_stake.sort_by_key(|k| k.0);
-    bonus_stake.sort_by_key(|k| k.0);
+    // When planning over several epochs, each validator only moves as far as the first epoch of
+    // the schedule takes it
+    let rebalance_plan = match max_epoch_stake_change_percentage {
+        Some(max_epoch_stake_change_percentage) => {
+            let balances = validator_stake
+                .iter()
+                .map(|(balance, validator_stake)| {
+                    let desired_balance = desired_balance_of(validator_stake);
+                    let distance = balance.max(&desired_balance) - balance.min(&desired_balance);
+                    let target_balance = if distance < min_stake_change.amount(desired_balance)
+                        || stake_tolerance.contains(*balance, desired_balance)
+                    {
+                        *balance
+                    } else {
+                        desired_balance
+                    };
+                    (validator_stake.vote_address, *balance, target_balance)
+                })
+                .collect::<Vec<_>>();
+            let rebalance_plan = rebalance_plan::plan(
+                epoch,
+                reserve_stake_balance,
+                &balances,
+                max_epoch_stake_change_percentage,
+                min_transient_stake_balance,
+            );
+            for epoch_plan in &rebalance_plan.epochs {
+                info!(
+                    "Planned for epoch {}: {} stake movements, adding {} and removing {}",
+                    epoch_plan.epoch,
+                    epoch_plan.moves.len(),
+                    Sol(epoch_plan.stake_added_lamports()),
+                    Sol(epoch_plan.stake_removed_lamports())
+                );
+            }
+            Some(rebalance_plan)
+        }
+        None => None,
+    };
+    let first_epoch_balances = rebalance_plan
+        .as_ref()
+        .map(|rebalance_plan| rebalance_plan.first_epoch_balances());
 
-    let mut transactions = vec![];
-    for (
-        balance,
-        ValidatorStake {
+    let mut summary = StakePoolSummary {
+        reserve_shortfall_lamports,
+        ..StakePoolSummary::default()
+    };
+    let mut stake_ops = vec![];
+    for (balance, validator_stake) in validator_stake {
+        let target_balance = desired_balance_of(&validator_stake);
+        let desired_balance = match &first_epoch_balances {
+            Some(first_epoch_balances) => first_epoch_balances
+                .get(&validator_stake.vote_address)
+                .copied()
+                .unwrap_or(balance),
+            None => target_balance,
+        };
+        let ValidatorStake {
             identity,
             stake_state,
             vote_address,
-        },
-    ) in min_stake
-        .into_iter()
-        .chain(baseline_stake)
-        .chain(bonus_stake)
-    {
-        let desired_balance = match stake_state {
-            ValidatorStakeState::None => 0,
-            ValidatorStakeState::Baseline => baseline_stake_amount,
-            ValidatorStakeState::Bonus => bonus_stake_amount,
-        };
+            ..
+        } = validator_stake;
         info!(
             "desired stake for {} ({:?}) is {}, current balance is {}",
             identity,
@@ -820,57 +2727,93 @@ where
             Sol(balance)
         );
 
+        summary.add_stake(stake_state, balance);
+        let min_stake_change_amount = min_stake_change.amount(desired_balance);
+
+        let within_tolerance =
+            balance != desired_balance && stake_tolerance.contains(balance, desired_balance);
+
         #[allow(clippy::comparison_chain)]
-        let op_msg = if balance > desired_balance {
+        let op_msg = if within_tolerance {
+            "within tolerance".to_string()
+        } else if balance > desired_balance {
             let amount_to_remove = balance - desired_balance;
-            if amount_to_remove < MIN_STAKE_CHANGE_AMOUNT {
+            let added_last_epoch = previous_stake_movement_lamports
+                .get(&vote_address)
+                .map_or(false, |lamports| *lamports > 0);
+            if amount_to_remove < min_stake_change_amount {
                 format!("not removing {} (amount too small)", Sol(amount_to_remove))
+            } else if added_last_epoch && stake_state != ValidatorStakeState::None {
+                // Stake that just completed its warmup would go straight into cooldown, having
+                // been inactive for nothing
+                summary
+                    .off_target_lamports
+                    .insert(vote_address, amount_to_remove);
+                format!(
+                    "not removing {} (stake was added last epoch)",
+                    Sol(amount_to_remove)
+                )
             } else {
-                transactions.push(Transaction::new_with_payer(
-                    &[
-                        spl_stake_pool::instruction::decrease_validator_stake_with_vote(
-                            stake_pool,
-                            stake_pool_address,
-                            &vote_address,
-                            amount_to_remove,
-                        ),
-                    ],
-                    Some(&authorized_staker.pubkey()),
-                ));
+                stake_ops.push(StakeOp::Decrease {
+                    vote_address,
+                    lamports: amount_to_remove,
+                });
+                summary.stake_removed_lamports += amount_to_remove;
+                summary
+                    .stake_movement_lamports
+                    .insert(vote_address, -(amount_to_remove as i64));
+                summary
+                    .off_target_lamports
+                    .insert(vote_address, amount_to_remove);
                 format!("removing {}", Sol(amount_to_remove))
             }
         } else if balance < desired_balance {
-            let mut amount_to_add = desired_balance - balance;
+            let amount_to_add = desired_balance - balance;
 
-            if amount_to_add < MIN_STAKE_CHANGE_AMOUNT {
+            if amount_to_add < min_stake_change_amount {
                 format!("not adding {} (amount too small)", Sol(amount_to_add))
             } else {
+                summary
+                    .off_target_lamports
+                    .insert(vote_address, amount_to_add);
                 if amount_to_add > reserve_stake_balance {
                     trace!(
                         "note: amount_to_add > reserve_stake_balance: {} > {}",
                         amount_to_add,
                         reserve_stake_balance
                     );
-                    amount_to_add = reserve_stake_balance;
                 }
+                let amount_to_add = fundable_increase(
+                    match &pro_rata_allotments {
+                        Some(allotments) => amount_to_add
+                            .min(allotments.get(&vote_address).copied().unwrap_or_default()),
+                        None => amount_to_add,
+                    },
+                    reserve_stake_balance,
+                    min_transient_stake_balance,
+                );
 
-                if amount_to_add < MIN_STAKE_CHANGE_AMOUNT {
-                    "reserve depleted".to_string()
+                if amount_to_add == 0 {
+                    if desired_balance - balance < min_transient_stake_balance {
+                        format!(
+                            "not adding {} (below the rent-exempt transient stake minimum)",
+                            Sol(desired_balance - balance)
+                        )
+                    } else {
+                        "reserve depleted".to_string()
+                    }
                 } else {
                     reserve_stake_balance -= amount_to_add;
                     info!("adding {} stake", Sol(amount_to_add));
 
-                    transactions.push(Transaction::new_with_payer(
-                        &[
-                            spl_stake_pool::instruction::increase_validator_stake_with_vote(
-                                stake_pool,
-                                stake_pool_address,
-                                &vote_address,
-                                amount_to_add,
-                            ),
-                        ],
-                        Some(&authorized_staker.pubkey()),
-                    ));
+                    stake_ops.push(StakeOp::Increase {
+                        vote_address,
+                        lamports: amount_to_add,
+                    });
+                    summary.stake_added_lamports += amount_to_add;
+                    summary
+                        .stake_movement_lamports
+                        .insert(vote_address, amount_to_add as i64);
                     format!("adding {}", Sol(amount_to_add))
                 }
             }
@@ -886,57 +2829,289 @@ where
             Sol(balance),
             op_msg,
         );
-    }
-    info!(
-        "Reserve stake available balance after updates: {}",
-        Sol(reserve_stake_balance)
-    );
 
-    let ok = send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
-        .failed
-        .is_empty();
+        // Validators scheduled for a later epoch remain off target until then
+        if let Some(rebalance_plan) = &rebalance_plan {
+            let distance = balance.max(target_balance) - balance.min(target_balance);
+            let scheduled = rebalance_plan.epochs.iter().any(|epoch_plan| {
+                epoch_plan
+                    .moves
+                    .iter()
+                    .any(|m| m.vote_address == vote_address)
+            });
+            if scheduled
+                || rebalance_plan
+                    .unreachable_lamports
+                    .contains_key(&vote_address)
+            {
+                summary.off_target_lamports.insert(vote_address, distance);
+            }
+        }
+    }
+    summary.rebalance_plan = rebalance_plan;
+    summary.reserve_stake_lamports = reserve_stake_balance;
 
-    if !ok {
-        error!("One or more transactions failed to execute")
+    if operation_order.decreases_first {
+        // Stable, so the increases remain in funding order
+        stake_ops.sort_by_key(|stake_op| matches!(stake_op, StakeOp::Increase { .. }));
     }
-    Ok(ok)
+    (summary, stake_ops)
 }
 
-#[cfg(test)]
-mod test {
-    use {
-        super::*,
-        crate::rpc_client_utils::test::*,
-        solana_sdk::{
-            clock::Epoch,
-            epoch_schedule::{EpochSchedule, MINIMUM_SLOTS_PER_EPOCH},
-            native_token::sol_to_lamports,
-            signature::{Keypair, Signer},
-        },
-        solana_validator::test_validator::*,
-        spl_stake_pool::find_withdraw_authority_program_address,
-    };
-
-    fn num_stake_accounts(rpc_client: &RpcClient, authority: Pubkey) -> usize {
-        get_all_stake(rpc_client, authority).unwrap().0.len()
-    }
+/// Transactions of `phase` issuing `stake_ops` in order, paid for by `payer` and left for the
+/// staker to sign
+pub fn stake_op_transactions(
+    memo: &TransactionMemo,
+    phase: &str,
+    program_id: &Pubkey,
+    stake_pool: &StakePool,
+    stake_pool_address: &Pubkey,
+    payer: &Pubkey,
+    stake_ops: &[StakeOp],
+) -> Vec<Transaction> {
+    stake_ops
+        .iter()
+        .map(|stake_op| {
+            let (instruction, vote_address) = match *stake_op {
+                StakeOp::Increase {
+                    vote_address,
+                    lamports,
+                } => (
+                    stake_pool_program::increase_validator_stake_with_vote(
+                        program_id,
+                        stake_pool,
+                        stake_pool_address,
+                        &vote_address,
+                        lamports,
+                    ),
+                    vote_address,
+                ),
+                StakeOp::Decrease {
+                    vote_address,
+                    lamports,
+                } => (
+                    stake_pool_program::decrease_validator_stake_with_vote(
+                        program_id,
+                        stake_pool,
+                        stake_pool_address,
+                        &vote_address,
+                        lamports,
+                    ),
+                    vote_address,
+                ),
+            };
+            memo.transaction(&[instruction], payer, phase, Some(&vote_address))
+        })
+        .collect()
+}
 
-    fn validator_stake_balance(
-        rpc_client: &RpcClient,
-        stake_pool_address: &Pubkey,
-        validator: &ValidatorAddressPair,
-    ) -> u64 {
-        let stake_rent_exemption =
-            get_minimum_stake_balance_for_rent_exemption(rpc_client).unwrap();
-        let min_stake_account_balance = stake_rent_exemption + MIN_STAKE_ACCOUNT_BALANCE;
-        let stake_address = find_stake_program_address(
-            &spl_stake_pool::id(),
-            &validator.vote_address,
-            stake_pool_address,
-        )
-        .0;
-        let stake_balance = rpc_client.get_balance(&stake_address).unwrap();
-        info!("Stake {} has balance {}", stake_address, stake_balance);
+#[allow(clippy::too_many_arguments)]
+fn distribute_validator_stake<V>(
+    rpc_client: &RpcClient,
+    accounts: &AccountStore,
+    memo: &TransactionMemo,
+    executor: &dyn TransactionExecutor,
+    program_id: &Pubkey,
+    authorized_staker: &Keypair,
+    stake_pool_address: &Pubkey,
+    stake_pool: &StakePool,
+    validator_list: &ValidatorList,
+    desired_validator_stake: V,
+    baseline_stake_amount: u64,
+    bonus_stake_amounts: &HashMap<Pubkey, u64>,
+    kept_deposit_lamports: &HashMap<Pubkey, u64>,
+    min_stake_change: MinStakeChange,
+    stake_tolerance: StakeTolerance,
+    max_epoch_stake_change_percentage: Option<f64>,
+    previous_stake_movement_lamports: &HashMap<Pubkey, i64>,
+    busy_carryover_lamports: &HashMap<Pubkey, i64>,
+    operation_order: OperationOrder,
+    confirmation_gate: Option<&mut ConfirmationGate>,
+    rebalance: bool,
+) -> Result<StakePoolSummary, Box<dyn error::Error>>
+where
+    V: IntoIterator<Item = ValidatorStake>,
+{
+    let reserve_stake_balance = available_stake_balance(
+        stake_pool.reserve_stake,
+        accounts.balance(&stake_pool.reserve_stake)?,
+        MIN_STAKE_RESERVE_BALANCE,
+    )
+    .map_err(|err| {
+        format!(
+            "Unable to get reserve stake account balance: {}: {}",
+            stake_pool.reserve_stake, err
+        )
+    })?;
+
+    // The reserve must remain rent-exempt, and each increase must fund a rent-exempt transient
+    // stake account
+    let stake_rent_exemption = accounts.stake_rent_exemption();
+    let available_reserve_stake_balance =
+        reserve_stake_balance.saturating_sub(stake_rent_exemption);
+    let min_transient_stake_balance = stake_rent_exemption + min_stake_change.lamports;
+
+    info!(
+        "Reserve stake available balance before updates: {}",
+        Sol(available_reserve_stake_balance)
+    );
+
+    let mut validator_stake = vec![];
+    for validator_stake_entry in desired_validator_stake {
+        match validator_list.find(&validator_stake_entry.vote_address) {
+            None => warn!(
+                "Vote address {} found in desired validator stake, but not in stake pool",
+                &validator_stake_entry.vote_address
+            ),
+            Some(validator_entry) => {
+                validator_stake.push((validator_entry.stake_lamports, validator_stake_entry))
+            }
+        }
+    }
+    let balances = validator_stake
+        .iter()
+        .map(|(balance, validator_stake)| (validator_stake.vote_address, *balance))
+        .collect::<Vec<_>>();
+
+    let epoch_info = rpc_client.get_epoch_info()?;
+    let epoch = epoch_info.epoch;
+    let (mut summary, stake_ops) = plan_stake_changes(
+        epoch,
+        validator_stake,
+        available_reserve_stake_balance,
+        min_transient_stake_balance,
+        baseline_stake_amount,
+        bonus_stake_amounts,
+        kept_deposit_lamports,
+        min_stake_change,
+        stake_tolerance,
+        max_epoch_stake_change_percentage,
+        previous_stake_movement_lamports,
+        busy_carryover_lamports,
+        operation_order,
+    );
+
+    // Without rebalancing the validator stake and off-target amounts are still reported, but
+    // none of the stake movements are issued
+    if !rebalance {
+        summary.stake_movement_lamports.clear();
+    }
+
+    summary.validator_balance_lamports = balances.iter().cloned().collect();
+    summary.movement_slot_index = epoch_info.slot_index;
+    summary.slots_in_epoch = epoch_info.slots_in_epoch;
+
+    let cluster_stake = ClusterStake::fetch(rpc_client)?;
+    summary.projected_effective_stake_lamports = balances
+        .into_iter()
+        .map(|(vote_address, balance)| {
+            let movement = summary
+                .stake_movement_lamports
+                .get(&vote_address)
+                .copied()
+                .unwrap_or_default();
+            let stake = StakeActivity {
+                effective: balance,
+                activating: movement.max(0) as u64,
+                deactivating: (-movement).max(0) as u64,
+            };
+            (
+                vote_address,
+                stake_projection::project(&cluster_stake, &stake, PROJECTION_EPOCHS),
+            )
+        })
+        .collect();
+
+    info!(
+        "Reserve stake available balance after updates: {}",
+        Sol(summary.reserve_stake_lamports)
+    );
+
+    if !rebalance {
+        info!(
+            "Rebalancing is disabled, {} stake movements not issued",
+            stake_ops.len()
+        );
+        return Ok(StakePoolSummary {
+            reserve_stake_lamports: available_reserve_stake_balance,
+            stake_added_lamports: 0,
+            stake_removed_lamports: 0,
+            ..summary
+        });
+    }
+
+    if let Some(confirmation_gate) = confirmation_gate {
+        confirmation_gate.check(
+            stake_pool_address,
+            epoch,
+            summary.stake_added_lamports + summary.stake_removed_lamports,
+            0,
+        )?;
+    }
+
+    let transactions = stake_op_transactions(
+        memo,
+        &Phase::Rebalancing.to_string(),
+        program_id,
+        stake_pool,
+        stake_pool_address,
+        &authorized_staker.pubkey(),
+        &stake_ops,
+    );
+    summary.failed_transactions = executor
+        .execute(rpc_client, transactions, &[authorized_staker])?
+        .failed
+        .len();
+
+    if summary.failed_transactions > 0 {
+        error!("One or more transactions failed to execute")
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            chaos_rpc::{ChaosRpcProxy, FaultProbabilities},
+            journal::Journal,
+            rpc_client_utils::{retry_rpc_operation, test::*},
+            snapshot::{self, StakePoolSnapshot},
+            transaction_executor::RpcExecutor,
+        },
+        proptest::prelude::*,
+        solana_sdk::{
+            account::{Account, AccountSharedData},
+            clock::{Epoch, Slot},
+            epoch_schedule::{EpochSchedule, MINIMUM_SLOTS_PER_EPOCH},
+            native_token::sol_to_lamports,
+            signature::{Keypair, Signer},
+        },
+        solana_validator::test_validator::*,
+        spl_stake_pool::{find_withdraw_authority_program_address, state::ValidatorStakeInfo},
+    };
+
+    fn num_stake_accounts(rpc_client: &RpcClient, authority: Pubkey) -> usize {
+        get_all_stake(rpc_client, authority).unwrap().0.len()
+    }
+
+    fn validator_stake_balance(
+        rpc_client: &RpcClient,
+        stake_pool_address: &Pubkey,
+        validator: &ValidatorAddressPair,
+    ) -> u64 {
+        let stake_rent_exemption =
+            get_minimum_stake_balance_for_rent_exemption(rpc_client).unwrap();
+        let min_stake_account_balance = stake_rent_exemption + MIN_STAKE_ACCOUNT_BALANCE;
+        let stake_address = find_stake_program_address(
+            &spl_stake_pool::id(),
+            &validator.vote_address,
+            stake_pool_address,
+        )
+        .0;
+        let stake_balance = rpc_client.get_balance(&stake_address).unwrap();
+        info!("Stake {} has balance {}", stake_address, stake_balance);
         stake_balance - min_stake_account_balance
     }
 
@@ -956,11 +3131,7 @@ mod test {
 
         let desired_validator_stake = validators
             .iter()
-            .map(|vap| ValidatorStake {
-                identity: vap.identity,
-                vote_address: vap.vote_address,
-                stake_state,
-            })
+            .map(|vap| ValidatorStake::new(vap.identity, vap.vote_address, stake_state))
             .collect::<Vec<_>>();
 
         stake_o_matic
@@ -992,353 +3163,1426 @@ mod test {
     }
 
     #[test]
-    fn this_test_is_too_big_and_slow() {
-        solana_logger::setup_with_default("solana_stake_o_matic=info");
+    fn test_phase_from_str() {
+        for phase in Phase::ALL {
+            assert_eq!(phase.parse::<Phase>().unwrap().to_string(), *phase);
+        }
+        assert_eq!(
+            "account-creation".parse::<Phase>(),
+            Ok(Phase::AccountCreation)
+        );
+        assert!("deposits".parse::<Phase>().is_err());
+    }
 
-        let mut test_validator_genesis = TestValidatorGenesis::default();
-        test_validator_genesis
-            .epoch_schedule(EpochSchedule::custom(
-                MINIMUM_SLOTS_PER_EPOCH,
-                MINIMUM_SLOTS_PER_EPOCH,
-                /* enable_warmup_epochs = */ false,
-            ))
-            .add_program("spl_stake_pool", spl_stake_pool::id());
-        let (test_validator, authorized_staker) = test_validator_genesis.start();
+    #[test]
+    fn test_fundable_increase() {
+        let rent = 2_282_880;
+        let min_transient_stake_balance = rent + MIN_STAKE_ACCOUNT_BALANCE;
 
-        let (rpc_client, _recent_blockhash, _fee_calculator) = test_validator.rpc_client();
+        // Fully funded, including an increase of exactly the transient stake minimum
+        assert_eq!(
+            fundable_increase(
+                sol_to_lamports(5.),
+                sol_to_lamports(10.),
+                min_transient_stake_balance
+            ),
+            sol_to_lamports(5.)
+        );
+        assert_eq!(
+            fundable_increase(
+                min_transient_stake_balance,
+                min_transient_stake_balance,
+                min_transient_stake_balance
+            ),
+            min_transient_stake_balance
+        );
 
-        let stake_pool = Keypair::new();
-        let pool_withdraw_authority =
-            find_withdraw_authority_program_address(&spl_stake_pool::id(), &stake_pool.pubkey()).0;
+        // Clipped to the reserve
+        assert_eq!(
+            fundable_increase(
+                sol_to_lamports(5.),
+                sol_to_lamports(2.),
+                min_transient_stake_balance
+            ),
+            sol_to_lamports(2.)
+        );
 
-        let assert_validator_stake_activation =
-            |vap: &ValidatorAddressPair, epoch: Epoch, state: StakeActivationState| {
-                let stake_address = find_stake_program_address(
-                    &spl_stake_pool::id(),
-                    &vap.vote_address,
-                    &stake_pool.pubkey(),
-                )
-                .0;
-                assert_eq!(
-                    rpc_client
-                        .get_stake_activation(stake_address, Some(epoch))
-                        .unwrap()
-                        .state,
-                    state,
-                );
-            };
+        // The minimum stake alone cannot cover the transient stake account rent
+        assert_eq!(
+            fundable_increase(
+                MIN_STAKE_ACCOUNT_BALANCE,
+                sol_to_lamports(10.),
+                min_transient_stake_balance
+            ),
+            0
+        );
 
-        // ===========================================================
-        info!("Create stake pool: mint, fee account, reserve stake, and pool itself");
-        let stake_rent_exemption =
-            get_minimum_stake_balance_for_rent_exemption(&rpc_client).unwrap();
-        let withdraw_authority =
-            find_withdraw_authority_program_address(&spl_stake_pool::id(), &stake_pool.pubkey()).0;
-        let pool_mint = create_mint(&rpc_client, &authorized_staker, &withdraw_authority).unwrap();
-        let pool_fee_account = create_token_account(
-            &rpc_client,
-            &authorized_staker,
-            &pool_mint,
-            &authorized_staker.pubkey(),
-        )
-        .unwrap();
-        let pool_reserve_stake = create_stake_account(
-            &rpc_client,
-            &authorized_staker,
-            &withdraw_authority,
-            stake_rent_exemption + MIN_STAKE_RESERVE_BALANCE,
-        )
-        .unwrap()
-        .pubkey();
-        let num_validators = 3;
-        create_stake_pool(
-            &rpc_client,
-            &authorized_staker,
-            &stake_pool,
-            &pool_reserve_stake,
-            &pool_mint,
-            &pool_fee_account,
-            &authorized_staker,
-            &authorized_staker.pubkey(),
-            num_validators,
+        // The reserve is one lamport short of a rent-exempt transient stake account
+        assert_eq!(
+            fundable_increase(
+                sol_to_lamports(5.),
+                min_transient_stake_balance - 1,
+                min_transient_stake_balance
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn test_project_reserve_shortfall() {
+        let rent = 2_282_880;
+        let min_transient_stake_balance = rent + MIN_STAKE_ACCOUNT_BALANCE;
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+        let sol = sol_to_lamports;
+
+        // Enough for every increase
+        assert!(project_reserve_shortfall(
+            sol(3.) + rent,
+            min_transient_stake_balance,
+            &[(a, sol(2.)), (b, sol(1.) + rent)]
         )
-        .unwrap();
+        .is_empty());
 
-        info!("Create three validators");
-        let validators =
-            create_validators(&rpc_client, &authorized_staker, num_validators).unwrap();
+        // Too little left for the rent-exempt transient stake account of the second increase
+        assert_eq!(
+            project_reserve_shortfall(
+                sol(3.),
+                min_transient_stake_balance,
+                &[(a, sol(2.)), (b, sol(1.) + rent)]
+            ),
+            vec![(b, sol(1.) + rent)].into_iter().collect()
+        );
 
-        let baseline_stake_amount = sol_to_lamports(10.);
-        let total_stake_amount =
-            (baseline_stake_amount + sol_to_lamports(100.)) * validators.len() as u64;
-        let total_stake_amount_plus_min =
-            total_stake_amount + stake_rent_exemption + MIN_STAKE_RESERVE_BALANCE;
+        // A partially funded increase exhausts the reserve for the following ones
+        assert_eq!(
+            project_reserve_shortfall(
+                sol(4.) + rent,
+                min_transient_stake_balance,
+                &[(a, sol(2.)), (b, sol(5.)), (c, sol(5.))]
+            ),
+            vec![(b, sol(3.) - rent), (c, sol(5.))]
+                .into_iter()
+                .collect()
+        );
+    }
 
-        let assert_reserve_account_only = |current_reserve_amount| {
-            assert_eq!(
-                rpc_client.get_balance(&pool_reserve_stake).unwrap(),
-                current_reserve_amount,
+    fn stake_state_strategy() -> impl Strategy<Value = ValidatorStakeState> {
+        prop_oneof![
+            Just(ValidatorStakeState::None),
+            Just(ValidatorStakeState::Baseline),
+            Just(ValidatorStakeState::Bonus),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_plan_stake_changes(
+            reserve_stake_balance in 0..2_000 * LAMPORTS_PER_SOL,
+            baseline_stake_amount in 0..100 * LAMPORTS_PER_SOL,
+            min_stake_change_lamports in 0..10 * LAMPORTS_PER_SOL,
+            min_stake_change_percentage in proptest::option::of(0f64..10.),
+            below_percentage in 0f64..10.,
+            above_percentage in 0f64..10.,
+            validators in proptest::collection::vec(
+                (0..1_000 * LAMPORTS_PER_SOL, stake_state_strategy(), 0..1_000 * LAMPORTS_PER_SOL),
+                0..20,
+            ),
+        ) {
+            let rent = 2_282_880;
+            let min_stake_change = MinStakeChange {
+                lamports: min_stake_change_lamports,
+                target_percentage: min_stake_change_percentage,
+            };
+            let min_transient_stake_balance = rent + min_stake_change.lamports;
+            let stake_tolerance = StakeTolerance {
+                below_percentage,
+                above_percentage,
+            };
+
+            let mut bonus_stake_amounts = HashMap::new();
+            let validator_stake = validators
+                .into_iter()
+                .map(|(balance, stake_state, bonus_stake_amount)| {
+                    let vote_address = Pubkey::new_unique();
+                    bonus_stake_amounts.insert(vote_address, bonus_stake_amount);
+                    (
+                        balance,
+                        ValidatorStake::new(Pubkey::new_unique(), vote_address, stake_state),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let (summary, stake_ops) = plan_stake_changes(
+                0,
+                validator_stake.clone(),
+                reserve_stake_balance,
+                min_transient_stake_balance,
+                baseline_stake_amount,
+                &bonus_stake_amounts,
+                &HashMap::new(),
+                min_stake_change,
+                stake_tolerance,
+                None,
+                &HashMap::new(),
+                &HashMap::new(),
+                OperationOrder::default(),
             );
-            {
-                let (all_stake, all_stake_total_amount) =
-                    get_all_stake(&rpc_client, withdraw_authority).unwrap();
-                assert_eq!(all_stake_total_amount, current_reserve_amount);
-                assert_eq!(all_stake.len(), 1);
-                assert!(all_stake.contains(&pool_reserve_stake));
+            let stake_movements = stake_ops
+                .iter()
+                .map(|stake_op| match *stake_op {
+                    StakeOp::Increase {
+                        vote_address,
+                        lamports,
+                    } => (vote_address, lamports as i64),
+                    StakeOp::Decrease {
+                        vote_address,
+                        lamports,
+                    } => (vote_address, -(lamports as i64)),
+                })
+                .collect::<HashMap<_, _>>();
+            // At most one change per validator
+            prop_assert_eq!(stake_movements.len(), stake_ops.len());
+
+            // The reserve funds every increase
+            let stake_added_lamports = stake_movements
+                .values()
+                .filter(|lamports| **lamports > 0)
+                .map(|lamports| *lamports as u64)
+                .sum::<u64>();
+            prop_assert!(stake_added_lamports <= reserve_stake_balance);
+            prop_assert_eq!(summary.stake_added_lamports, stake_added_lamports);
+            prop_assert_eq!(
+                summary.reserve_stake_lamports,
+                reserve_stake_balance - stake_added_lamports
+            );
+
+            // Processing order: by stake state, then from lowest to highest balance
+            let priority = |(balance, validator_stake): &(u64, ValidatorStake)| {
+                let rank = match validator_stake.stake_state {
+                    ValidatorStakeState::None => 0,
+                    ValidatorStakeState::Baseline => 1,
+                    ValidatorStakeState::Bonus => 2,
+                };
+                (rank, *balance)
+            };
+            let mut funded = vec![];
+            let mut short = vec![];
+            for entry in &validator_stake {
+                let (balance, validator_stake) = entry;
+                let desired_balance = match validator_stake.stake_state {
+                    ValidatorStakeState::None => 0,
+                    ValidatorStakeState::Baseline => baseline_stake_amount,
+                    ValidatorStakeState::Bonus => {
+                        bonus_stake_amounts[&validator_stake.vote_address]
+                    }
+                };
+                let movement = stake_movements
+                    .get(&validator_stake.vote_address)
+                    .copied()
+                    .unwrap_or_default();
+
+                // No balance goes negative or past the desired stake
+                let new_balance = *balance as i128 + movement as i128;
+                prop_assert!(new_balance >= 0);
+                prop_assert!(
+                    (new_balance - desired_balance as i128).abs()
+                        <= (*balance as i128 - desired_balance as i128).abs()
+                );
+
+                // No movement is smaller than the minimum stake change
+                if movement != 0 {
+                    prop_assert!(
+                        movement.abs() as u64 >= min_stake_change.amount(desired_balance)
+                    );
+                    prop_assert!(!stake_tolerance.contains(*balance, desired_balance));
+                }
+                if movement > 0 {
+                    prop_assert!(movement as u64 >= min_transient_stake_balance);
+                    funded.push(priority(entry));
+                }
+
+                let amount_to_add = desired_balance.saturating_sub(*balance);
+                if amount_to_add >= min_stake_change.amount(desired_balance)
+                    && amount_to_add >= min_transient_stake_balance
+                    && !stake_tolerance.contains(*balance, desired_balance)
+                    && movement < amount_to_add as i64
+                {
+                    short.push(priority(entry));
+                }
             }
-        };
-        assert_reserve_account_only(MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption);
 
-        let mut stake_o_matic = new(
-            &rpc_client,
-            authorized_staker,
-            stake_pool.pubkey(),
-            baseline_stake_amount,
-        )
-        .unwrap();
+            // Once the reserve falls short of an increase, no validator after it is funded
+            if let Some(first_short) = short.iter().min() {
+                prop_assert!(funded.iter().all(|funded| funded <= first_short));
+            }
+        }
+    }
 
-        // ===========================================================
-        info!(
-            "Start with creating validator stake accounts and deposit stake, no managed stake yet"
+    #[test]
+    fn test_pro_rata_increases() {
+        let (a, b, c) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
         );
-        let epoch = rpc_client.get_epoch_info().unwrap().epoch;
-        stake_o_matic
-            .apply(
-                &rpc_client,
-                false,
-                &validators
-                    .iter()
-                    .map(|vap| ValidatorStake {
-                        identity: vap.identity,
-                        vote_address: vap.vote_address,
-                        stake_state: ValidatorStakeState::None,
-                    })
-                    .collect::<Vec<_>>(),
-            )
-            .unwrap();
 
-        let deposit_stake_address = create_stake_account(
-            &rpc_client,
-            &stake_o_matic.authorized_staker,
-            &stake_o_matic.authorized_staker.pubkey(),
-            total_stake_amount,
-        )
-        .unwrap()
-        .pubkey();
-        let deposit_vote_address = validators.first().unwrap().vote_address;
-        delegate_stake(
-            &rpc_client,
-            &stake_o_matic.authorized_staker,
-            &deposit_stake_address,
-            &deposit_vote_address,
-        )
-        .unwrap();
+        // Fully funded when the reserve covers every increase
+        assert_eq!(
+            pro_rata_increases(100, 10, &[(a, 60), (b, 40)]),
+            vec![(a, 60), (b, 40)].into_iter().collect()
+        );
 
-        info!("min: wait for stake activation");
+        // Scaled down otherwise
         assert_eq!(
-            rpc_client.get_balance(&pool_reserve_stake).unwrap(),
-            MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption,
+            pro_rata_increases(50, 10, &[(a, 60), (b, 40)]),
+            vec![(a, 30), (b, 20)].into_iter().collect()
         );
 
-        for validator in &validators {
-            assert_validator_stake_activation(validator, epoch, StakeActivationState::Activating);
-            assert_eq!(
-                validator_stake_balance(&rpc_client, &stake_pool.pubkey(), validator),
-                0,
-            );
-        }
-        assert_eq!(num_stake_accounts(&rpc_client, pool_withdraw_authority), 1);
+        // `c` would receive less than the minimum, its share goes to the others
         assert_eq!(
-            num_stake_accounts(&rpc_client, stake_o_matic.authorized_staker.pubkey()),
-            validators.len() + 1
+            pro_rata_increases(50, 10, &[(a, 60), (b, 40), (c, 15)]),
+            vec![(a, 30), (b, 20)].into_iter().collect()
         );
-        let epoch = wait_for_next_epoch(&rpc_client).unwrap();
+    }
 
-        // To simulate a reward-earning environment, we add a few lamports
-        // to the validator stake accounts. This way, during the
-        // `add_validators_to_pool` phase, we can test the logic to split from
-        // the validator stake account.
-        for validator in &validators {
-            assert_validator_stake_activation(validator, epoch, StakeActivationState::Active);
-            let stake_address = find_stake_program_address(
-                &spl_stake_pool::id(),
-                &validator.vote_address,
-                &stake_o_matic.stake_pool_address,
+    #[test]
+    fn test_soft_removals() {
+        let (recovered, failing, persistent) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let soft_removed_since = vec![(recovered, 8), (persistent, 7)]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let failing_vote_addresses = vec![failing, persistent].into_iter().collect();
+
+        // `failing` starts its soft removal, `persistent` is removed after 3 epochs and
+        // `recovered` no longer fails
+        assert_eq!(
+            soft_removals(10, 3, &soft_removed_since, &failing_vote_addresses),
+            (
+                vec![(failing, 10)].into_iter().collect(),
+                vec![persistent].into_iter().collect()
             )
-            .0;
-            transfer(
-                &rpc_client,
-                &stake_o_matic.authorized_staker,
-                &stake_address,
-                30,
+        );
+        assert_eq!(
+            soft_removals(9, 3, &soft_removed_since, &failing_vote_addresses),
+            (
+                vec![(failing, 9), (persistent, 7)].into_iter().collect(),
+                HashSet::new()
             )
-            .unwrap();
-        }
+        );
 
-        // ===========================================================
-        stake_o_matic.epoch_update(&rpc_client).unwrap();
-        info!("Add all validators to the pool");
-        stake_o_matic
-            .apply(
-                &rpc_client,
-                false,
-                &validators
+        // Without soft removal, failing validators are removed at once
+        assert_eq!(
+            soft_removals(10, 0, &HashMap::new(), &failing_vote_addresses),
+            (HashMap::new(), failing_vote_addresses)
+        );
+    }
+
+    #[test]
+    fn test_stake_allocation() {
+        let (removed, baseline, bonus, added) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut validator_list = ValidatorList::new(0);
+        validator_list.validators = vec![(removed, 30), (baseline, 10), (bonus, 10)]
+            .into_iter()
+            .map(
+                |(vote_account_address, stake_lamports)| ValidatorStakeInfo {
+                    vote_account_address,
+                    stake_lamports,
+                    ..ValidatorStakeInfo::default()
+                },
+            )
+            .collect();
+        let desired_validator_stake = vec![
+            ValidatorStake::new(
+                Pubkey::new_unique(),
+                baseline,
+                ValidatorStakeState::Baseline,
+            ),
+            ValidatorStake::new(Pubkey::new_unique(), bonus, ValidatorStakeState::Bonus),
+            ValidatorStake::new(Pubkey::new_unique(), added, ValidatorStakeState::Bonus),
+        ];
+        let bonus_stake_amounts = vec![(bonus, 40), (added, 25)].into_iter().collect();
+
+        let allocation = stake_allocation(
+            &validator_list,
+            &desired_validator_stake,
+            5,
+            &bonus_stake_amounts,
+        );
+        let allocation_of = |vote_address| {
+            let StakeAllocation {
+                current_lamports,
+                desired_lamports,
+            } = allocation[vote_address];
+            (current_lamports, desired_lamports)
+        };
+        assert_eq!(allocation.len(), 4);
+        assert_eq!(allocation_of(&removed), (30, 0));
+        assert_eq!(allocation_of(&baseline), (10, 5));
+        assert_eq!(allocation_of(&bonus), (10, 40));
+        assert_eq!(allocation_of(&added), (0, 25));
+        assert_eq!(allocation[&bonus].change_lamports(), 30);
+        assert_eq!(allocation[&removed].change_lamports(), -30);
+    }
+
+    #[test]
+    fn test_plan_stake_changes_operation_order() {
+        let bonus_validator = |balance: u64, desired_balance: u64| {
+            (
+                balance * LAMPORTS_PER_SOL,
+                desired_balance * LAMPORTS_PER_SOL,
+                ValidatorStake::new(
+                    Pubkey::new_unique(),
+                    Pubkey::new_unique(),
+                    ValidatorStakeState::Bonus,
+                ),
+            )
+        };
+        let far_below = bonus_validator(10, 100);
+        let smallest = bonus_validator(5, 20);
+        let above = bonus_validator(60, 20);
+        let validators = [&far_below, &smallest, &above];
+        let bonus_stake_amounts = validators
+            .iter()
+            .map(|(_, desired_balance, vs)| (vs.vote_address, *desired_balance))
+            .collect::<HashMap<_, _>>();
+
+        let plan_with_carryover = |operation_order, busy_carryover_lamports: &HashMap<_, _>| {
+            plan_stake_changes(
+                0,
+                validators
                     .iter()
-                    .map(|vap| ValidatorStake {
-                        identity: vap.identity,
-                        vote_address: vap.vote_address,
-                        stake_state: ValidatorStakeState::None,
-                    })
-                    .collect::<Vec<_>>(),
+                    .map(|(balance, _, vs)| (*balance, vs.clone()))
+                    .collect(),
+                50 * LAMPORTS_PER_SOL,
+                LAMPORTS_PER_SOL,
+                0,
+                &bonus_stake_amounts,
+                &HashMap::new(),
+                MinStakeChange {
+                    lamports: LAMPORTS_PER_SOL,
+                    target_percentage: None,
+                },
+                StakeTolerance {
+                    below_percentage: 0.,
+                    above_percentage: 0.,
+                },
+                None,
+                &HashMap::new(),
+                busy_carryover_lamports,
+                operation_order,
             )
+            .1
+        };
+        let plan = |operation_order| plan_with_carryover(operation_order, &HashMap::new());
+        let increase = |vs: &ValidatorStake, sol: u64| StakeOp::Increase {
+            vote_address: vs.vote_address,
+            lamports: sol * LAMPORTS_PER_SOL,
+        };
+        let decrease = StakeOp::Decrease {
+            vote_address: above.2.vote_address,
+            lamports: 40 * LAMPORTS_PER_SOL,
+        };
+
+        // The smallest account is funded in full, the rest of the reserve goes to the next one
+        assert_eq!(
+            plan(OperationOrder::default()),
+            vec![
+                increase(&smallest.2, 15),
+                increase(&far_below.2, 35),
+                decrease
+            ]
+        );
+
+        // The whole reserve goes to the validator furthest below its desired stake
+        assert_eq!(
+            plan(OperationOrder {
+                funding_priority: FundingPriority::LargestDeficit,
+                ..OperationOrder::default()
+            }),
+            vec![increase(&far_below.2, 50), decrease]
+        );
+
+        // Both increases are scaled by the 50 SOL available over the 105 SOL requested
+        assert_eq!(
+            plan(OperationOrder {
+                funding_priority: FundingPriority::ProRata,
+                ..OperationOrder::default()
+            }),
+            vec![
+                StakeOp::Increase {
+                    vote_address: smallest.2.vote_address,
+                    lamports: 7_142_857_142,
+                },
+                StakeOp::Increase {
+                    vote_address: far_below.2.vote_address,
+                    lamports: 42_857_142_857,
+                },
+                decrease
+            ]
+        );
+
+        assert_eq!(
+            plan(OperationOrder {
+                decreases_first: true,
+                ..OperationOrder::default()
+            }),
+            vec![
+                decrease,
+                increase(&smallest.2, 15),
+                increase(&far_below.2, 35)
+            ]
+        );
+
+        // A validator left short while busy in the previous epoch is funded first
+        assert_eq!(
+            plan_with_carryover(
+                OperationOrder::default(),
+                &vec![(far_below.2.vote_address, 90 * LAMPORTS_PER_SOL as i64)]
+                    .into_iter()
+                    .collect()
+            ),
+            vec![increase(&far_below.2, 50), decrease]
+        );
+    }
+
+    #[test]
+    fn test_execute_transactions_with_rpc_faults() {
+        solana_logger::setup_with_default("solana_stake_o_matic=info");
+
+        let (test_validator, payer) = TestValidatorGenesis::default().start();
+        let (rpc_client, _recent_blockhash, _fee_calculator) = test_validator.rpc_client();
+
+        // Every transaction is dropped, and the blockhash expires while they are pending
+        let chaos = ChaosRpcProxy::start(
+            &test_validator.rpc_url(),
+            FaultProbabilities {
+                dropped_transaction: 1.,
+                stale_blockhash: 1.,
+                ..FaultProbabilities::default()
+            },
+            42,
+        );
+        let chaos_rpc_client = chaos.rpc_client(rpc_client.commitment());
+
+        let recipients = (0..3).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let transfers = || {
+            recipients
+                .iter()
+                .map(|recipient| {
+                    Transaction::new_with_payer(
+                        &[system_instruction::transfer(
+                            &payer.pubkey(),
+                            recipient,
+                            sol_to_lamports(1.),
+                        )],
+                        Some(&payer.pubkey()),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let result = RpcExecutor::default()
+            .execute(&chaos_rpc_client, transfers(), &[&payer])
+            .unwrap();
+        assert!(result.succeeded.is_empty());
+        assert_eq!(result.failed.len(), recipients.len());
+        for recipient in &recipients {
+            assert_eq!(rpc_client.get_balance(recipient).unwrap(), 0);
+        }
+        assert_eq!(chaos.injected().dropped_transactions, recipients.len());
+        assert!(chaos.injected().stale_blockhashes > 0);
+
+        // Rate limited requests are retried
+        chaos.set_probabilities(FaultProbabilities {
+            rate_limit: 0.25,
+            ..FaultProbabilities::default()
+        });
+        let result = RpcExecutor::default()
+            .execute(&chaos_rpc_client, transfers(), &[&payer])
             .unwrap();
+        assert_eq!(result.succeeded.len(), recipients.len());
+        assert!(result.failed.is_empty());
+        for recipient in &recipients {
+            assert_eq!(
+                rpc_client.get_balance(recipient).unwrap(),
+                sol_to_lamports(1.)
+            );
+        }
 
-        info!("Deposit stake");
-        let staker_pool_token_address = create_token_account(
+        chaos.set_probabilities(FaultProbabilities {
+            rate_limit: 0.9,
+            ..FaultProbabilities::default()
+        });
+        assert_eq!(
+            retry_rpc_operation(10, || chaos_rpc_client.get_epoch_info())
+                .unwrap()
+                .epoch,
+            rpc_client.get_epoch_info().unwrap().epoch
+        );
+        assert!(chaos.injected().rate_limits > 0);
+    }
+
+    #[test]
+    fn test_apply_recovers_from_rpc_faults() {
+        solana_logger::setup_with_default("solana_stake_o_matic=info");
+
+        // Long enough epochs for every run to complete within the same epoch
+        let mut test_validator_genesis = TestValidatorGenesis::default();
+        test_validator_genesis
+            .epoch_schedule(EpochSchedule::custom(
+                MINIMUM_SLOTS_PER_EPOCH * 32,
+                MINIMUM_SLOTS_PER_EPOCH * 32,
+                /* enable_warmup_epochs = */ false,
+            ))
+            .add_program("spl_stake_pool", spl_stake_pool::id());
+        let (test_validator, authorized_staker) = test_validator_genesis.start();
+        let (rpc_client, _recent_blockhash, _fee_calculator) = test_validator.rpc_client();
+
+        let stake_pool = Keypair::new();
+        let withdraw_authority =
+            find_withdraw_authority_program_address(&spl_stake_pool::id(), &stake_pool.pubkey()).0;
+        let stake_rent_exemption =
+            get_minimum_stake_balance_for_rent_exemption(&rpc_client).unwrap();
+        let pool_mint = create_mint(&rpc_client, &authorized_staker, &withdraw_authority).unwrap();
+        let pool_fee_account = create_token_account(
             &rpc_client,
-            &stake_o_matic.authorized_staker,
+            &authorized_staker,
             &pool_mint,
-            &stake_o_matic.authorized_staker.pubkey(),
+            &authorized_staker.pubkey(),
         )
         .unwrap();
-        deposit_into_stake_pool(
+        let pool_reserve_stake = create_stake_account(
             &rpc_client,
-            &stake_o_matic.authorized_staker,
-            &stake_o_matic.stake_pool_address,
-            &stake_o_matic.stake_pool,
-            &deposit_vote_address,
-            &deposit_stake_address,
-            &staker_pool_token_address,
+            &authorized_staker,
+            &withdraw_authority,
+            stake_rent_exemption + MIN_STAKE_RESERVE_BALANCE,
+        )
+        .unwrap()
+        .pubkey();
+        let num_validators = 2;
+        create_stake_pool(
+            &rpc_client,
+            &authorized_staker,
+            &stake_pool,
+            &pool_reserve_stake,
+            &pool_mint,
+            &pool_fee_account,
+            &authorized_staker,
+            &authorized_staker.pubkey(),
+            num_validators,
         )
         .unwrap();
+        let validators =
+            create_validators(&rpc_client, &authorized_staker, num_validators).unwrap();
 
-        info!("All validators to nothing, moving all to reserve");
-        stake_o_matic
-            .apply(
+        let chaos = ChaosRpcProxy::start(
+            &test_validator.rpc_url(),
+            FaultProbabilities {
+                dropped_transaction: 0.3,
+                stale_blockhash: 0.2,
+                rate_limit: 0.1,
+                partial_account_data: 0.1,
+            },
+            42,
+        );
+        let chaos_rpc_client = chaos.rpc_client(rpc_client.commitment());
+
+        let mut stake_o_matic = new(
+            &rpc_client,
+            StakePoolRoles::new(authorized_staker),
+            spl_stake_pool::id(),
+            stake_pool.pubkey(),
+            sol_to_lamports(10.),
+            MinStakeChange {
+                lamports: MIN_STAKE_ACCOUNT_BALANCE,
+                target_percentage: None,
+            },
+            StakeTolerance::default(),
+            None,
+            false,
+            false,
+            HashSet::new(),
+            None,
+            None,
+            None,
+            BaselineShortfall::default(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            OperationOrder::default(),
+            None,
+            Box::new(RpcExecutor::default()),
+        )
+        .unwrap();
+
+        // Each failed run is followed by another, as the bot would be rerun for the same epoch.
+        // A rerun resumes from the journal and the summary of the runs that completed before it
+        let journal_path =
+            std::env::temp_dir().join(format!("chaos-journal-{}", Pubkey::new_unique()));
+        let epoch = rpc_client.get_epoch_info().unwrap().epoch;
+        let desired_validator_stake = desired_stake_of(&validators, ValidatorStakeState::None);
+        let mut latest_run: Option<StakePoolSummary> = None;
+        let mut runs = 0;
+        loop {
+            runs += 1;
+            assert!(
+                runs <= 20,
+                "the stake pool did not recover from the RPC faults"
+            );
+            let journal = Journal::load(epoch, &journal_path).unwrap_or_else(|_| Journal {
+                desired_validator_stake: desired_validator_stake.clone(),
+                ..Journal::default()
+            });
+            if let Some(latest_run) = &latest_run {
+                stake_o_matic.set_incremental(latest_run.clone());
+            }
+            let result =
+                stake_o_matic.apply(&chaos_rpc_client, false, &journal.desired_validator_stake);
+            if result.is_ok() {
+                let summary = stake_o_matic.summary().unwrap();
+                let summary = match latest_run.take() {
+                    Some(latest_run) => latest_run.merge_rerun(summary),
+                    None => summary,
+                };
+                Journal {
+                    stake_movement_lamports: summary.stake_movement_lamports.clone(),
+                    ..journal
+                }
+                .save(epoch, &journal_path)
+                .unwrap();
+                latest_run = Some(summary);
+            }
+            match result {
+                Ok((_notes, true)) => break,
+                Ok((_notes, false)) => warn!("Run {}: transactions failed", runs),
+                Err(err) => warn!("Run {}: {}", runs, err),
+            }
+        }
+        assert!(chaos.injected().total() > 0);
+
+        // The interrupted runs were resumed from the journal, which holds the stake movements of
+        // every run that completed
+        let journal = Journal::load(epoch, &journal_path).unwrap();
+        assert_eq!(journal.desired_validator_stake, desired_validator_stake);
+        assert_eq!(
+            journal.stake_movement_lamports,
+            latest_run.unwrap().stake_movement_lamports
+        );
+
+        // The retried runs left the pool as a single clean run would have, without duplicate
+        // accounts or stray lamports
+        assert_eq!(
+            rpc_client.get_balance(&pool_reserve_stake).unwrap(),
+            MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption,
+        );
+        assert_eq!(num_stake_accounts(&rpc_client, withdraw_authority), 1);
+        assert_eq!(
+            num_stake_accounts(&rpc_client, stake_o_matic.roles.staker.pubkey()),
+            validators.len()
+        );
+        for validator in &validators {
+            assert_eq!(
+                validator_stake_balance(&rpc_client, &stake_pool.pubkey(), validator),
+                0,
+            );
+        }
+    }
+
+    fn desired_stake_of(
+        validators: &[ValidatorAddressPair],
+        stake_state: ValidatorStakeState,
+    ) -> Vec<ValidatorStake> {
+        validators
+            .iter()
+            .map(|vap| ValidatorStake::new(vap.identity, vap.vote_address, stake_state))
+            .collect()
+    }
+
+    fn assert_validator_stake_activation(
+        rpc_client: &RpcClient,
+        stake_pool_address: &Pubkey,
+        validator: &ValidatorAddressPair,
+        epoch: Epoch,
+        state: StakeActivationState,
+    ) {
+        let stake_address = find_stake_program_address(
+            &spl_stake_pool::id(),
+            &validator.vote_address,
+            stake_pool_address,
+        )
+        .0;
+        assert_eq!(
+            rpc_client
+                .get_stake_activation(stake_address, Some(epoch))
+                .unwrap()
+                .state,
+            state,
+        );
+    }
+
+    fn test_validator_genesis() -> TestValidatorGenesis {
+        let mut test_validator_genesis = TestValidatorGenesis::default();
+        test_validator_genesis
+            .epoch_schedule(EpochSchedule::custom(
+                MINIMUM_SLOTS_PER_EPOCH,
+                MINIMUM_SLOTS_PER_EPOCH,
+                /* enable_warmup_epochs = */ false,
+            ))
+            .add_program("spl_stake_pool", spl_stake_pool::id());
+        test_validator_genesis
+    }
+
+    fn new_stake_o_matic(
+        rpc_client: &RpcClient,
+        staker: Keypair,
+        stake_pool_address: Pubkey,
+        baseline_stake_amount: u64,
+    ) -> StakePoolOMatic {
+        new(
+            rpc_client,
+            StakePoolRoles::new(staker),
+            spl_stake_pool::id(),
+            stake_pool_address,
+            baseline_stake_amount,
+            MinStakeChange {
+                lamports: MIN_STAKE_ACCOUNT_BALANCE,
+                target_percentage: None,
+            },
+            StakeTolerance::default(),
+            None,
+            false,
+            false,
+            HashSet::new(),
+            None,
+            None,
+            None,
+            BaselineShortfall::default(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            OperationOrder::default(),
+            None,
+            Box::new(RpcExecutor::default()),
+        )
+        .unwrap()
+    }
+
+    /// Builds a stake pool on a test validator, with its validators added to the pool without
+    /// stake and the deposited stake held in the reserve, at the start of an epoch
+    struct PoolFixtureBuilder {
+        num_validators: u32,
+        baseline_stake_amount: u64,
+        deposit_amount: Option<u64>,
+    }
+
+    impl PoolFixtureBuilder {
+        fn new(num_validators: u32) -> Self {
+            Self {
+                num_validators,
+                baseline_stake_amount: sol_to_lamports(10.),
+                deposit_amount: None,
+            }
+        }
+
+        /// Stake deposited into the pool, by default the baseline stake plus 100 SOL for each
+        /// validator
+        fn deposit_amount(mut self, deposit_amount: u64) -> Self {
+            self.deposit_amount = Some(deposit_amount);
+            self
+        }
+
+        fn build(self) -> PoolFixture {
+            solana_logger::setup_with_default("solana_stake_o_matic=info");
+
+            let (test_validator, authorized_staker) = test_validator_genesis().start();
+            let (rpc_client, _recent_blockhash, _fee_calculator) = test_validator.rpc_client();
+
+            info!("Create stake pool: mint, fee account, reserve stake, and pool itself");
+            let stake_pool = Keypair::new();
+            let stake_rent_exemption =
+                get_minimum_stake_balance_for_rent_exemption(&rpc_client).unwrap();
+            let withdraw_authority = find_withdraw_authority_program_address(
+                &spl_stake_pool::id(),
+                &stake_pool.pubkey(),
+            )
+            .0;
+            let pool_mint =
+                create_mint(&rpc_client, &authorized_staker, &withdraw_authority).unwrap();
+            let pool_fee_account = create_token_account(
                 &rpc_client,
-                false,
-                &validators
-                    .iter()
-                    .map(|vap| ValidatorStake {
-                        identity: vap.identity,
-                        vote_address: vap.vote_address,
-                        stake_state: ValidatorStakeState::None,
-                    })
-                    .collect::<Vec<_>>(),
+                &authorized_staker,
+                &pool_mint,
+                &authorized_staker.pubkey(),
             )
             .unwrap();
-        let _epoch = wait_for_next_epoch(&rpc_client).unwrap();
+            let pool_reserve_stake = create_stake_account(
+                &rpc_client,
+                &authorized_staker,
+                &withdraw_authority,
+                stake_rent_exemption + MIN_STAKE_RESERVE_BALANCE,
+            )
+            .unwrap()
+            .pubkey();
+            create_stake_pool(
+                &rpc_client,
+                &authorized_staker,
+                &stake_pool,
+                &pool_reserve_stake,
+                &pool_mint,
+                &pool_fee_account,
+                &authorized_staker,
+                &authorized_staker.pubkey(),
+                self.num_validators,
+            )
+            .unwrap();
+
+            info!("Create {} validators", self.num_validators);
+            let validators =
+                create_validators(&rpc_client, &authorized_staker, self.num_validators).unwrap();
+            let deposit_amount = self.deposit_amount.unwrap_or(
+                (self.baseline_stake_amount + sol_to_lamports(100.)) * validators.len() as u64,
+            );
+
+            let mut stake_o_matic = new_stake_o_matic(
+                &rpc_client,
+                authorized_staker,
+                stake_pool.pubkey(),
+                self.baseline_stake_amount,
+            );
+
+            info!("Create validator stake accounts and deposit stake, no managed stake yet");
+            let epoch = rpc_client.get_epoch_info().unwrap().epoch;
+            let no_stake = desired_stake_of(&validators, ValidatorStakeState::None);
+            stake_o_matic.apply(&rpc_client, false, &no_stake).unwrap();
+
+            let deposit_stake_address = create_stake_account(
+                &rpc_client,
+                &stake_o_matic.roles.staker,
+                &stake_o_matic.roles.staker.pubkey(),
+                deposit_amount,
+            )
+            .unwrap()
+            .pubkey();
+            let deposit_vote_address = validators.first().unwrap().vote_address;
+            delegate_stake(
+                &rpc_client,
+                &stake_o_matic.roles.staker,
+                &deposit_stake_address,
+                &deposit_vote_address,
+            )
+            .unwrap();
+
+            assert_eq!(
+                rpc_client.get_balance(&pool_reserve_stake).unwrap(),
+                MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption,
+            );
+            for validator in &validators {
+                assert_validator_stake_activation(
+                    &rpc_client,
+                    &stake_pool.pubkey(),
+                    validator,
+                    epoch,
+                    StakeActivationState::Activating,
+                );
+                assert_eq!(
+                    validator_stake_balance(&rpc_client, &stake_pool.pubkey(), validator),
+                    0,
+                );
+            }
+            assert_eq!(num_stake_accounts(&rpc_client, withdraw_authority), 1);
+            assert_eq!(
+                num_stake_accounts(&rpc_client, stake_o_matic.roles.staker.pubkey()),
+                validators.len() + 1
+            );
+            let epoch = wait_for_next_epoch(&rpc_client).unwrap();
+
+            // To simulate a reward-earning environment, we add a few lamports
+            // to the validator stake accounts. This way, during the
+            // `add_validators_to_pool` phase, we can test the logic to split from
+            // the validator stake account.
+            for validator in &validators {
+                assert_validator_stake_activation(
+                    &rpc_client,
+                    &stake_pool.pubkey(),
+                    validator,
+                    epoch,
+                    StakeActivationState::Active,
+                );
+                let stake_address = find_stake_program_address(
+                    &spl_stake_pool::id(),
+                    &validator.vote_address,
+                    &stake_pool.pubkey(),
+                )
+                .0;
+                transfer(&rpc_client, &stake_o_matic.roles.staker, &stake_address, 30).unwrap();
+            }
+
+            stake_o_matic.epoch_update(&rpc_client).unwrap();
+            info!("Add all validators to the pool");
+            stake_o_matic.apply(&rpc_client, false, &no_stake).unwrap();
+
+            info!("Deposit stake");
+            let staker_pool_token_address = create_token_account(
+                &rpc_client,
+                &stake_o_matic.roles.staker,
+                &pool_mint,
+                &stake_o_matic.roles.staker.pubkey(),
+            )
+            .unwrap();
+            deposit_into_stake_pool(
+                &rpc_client,
+                &stake_o_matic.roles.staker,
+                &stake_o_matic.stake_pool_address,
+                &stake_o_matic.stake_pool,
+                &deposit_vote_address,
+                &deposit_stake_address,
+                &staker_pool_token_address,
+            )
+            .unwrap();
+
+            info!("All validators to nothing, moving all to reserve");
+            stake_o_matic.apply(&rpc_client, false, &no_stake).unwrap();
+            let _epoch = wait_for_next_epoch(&rpc_client).unwrap();
+            stake_o_matic.epoch_update(&rpc_client).unwrap();
+
+            PoolFixture {
+                _test_validator: test_validator,
+                rpc_client,
+                stake_o_matic,
+                validators,
+                baseline_stake_amount: self.baseline_stake_amount,
+                deposit_amount,
+                stake_rent_exemption,
+                token_accounts: vec![pool_fee_account, staker_pool_token_address],
+            }
+        }
+    }
+
+    struct PoolFixture {
+        // Shuts the test validator down when the fixture is dropped
+        _test_validator: TestValidator,
+        rpc_client: RpcClient,
+        stake_o_matic: StakePoolOMatic,
+        validators: Vec<ValidatorAddressPair>,
+        baseline_stake_amount: u64,
+        deposit_amount: u64,
+        stake_rent_exemption: u64,
+        // Pool token accounts, which cannot be found from the stake pool alone
+        token_accounts: Vec<Pubkey>,
+    }
+
+    /// The accounts of a `PoolFixture` at a slot, from which `PoolFixture::restore` starts a new
+    /// test validator. Stake history is not preserved, so take snapshots once the pool's stake
+    /// has settled
+    struct PoolFixtureSnapshot {
+        slot: Slot,
+        accounts: Vec<(Pubkey, Account)>,
+        staker: Vec<u8>,
+        stake_pool_address: Pubkey,
+        validators: Vec<(Pubkey, Pubkey)>,
+        baseline_stake_amount: u64,
+        deposit_amount: u64,
+        token_accounts: Vec<Pubkey>,
+    }
+
+    impl PoolFixture {
+        fn stake_pool_address(&self) -> Pubkey {
+            self.stake_o_matic.stake_pool_address
+        }
+
+        fn min_reserve_stake_balance(&self) -> u64 {
+            MIN_STAKE_RESERVE_BALANCE + self.stake_rent_exemption
+        }
+
+        fn reserve_stake_balance(&self) -> u64 {
+            self.rpc_client
+                .get_balance(&self.stake_o_matic.stake_pool.reserve_stake)
+                .unwrap()
+        }
+
+        fn validator_stake_balances(&self) -> Vec<u64> {
+            self.validators
+                .iter()
+                .map(|validator| {
+                    validator_stake_balance(&self.rpc_client, &self.stake_pool_address(), validator)
+                })
+                .collect()
+        }
+
+        fn apply(&mut self, desired_validator_stake: &[ValidatorStake]) -> Vec<String> {
+            let (notes, success) = self
+                .stake_o_matic
+                .apply(&self.rpc_client, false, desired_validator_stake)
+                .unwrap();
+            assert!(success);
+            notes
+        }
+
+        fn wait_for_next_epoch(&self) -> Epoch {
+            wait_for_next_epoch(&self.rpc_client).unwrap()
+        }
+
+        /// The stake pool as `snapshot::export` sees it, for comparisons with `snapshot::diff`
+        fn export(&self) -> StakePoolSnapshot {
+            snapshot::export(
+                &self.rpc_client,
+                &spl_stake_pool::id(),
+                &self.stake_pool_address(),
+            )
+            .unwrap()
+        }
+
+        fn snapshot(&self) -> PoolFixtureSnapshot {
+            let stake_pool = &self.stake_o_matic.stake_pool;
+            let staker = self.stake_o_matic.roles.staker.pubkey();
+            let mut addresses = vec![
+                self.stake_pool_address(),
+                stake_pool.validator_list,
+                stake_pool.reserve_stake,
+                stake_pool.pool_mint,
+                staker,
+            ];
+            addresses.extend(&self.token_accounts);
+            for validator in &self.validators {
+                addresses.push(validator.identity);
+                addresses.push(validator.vote_address);
+                addresses.push(
+                    find_stake_program_address(
+                        &spl_stake_pool::id(),
+                        &validator.vote_address,
+                        &self.stake_pool_address(),
+                    )
+                    .0,
+                );
+                addresses.push(
+                    find_transient_stake_program_address(
+                        &spl_stake_pool::id(),
+                        &validator.vote_address,
+                        &self.stake_pool_address(),
+                    )
+                    .0,
+                );
+            }
+            addresses.extend(get_all_stake(&self.rpc_client, staker).unwrap().0);
+
+            let slot = self.rpc_client.get_slot().unwrap();
+            let accounts = addresses
+                .chunks(100)
+                .flat_map(|addresses| {
+                    addresses
+                        .iter()
+                        .cloned()
+                        .zip(self.rpc_client.get_multiple_accounts(addresses).unwrap())
+                        .filter_map(|(address, account)| Some((address, account?)))
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+
+            PoolFixtureSnapshot {
+                slot,
+                accounts,
+                staker: self.stake_o_matic.roles.staker.to_bytes().to_vec(),
+                stake_pool_address: self.stake_pool_address(),
+                validators: self
+                    .validators
+                    .iter()
+                    .map(|vap| (vap.identity, vap.vote_address))
+                    .collect(),
+                baseline_stake_amount: self.baseline_stake_amount,
+                deposit_amount: self.deposit_amount,
+                token_accounts: self.token_accounts.clone(),
+            }
+        }
+
+        /// Start a new test validator holding the accounts of `snapshot`, from its slot
+        fn restore(snapshot: &PoolFixtureSnapshot) -> Self {
+            let mut test_validator_genesis = test_validator_genesis();
+            test_validator_genesis
+                .warp_slot(snapshot.slot)
+                .add_accounts(snapshot.accounts.iter().map(|(address, account)| {
+                    (*address, AccountSharedData::from(account.clone()))
+                }));
+            let (test_validator, _mint_keypair) = test_validator_genesis.start();
+            let (rpc_client, _recent_blockhash, _fee_calculator) = test_validator.rpc_client();
+            let stake_rent_exemption =
+                get_minimum_stake_balance_for_rent_exemption(&rpc_client).unwrap();
+
+            let stake_o_matic = new_stake_o_matic(
+                &rpc_client,
+                Keypair::from_bytes(&snapshot.staker).unwrap(),
+                snapshot.stake_pool_address,
+                snapshot.baseline_stake_amount,
+            );
+
+            Self {
+                _test_validator: test_validator,
+                rpc_client,
+                stake_o_matic,
+                validators: snapshot
+                    .validators
+                    .iter()
+                    .map(|(identity, vote_address)| ValidatorAddressPair {
+                        identity: *identity,
+                        vote_address: *vote_address,
+                    })
+                    .collect(),
+                baseline_stake_amount: snapshot.baseline_stake_amount,
+                deposit_amount: snapshot.deposit_amount,
+                stake_rent_exemption,
+                token_accounts: snapshot.token_accounts.clone(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_validator_stake() {
+        let mut fixture = PoolFixtureBuilder::new(3).build();
+        let validators = fixture.validators.len() as u64;
 
-        // ===========================================================
-        stake_o_matic.epoch_update(&rpc_client).unwrap();
         info!("All validators to baseline");
         uniform_stake_pool_apply(
-            &mut stake_o_matic,
-            &rpc_client,
-            &validators,
+            &mut fixture.stake_o_matic,
+            &fixture.rpc_client,
+            &fixture.validators,
             ValidatorStakeState::Baseline,
-            baseline_stake_amount,
-            total_stake_amount_plus_min - baseline_stake_amount * validators.len() as u64,
+            fixture.baseline_stake_amount,
+            fixture.min_reserve_stake_balance() + fixture.deposit_amount
+                - fixture.baseline_stake_amount * validators,
         );
 
-        // ===========================================================
         info!("All the validators to bonus stake level");
         uniform_stake_pool_apply(
-            &mut stake_o_matic,
-            &rpc_client,
-            &validators,
+            &mut fixture.stake_o_matic,
+            &fixture.rpc_client,
+            &fixture.validators,
             ValidatorStakeState::Bonus,
-            total_stake_amount / validators.len() as u64,
-            MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption,
+            fixture.deposit_amount / validators,
+            fixture.min_reserve_stake_balance(),
+        );
+    }
+
+    #[test]
+    fn test_different_stake_for_each_validator() {
+        let mut fixture = PoolFixtureBuilder::new(3).build();
+        let validators = fixture.validators.len() as u64;
+        uniform_stake_pool_apply(
+            &mut fixture.stake_o_matic,
+            &fixture.rpc_client,
+            &fixture.validators,
+            ValidatorStakeState::Bonus,
+            fixture.deposit_amount / validators,
+            fixture.min_reserve_stake_balance(),
         );
 
-        // ===========================================================
-        info!("Different stake for each validator");
         let desired_validator_stake = vec![
-            ValidatorStake {
-                identity: validators[0].identity,
-                vote_address: validators[0].vote_address,
-                stake_state: ValidatorStakeState::None,
-            },
-            ValidatorStake {
-                identity: validators[1].identity,
-                vote_address: validators[1].vote_address,
-                stake_state: ValidatorStakeState::Baseline,
-            },
-            ValidatorStake {
-                identity: validators[2].identity,
-                vote_address: validators[2].vote_address,
-                stake_state: ValidatorStakeState::Bonus,
-            },
+            ValidatorStake::new(
+                fixture.validators[0].identity,
+                fixture.validators[0].vote_address,
+                ValidatorStakeState::None,
+            ),
+            ValidatorStake::new(
+                fixture.validators[1].identity,
+                fixture.validators[1].vote_address,
+                ValidatorStakeState::Baseline,
+            ),
+            ValidatorStake::new(
+                fixture.validators[2].identity,
+                fixture.validators[2].vote_address,
+                ValidatorStakeState::Bonus,
+            ),
         ];
+        fixture.apply(&desired_validator_stake);
+        fixture.wait_for_next_epoch();
+        fixture.apply(&desired_validator_stake);
 
-        stake_o_matic
-            .apply(&rpc_client, false, &desired_validator_stake)
-            .unwrap();
-        let _epoch = wait_for_next_epoch(&rpc_client).unwrap();
-        stake_o_matic
-            .apply(&rpc_client, false, &desired_validator_stake)
-            .unwrap();
-
-        info!("Check after first epoch");
         // after the first epoch, validators 0 and 1 are at their target levels but validator 2
         // needs one more epoch for the additional bonus stake to arrive
-        for (validator, expected_sol_balance) in validators.iter().zip(&[0., 10., 110.]) {
-            assert_eq!(
-                sol_to_lamports(*expected_sol_balance),
-                validator_stake_balance(&rpc_client, &stake_pool.pubkey(), validator),
-                "stake balance mismatch for validator {}, expected {}",
-                validator.identity,
-                expected_sol_balance
-            );
-        }
-
         assert_eq!(
-            rpc_client
-                .get_balance(&stake_o_matic.stake_pool.reserve_stake)
-                .unwrap(),
-            MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption,
+            fixture.validator_stake_balances(),
+            vec![0., 10., 110.]
+                .into_iter()
+                .map(sol_to_lamports)
+                .collect::<Vec<_>>()
         );
-
-        info!("Check after second epoch");
-        let _epoch = wait_for_next_epoch(&rpc_client).unwrap();
-        stake_o_matic
-            .apply(&rpc_client, false, &desired_validator_stake)
-            .unwrap();
-
         assert_eq!(
-            rpc_client
-                .get_balance(&stake_o_matic.stake_pool.reserve_stake)
-                .unwrap(),
-            MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption,
+            fixture.reserve_stake_balance(),
+            fixture.min_reserve_stake_balance()
         );
 
+        fixture.wait_for_next_epoch();
+        fixture.apply(&desired_validator_stake);
+
         // after the second epoch, validator 2 is now has all the bonus stake
-        for (validator, expected_sol_balance) in validators.iter().zip(&[0., 10., 320.]) {
-            assert_eq!(
-                sol_to_lamports(*expected_sol_balance),
-                validator_stake_balance(&rpc_client, &stake_pool.pubkey(), validator),
-                "stake balance mismatch for validator {}",
-                validator.identity
-            );
-        }
+        assert_eq!(
+            fixture.validator_stake_balances(),
+            vec![0., 10., 320.]
+                .into_iter()
+                .map(sol_to_lamports)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            fixture.reserve_stake_balance(),
+            fixture.min_reserve_stake_balance()
+        );
+    }
 
-        // ===========================================================
-        info!("remove all validators");
+    #[test]
+    fn test_remove_validators() {
+        let mut fixture = PoolFixtureBuilder::new(3).build();
+        let validators = fixture.validators.len() as u64;
+        uniform_stake_pool_apply(
+            &mut fixture.stake_o_matic,
+            &fixture.rpc_client,
+            &fixture.validators,
+            ValidatorStakeState::Baseline,
+            fixture.baseline_stake_amount,
+            fixture.min_reserve_stake_balance() + fixture.deposit_amount
+                - fixture.baseline_stake_amount * validators,
+        );
+        let snapshot = fixture.snapshot();
 
+        info!("Remove all validators");
+        let withdraw_authority = find_withdraw_authority_program_address(
+            &spl_stake_pool::id(),
+            &fixture.stake_pool_address(),
+        )
+        .0;
         // deactivate all validator stake and remove from pool
-        stake_o_matic.apply(&rpc_client, false, &[]).unwrap();
-        let _epoch = wait_for_next_epoch(&rpc_client).unwrap();
+        fixture.apply(&[]);
+        fixture.wait_for_next_epoch();
         // withdraw removed validator stake into the staker
-        stake_o_matic.apply(&rpc_client, false, &[]).unwrap();
+        fixture.apply(&[]);
         // all stake has been returned to the reserve account
-        assert_reserve_account_only(
-            MIN_STAKE_RESERVE_BALANCE + stake_rent_exemption + total_stake_amount,
+        let (all_stake, all_stake_total_amount) =
+            get_all_stake(&fixture.rpc_client, withdraw_authority).unwrap();
+        assert_eq!(all_stake.len(), 1);
+        assert!(all_stake.contains(&fixture.stake_o_matic.stake_pool.reserve_stake));
+        assert_eq!(
+            all_stake_total_amount,
+            fixture.min_reserve_stake_balance() + fixture.deposit_amount
         );
         // staker has recovered all of their SOL from stake accounts
         assert_eq!(
-            num_stake_accounts(&rpc_client, stake_o_matic.authorized_staker.pubkey()),
+            num_stake_accounts(
+                &fixture.rpc_client,
+                fixture.stake_o_matic.roles.staker.pubkey()
+            ),
             0
         );
+
+        info!("Remove a single validator from the restored pool");
+        let mut fixture = PoolFixture::restore(&snapshot);
+        fixture.validators.remove(0);
+        let desired_validator_stake =
+            desired_stake_of(&fixture.validators, ValidatorStakeState::Baseline);
+        fixture.apply(&desired_validator_stake);
+        fixture.wait_for_next_epoch();
+        fixture.apply(&desired_validator_stake);
+        assert_eq!(
+            fixture.validator_stake_balances(),
+            vec![fixture.baseline_stake_amount; 2]
+        );
+        assert_eq!(
+            fixture.reserve_stake_balance(),
+            fixture.min_reserve_stake_balance() + fixture.deposit_amount
+                - fixture.baseline_stake_amount * (validators - 1),
+        );
+    }
+
+    #[test]
+    fn test_reserve_exhaustion() {
+        // Enough stake for two and a half validators at the baseline stake level
+        let mut fixture = PoolFixtureBuilder::new(3)
+            .deposit_amount(sol_to_lamports(25.))
+            .build();
+
+        let desired_validator_stake =
+            desired_stake_of(&fixture.validators, ValidatorStakeState::Baseline);
+        let notes = fixture.apply(&desired_validator_stake);
+        assert!(notes
+            .iter()
+            .any(|note| note.starts_with("Reserve stake is insufficient for 1 validators")));
+        assert_eq!(
+            fixture
+                .stake_o_matic
+                .summary
+                .as_ref()
+                .unwrap()
+                .reserve_shortfall_lamports
+                .len(),
+            1
+        );
+        // The reserve never falls below its minimum
+        assert!(fixture.reserve_stake_balance() >= fixture.min_reserve_stake_balance());
+
+        fixture.wait_for_next_epoch();
+        fixture.apply(&desired_validator_stake);
+        assert_eq!(
+            fixture.reserve_stake_balance(),
+            fixture.min_reserve_stake_balance()
+        );
+        let mut balances = fixture.validator_stake_balances();
+        balances.sort_unstable();
+        assert_eq!(
+            balances,
+            vec![
+                sol_to_lamports(5.),
+                fixture.baseline_stake_amount,
+                fixture.baseline_stake_amount
+            ]
+        );
+    }
+
+    #[test]
+    fn test_busy_validators() {
+        let mut fixture = PoolFixtureBuilder::new(3).build();
+        let validators = fixture.validators.len() as u64;
+
+        info!("Start moving the validators to baseline");
+        fixture.apply(&desired_stake_of(
+            &fixture.validators,
+            ValidatorStakeState::Baseline,
+        ));
+        let before = fixture.export();
+        assert!(before
+            .validators
+            .iter()
+            .all(|validator| validator.transient_stake_account_lamports > 0));
+
+        // Validators with transient stake are busy until it merges at the next epoch, so a new
+        // desired stake level within the same epoch leaves their stake untouched
+        info!("Move the busy validators to bonus within the same epoch");
+        let bonus_stake = desired_stake_of(&fixture.validators, ValidatorStakeState::Bonus);
+        fixture.apply(&bonus_stake);
+        assert_eq!(fixture.export().validators, before.validators);
+
+        fixture.wait_for_next_epoch();
+        fixture
+            .stake_o_matic
+            .epoch_update(&fixture.rpc_client)
+            .unwrap();
+        uniform_stake_pool_apply(
+            &mut fixture.stake_o_matic,
+            &fixture.rpc_client,
+            &fixture.validators,
+            ValidatorStakeState::Bonus,
+            fixture.deposit_amount / validators,
+            fixture.min_reserve_stake_balance(),
+        );
     }
 }
@@ -0,0 +1,259 @@
+//! The stake pool program the bot manages. Only the layout of spl-stake-pool 0.2, whose
+//! instruction builders are linked into the bot, is supported; `detect` refuses the others
+use {
+    borsh::BorshDeserialize,
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{instruction::Instruction, pubkey::Pubkey},
+    spl_stake_pool::{
+        find_stake_program_address, find_transient_stake_program_address,
+        find_withdraw_authority_program_address, instruction,
+        state::{AccountType, StakePool, ValidatorList},
+        MAX_VALIDATORS_TO_UPDATE,
+    },
+    std::{error, fmt},
+};
+
+/// Versions of the stake pool program that the bot can manage
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgramVersion {
+    /// spl-stake-pool 0.2, the version of the instruction builders linked into the bot
+    V0_2,
+}
+
+impl fmt::Display for ProgramVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramVersion::V0_2 => write!(f, "0.2"),
+        }
+    }
+}
+
+/// Identify the program version from the layout of a stake pool account
+pub fn detect_layout(data: &[u8]) -> Result<ProgramVersion, String> {
+    match StakePool::try_from_slice(data) {
+        Ok(stake_pool) if stake_pool.account_type == AccountType::StakePool => {
+            Ok(ProgramVersion::V0_2)
+        }
+        Ok(_) => Err("Account is not an initialized stake pool".into()),
+        Err(_) => {
+            // Later program versions append fields to the stake pool account
+            let mut remaining = data;
+            match StakePool::deserialize(&mut remaining) {
+                Ok(stake_pool) if stake_pool.account_type == AccountType::StakePool => {
+                    Err(format!(
+                        "Stake pool account has {} bytes beyond the layout of version {}, the \
+                         deployed program is newer than this bot supports",
+                        remaining.len(),
+                        ProgramVersion::V0_2
+                    ))
+                }
+                _ => Err("Unrecognized stake pool account layout".into()),
+            }
+        }
+    }
+}
+
+/// Detect the version of the stake pool program managing `stake_pool_address`
+pub fn detect(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    stake_pool_address: &Pubkey,
+) -> Result<ProgramVersion, Box<dyn error::Error>> {
+    let program_account = rpc_client
+        .get_account(program_id)
+        .map_err(|err| format!("Unable to get stake pool program {}: {}", program_id, err))?;
+    if !program_account.executable {
+        return Err(format!("Stake pool program {} is not executable", program_id).into());
+    }
+
+    let account = rpc_client.get_account(stake_pool_address)?;
+    if account.owner != *program_id {
+        return Err(format!(
+            "Stake pool {} is owned by {}, not the stake pool program {}",
+            stake_pool_address, account.owner, program_id
+        )
+        .into());
+    }
+    Ok(detect_layout(&account.data)
+        .map_err(|err| format!("Stake pool {}: {}", stake_pool_address, err))?)
+}
+
+// The `*_with_vote` instruction builders of spl-stake-pool always use the default program ID, the
+// following equivalents accept the ID of the deployed stake pool program
+
+pub fn create_validator_stake_account_with_vote(
+    program_id: &Pubkey,
+    stake_pool_address: &Pubkey,
+    staker: &Pubkey,
+    funder: &Pubkey,
+    vote_account_address: &Pubkey,
+) -> Instruction {
+    let stake_account =
+        find_stake_program_address(program_id, vote_account_address, stake_pool_address).0;
+    instruction::create_validator_stake_account(
+        program_id,
+        stake_pool_address,
+        staker,
+        funder,
+        &stake_account,
+        vote_account_address,
+    )
+}
+
+pub fn add_validator_to_pool_with_vote(
+    program_id: &Pubkey,
+    stake_pool: &StakePool,
+    stake_pool_address: &Pubkey,
+    vote_account_address: &Pubkey,
+) -> Instruction {
+    instruction::add_validator_to_pool(
+        program_id,
+        stake_pool_address,
+        &stake_pool.staker,
+        &find_withdraw_authority_program_address(program_id, stake_pool_address).0,
+        &stake_pool.validator_list,
+        &find_stake_program_address(program_id, vote_account_address, stake_pool_address).0,
+    )
+}
+
+pub fn remove_validator_from_pool_with_vote(
+    program_id: &Pubkey,
+    stake_pool: &StakePool,
+    stake_pool_address: &Pubkey,
+    vote_account_address: &Pubkey,
+    new_stake_account_authority: &Pubkey,
+) -> Instruction {
+    instruction::remove_validator_from_pool(
+        program_id,
+        stake_pool_address,
+        &stake_pool.staker,
+        &find_withdraw_authority_program_address(program_id, stake_pool_address).0,
+        new_stake_account_authority,
+        &stake_pool.validator_list,
+        &find_stake_program_address(program_id, vote_account_address, stake_pool_address).0,
+        &find_transient_stake_program_address(program_id, vote_account_address, stake_pool_address)
+            .0,
+    )
+}
+
+pub fn increase_validator_stake_with_vote(
+    program_id: &Pubkey,
+    stake_pool: &StakePool,
+    stake_pool_address: &Pubkey,
+    vote_account_address: &Pubkey,
+    lamports: u64,
+) -> Instruction {
+    instruction::increase_validator_stake(
+        program_id,
+        stake_pool_address,
+        &stake_pool.staker,
+        &find_withdraw_authority_program_address(program_id, stake_pool_address).0,
+        &stake_pool.validator_list,
+        &stake_pool.reserve_stake,
+        &find_transient_stake_program_address(program_id, vote_account_address, stake_pool_address)
+            .0,
+        vote_account_address,
+        lamports,
+    )
+}
+
+pub fn decrease_validator_stake_with_vote(
+    program_id: &Pubkey,
+    stake_pool: &StakePool,
+    stake_pool_address: &Pubkey,
+    vote_account_address: &Pubkey,
+    lamports: u64,
+) -> Instruction {
+    instruction::decrease_validator_stake(
+        program_id,
+        stake_pool_address,
+        &stake_pool.staker,
+        &find_withdraw_authority_program_address(program_id, stake_pool_address).0,
+        &stake_pool.validator_list,
+        &find_stake_program_address(program_id, vote_account_address, stake_pool_address).0,
+        &find_transient_stake_program_address(program_id, vote_account_address, stake_pool_address)
+            .0,
+        lamports,
+    )
+}
+
+pub fn update_stake_pool(
+    program_id: &Pubkey,
+    stake_pool: &StakePool,
+    validator_list: &ValidatorList,
+    stake_pool_address: &Pubkey,
+    no_merge: bool,
+) -> Vec<Instruction> {
+    let withdraw_authority =
+        find_withdraw_authority_program_address(program_id, stake_pool_address).0;
+    let vote_accounts = validator_list
+        .validators
+        .iter()
+        .map(|item| item.vote_account_address)
+        .collect::<Vec<_>>();
+
+    let mut instructions = vec![];
+    for (i, accounts_chunk) in vote_accounts.chunks(MAX_VALIDATORS_TO_UPDATE).enumerate() {
+        instructions.push(instruction::update_validator_list_balance(
+            program_id,
+            stake_pool_address,
+            &withdraw_authority,
+            &stake_pool.validator_list,
+            &stake_pool.reserve_stake,
+            accounts_chunk,
+            (i * MAX_VALIDATORS_TO_UPDATE) as u32,
+            no_merge,
+        ));
+    }
+    instructions.push(instruction::update_stake_pool_balance(
+        program_id,
+        stake_pool_address,
+        &withdraw_authority,
+        &stake_pool.validator_list,
+        &stake_pool.reserve_stake,
+        &stake_pool.manager_fee_account,
+        &stake_pool.pool_mint,
+    ));
+    instructions
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, borsh::BorshSerialize};
+
+    // Serialized stake pool account, as created by version 0.2
+    fn stake_pool_data(account_type: AccountType) -> Vec<u8> {
+        StakePool {
+            account_type,
+            ..StakePool::default()
+        }
+        .try_to_vec()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_detect_layout() {
+        let data = stake_pool_data(AccountType::StakePool);
+        assert_eq!(detect_layout(&data), Ok(ProgramVersion::V0_2));
+
+        let mut extended_data = data.clone();
+        extended_data.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            detect_layout(&extended_data),
+            Err(
+                "Stake pool account has 8 bytes beyond the layout of version 0.2, the deployed \
+                 program is newer than this bot supports"
+                    .to_string()
+            )
+        );
+
+        assert_eq!(
+            detect_layout(&stake_pool_data(AccountType::Uninitialized)),
+            Err("Account is not an initialized stake pool".to_string())
+        );
+        assert_eq!(
+            detect_layout(&data[..data.len() / 2]),
+            Err("Unrecognized stake pool account layout".to_string())
+        );
+    }
+}
@@ -1,7 +1,12 @@
 use {
     crate::{
         generic_stake_pool::*,
-        rpc_client_utils::{get_all_stake, send_and_confirm_transactions},
+        memo::TransactionMemo,
+        rpc_client_utils::get_all_stake,
+        run_status::guardrail,
+        small_pool::SmallPool,
+        stake_account_check::{self, ExpectedStakeAccount},
+        transaction_executor::TransactionExecutor,
     },
     log::*,
     solana_client::{rpc_client::RpcClient, rpc_response::StakeActivationState},
@@ -10,35 +15,75 @@ use {
         native_token::{Sol, LAMPORTS_PER_SOL},
         pubkey::Pubkey,
         signature::{Keypair, Signer},
-        transaction::Transaction,
     },
     solana_stake_program::{stake_instruction, stake_state::StakeState},
-    std::{collections::HashSet, error},
+    std::{
+        collections::{HashMap, HashSet},
+        error, mem,
+    },
 };
 
 // Minimum amount of lamports in a stake pool account
 pub const MIN_STAKE_ACCOUNT_BALANCE: u64 = LAMPORTS_PER_SOL;
 
-// Don't bother adjusting stake if less than this amount of lamports will be affected
-// (must be >= MIN_STAKE_ACCOUNT_BALANCE)
-const MIN_STAKE_CHANGE_AMOUNT: u64 = MIN_STAKE_ACCOUNT_BALANCE;
-
 #[derive(Debug)]
 pub struct StakePool {
     authorized_staker: Keypair,
     baseline_stake_amount: u64,
+    min_stake_change: MinStakeChange,
+    stake_tolerance: StakeTolerance,
     reserve_stake_address: Pubkey,
     min_reserve_stake_balance: u64,
+    min_stake_account_balance: u64,
+    small_pool: Option<SmallPool>,
+    summary: Option<StakePoolSummary>,
+    run_id: Option<String>,
+    transaction_executor: Box<dyn TransactionExecutor>,
+}
+
+fn min_stake_account_balance(small_pool: Option<SmallPool>) -> u64 {
+    // In small pool mode the minimum stake follows the minimum delegation of the cluster
+    small_pool
+        .map(|small_pool| small_pool.min_stake_lamports)
+        .unwrap_or(MIN_STAKE_ACCOUNT_BALANCE)
 }
 
+/// Smallest stake movement the pool can make. Every movement is split into a transient stake
+/// account, which must be rent-exempt and hold the minimum stake
+pub fn min_stake_change_lamports(
+    rpc_client: &RpcClient,
+    small_pool: Option<SmallPool>,
+) -> Result<u64, Box<dyn error::Error>> {
+    let stake_rent_exemption =
+        rpc_client.get_minimum_balance_for_rent_exemption(mem::size_of::<StakeState>())?;
+    Ok(stake_rent_exemption + min_stake_account_balance(small_pool))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn new(
-    _rpc_client: &RpcClient,
+    rpc_client: &RpcClient,
     authorized_staker: Keypair,
     baseline_stake_amount: u64,
     reserve_stake_address: Pubkey,
     min_reserve_stake_balance: u64,
+    min_stake_change: MinStakeChange,
+    stake_tolerance: StakeTolerance,
+    small_pool: Option<SmallPool>,
+    transaction_executor: Box<dyn TransactionExecutor>,
 ) -> Result<StakePool, Box<dyn error::Error>> {
-    if baseline_stake_amount < MIN_STAKE_CHANGE_AMOUNT {
+    let min_stake_account_balance = min_stake_account_balance(small_pool);
+
+    let min_stake_change_lamports = min_stake_change_lamports(rpc_client, small_pool)?;
+    if min_stake_change.lamports < min_stake_change_lamports {
+        return Err(format!(
+            "minimum stake change amount too small: {}. Minimum is {}",
+            Sol(min_stake_change.lamports),
+            Sol(min_stake_change_lamports)
+        )
+        .into());
+    }
+
+    if baseline_stake_amount < min_stake_change.lamports {
         return Err(format!(
             "baseline stake amount too small: {}",
             Sol(baseline_stake_amount)
@@ -46,7 +91,7 @@ pub fn new(
         .into());
     }
 
-    if min_reserve_stake_balance < MIN_STAKE_ACCOUNT_BALANCE {
+    if min_reserve_stake_balance < min_stake_account_balance {
         return Err(format!(
             "minimum reserve stake balance is too small: {}",
             Sol(min_reserve_stake_balance)
@@ -57,8 +102,15 @@ pub fn new(
     Ok(StakePool {
         authorized_staker,
         baseline_stake_amount,
+        min_stake_change,
+        stake_tolerance,
         reserve_stake_address,
         min_reserve_stake_balance,
+        min_stake_account_balance,
+        small_pool,
+        summary: None,
+        run_id: None,
+        transaction_executor,
     })
 }
 
@@ -95,6 +147,10 @@ impl GenericStakePool for StakePool {
         dry_run: bool,
         desired_validator_stake: &[ValidatorStake],
     ) -> Result<(Vec<String>, bool), Box<dyn error::Error>> {
+        let memo = TransactionMemo::new(
+            self.run_id.as_deref().unwrap_or("-"),
+            rpc_client.get_epoch_info()?.epoch,
+        );
         let mut inuse_stake_addresses = HashSet::new();
         inuse_stake_addresses.insert(self.reserve_stake_address);
 
@@ -126,20 +182,47 @@ impl GenericStakePool for StakePool {
         let (all_stake_addresses, all_stake_total_amount) =
             get_all_stake(rpc_client, self.authorized_staker.pubkey())?;
 
+        if let Some(reserve_stake_account) = rpc_client
+            .get_account_with_commitment(&self.reserve_stake_address, rpc_client.commitment())?
+            .value
+        {
+            stake_account_check::check(
+                &self.reserve_stake_address,
+                &reserve_stake_account,
+                &ExpectedStakeAccount::authorized(self.authorized_staker.pubkey()),
+            )
+            .map_err(|err| guardrail(format!("Reserve stake account: {}", err)))?;
+        }
+
+        info!("Check the authorities of the validator stake accounts");
+        let mut busy_validators = check_validator_stake_accounts(
+            rpc_client,
+            &self.authorized_staker,
+            desired_validator_stake,
+        )?;
+        let checked_validator_stake = desired_validator_stake
+            .iter()
+            .filter(|vs| !busy_validators.contains(&vs.identity))
+            .cloned()
+            .collect::<Vec<_>>();
+
         info!("Merge orphaned stake into the reserve");
         merge_orphaned_stake_accounts(
             rpc_client,
+            self.transaction_executor.as_ref(),
+            &memo,
             &self.authorized_staker,
             &all_stake_addresses - &inuse_stake_addresses,
             self.reserve_stake_address,
         )?;
 
         info!("Merge transient stake back into either the reserve or validator stake");
-        let mut busy_validators = HashSet::new();
         merge_transient_stake_accounts(
             rpc_client,
+            self.transaction_executor.as_ref(),
+            &memo,
             &self.authorized_staker,
-            desired_validator_stake,
+            &checked_validator_stake,
             self.reserve_stake_address,
             &mut busy_validators,
         )?;
@@ -147,10 +230,13 @@ impl GenericStakePool for StakePool {
         info!("Create validator stake accounts if needed");
         create_validator_stake_accounts(
             rpc_client,
+            self.transaction_executor.as_ref(),
+            &memo,
             &self.authorized_staker,
-            desired_validator_stake,
+            &checked_validator_stake,
             self.reserve_stake_address,
             self.min_reserve_stake_balance,
+            self.min_stake_account_balance,
             &mut busy_validators,
         )?;
 
@@ -159,7 +245,7 @@ impl GenericStakePool for StakePool {
 
         info!("Total stake pool balance: {}", Sol(total_stake_amount));
 
-        let total_min_stake_amount = min_stake_node_count * MIN_STAKE_ACCOUNT_BALANCE;
+        let total_min_stake_amount = min_stake_node_count * self.min_stake_account_balance;
         info!("Min node count: {}", min_stake_node_count);
         info!("Min stake amount: {}", Sol(total_min_stake_amount));
 
@@ -172,7 +258,7 @@ impl GenericStakePool for StakePool {
         );
 
         if total_stake_amount < total_baseline_stake_amount {
-            return Err("Not enough stake to cover the baseline".into());
+            return Err(guardrail("Not enough stake to cover the baseline"));
         }
 
         info!("Bonus node count: {}", bonus_stake_node_count);
@@ -183,38 +269,86 @@ impl GenericStakePool for StakePool {
             Sol(total_bonus_stake_amount)
         );
 
-        let bonus_stake_amount = if bonus_stake_node_count == 0 {
-            0
-        } else {
-            total_bonus_stake_amount / (bonus_stake_node_count as u64)
-        };
+        let total_bonus_weight = total_bonus_weight(desired_validator_stake);
+        let bonus_stake_amounts =
+            bonus_stake_amounts(total_bonus_stake_amount, desired_validator_stake);
+        let bonus_stake_amount = bonus_stake_amount(
+            total_bonus_stake_amount,
+            total_bonus_weight,
+            DEFAULT_BONUS_WEIGHT,
+        );
 
         info!("Bonus stake amount: {}", Sol(bonus_stake_amount));
 
-        let notes = vec![
+        let mut notes = vec![
             format!("Baseline stake amount: {}", Sol(self.baseline_stake_amount)),
             format!("Bonus stake amount: {}", Sol(bonus_stake_amount)),
         ];
+        if let Some(small_pool) = &self.small_pool {
+            let stake_rent_exemption =
+                rpc_client.get_minimum_balance_for_rent_exemption(mem::size_of::<StakeState>())?;
+            let warnings = small_pool.warnings(
+                total_stake_amount,
+                all_stake_addresses.len(),
+                stake_rent_exemption,
+            );
+            for warning in &warnings {
+                warn!("{}", warning);
+            }
+            notes.extend(warnings);
+        }
 
         if dry_run {
             return Ok((notes, true));
         }
 
-        Ok((
-            notes,
-            distribute_validator_stake(
-                rpc_client,
-                &self.authorized_staker,
-                desired_validator_stake
-                    .iter()
-                    .filter(|vs| !busy_validators.contains(&vs.identity))
-                    .cloned(),
-                self.reserve_stake_address,
-                self.min_reserve_stake_balance,
-                self.baseline_stake_amount,
-                bonus_stake_amount,
-            )?,
-        ))
+        let summary = distribute_validator_stake(
+            rpc_client,
+            self.transaction_executor.as_ref(),
+            &memo,
+            &self.authorized_staker,
+            desired_validator_stake
+                .iter()
+                .filter(|vs| !busy_validators.contains(&vs.identity))
+                .cloned(),
+            self.reserve_stake_address,
+            self.min_reserve_stake_balance,
+            self.min_stake_account_balance,
+            self.baseline_stake_amount,
+            &bonus_stake_amounts,
+            self.min_stake_change,
+            self.stake_tolerance,
+        )?;
+        let ok = summary.failed_transactions == 0;
+        self.summary = Some(summary);
+        Ok((notes, ok))
+    }
+
+    fn set_run_id(&mut self, run_id: &str) {
+        self.run_id = Some(run_id.to_string());
+    }
+
+    fn summary(&self) -> Option<StakePoolSummary> {
+        self.summary.clone()
+    }
+
+    fn effective_config(&self) -> Vec<(String, String)> {
+        vec![
+            (
+                "baseline_stake_amount",
+                self.baseline_stake_amount.to_string(),
+            ),
+            ("min_stake_change", format!("{:?}", self.min_stake_change)),
+            ("stake_tolerance", format!("{:?}", self.stake_tolerance)),
+            (
+                "min_reserve_stake_balance",
+                self.min_reserve_stake_balance.to_string(),
+            ),
+            ("small_pool", format!("{:?}", self.small_pool)),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
     }
 }
 
@@ -243,14 +377,68 @@ fn get_available_stake_balance(
     }
 }
 
+/// Identities of the validators whose stake or transient stake account no longer has the staker
+/// as its authorities, or is delegated to another vote account, such as after a manual
+/// intervention. Their stake is left untouched
+fn check_validator_stake_accounts(
+    rpc_client: &RpcClient,
+    authorized_staker: &Keypair,
+    desired_validator_stake: &[ValidatorStake],
+) -> Result<HashSet<Pubkey>, Box<dyn error::Error>> {
+    let mut mismatched_validators = HashSet::new();
+    for ValidatorStake {
+        identity,
+        vote_address,
+        ..
+    } in desired_validator_stake
+    {
+        let expected = ExpectedStakeAccount::authorized(authorized_staker.pubkey())
+            .delegated_to(*vote_address);
+        for stake_address in &[
+            validator_stake_address(authorized_staker.pubkey(), *vote_address),
+            validator_transient_stake_address(authorized_staker.pubkey(), *vote_address),
+        ] {
+            if let Some(stake_account) = rpc_client
+                .get_account_with_commitment(stake_address, rpc_client.commitment())?
+                .value
+            {
+                if let Err(err) =
+                    stake_account_check::check(stake_address, &stake_account, &expected)
+                {
+                    warn!("Validator {} skipped: {}", identity, err);
+                    mismatched_validators.insert(*identity);
+                }
+            }
+        }
+    }
+    Ok(mismatched_validators)
+}
+
 fn merge_orphaned_stake_accounts(
     rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    memo: &TransactionMemo,
     authorized_staker: &Keypair,
     source_stake_addresses: HashSet<Pubkey>,
     reserve_stake_address: Pubkey,
 ) -> Result<(), Box<dyn error::Error>> {
     let mut transactions = vec![];
     for stake_address in source_stake_addresses {
+        // The staker is matched by `get_all_stake`, the withdrawer may have been changed since
+        if let Some(stake_account) = rpc_client
+            .get_account_with_commitment(&stake_address, rpc_client.commitment())?
+            .value
+        {
+            if let Err(err) = stake_account_check::check(
+                &stake_address,
+                &stake_account,
+                &ExpectedStakeAccount::authorized(authorized_staker.pubkey()),
+            ) {
+                warn!("{}, not merging into the reserve", err);
+                continue;
+            }
+        }
+
         let stake_activation = rpc_client
             .get_stake_activation(stake_address, None)
             .map_err(|err| {
@@ -263,23 +451,27 @@ fn merge_orphaned_stake_accounts(
         match stake_activation.state {
             StakeActivationState::Activating | StakeActivationState::Deactivating => {}
             StakeActivationState::Active => {
-                transactions.push(Transaction::new_with_payer(
+                transactions.push(memo.transaction(
                     &[stake_instruction::deactivate_stake(
                         &stake_address,
                         &authorized_staker.pubkey(),
                     )],
-                    Some(&authorized_staker.pubkey()),
+                    &authorized_staker.pubkey(),
+                    "orphan-merge",
+                    None,
                 ));
                 debug!("Deactivating stake {}", stake_address);
             }
             StakeActivationState::Inactive => {
-                transactions.push(Transaction::new_with_payer(
+                transactions.push(memo.transaction(
                     &stake_instruction::merge(
                         &reserve_stake_address,
                         &stake_address,
                         &authorized_staker.pubkey(),
                     ),
-                    Some(&authorized_staker.pubkey()),
+                    &authorized_staker.pubkey(),
+                    "orphan-merge",
+                    None,
                 ));
 
                 debug!(
@@ -290,7 +482,8 @@ fn merge_orphaned_stake_accounts(
         }
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
+    if !executor
+        .execute(rpc_client, transactions, &[authorized_staker])?
         .failed
         .is_empty()
     {
@@ -302,6 +495,8 @@ fn merge_orphaned_stake_accounts(
 
 fn merge_transient_stake_accounts(
     rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    memo: &TransactionMemo,
     authorized_staker: &Keypair,
     desired_validator_stake: &[ValidatorStake],
     reserve_stake_address: Pubkey,
@@ -352,13 +547,15 @@ fn merge_transient_stake_accounts(
                         &stake_account,
                         &transient_stake_account,
                     )? {
-                        transactions.push(Transaction::new_with_payer(
+                        transactions.push(memo.transaction(
                             &stake_instruction::merge(
                                 &stake_address,
                                 &transient_stake_address,
                                 &authorized_staker.pubkey(),
                             ),
-                            Some(&authorized_staker.pubkey()),
+                            &authorized_staker.pubkey(),
+                            "transient-merge",
+                            Some(vote_address),
                         ));
                         debug!("Merging active transient stake for {}", identity);
                     } else {
@@ -370,13 +567,15 @@ fn merge_transient_stake_accounts(
                     }
                 }
                 StakeActivationState::Inactive => {
-                    transactions.push(Transaction::new_with_payer(
+                    transactions.push(memo.transaction(
                         &stake_instruction::merge(
                             &reserve_stake_address,
                             &transient_stake_address,
                             &authorized_staker.pubkey(),
                         ),
-                        Some(&authorized_staker.pubkey()),
+                        &authorized_staker.pubkey(),
+                        "transient-merge",
+                        Some(vote_address),
                     ));
                     debug!("Merging inactive transient stake for {}", identity);
                 }
@@ -384,7 +583,8 @@ fn merge_transient_stake_accounts(
         }
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
+    if !executor
+        .execute(rpc_client, transactions, &[authorized_staker])?
         .failed
         .is_empty()
     {
@@ -427,12 +627,16 @@ fn stake_accounts_have_same_credits_observed(
     Ok(false)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_validator_stake_accounts(
     rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    memo: &TransactionMemo,
     authorized_staker: &Keypair,
     desired_validator_stake: &[ValidatorStake],
     reserve_stake_address: Pubkey,
     min_reserve_stake_balance: u64,
+    min_stake_account_balance: u64,
     busy_validators: &mut HashSet<Pubkey>,
 ) -> Result<(), Box<dyn error::Error>> {
     let mut reserve_stake_balance =
@@ -485,13 +689,15 @@ fn create_validator_stake_accounts(
                         "Validator {} busy due to inactive stake {}: {:?}",
                         identity, stake_address, stake_activation
                     );
-                    transactions.push(Transaction::new_with_payer(
+                    transactions.push(memo.transaction(
                         &[stake_instruction::delegate_stake(
                             &stake_address,
                             &authorized_staker.pubkey(),
                             vote_address,
                         )],
-                        Some(&authorized_staker.pubkey()),
+                        &authorized_staker.pubkey(),
+                        "account-creation",
+                        Some(vote_address),
                     ));
                     debug!(
                         "Activating stake account for validator {} ({})",
@@ -501,20 +707,20 @@ fn create_validator_stake_accounts(
                 }
             }
         } else {
-            if reserve_stake_balance < MIN_STAKE_ACCOUNT_BALANCE {
+            if reserve_stake_balance < min_stake_account_balance {
                 // Try again next epoch
                 warn!(
                     "Insufficient funds in reserve stake account to create stake account: {} required, {} balance",
-                    Sol(MIN_STAKE_ACCOUNT_BALANCE), Sol(reserve_stake_balance)
+                    Sol(min_stake_account_balance), Sol(reserve_stake_balance)
                 );
             } else {
                 // Create a stake account for the validator
-                reserve_stake_balance -= MIN_STAKE_ACCOUNT_BALANCE;
+                reserve_stake_balance -= min_stake_account_balance;
 
                 let mut instructions = stake_instruction::split_with_seed(
                     &reserve_stake_address,
                     &authorized_staker.pubkey(),
-                    MIN_STAKE_ACCOUNT_BALANCE,
+                    min_stake_account_balance,
                     &stake_address,
                     &authorized_staker.pubkey(),
                     &validator_stake_address_seed(*vote_address),
@@ -525,9 +731,11 @@ fn create_validator_stake_accounts(
                     vote_address,
                 ));
 
-                transactions.push(Transaction::new_with_payer(
+                transactions.push(memo.transaction(
                     &instructions,
-                    Some(&authorized_staker.pubkey()),
+                    &authorized_staker.pubkey(),
+                    "account-creation",
+                    Some(vote_address),
                 ));
                 debug!(
                     "Creating stake account for validator {} ({})",
@@ -539,7 +747,8 @@ fn create_validator_stake_accounts(
         }
     }
 
-    if !send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
+    if !executor
+        .execute(rpc_client, transactions, &[authorized_staker])?
         .failed
         .is_empty()
     {
@@ -549,15 +758,21 @@ fn create_validator_stake_accounts(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn distribute_validator_stake<V>(
     rpc_client: &RpcClient,
+    executor: &dyn TransactionExecutor,
+    memo: &TransactionMemo,
     authorized_staker: &Keypair,
     desired_validator_stake: V,
     reserve_stake_address: Pubkey,
     min_reserve_stake_balance: u64,
+    min_stake_account_balance: u64,
     baseline_stake_amount: u64,
-    bonus_stake_amount: u64,
-) -> Result<bool, Box<dyn error::Error>>
+    bonus_stake_amounts: &HashMap<Pubkey, u64>,
+    min_stake_change: MinStakeChange,
+    stake_tolerance: StakeTolerance,
+) -> Result<StakePoolSummary, Box<dyn error::Error>>
 where
     V: IntoIterator<Item = ValidatorStake>,
 {
@@ -575,6 +790,11 @@ where
         Sol(reserve_stake_balance)
     );
 
+    // Each split below creates a transient stake account, which must be rent-exempt and hold the
+    // minimum stake
+    let min_transient_stake_balance = min_stake_account_balance
+        + rpc_client.get_minimum_balance_for_rent_exemption(mem::size_of::<StakeState>())?;
+
     // Prioritize funding smaller stake accounts to maximize the number of accounts that will be
     // funded with the available reserve stake.
     let mut min_stake = vec![];
@@ -621,6 +841,7 @@ where
     baseline_stake.sort_by_key(|k| k.0);
     bonus_stake.sort_by_key(|k| k.0);
 
+    let mut summary = StakePoolSummary::default();
     let mut transactions = vec![];
     for (
         balance,
@@ -630,6 +851,7 @@ where
             identity,
             stake_state,
             vote_address,
+            ..
         },
     ) in min_stake
         .into_iter()
@@ -637,16 +859,24 @@ where
         .chain(bonus_stake)
     {
         let desired_balance = match stake_state {
-            ValidatorStakeState::None => MIN_STAKE_ACCOUNT_BALANCE,
+            ValidatorStakeState::None => min_stake_account_balance,
             ValidatorStakeState::Baseline => baseline_stake_amount,
-            ValidatorStakeState::Bonus => bonus_stake_amount,
+            ValidatorStakeState::Bonus => bonus_stake_amounts[&vote_address],
         };
         let transient_stake_address_seed = validator_transient_stake_address_seed(vote_address);
 
+        summary.add_stake(stake_state, balance);
+        let min_stake_change_amount = min_stake_change.amount(desired_balance);
+
+        let within_tolerance =
+            balance != desired_balance && stake_tolerance.contains(balance, desired_balance);
+
         #[allow(clippy::comparison_chain)]
-        let op_msg = if balance > desired_balance {
+        let op_msg = if within_tolerance {
+            "within tolerance".to_string()
+        } else if balance > desired_balance {
             let amount_to_remove = balance - desired_balance;
-            if amount_to_remove < MIN_STAKE_CHANGE_AMOUNT {
+            if amount_to_remove < min_stake_change_amount.max(min_transient_stake_balance) {
                 format!("not removing {} (amount too small)", Sol(amount_to_remove))
             } else {
                 let mut instructions = stake_instruction::split_with_seed(
@@ -662,18 +892,27 @@ where
                     &authorized_staker.pubkey(),
                 ));
 
-                transactions.push(Transaction::new_with_payer(
+                transactions.push(memo.transaction(
                     &instructions,
-                    Some(&authorized_staker.pubkey()),
+                    &authorized_staker.pubkey(),
+                    "rebalancing",
+                    Some(&vote_address),
                 ));
+                summary.stake_removed_lamports += amount_to_remove;
+                summary
+                    .off_target_lamports
+                    .insert(vote_address, amount_to_remove);
                 format!("removing {}", Sol(amount_to_remove))
             }
         } else if balance < desired_balance {
             let mut amount_to_add = desired_balance - balance;
 
-            if amount_to_add < MIN_STAKE_CHANGE_AMOUNT {
+            if amount_to_add < min_stake_change_amount {
                 format!("not adding {} (amount too small)", Sol(amount_to_add))
             } else {
+                summary
+                    .off_target_lamports
+                    .insert(vote_address, amount_to_add);
                 if amount_to_add > reserve_stake_balance {
                     trace!(
                         "note: amount_to_add > reserve_stake_balance: {} > {}",
@@ -683,7 +922,7 @@ where
                     amount_to_add = reserve_stake_balance;
                 }
 
-                if amount_to_add < MIN_STAKE_CHANGE_AMOUNT {
+                if amount_to_add < min_stake_change.lamports.max(min_transient_stake_balance) {
                     "reserve depleted".to_string()
                 } else {
                     reserve_stake_balance -= amount_to_add;
@@ -702,10 +941,13 @@ where
                         &vote_address,
                     ));
 
-                    transactions.push(Transaction::new_with_payer(
+                    transactions.push(memo.transaction(
                         &instructions,
-                        Some(&authorized_staker.pubkey()),
+                        &authorized_staker.pubkey(),
+                        "rebalancing",
+                        Some(&vote_address),
                     ));
+                    summary.stake_added_lamports += amount_to_add;
                     format!("adding {}", Sol(amount_to_add))
                 }
             }
@@ -727,21 +969,24 @@ where
         Sol(reserve_stake_balance)
     );
 
-    let ok = send_and_confirm_transactions(rpc_client, false, transactions, authorized_staker)?
+    summary.reserve_stake_lamports = reserve_stake_balance;
+
+    summary.failed_transactions = executor
+        .execute(rpc_client, transactions, &[authorized_staker])?
         .failed
-        .is_empty();
+        .len();
 
-    if !ok {
+    if summary.failed_transactions > 0 {
         error!("One or more transactions failed to execute")
     }
-    Ok(ok)
+    Ok(summary)
 }
 
 #[cfg(test)]
 mod test {
     use {
         super::*,
-        crate::rpc_client_utils::test::*,
+        crate::{rpc_client_utils::test::*, transaction_executor::RpcExecutor},
         solana_sdk::{
             clock::Epoch,
             epoch_schedule::{EpochSchedule, MINIMUM_SLOTS_PER_EPOCH},
@@ -777,11 +1022,7 @@ mod test {
     ) {
         let desired_validator_stake = validators
             .iter()
-            .map(|vap| ValidatorStake {
-                identity: vap.identity,
-                vote_address: vap.vote_address,
-                stake_state,
-            })
+            .map(|vap| ValidatorStake::new(vap.identity, vap.vote_address, stake_state))
             .collect::<Vec<_>>();
 
         stake_pool
@@ -898,6 +1139,13 @@ mod test {
             baseline_stake_amount,
             reserve_stake_address,
             min_reserve_stake_balance,
+            MinStakeChange {
+                lamports: min_stake_change_lamports(&rpc_client, None).unwrap(),
+                target_percentage: None,
+            },
+            StakeTolerance::default(),
+            None,
+            Box::new(RpcExecutor::default()),
         )
         .unwrap();
 
@@ -910,10 +1158,12 @@ mod test {
                 false,
                 &validators
                     .iter()
-                    .map(|vap| ValidatorStake {
-                        identity: vap.identity,
-                        vote_address: vap.vote_address,
-                        stake_state: ValidatorStakeState::None,
+                    .map(|vap| {
+                        ValidatorStake::new(
+                            vap.identity,
+                            vap.vote_address,
+                            ValidatorStakeState::None,
+                        )
                     })
                     .collect::<Vec<_>>(),
             )
@@ -972,21 +1222,21 @@ mod test {
         // ===========================================================
         info!("Different stake for each validator");
         let desired_validator_stake = vec![
-            ValidatorStake {
-                identity: validators[0].identity,
-                vote_address: validators[0].vote_address,
-                stake_state: ValidatorStakeState::None,
-            },
-            ValidatorStake {
-                identity: validators[1].identity,
-                vote_address: validators[1].vote_address,
-                stake_state: ValidatorStakeState::Baseline,
-            },
-            ValidatorStake {
-                identity: validators[2].identity,
-                vote_address: validators[2].vote_address,
-                stake_state: ValidatorStakeState::Bonus,
-            },
+            ValidatorStake::new(
+                validators[0].identity,
+                validators[0].vote_address,
+                ValidatorStakeState::None,
+            ),
+            ValidatorStake::new(
+                validators[1].identity,
+                validators[1].vote_address,
+                ValidatorStakeState::Baseline,
+            ),
+            ValidatorStake::new(
+                validators[2].identity,
+                validators[2].vote_address,
+                ValidatorStakeState::Bonus,
+            ),
         ];
 
         stake_pool
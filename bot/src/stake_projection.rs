@@ -0,0 +1,147 @@
+use {
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{account::from_account, stake_history::StakeHistory, sysvar},
+    solana_stake_program::config::DEFAULT_WARMUP_COOLDOWN_RATE,
+    std::error,
+};
+
+/// Number of epochs covered by the effective stake projection
+pub const PROJECTION_EPOCHS: usize = 3;
+
+/// Stake of the whole cluster, as recorded in the stake history at the last epoch boundary.
+/// Each epoch the cluster activates, and separately deactivates, at most
+/// `DEFAULT_WARMUP_COOLDOWN_RATE` of its effective stake, shared pro rata by all the stake
+/// warming up or cooling down
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ClusterStake {
+    pub effective: u64,
+    pub activating: u64,
+    pub deactivating: u64,
+}
+
+impl ClusterStake {
+    pub fn fetch(rpc_client: &RpcClient) -> Result<Self, Box<dyn error::Error>> {
+        let stake_history_account = rpc_client.get_account(&sysvar::stake_history::id())?;
+        let stake_history: StakeHistory =
+            from_account(&stake_history_account).ok_or("Failed to deserialize stake history")?;
+        Ok(stake_history
+            .iter()
+            .next()
+            .map(|(_, entry)| Self {
+                effective: entry.effective,
+                activating: entry.activating,
+                deactivating: entry.deactivating,
+            })
+            .unwrap_or_default())
+    }
+
+    // Portion of the pending stake that completes its warmup or cooldown this epoch
+    fn rate(&self, pending: u64) -> f64 {
+        if pending == 0 {
+            1.
+        } else {
+            self.effective as f64 * DEFAULT_WARMUP_COOLDOWN_RATE / pending as f64
+        }
+    }
+
+    pub fn warmup_rate(&self) -> f64 {
+        self.rate(self.activating)
+    }
+
+    pub fn cooldown_rate(&self) -> f64 {
+        self.rate(self.deactivating)
+    }
+}
+
+// Amount of `lamports` that completes at `rate`, exact when nothing is rate limited
+fn portion(lamports: u64, rate: f64) -> u64 {
+    if rate >= 1. {
+        lamports
+    } else {
+        (lamports as f64 * rate) as u64
+    }
+}
+
+/// Stake of a validator, where the deactivating stake is still effective until it has cooled
+/// down
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StakeActivity {
+    pub effective: u64,
+    pub activating: u64,
+    pub deactivating: u64,
+}
+
+/// Project the effective stake of `stake` at the start of each of the next `epochs` epochs,
+/// assuming the cluster activates and deactivates no other stake meanwhile
+pub fn project(cluster: &ClusterStake, stake: &StakeActivity, epochs: usize) -> Vec<u64> {
+    let mut cluster = *cluster;
+    let mut stake = *stake;
+    (0..epochs)
+        .map(|_| {
+            let (warmup_rate, cooldown_rate) = (cluster.warmup_rate(), cluster.cooldown_rate());
+
+            let activated = portion(stake.activating, warmup_rate);
+            let deactivated = portion(stake.deactivating, cooldown_rate);
+            stake.effective = stake.effective + activated - deactivated;
+            stake.activating -= activated;
+            stake.deactivating -= deactivated;
+
+            let activated = portion(cluster.activating, warmup_rate);
+            let deactivated = portion(cluster.deactivating, cooldown_rate);
+            cluster.effective = (cluster.effective + activated).saturating_sub(deactivated);
+            cluster.activating -= activated;
+            cluster.deactivating -= deactivated;
+
+            stake.effective
+        })
+        .collect()
+}
+
+/// Average stake delegated to a validator over an epoch, holding `balance` until its stake
+/// movement of `movement` lamports is issued once `movement_epoch_fraction` of the epoch has
+/// elapsed. Activating stake is delegated from the moment it is issued, and deactivating stake
+/// no longer is
+pub fn time_weighted_stake(balance: u64, movement: i64, movement_epoch_fraction: f64) -> u64 {
+    let moved_balance = (balance as i64 + movement).max(0) as f64;
+    let fraction = movement_epoch_fraction.max(0.).min(1.);
+    (balance as f64 * fraction + moved_balance * (1. - fraction)).round() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_project() {
+        let stake = StakeActivity {
+            effective: 1_000,
+            activating: 400,
+            deactivating: 200,
+        };
+
+        // Without other pending stake, movements complete at the next epoch boundary
+        let cluster = ClusterStake {
+            effective: 100_000,
+            ..ClusterStake::default()
+        };
+        assert_eq!(project(&cluster, &stake, 2), vec![1_200, 1_200]);
+
+        // Warmup is rate limited while the cluster activates more than a quarter of its
+        // effective stake
+        let cluster = ClusterStake {
+            effective: 100_000,
+            activating: 40_000,
+            deactivating: 0,
+        };
+        assert_eq!(cluster.warmup_rate(), 0.625);
+        assert_eq!(project(&cluster, &stake, 2), vec![1_050, 1_200]);
+    }
+
+    #[test]
+    fn test_time_weighted_stake() {
+        assert_eq!(time_weighted_stake(1_000, 0, 0.5), 1_000);
+        assert_eq!(time_weighted_stake(1_000, 400, 0.25), 1_300);
+        assert_eq!(time_weighted_stake(1_000, -1_000, 0.75), 750);
+        assert_eq!(time_weighted_stake(1_000, 400, 0.), 1_400);
+    }
+}
@@ -0,0 +1,173 @@
+//! Streak leaderboard of the epoch report, for the outreach to the validators that have been
+//! failing the longest and to those that just recovered. Streaks are counted over the epoch
+//! classifications of the history, skipping the epochs in which classification was paused
+use {
+    crate::{db::EpochClassificationV1, generic_stake_pool::ValidatorStakeState},
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::collections::HashMap,
+};
+
+/// Validators listed in each section of the leaderboard
+pub const LEADERBOARD_SIZE: usize = 10;
+
+// Stake state reason of the validators classified as delinquent
+const DELINQUENCY_REASON: &str = "insufficient vote credits";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct StreakLeaderboard {
+    /// Validators unstaked as delinquent, with the number of consecutive epochs
+    pub delinquency: Vec<(Pubkey, usize)>,
+
+    /// Validators unstaked for failing any criteria, with the number of consecutive epochs
+    pub criteria_failure: Vec<(Pubkey, usize)>,
+
+    /// Validators staked again in the latest epoch, with their stake state and the number of
+    /// consecutive epochs they were unstaked for
+    pub recovered: Vec<(Pubkey, ValidatorStakeState, usize)>,
+}
+
+fn longest(streaks: HashMap<Pubkey, usize>, limit: usize) -> Vec<(Pubkey, usize)> {
+    let mut streaks = streaks.into_iter().collect::<Vec<_>>();
+    streaks.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    streaks.truncate(limit);
+    streaks
+}
+
+/// The current streaks of the validators of the latest epoch of `history`, newest first, and
+/// the validators that recovered in it, up to `limit` of each
+pub fn leaderboard(history: &[(Epoch, EpochClassificationV1)], limit: usize) -> StreakLeaderboard {
+    let mut classifications = history.iter().filter_map(|(_, epoch_classification)| {
+        epoch_classification.validator_classifications.as_ref()
+    });
+    let latest = match classifications.next() {
+        Some(latest) => latest,
+        None => return StreakLeaderboard::default(),
+    };
+    let previous = classifications.collect::<Vec<_>>();
+
+    // Consecutive epochs, from the latest backwards, in which `failing` holds for the validator
+    let streak = |identity: &Pubkey, failing: &dyn Fn(&str) -> bool| {
+        previous
+            .iter()
+            .take_while(|classifications| {
+                classifications
+                    .get(identity)
+                    .map(|classification| {
+                        classification.stake_state == ValidatorStakeState::None
+                            && failing(&classification.stake_state_reason)
+                    })
+                    .unwrap_or(false)
+            })
+            .count()
+    };
+
+    let mut delinquency = HashMap::new();
+    let mut criteria_failure = HashMap::new();
+    let mut recovered = vec![];
+    for (identity, classification) in latest {
+        if classification.stake_state == ValidatorStakeState::None {
+            if classification
+                .stake_state_reason
+                .starts_with(DELINQUENCY_REASON)
+            {
+                delinquency.insert(
+                    *identity,
+                    1 + streak(identity, &|reason: &str| {
+                        reason.starts_with(DELINQUENCY_REASON)
+                    }),
+                );
+            }
+            criteria_failure.insert(*identity, 1 + streak(identity, &|_| true));
+        } else {
+            let unstaked_epochs = streak(identity, &|_| true);
+            if unstaked_epochs > 0 {
+                recovered.push((*identity, classification.stake_state, unstaked_epochs));
+            }
+        }
+    }
+    recovered.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+    recovered.truncate(limit);
+
+    StreakLeaderboard {
+        delinquency: longest(delinquency, limit),
+        criteria_failure: longest(criteria_failure, limit),
+        recovered,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::db::ValidatorClassification};
+
+    #[test]
+    fn test_leaderboard() {
+        let delinquent = Pubkey::new_unique();
+        let failing = Pubkey::new_unique();
+        let recovered = Pubkey::new_unique();
+        let epoch_classification =
+            |states: Vec<(Pubkey, ValidatorStakeState, &str)>| EpochClassificationV1 {
+                validator_classifications: Some(
+                    states
+                        .into_iter()
+                        .map(|(identity, stake_state, reason)| {
+                            (
+                                identity,
+                                ValidatorClassification {
+                                    identity,
+                                    stake_state,
+                                    stake_state_reason: reason.to_string(),
+                                    ..ValidatorClassification::default()
+                                },
+                            )
+                        })
+                        .collect(),
+                ),
+                ..EpochClassificationV1::default()
+            };
+        let unstaked = ValidatorStakeState::None;
+        let history = vec![
+            (
+                13,
+                epoch_classification(vec![
+                    (delinquent, unstaked, "insufficient vote credits: 10"),
+                    (failing, unstaked, "commission is too high: 100% commission"),
+                    (recovered, ValidatorStakeState::Baseline, "good"),
+                ]),
+            ),
+            // Paused classification
+            (12, EpochClassificationV1::default()),
+            (
+                11,
+                epoch_classification(vec![
+                    (delinquent, unstaked, "insufficient vote credits: 10"),
+                    (failing, unstaked, "insufficient vote credits: 10"),
+                    (recovered, unstaked, "insufficient vote credits: 10"),
+                ]),
+            ),
+            (
+                10,
+                epoch_classification(vec![
+                    (
+                        delinquent,
+                        unstaked,
+                        "commission is too high: 100% commission",
+                    ),
+                    (failing, unstaked, "insufficient vote credits: 10"),
+                    (recovered, unstaked, "insufficient vote credits: 10"),
+                ]),
+            ),
+        ];
+
+        let streaks = leaderboard(&history, LEADERBOARD_SIZE);
+        assert_eq!(streaks.delinquency, vec![(delinquent, 2)]);
+        assert_eq!(streaks.criteria_failure.len(), 2);
+        assert_eq!(streaks.criteria_failure[0].1, 3);
+        assert_eq!(
+            streaks.recovered,
+            vec![(recovered, ValidatorStakeState::Baseline, 2)]
+        );
+
+        assert_eq!(leaderboard(&history, 1).criteria_failure.len(), 1);
+        assert_eq!(leaderboard(&[], 1), StreakLeaderboard::default());
+    }
+}
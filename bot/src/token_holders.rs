@@ -0,0 +1,247 @@
+//! Snapshot of the stake pool token holders and the SOL underlying their pool tokens, for
+//! governance weights and incentive distributions
+use {
+    crate::schema::{self, Schema},
+    borsh::BorshDeserialize,
+    log::*,
+    serde::{Deserialize, Serialize},
+    solana_account_decoder::UiAccountEncoding,
+    solana_client::{
+        rpc_client::RpcClient,
+        rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+        rpc_filter::{Memcmp, MemcmpEncodedBytes, MemcmpEncoding, RpcFilterType},
+    },
+    solana_sdk::{
+        clock::{Epoch, Slot},
+        native_token::lamports_to_sol,
+        program_pack::Pack,
+        pubkey::Pubkey,
+    },
+    spl_stake_pool::state::StakePool,
+    std::{collections::HashMap, error, fs, path::Path, thread::sleep, time::Duration},
+};
+
+// Interval at which the cluster is polled while waiting for the snapshot slot
+const SLOT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TokenHolder {
+    /// Owner of the pool token accounts
+    pub owner: Pubkey,
+    pub token_accounts: usize,
+    pub pool_tokens: u64,
+
+    /// SOL withdrawable for the pool tokens
+    pub lamports: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TokenHolderSnapshot {
+    // The slot and epoch at which the snapshot was taken
+    pub slot: Slot,
+    pub epoch: Epoch,
+
+    pub stake_pool_address: Pubkey,
+    pub pool_mint: Pubkey,
+    pub total_stake_lamports: u64,
+    pub pool_token_supply: u64,
+
+    /// Holders with a non-zero balance, largest first
+    pub holders: Vec<TokenHolder>,
+}
+
+impl Schema for TokenHolderSnapshot {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl TokenHolderSnapshot {
+    pub fn to_csv(&self) -> String {
+        let mut csv = vec!["owner,token_accounts,pool_tokens,lamports,sol".to_string()];
+        csv.extend(self.holders.iter().map(|holder| {
+            format!(
+                "{},{},{},{},{}",
+                holder.owner,
+                holder.token_accounts,
+                holder.pool_tokens,
+                holder.lamports,
+                lamports_to_sol(holder.lamports)
+            )
+        }));
+        csv.join("\n") + "\n"
+    }
+
+    /// Write the snapshot as CSV if the file name ends in .csv, and JSON otherwise
+    pub fn save<P>(&self, path: P) -> Result<(), Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let serialized = if path.extension().and_then(|extension| extension.to_str()) == Some("csv")
+        {
+            self.to_csv()
+        } else {
+            schema::to_json_pretty(self)?
+        };
+        fs::write(path, serialized)
+            .map_err(|err| format!("Unable to write {}: {}", path.display(), err).into())
+    }
+}
+
+/// The holders of the `token_accounts`, given by owner and pool token balance, with the SOL
+/// underlying their pool tokens in `stake_pool`
+fn token_holders(stake_pool: &StakePool, token_accounts: &[(Pubkey, u64)]) -> Vec<TokenHolder> {
+    let mut holdings = HashMap::<Pubkey, (usize, u64)>::new();
+    for (owner, pool_tokens) in token_accounts {
+        if *pool_tokens > 0 {
+            let holding = holdings.entry(*owner).or_default();
+            holding.0 += 1;
+            holding.1 += pool_tokens;
+        }
+    }
+
+    let mut holders = holdings
+        .into_iter()
+        .map(|(owner, (token_accounts, pool_tokens))| TokenHolder {
+            owner,
+            token_accounts,
+            pool_tokens,
+            lamports: stake_pool
+                .calc_lamports_withdraw_amount(pool_tokens)
+                .unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    holders.sort_by(|a, b| {
+        b.pool_tokens
+            .cmp(&a.pool_tokens)
+            .then_with(|| a.owner.cmp(&b.owner))
+    });
+    holders
+}
+
+/// Snapshot the holders of the pool tokens of `stake_pool_address`. If `slot` is Some(), wait for
+/// the cluster to reach that slot first, as RPC nodes do not serve the accounts of past slots
+pub fn export(
+    rpc_client: &RpcClient,
+    stake_pool_address: &Pubkey,
+    slot: Option<Slot>,
+) -> Result<TokenHolderSnapshot, Box<dyn error::Error>> {
+    if let Some(slot) = slot {
+        let current_slot = rpc_client.get_slot()?;
+        if current_slot > slot {
+            return Err(format!(
+                "Slot {} has passed, the cluster is at slot {}. Snapshots can only be taken of \
+                 the current or a future slot",
+                slot, current_slot
+            )
+            .into());
+        }
+        info!("Waiting for slot {}", slot);
+        while rpc_client.get_slot()? < slot {
+            sleep(SLOT_POLL_INTERVAL);
+        }
+    }
+    let epoch_info = rpc_client.get_epoch_info()?;
+
+    let account_data = rpc_client.get_account_data(&stake_pool_address)?;
+    let stake_pool = StakePool::try_from_slice(account_data.as_slice())
+        .map_err(|err| format!("Invalid stake pool {}: {}", stake_pool_address, err))?;
+
+    let token_accounts = rpc_client.get_program_accounts_with_config(
+        &spl_token::id(),
+        RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(spl_token::state::Account::LEN as u64),
+                // Filter by `Account::mint`, which begins at byte offset 0
+                RpcFilterType::Memcmp(Memcmp {
+                    offset: 0,
+                    bytes: MemcmpEncodedBytes::Binary(stake_pool.pool_mint.to_string()),
+                    encoding: Some(MemcmpEncoding::Binary),
+                }),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(rpc_client.commitment()),
+                ..RpcAccountInfoConfig::default()
+            },
+        },
+    )?;
+    let token_accounts = token_accounts
+        .into_iter()
+        .map(|(address, account)| {
+            spl_token::state::Account::unpack(&account.data)
+                .map(|token_account| (token_account.owner, token_account.amount))
+                .map_err(|err| format!("Invalid token account {}: {}", address, err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let holders = token_holders(&stake_pool, &token_accounts);
+    info!(
+        "{} holders of the pool tokens of {} in {} token accounts",
+        holders.len(),
+        stake_pool_address,
+        token_accounts.len()
+    );
+    Ok(TokenHolderSnapshot {
+        slot: epoch_info.absolute_slot,
+        epoch: epoch_info.epoch,
+        stake_pool_address: *stake_pool_address,
+        pool_mint: stake_pool.pool_mint,
+        total_stake_lamports: stake_pool.total_stake_lamports,
+        pool_token_supply: stake_pool.pool_token_supply,
+        holders,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_holders() {
+        let stake_pool = StakePool {
+            total_stake_lamports: 3_000,
+            pool_token_supply: 1_500,
+            ..StakePool::default()
+        };
+        let (a, b, c) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+
+        let snapshot = TokenHolderSnapshot {
+            slot: 1,
+            epoch: 0,
+            stake_pool_address: Pubkey::new_unique(),
+            pool_mint: Pubkey::new_unique(),
+            total_stake_lamports: stake_pool.total_stake_lamports,
+            pool_token_supply: stake_pool.pool_token_supply,
+            holders: token_holders(&stake_pool, &[(a, 100), (b, 400), (a, 200), (c, 0)]),
+        };
+        assert_eq!(
+            snapshot.holders,
+            vec![
+                TokenHolder {
+                    owner: b,
+                    token_accounts: 1,
+                    pool_tokens: 400,
+                    lamports: 800,
+                },
+                TokenHolder {
+                    owner: a,
+                    token_accounts: 2,
+                    pool_tokens: 300,
+                    lamports: 600,
+                },
+            ]
+        );
+        assert_eq!(
+            snapshot.to_csv(),
+            format!(
+                "owner,token_accounts,pool_tokens,lamports,sol\n{},1,400,800,0.0000008\n\
+                 {},2,300,600,0.0000006\n",
+                b, a
+            )
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! Backends that submit the transactions of every stake pool phase
+use {
+    crate::{
+        blockhash_service::BlockhashService,
+        leader_timing::BurstTimer,
+        rpc_client_utils::{
+            send_and_confirm_transactions_via, send_and_confirm_transactions_with_signers,
+            SendAndConfirmTransactionResult,
+        },
+        schema::{self, Schema},
+    },
+    log::*,
+    serde::{Deserialize, Serialize},
+    solana_cli_config::Config as CliConfig,
+    solana_client::{
+        rpc_client::RpcClient,
+        tpu_client::{TpuClient, TpuClientConfig},
+    },
+    solana_sdk::{
+        signature::{Keypair, Signature, Signer},
+        transaction::Transaction,
+    },
+    std::{
+        error, fmt,
+        fs::OpenOptions,
+        io::Write,
+        path::{Path, PathBuf},
+        str::FromStr,
+        sync::Arc,
+    },
+};
+
+pub trait TransactionExecutor {
+    /// Submit `transactions`, signed by `signers` of which the first pays the fees. Transactions
+    /// handed over to be submitted elsewhere are neither succeeded nor failed
+    fn execute(
+        &self,
+        rpc_client: &RpcClient,
+        transactions: Vec<Transaction>,
+        signers: &[&Keypair],
+    ) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>>;
+}
+
+// Lets the stake pools that hold an executor derive `Debug`
+impl fmt::Debug for dyn TransactionExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TransactionExecutor")
+    }
+}
+
+/// Sends the transactions with the `sendTransaction` RPC method. Without a blockhash service the
+/// transactions of each batch are signed with one blockhash fetched from the RPC client
+#[derive(Default)]
+pub struct RpcExecutor {
+    blockhash_service: Option<BlockhashService>,
+}
+
+impl RpcExecutor {
+    pub fn new(json_rpc_url: &str) -> Result<Self, Box<dyn error::Error>> {
+        Ok(Self {
+            blockhash_service: Some(BlockhashService::start(json_rpc_url)?),
+        })
+    }
+}
+
+impl TransactionExecutor for RpcExecutor {
+    fn execute(
+        &self,
+        rpc_client: &RpcClient,
+        transactions: Vec<Transaction>,
+        signers: &[&Keypair],
+    ) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>> {
+        send_and_confirm_transactions_with_signers(
+            rpc_client,
+            self.blockhash_service.as_ref(),
+            false,
+            transactions,
+            signers,
+        )
+    }
+}
+
+/// Signs the transactions without sending them, reporting them all as succeeded
+pub struct DryRunExecutor;
+
+impl TransactionExecutor for DryRunExecutor {
+    fn execute(
+        &self,
+        rpc_client: &RpcClient,
+        transactions: Vec<Transaction>,
+        signers: &[&Keypair],
+    ) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>> {
+        send_and_confirm_transactions_with_signers(rpc_client, None, true, transactions, signers)
+    }
+}
+
+/// Sends the transactions straight to the TPU of the upcoming leaders, confirming them over RPC.
+/// Bursts of transactions are timed toward leaders that produced their blocks this epoch
+pub struct TpuExecutor {
+    tpu_client: TpuClient,
+    blockhash_service: BlockhashService,
+}
+
+impl TpuExecutor {
+    pub fn new(json_rpc_url: &str) -> Result<Self, Box<dyn error::Error>> {
+        let websocket_url = CliConfig::compute_websocket_url(json_rpc_url);
+        let tpu_client = TpuClient::new(
+            Arc::new(RpcClient::new(json_rpc_url.to_string())),
+            &websocket_url,
+            TpuClientConfig::default(),
+        )
+        .map_err(|err| {
+            format!(
+                "Unable to connect to the TPU via {}: {}",
+                websocket_url, err
+            )
+        })?;
+        Ok(Self {
+            tpu_client,
+            blockhash_service: BlockhashService::start(json_rpc_url)?,
+        })
+    }
+}
+
+impl TransactionExecutor for TpuExecutor {
+    fn execute(
+        &self,
+        rpc_client: &RpcClient,
+        transactions: Vec<Transaction>,
+        signers: &[&Keypair],
+    ) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>> {
+        let burst_timer = BurstTimer::new(rpc_client);
+        send_and_confirm_transactions_via(
+            rpc_client,
+            Some(&self.blockhash_service),
+            false,
+            transactions,
+            signers,
+            |transaction| {
+                burst_timer.wait(rpc_client);
+                if self.tpu_client.send_transaction(transaction) {
+                    Ok(())
+                } else {
+                    Err(format!("Unable to send {} to the TPU", transaction.signatures[0]).into())
+                }
+            },
+        )
+    }
+}
+
+/// Transaction written out for signing and submission elsewhere
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct PendingTransaction {
+    // Base58 encoded, bincode serialized transaction
+    transaction: String,
+    // Signers whose signature the transaction still requires
+    missing_signers: Vec<String>,
+}
+
+impl Schema for PendingTransaction {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+// Append `transactions` to `path`, one JSON object per line
+fn write_pending_transactions(
+    path: &Path,
+    transactions: &[Transaction],
+) -> Result<(), Box<dyn error::Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+    for transaction in transactions {
+        let num_required_signatures = transaction.message.header.num_required_signatures as usize;
+        let pending_transaction = PendingTransaction {
+            transaction: bs58::encode(bincode::serialize(transaction)?).into_string(),
+            missing_signers: transaction.message.account_keys[..num_required_signatures]
+                .iter()
+                .zip(&transaction.signatures)
+                .filter(|(_, signature)| **signature == Default::default())
+                .map(|(pubkey, _)| pubkey.to_string())
+                .collect(),
+        };
+        writeln!(file, "{}", schema::to_json(&pending_transaction)?)?;
+    }
+    info!(
+        "Wrote {} transactions to {}",
+        transactions.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Writes the transactions, unsigned, to a file for an offline signer
+pub struct OfflineFileExecutor {
+    path: PathBuf,
+}
+
+impl TransactionExecutor for OfflineFileExecutor {
+    fn execute(
+        &self,
+        _rpc_client: &RpcClient,
+        transactions: Vec<Transaction>,
+        _signers: &[&Keypair],
+    ) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>> {
+        write_pending_transactions(&self.path, &transactions)?;
+        Ok(SendAndConfirmTransactionResult {
+            succeeded: Default::default(),
+            failed: Default::default(),
+            signatures: vec![Signature::default(); transactions.len()],
+        })
+    }
+}
+
+/// Writes the transactions to a file as proposals for the members of a multisig, signed by the
+/// signers the bot holds. The remaining signers must sign before the recent blockhash expires
+pub struct MultisigProposalExecutor {
+    path: PathBuf,
+}
+
+impl TransactionExecutor for MultisigProposalExecutor {
+    fn execute(
+        &self,
+        rpc_client: &RpcClient,
+        mut transactions: Vec<Transaction>,
+        signers: &[&Keypair],
+    ) -> Result<SendAndConfirmTransactionResult, Box<dyn error::Error>> {
+        let (blockhash, _fee_calculator) = rpc_client.get_recent_blockhash()?;
+        for transaction in transactions.iter_mut() {
+            let num_required_signatures =
+                transaction.message.header.num_required_signatures as usize;
+            let required_signers = &transaction.message.account_keys[..num_required_signatures];
+            let mut held_signers: Vec<&Keypair> = vec![];
+            for signer in signers {
+                if required_signers.contains(&signer.pubkey())
+                    && !held_signers.iter().any(|s| s.pubkey() == signer.pubkey())
+                {
+                    held_signers.push(*signer);
+                }
+            }
+            transaction.try_partial_sign(&held_signers, blockhash)?;
+        }
+        write_pending_transactions(&self.path, &transactions)?;
+        Ok(SendAndConfirmTransactionResult {
+            succeeded: Default::default(),
+            failed: Default::default(),
+            signatures: vec![Signature::default(); transactions.len()],
+        })
+    }
+}
+
+/// Backend selected with `--executor`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutorKind {
+    Rpc,
+    Tpu,
+    OfflineFile,
+    MultisigProposal,
+}
+
+impl FromStr for ExecutorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rpc" => Ok(Self::Rpc),
+            "tpu" => Ok(Self::Tpu),
+            "offline-file" => Ok(Self::OfflineFile),
+            "multisig-proposal" => Ok(Self::MultisigProposal),
+            _ => Err(format!("Unknown executor: {}", s)),
+        }
+    }
+}
+
+/// The executor of `kind`. In a dry run nothing is submitted, whatever the kind. Transactions
+/// handed over to be submitted elsewhere are appended to `output_path`
+pub fn new(
+    kind: ExecutorKind,
+    dry_run: bool,
+    json_rpc_url: &str,
+    output_path: Option<&Path>,
+) -> Result<Box<dyn TransactionExecutor>, Box<dyn error::Error>> {
+    if dry_run {
+        return Ok(Box::new(DryRunExecutor));
+    }
+    let output_path = || {
+        output_path
+            .map(Path::to_path_buf)
+            .ok_or_else(|| format!("The {:?} executor requires an output file", kind))
+    };
+    Ok(match kind {
+        ExecutorKind::Rpc => Box::new(RpcExecutor::new(json_rpc_url)?),
+        ExecutorKind::Tpu => Box::new(TpuExecutor::new(json_rpc_url)?),
+        ExecutorKind::OfflineFile => Box::new(OfflineFileExecutor {
+            path: output_path()?,
+        }),
+        ExecutorKind::MultisigProposal => Box::new(MultisigProposalExecutor {
+            path: output_path()?,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        solana_sdk::{hash::Hash, pubkey::Pubkey, system_instruction},
+    };
+
+    #[test]
+    fn test_write_pending_transactions() {
+        let payer = Keypair::new();
+        let authority = Pubkey::new_unique();
+        let mut transaction = Transaction::new_with_payer(
+            &[system_instruction::transfer(
+                &authority,
+                &Pubkey::new_unique(),
+                1,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.partial_sign(&[&payer], Hash::default());
+
+        let path = std::env::temp_dir().join(format!("pending-{}.jsonl", payer.pubkey()));
+        write_pending_transactions(&path, &[transaction.clone()]).unwrap();
+        write_pending_transactions(&path, &[transaction]).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let pending_transaction: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(
+            pending_transaction["schema_version"],
+            PendingTransaction::SCHEMA_VERSION
+        );
+        assert_eq!(
+            pending_transaction["missing_signers"],
+            serde_json::json!([authority.to_string()])
+        );
+    }
+}
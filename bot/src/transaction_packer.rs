@@ -0,0 +1,107 @@
+//! Packing of the ordered instructions of an operation into as few transactions as fit in a
+//! packet. An operation that fits in a single transaction remains atomic. Each transaction ends
+//! with the memo of the operation, if any
+use {
+    solana_sdk::{
+        instruction::Instruction, packet::PACKET_DATA_SIZE, pubkey::Pubkey,
+        transaction::Transaction,
+    },
+    std::slice,
+};
+
+fn transaction(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    memo: Option<&Instruction>,
+) -> Transaction {
+    let instructions = instructions.iter().chain(memo).cloned().collect::<Vec<_>>();
+    Transaction::new_with_payer(&instructions, Some(payer))
+}
+
+fn fits_in_packet(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    memo: Option<&Instruction>,
+) -> bool {
+    // The signatures of an unsigned transaction are already sized
+    let transaction = transaction(payer, instructions, memo);
+    bincode::serialized_size(&transaction)
+        .map(|size| size as usize <= PACKET_DATA_SIZE)
+        .unwrap_or(false)
+}
+
+/// Pack `instructions`, in order, into consecutive transactions paid by `payer`, each followed by
+/// `memo`. Each transaction depends on the ones before it, see `OperationGraph::add_sequence`
+pub fn pack(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    memo: Option<&Instruction>,
+) -> Result<Vec<Transaction>, String> {
+    let mut transactions = vec![];
+    let mut packed: Vec<Instruction> = vec![];
+    for instruction in instructions {
+        if !fits_in_packet(payer, slice::from_ref(instruction), memo) {
+            return Err(format!(
+                "Instruction of program {} does not fit in a transaction",
+                instruction.program_id
+            ));
+        }
+
+        packed.push(instruction.clone());
+        if !fits_in_packet(payer, &packed, memo) {
+            let instruction = packed.pop().unwrap();
+            transactions.push(transaction(payer, &packed, memo));
+            packed = vec![instruction];
+        }
+    }
+    if !packed.is_empty() {
+        transactions.push(transaction(payer, &packed, memo));
+    }
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_sdk::system_instruction, std::convert::TryInto};
+
+    #[test]
+    fn test_pack() {
+        let payer = Pubkey::new_unique();
+        let transfers = (0..40)
+            .map(|lamports| system_instruction::transfer(&payer, &Pubkey::new_unique(), lamports))
+            .collect::<Vec<_>>();
+
+        let transactions = pack(&payer, &transfers[..3], None).unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].message.instructions.len(), 3);
+
+        let transactions = pack(&payer, &transfers, None).unwrap();
+        assert!(transactions.len() > 1);
+        let mut lamports = vec![];
+        for transaction in &transactions {
+            assert!(bincode::serialized_size(transaction).unwrap() as usize <= PACKET_DATA_SIZE);
+            for instruction in &transaction.message.instructions {
+                let amount: [u8; 8] = instruction.data[4..12].try_into().unwrap();
+                lamports.push(u64::from_le_bytes(amount));
+            }
+        }
+        // The instructions keep their order
+        assert_eq!(lamports, (0..40).collect::<Vec<_>>());
+
+        assert!(pack(&payer, &[], None).unwrap().is_empty());
+        let oversized = Instruction::new_with_bytes(payer, &[0; PACKET_DATA_SIZE], vec![]);
+        assert!(pack(&payer, &[oversized], None).is_err());
+
+        // The memo ends every transaction, and counts toward its size
+        let memo = Instruction::new_with_bytes(payer, &[7; 100], vec![]);
+        let memo_transactions = pack(&payer, &transfers, Some(&memo)).unwrap();
+        assert!(memo_transactions.len() >= transactions.len());
+        for transaction in &memo_transactions {
+            assert!(bincode::serialized_size(transaction).unwrap() as usize <= PACKET_DATA_SIZE);
+            assert_eq!(
+                transaction.message.instructions.last().unwrap().data,
+                vec![7; 100]
+            );
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Applications of new validators to the desired list. An application declares the identity and
+//! vote account of the validator, how to reach its operator, its data center and its self stake;
+//! `validate-application` checks the declared facts against the chain before the validator is
+//! added to a desired stake file
+use {
+    crate::{data_center_info::DataCenterId, vote_account_health::VoteAccountHealth},
+    serde::{Deserialize, Serialize},
+    solana_sdk::{
+        native_token::{lamports_to_sol, sol_to_lamports},
+        pubkey::Pubkey,
+    },
+    std::{error, fmt, fs::File, path::Path, str::FromStr},
+};
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Contact {
+    pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+
+    /// Handle on a chat platform, such as `@validator on Discord`
+    #[serde(default)]
+    pub chat: Option<String>,
+}
+
+/// Application of a validator, as a YAML file
+///
+/// ```yaml
+/// identity: <IDENTITY>
+/// vote_address: <VOTE_ADDRESS>
+/// contact:
+///   name: Example Validator
+///   email: ops@example.com
+/// data_center: 16509-US-Ashburn
+/// self_stake: 100
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValidatorApplication {
+    pub identity: String,
+    pub vote_address: String,
+    pub contact: Contact,
+
+    /// Data center the validator runs in, as `ASN-LOCATION` like the data center ids of the
+    /// classification
+    pub data_center: String,
+
+    /// Self stake the operator declares, in SOL
+    pub self_stake: f64,
+}
+
+impl ValidatorApplication {
+    pub fn load<P>(path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+        Ok(serde_yaml::from_reader(file)
+            .map_err(|err| format!("Invalid application {}: {}", path.display(), err))?)
+    }
+
+    /// The identity and vote address of the application, checked to be valid addresses
+    pub fn addresses(&self) -> Result<(Pubkey, Pubkey), String> {
+        let parse = |name, address: &str| {
+            Pubkey::from_str(address)
+                .map_err(|err| format!("Invalid {} {}: {}", name, address, err))
+        };
+        Ok((
+            parse("identity", &self.identity)?,
+            parse("vote address", &self.vote_address)?,
+        ))
+    }
+}
+
+/// What the chain tells about the validator of an application
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OnChainFacts {
+    /// Health of the vote account, None if the account does not exist
+    pub vote_account_health: Option<VoteAccountHealth>,
+
+    /// Whether the vote account is delinquent, None if it is missing from the vote accounts of
+    /// the cluster
+    pub delinquent: Option<bool>,
+
+    /// Data center of the identity, None if it could not be resolved
+    pub data_center: Option<DataCenterId>,
+
+    /// Stake delegated to the vote account and withdrawable by its authorized withdrawer
+    pub self_stake_lamports: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckOutcome {
+    Pass,
+    Fail,
+    /// The fact could not be observed, which is left to the judgement of the operator
+    Unverified,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplicationCheck {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+    pub detail: String,
+}
+
+impl fmt::Display for ApplicationCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outcome = match self.outcome {
+            CheckOutcome::Pass => "ok",
+            CheckOutcome::Fail => "FAILED",
+            CheckOutcome::Unverified => "unverified",
+        };
+        write!(f, "{}: {}: {}", self.name, outcome, self.detail)
+    }
+}
+
+fn check(name: &'static str, outcome: CheckOutcome, detail: String) -> ApplicationCheck {
+    ApplicationCheck {
+        name,
+        outcome,
+        detail,
+    }
+}
+
+/// Check `application` against the `facts` observed on chain. `min_self_stake_lamports` is the
+/// self stake the classification requires, if any
+pub fn validate(
+    application: &ValidatorApplication,
+    facts: &OnChainFacts,
+    min_self_stake_lamports: u64,
+) -> Vec<ApplicationCheck> {
+    use CheckOutcome::*;
+
+    let mut checks = vec![];
+    let identity = match application.addresses() {
+        Ok((identity, _)) => identity,
+        Err(err) => return vec![check("addresses", Fail, err)],
+    };
+
+    checks.push(
+        if application.contact.name.trim().is_empty()
+            || (application.contact.email.is_none() && application.contact.chat.is_none())
+        {
+            check(
+                "contact",
+                Fail,
+                "a name and an email address or chat handle are required".to_string(),
+            )
+        } else {
+            check("contact", Pass, application.contact.name.clone())
+        },
+    );
+
+    checks.push(match &facts.vote_account_health {
+        None => check("vote account", Fail, "account does not exist".to_string()),
+        Some(vote_account_health) => match vote_account_health.problem(&identity) {
+            Some(problem) => check("vote account", Fail, problem),
+            None => check(
+                "vote account",
+                Pass,
+                format!("votes for node identity {}", identity),
+            ),
+        },
+    });
+
+    checks.push(match facts.delinquent {
+        None => check(
+            "voting",
+            Fail,
+            "vote account is missing from the vote accounts of the cluster".to_string(),
+        ),
+        Some(true) => check("voting", Fail, "vote account is delinquent".to_string()),
+        Some(false) => check("voting", Pass, "vote account is current".to_string()),
+    });
+
+    // Data centers resolved offline only know the country of the ASN, so that the city of the
+    // declared location is not compared
+    checks.push(
+        match (
+            DataCenterId::from_str(&application.data_center),
+            &facts.data_center,
+        ) {
+            (Err(err), _) => check("data center", Fail, err),
+            (Ok(_), None) => check(
+                "data center",
+                Unverified,
+                "the data center of the identity could not be resolved".to_string(),
+            ),
+            (Ok(declared), Some(observed)) => {
+                if declared.asn == observed.asn && declared.country() == observed.country() {
+                    check("data center", Pass, observed.to_string())
+                } else {
+                    check(
+                        "data center",
+                        Fail,
+                        format!("declared {}, observed {}", declared, observed),
+                    )
+                }
+            }
+        },
+    );
+
+    let declared_self_stake_lamports = sol_to_lamports(application.self_stake);
+    checks.push(
+        if facts.self_stake_lamports < declared_self_stake_lamports {
+            check(
+                "self stake",
+                Fail,
+                format!(
+                    "declared ◎{}, observed ◎{}",
+                    application.self_stake,
+                    lamports_to_sol(facts.self_stake_lamports)
+                ),
+            )
+        } else if facts.self_stake_lamports < min_self_stake_lamports {
+            check(
+                "self stake",
+                Fail,
+                format!(
+                    "◎{} is below the minimum self stake of ◎{}",
+                    lamports_to_sol(facts.self_stake_lamports),
+                    lamports_to_sol(min_self_stake_lamports)
+                ),
+            )
+        } else {
+            check(
+                "self stake",
+                Pass,
+                format!("◎{}", lamports_to_sol(facts.self_stake_lamports)),
+            )
+        },
+    );
+
+    checks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let identity = Pubkey::new_unique();
+        let application = ValidatorApplication {
+            identity: identity.to_string(),
+            vote_address: Pubkey::new_unique().to_string(),
+            contact: Contact {
+                name: "Example Validator".to_string(),
+                email: Some("ops@example.com".to_string()),
+                chat: None,
+            },
+            data_center: "16509-US-Ashburn".to_string(),
+            self_stake: 100.,
+        };
+        let facts = OnChainFacts {
+            vote_account_health: Some(VoteAccountHealth {
+                lamports: 1_000,
+                rent_exempt_lamports: 1_000,
+                node_pubkey: Some(identity.to_string()),
+                authorized_withdrawer: Some(Pubkey::new_unique().to_string()),
+            }),
+            delinquent: Some(false),
+            data_center: Some("16509-US".parse().unwrap()),
+            self_stake_lamports: sol_to_lamports(150.),
+        };
+        let outcomes = |checks: Vec<ApplicationCheck>| {
+            checks
+                .into_iter()
+                .map(|check| (check.name, check.outcome))
+                .collect::<Vec<_>>()
+        };
+
+        let checks = validate(&application, &facts, 0);
+        assert!(checks
+            .iter()
+            .all(|check| check.outcome == CheckOutcome::Pass));
+        assert_eq!(checks[3].to_string(), "data center: ok: 16509-US");
+
+        let mismatched = OnChainFacts {
+            vote_account_health: Some(VoteAccountHealth {
+                node_pubkey: Some(Pubkey::new_unique().to_string()),
+                ..facts.vote_account_health.clone().unwrap()
+            }),
+            delinquent: None,
+            data_center: Some("24940-DE".parse().unwrap()),
+            self_stake_lamports: sol_to_lamports(50.),
+        };
+        assert_eq!(
+            outcomes(validate(&application, &mismatched, 0)),
+            vec![
+                ("contact", CheckOutcome::Pass),
+                ("vote account", CheckOutcome::Fail),
+                ("voting", CheckOutcome::Fail),
+                ("data center", CheckOutcome::Fail),
+                ("self stake", CheckOutcome::Fail),
+            ]
+        );
+
+        let unresolved = OnChainFacts {
+            data_center: None,
+            ..facts.clone()
+        };
+        assert_eq!(
+            validate(&application, &unresolved, sol_to_lamports(200.))[3..]
+                .iter()
+                .map(|check| check.outcome)
+                .collect::<Vec<_>>(),
+            vec![CheckOutcome::Unverified, CheckOutcome::Fail]
+        );
+
+        let anonymous = ValidatorApplication {
+            contact: Contact {
+                name: "Example Validator".to_string(),
+                ..Contact::default()
+            },
+            ..application.clone()
+        };
+        assert_eq!(
+            validate(&anonymous, &facts, 0)[0].outcome,
+            CheckOutcome::Fail
+        );
+
+        let invalid = ValidatorApplication {
+            identity: "unknown".to_string(),
+            ..application
+        };
+        assert_eq!(
+            outcomes(validate(&invalid, &facts, 0)),
+            vec![("addresses", CheckOutcome::Fail)]
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! Advance notices to validators that are about to lose their stake, such as "you will lose your
+//! stake next epoch unless your commission is lowered". The next classification is projected from
+//! the current on-chain state of each validator, and the notice is sent to the contacts the
+//! validator registered while there is still time to act on it
+use {
+    crate::{
+        db::ValidatorClassificationByIdentity, email_report::Mailer,
+        generic_stake_pool::ValidatorStakeState,
+    },
+    log::*,
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::{
+        collections::{HashMap, HashSet},
+        error,
+        fs::{self, File},
+        path::{Path, PathBuf},
+        str::FromStr,
+        time::Duration,
+    },
+};
+
+/// How to reach the operator of a validator
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct Contact {
+    pub email: Option<String>,
+
+    /// Discord user ID, mentioned in the notices posted to the Discord webhook
+    pub discord_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SerializedContactRegistry {
+    validators: HashMap<String, Contact>,
+}
+
+/// Contacts of the validators, by identity
+///
+/// ```yaml
+/// validators:
+///   9QxCLckBiJc783jnMvXZubK4wH86Eqqvashtrwvcsgkv:
+///     email: ops@example.com
+///     discord_id: "123456789012345678"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ContactRegistry {
+    pub validators: HashMap<Pubkey, Contact>,
+}
+
+impl ContactRegistry {
+    pub fn load<P>(path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+        let registry: SerializedContactRegistry = serde_yaml::from_reader(file)
+            .map_err(|err| format!("Invalid contact registry {}: {}", path.display(), err))?;
+        Ok(Self {
+            validators: registry
+                .validators
+                .into_iter()
+                .map(|(identity, contact)| {
+                    Pubkey::from_str(&identity)
+                        .map(|identity| (identity, contact))
+                        .map_err(|err| format!("Invalid identity {}: {}", identity, err))
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Current on-chain state of a validator
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatorState {
+    pub commission: u8,
+    pub release_version: Option<semver::Version>,
+}
+
+/// Limits of the next classification that a validator can still meet by acting now
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoticeThresholds {
+    pub max_commission: u8,
+    pub min_release_version: Option<semver::Version>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notice {
+    pub identity: Pubkey,
+    pub message: String,
+}
+
+/// Notices to the validators staked by the classification of `epoch` whose `current` state would
+/// leave them unstaked by the classification of the next epoch
+pub fn project(
+    epoch: Epoch,
+    classifications: &ValidatorClassificationByIdentity,
+    current: &HashMap<Pubkey, ValidatorState>,
+    thresholds: &NoticeThresholds,
+) -> Vec<Notice> {
+    let mut notices = vec![];
+    for (identity, classification) in classifications {
+        if classification.stake_state == ValidatorStakeState::None {
+            continue;
+        }
+        let state = match current.get(identity) {
+            Some(state) => state,
+            None => continue,
+        };
+
+        let mut unless = vec![];
+        if state.commission > thresholds.max_commission {
+            unless.push(format!(
+                "its commission is lowered to {}% or less, it is {}%",
+                thresholds.max_commission, state.commission
+            ));
+        }
+        if let (Some(min_release_version), Some(release_version)) =
+            (&thresholds.min_release_version, &state.release_version)
+        {
+            if release_version < min_release_version {
+                unless.push(format!(
+                    "it is upgraded to release {} or later, it runs {}",
+                    min_release_version, release_version
+                ));
+            }
+        }
+        if !unless.is_empty() {
+            notices.push(Notice {
+                identity: *identity,
+                message: format!(
+                    "Validator {} will lose its {:?} stake in epoch {} unless {}",
+                    identity,
+                    classification.stake_state,
+                    epoch + 1,
+                    unless.join(", and ")
+                ),
+            });
+        }
+    }
+    notices.sort_by(|a, b| a.identity.cmp(&b.identity));
+    notices
+}
+
+/// Notices already sent during an epoch, so that repeated runs do not send them again
+#[derive(Default, Deserialize, Serialize)]
+pub struct SentNotices {
+    pub messages: HashSet<String>,
+}
+
+impl SentNotices {
+    pub fn file_name<P>(epoch: Epoch, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        path.as_ref().join(format!("epoch-{}-notices.yml", epoch))
+    }
+
+    pub fn load<P>(epoch: Epoch, path: P) -> Result<Self, Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file_name = Self::file_name(epoch, path);
+        if !file_name.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_yaml::from_reader(File::open(file_name)?)?)
+    }
+
+    pub fn save<P>(&self, epoch: Epoch, path: P) -> Result<(), Box<dyn error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(&path)?;
+        fs::write(Self::file_name(epoch, path), serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Sends notices by email and to a Discord webhook, mentioning the Discord ID of the validator
+pub struct NoticeSender {
+    mailer: Option<Mailer>,
+    discord_webhook_url: Option<String>,
+    client: reqwest::blocking::Client,
+}
+
+impl NoticeSender {
+    pub fn new(
+        mailer: Option<Mailer>,
+        discord_webhook_url: Option<String>,
+    ) -> Result<Self, Box<dyn error::Error>> {
+        Ok(Self {
+            mailer,
+            discord_webhook_url,
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+        })
+    }
+
+    /// Send `notice` to every channel of `contact` that is configured. Returns whether it was
+    /// sent through any channel
+    pub fn send(&self, contact: &Contact, notice: &Notice) -> Result<bool, Box<dyn error::Error>> {
+        let mut sent = false;
+        if let (Some(mailer), Some(email)) = (&self.mailer, &contact.email) {
+            mailer.send_notice(email, "Stake-o-matic advance notice", &notice.message)?;
+            sent = true;
+        }
+        if let (Some(url), Some(discord_id)) = (&self.discord_webhook_url, &contact.discord_id) {
+            self.client
+                .post(url)
+                .json(&json!({ "content": format!("<@{}> {}", discord_id, notice.message) }))
+                .send()?
+                .error_for_status()?;
+            sent = true;
+        }
+        if !sent {
+            debug!("No configured contact channel for {}", notice.identity);
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::db::ValidatorClassification};
+
+    #[test]
+    fn test_project() {
+        let classification = |stake_state| ValidatorClassification {
+            stake_state,
+            ..ValidatorClassification::default()
+        };
+        let bonus = Pubkey::new_unique();
+        let unstaked = Pubkey::new_unique();
+        let compliant = Pubkey::new_unique();
+        let classifications = vec![
+            (bonus, classification(ValidatorStakeState::Bonus)),
+            (unstaked, classification(ValidatorStakeState::None)),
+            (compliant, classification(ValidatorStakeState::Baseline)),
+        ]
+        .into_iter()
+        .collect();
+
+        let high_commission = ValidatorState {
+            commission: 20,
+            release_version: Some(semver::Version::new(1, 6, 0)),
+        };
+        let current = vec![
+            (bonus, high_commission.clone()),
+            (unstaked, high_commission),
+            (
+                compliant,
+                ValidatorState {
+                    commission: 10,
+                    release_version: None,
+                },
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let thresholds = NoticeThresholds {
+            max_commission: 10,
+            min_release_version: None,
+        };
+        assert_eq!(
+            project(42, &classifications, &current, &thresholds),
+            vec![Notice {
+                identity: bonus,
+                message: format!(
+                    "Validator {} will lose its Bonus stake in epoch 43 unless its commission \
+                     is lowered to 10% or less, it is 20%",
+                    bonus
+                ),
+            }]
+        );
+
+        let thresholds = NoticeThresholds {
+            max_commission: 20,
+            min_release_version: Some(semver::Version::new(1, 6, 8)),
+        };
+        let notices = project(42, &classifications, &current, &thresholds);
+        assert_eq!(notices.len(), 1);
+        assert!(notices[0]
+            .message
+            .ends_with("unless it is upgraded to release 1.6.8 or later, it runs 1.6.0"));
+    }
+
+    #[test]
+    fn test_load_contact_registry() {
+        let identity = Pubkey::new_unique();
+        let path = std::env::temp_dir().join(format!("contacts-{}.yml", identity));
+        fs::write(
+            &path,
+            format!(
+                "validators:\n  {}:\n    email: ops@example.com\n    discord_id: \"42\"\n",
+                identity
+            ),
+        )
+        .unwrap();
+        let registry = ContactRegistry::load(&path).unwrap();
+        assert_eq!(
+            registry.validators[&identity],
+            Contact {
+                email: Some("ops@example.com".to_string()),
+                discord_id: Some("42".to_string()),
+            }
+        );
+
+        fs::write(&path, "validators:\n  not-a-pubkey: {}\n").unwrap();
+        assert!(ContactRegistry::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! Status of a validator in the latest classified epoch, served by the daemon API so that the
+//! validators can find out why they hold the stake they hold, and what it is about to become,
+//! without asking the operators of the pool
+use {
+    crate::{
+        criteria::{self, CriterionOutcome},
+        db::{EpochClassification, EpochClassificationV1},
+        generic_stake_pool::ValidatorStakeState,
+    },
+    serde::Serialize,
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::{io, path::Path},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedStake {
+    pub epoch: Epoch,
+    pub from_lamports: u64,
+    pub to_lamports: u64,
+}
+
+/// Stake changes of the validator that are not effective yet
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct PendingChanges {
+    /// Stake added (positive) or removed (negative) by the latest run, effective at the next
+    /// epoch
+    pub stake_movement_lamports: i64,
+
+    /// Stake the validator is short (positive) or over (negative) of its desired stake, skipped
+    /// as busy and carried over to the next run
+    pub busy_carryover_lamports: Option<i64>,
+
+    /// Stake movements the rebalance plan schedules for the next epochs
+    pub planned: Vec<PlannedStake>,
+
+    /// Status of the addition of the validator to the pool, if it is not in the pool yet
+    pub pool_addition: Option<String>,
+
+    /// Epoch the soft removal of the validator from the pool began
+    pub soft_removed_since: Option<Epoch>,
+}
+
+/// Pubkeys are strings, as in the other JSON documents of the bot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidatorStatus {
+    pub epoch: Epoch,
+    pub identity: String,
+    pub vote_address: String,
+    pub stake_state: ValidatorStakeState,
+    pub stake_state_reason: String,
+    pub notes: Vec<String>,
+
+    /// Stake of the validator in the pool before the stake movements of the epoch, None if the
+    /// run did not record it
+    pub stake_lamports: Option<u64>,
+
+    /// Effective stake of the validator projected for the next epochs
+    pub projected_effective_stake_lamports: Vec<u64>,
+    pub pending_changes: PendingChanges,
+
+    /// Outcome of each staking criterion, empty for the epochs classified before the outcomes
+    /// were recorded
+    pub criteria: Vec<CriterionOutcome>,
+}
+
+/// The status of `validator`, given by identity or vote address, in `epoch_classification`. None
+/// if the validator was not classified
+pub fn validator_status(
+    validator: &Pubkey,
+    epoch: Epoch,
+    epoch_classification: &EpochClassificationV1,
+) -> Option<ValidatorStatus> {
+    let classification = epoch_classification
+        .validator_classifications
+        .as_ref()?
+        .values()
+        .find(|classification| {
+            classification.identity == *validator || classification.vote_address == *validator
+        })?;
+    let vote_address = classification.vote_address;
+
+    let summary = epoch_classification.stake_pool_summary.as_ref();
+    let pending_changes = summary
+        .map(|summary| PendingChanges {
+            stake_movement_lamports: summary
+                .stake_movement_lamports
+                .get(&vote_address)
+                .copied()
+                .unwrap_or_default(),
+            busy_carryover_lamports: summary.busy_carryover_lamports.get(&vote_address).copied(),
+            planned: summary
+                .rebalance_plan
+                .iter()
+                .flat_map(|rebalance_plan| rebalance_plan.epochs.iter())
+                .filter(|epoch_plan| epoch_plan.epoch > epoch)
+                .flat_map(|epoch_plan| {
+                    epoch_plan
+                        .moves
+                        .iter()
+                        .filter(|planned_move| planned_move.vote_address == vote_address)
+                        .map(move |planned_move| PlannedStake {
+                            epoch: epoch_plan.epoch,
+                            from_lamports: planned_move.from_lamports,
+                            to_lamports: planned_move.to_lamports,
+                        })
+                })
+                .collect(),
+            pool_addition: summary
+                .missing_from_pool
+                .get(&vote_address)
+                .map(|status| status.to_string()),
+            soft_removed_since: summary.soft_removed_since.get(&vote_address).copied(),
+        })
+        .unwrap_or_default();
+
+    Some(ValidatorStatus {
+        epoch,
+        identity: classification.identity.to_string(),
+        vote_address: vote_address.to_string(),
+        stake_state: classification.stake_state,
+        stake_state_reason: classification.stake_state_reason.clone(),
+        notes: classification.notes.clone(),
+        stake_lamports: summary
+            .and_then(|summary| summary.validator_balance_lamports.get(&vote_address))
+            .copied(),
+        projected_effective_stake_lamports: summary
+            .and_then(|summary| {
+                summary
+                    .projected_effective_stake_lamports
+                    .get(&vote_address)
+                    .cloned()
+            })
+            .or_else(|| classification.projected_effective_stake.clone())
+            .unwrap_or_default(),
+        pending_changes,
+        criteria: classification
+            .failed_criteria
+            .as_ref()
+            .map(criteria::breakdown)
+            .unwrap_or_default(),
+    })
+}
+
+/// The status of `validator` in the latest epoch of `path` whose classification was not paused
+pub fn load<P>(validator: &Pubkey, path: P) -> Result<Option<ValidatorStatus>, io::Error>
+where
+    P: AsRef<Path>,
+{
+    let mut next = EpochClassification::load_latest(&path)?;
+    while let Some((epoch, epoch_classification)) = next {
+        let epoch_classification = epoch_classification.into_current();
+        if epoch_classification.validator_classifications.is_some() {
+            return Ok(validator_status(validator, epoch, &epoch_classification));
+        }
+        next = EpochClassification::load_previous(epoch, &path)?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            criteria::{Criterion, FailedCriteria},
+            db::ValidatorClassification,
+            generic_stake_pool::StakePoolSummary,
+        },
+        solana_stake_o_matic::rebalance_plan::{EpochPlan, PlannedMove, RebalancePlan},
+    };
+
+    #[test]
+    fn test_validator_status() {
+        let identity = Pubkey::new_unique();
+        let vote_address = Pubkey::new_unique();
+        let mut failed_criteria = FailedCriteria::new();
+        failed_criteria.insert(Criterion::VoteLag, "high vote lag".to_string());
+        let epoch_classification = EpochClassificationV1 {
+            validator_classifications: Some(
+                vec![(
+                    identity,
+                    ValidatorClassification {
+                        identity,
+                        vote_address,
+                        stake_state: ValidatorStakeState::Baseline,
+                        stake_state_reason: "high vote lag".to_string(),
+                        failed_criteria: Some(failed_criteria),
+                        ..ValidatorClassification::default()
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            ),
+            stake_pool_summary: Some(StakePoolSummary {
+                stake_movement_lamports: vec![(vote_address, -500)].into_iter().collect(),
+                validator_balance_lamports: vec![(vote_address, 1_500)].into_iter().collect(),
+                rebalance_plan: Some(RebalancePlan {
+                    epochs: vec![10, 11]
+                        .into_iter()
+                        .map(|epoch| EpochPlan {
+                            epoch,
+                            moves: vec![PlannedMove {
+                                vote_address,
+                                from_lamports: 1_000,
+                                to_lamports: 500,
+                            }],
+                        })
+                        .collect(),
+                    ..RebalancePlan::default()
+                }),
+                ..StakePoolSummary::default()
+            }),
+            ..EpochClassificationV1::default()
+        };
+
+        let status = validator_status(&vote_address, 10, &epoch_classification).unwrap();
+        assert_eq!(
+            status,
+            validator_status(&identity, 10, &epoch_classification).unwrap()
+        );
+        assert_eq!(status.stake_state, ValidatorStakeState::Baseline);
+        assert_eq!(status.stake_lamports, Some(1_500));
+        assert_eq!(status.pending_changes.stake_movement_lamports, -500);
+        assert_eq!(
+            status.pending_changes.planned,
+            vec![PlannedStake {
+                epoch: 11,
+                from_lamports: 1_000,
+                to_lamports: 500,
+            }]
+        );
+        assert_eq!(status.criteria.len(), Criterion::ALL.len());
+        assert_eq!(
+            status
+                .criteria
+                .iter()
+                .filter(|outcome| !outcome.passed)
+                .map(|outcome| outcome.criterion)
+                .collect::<Vec<_>>(),
+            vec![Criterion::VoteLag]
+        );
+
+        assert!(validator_status(&Pubkey::new_unique(), 10, &epoch_classification).is_none());
+        assert!(validator_status(&identity, 10, &EpochClassificationV1::default()).is_none());
+    }
+}
@@ -0,0 +1,222 @@
+//! Vote accounts shared by the runs of the daemon. getVoteAccounts returns every vote account of
+//! the cluster along with its recent epoch credits, one of the heaviest requests the bot sends,
+//! and each run sent it several times. The cache serves them from a single view, fetched again
+//! in full once older than its maximum age or once the epoch changes. It is not refreshed
+//! incrementally: a programSubscribe to the vote program notifies the changed vote accounts, but
+//! with their raw state only, not the activated stake and delinquency that getVoteAccounts adds.
+//! Each refresh logs how the vote accounts changed since the previous view
+use {
+    log::*,
+    solana_client::{
+        client_error::Result as ClientResult, rpc_client::RpcClient,
+        rpc_response::RpcVoteAccountStatus,
+    },
+    solana_sdk::clock::Epoch,
+    std::{
+        collections::HashMap,
+        fmt,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+struct CachedView {
+    epoch: Epoch,
+    fetched_at: Instant,
+    vote_accounts: RpcVoteAccountStatus,
+}
+
+pub struct VoteAccountCache {
+    max_age: Duration,
+    view: Mutex<Option<CachedView>>,
+}
+
+impl fmt::Debug for VoteAccountCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VoteAccountCache")
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl VoteAccountCache {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            view: Mutex::new(None),
+        }
+    }
+
+    /// The vote accounts of the cluster, as cached unless the view is older than the maximum age
+    /// or from an earlier epoch
+    pub fn get_vote_accounts(&self, rpc_client: &RpcClient) -> ClientResult<RpcVoteAccountStatus> {
+        let epoch = rpc_client.get_epoch_info()?.epoch;
+        let mut view = self.view.lock().unwrap();
+        if let Some(view) = view
+            .as_ref()
+            .filter(|view| view.epoch == epoch && view.fetched_at.elapsed() < self.max_age)
+        {
+            return Ok(view.vote_accounts.clone());
+        }
+
+        let vote_accounts = rpc_client.get_vote_accounts()?;
+        match view.as_ref() {
+            Some(previous) => info!(
+                "Vote accounts refreshed: {}",
+                VoteAccountChanges::new(&previous.vote_accounts, &vote_accounts)
+            ),
+            None => info!(
+                "Vote accounts cached: {}",
+                vote_accounts.current.len() + vote_accounts.delinquent.len()
+            ),
+        }
+        *view = Some(CachedView {
+            epoch,
+            fetched_at: Instant::now(),
+            vote_accounts: vote_accounts.clone(),
+        });
+        Ok(vote_accounts)
+    }
+}
+
+/// The vote accounts of the cluster, through `vote_account_cache` if there is one
+pub fn get_vote_accounts(
+    rpc_client: &RpcClient,
+    vote_account_cache: Option<&VoteAccountCache>,
+) -> ClientResult<RpcVoteAccountStatus> {
+    match vote_account_cache {
+        Some(vote_account_cache) => vote_account_cache.get_vote_accounts(rpc_client),
+        None => rpc_client.get_vote_accounts(),
+    }
+}
+
+/// How the vote accounts changed between two views
+#[derive(Debug, Default, PartialEq)]
+pub struct VoteAccountChanges {
+    pub added: usize,
+    pub removed: usize,
+    pub commission_changed: usize,
+    pub became_delinquent: usize,
+    pub recovered: usize,
+}
+
+impl VoteAccountChanges {
+    pub fn new(previous: &RpcVoteAccountStatus, current: &RpcVoteAccountStatus) -> Self {
+        // Commission and delinquency of each vote account, by vote address
+        let index = |vote_accounts: &RpcVoteAccountStatus| {
+            vote_accounts
+                .current
+                .iter()
+                .map(|vote_account| (vote_account, false))
+                .chain(
+                    vote_accounts
+                        .delinquent
+                        .iter()
+                        .map(|vote_account| (vote_account, true)),
+                )
+                .map(|(vote_account, delinquent)| {
+                    (
+                        vote_account.vote_pubkey.clone(),
+                        (vote_account.commission, delinquent),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        let previous = index(previous);
+        let current = index(current);
+
+        let mut changes = VoteAccountChanges {
+            removed: previous
+                .keys()
+                .filter(|vote_pubkey| !current.contains_key(*vote_pubkey))
+                .count(),
+            ..VoteAccountChanges::default()
+        };
+        for (vote_pubkey, (commission, delinquent)) in &current {
+            match previous.get(vote_pubkey) {
+                None => changes.added += 1,
+                Some((previous_commission, previous_delinquent)) => {
+                    if previous_commission != commission {
+                        changes.commission_changed += 1;
+                    }
+                    match (previous_delinquent, delinquent) {
+                        (false, true) => changes.became_delinquent += 1,
+                        (true, false) => changes.recovered += 1,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        changes
+    }
+}
+
+impl fmt::Display for VoteAccountChanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} commission changes, {} delinquent, {} recovered",
+            self.added,
+            self.removed,
+            self.commission_changed,
+            self.became_delinquent,
+            self.recovered
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_client::rpc_response::RpcVoteAccountInfo};
+
+    #[test]
+    fn test_vote_account_changes() {
+        let vote_account = |vote_pubkey: &str, commission| RpcVoteAccountInfo {
+            vote_pubkey: vote_pubkey.to_string(),
+            node_pubkey: String::new(),
+            activated_stake: 0,
+            commission,
+            epoch_vote_account: true,
+            epoch_credits: vec![],
+            last_vote: 0,
+            root_slot: 0,
+        };
+        let previous = RpcVoteAccountStatus {
+            current: vec![
+                vote_account("steady", 5),
+                vote_account("raised", 5),
+                vote_account("lagging", 5),
+                vote_account("removed", 5),
+            ],
+            delinquent: vec![vote_account("recovering", 5)],
+        };
+        let current = RpcVoteAccountStatus {
+            current: vec![
+                vote_account("steady", 5),
+                vote_account("raised", 10),
+                vote_account("recovering", 5),
+                vote_account("added", 5),
+            ],
+            delinquent: vec![vote_account("lagging", 5)],
+        };
+        let changes = VoteAccountChanges::new(&previous, &current);
+        assert_eq!(
+            changes,
+            VoteAccountChanges {
+                added: 1,
+                removed: 1,
+                commission_changed: 1,
+                became_delinquent: 1,
+                recovered: 1,
+            }
+        );
+        assert_eq!(
+            changes.to_string(),
+            "1 added, 1 removed, 1 commission changes, 1 delinquent, 1 recovered"
+        );
+        assert_eq!(
+            VoteAccountChanges::new(&current, &current),
+            VoteAccountChanges::default()
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Health of the validators' vote accounts. Stake delegated to a vote account that is not owned
+//! by the vote program, cannot be decoded, or votes for another node identity earns nothing, and
+//! an account that is not rent-exempt pays rent until it is reaped, so validators with a broken
+//! vote account are destaked before any stake is created for them
+use {
+    serde::{Deserialize, Serialize},
+    solana_sdk::{account::Account, pubkey::Pubkey, rent::Rent},
+    solana_vote_program::vote_state::VoteState,
+    std::str::FromStr,
+};
+
+/// Observation of a vote account, as cached by the enrichment
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct VoteAccountHealth {
+    pub lamports: u64,
+
+    /// Balance the account needs to be exempt from rent
+    pub rent_exempt_lamports: u64,
+
+    /// Node identity and authorized withdrawer of the vote state, as strings like the other
+    /// pubkeys of the data directory. None if the account does not hold a vote state
+    pub node_pubkey: Option<String>,
+    pub authorized_withdrawer: Option<String>,
+}
+
+impl VoteAccountHealth {
+    pub fn new(account: &Account, rent: &Rent) -> Self {
+        let vote_state = if account.owner == solana_vote_program::id() {
+            VoteState::from(account)
+        } else {
+            None
+        };
+        Self {
+            lamports: account.lamports,
+            rent_exempt_lamports: rent.minimum_balance(account.data.len()),
+            node_pubkey: vote_state
+                .as_ref()
+                .map(|vote_state| vote_state.node_pubkey.to_string()),
+            authorized_withdrawer: vote_state
+                .as_ref()
+                .map(|vote_state| vote_state.authorized_withdrawer.to_string()),
+        }
+    }
+
+    pub fn authorized_withdrawer(&self) -> Option<Pubkey> {
+        self.authorized_withdrawer
+            .as_ref()
+            .and_then(|authorized_withdrawer| Pubkey::from_str(authorized_withdrawer).ok())
+    }
+
+    /// Why the vote account of the validator `identity` cannot receive stake, if it cannot
+    pub fn problem(&self, identity: &Pubkey) -> Option<String> {
+        let node_pubkey = match &self.node_pubkey {
+            Some(node_pubkey) => node_pubkey,
+            None => return Some("vote account does not hold a vote state".to_string()),
+        };
+        if *node_pubkey != identity.to_string() {
+            return Some(format!(
+                "vote account votes for node identity {} instead of {}",
+                node_pubkey, identity
+            ));
+        }
+        if self.lamports < self.rent_exempt_lamports {
+            return Some(format!(
+                "vote account is not rent-exempt: {} lamports of the {} required",
+                self.lamports, self.rent_exempt_lamports
+            ));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, solana_vote_program::vote_state::VoteStateVersions};
+
+    #[test]
+    fn test_problem() {
+        let identity = Pubkey::new_unique();
+        let rent = Rent::default();
+        let vote_state = VoteState {
+            node_pubkey: identity,
+            ..VoteState::default()
+        };
+        let mut account = Account::new(
+            rent.minimum_balance(VoteState::size_of()),
+            VoteState::size_of(),
+            &solana_vote_program::id(),
+        );
+        VoteState::to(
+            &VoteStateVersions::new_current(vote_state.clone()),
+            &mut account,
+        )
+        .unwrap();
+
+        let health = VoteAccountHealth::new(&account, &rent);
+        assert_eq!(health.problem(&identity), None);
+        assert_eq!(
+            health.authorized_withdrawer(),
+            Some(vote_state.authorized_withdrawer)
+        );
+        assert!(health
+            .problem(&Pubkey::new_unique())
+            .unwrap()
+            .contains("instead of"));
+
+        account.lamports -= 1;
+        assert!(VoteAccountHealth::new(&account, &rent)
+            .problem(&identity)
+            .unwrap()
+            .contains("not rent-exempt"));
+
+        account.owner = Pubkey::new_unique();
+        assert_eq!(
+            VoteAccountHealth::new(&account, &rent).problem(&identity),
+            Some("vote account does not hold a vote state".to_string())
+        );
+    }
+}
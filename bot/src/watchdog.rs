@@ -0,0 +1,116 @@
+//! Dead man's switch integration. An external watchdog, such as healthchecks.io or Cronitor, is
+//! pinged on startup and after each run, and alerts on its own when the pings stop arriving, which
+//! catches the crashed or stuck daemons that cannot report their own failure
+use {
+    log::*,
+    reqwest::Url,
+    std::{error, time::Duration},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogEvent {
+    Start,
+    Success,
+    Failure,
+}
+
+pub struct Watchdog {
+    url: Url,
+    client: reqwest::blocking::Client,
+}
+
+impl Watchdog {
+    pub fn new(url: &str) -> Result<Self, Box<dyn error::Error>> {
+        Ok(Self {
+            url: Url::parse(url).map_err(|err| format!("Invalid watchdog URL {}: {}", url, err))?,
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+        })
+    }
+
+    /// The URL that signals `event`. Cronitor telemetry URLs take the event as a `state` query
+    /// parameter, other URLs follow the healthchecks.io convention of `/start` and `/fail` suffixes
+    fn event_url(&self, event: WatchdogEvent, message: &str) -> Url {
+        let mut url = self.url.clone();
+        let is_cronitor = url
+            .host_str()
+            .map(|host| host.ends_with("cronitor.link") || host.ends_with("cronitor.io"))
+            .unwrap_or(false);
+
+        if is_cronitor {
+            let state = match event {
+                WatchdogEvent::Start => "run",
+                WatchdogEvent::Success => "complete",
+                WatchdogEvent::Failure => "fail",
+            };
+            url.query_pairs_mut().append_pair("state", state);
+            if !message.is_empty() {
+                url.query_pairs_mut().append_pair("message", message);
+            }
+        } else {
+            let suffix = match event {
+                WatchdogEvent::Start => "start",
+                WatchdogEvent::Success => return url,
+                WatchdogEvent::Failure => "fail",
+            };
+            let path = format!("{}/{}", url.path().trim_end_matches('/'), suffix);
+            url.set_path(&path);
+        }
+        url
+    }
+
+    /// Signal `event` to the watchdog, with `message` as the body of the ping. A watchdog that
+    /// cannot be reached only logs a warning, it never fails the run
+    pub fn ping(&self, event: WatchdogEvent, message: &str) {
+        let url = self.event_url(event, message);
+        match self
+            .client
+            .post(url)
+            .body(message.to_string())
+            .send()
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(_) => debug!("Watchdog pinged: {:?}", event),
+            Err(err) => warn!("Unable to ping the watchdog: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event_url() {
+        let healthchecks = Watchdog::new("https://hc-ping.com/0e9c7a1c-2b7e/").unwrap();
+        assert_eq!(
+            healthchecks.event_url(WatchdogEvent::Start, "").as_str(),
+            "https://hc-ping.com/0e9c7a1c-2b7e/start"
+        );
+        assert_eq!(
+            healthchecks.event_url(WatchdogEvent::Success, "").as_str(),
+            "https://hc-ping.com/0e9c7a1c-2b7e/"
+        );
+        assert_eq!(
+            healthchecks
+                .event_url(WatchdogEvent::Failure, "RPC failure")
+                .as_str(),
+            "https://hc-ping.com/0e9c7a1c-2b7e/fail"
+        );
+
+        let cronitor = Watchdog::new("https://cronitor.link/p/key/stake-o-matic").unwrap();
+        assert_eq!(
+            cronitor.event_url(WatchdogEvent::Start, "").as_str(),
+            "https://cronitor.link/p/key/stake-o-matic?state=run"
+        );
+        assert_eq!(
+            cronitor
+                .event_url(WatchdogEvent::Failure, "RPC failure")
+                .as_str(),
+            "https://cronitor.link/p/key/stake-o-matic?state=fail&message=RPC+failure"
+        );
+
+        assert!(Watchdog::new("not a url").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Monitoring of the authorized withdrawer of the validators' vote accounts, the destination of
+//! their commission. A withdrawer changed to an address nobody announced can mean a compromised
+//! key or a validator sold to a new operator, so the change is flagged, and optionally demoted,
+//! until the new withdrawer is added to the allowlist of planned rotations
+use {
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    std::{collections::HashSet, error, fs::File, path::Path, str::FromStr},
+};
+
+/// Treatment of the validators whose withdrawer changed to an unknown address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WithdrawerChangeAction {
+    /// Validators are only flagged in the notes
+    Warn,
+    /// Validators are held at the Baseline stake level
+    Baseline,
+    /// Validators are destaked
+    Destake,
+}
+
+impl FromStr for WithdrawerChangeAction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "baseline" => Ok(Self::Baseline),
+            "destake" => Ok(Self::Destake),
+            _ => Err(format!("Invalid withdrawer change action: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithdrawerChangePolicy {
+    pub action: WithdrawerChangeAction,
+
+    /// Withdrawers announced for planned rotations, which are not flagged
+    pub allowlist: HashSet<Pubkey>,
+}
+
+impl WithdrawerChangePolicy {
+    /// The policy applying `action`, with the allowlist read from the YAML list of addresses at
+    /// `allowlist_path`, if any
+    pub fn new(
+        action: WithdrawerChangeAction,
+        allowlist_path: Option<&Path>,
+    ) -> Result<Self, Box<dyn error::Error>> {
+        let allowlist = match allowlist_path {
+            Some(path) => {
+                let file = File::open(path)
+                    .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+                let addresses: Vec<String> = serde_yaml::from_reader(file).map_err(|err| {
+                    format!("Invalid withdrawer allowlist {}: {}", path.display(), err)
+                })?;
+                addresses
+                    .iter()
+                    .map(|address| {
+                        Pubkey::from_str(address)
+                            .map_err(|err| format!("Invalid withdrawer {}: {}", address, err))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            None => HashSet::default(),
+        };
+        Ok(Self { action, allowlist })
+    }
+
+    /// Epoch in which the vote account's withdrawer last changed to an address that is not in
+    /// the allowlist, given the withdrawer and change epoch of the previous epoch. The change
+    /// remains flagged until the withdrawer is allowlisted
+    pub fn change_epoch(
+        &self,
+        epoch: Epoch,
+        previous_withdrawer: Option<&Pubkey>,
+        withdrawer: Option<&Pubkey>,
+        previous_change_epoch: Option<Epoch>,
+    ) -> Option<Epoch> {
+        match (previous_withdrawer, withdrawer) {
+            (_, Some(withdrawer)) if self.allowlist.contains(withdrawer) => None,
+            (Some(previous_withdrawer), Some(withdrawer)) if previous_withdrawer != withdrawer => {
+                Some(epoch)
+            }
+            _ => previous_change_epoch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_change_epoch() {
+        let original = Pubkey::new_unique();
+        let unknown = Pubkey::new_unique();
+        let planned = Pubkey::new_unique();
+        let policy = WithdrawerChangePolicy {
+            action: WithdrawerChangeAction::Baseline,
+            allowlist: vec![planned].into_iter().collect(),
+        };
+
+        assert_eq!(
+            policy.change_epoch(10, Some(&original), Some(&original), None),
+            None
+        );
+        // New validators have no previous withdrawer to compare with
+        assert_eq!(policy.change_epoch(10, None, Some(&unknown), None), None);
+        assert_eq!(
+            policy.change_epoch(10, Some(&original), Some(&unknown), None),
+            Some(10)
+        );
+        assert_eq!(
+            policy.change_epoch(11, Some(&unknown), Some(&unknown), Some(10)),
+            Some(10)
+        );
+        assert_eq!(
+            policy.change_epoch(11, Some(&unknown), None, Some(10)),
+            Some(10)
+        );
+        assert_eq!(
+            policy.change_epoch(10, Some(&original), Some(&planned), None),
+            None
+        );
+        assert_eq!(
+            policy.change_epoch(12, Some(&unknown), Some(&planned), Some(10)),
+            None
+        );
+
+        assert_eq!(
+            "destake".parse::<WithdrawerChangeAction>(),
+            Ok(WithdrawerChangeAction::Destake)
+        );
+        assert!("demote".parse::<WithdrawerChangeAction>().is_err());
+    }
+}
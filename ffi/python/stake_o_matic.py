@@ -0,0 +1,71 @@
+"""Python bindings of the stake-o-matic planner, through the C ABI of the stake-o-matic-ffi
+library. Build the library with `cargo build --release -p stake-o-matic-ffi`, then:
+
+    from stake_o_matic import Planner
+
+    planner = Planner()
+    allocation = planner.allocate(
+        total_stake_lamports=10_000_000_000_000,
+        baseline_stake_lamports=5_000_000_000,
+        seed=250,
+        validators=[
+            {"identity": "...", "vote_address": "...", "stake_state": "Bonus"},
+        ],
+    )
+"""
+import ctypes
+import json
+import os
+import sys
+
+_LIBRARY_NAMES = {
+    "darwin": "libstake_o_matic_ffi.dylib",
+    "win32": "stake_o_matic_ffi.dll",
+}
+
+
+def _default_library_path():
+    name = _LIBRARY_NAMES.get(sys.platform, "libstake_o_matic_ffi.so")
+    root = os.path.join(os.path.dirname(os.path.abspath(__file__)), "..", "..")
+    return os.path.join(root, "target", "release", name)
+
+
+class PlannerError(Exception):
+    """Error reported by the planner, such as an invalid request"""
+
+
+class Planner:
+    def __init__(self, library_path=None):
+        self._library = ctypes.CDLL(library_path or _default_library_path())
+        for function in (
+            "stake_o_matic_allocate",
+            "stake_o_matic_plan_rebalance",
+            "stake_o_matic_score",
+        ):
+            getattr(self._library, function).argtypes = [ctypes.c_char_p]
+            getattr(self._library, function).restype = ctypes.c_void_p
+        self._library.stake_o_matic_free.argtypes = [ctypes.c_void_p]
+        self._library.stake_o_matic_free.restype = None
+
+    def _call(self, function, request):
+        response = getattr(self._library, function)(json.dumps(request).encode())
+        try:
+            response_json = ctypes.string_at(response).decode()
+        finally:
+            self._library.stake_o_matic_free(response)
+        response = json.loads(response_json)
+        if "error" in response:
+            raise PlannerError(response["error"])
+        return response["ok"]
+
+    def allocate(self, **request):
+        """The desired stake of each validator, as the stake pool update allocates it"""
+        return self._call("stake_o_matic_allocate", request)
+
+    def plan_rebalance(self, **request):
+        """The stake movements of each epoch, as planned with --max-epoch-stake-change"""
+        return self._call("stake_o_matic_plan_rebalance", request)
+
+    def score(self, **request):
+        """The reputation score of each validator and the stake state it leads to"""
+        return self._call("stake_o_matic_score", request)
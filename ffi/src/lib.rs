@@ -0,0 +1,488 @@
+//! C ABI of the stake-o-matic planner, so that the allocation, the rebalance planning and the
+//! reputation scoring of the production bot can run outside of it, such as in Python notebooks
+//! through `python/`.
+//!
+//! Each function takes a JSON request as a NUL-terminated string and returns a JSON response,
+//! `{"ok": ...}` or `{"error": "..."}`, that the caller releases with `stake_o_matic_free`.
+//! Pubkeys are base58 strings in both
+use {
+    serde::{Deserialize, Serialize},
+    serde_json::{json, Value},
+    solana_sdk::{clock::Epoch, pubkey::Pubkey},
+    solana_stake_o_matic::{
+        bonus_ramp::BonusRampStage,
+        generic_stake_pool::{sort_by_seed, ValidatorStake, ValidatorStakeState},
+        planner::{self, AllocationParams, BaselineShortfall},
+        rebalance_plan,
+        reputation::{self, ReputationScore},
+    },
+    std::{
+        any::Any,
+        error,
+        ffi::{CStr, CString},
+        os::raw::c_char,
+        panic,
+        str::FromStr,
+    },
+};
+
+fn parse_pubkey(address: &str) -> Result<Pubkey, String> {
+    Pubkey::from_str(address).map_err(|err| format!("Invalid address {}: {}", address, err))
+}
+
+#[derive(Deserialize)]
+struct ValidatorEntry {
+    identity: String,
+    vote_address: String,
+    stake_state: ValidatorStakeState,
+    #[serde(default)]
+    bonus_weight: Option<u64>,
+    #[serde(default)]
+    asn: Option<u64>,
+    #[serde(default)]
+    score: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct AllocateRequest {
+    #[serde(flatten)]
+    params: AllocationParams,
+
+    /// Validator ordering seed of the epoch, the epoch itself unless the bot was given `--seed`.
+    /// The validators keep the order given without one
+    #[serde(default)]
+    seed: Option<u64>,
+
+    validators: Vec<ValidatorEntry>,
+}
+
+#[derive(Serialize)]
+struct AllocatedValidator {
+    identity: String,
+    vote_address: String,
+    stake_state: ValidatorStakeState,
+    desired_lamports: u64,
+}
+
+#[derive(Serialize)]
+struct AllocateResponse {
+    baseline_stake_lamports: u64,
+    bonus_stake_lamports: u64,
+    validators: Vec<AllocatedValidator>,
+    capped_asns: Vec<u64>,
+    bonus_ramp: Option<BonusRampStage>,
+    baseline_shortfall: Option<BaselineShortfall>,
+    liquidity_buffer_lamports: u64,
+}
+
+fn allocate(request: &str) -> Result<Value, Box<dyn error::Error>> {
+    let request: AllocateRequest = serde_json::from_str(request)?;
+    let mut desired_validator_stake = request
+        .validators
+        .into_iter()
+        .map(|entry| {
+            let mut validator_stake = ValidatorStake::new(
+                parse_pubkey(&entry.identity)?,
+                parse_pubkey(&entry.vote_address)?,
+                entry.stake_state,
+            );
+            if let Some(bonus_weight) = entry.bonus_weight {
+                validator_stake.bonus_weight = bonus_weight;
+            }
+            validator_stake.asn = entry.asn;
+            validator_stake.score = entry.score;
+            Ok(validator_stake)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if let Some(seed) = request.seed {
+        sort_by_seed(&mut desired_validator_stake, seed);
+    }
+
+    let allocation = planner::allocate(&request.params, &desired_validator_stake)?;
+    Ok(serde_json::to_value(AllocateResponse {
+        baseline_stake_lamports: allocation.baseline_stake_lamports,
+        bonus_stake_lamports: allocation.bonus_stake_lamports,
+        validators: desired_validator_stake
+            .iter()
+            .map(|validator_stake| AllocatedValidator {
+                identity: validator_stake.identity.to_string(),
+                vote_address: validator_stake.vote_address.to_string(),
+                stake_state: validator_stake.stake_state,
+                desired_lamports: allocation.desired_lamports(validator_stake),
+            })
+            .collect(),
+        capped_asns: allocation.capped_asns,
+        bonus_ramp: allocation.bonus_ramp,
+        baseline_shortfall: allocation.baseline_shortfall,
+        liquidity_buffer_lamports: allocation.liquidity_buffer_lamports,
+    })?)
+}
+
+#[derive(Deserialize)]
+struct PlanBalance {
+    vote_address: String,
+    current_lamports: u64,
+    target_lamports: u64,
+}
+
+#[derive(Deserialize)]
+struct PlanRebalanceRequest {
+    epoch: Epoch,
+    reserve_lamports: u64,
+    balances: Vec<PlanBalance>,
+    max_epoch_change_percentage: f64,
+    min_move_lamports: u64,
+}
+
+fn plan_rebalance(request: &str) -> Result<Value, Box<dyn error::Error>> {
+    let request: PlanRebalanceRequest = serde_json::from_str(request)?;
+    let balances = request
+        .balances
+        .iter()
+        .map(|balance| {
+            Ok((
+                parse_pubkey(&balance.vote_address)?,
+                balance.current_lamports,
+                balance.target_lamports,
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let plan = rebalance_plan::plan(
+        request.epoch,
+        request.reserve_lamports,
+        &balances,
+        request.max_epoch_change_percentage,
+        request.min_move_lamports,
+    );
+    Ok(json!({
+        "epochs": plan
+            .epochs
+            .iter()
+            .map(|epoch_plan| json!({
+                "epoch": epoch_plan.epoch,
+                "moves": epoch_plan
+                    .moves
+                    .iter()
+                    .map(|planned_move| json!({
+                        "vote_address": planned_move.vote_address.to_string(),
+                        "from_lamports": planned_move.from_lamports,
+                        "to_lamports": planned_move.to_lamports,
+                    }))
+                    .collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>(),
+        "unreachable_lamports": plan
+            .unreachable_lamports
+            .iter()
+            .map(|(vote_address, lamports)| (vote_address.to_string(), json!(lamports)))
+            .collect::<serde_json::Map<_, _>>(),
+    }))
+}
+
+#[derive(Deserialize)]
+struct ScoreEntry {
+    identity: String,
+    vote_address: String,
+    stake_state: ValidatorStakeState,
+    #[serde(default)]
+    previous_reputation: f64,
+}
+
+#[derive(Deserialize)]
+struct ScoreRequest {
+    reputation_decay_percentage: usize,
+    #[serde(default)]
+    min_bonus_reputation: Option<f64>,
+    validators: Vec<ScoreEntry>,
+}
+
+#[derive(Serialize)]
+struct ScoredValidator {
+    identity: String,
+    vote_address: String,
+    #[serde(flatten)]
+    score: ReputationScore,
+}
+
+fn score(request: &str) -> Result<Value, Box<dyn error::Error>> {
+    let ScoreRequest {
+        reputation_decay_percentage,
+        min_bonus_reputation,
+        validators,
+    } = serde_json::from_str(request)?;
+    let validators = validators
+        .into_iter()
+        .map(|entry| {
+            Ok(ScoredValidator {
+                identity: parse_pubkey(&entry.identity)?.to_string(),
+                vote_address: parse_pubkey(&entry.vote_address)?.to_string(),
+                score: reputation::score(
+                    entry.stake_state,
+                    entry.previous_reputation,
+                    reputation_decay_percentage,
+                    min_bonus_reputation,
+                ),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(json!({ "validators": validators }))
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string());
+    format!("Panicked: {}", message)
+}
+
+/// Run `handler` on the request at `request`, returning its response as a string owned by Rust.
+/// A panic must not unwind into the caller, it is answered as an error
+unsafe fn call(
+    request: *const c_char,
+    handler: fn(&str) -> Result<Value, Box<dyn error::Error>>,
+) -> *mut c_char {
+    let response = if request.is_null() {
+        Err("No request given".into())
+    } else {
+        CStr::from_ptr(request)
+            .to_str()
+            .map_err(|err| format!("Request is not UTF-8: {}", err).into())
+            .and_then(|request| {
+                panic::catch_unwind(|| handler(request))
+                    .unwrap_or_else(|payload| Err(panic_message(payload).into()))
+            })
+    };
+    let response = match response {
+        Ok(value) => json!({ "ok": value }),
+        Err(err) => json!({ "error": err.to_string() }),
+    };
+    // A JSON string never holds a NUL byte, control characters are escaped
+    CString::new(response.to_string()).unwrap().into_raw()
+}
+
+/// Allocate the stake of a pool as the stake pool update does. The request holds the
+/// `planner::AllocationParams` fields, an optional `seed` and the `validators`, each with an
+/// `identity`, a `vote_address`, a `stake_state` and optionally a `bonus_weight`, an `asn` and a
+/// `score`
+///
+/// # Safety
+/// `request` must be NULL or point to a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn stake_o_matic_allocate(request: *const c_char) -> *mut c_char {
+    call(request, allocate)
+}
+
+/// Plan the stake movements over several epochs as `--max-epoch-stake-change` does. The request
+/// holds the arguments of `rebalance_plan::plan`, with the `balances` given as `vote_address`,
+/// `current_lamports` and `target_lamports` objects
+///
+/// # Safety
+/// `request` must be NULL or point to a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn stake_o_matic_plan_rebalance(request: *const c_char) -> *mut c_char {
+    call(request, plan_rebalance)
+}
+
+/// Score the validators of an epoch as the classification does, once the staking criteria gave
+/// their stake state. The request holds the `reputation_decay_percentage`, an optional
+/// `min_bonus_reputation` and the `validators`, each with an `identity`, a `vote_address`, the
+/// `stake_state` given by the staking criteria and the `previous_reputation` of the validator.
+/// Each validator is returned with its `reputation`, its `stake_state` and the `failure` of the
+/// bonus reputation criterion
+///
+/// # Safety
+/// `request` must be NULL or point to a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn stake_o_matic_score(request: *const c_char) -> *mut c_char {
+    call(request, score)
+}
+
+/// Release a response
+///
+/// # Safety
+/// `response` must be NULL or a response returned by this library, released only once
+#[no_mangle]
+pub unsafe extern "C" fn stake_o_matic_free(response: *mut c_char) {
+    if !response.is_null() {
+        drop(CString::from_raw(response));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call_json(
+        function: unsafe extern "C" fn(*const c_char) -> *mut c_char,
+        request: &Value,
+    ) -> Value {
+        let request = CString::new(request.to_string()).unwrap();
+        unsafe {
+            let response = function(request.as_ptr());
+            let value = serde_json::from_str(CStr::from_ptr(response).to_str().unwrap()).unwrap();
+            stake_o_matic_free(response);
+            value
+        }
+    }
+
+    #[test]
+    fn test_allocate() {
+        let baseline = (Pubkey::new_unique(), Pubkey::new_unique());
+        let bonus = (Pubkey::new_unique(), Pubkey::new_unique());
+        let response = call_json(
+            stake_o_matic_allocate,
+            &json!({
+                "total_stake_lamports": 1_000,
+                "baseline_stake_lamports": 100,
+                "validators": [
+                    {
+                        "identity": baseline.0.to_string(),
+                        "vote_address": baseline.1.to_string(),
+                        "stake_state": "Baseline",
+                    },
+                    {
+                        "identity": bonus.0.to_string(),
+                        "vote_address": bonus.1.to_string(),
+                        "stake_state": "Bonus",
+                        "bonus_weight": 200,
+                    },
+                ],
+            }),
+        );
+        assert_eq!(response["ok"]["bonus_stake_lamports"], json!(450));
+        assert_eq!(
+            response["ok"]["validators"][1],
+            json!({
+                "identity": bonus.0.to_string(),
+                "vote_address": bonus.1.to_string(),
+                "stake_state": "Bonus",
+                "desired_lamports": 900,
+            })
+        );
+
+        let response = call_json(
+            stake_o_matic_allocate,
+            &json!({
+                "total_stake_lamports": 50,
+                "baseline_stake_lamports": 100,
+                "validators": [{
+                    "identity": baseline.0.to_string(),
+                    "vote_address": baseline.1.to_string(),
+                    "stake_state": "Baseline",
+                }],
+            }),
+        );
+        assert_eq!(
+            response["error"],
+            json!("Not enough stake to cover the baseline")
+        );
+
+        let response = call_json(
+            stake_o_matic_allocate,
+            &json!({
+                "total_stake_lamports": 50,
+                "baseline_stake_lamports": 100,
+                "baseline_shortfall": "reduce-baseline",
+                "validators": [{
+                    "identity": baseline.0.to_string(),
+                    "vote_address": baseline.1.to_string(),
+                    "stake_state": "Baseline",
+                }],
+            }),
+        );
+        assert_eq!(response["ok"]["baseline_stake_lamports"], json!(50));
+        assert_eq!(
+            response["ok"]["baseline_shortfall"],
+            json!("reduce-baseline")
+        );
+        assert!(call_json(stake_o_matic_allocate, &json!({}))["error"].is_string());
+    }
+
+    #[test]
+    fn test_call_panic() {
+        let request = CString::new("{}").unwrap();
+        unsafe {
+            let response = call(request.as_ptr(), |_| panic!("invalid state"));
+            assert_eq!(
+                serde_json::from_str::<Value>(CStr::from_ptr(response).to_str().unwrap()).unwrap(),
+                json!({ "error": "Panicked: invalid state" })
+            );
+            stake_o_matic_free(response);
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        let validator = (Pubkey::new_unique(), Pubkey::new_unique());
+        let response = call_json(
+            stake_o_matic_score,
+            &json!({
+                "reputation_decay_percentage": 50,
+                "min_bonus_reputation": 0.,
+                "validators": [{
+                    "identity": validator.0.to_string(),
+                    "vote_address": validator.1.to_string(),
+                    "stake_state": "Bonus",
+                    "previous_reputation": -4.,
+                }],
+            }),
+        );
+        assert_eq!(
+            response["ok"]["validators"][0],
+            json!({
+                "identity": validator.0.to_string(),
+                "vote_address": validator.1.to_string(),
+                "reputation": -1.,
+                "stake_state": "Baseline",
+                "failure": "reputation -1.00 is below the 0.00 required for bonus stake",
+            })
+        );
+
+        let response = call_json(
+            stake_o_matic_score,
+            &json!({
+                "reputation_decay_percentage": 50,
+                "validators": [{
+                    "identity": validator.0.to_string(),
+                    "vote_address": validator.1.to_string(),
+                    "stake_state": "Bonus",
+                }],
+            }),
+        );
+        assert_eq!(response["ok"]["validators"][0]["reputation"], json!(1.));
+        assert_eq!(response["ok"]["validators"][0]["failure"], Value::Null);
+        assert!(call_json(stake_o_matic_score, &json!({}))["error"].is_string());
+    }
+
+    #[test]
+    fn test_plan_rebalance() {
+        let vote_address = Pubkey::new_unique();
+        let response = call_json(
+            stake_o_matic_plan_rebalance,
+            &json!({
+                "epoch": 10,
+                "reserve_lamports": 1_000,
+                "balances": [{
+                    "vote_address": vote_address.to_string(),
+                    "current_lamports": 0,
+                    "target_lamports": 200,
+                }],
+                "max_epoch_change_percentage": 10.,
+                "min_move_lamports": 1,
+            }),
+        );
+        let epochs = response["ok"]["epochs"].as_array().unwrap();
+        assert_eq!(epochs.len(), 2);
+        assert_eq!(
+            epochs[0]["moves"][0],
+            json!({
+                "vote_address": vote_address.to_string(),
+                "from_lamports": 0,
+                "to_lamports": 100,
+            })
+        );
+    }
+}